@@ -11,7 +11,7 @@ use crate::db::DBPtr;
 use slimchain_common::{
     basic::{AccountData, Address, Code, Nonce, StateKey, StateValue, H256},
     error::Result,
-    tx_req::SignedTxRequest,
+    tx_req::{SignedTxRequest, TxSigConfig},
 };
 use slimchain_merkle_trie::prelude::*;
 use slimchain_tx_executor::execute_tx;
@@ -74,11 +74,11 @@ pub(crate) async fn exec_tx(
         let state_root = pending_update.root;
         let state_view = TxStateViewWithUpdate::new(db, pending_update);
         let backend = ExecutorBackend::new(&state_view, state_root);
-        let output = execute_tx(signed_tx_req.clone(), &backend)?;
+        let output = execute_tx(signed_tx_req.clone(), &TxSigConfig::get(), &backend)?;
         let new_update = update_tx_state(&state_view, state_root, &output.writes)?;
 
         let mut update = pending_update.clone();
-        update.merge(new_update);
+        update.extend(new_update);
         Ok(update)
     })
 }
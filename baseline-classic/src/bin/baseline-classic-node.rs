@@ -55,6 +55,7 @@ async fn main() -> Result<()> {
     info!("Role: {}", role);
     let chain_cfg: ChainConfig = cfg.get("chain")?;
     info!("Chain Cfg: {:#?}", chain_cfg);
+    chain_cfg.tx_sig.install_as_global()?;
 
     let db = DB::open_or_create_in_dir(&opts.data.unwrap_or(bin_dir), role, opts.db_statistics)?;
 
@@ -62,13 +63,20 @@ async fn main() -> Result<()> {
         Consensus::PoW => {
             use baseline_classic::network::pow::*;
             use slimchain_chain::config::PoWConfig;
-            use slimchain_network::p2p::{config::NetworkConfig, control::Swarmer};
+            use slimchain_network::p2p::{
+                config::{NetworkConfig, PubSubConfig},
+                control::Swarmer,
+            };
 
             let net_cfg: NetworkConfig = cfg.get("network")?;
             let pow_cfg: PoWConfig = cfg.get("pow").unwrap_or_default();
             info!("PoW initial difficulty: {}", pow_cfg.init_diff);
             pow_cfg.install_as_global()?;
 
+            let pubsub_cfg: PubSubConfig = cfg.get("pubsub").unwrap_or_default();
+            info!("PubSub Cfg: {:#?}", pubsub_cfg);
+            pubsub_cfg.install_as_global()?;
+
             match role {
                 Role::Client => {
                     let behavior = ClientBehavior::new(db, &net_cfg).await?;
@@ -104,6 +112,8 @@ async fn main() -> Result<()> {
             use slimchain_network::http::config::{NetworkConfig, RaftConfig};
 
             let net_cfg: NetworkConfig = cfg.get("network")?;
+            net_cfg.http_client.install_as_global()?;
+            net_cfg.auth.clone().install_as_global()?;
             let raft_cfg: RaftConfig = cfg.get("raft")?;
 
             match role {
@@ -115,8 +125,8 @@ async fn main() -> Result<()> {
                     info!("Quitting.");
                     client.shutdown().await?;
                 }
-                Role::Storage(_) | Role::Miner => {
-                    bail!("Role cannot be storage or miner.");
+                Role::Storage(_) | Role::Miner | Role::Observer => {
+                    bail!("Role cannot be storage, miner or observer.");
                 }
             }
         }
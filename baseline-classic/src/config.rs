@@ -1,8 +1,12 @@
 use serde::Deserialize;
 use slimchain_chain::consensus::Consensus;
+use slimchain_common::tx_req::TxSigConfig;
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct ChainConfig {
     /// Consensus method. Possible values: pow, raft.
     pub consensus: Consensus,
+    /// How the signatures of the tx requests are verified.
+    #[serde(default)]
+    pub tx_sig: TxSigConfig,
 }
@@ -81,7 +81,7 @@ impl NetworkBehaviourEventProcess<DiscoveryEvent> for ClientBehavior {
 
 impl NetworkBehaviourEventProcess<PubSubEvent<SignedTxRequest, Block>> for ClientBehavior {
     fn inject_event(&mut self, event: PubSubEvent<SignedTxRequest, Block>) {
-        if let PubSubEvent::BlockProposal(input) = event {
+        if let PubSubEvent::BlockProposal(input, _) = event {
             trace!(
                 height = input.block_height().0,
                 txs = input.tx_list().len(),
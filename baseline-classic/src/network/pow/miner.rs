@@ -64,7 +64,7 @@ impl NetworkBehaviourEventProcess<DiscoveryEvent> for MinerBehavior {
 
 impl NetworkBehaviourEventProcess<PubSubEvent<SignedTxRequest, Block>> for MinerBehavior {
     fn inject_event(&mut self, event: PubSubEvent<SignedTxRequest, Block>) {
-        if let PubSubEvent::TxProposal(input) = event {
+        if let PubSubEvent::TxProposal(input, _) = event {
             record_event!("miner_recv_tx", "tx_id": input.id());
             self.worker.add_tx(input);
         }
@@ -156,7 +156,7 @@ impl<Tx: TxTrait + Serialize>
     NetworkBehaviourEventProcess<PubSubEvent<Tx, BlockProposal<Block, Tx>>> for ClientBehavior<Tx>
 {
     fn inject_event(&mut self, event: PubSubEvent<Tx, BlockProposal<Block, Tx>>) {
-        if let PubSubEvent::BlockProposal(input) = event {
+        if let PubSubEvent::BlockProposal(input, _) = event {
             trace!(
                 height = input.get_block_height().0,
                 txs = input.get_txs().len(),
@@ -85,7 +85,7 @@ impl<Tx: TxTrait + Serialize>
     NetworkBehaviourEventProcess<PubSubEvent<Tx, BlockProposal<Block, Tx>>> for MinerBehavior<Tx>
 {
     fn inject_event(&mut self, event: PubSubEvent<Tx, BlockProposal<Block, Tx>>) {
-        if let PubSubEvent::TxProposal(input) = event {
+        if let PubSubEvent::TxProposal(input, _) = event {
             record_event!("miner_recv_tx", "tx_id": input.id());
             self.worker.add_tx(input);
         }
@@ -224,9 +224,12 @@ impl<Tx: TxTrait + Serialize + for<'de> Deserialize<'de> + 'static> ClientNode<T
         info!("Create http server, listen on {}", net_cfg.http_listen);
         let listen_addr: SocketAddr = net_cfg.http_listen.parse()?;
         let (srv_shutdown_tx, srv_shutdown_rx) = oneshot::channel::<()>();
-        let (_, srv) = warp::serve(client_rpc_srv.or(
-            warp::path(NODE_RPC_ROUTE_PATH).and(raft_rpc_srv.or(leader_rpc_srv).or(block_rpc_srv)),
-        ))
+        let (_, srv) = warp::serve(
+            client_rpc_srv.or(warp::path(NODE_RPC_ROUTE_PATH)
+                .and(warp_node_rpc_auth())
+                .and(raft_rpc_srv.or(leader_rpc_srv).or(block_rpc_srv))
+                .recover(recover_unauthorized)),
+        )
         .bind_with_graceful_shutdown(listen_addr, async {
             srv_shutdown_rx.await.ok();
         });
@@ -325,11 +325,15 @@ impl<Tx: TxTrait + Serialize + for<'de> Deserialize<'de> + 'static> StorageNode<
         info!("Create http server, listen on {}", net_cfg.http_listen);
         let listen_addr: SocketAddr = net_cfg.http_listen.parse()?;
         let (srv_shutdown_tx, srv_shutdown_rx) = oneshot::channel::<()>();
-        let (_, srv) =
-            warp::serve(warp::path(NODE_RPC_ROUTE_PATH).and(tx_exec_srv.or(block_import_srv)))
-                .bind_with_graceful_shutdown(listen_addr, async {
-                    srv_shutdown_rx.await.ok();
-                });
+        let (_, srv) = warp::serve(
+            warp::path(NODE_RPC_ROUTE_PATH)
+                .and(warp_node_rpc_auth())
+                .and(tx_exec_srv.or(block_import_srv))
+                .recover(recover_unauthorized),
+        )
+        .bind_with_graceful_shutdown(listen_addr, async {
+            srv_shutdown_rx.await.ok();
+        });
         let srv_handle = tokio::spawn(srv);
 
         Ok(Self {
@@ -68,14 +68,22 @@ pub async fn node_main<Tx: TxTrait + Serialize + for<'de> Deserialize<'de> + 'st
     info!("Role: {}", role);
     let chain_cfg: ChainConfig = cfg.get("chain")?;
     info!("Chain Cfg: {:#?}", chain_cfg);
+    chain_cfg.tx_sig.install_as_global()?;
 
-    let db = DB::open_or_create_in_dir(&opts.data.unwrap_or(bin_dir), role, opts.db_statistics)?;
+    let db = DB::open_with_backend(
+        chain_cfg.db_backend,
+        &opts.data.unwrap_or(bin_dir),
+        role,
+        opts.db_statistics,
+        !chain_cfg.disable_db_journal,
+        chain_cfg.db_read_threads,
+    )?;
 
     match chain_cfg.consensus {
         Consensus::PoW => {
             use crate::network::pow::*;
             use slimchain_chain::config::PoWConfig;
-            use slimchain_network::p2p::config::NetworkConfig;
+            use slimchain_network::p2p::config::{NetworkConfig, PubSubConfig};
 
             let net_cfg: NetworkConfig = cfg.get("network")?;
 
@@ -83,6 +91,10 @@ pub async fn node_main<Tx: TxTrait + Serialize + for<'de> Deserialize<'de> + 'st
             info!("PoW initial difficulty: {}", pow_cfg.init_diff);
             pow_cfg.install_as_global()?;
 
+            let pubsub_cfg: PubSubConfig = cfg.get("pubsub").unwrap_or_default();
+            info!("PubSub Cfg: {:#?}", pubsub_cfg);
+            pubsub_cfg.install_as_global()?;
+
             match role {
                 Role::Client => {
                     let behavior = ClientBehavior::<Tx>::new(db, &chain_cfg, &net_cfg).await?;
@@ -130,6 +142,9 @@ pub async fn node_main<Tx: TxTrait + Serialize + for<'de> Deserialize<'de> + 'st
                         .context("Failed to find miner.")?;
                     ctrl.run_until_interrupt().await?;
                 }
+                Role::Observer => {
+                    bail!("Role cannot be observer.");
+                }
             }
         }
         Consensus::Raft => {
@@ -137,6 +152,8 @@ pub async fn node_main<Tx: TxTrait + Serialize + for<'de> Deserialize<'de> + 'st
             use slimchain_network::http::config::{NetworkConfig, RaftConfig};
 
             let net_cfg: NetworkConfig = cfg.get("network")?;
+            net_cfg.http_client.install_as_global()?;
+            net_cfg.auth.clone().install_as_global()?;
             let raft_cfg: RaftConfig = cfg.get("raft")?;
 
             match role {
@@ -158,8 +175,8 @@ pub async fn node_main<Tx: TxTrait + Serialize + for<'de> Deserialize<'de> + 'st
                     info!("Quitting.");
                     storage.shutdown().await?;
                 }
-                Role::Miner => {
-                    bail!("Role cannot be miner.");
+                Role::Miner | Role::Observer => {
+                    bail!("Role cannot be miner or observer.");
                 }
             }
         }
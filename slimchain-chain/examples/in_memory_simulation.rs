@@ -0,0 +1,256 @@
+//! Simulate a raft network of one miner, two storage nodes and two client nodes in one process,
+//! with the in-memory databases and without the networking.
+//!
+//! The first storage node executes the txs for the miner, and every node verifies and commits
+//! the blocks proposed, like in the real network.
+//!
+//! Run it by `cargo run -p slimchain-chain --example in_memory_simulation`.
+
+use futures::{channel::mpsc::unbounded, prelude::*};
+use rand::SeedableRng;
+use slimchain_chain::{
+    behavior::{
+        commit_block, commit_block_storage_node, propose_block, verify_block, TxExecuteStream,
+    },
+    block::BlockTrait,
+    config::{ChainConfig, MinerConfig, StorageMode},
+    conflict_check::ConflictCheck,
+    consensus::{
+        raft::{create_new_block, verify_consensus, Block},
+        Consensus,
+    },
+    db::{read_pool::DEFAULT_READ_THREADS, DBPtr, DbBackend, DB},
+    latest::{LatestBlockHeaderPtr, LatestTxCount, LatestTxCountPtr},
+    snapshot::Snapshot,
+};
+use slimchain_common::{
+    basic::{ShardId, U256},
+    ed25519::Keypair,
+    error::Result,
+    tx_req::{caller_address_from_pk, TxRequest},
+};
+use slimchain_tx_engine::TxEngine;
+use slimchain_tx_engine_simple::SimpleTxEngineWorker;
+use slimchain_tx_state::{StorageTxTrie, TxTrie, TxTrieTrait};
+use slimchain_utils::contract::{contract_address, Contract, Token};
+use std::{path::PathBuf, time::Duration};
+
+const TOTAL_TXS: u64 = 10;
+
+struct SimNode<TxTrie: TxTrieTrait> {
+    name: &'static str,
+    db: DBPtr,
+    snapshot: Snapshot<Block, TxTrie>,
+    blk_latest: LatestBlockHeaderPtr,
+    tx_latest: LatestTxCountPtr,
+}
+
+impl SimNode<TxTrie> {
+    fn client(name: &'static str, chain_cfg: &ChainConfig) -> Result<Self> {
+        let db = DB::open_memory(DEFAULT_READ_THREADS);
+        let snapshot = Snapshot::<Block, TxTrie>::load_from_db(&db, chain_cfg.state_len)?;
+        Ok(Self::new(name, db, snapshot))
+    }
+}
+
+impl SimNode<StorageTxTrie> {
+    fn storage(name: &'static str, chain_cfg: &ChainConfig) -> Result<Self> {
+        let db = DB::open_memory(DEFAULT_READ_THREADS);
+        let snapshot = Snapshot::<Block, StorageTxTrie>::load_from_db(
+            &db,
+            chain_cfg.state_len,
+            ShardId::default(),
+        )?;
+        Ok(Self::new(name, db, snapshot))
+    }
+}
+
+impl<TxTrie: TxTrieTrait> SimNode<TxTrie> {
+    fn new(name: &'static str, db: DBPtr, snapshot: Snapshot<Block, TxTrie>) -> Self {
+        let blk_latest = snapshot.to_latest_block_header();
+        Self {
+            name,
+            db,
+            snapshot,
+            blk_latest,
+            tx_latest: LatestTxCount::new(0),
+        }
+    }
+
+    fn report(&self) -> Result<()> {
+        let stats = self.db.storage_stats()?;
+        let latest_blk = self.snapshot.get_latest_block().expect("No latest block.");
+        println!(
+            "{}: height={}, state_root={}, blocks={}, txs={}, state_nodes={}",
+            self.name,
+            latest_blk.block_height(),
+            latest_blk.state_root(),
+            stats.block_count,
+            stats.tx_count,
+            stats.state_node_count,
+        );
+        Ok(())
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let chain_cfg = ChainConfig {
+        conflict_check: ConflictCheck::SSI,
+        state_len: 2,
+        consensus: Consensus::Raft,
+        index_tx_location: true,
+        index_addresses: false,
+        checkpoint_interval: None,
+        fast_sync: false,
+        verify_proposer: false,
+        proposer_keys: Vec::new(),
+        proposer_peers: Vec::new(),
+        tx_status_ttl: Duration::from_secs(60),
+        tx_sig: Default::default(),
+        full_validation: false,
+        db_read_threads: DEFAULT_READ_THREADS,
+        db_backend: DbBackend::Memory,
+        disable_db_journal: false,
+        storage_mode: StorageMode::Full,
+        full_block_window: 1024,
+    };
+    let miner_cfg = MinerConfig {
+        compress_trie: true,
+        max_txs: 2,
+        max_block_gas: None,
+        min_txs: 1,
+        max_block_interval: Duration::from_millis(100),
+        max_tx_age_blocks: None,
+        nonce_ordering: false,
+        deterministic_assembly: false,
+        proposer_keypair: None,
+        proposer_id: None,
+        verify_threads: 0,
+        mempool: Default::default(),
+        cross_shard_timeout: Duration::from_secs(5),
+    };
+
+    let contract_file = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .parent()
+        .expect("No parent dir.")
+        .join("contracts/build/contracts/SimpleStorage.json");
+    let contract = Contract::from_json_file(&contract_file)?;
+    let mut rng = rand::rngs::StdRng::seed_from_u64(1u64);
+    let keypair = Keypair::generate(&mut rng);
+    let contract_address = contract_address(
+        caller_address_from_pk(&keypair.public),
+        U256::from(0).into(),
+    );
+
+    let mut miner = SimNode::client("miner", &chain_cfg)?;
+    let mut storages = vec![
+        SimNode::storage("storage0", &chain_cfg)?,
+        SimNode::storage("storage1", &chain_cfg)?,
+    ];
+    let mut clients = vec![
+        SimNode::client("client0", &chain_cfg)?,
+        SimNode::client("client1", &chain_cfg)?,
+    ];
+
+    let engine = TxEngine::new(2, || {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(1u64);
+        Box::new(SimpleTxEngineWorker::new(Keypair::generate(&mut rng)))
+    });
+    let (mut req_tx, req_rx) = unbounded();
+    let mut tx_rx = TxExecuteStream::new(req_rx, engine, &storages[0].db, &storages[0].blk_latest);
+
+    let mut tx_reqs = vec![TxRequest::Create {
+        nonce: U256::from(0).into(),
+        code: contract.code().clone(),
+    }];
+    for i in 1..TOTAL_TXS {
+        tx_reqs.push(TxRequest::Call {
+            address: contract_address,
+            nonce: U256::from(i).into(),
+            data: contract.encode_tx_input(
+                "set",
+                &[Token::Uint(U256::from(i)), Token::Uint(U256::from(i))],
+            )?,
+        });
+    }
+
+    for tx_req in tx_reqs {
+        req_tx.send(tx_req.sign(&keypair, 0)).await?;
+        let blk_proposal = match propose_block(
+            &chain_cfg,
+            &miner_cfg,
+            &mut miner.snapshot,
+            &mut tx_rx,
+            &mut Vec::new(),
+            false,
+            create_new_block,
+        )
+        .await?
+        {
+            Some(blk_proposal) => blk_proposal,
+            None => continue,
+        };
+
+        for storage in storages.iter_mut() {
+            let update = verify_block(
+                &chain_cfg,
+                &mut storage.snapshot,
+                &blk_proposal,
+                verify_consensus,
+            )
+            .await?;
+            commit_block_storage_node(
+                &chain_cfg,
+                &blk_proposal,
+                &update,
+                &storage.db,
+                &storage.blk_latest,
+                &storage.tx_latest,
+            )
+            .await?;
+        }
+        for client in clients.iter_mut() {
+            verify_block(
+                &chain_cfg,
+                &mut client.snapshot,
+                &blk_proposal,
+                verify_consensus,
+            )
+            .await?;
+            commit_block(
+                &chain_cfg,
+                &blk_proposal,
+                &client.db,
+                &client.blk_latest,
+                &client.tx_latest,
+            )
+            .await?;
+        }
+        commit_block(
+            &chain_cfg,
+            &blk_proposal,
+            &miner.db,
+            &miner.blk_latest,
+            &miner.tx_latest,
+        )
+        .await?;
+    }
+
+    miner.report()?;
+    for storage in storages.iter() {
+        storage.report()?;
+        assert_eq!(
+            storage.snapshot.get_latest_block(),
+            miner.snapshot.get_latest_block()
+        );
+    }
+    for client in clients.iter() {
+        client.report()?;
+        assert_eq!(
+            client.snapshot.get_latest_block(),
+            miner.snapshot.get_latest_block()
+        );
+    }
+    Ok(())
+}
@@ -1,6 +1,6 @@
 use serde::{Deserialize, Serialize};
 use slimchain_common::{
-    basic::{Address, BlockDistance, BlockHeight},
+    basic::{Address, BlockHeight},
     rw_set::{TxReadSet, TxWriteData},
 };
 
@@ -43,8 +43,9 @@ impl AccessMap {
     }
 
     pub fn oldest_block_height(&self) -> BlockHeight {
-        let dist = BlockDistance::from(self.read_map.len() as i64 - 1);
-        self.block_height - dist
+        self.block_height
+            .checked_sub(self.read_map.len() as u64 - 1)
+            .expect("AccessMap holds more blocks than its height.")
     }
 
     pub fn get_read_rev(&self, acc_addr: Address) -> Option<&ReadRevAccessItem> {
@@ -0,0 +1,671 @@
+use slimchain_common::{
+    basic::{Address, Nonce},
+    collections::HashMap,
+    rw_set::{TxReadSet, TxWriteData},
+    tx::TxTrait,
+};
+use slimchain_tx_state::TxProposal;
+use slimchain_utils::record_event;
+use std::{cmp::Reverse, collections::BTreeMap};
+
+pub struct NonceOrdering<T> {
+    /// Proposals ready to be included, ordered by nonce within each account.
+    pub included: Vec<T>,
+    /// Proposals waiting for a lower nonce from the same account, in arrival order.
+    pub pending: Vec<T>,
+    /// Proposals with a stale or duplicated nonce.
+    pub dropped: Vec<T>,
+}
+
+/// Order the proposals by the nonce of each caller.
+///
+/// `next_nonce` returns the next nonce expected for an account by the committed state, e.g.,
+/// its state nonce advanced by the committed txs. Only if it is unknown and `None` is returned,
+/// the smallest nonce seen from that account starts the sequence.
+/// A proposal is included only if all lower nonces from the same account are either
+/// committed or included earlier. For the same nonce, the first arrived proposal wins.
+pub fn order_by_nonce<T, Tx: TxTrait>(
+    proposals: impl IntoIterator<Item = T>,
+    get_tx: impl Fn(&T) -> &Tx,
+    next_nonce: impl Fn(Address) -> Option<Nonce>,
+) -> NonceOrdering<T> {
+    let mut dropped = Vec::new();
+    let mut arrivals: Vec<(Address, Nonce)> = Vec::new();
+    let mut groups: HashMap<Address, BTreeMap<Nonce, (usize, T)>> = HashMap::new();
+    let mut expected: HashMap<Address, Option<Nonce>> = HashMap::new();
+
+    for proposal in proposals {
+        let tx = get_tx(&proposal);
+        let caller = tx.tx_caller();
+        let nonce = tx.tx_input().nonce();
+        let next = *expected.entry(caller).or_insert_with(|| next_nonce(caller));
+        if matches!(next, Some(next) if nonce < next) {
+            dropped.push(proposal);
+            continue;
+        }
+
+        let group = groups.entry(caller).or_default();
+        if group.contains_key(&nonce) {
+            dropped.push(proposal);
+            continue;
+        }
+        group.insert(nonce, (arrivals.len(), proposal));
+        arrivals.push((caller, nonce));
+    }
+
+    let mut included = Vec::with_capacity(arrivals.len());
+    for (caller, nonce) in arrivals {
+        let group = match groups.get_mut(&caller) {
+            Some(group) => group,
+            None => continue,
+        };
+        let next = expected
+            .get_mut(&caller)
+            .expect("order_by_nonce: expected nonce not found");
+        let mut next_value = match next {
+            Some(next) => *next,
+            None => match group.keys().next() {
+                Some(&first) => first,
+                None => continue,
+            },
+        };
+        if nonce != next_value {
+            continue;
+        }
+
+        while let Some((_, proposal)) = group.remove(&next_value) {
+            included.push(proposal);
+            // The nonce is chosen by the caller, so it may be the max one, which ends the run.
+            match next_value.checked_add(Nonce::from(1)) {
+                Some(value) => next_value = value,
+                None => break,
+            }
+        }
+        *next = Some(next_value);
+    }
+
+    let mut pending: Vec<(usize, T)> = groups
+        .into_iter()
+        .flat_map(|(_, group)| group.into_iter().map(|(_, v)| v))
+        .collect();
+    pending.sort_by_key(|(idx, _)| *idx);
+
+    NonceOrdering {
+        included,
+        pending: pending.into_iter().map(|(_, proposal)| proposal).collect(),
+        dropped,
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum BlockConflict {
+    /// Both txs write the same item with different values.
+    WriteWrite,
+    /// The tx reads an item written by a previous tx in the same block.
+    ReadWrite,
+}
+
+impl BlockConflict {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::WriteWrite => "write_write",
+            Self::ReadWrite => "read_write",
+        }
+    }
+}
+
+/// Detect the conflicts between the txs included in the same block.
+///
+/// All txs in one block are executed independently against the same pre-state.
+/// Hence, a tx conflicts with the previous ones if it reads what they write, or if
+/// it writes a different value to what they write.
+#[derive(Debug, Default, Clone)]
+pub struct BlockConflictChecker {
+    writes: TxWriteData,
+}
+
+impl BlockConflictChecker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn check(&self, reads: &TxReadSet, writes: &TxWriteData) -> Option<BlockConflict> {
+        for (acc_addr, acc_write) in writes.iter() {
+            let prev = match self.writes.get(acc_addr) {
+                Some(prev) => prev,
+                None => continue,
+            };
+
+            if matches!((&prev.nonce, &acc_write.nonce), (Some(a), Some(b)) if a != b)
+                || matches!((&prev.code, &acc_write.code), (Some(a), Some(b)) if a != b)
+            {
+                return Some(BlockConflict::WriteWrite);
+            }
+
+            if (prev.reset_values && (acc_write.reset_values || !acc_write.values.is_empty()))
+                || (acc_write.reset_values && !prev.values.is_empty())
+            {
+                return Some(BlockConflict::WriteWrite);
+            }
+
+            for (key, value) in acc_write.values.iter() {
+                if matches!(prev.values.get(key), Some(prev_value) if prev_value != value) {
+                    return Some(BlockConflict::WriteWrite);
+                }
+            }
+        }
+
+        for (acc_addr, acc_read) in reads.iter() {
+            let prev = match self.writes.get(acc_addr) {
+                Some(prev) => prev,
+                None => continue,
+            };
+
+            if (acc_read.get_nonce() && prev.has_nonce())
+                || (acc_read.get_code() && prev.has_code())
+            {
+                return Some(BlockConflict::ReadWrite);
+            }
+
+            if !acc_read.get_values().is_empty() && prev.has_reset_values() {
+                return Some(BlockConflict::ReadWrite);
+            }
+
+            if acc_read
+                .value_iter()
+                .any(|key| prev.values.contains_key(key))
+            {
+                return Some(BlockConflict::ReadWrite);
+            }
+        }
+
+        None
+    }
+
+    pub fn add(&mut self, writes: &TxWriteData) {
+        self.writes.merge(writes);
+    }
+
+    /// Check the tx against the previous ones and report the conflict if any.
+    pub fn check_tx<Tx: TxTrait>(&self, tx: &Tx) -> Option<BlockConflict> {
+        let conflict = self.check(tx.tx_reads(), tx.tx_writes())?;
+        record_event!("tx_block_conflict", "tx_id": tx.id(), "kind": conflict.as_str());
+        Some(conflict)
+    }
+
+    /// Check the tx against the previous ones. Add its writes if there is no conflict.
+    pub fn check_and_add<Tx: TxTrait>(&mut self, tx: &Tx) -> Option<BlockConflict> {
+        let conflict = self.check_tx(tx);
+        if conflict.is_none() {
+            self.add(tx.tx_writes());
+        }
+        conflict
+    }
+}
+
+pub struct ConflictFilter<T> {
+    /// Proposals without conflicts, in the original order.
+    pub included: Vec<T>,
+    /// Proposals conflicting with the earlier ones, to be retried on the new state.
+    pub deferred: Vec<T>,
+}
+
+/// Split the ordered candidates into the ones to be included in the block and the
+/// ones deferred due to the conflicts with the earlier candidates.
+pub fn defer_conflicting<T, Tx: TxTrait>(
+    candidates: impl IntoIterator<Item = T>,
+    get_tx: impl Fn(&T) -> &Tx,
+) -> ConflictFilter<T> {
+    let mut checker = BlockConflictChecker::new();
+    let mut included = Vec::new();
+    let mut deferred = Vec::new();
+
+    for candidate in candidates {
+        match checker.check_and_add(get_tx(&candidate)) {
+            Some(_) => deferred.push(candidate),
+            None => included.push(candidate),
+        }
+    }
+
+    ConflictFilter { included, deferred }
+}
+
+/// Track the gas used by the txs included in a block against the optional limit.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct BlockGasMeter {
+    limit: Option<u64>,
+    used: u64,
+}
+
+impl BlockGasMeter {
+    pub fn new(limit: Option<u64>) -> Self {
+        Self { limit, used: 0 }
+    }
+
+    pub fn used(&self) -> u64 {
+        self.used
+    }
+
+    /// Whether the tx fits into the gas left in the block.
+    pub fn fits<Tx: TxTrait>(&self, tx: &Tx) -> bool {
+        match self.limit {
+            Some(limit) => self.used.saturating_add(tx.gas_used()) <= limit,
+            None => true,
+        }
+    }
+
+    pub fn add<Tx: TxTrait>(&mut self, tx: &Tx) {
+        self.used = self.used.saturating_add(tx.gas_used());
+    }
+}
+
+/// The limits of a block packed by [`assemble_block`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct PackLimits {
+    pub max_txs: usize,
+    /// If missing, the gas is not limited.
+    pub max_block_gas: Option<u64>,
+}
+
+/// Pack a block from the candidates, which are verified and checked against the conflicts with
+/// the earlier blocks, so that the block is a pure function of the candidates and the limits
+/// regardless of their arrival order.
+///
+/// The candidates are ordered by the fee (the higher first), the caller, the nonce and the tx
+/// hash, and are packed greedily: each one is included unless it conflicts with the ones
+/// included before, or exceeds the limits left. Return the included ones and the leftovers,
+/// both in that order.
+pub fn assemble_block<Tx: TxTrait>(
+    mut candidates: Vec<TxProposal<Tx>>,
+    limits: &PackLimits,
+) -> (Vec<TxProposal<Tx>>, Vec<TxProposal<Tx>>) {
+    candidates.sort_by_cached_key(|candidate| {
+        let tx = &candidate.tx;
+        (
+            Reverse(tx.fee()),
+            tx.tx_caller(),
+            tx.tx_input().nonce(),
+            tx.to_digest(),
+        )
+    });
+
+    let mut checker = BlockConflictChecker::new();
+    let mut gas_meter = BlockGasMeter::new(limits.max_block_gas);
+    let mut included = Vec::with_capacity(candidates.len().min(limits.max_txs));
+    let mut leftovers = Vec::new();
+    for candidate in candidates {
+        if included.len() >= limits.max_txs
+            || !gas_meter.fits(&candidate.tx)
+            || checker.check_tx(&candidate.tx).is_some()
+        {
+            leftovers.push(candidate);
+            continue;
+        }
+        gas_meter.add(&candidate.tx);
+        checker.add(candidate.tx.tx_writes());
+        included.push(candidate);
+    }
+    (included, leftovers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::DummyTx;
+    use rand::seq::SliceRandom;
+    use slimchain_common::{
+        basic::{StateKey, StateValue, H160, H256, U256},
+        create_tx_read_set, create_tx_write_set,
+        tx_req::TxRequest,
+    };
+
+    fn proposal(id: u64, caller: u64, nonce: u64) -> TxProposal<DummyTx> {
+        DummyTx::new(id, caller, nonce).into_proposal()
+    }
+
+    fn rw_proposal(id: u64, reads: TxReadSet, writes: TxWriteData) -> TxProposal<DummyTx> {
+        let mut proposal = proposal(id, id, 0);
+        proposal.tx.reads = reads;
+        proposal.tx.writes = writes;
+        proposal
+    }
+
+    fn order(
+        proposals: Vec<TxProposal<DummyTx>>,
+        next_nonce: impl Fn(Address) -> Option<Nonce>,
+    ) -> NonceOrdering<TxProposal<DummyTx>> {
+        order_by_nonce(proposals, |p| &p.tx, next_nonce)
+    }
+
+    fn ids(proposals: &[TxProposal<DummyTx>]) -> Vec<u64> {
+        proposals.iter().map(|p| p.tx.id).collect()
+    }
+
+    #[test]
+    fn test_order_by_nonce_gap() {
+        let res = order(
+            vec![proposal(1, 1, 2), proposal(2, 1, 0), proposal(3, 1, 4)],
+            |_| Some(Nonce::zero()),
+        );
+        assert_eq!(ids(&res.included), vec![2]);
+        assert_eq!(ids(&res.pending), vec![1, 3]);
+        assert!(res.dropped.is_empty());
+
+        let res = order(vec![proposal(1, 1, 2), proposal(2, 1, 3)], |_| {
+            Some(Nonce::from(2))
+        });
+        assert_eq!(ids(&res.included), vec![1, 2]);
+        assert!(res.pending.is_empty());
+    }
+
+    #[test]
+    fn test_order_by_nonce_duplicate() {
+        let res = order(
+            vec![
+                proposal(1, 1, 1),
+                proposal(2, 1, 0),
+                proposal(3, 1, 1),
+                proposal(4, 1, 0),
+            ],
+            |_| None,
+        );
+        assert_eq!(ids(&res.included), vec![2, 1]);
+        assert!(res.pending.is_empty());
+        assert_eq!(ids(&res.dropped), vec![3, 4]);
+
+        let res = order(vec![proposal(1, 1, 0), proposal(2, 1, 1)], |_| {
+            Some(Nonce::from(1))
+        });
+        assert_eq!(ids(&res.included), vec![2]);
+        assert_eq!(ids(&res.dropped), vec![1]);
+    }
+
+    #[test]
+    fn test_order_by_nonce_max() {
+        let max_proposal = |id: u64, caller: u64, nonce: U256| {
+            let mut proposal = proposal(id, caller, 0);
+            proposal.tx.input = TxRequest::Create {
+                nonce: Nonce(nonce),
+                code: Default::default(),
+            };
+            proposal
+        };
+        let res = order(
+            vec![
+                max_proposal(1, 1, U256::MAX),
+                max_proposal(2, 1, U256::MAX - 1),
+            ],
+            |_| Some(Nonce(U256::MAX - 1)),
+        );
+        assert_eq!(ids(&res.included), vec![2, 1]);
+        assert!(res.pending.is_empty());
+        assert!(res.dropped.is_empty());
+    }
+
+    #[test]
+    fn test_order_by_nonce_interleaving() {
+        let res = order(
+            vec![
+                proposal(1, 1, 1),
+                proposal(2, 2, 5),
+                proposal(3, 1, 0),
+                proposal(4, 2, 7),
+                proposal(5, 3, 0),
+                proposal(6, 2, 6),
+            ],
+            |addr| {
+                if addr == Address::from(H160::from_low_u64_be(2)) {
+                    Some(Nonce::from(5))
+                } else {
+                    None
+                }
+            },
+        );
+        assert_eq!(ids(&res.included), vec![2, 6, 4, 3, 1, 5]);
+        assert!(res.pending.is_empty());
+        assert!(res.dropped.is_empty());
+    }
+
+    #[test]
+    fn test_block_conflict_write_write() {
+        let res = defer_conflicting(
+            vec![
+                rw_proposal(
+                    1,
+                    Default::default(),
+                    create_tx_write_set! {
+                        "0000000000000000000000000000000000000000" => {
+                            values: {
+                                "0000000000000000000000000000000000000000000000000000000000000001" => 1,
+                            }
+                        },
+                    },
+                ),
+                rw_proposal(
+                    2,
+                    Default::default(),
+                    create_tx_write_set! {
+                        "0000000000000000000000000000000000000000" => {
+                            values: {
+                                "0000000000000000000000000000000000000000000000000000000000000001" => 2,
+                            }
+                        },
+                    },
+                ),
+                rw_proposal(
+                    3,
+                    Default::default(),
+                    create_tx_write_set! {
+                        "0000000000000000000000000000000000000000" => {
+                            values: {
+                                "0000000000000000000000000000000000000000000000000000000000000001" => 1,
+                                "0000000000000000000000000000000000000000000000000000000000000002" => 2,
+                            }
+                        },
+                    },
+                ),
+                rw_proposal(
+                    4,
+                    Default::default(),
+                    create_tx_write_set! {
+                        "0000000000000000000000000000000000000000" => {
+                            reset_values: true,
+                        },
+                    },
+                ),
+            ],
+            |p| &p.tx,
+        );
+        assert_eq!(ids(&res.included), vec![1, 3]);
+        assert_eq!(ids(&res.deferred), vec![2, 4]);
+    }
+
+    #[test]
+    fn test_block_conflict_read_write() {
+        let mut checker = BlockConflictChecker::new();
+        checker.add(&create_tx_write_set! {
+            "0000000000000000000000000000000000000000" => {
+                nonce: 1,
+                values: {
+                    "0000000000000000000000000000000000000000000000000000000000000001" => 1,
+                }
+            },
+        });
+
+        let reads = create_tx_read_set! {
+            "0000000000000000000000000000000000000000" => {
+                values: [
+                    "0000000000000000000000000000000000000000000000000000000000000002",
+                ]
+            },
+        };
+        assert_eq!(checker.check(&reads, &Default::default()), None);
+
+        let reads = create_tx_read_set! {
+            "0000000000000000000000000000000000000000" => {
+                values: [
+                    "0000000000000000000000000000000000000000000000000000000000000001",
+                ]
+            },
+        };
+        assert_eq!(
+            checker.check(&reads, &Default::default()),
+            Some(BlockConflict::ReadWrite)
+        );
+
+        let reads = create_tx_read_set! {
+            "0000000000000000000000000000000000000000" => {
+                nonce: true,
+            },
+        };
+        assert_eq!(
+            checker.check(&reads, &Default::default()),
+            Some(BlockConflict::ReadWrite)
+        );
+
+        let reads = create_tx_read_set! {
+            "0000000000000000000000000000000000000001" => {
+                values: [
+                    "0000000000000000000000000000000000000000000000000000000000000001",
+                ]
+            },
+        };
+        assert_eq!(checker.check(&reads, &Default::default()), None);
+    }
+
+    #[test]
+    fn test_block_gas_meter() {
+        let tx = |gas_used| {
+            let mut proposal = proposal(1, 1, 0);
+            proposal.tx.gas_used = gas_used;
+            proposal.tx
+        };
+
+        let mut meter = BlockGasMeter::new(Some(100));
+        assert!(meter.fits(&tx(60)));
+        meter.add(&tx(60));
+        assert!(meter.fits(&tx(40)));
+        assert!(!meter.fits(&tx(41)));
+        meter.add(&tx(40));
+        assert_eq!(100, meter.used());
+        assert!(meter.fits(&tx(0)));
+        assert!(!meter.fits(&tx(1)));
+
+        let mut meter = BlockGasMeter::new(None);
+        meter.add(&tx(u64::MAX));
+        assert!(meter.fits(&tx(u64::MAX)));
+        meter.add(&tx(1));
+        assert_eq!(u64::MAX, meter.used());
+    }
+
+    fn pack(
+        candidates: Vec<TxProposal<DummyTx>>,
+        max_txs: usize,
+        max_block_gas: Option<u64>,
+    ) -> (Vec<u64>, Vec<u64>) {
+        let limits = PackLimits {
+            max_txs,
+            max_block_gas,
+        };
+        let (included, leftovers) = assemble_block(candidates, &limits);
+        (ids(&included), ids(&leftovers))
+    }
+
+    fn fee_proposal(id: u64, caller: u64, nonce: u64, fee: u64) -> TxProposal<DummyTx> {
+        let mut proposal = proposal(id, caller, nonce);
+        proposal.tx.fee = fee;
+        proposal
+    }
+
+    fn write_proposal(id: u64, caller: u64, key: u64, value: u64) -> TxProposal<DummyTx> {
+        let mut proposal = proposal(id, caller, 0);
+        proposal.tx.writes.add_value(
+            Address::default(),
+            StateKey(H256::from_low_u64_be(key)),
+            StateValue(H256::from_low_u64_be(value)),
+        );
+        proposal
+    }
+
+    #[test]
+    fn test_assemble_block_order() {
+        // By the fee first, then the caller, the nonce and the tx hash.
+        let candidates = vec![
+            fee_proposal(1, 2, 0, 10),
+            fee_proposal(2, 1, 1, 10),
+            fee_proposal(3, 3, 0, 20),
+            fee_proposal(4, 1, 0, 10),
+            fee_proposal(5, 1, 0, 0),
+            fee_proposal(6, 1, 0, 10),
+        ];
+        assert_eq!(pack(candidates, 10, None), (vec![3, 4, 6, 2, 1, 5], vec![]));
+        assert!(pack(Vec::new(), 10, None).0.is_empty());
+    }
+
+    #[test]
+    fn test_assemble_block_arrival_order() {
+        let candidates = vec![
+            fee_proposal(1, 1, 0, 5),
+            write_proposal(2, 2, 1, 1),
+            write_proposal(3, 3, 1, 2),
+            fee_proposal(4, 4, 3, 0),
+            fee_proposal(5, 5, 0, 7),
+            write_proposal(6, 6, 2, 1),
+        ];
+        let expect = pack(candidates.clone(), 4, Some(100));
+
+        let mut rng = rand::thread_rng();
+        for _ in 0..20 {
+            let mut shuffled = candidates.clone();
+            shuffled.shuffle(&mut rng);
+            assert_eq!(pack(shuffled, 4, Some(100)), expect);
+        }
+        let mut reversed = candidates;
+        reversed.reverse();
+        assert_eq!(pack(reversed, 4, Some(100)), expect);
+    }
+
+    #[test]
+    fn test_assemble_block_limits() {
+        let candidates = || (1..=5).map(|id| fee_proposal(id, id, 0, 10 - id)).collect();
+        assert_eq!(pack(candidates(), 3, None), (vec![1, 2, 3], vec![4, 5]));
+        assert_eq!(pack(candidates(), 5, None), (vec![1, 2, 3, 4, 5], vec![]));
+
+        // The ones exceeding the gas left are skipped, while the later ones still fit.
+        let gas = |mut proposal: TxProposal<DummyTx>, gas_used| {
+            proposal.tx.gas_used = gas_used;
+            proposal
+        };
+        let candidates = || {
+            vec![
+                gas(fee_proposal(1, 1, 0, 4), 60),
+                gas(fee_proposal(2, 2, 0, 3), 50),
+                gas(fee_proposal(3, 3, 0, 2), 40),
+                gas(fee_proposal(4, 4, 0, 1), 101),
+            ]
+        };
+        assert_eq!(pack(candidates(), 10, Some(100)), (vec![1, 3], vec![2, 4]));
+        assert_eq!(pack(candidates(), 1, Some(100)), (vec![1], vec![2, 3, 4]));
+        assert_eq!(pack(candidates(), 10, None), (vec![1, 2, 3, 4], vec![]));
+    }
+
+    #[test]
+    fn test_assemble_block_conflict() {
+        // The conflicting ones are left over in favor of the earlier ones in the order.
+        let candidates = vec![
+            write_proposal(1, 3, 1, 1),
+            write_proposal(2, 1, 1, 2),
+            write_proposal(3, 2, 1, 2),
+            write_proposal(4, 4, 2, 1),
+        ];
+        assert_eq!(pack(candidates, 10, None), (vec![2, 3, 4], vec![1]));
+
+        // A conflicting one does not count against the limits.
+        let candidates = vec![
+            write_proposal(1, 1, 1, 1),
+            write_proposal(2, 2, 1, 2),
+            write_proposal(3, 3, 2, 1),
+        ];
+        assert_eq!(pack(candidates, 2, None), (vec![1, 3], vec![2]));
+    }
+}
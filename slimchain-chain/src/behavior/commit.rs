@@ -1,13 +1,23 @@
 use crate::{
     block::BlockTrait,
     block_proposal::BlockProposal,
+    commit_event::COMMIT_EVENTS,
+    config::{ChainConfig, ObserverConfig, StorageMode},
     db::{DBPtr, Transaction},
     latest::{LatestBlockHeaderPtr, LatestTxCountPtr},
+    loader::BlockLoaderTrait,
+    metrics::{update_db_stats, CHAIN_METRICS, DB_STATS_INTERVAL},
+    tx_status::TX_STATUS,
 };
-use serde::Serialize;
-use slimchain_common::{error::Result, tx::TxTrait};
+use serde::{Deserialize, Serialize};
+use slimchain_common::{basic::BlockHeight, error::Result, tx::TxTrait};
 use slimchain_tx_state::TxStateUpdate;
 use slimchain_utils::record_event;
+use std::ops::Range;
+
+/// Max number of the block bodies pruned in one commit, so that switching a long chain to the
+/// headers storage mode prunes its history gradually.
+const MAX_PRUNED_BLOCKS_PER_COMMIT: u64 = 64;
 
 fn record_txs<Tx, Block>(
     blk_proposal: &BlockProposal<Block, Tx>,
@@ -21,11 +31,18 @@ fn record_txs<Tx, Block>(
     info!("Commit {} TX.", tx_len);
     latest_tx_count.add(tx_len);
     let tx_ids: Vec<_> = txs.iter().map(|tx| tx.id()).collect();
-    record_event!("tx_commit", "tx_ids": tx_ids, "height": blk_proposal.get_block_height().0);
+    let gas_used = txs
+        .iter()
+        .fold(0u64, |acc, tx| acc.saturating_add(tx.gas_used()));
+    CHAIN_METRICS.record_block_commit(&tx_ids);
+    TX_STATUS.record_block_commit(blk_proposal.get_block_height(), &tx_ids);
+    record_event!("tx_commit", "tx_ids": tx_ids, "height": blk_proposal.get_block_height().0, "gas_used": gas_used);
+    COMMIT_EVENTS.publish_block_commit(blk_proposal);
 }
 
-#[tracing::instrument(level = "info", skip(blk_proposal, db, latest_block_header, latest_tx_count), fields(height = blk_proposal.get_block_height().0), err)]
+#[tracing::instrument(level = "info", skip(chain_cfg, blk_proposal, db, latest_block_header, latest_tx_count), fields(height = blk_proposal.get_block_height().0), err)]
 pub async fn commit_block<Tx, Block>(
+    chain_cfg: &ChainConfig,
     blk_proposal: &BlockProposal<Block, Tx>,
     db: &DBPtr,
     latest_block_header: &LatestBlockHeaderPtr,
@@ -33,19 +50,98 @@ pub async fn commit_block<Tx, Block>(
 ) -> Result<()>
 where
     Tx: TxTrait + Serialize,
-    Block: BlockTrait + Serialize,
+    Block: BlockTrait + Serialize + for<'de> Deserialize<'de> + 'static,
 {
-    let mut db_tx = Transaction::with_capacity(1);
+    let mut db_tx = Transaction::with_capacity(2);
     let blk = blk_proposal.get_block();
     db_tx.insert_block(blk)?;
+    db_tx.insert_latest_block_header(blk.block_header())?;
+    if chain_cfg.index_tx_location {
+        db_tx.insert_tx_locations(blk)?;
+    }
+    if chain_cfg.storage_mode == StorageMode::Headers {
+        prune_blocks::<Block>(chain_cfg, blk.block_height(), db, &mut db_tx).await?;
+    }
     db.write_async(db_tx).await?;
     latest_block_header.set_from_block(blk);
     record_txs(blk_proposal, latest_tx_count);
     Ok(())
 }
 
-#[tracing::instrument(level = "info", skip(blk_proposal, state_update, db, latest_block_header, latest_tx_count), fields(height = blk_proposal.get_block_height().0), err)]
+/// Prune the bodies of the blocks out of the window of the full blocks at `height`.
+async fn prune_blocks<Block>(
+    chain_cfg: &ChainConfig,
+    height: BlockHeight,
+    db: &DBPtr,
+    db_tx: &mut Transaction,
+) -> Result<()>
+where
+    Block: BlockTrait + for<'de> Deserialize<'de> + 'static,
+{
+    let prune_to = height.0.saturating_sub(chain_cfg.full_block_window);
+    let blocks = db
+        .read_async(move |db| {
+            let pruned_height = db.get_pruned_height()?.0;
+            let prune_to = prune_to.min(pruned_height + MAX_PRUNED_BLOCKS_PER_COMMIT);
+            (pruned_height + 1..=prune_to)
+                .map(|height| db.get_block(height.into()))
+                .collect::<Result<Vec<Block>>>()
+        })
+        .await?;
+    if let Some(last) = blocks.last() {
+        debug!(
+            "Prune the bodies of {} blocks up to height {}.",
+            blocks.len(),
+            last.block_height()
+        );
+        for block in &blocks {
+            db_tx.prune_block(block)?;
+        }
+        db_tx.insert_pruned_height(last.block_height())?;
+    }
+    Ok(())
+}
+
+/// Insert the block and its txs along with their indexes.
+fn insert_block_storage_node<Tx, Block>(
+    chain_cfg: &ChainConfig,
+    blk_proposal: &BlockProposal<Block, Tx>,
+    db_tx: &mut Transaction,
+) -> Result<()>
+where
+    Tx: TxTrait + Serialize,
+    Block: BlockTrait + Serialize,
+{
+    let blk = blk_proposal.get_block();
+    let txs = blk_proposal.get_txs();
+
+    db_tx.insert_block(blk)?;
+    for (&tx_hash, tx) in blk.tx_list().iter().zip(txs.iter()) {
+        debug_assert_eq!(tx_hash, tx.to_digest());
+        db_tx.insert_tx(tx_hash, tx)?;
+    }
+    if chain_cfg.index_tx_location {
+        db_tx.insert_tx_locations(blk)?;
+    }
+    if chain_cfg.index_addresses {
+        db_tx.insert_address_txs(blk, txs)?;
+    }
+    Ok(())
+}
+
+async fn update_db_stats_if_due(db: &DBPtr, heights: Range<u64>) {
+    let height = match heights.clone().rev().find(|h| h % DB_STATS_INTERVAL == 0) {
+        Some(height) => BlockHeight::from(height),
+        None => return,
+    };
+    if let Err(e) = update_db_stats(db, height).await {
+        warn!("Failed to collect the storage statistics. Error: {}", e);
+    }
+}
+
+#[tracing::instrument(level = "info", skip(chain_cfg, blk_proposal, state_update, db, latest_block_header, latest_tx_count), fields(height = blk_proposal.get_block_height().0), err)]
 pub async fn commit_block_storage_node<Tx, Block>(
+    chain_cfg: &ChainConfig,
     blk_proposal: &BlockProposal<Block, Tx>,
     state_update: &TxStateUpdate,
     db: &DBPtr,
@@ -58,14 +154,98 @@ where
 {
     let mut db_tx = Transaction::new();
     let blk = blk_proposal.get_block();
-    let txs = blk_proposal.get_txs();
+
+    insert_block_storage_node(chain_cfg, blk_proposal, &mut db_tx)?;
+    db_tx.insert_latest_block_header(blk.block_header())?;
+    db_tx.update_state(state_update)?;
+
+    db.write_async(db_tx).await?;
+    latest_block_header.set_from_block(blk);
+    record_txs(blk_proposal, latest_tx_count);
+
+    let height = blk_proposal.get_block_height().0;
+    update_db_stats_if_due(db, height..height + 1).await;
+    Ok(())
+}
+
+/// Merge the state updates of the consecutive blocks into one, which writes only the nodes of
+/// the final state, except that the states of the last `retained` blocks are kept intact, so
+/// that they can still be read like the ones committed one by one.
+pub fn coalesce_state_updates(updates: &[&TxStateUpdate], retained: usize) -> TxStateUpdate {
+    let (merged, retained) = updates.split_at(updates.len().saturating_sub(retained));
+    let mut out = match merged.split_first() {
+        Some((&first, rest)) => rest
+            .iter()
+            .fold(first.clone(), |update, &next| update.merge(next)),
+        None => TxStateUpdate::default(),
+    };
+    for &update in retained {
+        out.extend(update.clone());
+    }
+    out
+}
+
+/// Commit the consecutive blocks verified along with their state updates in one write, e.g.,
+/// when catching up. The state updates are coalesced by [`coalesce_state_updates`], keeping
+/// the states of the last `state_len` blocks.
+#[tracing::instrument(level = "info", skip(chain_cfg, blk_proposals, db, latest_block_header, latest_tx_count), fields(blocks = blk_proposals.len()), err)]
+pub async fn commit_blocks_storage_node<Tx, Block>(
+    chain_cfg: &ChainConfig,
+    blk_proposals: &[(BlockProposal<Block, Tx>, TxStateUpdate)],
+    db: &DBPtr,
+    latest_block_header: &LatestBlockHeaderPtr,
+    latest_tx_count: &LatestTxCountPtr,
+) -> Result<()>
+where
+    Tx: TxTrait + Serialize,
+    Block: BlockTrait + Serialize,
+{
+    let (first, last) = match (blk_proposals.first(), blk_proposals.last()) {
+        (Some((first, _)), Some((last, _))) => (first, last),
+        _ => return Ok(()),
+    };
+    let mut db_tx = Transaction::new();
+    for (blk_proposal, _) in blk_proposals {
+        insert_block_storage_node(chain_cfg, blk_proposal, &mut db_tx)?;
+    }
+    let last_blk = last.get_block();
+    db_tx.insert_latest_block_header(last_blk.block_header())?;
+    let updates: Vec<_> = blk_proposals.iter().map(|(_, update)| update).collect();
+    db_tx.update_state(&coalesce_state_updates(&updates, chain_cfg.state_len))?;
+
+    db.write_async(db_tx).await?;
+    latest_block_header.set_from_block(last_blk);
+    for (blk_proposal, _) in blk_proposals {
+        record_txs(blk_proposal, latest_tx_count);
+    }
+
+    let heights = first.get_block_height().0..last.get_block_height().0 + 1;
+    update_db_stats_if_due(db, heights).await;
+    Ok(())
+}
+
+#[tracing::instrument(level = "info", skip(observer_cfg, blk_proposal, db, latest_block_header, latest_tx_count), fields(height = blk_proposal.get_block_height().0), err)]
+pub async fn commit_block_observer_node<Tx, Block>(
+    observer_cfg: &ObserverConfig,
+    blk_proposal: &BlockProposal<Block, Tx>,
+    db: &DBPtr,
+    latest_block_header: &LatestBlockHeaderPtr,
+    latest_tx_count: &LatestTxCountPtr,
+) -> Result<()>
+where
+    Tx: TxTrait + Serialize,
+    Block: BlockTrait + Serialize,
+{
+    let mut db_tx = Transaction::new();
+    let blk = blk_proposal.get_block();
 
     db_tx.insert_block(blk)?;
-    for (&tx_hash, tx) in blk.tx_list().iter().zip(txs.iter()) {
-        debug_assert_eq!(tx_hash, tx.to_digest());
-        db_tx.insert_tx(tx_hash, tx)?;
+    db_tx.insert_latest_block_header(blk.block_header())?;
+    if observer_cfg.store_txs {
+        for (&tx_hash, tx) in blk.tx_list().iter().zip(blk_proposal.get_txs().iter()) {
+            db_tx.insert_tx(tx_hash, tx)?;
+        }
     }
-    db_tx.update_state(state_update)?;
 
     db.write_async(db_tx).await?;
     latest_block_header.set_from_block(blk);
@@ -1,4 +1,4 @@
-use crate::{db::DBPtr, latest::LatestBlockHeaderPtr};
+use crate::{db::DBPtr, latest::LatestBlockHeaderPtr, remote_state::RemoteStateFetcher};
 use futures::{prelude::*, ready, stream::Fuse};
 use pin_project::pin_project;
 use slimchain_common::{
@@ -7,12 +7,19 @@ use slimchain_common::{
     tx_req::SignedTxRequest,
 };
 use slimchain_tx_engine::{TxEngine, TxTask};
-use slimchain_tx_state::TxProposal;
+use slimchain_tx_state::{TxProposal, TxStateView};
+use slimchain_utils::backpressure::PipelineStage;
 use std::{
     pin::Pin,
+    sync::Arc,
     task::{Context, Poll},
 };
 
+/// Execute the tx requests from `input` by [`TxEngine`] and yield the tx proposals.
+///
+/// If a [`PipelineStage`] is attached, its depth is the number of the tx requests in the engine,
+/// including the executed ones not taken yet. No more tx requests are taken from `input` while
+/// it is saturated.
 #[pin_project]
 pub struct TxExecuteStream<Tx: TxTrait + 'static, Input: Stream<Item = SignedTxRequest>> {
     #[pin]
@@ -20,6 +27,8 @@ pub struct TxExecuteStream<Tx: TxTrait + 'static, Input: Stream<Item = SignedTxR
     engine: TxEngine<Tx>,
     db: DBPtr,
     latest_block_header: LatestBlockHeaderPtr,
+    stage: Option<Arc<PipelineStage>>,
+    remote_state: Option<Arc<RemoteStateFetcher>>,
 }
 
 impl<Tx: TxTrait, Input: Stream<Item = SignedTxRequest>> TxExecuteStream<Tx, Input> {
@@ -37,8 +46,21 @@ impl<Tx: TxTrait, Input: Stream<Item = SignedTxRequest>> TxExecuteStream<Tx, Inp
             engine,
             db,
             latest_block_header,
+            stage: None,
+            remote_state: None,
         }
     }
+
+    pub fn with_pipeline_stage(mut self, stage: Arc<PipelineStage>) -> Self {
+        self.stage = Some(stage);
+        self
+    }
+
+    /// Fetch the state nodes missing from `db` by `fetcher` instead of failing the txs.
+    pub fn with_remote_state(mut self, fetcher: Arc<RemoteStateFetcher>) -> Self {
+        self.remote_state = Some(fetcher);
+        self
+    }
 }
 
 impl<Tx: TxTrait, Input: Stream<Item = SignedTxRequest>> Stream for TxExecuteStream<Tx, Input> {
@@ -46,12 +68,29 @@ impl<Tx: TxTrait, Input: Stream<Item = SignedTxRequest>> Stream for TxExecuteStr
 
     fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
         let mut this = self.project();
-        while let Poll::Ready(Some(req)) = this.input.as_mut().poll_next(cx) {
+        let stage = this.stage.as_ref();
+        let set_depth = |depth: usize| {
+            if let Some(stage) = stage {
+                stage.set_depth(depth);
+            }
+        };
+
+        // The saturated stage is polled again once a result is taken.
+        while !stage.map_or(false, |stage| stage.is_saturated()) {
+            let req = match this.input.as_mut().poll_next(cx) {
+                Poll::Ready(Some(req)) => req,
+                _ => break,
+            };
             let latest_block_header = this.latest_block_header.clone();
-            let task = TxTask::new(this.db.clone(), req, move || -> (BlockHeight, H256) {
+            let state_view: Arc<dyn TxStateView + Sync + Send> = match this.remote_state.as_ref() {
+                Some(fetcher) => Arc::new(fetcher.state_view(this.db.clone(), req.id())),
+                None => this.db.clone(),
+            };
+            let task = TxTask::new(state_view, req, move || -> (BlockHeight, H256) {
                 latest_block_header.get_height_and_state_root()
             });
             this.engine.push_task(task);
+            set_depth(this.engine.remaining_tasks());
         }
 
         if this.input.is_done() && this.engine.remaining_tasks() == 0 {
@@ -59,6 +98,7 @@ impl<Tx: TxTrait, Input: Stream<Item = SignedTxRequest>> Stream for TxExecuteStr
         }
 
         let result = ready!(this.engine.poll_result(cx));
+        set_depth(this.engine.remaining_tasks());
         Poll::Ready(Some(result.tx_proposal))
     }
 }
@@ -1,8 +1,10 @@
 use crate::{
+    assemble::{assemble_block, BlockConflictChecker, BlockGasMeter, PackLimits},
     block::{BlockHeader, BlockTrait, BlockTxList},
     block_proposal::{BlockProposal, BlockProposalTrie},
     config::{ChainConfig, MinerConfig},
     snapshot::Snapshot,
+    tx_status::TX_STATUS,
 };
 use chrono::Utc;
 use futures::prelude::*;
@@ -25,12 +27,45 @@ enum TxTries {
     UncompressedTries(Vec<(BlockHeight, TxWriteSetTrie)>),
 }
 
-#[tracing::instrument(level = "info", skip(chain_cfg, miner_cfg, snapshot, tx_proposals, new_block_fn), fields(height = snapshot.current_height().0 + 1), err)]
+/// Include the tx in the block being assembled.
+fn include_tx<Tx: TxTrait, Block: BlockTrait>(
+    snapshot: &mut Snapshot<Block, TxTrie>,
+    tx_proposal: TxProposal<Tx>,
+    txs: &mut Vec<Tx>,
+    tx_tries: &mut TxTries,
+    writes: &mut TxWriteData,
+) {
+    let TxProposal { tx, write_trie } = tx_proposal;
+
+    snapshot.access_map.add_read(tx.tx_reads());
+    snapshot.access_map.add_write(tx.tx_writes());
+    writes.merge(tx.tx_writes());
+
+    match tx_tries {
+        TxTries::Diff(diffs) => {
+            let diff = snapshot.tx_trie.diff_missing_branches(&write_trie);
+            diffs.push(diff);
+        }
+        TxTries::UncompressedTries(tries) => {
+            tries.push((tx.tx_block_height(), write_trie));
+        }
+    }
+    txs.push(tx);
+}
+
+/// Assemble the next block from `tx_proposals`. If `pre_verified`, their signatures and write
+/// tries are checked by [`VerifyEngine`](slimchain_tx_engine::verify::VerifyEngine) already.
+///
+/// With `miner_cfg.deterministic_assembly`, the tx proposals collected are packed by
+/// [`assemble_block`] instead of in their arrival order.
+#[tracing::instrument(level = "info", skip(chain_cfg, miner_cfg, snapshot, tx_proposals, deferred_tx_proposals, new_block_fn), fields(height = snapshot.current_height().0 + 1), err)]
 pub async fn propose_block<Tx, Block, TxStream, NewBlockFn, NewBlockFnOutput>(
     chain_cfg: &ChainConfig,
     miner_cfg: &MinerConfig,
     snapshot: &mut Snapshot<Block, TxTrie>,
     tx_proposals: &mut TxStream,
+    deferred_tx_proposals: &mut Vec<TxProposal<Tx>>,
+    pre_verified: bool,
     new_block_fn: NewBlockFn,
 ) -> Result<Option<BlockProposal<Block, Tx>>>
 where
@@ -55,9 +90,12 @@ where
 
     snapshot.access_map.alloc_new_block();
     let mut writes = TxWriteData::default();
+    let mut block_conflict_checker = BlockConflictChecker::new();
+    let mut block_gas_meter = BlockGasMeter::new(miner_cfg.max_block_gas);
+    let mut candidates: Vec<TxProposal<Tx>> = Vec::new();
 
-    while txs.len() < miner_cfg.max_txs {
-        let tx_proposal = if txs.len() < miner_cfg.min_txs {
+    while txs.len() + candidates.len() < miner_cfg.max_txs {
+        let tx_proposal = if txs.len() + candidates.len() < miner_cfg.min_txs {
             tx_proposals.next().await
         } else {
             if Instant::now() > deadline {
@@ -73,13 +111,14 @@ where
             }
         };
 
-        let TxProposal { tx, write_trie } = match tx_proposal {
+        let tx_proposal = match tx_proposal {
             Some(tx_proposal) => tx_proposal,
             None => {
                 debug!("No tx proposal is available.");
                 return Ok(None);
             }
         };
+        let TxProposal { tx, write_trie } = &tx_proposal;
 
         let tx_id = tx.id();
         record_event!("blk_recv_tx", "tx_id": tx_id, "height": next_block_height.0);
@@ -88,11 +127,19 @@ where
         if tx_block_height < snapshot.access_map.oldest_block_height() {
             debug!("Tx proposal is outdated.");
             record_event!("discard_tx", "tx_id": tx_id, "reason": "tx_outdated");
+            TX_STATUS.record_failure(tx_id, "tx_outdated");
             continue;
         }
         if tx_block_height > last_block_height {
             warn!("Tx proposal is too new.");
             record_event!("discard_tx", "tx_id": tx_id, "reason": "tx_too_new");
+            TX_STATUS.record_failure(tx_id, "tx_too_new");
+            continue;
+        }
+
+        if !miner_cfg.deterministic_assembly && block_conflict_checker.check_tx(tx).is_some() {
+            debug!("Received a tx conflicting with the others in the block.");
+            deferred_tx_proposals.push(tx_proposal);
             continue;
         }
 
@@ -104,6 +151,7 @@ where
         ) {
             debug!("Received a tx with conflict");
             record_event!("discard_tx", "tx_id": tx_id, "reason": "tx_conflict");
+            TX_STATUS.record_failure(tx_id, "tx_conflict");
             continue;
         }
 
@@ -114,35 +162,65 @@ where
         if tx.tx_state_root() != tx_block.state_root() {
             warn!("Received a tx with invalid state root.");
             record_event!("discard_tx", "tx_id": tx_id, "reason": "invalid_state_root");
+            TX_STATUS.record_failure(tx_id, "invalid_state_root");
             continue;
         }
 
-        if let Err(e) = tx.verify_sig() {
-            warn!("Received a tx with invalid sig. Error: {:?}", e);
-            record_event!("discard_tx", "tx_id": tx_id, "reason": "invalid_sig", "detail": std::format!("{}", e));
-            continue;
+        if !pre_verified {
+            if let Err(e) = tx.verify_sig() {
+                warn!("Received a tx with invalid sig. Error: {:?}", e);
+                record_event!("discard_tx", "tx_id": tx_id, "reason": "invalid_sig", "detail": std::format!("{}", e));
+                TX_STATUS.record_failure(tx_id, "invalid_sig");
+                continue;
+            }
+
+            if let Err(e) = write_trie.verify(tx_block.state_root()) {
+                warn!("Received a tx with invalid write trie. Error: {:?}", e);
+                record_event!("discard_tx", "tx_id": tx_id, "reason": "invalid_write_trie", "detail": std::format!("{}", e));
+                TX_STATUS.record_failure(tx_id, "invalid_write_trie");
+                continue;
+            }
         }
 
-        if let Err(e) = write_trie.verify(tx_block.state_root()) {
-            warn!("Received a tx with invalid write trie. Error: {:?}", e);
-            record_event!("discard_tx", "tx_id": tx_id, "reason": "invalid_write_trie", "detail": std::format!("{}", e));
+        if miner_cfg.deterministic_assembly {
+            if !BlockGasMeter::new(miner_cfg.max_block_gas).fits(tx) {
+                warn!("Received a tx exceeding the block gas limit.");
+                record_event!("discard_tx", "tx_id": tx_id, "reason": "block_gas_limit");
+                TX_STATUS.record_failure(tx_id, "block_gas_limit");
+                continue;
+            }
+            candidates.push(tx_proposal);
             continue;
         }
 
-        snapshot.access_map.add_read(tx.tx_reads());
-        snapshot.access_map.add_write(tx.tx_writes());
-        writes.merge(tx.tx_writes());
-
-        txs.push(tx);
-        match &mut tx_tries {
-            TxTries::Diff(diffs) => {
-                let diff = snapshot.tx_trie.diff_missing_branches(&write_trie);
-                diffs.push(diff);
-            }
-            TxTries::UncompressedTries(tries) => {
-                tries.push((tx_block_height, write_trie));
+        if !block_gas_meter.fits(tx) {
+            if txs.is_empty() {
+                warn!("Received a tx exceeding the block gas limit.");
+                record_event!("discard_tx", "tx_id": tx_id, "reason": "block_gas_limit");
+                TX_STATUS.record_failure(tx_id, "block_gas_limit");
+                continue;
             }
+            debug!("The block gas limit is reached.");
+            deferred_tx_proposals.push(tx_proposal);
+            break;
         }
+
+        block_gas_meter.add(tx);
+        block_conflict_checker.add(tx.tx_writes());
+        include_tx(snapshot, tx_proposal, &mut txs, &mut tx_tries, &mut writes);
+    }
+
+    if !candidates.is_empty() {
+        let limits = PackLimits {
+            max_txs: miner_cfg.max_txs,
+            max_block_gas: miner_cfg.max_block_gas,
+        };
+        let (included, leftovers) = assemble_block(candidates, &limits);
+        for tx_proposal in included {
+            block_gas_meter.add(&tx_proposal.tx);
+            include_tx(snapshot, tx_proposal, &mut txs, &mut tx_tries, &mut writes);
+        }
+        deferred_tx_proposals.extend(leftovers);
     }
 
     let blk_proposal_trie = match tx_tries {
@@ -185,13 +263,16 @@ where
         new_state_root,
     );
     let new_blk = new_block_fn(block_header, last_block).await?;
-    let blk_proposal = BlockProposal::new(new_blk, txs, blk_proposal_trie);
+    let mut blk_proposal = BlockProposal::new(new_blk, txs, blk_proposal_trie);
+    if let Some(keypair) = miner_cfg.proposer_keypair.as_ref() {
+        blk_proposal.sign(&keypair.0, miner_cfg.proposer_id);
+    }
 
     snapshot.remove_oldest_block()?;
     snapshot.commit_block(blk_proposal.get_block().clone());
 
     let end = Instant::now();
-    record_event!("propose_end", "height": blk_proposal.get_block_height().0);
+    record_event!("propose_end", "height": blk_proposal.get_block_height().0, "gas_used": block_gas_meter.used());
     info!(time = ?(end - begin));
     Ok(Some(blk_proposal))
 }
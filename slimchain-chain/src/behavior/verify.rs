@@ -2,14 +2,17 @@ use crate::{
     block::BlockTrait,
     block_proposal::{BlockProposal, BlockProposalTrie},
     config::ChainConfig,
+    db::DBPtr,
     snapshot::Snapshot,
 };
 use slimchain_common::{
     basic::H256,
+    digest::Digestible,
     error::{bail, ensure, Context as _, Result},
     rw_set::TxWriteData,
     tx::TxTrait,
 };
+use slimchain_tx_engine_simple::reexecute_gas_used;
 use slimchain_tx_state::{TxStateUpdate, TxTrieTrait};
 use slimchain_utils::record_time;
 use std::time::Instant;
@@ -32,6 +35,9 @@ where
         .get_latest_block()
         .context("Failed to get the last block")?;
 
+    if chain_cfg.verify_proposer {
+        blk_proposal.verify_proposer(&chain_cfg.proposer_keys, &chain_cfg.proposer_peers)?;
+    }
     blk_proposal.get_block().verify_block_header(last_block)?;
     verify_consensus_fn(blk_proposal.get_block(), last_block)?;
 
@@ -116,3 +122,67 @@ where
     info!(?time);
     Ok(update)
 }
+
+/// Re-execute the txs in the block proposal on the state of the storage node, and check the
+/// gas they declare against the gas used. Only enabled by `full_validation`.
+#[tracing::instrument(level = "info", skip(db, blk_proposal), fields(height = blk_proposal.get_block_height().0), err)]
+pub async fn verify_tx_gas<Tx, Block>(
+    db: &DBPtr,
+    blk_proposal: &BlockProposal<Block, Tx>,
+) -> Result<()>
+where
+    Tx: TxTrait + 'static,
+    Block: BlockTrait,
+{
+    let begin = Instant::now();
+    let txs = blk_proposal.get_txs().to_vec();
+    db.read_async(move |db| {
+        for tx in &txs {
+            let gas_used = reexecute_gas_used(db, tx)
+                .with_context(|| format!("Failed to re-execute tx {}.", tx.id()))?;
+            ensure!(
+                tx.gas_used() == gas_used,
+                "Tx with invalid gas (expect: {}, actual: {}).",
+                tx.gas_used(),
+                gas_used,
+            );
+        }
+        Ok(())
+    })
+    .await?;
+
+    let time = Instant::now() - begin;
+    record_time!("verify_tx_gas", time, "height": blk_proposal.get_block_height().0);
+    Ok(())
+}
+
+/// Verify the block proposal on observer nodes, which follow the block headers without
+/// executing the txs.
+#[tracing::instrument(level = "info", skip(last_block, blk_proposal, verify_consensus_fn), fields(height = blk_proposal.get_block_height().0), err)]
+pub fn verify_block_observer_node<Tx, Block, VerifyConsensusFn>(
+    last_block: &Block,
+    blk_proposal: &BlockProposal<Block, Tx>,
+    verify_consensus_fn: VerifyConsensusFn,
+) -> Result<()>
+where
+    Tx: TxTrait,
+    Block: BlockTrait,
+    VerifyConsensusFn: Fn(&Block, &Block) -> Result<()>,
+{
+    let block = blk_proposal.get_block();
+    block.verify_block_header(last_block)?;
+    verify_consensus_fn(block, last_block)?;
+
+    let tx_list = block.tx_list();
+    let txs = blk_proposal.get_txs();
+    ensure!(
+        tx_list.len() == txs.len()
+            && tx_list
+                .iter()
+                .zip(txs.iter())
+                .all(|(&tx_hash, tx)| tx_hash == tx.to_digest()),
+        "Invalid tx list in the block proposal."
+    );
+
+    Ok(())
+}
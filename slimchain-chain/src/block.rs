@@ -72,6 +72,47 @@ impl BlockHeader {
     }
 }
 
+/// What the miner and client nodes keep of a block out of the window of the full blocks, see
+/// [`StorageMode::Headers`](crate::config::StorageMode::Headers). The tx list of the header is
+/// replaced by its root and its length, and the block hash is kept, which also covers the
+/// consensus data.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct PrunedBlock {
+    pub hash: H256,
+    pub height: BlockHeight,
+    pub prev_blk_hash: H256,
+    #[serde(with = "chrono::serde::ts_milliseconds")]
+    pub time_stamp: DateTime<Utc>,
+    pub tx_root: H256,
+    pub tx_count: usize,
+    pub state_root: H256,
+}
+
+impl PrunedBlock {
+    pub fn from_block(block: &impl BlockTrait) -> Self {
+        Self {
+            hash: block.to_digest(),
+            height: block.block_height(),
+            prev_blk_hash: block.prev_blk_hash(),
+            time_stamp: block.time_stamp(),
+            tx_root: block.tx_root(),
+            tx_count: block.tx_list().len(),
+            state_root: block.state_root(),
+        }
+    }
+
+    /// The header of the block without its tx list.
+    pub fn to_block_header(&self) -> BlockHeader {
+        BlockHeader {
+            height: self.height,
+            prev_blk_hash: self.prev_blk_hash,
+            time_stamp: self.time_stamp,
+            tx_list: BlockTxList::new(),
+            state_root: self.state_root,
+        }
+    }
+}
+
 #[derive(Debug, Default, Clone, Eq, PartialEq, Serialize, Deserialize, Deref, DerefMut)]
 pub struct BlockTxList(pub Vec<H256>);
 
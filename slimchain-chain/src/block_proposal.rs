@@ -1,5 +1,6 @@
 use crate::{
     block::BlockTrait,
+    config::ProposerPeerConfig,
     db::DBPtr,
     loader::{BlockLoaderTrait, TxLoaderTrait},
 };
@@ -10,7 +11,9 @@ use serde::{
 };
 use slimchain_common::{
     basic::BlockHeight,
-    error::{ensure, Result},
+    digest::Digestible,
+    ed25519::{Keypair, PubSigPair, PublicKey},
+    error::{bail, ensure, Result},
     rw_set::TxWriteData,
     tx::TxTrait,
 };
@@ -22,11 +25,21 @@ pub struct BlockProposal<Block: BlockTrait, Tx: TxTrait> {
     block: Block,
     txs: Vec<Tx>,
     trie: BlockProposalTrie,
+    /// The public key of the proposer and its signature over the block digest.
+    proposer: Option<PubSigPair>,
+    /// The peer id of the proposer, e.g., the `network.peer_id` of the raft leader.
+    proposer_id: Option<u64>,
 }
 
 impl<Block: BlockTrait, Tx: TxTrait> BlockProposal<Block, Tx> {
     pub fn new(block: Block, txs: Vec<Tx>, trie: BlockProposalTrie) -> Self {
-        Self { block, txs, trie }
+        Self {
+            block,
+            txs,
+            trie,
+            proposer: None,
+            proposer_id: None,
+        }
     }
 
     pub fn from_existing_block(
@@ -77,6 +90,54 @@ impl<Block: BlockTrait, Tx: TxTrait> BlockProposal<Block, Tx> {
         &self.trie
     }
 
+    pub fn get_proposer(&self) -> Option<&PubSigPair> {
+        self.proposer.as_ref()
+    }
+
+    pub fn get_proposer_id(&self) -> Option<u64> {
+        self.proposer_id
+    }
+
+    /// Sign the block digest by the proposer's `keypair`, and name the proposer by its
+    /// `proposer_id` if any.
+    ///
+    /// It should be called after the block is finalized, e.g., after PoW mining.
+    pub fn sign(&mut self, keypair: &Keypair, proposer_id: Option<u64>) {
+        self.proposer = Some(PubSigPair::create(keypair, self.block.to_digest()));
+        self.proposer_id = proposer_id;
+    }
+
+    /// Verify that the block proposal is signed by one of the `expected_proposers`.
+    ///
+    /// If it names its proposer, the name should also be mapped to the signing key by
+    /// `proposer_peers`.
+    pub fn verify_proposer(
+        &self,
+        expected_proposers: &[PublicKey],
+        proposer_peers: &[ProposerPeerConfig],
+    ) -> Result<()> {
+        let proposer = match self.proposer.as_ref() {
+            Some(proposer) => proposer,
+            None => bail!("Block proposal without the proposer signature."),
+        };
+        ensure!(
+            expected_proposers.contains(proposer.public()),
+            "Block proposal signed by an unknown proposer."
+        );
+        if let Some(proposer_id) = self.proposer_id {
+            let peer = match proposer_peers.iter().find(|p| p.peer_id == proposer_id) {
+                Some(peer) => peer,
+                None => bail!("Block proposal from an unknown proposer #{}.", proposer_id),
+            };
+            ensure!(
+                &peer.public_key == proposer.public(),
+                "Block proposal from proposer #{} signed by another key.",
+                proposer_id
+            );
+        }
+        proposer.verify(self.block.to_digest())
+    }
+
     pub fn unpack(self) -> (Block, Vec<Tx>) {
         (self.block, self.txs)
     }
@@ -100,10 +161,12 @@ impl<Block: BlockTrait + Serialize, Tx: TxTrait + Serialize> Serialize
     {
         let mut ser_block = self.block.clone();
         ser_block.tx_list_mut().clear();
-        let mut state = serializer.serialize_struct("BlockProposal", 3)?;
+        let mut state = serializer.serialize_struct("BlockProposal", 5)?;
         state.serialize_field("block", &ser_block)?;
         state.serialize_field("txs", &self.txs)?;
         state.serialize_field("trie", &self.trie)?;
+        state.serialize_field("proposer", &self.proposer)?;
+        state.serialize_field("proposer_id", &self.proposer_id)?;
         state.end()
     }
 }
@@ -116,11 +179,13 @@ impl<'de, Block: BlockTrait + Deserialize<'de>, Tx: TxTrait + Deserialize<'de>>
         D: Deserializer<'de>,
     {
         #[derive(Deserialize)]
-        #[serde(field_identifier, rename_all = "lowercase")]
+        #[serde(field_identifier, rename_all = "snake_case")]
         enum Field {
             Block,
             Txs,
             Trie,
+            Proposer,
+            ProposerId,
         }
 
         struct BlockProposalVisitor<Block: BlockTrait, Tx: TxTrait> {
@@ -157,8 +222,16 @@ impl<'de, Block: BlockTrait + Deserialize<'de>, Tx: TxTrait + Deserialize<'de>>
                 let trie: BlockProposalTrie = seq
                     .next_element()?
                     .ok_or_else(|| de::Error::invalid_length(1, &self))?;
+                let proposer: Option<PubSigPair> = seq.next_element()?.unwrap_or_default();
+                let proposer_id: Option<u64> = seq.next_element()?.unwrap_or_default();
                 *block.tx_list_mut() = txs.iter().collect();
-                Ok(BlockProposal::new(block, txs, trie))
+                Ok(BlockProposal {
+                    block,
+                    txs,
+                    trie,
+                    proposer,
+                    proposer_id,
+                })
             }
 
             fn visit_map<V>(self, mut map: V) -> Result<Self::Value, V::Error>
@@ -168,6 +241,8 @@ impl<'de, Block: BlockTrait + Deserialize<'de>, Tx: TxTrait + Deserialize<'de>>
                 let mut block: Option<Block> = None;
                 let mut txs: Option<Vec<Tx>> = None;
                 let mut trie: Option<BlockProposalTrie> = None;
+                let mut proposer: Option<PubSigPair> = None;
+                let mut proposer_id: Option<u64> = None;
                 while let Some(key) = map.next_key()? {
                     match key {
                         Field::Block => {
@@ -188,17 +263,35 @@ impl<'de, Block: BlockTrait + Deserialize<'de>, Tx: TxTrait + Deserialize<'de>>
                             }
                             trie = Some(map.next_value()?);
                         }
+                        Field::Proposer => {
+                            if proposer.is_some() {
+                                return Err(de::Error::duplicate_field("proposer"));
+                            }
+                            proposer = map.next_value()?;
+                        }
+                        Field::ProposerId => {
+                            if proposer_id.is_some() {
+                                return Err(de::Error::duplicate_field("proposer_id"));
+                            }
+                            proposer_id = map.next_value()?;
+                        }
                     }
                 }
                 let mut block = block.ok_or_else(|| de::Error::missing_field("block"))?;
                 let txs = txs.ok_or_else(|| de::Error::missing_field("txs"))?;
                 let trie = trie.ok_or_else(|| de::Error::missing_field("trie"))?;
                 *block.tx_list_mut() = txs.iter().collect();
-                Ok(BlockProposal::new(block, txs, trie))
+                Ok(BlockProposal {
+                    block,
+                    txs,
+                    trie,
+                    proposer,
+                    proposer_id,
+                })
             }
         }
 
-        const FIELDS: &[&str] = &["block", "txs", "trie"];
+        const FIELDS: &[&str] = &["block", "txs", "trie", "proposer", "proposer_id"];
         deserializer.deserialize_struct(
             "BlockProposal",
             FIELDS,
@@ -217,90 +310,129 @@ pub enum BlockProposalTrie {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::block::{BlockHeader, BlockTxList};
-    use slimchain_common::{
-        basic::{Address, BlockHeight, H256},
-        digest::Digestible,
-        rw_set::{TxReadSet, TxWriteData},
-        tx::TxTrait,
-        tx_req::TxRequest,
+    use crate::{
+        block::{BlockHeader, BlockTxList},
+        test_utils::DummyTx,
     };
+    use slimchain_common::{basic::H256, digest::Digestible};
     use slimchain_utils::serde::{binary_decode, binary_encode};
 
-    #[test]
-    fn test_serde() {
-        #[derive(Debug, Default, Clone, Eq, PartialEq, Serialize, Deserialize)]
-        struct DummyTx;
+    #[derive(Debug, Default, Clone, Eq, PartialEq, Serialize, Deserialize)]
+    struct DummyBlock {
+        tx_list: BlockTxList,
+    }
 
-        impl Digestible for DummyTx {
-            fn to_digest(&self) -> H256 {
-                H256::zero()
-            }
+    impl Digestible for DummyBlock {
+        fn to_digest(&self) -> H256 {
+            self.tx_list.to_digest()
         }
+    }
 
-        impl TxTrait for DummyTx {
-            fn tx_caller(&self) -> Address {
-                unreachable!();
-            }
-            fn tx_input(&self) -> &TxRequest {
-                unreachable!();
-            }
-            fn tx_block_height(&self) -> BlockHeight {
-                unreachable!();
-            }
-            fn tx_state_root(&self) -> H256 {
-                unreachable!();
-            }
-            fn tx_reads(&self) -> &TxReadSet {
-                unreachable!();
-            }
-            fn tx_writes(&self) -> &TxWriteData {
-                unreachable!();
-            }
-            fn verify_sig(&self) -> Result<()> {
-                unreachable!();
-            }
+    impl BlockTrait for DummyBlock {
+        fn genesis_block() -> Self {
+            unreachable!();
         }
-
-        #[derive(Debug, Default, Clone, Eq, PartialEq, Serialize, Deserialize)]
-        struct DummyBlock {
-            tx_list: BlockTxList,
+        fn block_header(&self) -> &BlockHeader {
+            unreachable!();
         }
-
-        impl Digestible for DummyBlock {
-            fn to_digest(&self) -> H256 {
-                self.tx_list.to_digest()
-            }
+        fn block_header_mut(&mut self) -> &mut BlockHeader {
+            unreachable!();
         }
-
-        impl BlockTrait for DummyBlock {
-            fn genesis_block() -> Self {
-                unreachable!();
-            }
-            fn block_header(&self) -> &BlockHeader {
-                unreachable!();
-            }
-            fn block_header_mut(&mut self) -> &mut BlockHeader {
-                unreachable!();
-            }
-            fn tx_list(&self) -> &BlockTxList {
-                &self.tx_list
-            }
-            fn tx_list_mut(&mut self) -> &mut BlockTxList {
-                &mut self.tx_list
-            }
+        fn tx_list(&self) -> &BlockTxList {
+            &self.tx_list
+        }
+        fn tx_list_mut(&mut self) -> &mut BlockTxList {
+            &mut self.tx_list
         }
+    }
 
+    fn dummy_proposal() -> BlockProposal<DummyBlock, DummyTx> {
         let tx = DummyTx::default();
         let tx_list: BlockTxList = std::iter::once(&tx).collect();
         let block = DummyBlock { tx_list };
-        let proposal =
-            BlockProposal::new(block, vec![tx], BlockProposalTrie::Diff(Default::default()));
+        BlockProposal::new(block, vec![tx], BlockProposalTrie::Diff(Default::default()))
+    }
+
+    #[test]
+    fn test_serde() {
+        let mut rng = rand::thread_rng();
+        let mut proposal = dummy_proposal();
 
         let bin = binary_encode(&proposal).unwrap();
         assert_eq!(proposal, binary_decode(&bin[..]).unwrap());
 
         let json = serde_json::to_string(&proposal).unwrap();
         assert_eq!(proposal, serde_json::from_str(&json).unwrap());
+
+        proposal.sign(&Keypair::generate(&mut rng), Some(1));
+
+        let bin = binary_encode(&proposal).unwrap();
+        assert_eq!(proposal, binary_decode(&bin[..]).unwrap());
+
+        let mut json = serde_json::to_value(&proposal).unwrap();
+        assert_eq!(proposal, serde_json::from_value(json.clone()).unwrap());
+
+        // Block proposals without the proposer fields are still accepted.
+        json.as_object_mut().unwrap().remove("proposer");
+        json.as_object_mut().unwrap().remove("proposer_id");
+        let decoded: BlockProposal<DummyBlock, DummyTx> = serde_json::from_value(json).unwrap();
+        assert!(decoded.get_proposer().is_none());
+        assert!(decoded.get_proposer_id().is_none());
+    }
+
+    #[test]
+    fn test_proposer_sig() {
+        let mut rng = rand::thread_rng();
+        let keypair = Keypair::generate(&mut rng);
+        let other_keypair = Keypair::generate(&mut rng);
+
+        let mut proposal = dummy_proposal();
+        assert!(proposal.verify_proposer(&[keypair.public], &[]).is_err());
+
+        proposal.sign(&keypair, None);
+        proposal.verify_proposer(&[keypair.public], &[]).unwrap();
+        proposal
+            .verify_proposer(&[other_keypair.public, keypair.public], &[])
+            .unwrap();
+        assert!(proposal
+            .verify_proposer(&[other_keypair.public], &[])
+            .is_err());
+        assert!(proposal.verify_proposer(&[], &[]).is_err());
+
+        // The signature no longer matches once the block is changed.
+        proposal.get_block_mut().tx_list_mut().clear();
+        assert!(proposal.verify_proposer(&[keypair.public], &[]).is_err());
+    }
+
+    #[test]
+    fn test_proposer_id() {
+        let mut rng = rand::thread_rng();
+        let keypair = Keypair::generate(&mut rng);
+        let other_keypair = Keypair::generate(&mut rng);
+        let keys = [keypair.public, other_keypair.public];
+        let peers = [
+            ProposerPeerConfig {
+                peer_id: 1,
+                public_key: keypair.public,
+            },
+            ProposerPeerConfig {
+                peer_id: 2,
+                public_key: other_keypair.public,
+            },
+        ];
+
+        let mut proposal = dummy_proposal();
+        proposal.sign(&keypair, Some(1));
+        assert_eq!(Some(1), proposal.get_proposer_id());
+        proposal.verify_proposer(&keys, &peers).unwrap();
+
+        // The proposer id maps to another key.
+        proposal.sign(&keypair, Some(2));
+        assert!(proposal.verify_proposer(&keys, &peers).is_err());
+
+        // The proposer id is not mapped at all.
+        proposal.sign(&keypair, Some(3));
+        assert!(proposal.verify_proposer(&keys, &peers).is_err());
+        assert!(proposal.verify_proposer(&keys, &[]).is_err());
     }
 }
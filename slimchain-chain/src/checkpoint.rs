@@ -0,0 +1,397 @@
+use crate::{
+    access_map::AccessMap,
+    block::BlockTrait,
+    config::ChainConfig,
+    db::{DBPtr, Transaction},
+    snapshot::{load_recent_blocks, Snapshot},
+};
+use futures::prelude::*;
+use serde::{Deserialize, Serialize};
+use slimchain_common::{
+    basic::{AccountData, BlockHeight, StateValue, H256},
+    collections::HashSet,
+    digest::Digestible,
+    error::{bail, ensure, Context as _, Result},
+};
+use slimchain_tx_state::{OutShardData, StorageTxTrie, TrieNode};
+use slimchain_utils::serde::binary_decode;
+use std::collections::VecDeque;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub height: BlockHeight,
+    pub block_hash: H256,
+    /// The root of the state trie in the node store.
+    pub state_root: H256,
+}
+
+impl Checkpoint {
+    pub fn from_block(block: &impl BlockTrait) -> Self {
+        Self {
+            height: block.block_height(),
+            block_hash: block.to_digest(),
+            state_root: block.state_root(),
+        }
+    }
+
+    pub fn load_from_db(db: &DBPtr) -> Result<Option<Self>> {
+        db.get_meta_object("checkpoint")
+    }
+}
+
+impl ChainConfig {
+    pub fn should_checkpoint(&self, height: BlockHeight) -> bool {
+        match self.checkpoint_interval {
+            Some(interval) if interval > 0 => !height.is_zero() && height.0 % interval == 0,
+            _ => false,
+        }
+    }
+}
+
+impl<Block: BlockTrait + for<'de> Deserialize<'de>> Snapshot<Block, StorageTxTrie> {
+    /// Persist a checkpoint at the latest block. The state nodes are not copied since they
+    /// are never removed from the node store.
+    pub fn write_checkpoint_db_tx(&self) -> Result<Transaction> {
+        let block = self
+            .get_latest_block()
+            .context("Failed to access the latest block.")?;
+        let mut tx = Transaction::with_capacity(3);
+        tx.insert_meta_object("checkpoint", &Checkpoint::from_block(block))?;
+        tx.insert_meta_object("checkpoint-access-map", &self.access_map)?;
+        tx.insert_meta_object(
+            "checkpoint-out-shard-data",
+            self.tx_trie.get_out_shard_data(),
+        )?;
+        Ok(tx)
+    }
+}
+
+/// Everything other than the state trie needed to start a storage node at the checkpoint.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CheckpointData<Block: BlockTrait> {
+    pub checkpoint: Checkpoint,
+    pub recent_blocks: Vec<Block>,
+    pub access_map: AccessMap,
+    pub out_shard_data: OutShardData,
+}
+
+impl<Block: BlockTrait + Serialize + for<'de> Deserialize<'de>> CheckpointData<Block> {
+    pub fn load_from_db(db: &DBPtr, state_len: usize) -> Result<Option<Self>> {
+        let checkpoint = match Checkpoint::load_from_db(db)? {
+            Some(checkpoint) => checkpoint,
+            None => return Ok(None),
+        };
+        let recent_blocks = load_recent_blocks::<Block>(db, checkpoint.height, state_len)?
+            .into_iter()
+            .collect();
+        let access_map = db
+            .get_existing_meta_object("checkpoint-access-map")
+            .context("Failed to get the checkpoint access map from the database.")?;
+        let out_shard_data = db
+            .get_existing_meta_object("checkpoint-out-shard-data")
+            .context("Failed to get the checkpoint out shard data from the database.")?;
+        Ok(Some(Self {
+            checkpoint,
+            recent_blocks,
+            access_map,
+            out_shard_data,
+        }))
+    }
+
+    /// Verify the recent blocks are chained and end at the checkpointed block.
+    pub fn verify(&self) -> Result<()> {
+        let last_block = self
+            .recent_blocks
+            .last()
+            .context("No block in the checkpoint.")?;
+        ensure!(
+            last_block.block_height() == self.checkpoint.height
+                && last_block.to_digest() == self.checkpoint.block_hash
+                && last_block.state_root() == self.checkpoint.state_root,
+            "The checkpoint mismatches its block."
+        );
+        ensure!(
+            self.access_map.latest_block_height() == self.checkpoint.height,
+            "The checkpoint access map mismatches its height."
+        );
+        for blocks in self.recent_blocks.windows(2) {
+            ensure!(
+                blocks[1].block_height() == blocks[0].block_height().next_height()
+                    && blocks[1].prev_blk_hash() == blocks[0].to_digest(),
+                "Recent blocks in the checkpoint are not chained (height: {}).",
+                blocks[1].block_height()
+            );
+        }
+        Ok(())
+    }
+
+    /// Build the database transaction to install the checkpoint to an empty storage node.
+    /// It should be written together with the state trie downloaded by `download_state_trie`.
+    pub fn write_db_tx(&self) -> Result<Transaction> {
+        let last_block = self
+            .recent_blocks
+            .last()
+            .context("No block in the checkpoint.")?;
+        let mut tx = Transaction::with_capacity(self.recent_blocks.len() + 7);
+        for block in &self.recent_blocks {
+            if !block.block_height().is_zero() {
+                tx.insert_block(block)?;
+            }
+        }
+        tx.insert_latest_block_header(last_block.block_header())?;
+        tx.insert_meta_object("height", &self.checkpoint.height)?;
+        tx.insert_meta_object("access-map", &self.access_map)?;
+        tx.insert_meta_object("out-shard-data", &self.out_shard_data)?;
+        tx.insert_meta_object("checkpoint", &self.checkpoint)?;
+        tx.insert_meta_object("checkpoint-access-map", &self.access_map)?;
+        tx.insert_meta_object("checkpoint-out-shard-data", &self.out_shard_data)?;
+        Ok(tx)
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum StateNodeKind {
+    Account,
+    AccountStateRoot,
+    State,
+}
+
+/// Download the state trie rooted at `state_root` into a database transaction.
+///
+/// `fetch_fn` returns the encoded state nodes for the requested hashes, or `None` if the
+/// peer does not have them. Every node is checked against its hash. The state trie of an
+/// account is skipped if the peer does not have its root, i.e., the account is out of shard.
+pub async fn download_state_trie<F, Fut>(
+    state_root: H256,
+    batch_size: usize,
+    fetch_fn: F,
+) -> Result<Transaction>
+where
+    F: Fn(Vec<H256>) -> Fut,
+    Fut: Future<Output = Result<Vec<Option<Vec<u8>>>>>,
+{
+    let mut tx = Transaction::new();
+    let mut visited: HashSet<H256> = HashSet::new();
+    let mut queue: VecDeque<(H256, StateNodeKind)> = VecDeque::new();
+    if !state_root.is_zero() {
+        queue.push_back((state_root, StateNodeKind::Account));
+    }
+
+    while !queue.is_empty() {
+        let batch_len = std::cmp::min(batch_size.max(1), queue.len());
+        let batch: Vec<_> = queue
+            .drain(..batch_len)
+            .filter(|(hash, _)| visited.insert(*hash))
+            .collect();
+        if batch.is_empty() {
+            continue;
+        }
+
+        let nodes = fetch_fn(batch.iter().map(|(hash, _)| *hash).collect()).await?;
+        ensure!(
+            nodes.len() == batch.len(),
+            "Expect {} state nodes from the peer, but got {}.",
+            batch.len(),
+            nodes.len()
+        );
+
+        for ((hash, kind), bin) in batch.into_iter().zip(nodes.into_iter()) {
+            let bin = match (bin, kind) {
+                (Some(bin), _) => bin,
+                (None, StateNodeKind::AccountStateRoot) => continue,
+                (None, _) => bail!("Missing state node {} from the peer.", hash),
+            };
+
+            if kind == StateNodeKind::Account {
+                let node: TrieNode<AccountData> = binary_decode(&bin[..])?;
+                ensure!(node.to_digest() == hash, "Invalid state node {}.", hash);
+                match &node {
+                    TrieNode::Extension(n) => queue.push_back((n.child, kind)),
+                    TrieNode::Branch(n) => {
+                        queue.extend(n.children.iter().flatten().map(|&child| (child, kind)));
+                    }
+                    TrieNode::Leaf(n) => {
+                        if !n.value.acc_state_root.is_zero() {
+                            queue.push_back((
+                                n.value.acc_state_root,
+                                StateNodeKind::AccountStateRoot,
+                            ));
+                        }
+                    }
+                }
+            } else {
+                let node: TrieNode<StateValue> = binary_decode(&bin[..])?;
+                ensure!(node.to_digest() == hash, "Invalid state node {}.", hash);
+                match &node {
+                    TrieNode::Extension(n) => queue.push_back((n.child, StateNodeKind::State)),
+                    TrieNode::Branch(n) => {
+                        queue.extend(
+                            n.children
+                                .iter()
+                                .flatten()
+                                .map(|&child| (child, StateNodeKind::State)),
+                        );
+                    }
+                    TrieNode::Leaf(_) => {}
+                }
+            }
+
+            tx.insert_state_node_bin(hash, bin);
+        }
+    }
+
+    Ok(tx)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        consensus::raft::Block, db::DB, loader::BlockLoaderTrait, test_utils::test_block_tx,
+    };
+    use slimchain_common::{
+        basic::{Address, Nonce, ShardId, StateKey, H160},
+        rw_set::TxWriteData,
+    };
+    use slimchain_tx_state::TxStateReadContext;
+
+    fn create_test_db(max_height: u64, state_len: usize) -> DBPtr {
+        let db = DB::load_test();
+        let mut prev_blk = Block::genesis_block();
+        let mut access_map = AccessMap::new(state_len);
+        for i in 1..=max_height {
+            let mut writes = TxWriteData::default();
+            for j in 1..=3 {
+                let acc_addr = Address::from(H160::from_low_u64_be(j));
+                writes.add_nonce(acc_addr, Nonce::from(i));
+                writes.add_value(
+                    acc_addr,
+                    StateKey::from(H256::from_low_u64_be(i)),
+                    StateValue::from(i * j),
+                );
+            }
+            let (blk, mut tx) = test_block_tx(&db, &prev_blk, &writes, &[]);
+
+            access_map.alloc_new_block();
+            access_map.add_write(&writes);
+            let _ = access_map.remove_oldest_block();
+
+            tx.insert_meta_object("height", &blk.block_height())
+                .unwrap();
+            tx.insert_meta_object("access-map", &access_map).unwrap();
+            tx.insert_meta_object("out-shard-data", &OutShardData::default())
+                .unwrap();
+            db.write_sync(tx).unwrap();
+            prev_blk = blk;
+        }
+        db
+    }
+
+    #[test]
+    fn test_should_checkpoint() {
+        let mut cfg: ChainConfig = serde_json::from_value(serde_json::json!({
+            "conflict_check": "ssi",
+            "state_len": 2,
+            "consensus": "raft",
+        }))
+        .unwrap();
+        assert!(!cfg.should_checkpoint(4.into()));
+        cfg.checkpoint_interval = Some(4);
+        assert!(!cfg.should_checkpoint(0.into()));
+        assert!(!cfg.should_checkpoint(3.into()));
+        assert!(cfg.should_checkpoint(4.into()));
+        assert!(cfg.should_checkpoint(8.into()));
+    }
+
+    #[tokio::test]
+    async fn test_checkpoint_sync() {
+        const STATE_LEN: usize = 3;
+        let src_db = create_test_db(10, STATE_LEN);
+        let snapshot =
+            Snapshot::<Block, StorageTxTrie>::load_from_db(&src_db, STATE_LEN, ShardId::default())
+                .unwrap();
+        src_db
+            .write_sync(snapshot.write_checkpoint_db_tx().unwrap())
+            .unwrap();
+
+        let data = CheckpointData::<Block>::load_from_db(&src_db, STATE_LEN)
+            .unwrap()
+            .unwrap();
+        assert_eq!(data.checkpoint.height, BlockHeight::from(10));
+        assert_eq!(data.recent_blocks.len(), STATE_LEN);
+        data.verify().unwrap();
+
+        let mut bad_data = data.clone();
+        bad_data.recent_blocks.remove(0);
+        bad_data.recent_blocks.insert(0, Block::genesis_block());
+        assert!(bad_data.verify().is_err());
+
+        let fetch_fn = |hashes: Vec<H256>| {
+            let src_db = src_db.clone();
+            async move {
+                hashes
+                    .into_iter()
+                    .map(|hash| src_db.get_state_node_bin(hash))
+                    .collect::<Result<Vec<_>>>()
+            }
+        };
+        let state_tx = download_state_trie(data.checkpoint.state_root, 4, fetch_fn)
+            .await
+            .unwrap();
+
+        let dst_db = DB::load_test();
+        dst_db.write_sync(state_tx).unwrap();
+        dst_db.write_sync(data.write_db_tx().unwrap()).unwrap();
+
+        let dst_snapshot =
+            Snapshot::<Block, StorageTxTrie>::load_from_db(&dst_db, STATE_LEN, ShardId::default())
+                .unwrap();
+        assert_eq!(dst_snapshot.current_height(), BlockHeight::from(10));
+        assert_eq!(
+            Checkpoint::load_from_db(&dst_db).unwrap(),
+            Some(data.checkpoint)
+        );
+
+        let mut ctx = TxStateReadContext::new(dst_db, data.checkpoint.state_root);
+        for j in 1..=3 {
+            let acc_addr = Address::from(H160::from_low_u64_be(j));
+            assert_eq!(ctx.get_nonce(acc_addr).unwrap(), Nonce::from(10));
+            for i in 1..=10 {
+                assert_eq!(
+                    ctx.get_value(acc_addr, StateKey::from(H256::from_low_u64_be(i)))
+                        .unwrap(),
+                    StateValue::from(i * j)
+                );
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_download_state_trie_invalid_node() {
+        let src_db = create_test_db(2, 2);
+        let root = src_db
+            .get_latest_block_header()
+            .unwrap()
+            .unwrap()
+            .state_root;
+        let other_blk: Block = src_db.get_block(1.into()).unwrap();
+        let other_root = other_blk.state_root();
+
+        let fetch_fn = |hashes: Vec<H256>| {
+            let src_db = src_db.clone();
+            async move {
+                hashes
+                    .into_iter()
+                    .map(|hash| {
+                        let hash = if hash == root { other_root } else { hash };
+                        src_db.get_state_node_bin(hash)
+                    })
+                    .collect::<Result<Vec<_>>>()
+            }
+        };
+        assert!(download_state_trie(root, 4, fetch_fn).await.is_err());
+
+        let fetch_fn = |hashes: Vec<H256>| async move { Ok(vec![None; hashes.len()]) };
+        assert!(download_state_trie(root, 4, fetch_fn).await.is_err());
+    }
+}
@@ -0,0 +1,143 @@
+use crate::{block::BlockTrait, block_proposal::BlockProposal};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use slimchain_common::{
+    basic::{BlockHeight, H256},
+    digest::Digestible,
+    tx::TxTrait,
+};
+use tokio::sync::broadcast;
+
+/// Max number of the events buffered for the slowest subscriber. The ones lagging further
+/// behind miss the oldest events.
+const COMMIT_EVENT_CAPACITY: usize = 4096;
+
+pub static COMMIT_EVENTS: Lazy<CommitEventBroadcaster> =
+    Lazy::new(|| CommitEventBroadcaster::new(COMMIT_EVENT_CAPACITY));
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum CommitEvent {
+    NewBlock {
+        height: BlockHeight,
+        hash: H256,
+        tx_count: usize,
+    },
+    /// Sent after the [`CommitEvent::NewBlock`] of the block including the tx.
+    TxCommit {
+        height: BlockHeight,
+        position: usize,
+        tx_hash: H256,
+        /// None if the tx body is not available, e.g., replayed from a client node.
+        tx_id: Option<H256>,
+    },
+}
+
+impl CommitEvent {
+    pub fn height(&self) -> BlockHeight {
+        match self {
+            Self::NewBlock { height, .. } | Self::TxCommit { height, .. } => *height,
+        }
+    }
+
+    /// The events of `block`, along with the ids of `txs` if available.
+    pub fn from_block<Block: BlockTrait, Tx: TxTrait>(
+        block: &Block,
+        txs: Option<&[Tx]>,
+    ) -> Vec<Self> {
+        let height = block.block_height();
+        let tx_list = block.tx_list();
+        let mut events = Vec::with_capacity(tx_list.len() + 1);
+        events.push(Self::NewBlock {
+            height,
+            hash: block.to_digest(),
+            tx_count: tx_list.len(),
+        });
+        for (position, &tx_hash) in tx_list.iter().enumerate() {
+            events.push(Self::TxCommit {
+                height,
+                position,
+                tx_hash,
+                tx_id: txs.and_then(|txs| txs.get(position)).map(|tx| tx.id()),
+            });
+        }
+        events
+    }
+}
+
+/// Broadcast the committed blocks and txs to the subscribers, e.g., the WebSocket
+/// connections. The events are dropped if there is no subscriber.
+pub struct CommitEventBroadcaster {
+    tx: broadcast::Sender<CommitEvent>,
+}
+
+impl CommitEventBroadcaster {
+    pub fn new(capacity: usize) -> Self {
+        let (tx, _) = broadcast::channel(capacity);
+        Self { tx }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<CommitEvent> {
+        self.tx.subscribe()
+    }
+
+    pub fn subscriber_count(&self) -> usize {
+        self.tx.receiver_count()
+    }
+
+    pub fn publish(&self, event: CommitEvent) {
+        self.tx.send(event).ok();
+    }
+
+    pub fn publish_block_commit<Block: BlockTrait, Tx: TxTrait>(
+        &self,
+        blk_proposal: &BlockProposal<Block, Tx>,
+    ) {
+        if self.subscriber_count() == 0 {
+            return;
+        }
+        for event in CommitEvent::from_block(blk_proposal.get_block(), Some(blk_proposal.get_txs()))
+        {
+            self.publish(event);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_commit_events() {
+        let broadcaster = CommitEventBroadcaster::new(16);
+        let new_block = CommitEvent::NewBlock {
+            height: 1.into(),
+            hash: H256::repeat_byte(1),
+            tx_count: 1,
+        };
+        let tx_commit = CommitEvent::TxCommit {
+            height: 1.into(),
+            position: 0,
+            tx_hash: H256::repeat_byte(2),
+            tx_id: None,
+        };
+
+        // Dropped without any subscriber.
+        broadcaster.publish(new_block.clone());
+        let mut rx = broadcaster.subscribe();
+        assert_eq!(1, broadcaster.subscriber_count());
+        broadcaster.publish(new_block.clone());
+        broadcaster.publish(tx_commit.clone());
+        assert_eq!(new_block, rx.recv().await.unwrap());
+        assert_eq!(tx_commit, rx.recv().await.unwrap());
+        assert!(rx.try_recv().is_err());
+
+        let json = serde_json::to_value(&new_block).unwrap();
+        assert_eq!("new_block", json["type"]);
+        assert_eq!(1, json["height"]);
+        assert_eq!(new_block, serde_json::from_value(json).unwrap());
+        let json = serde_json::to_value(&tx_commit).unwrap();
+        assert_eq!("tx_commit", json["type"]);
+        assert_eq!(tx_commit, serde_json::from_value(json).unwrap());
+    }
+}
@@ -1,8 +1,15 @@
-use crate::{conflict_check::ConflictCheck, consensus::Consensus};
+use crate::{conflict_check::ConflictCheck, consensus::Consensus, db::DbBackend};
 use once_cell::sync::OnceCell;
-use serde::Deserialize;
-use slimchain_common::error::{anyhow, Result};
-use std::time::Duration;
+use serde::{de::Error as SerdeError, Deserialize, Deserializer};
+use slimchain_common::{
+    ed25519::{Keypair, PublicKey},
+    error::{anyhow, Result},
+    tx_req::TxSigConfig,
+    utils::hex,
+};
+use slimchain_utils::config::{join_path, on_section_reload, ConfigErrors, ValidateConfig};
+use std::{fmt, time::Duration};
+use tokio::sync::watch;
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct ChainConfig {
@@ -12,6 +19,149 @@ pub struct ChainConfig {
     pub state_len: usize,
     /// Consensus method. Possible values: pow, raft.
     pub consensus: Consensus,
+    /// Whether to index tx locations (tx_hash -> block height) on client and storage nodes.
+    /// The tx receipts of the storage nodes require it. Default false.
+    #[serde(default)]
+    pub index_tx_location: bool,
+    /// Whether to index txs by their caller addresses on storage nodes. Default false.
+    #[serde(default)]
+    pub index_addresses: bool,
+    /// Create a checkpoint every K blocks on storage nodes. If missing, no checkpoint is created.
+    #[serde(default)]
+    pub checkpoint_interval: Option<u64>,
+    /// Whether a new storage node syncs from the latest checkpoint of its peers. Default false.
+    #[serde(default)]
+    pub fast_sync: bool,
+    /// Whether to only import the block proposals signed by one of `proposer_keys`. Default false.
+    /// The block proposals fetched during `fast_sync` are not signed.
+    #[serde(default)]
+    pub verify_proposer: bool,
+    /// Hex encoded public keys of the raft leaders or PoW miners.
+    #[serde(
+        default,
+        deserialize_with = "slimchain_utils::config::deserialize_public_keys_from_hex"
+    )]
+    pub proposer_keys: Vec<PublicKey>,
+    /// The peer ids of the proposers mapped to their keys in `proposer_keys`. When
+    /// `verify_proposer` is set, a block proposal naming its proposer is only imported if the
+    /// name maps to the key signing it.
+    #[serde(default)]
+    pub proposer_peers: Vec<ProposerPeerConfig>,
+    /// How long the committed and failed txs are kept for the tx status queries.
+    /// Default 60 seconds.
+    #[serde(
+        default = "default_tx_status_ttl",
+        deserialize_with = "slimchain_utils::config::deserialize_duration_from_millis"
+    )]
+    pub tx_status_ttl: Duration,
+    /// How the signatures of the tx requests are verified.
+    #[serde(default)]
+    pub tx_sig: TxSigConfig,
+    /// Whether storage nodes re-execute the txs in the imported blocks to check the gas they
+    /// declare. Default false.
+    #[serde(default)]
+    pub full_validation: bool,
+    /// Number of threads serving the async reads of the database, e.g., of the HTTP queries and
+    /// the block validation. Default 4.
+    #[serde(default = "default_db_read_threads")]
+    pub db_read_threads: usize,
+    /// Where the database is stored. Possible values: rocksdb, memory. The in-memory one is
+    /// lost once the node exits, so it is only for the tests and the simulations. Default
+    /// rocksdb.
+    #[serde(default)]
+    pub db_backend: DbBackend,
+    /// Whether the on-disk database writes skip its journal. It saves a sync per commit, but a
+    /// crash may then leave a commit partially applied. Default false.
+    #[serde(default)]
+    pub disable_db_journal: bool,
+    /// How the miner and client nodes store the blocks committed. Possible values: full,
+    /// headers. Default full.
+    #[serde(default)]
+    pub storage_mode: StorageMode,
+    /// Number of the latest blocks kept in full when `storage_mode` is headers. It should be
+    /// at least `state_len`. Default 1024.
+    #[serde(default = "default_full_block_window")]
+    pub full_block_window: u64,
+}
+
+/// A block proposer known by its peer id, e.g., the `network.peer_id` of the raft leader.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ProposerPeerConfig {
+    pub peer_id: u64,
+    /// Hex encoded public key of the proposer.
+    #[serde(deserialize_with = "slimchain_utils::config::deserialize_public_key_from_hex")]
+    pub public_key: PublicKey,
+}
+
+/// How the miner and client nodes store the blocks committed by
+/// [`commit_block`](crate::behavior::commit_block).
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageMode {
+    /// Keep all the blocks.
+    Full,
+    /// Keep the last `full_block_window` blocks, and only the [`PrunedBlock`] of the older
+    /// ones. Their tx lists are dropped, so the disk usage no longer grows with the txs.
+    ///
+    /// [`PrunedBlock`]: crate::block::PrunedBlock
+    Headers,
+}
+
+impl Default for StorageMode {
+    fn default() -> Self {
+        Self::Full
+    }
+}
+
+impl ValidateConfig for ChainConfig {
+    fn validate_at(&self, path: &str, errors: &mut ConfigErrors) {
+        errors.ensure(
+            self.state_len >= 1,
+            &join_path(path, "state_len"),
+            "Should be at least 1.",
+        );
+        errors.ensure(
+            self.checkpoint_interval != Some(0),
+            &join_path(path, "checkpoint_interval"),
+            "Should be positive.",
+        );
+        errors.ensure(
+            self.tx_status_ttl > Duration::from_millis(0),
+            &join_path(path, "tx_status_ttl"),
+            "Should be positive.",
+        );
+        errors.ensure(
+            !self.verify_proposer || !self.proposer_keys.is_empty(),
+            &join_path(path, "proposer_keys"),
+            "Should not be empty when `verify_proposer` is set.",
+        );
+        for (i, peer) in self.proposer_peers.iter().enumerate() {
+            let peer_path = join_path(path, &format!("proposer_peers[{}]", i));
+            errors.ensure(
+                self.proposer_keys.contains(&peer.public_key),
+                &join_path(&peer_path, "public_key"),
+                "Should be one of `proposer_keys`.",
+            );
+            errors.ensure(
+                self.proposer_peers[..i]
+                    .iter()
+                    .all(|p| p.peer_id != peer.peer_id),
+                &join_path(&peer_path, "peer_id"),
+                "Should be unique.",
+            );
+        }
+        errors.ensure(
+            self.db_read_threads >= 1,
+            &join_path(path, "db_read_threads"),
+            "Should be at least 1.",
+        );
+        errors.ensure(
+            self.storage_mode == StorageMode::Full
+                || self.full_block_window >= self.state_len as u64,
+            &join_path(path, "full_block_window"),
+            "Should be at least `state_len`.",
+        );
+    }
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -19,6 +169,9 @@ pub struct MinerConfig {
     /// Max number of txs in one block.
     #[serde(default = "default_max_txs")]
     pub max_txs: usize,
+    /// Max total gas used by the txs in one block. If missing, the gas is not limited.
+    #[serde(default)]
+    pub max_block_gas: Option<u64>,
     /// Min number of txs in one block. It should be greater than 0.
     #[serde(default)]
     pub min_txs: usize,
@@ -28,6 +181,174 @@ pub struct MinerConfig {
     /// Whether to compress partial tries. Default true.
     #[serde(default = "default_compress_trie")]
     pub compress_trie: bool,
+    /// Max number of blocks a pending tx proposal can wait before being dropped.
+    /// If missing, pending tx proposals never expire.
+    #[serde(default)]
+    pub max_tx_age_blocks: Option<u64>,
+    /// Whether to order tx proposals from the same caller by their nonces. Default false.
+    #[serde(default)]
+    pub nonce_ordering: bool,
+    /// Whether to pack the tx proposals collected for a block by
+    /// [`assemble_block`](crate::assemble::assemble_block), so that the block does not depend
+    /// on their arrival order. Default false.
+    #[serde(default)]
+    pub deterministic_assembly: bool,
+    /// Hex encoded keypair used to sign the block proposals. If missing, they are not signed.
+    #[serde(default)]
+    pub proposer_keypair: Option<ProposerKeypair>,
+    /// The peer id naming the proposer in the signed block proposals, e.g., the raft
+    /// `network.peer_id`. If missing, they do not name their proposer.
+    #[serde(default)]
+    pub proposer_id: Option<u64>,
+    /// Number of threads verifying the incoming tx proposals on the raft leader before they
+    /// enter the block assembly. If 0, they are verified by the block assembly. Default 0.
+    #[serde(default)]
+    pub verify_threads: usize,
+    /// The pool of the pending tx proposals on the raft leader.
+    #[serde(default)]
+    pub mempool: MempoolConfig,
+    /// How long the raft leader waits for the parts of all the shards written by a cross-shard
+    /// tx before aborting it. Default 5 seconds.
+    #[serde(
+        default = "default_cross_shard_timeout",
+        deserialize_with = "slimchain_utils::config::deserialize_duration_from_millis"
+    )]
+    pub cross_shard_timeout: Duration,
+}
+
+impl ValidateConfig for MinerConfig {
+    fn validate_at(&self, path: &str, errors: &mut ConfigErrors) {
+        self.block_limits().validate_at(path, errors);
+        errors.ensure(
+            self.max_tx_age_blocks != Some(0),
+            &join_path(path, "max_tx_age_blocks"),
+            "Should be positive.",
+        );
+        errors.ensure(
+            self.cross_shard_timeout > Duration::from_millis(0),
+            &join_path(path, "cross_shard_timeout"),
+            "Should be positive.",
+        );
+        self.mempool
+            .validate_at(&join_path(path, "mempool"), errors);
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct MempoolConfig {
+    /// Max number of pending tx proposals. When full, the ones with the lowest fees are evicted.
+    /// If missing, the pool is not limited.
+    pub max_size: Option<usize>,
+    /// Whether to keep the pending tx proposals in the database, so that they survive a
+    /// restart. Default false.
+    pub persist: bool,
+}
+
+impl ValidateConfig for MempoolConfig {
+    fn validate_at(&self, path: &str, errors: &mut ConfigErrors) {
+        errors.ensure(
+            self.max_size != Some(0),
+            &join_path(path, "max_size"),
+            "Should be positive.",
+        );
+    }
+}
+
+/// The limits of the block assembly in [`MinerConfig`], which can be reloaded without a
+/// restart.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Deserialize)]
+pub struct BlockLimits {
+    #[serde(default = "default_max_txs")]
+    pub max_txs: usize,
+    #[serde(default)]
+    pub max_block_gas: Option<u64>,
+    #[serde(default)]
+    pub min_txs: usize,
+    #[serde(deserialize_with = "slimchain_utils::config::deserialize_duration_from_millis")]
+    pub max_block_interval: Duration,
+}
+
+impl ValidateConfig for BlockLimits {
+    fn validate_at(&self, path: &str, errors: &mut ConfigErrors) {
+        errors.ensure(
+            self.max_txs >= 1,
+            &join_path(path, "max_txs"),
+            "Should be at least 1.",
+        );
+        errors.ensure(
+            self.min_txs <= self.max_txs,
+            &join_path(path, "min_txs"),
+            format!("Should not be larger than `max_txs` ({}).", self.max_txs),
+        );
+        errors.ensure(
+            self.max_block_gas != Some(0),
+            &join_path(path, "max_block_gas"),
+            "Should be positive.",
+        );
+        errors.ensure(
+            self.max_block_interval > Duration::from_millis(0),
+            &join_path(path, "max_block_interval"),
+            "Should be positive.",
+        );
+    }
+}
+
+impl MinerConfig {
+    pub fn block_limits(&self) -> BlockLimits {
+        BlockLimits {
+            max_txs: self.max_txs,
+            max_block_gas: self.max_block_gas,
+            min_txs: self.min_txs,
+            max_block_interval: self.max_block_interval,
+        }
+    }
+
+    pub fn set_block_limits(&mut self, limits: BlockLimits) {
+        self.max_txs = limits.max_txs;
+        self.max_block_gas = limits.max_block_gas;
+        self.min_txs = limits.min_txs;
+        self.max_block_interval = limits.max_block_interval;
+    }
+
+    /// The block limits following the reloads of the `miner` config, starting from the ones
+    /// of `self`.
+    pub fn watch_block_limits(&self) -> watch::Receiver<BlockLimits> {
+        let (limits_tx, limits_rx) = watch::channel(self.block_limits());
+        on_section_reload("miner", move |limits: BlockLimits| {
+            info!("Update the block limits: {:?}", limits);
+            limits_tx.send(limits).ok();
+        });
+        limits_rx
+    }
+}
+
+/// The ed25519 keypair used to sign the block proposals.
+pub struct ProposerKeypair(pub Keypair);
+
+impl Clone for ProposerKeypair {
+    fn clone(&self) -> Self {
+        Self(Keypair::from_bytes(&self.0.to_bytes()[..]).expect("Failed to clone the keypair."))
+    }
+}
+
+impl fmt::Debug for ProposerKeypair {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("ProposerKeypair")
+            .field(&hex::encode(self.0.public.as_bytes()))
+            .finish()
+    }
+}
+
+impl<'de> Deserialize<'de> for ProposerKeypair {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let bytes = hex::decode(String::deserialize(deserializer)?).map_err(SerdeError::custom)?;
+        let keypair = Keypair::from_bytes(&bytes[..]).map_err(SerdeError::custom)?;
+        Ok(Self(keypair))
+    }
 }
 
 fn default_max_txs() -> usize {
@@ -38,6 +359,33 @@ fn default_compress_trie() -> bool {
     true
 }
 
+fn default_cross_shard_timeout() -> Duration {
+    Duration::from_secs(5)
+}
+
+fn default_tx_status_ttl() -> Duration {
+    Duration::from_secs(60)
+}
+
+fn default_db_read_threads() -> usize {
+    crate::db::read_pool::DEFAULT_READ_THREADS
+}
+
+fn default_full_block_window() -> u64 {
+    1024
+}
+
+#[derive(Debug, Default, Copy, Clone, Deserialize)]
+#[serde(default)]
+pub struct ObserverConfig {
+    /// Whether to store the txs of the blocks on observer nodes. Default false.
+    pub store_txs: bool,
+}
+
+impl ValidateConfig for ObserverConfig {
+    fn validate_at(&self, _path: &str, _errors: &mut ConfigErrors) {}
+}
+
 #[derive(Debug, Copy, Clone, Deserialize)]
 #[serde(default)]
 pub struct PoWConfig {
@@ -53,10 +401,21 @@ impl Default for PoWConfig {
     }
 }
 
+impl ValidateConfig for PoWConfig {
+    fn validate_at(&self, path: &str, errors: &mut ConfigErrors) {
+        errors.ensure(
+            self.init_diff >= 1,
+            &join_path(path, "init_diff"),
+            "Should be at least 1.",
+        );
+    }
+}
+
 static GLOBAL_POW_CONFIG: OnceCell<PoWConfig> = OnceCell::new();
 
 impl PoWConfig {
     pub fn install_as_global(self) -> Result<()> {
+        self.validate_config("pow")?;
         GLOBAL_POW_CONFIG
             .set(self)
             .map_err(|_| anyhow!("Failed to set PoWConfig."))
@@ -66,3 +425,130 @@ impl PoWConfig {
         GLOBAL_POW_CONFIG.get().copied().unwrap_or_default()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use slimchain_utils::{config::Config, toml};
+
+    fn chain_cfg(input: &str) -> ChainConfig {
+        let input = format!(
+            "[chain]\nconflict_check = \"ssi\"\nstate_len = 16\nconsensus = \"pow\"\n{}",
+            input
+        );
+        Config::from_toml(toml::from_str(&input).unwrap())
+            .get("chain")
+            .unwrap()
+    }
+
+    fn miner_cfg(input: &str) -> MinerConfig {
+        let input = format!("[miner]\nmax_block_interval = 1000\n{}", input);
+        Config::from_toml(toml::from_str(&input).unwrap())
+            .get("miner")
+            .unwrap()
+    }
+
+    #[test]
+    fn test_validate_chain_config() {
+        assert!(chain_cfg("").validate_config("chain").is_ok());
+
+        let mut cfg = chain_cfg("");
+        cfg.state_len = 0;
+        assert!(cfg
+            .validate_config("chain")
+            .unwrap_err()
+            .contains("chain.state_len"));
+
+        let cfg = chain_cfg("checkpoint_interval = 0\n");
+        let errors = cfg.validate_config("chain").unwrap_err();
+        assert!(errors.contains("chain.checkpoint_interval"));
+
+        let cfg = chain_cfg("tx_status_ttl = 0\n");
+        let errors = cfg.validate_config("chain").unwrap_err();
+        assert!(errors.contains("chain.tx_status_ttl"));
+
+        let cfg = chain_cfg("verify_proposer = true\n");
+        let errors = cfg.validate_config("chain").unwrap_err();
+        assert!(errors.contains("chain.proposer_keys"));
+
+        let key = hex::encode(Keypair::generate(&mut rand::thread_rng()).public.as_bytes());
+        let other = hex::encode(Keypair::generate(&mut rand::thread_rng()).public.as_bytes());
+        let peer = |peer_id: u64, key: &str| {
+            format!("{{ peer_id = {}, public_key = \"{}\" }}", peer_id, key)
+        };
+        let with_peers = |peers: &[String]| {
+            chain_cfg(&format!(
+                "proposer_keys = [\"{}\"]\nproposer_peers = [{}]\n",
+                key,
+                peers.join(", ")
+            ))
+        };
+        let cfg = with_peers(&[peer(1, &key)]);
+        assert_eq!(1, cfg.proposer_peers[0].peer_id);
+        assert!(cfg.validate_config("chain").is_ok());
+        let errors = with_peers(&[peer(1, &key), peer(1, &other)])
+            .validate_config("chain")
+            .unwrap_err();
+        assert!(errors.contains("chain.proposer_peers[1].public_key"));
+        assert!(errors.contains("chain.proposer_peers[1].peer_id"));
+        assert!(!errors.contains("chain.proposer_peers[0]"));
+
+        let cfg = chain_cfg("storage_mode = \"headers\"\nfull_block_window = 8\n");
+        assert_eq!(cfg.storage_mode, StorageMode::Headers);
+        let errors = cfg.validate_config("chain").unwrap_err();
+        assert!(errors.contains("chain.full_block_window"));
+        assert!(chain_cfg("full_block_window = 8\n")
+            .validate_config("chain")
+            .is_ok());
+    }
+
+    #[test]
+    fn test_validate_miner_config() {
+        assert!(miner_cfg("").validate_config("miner").is_ok());
+
+        let errors = miner_cfg("max_txs = 0\n")
+            .validate_config("miner")
+            .unwrap_err();
+        assert!(errors.contains("miner.max_txs"));
+
+        let errors = miner_cfg("max_txs = 2\nmin_txs = 3\n")
+            .validate_config("miner")
+            .unwrap_err();
+        assert!(errors.contains("miner.min_txs"));
+        assert!(!errors.contains("miner.max_txs"));
+
+        let errors = miner_cfg("max_block_gas = 0\n")
+            .validate_config("miner")
+            .unwrap_err();
+        assert!(errors.contains("miner.max_block_gas"));
+
+        let mut cfg = miner_cfg("");
+        cfg.max_block_interval = Duration::from_millis(0);
+        let errors = cfg.validate_config("miner").unwrap_err();
+        assert!(errors.contains("miner.max_block_interval"));
+
+        let errors = miner_cfg("max_tx_age_blocks = 0\n")
+            .validate_config("miner")
+            .unwrap_err();
+        assert!(errors.contains("miner.max_tx_age_blocks"));
+
+        let errors = miner_cfg("cross_shard_timeout = 0\n")
+            .validate_config("miner")
+            .unwrap_err();
+        assert!(errors.contains("miner.cross_shard_timeout"));
+
+        let errors = miner_cfg("[miner.mempool]\nmax_size = 0\n")
+            .validate_config("miner")
+            .unwrap_err();
+        assert!(errors.contains("miner.mempool.max_size"));
+    }
+
+    #[test]
+    fn test_validate_pow_config() {
+        assert!(PoWConfig::default().validate_config("pow").is_ok());
+        let errors = PoWConfig { init_diff: 0 }
+            .validate_config("pow")
+            .unwrap_err();
+        assert!(errors.contains("pow.init_diff"));
+    }
+}
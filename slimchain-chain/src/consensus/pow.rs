@@ -1,6 +1,7 @@
 use crate::{
     block::{block_header_to_digest, BlockHeader, BlockTrait, BlockTxList},
     config::PoWConfig,
+    genesis::GenesisConfig,
 };
 use chrono::{DateTime, Utc};
 use futures::prelude::*;
@@ -35,22 +36,26 @@ impl Digestible for Block {
     }
 }
 
-impl BlockTrait for Block {
-    fn genesis_block() -> Self {
+impl Block {
+    pub fn genesis_from_config(cfg: &GenesisConfig, state_root: H256) -> Self {
         Self {
-            header: BlockHeader {
-                height: 0.into(),
-                prev_blk_hash: H256::zero(),
-                time_stamp: DateTime::parse_from_rfc3339("2020-08-01T00:00:00Z")
-                    .expect("Failed to parse the timestamp.")
-                    .with_timezone(&Utc),
-                tx_list: BlockTxList::default(),
-                state_root: H256::zero(),
-            },
-            diff: PoWConfig::get().init_diff,
+            header: BlockHeader::new(
+                0.into(),
+                H256::zero(),
+                cfg.time_stamp,
+                BlockTxList::default(),
+                state_root,
+            ),
+            diff: cfg.init_diff.unwrap_or_else(|| PoWConfig::get().init_diff),
             nonce: Nonce::zero(),
         }
     }
+}
+
+impl BlockTrait for Block {
+    fn genesis_block() -> Self {
+        Self::genesis_from_config(GenesisConfig::get(), GenesisConfig::get_state_root())
+    }
 
     fn block_header(&self) -> &BlockHeader {
         &self.header
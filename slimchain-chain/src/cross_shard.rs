@@ -0,0 +1,501 @@
+//! The two-phase commit of the txs writing the accounts of more than one shard.
+//!
+//! The storage node executing such a tx splits its write set per shard into the
+//! [`CrossShardTxProposal`]s, which share the tx id and the commitment to the full write set.
+//! The part of each shard is prepared by a storage node of that shard before it reaches the
+//! raft leader, where the [`CrossShardCoordinator`] only includes the tx once the parts of all
+//! the shards involved are present and consistent, and aborts it otherwise.
+//!
+//! The state is still committed by [`StorageTxTrie`](slimchain_tx_state::StorageTxTrie), which
+//! only writes the state nodes of the accounts in the shard of the storage node.
+//!
+//! Only the txs writing exactly two shards are supported for now.
+
+use serde::{Deserialize, Serialize};
+use slimchain_common::{
+    basic::{ShardId, H256},
+    collections::HashMap,
+    digest::Digestible,
+    rw_set::TxWriteData,
+    tx::TxTrait,
+};
+use slimchain_tx_state::TxProposal;
+use std::{
+    fmt,
+    time::{Duration, Instant},
+};
+
+/// The max number of the shards written by a cross-shard tx.
+pub const MAX_CROSS_SHARD_TX_SHARDS: usize = 2;
+
+/// The shards of `total` written by `writes`, in the order of their ids.
+pub fn write_shards(writes: &TxWriteData, total: u64) -> Vec<ShardId> {
+    let mut ids: Vec<u64> = writes
+        .keys()
+        .map(|&addr| ShardId::from_address(addr, total).id)
+        .collect();
+    ids.sort_unstable();
+    ids.dedup();
+    ids.into_iter().map(|id| ShardId::new(id, total)).collect()
+}
+
+/// Whether `writes` touches more than one shard of `total`.
+pub fn is_cross_shard(writes: &TxWriteData, total: u64) -> bool {
+    total > 1 && write_shards(writes, total).len() > 1
+}
+
+/// The part of a cross-shard tx for one of the shards it writes.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CrossShardTxProposal<Tx: TxTrait> {
+    pub tx_id: H256,
+    /// The shard of this part.
+    pub shard_id: ShardId,
+    /// All the shards written by the tx.
+    pub shards: Vec<ShardId>,
+    /// The digest of the full write set.
+    pub commitment: H256,
+    /// The writes of the accounts in `shard_id`.
+    pub writes: TxWriteData,
+    /// The tx proposal, carried by exactly one of the parts.
+    pub tx_proposal: Option<TxProposal<Tx>>,
+}
+
+impl<Tx: TxTrait> CrossShardTxProposal<Tx> {
+    /// Split `tx_proposal` executed by a storage node of `home_shard` into one part per shard
+    /// it writes. The tx proposal is carried by the part of `home_shard` if it is written, or
+    /// the first part otherwise.
+    pub fn split(tx_proposal: TxProposal<Tx>, home_shard: ShardId) -> Vec<Self> {
+        let writes = tx_proposal.tx.tx_writes();
+        let tx_id = tx_proposal.tx.id();
+        let commitment = writes.to_digest();
+        let shards = write_shards(writes, home_shard.total);
+
+        let mut parts: Vec<Self> = shards
+            .iter()
+            .map(|&shard_id| Self {
+                tx_id,
+                shard_id,
+                shards: shards.clone(),
+                commitment,
+                writes: TxWriteData(
+                    writes
+                        .iter()
+                        .filter(|(&addr, _)| shard_id.contains(addr))
+                        .map(|(&addr, acc_writes)| (addr, acc_writes.clone()))
+                        .collect(),
+                ),
+                tx_proposal: None,
+            })
+            .collect();
+        let carrier = parts
+            .iter()
+            .position(|part| part.shard_id == home_shard)
+            .unwrap_or(0);
+        if let Some(part) = parts.get_mut(carrier) {
+            part.tx_proposal = Some(tx_proposal);
+        }
+        parts
+    }
+
+    /// Prepare the part on a storage node of its shard, i.e., check that it only writes the
+    /// accounts in the shard.
+    pub fn prepare(&self) -> Result<(), CrossShardAbort> {
+        if self.shards.len() < 2 || self.shards.len() > MAX_CROSS_SHARD_TX_SHARDS {
+            return Err(CrossShardAbort::UnsupportedShards(self.shards.len()));
+        }
+        if !self.shards.contains(&self.shard_id)
+            || !self.writes.keys().all(|&addr| self.shard_id.contains(addr))
+        {
+            return Err(CrossShardAbort::InvalidSlice(self.shard_id));
+        }
+        Ok(())
+    }
+}
+
+/// Why a cross-shard tx is aborted.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum CrossShardAbort {
+    /// The tx writes a number of shards which is not supported.
+    UnsupportedShards(usize),
+    /// The part writes the accounts out of its shard.
+    InvalidSlice(ShardId),
+    /// The parts disagree on the shards or the commitment.
+    Inconsistent,
+    /// The parts do not add up to the write set committed, or the tx proposal writes
+    /// something else.
+    InvalidCommitment,
+    /// None or more than one of the parts carries the tx proposal.
+    InvalidProposal,
+    /// The parts of some shards are missing after the timeout.
+    Timeout,
+}
+
+impl CrossShardAbort {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::UnsupportedShards(_) => "unsupported_shards",
+            Self::InvalidSlice(_) => "invalid_slice",
+            Self::Inconsistent => "inconsistent",
+            Self::InvalidCommitment => "invalid_commitment",
+            Self::InvalidProposal => "invalid_proposal",
+            Self::Timeout => "timeout",
+        }
+    }
+}
+
+impl fmt::Display for CrossShardAbort {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnsupportedShards(n) => write!(
+                f,
+                "Cross-shard tx writing {} shards (supported: 2 to {}).",
+                n, MAX_CROSS_SHARD_TX_SHARDS
+            ),
+            Self::InvalidSlice(shard_id) => write!(
+                f,
+                "Cross-shard tx part writing the accounts out of shard {}/{}.",
+                shard_id.id, shard_id.total
+            ),
+            Self::Inconsistent => f.write_str("Inconsistent cross-shard tx parts."),
+            Self::InvalidCommitment => {
+                f.write_str("Cross-shard tx parts not matching the write set committed.")
+            }
+            Self::InvalidProposal => {
+                f.write_str("Cross-shard tx parts without exactly one tx proposal.")
+            }
+            Self::Timeout => f.write_str("Timeout to collect the cross-shard tx parts."),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum CrossShardOutcome<Tx: TxTrait> {
+    /// Waiting for the parts of the other shards.
+    Pending,
+    /// All the parts are present and consistent, and the tx may be included.
+    Ready(TxProposal<Tx>),
+    Aborted {
+        tx_id: H256,
+        reason: CrossShardAbort,
+    },
+}
+
+struct PendingCrossShardTx<Tx: TxTrait> {
+    parts: HashMap<ShardId, CrossShardTxProposal<Tx>>,
+    first_seen: Instant,
+}
+
+/// Collect the parts of the cross-shard txs on the raft leader.
+pub struct CrossShardCoordinator<Tx: TxTrait> {
+    timeout: Duration,
+    pending: HashMap<H256, PendingCrossShardTx<Tx>>,
+    /// The txs decided, whose late parts are dropped until the timeout.
+    decided: HashMap<H256, Instant>,
+}
+
+impl<Tx: TxTrait> CrossShardCoordinator<Tx> {
+    /// Create the coordinator aborting the txs whose parts are not all present within
+    /// `timeout`.
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            timeout,
+            pending: HashMap::new(),
+            decided: HashMap::new(),
+        }
+    }
+
+    pub fn pending_len(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn add(&mut self, part: CrossShardTxProposal<Tx>) -> CrossShardOutcome<Tx> {
+        let tx_id = part.tx_id;
+        if self.decided.contains_key(&tx_id) {
+            return CrossShardOutcome::Pending;
+        }
+
+        let outcome = self.add_inner(part);
+        if !matches!(outcome, CrossShardOutcome::Pending) {
+            self.pending.remove(&tx_id);
+            self.decided.insert(tx_id, Instant::now());
+        }
+        outcome
+    }
+
+    fn add_inner(&mut self, part: CrossShardTxProposal<Tx>) -> CrossShardOutcome<Tx> {
+        let tx_id = part.tx_id;
+        let abort = |reason| CrossShardOutcome::Aborted { tx_id, reason };
+        if let Err(reason) = part.prepare() {
+            return abort(reason);
+        }
+
+        let pending = self
+            .pending
+            .entry(tx_id)
+            .or_insert_with(|| PendingCrossShardTx {
+                parts: HashMap::new(),
+                first_seen: Instant::now(),
+            });
+        if pending
+            .parts
+            .values()
+            .any(|p| p.shards != part.shards || p.commitment != part.commitment)
+        {
+            return abort(CrossShardAbort::Inconsistent);
+        }
+        pending.parts.insert(part.shard_id, part);
+
+        let shards = pending
+            .parts
+            .values()
+            .next()
+            .map(|p| p.shards.clone())
+            .unwrap_or_default();
+        if !shards.iter().all(|shard| pending.parts.contains_key(shard)) {
+            return CrossShardOutcome::Pending;
+        }
+
+        let parts = pending.parts.values();
+        let mut writes = TxWriteData::default();
+        let mut tx_proposals = Vec::with_capacity(1);
+        let mut commitment = H256::zero();
+        for part in parts {
+            writes.merge(&part.writes);
+            commitment = part.commitment;
+            if let Some(tx_proposal) = part.tx_proposal.as_ref() {
+                tx_proposals.push(tx_proposal);
+            }
+        }
+        let tx_proposal = match tx_proposals.as_slice() {
+            [tx_proposal] => *tx_proposal,
+            _ => return abort(CrossShardAbort::InvalidProposal),
+        };
+        if tx_proposal.tx.id() != tx_id {
+            return abort(CrossShardAbort::Inconsistent);
+        }
+        if writes.to_digest() != commitment || tx_proposal.tx.tx_writes().to_digest() != commitment
+        {
+            return abort(CrossShardAbort::InvalidCommitment);
+        }
+        CrossShardOutcome::Ready(tx_proposal.clone())
+    }
+
+    /// Abort the txs pending for longer than the timeout by `now`, and forget the decided ones.
+    pub fn expire(&mut self, now: Instant) -> Vec<(H256, CrossShardAbort)> {
+        let timeout = self.timeout;
+        let expired: Vec<H256> = self
+            .pending
+            .iter()
+            .filter(|(_, pending)| now.saturating_duration_since(pending.first_seen) >= timeout)
+            .map(|(&tx_id, _)| tx_id)
+            .collect();
+        let mut aborted = Vec::with_capacity(expired.len());
+        for tx_id in expired {
+            self.pending.remove(&tx_id);
+            self.decided.insert(tx_id, now);
+            aborted.push((tx_id, CrossShardAbort::Timeout));
+        }
+        self.decided
+            .retain(|_, decided| now.saturating_duration_since(*decided) < timeout);
+        aborted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        db::{Transaction, DB},
+        test_utils::DummyTx,
+    };
+    use slimchain_common::basic::{Address, Nonce, StateKey, StateValue, H160};
+    use slimchain_tx_state::{
+        update_tx_state, InShardData, OutShardData, StorageTxTrie, TxTrieTrait,
+    };
+
+    fn addr(i: u64) -> Address {
+        Address::from(H160::from_low_u64_be(i))
+    }
+
+    /// A tx writing address 2 in shard 0 and address 3 in shard 1 of 2.
+    fn cross_shard_proposal(id: u64) -> TxProposal<DummyTx> {
+        let mut writes = TxWriteData::default();
+        for &i in &[2, 3] {
+            writes.add_nonce(addr(i), Nonce::from(id));
+            writes.add_value(
+                addr(i),
+                StateKey(H256::from_low_u64_be(i)),
+                StateValue(H256::from_low_u64_be(id)),
+            );
+        }
+        let tx = DummyTx {
+            writes,
+            ..DummyTx::new(id, id, id)
+        };
+        tx.into_proposal()
+    }
+
+    #[test]
+    fn test_split() {
+        let shard0 = ShardId::new(0, 2);
+        let shard1 = ShardId::new(1, 2);
+        let tx_proposal = cross_shard_proposal(1);
+        let writes = tx_proposal.tx.writes.clone();
+        assert!(is_cross_shard(&writes, 2));
+        assert!(!is_cross_shard(&writes, 1));
+        assert_eq!(write_shards(&writes, 2), vec![shard0, shard1]);
+
+        let parts = CrossShardTxProposal::split(tx_proposal, shard1);
+        assert_eq!(parts.len(), 2);
+        assert!(parts[0].tx_proposal.is_none());
+        assert!(parts[1].tx_proposal.is_some());
+        for part in &parts {
+            part.prepare().unwrap();
+            assert_eq!(part.commitment, writes.to_digest());
+            assert_eq!(part.writes.len(), 1);
+        }
+        assert!(parts[0].writes.contains_key(&addr(2)));
+        assert!(parts[1].writes.contains_key(&addr(3)));
+
+        let mut invalid = parts[0].clone();
+        invalid.shard_id = shard1;
+        assert_eq!(
+            invalid.prepare(),
+            Err(CrossShardAbort::InvalidSlice(shard1))
+        );
+    }
+
+    #[test]
+    fn test_coordinator_commit() {
+        let mut coordinator = CrossShardCoordinator::new(Duration::from_secs(10));
+        let tx_proposal = cross_shard_proposal(1);
+        let tx_id = tx_proposal.tx.id();
+        let mut parts = CrossShardTxProposal::split(tx_proposal, ShardId::new(0, 2));
+
+        // The parts may arrive in any order.
+        let part1 = parts.pop().unwrap();
+        let part0 = parts.pop().unwrap();
+        assert!(matches!(
+            coordinator.add(part1.clone()),
+            CrossShardOutcome::Pending
+        ));
+        assert_eq!(coordinator.pending_len(), 1);
+        match coordinator.add(part0) {
+            CrossShardOutcome::Ready(tx_proposal) => assert_eq!(tx_proposal.tx.id(), tx_id),
+            outcome => panic!("Unexpected outcome: {:?}", outcome),
+        }
+        assert_eq!(coordinator.pending_len(), 0);
+
+        // The late duplicates are dropped.
+        assert!(matches!(coordinator.add(part1), CrossShardOutcome::Pending));
+        assert_eq!(coordinator.pending_len(), 0);
+    }
+
+    #[test]
+    fn test_coordinator_abort() {
+        let timeout = Duration::from_secs(10);
+        let mut coordinator = CrossShardCoordinator::new(timeout);
+        let shard0 = ShardId::new(0, 2);
+
+        // The part of shard 1 never arrives.
+        let tx_proposal = cross_shard_proposal(1);
+        let tx_id1 = tx_proposal.tx.id();
+        let mut parts = CrossShardTxProposal::split(tx_proposal, shard0);
+        assert!(matches!(
+            coordinator.add(parts.remove(0)),
+            CrossShardOutcome::Pending
+        ));
+        assert!(coordinator.expire(Instant::now()).is_empty());
+        assert_eq!(
+            coordinator.expire(Instant::now() + timeout),
+            vec![(tx_id1, CrossShardAbort::Timeout)]
+        );
+        assert_eq!(coordinator.pending_len(), 0);
+
+        // The part of shard 1 does not add up to the write set committed.
+        let tx_proposal = cross_shard_proposal(2);
+        let tx_id2 = tx_proposal.tx.id();
+        let mut parts = CrossShardTxProposal::split(tx_proposal, shard0);
+        parts[1].writes.add_nonce(addr(3), Nonce::from(100));
+        let part1 = parts.pop().unwrap();
+        assert!(matches!(
+            coordinator.add(parts.pop().unwrap()),
+            CrossShardOutcome::Pending
+        ));
+        match coordinator.add(part1) {
+            CrossShardOutcome::Aborted { tx_id, reason } => {
+                assert_eq!(tx_id, tx_id2);
+                assert_eq!(reason, CrossShardAbort::InvalidCommitment);
+            }
+            outcome => panic!("Unexpected outcome: {:?}", outcome),
+        }
+
+        // The parts disagree on the commitment.
+        let tx_proposal = cross_shard_proposal(3);
+        let mut parts = CrossShardTxProposal::split(tx_proposal, shard0);
+        parts[1].commitment = H256::repeat_byte(1);
+        let part1 = parts.pop().unwrap();
+        coordinator.add(parts.pop().unwrap());
+        match coordinator.add(part1) {
+            CrossShardOutcome::Aborted { reason, .. } => {
+                assert_eq!(reason, CrossShardAbort::Inconsistent)
+            }
+            outcome => panic!("Unexpected outcome: {:?}", outcome),
+        }
+
+        // No part carries the tx proposal.
+        let tx_proposal = cross_shard_proposal(4);
+        let mut parts = CrossShardTxProposal::split(tx_proposal, shard0);
+        parts[0].tx_proposal = None;
+        let part1 = parts.pop().unwrap();
+        coordinator.add(parts.pop().unwrap());
+        match coordinator.add(part1) {
+            CrossShardOutcome::Aborted { reason, .. } => {
+                assert_eq!(reason, CrossShardAbort::InvalidProposal)
+            }
+            outcome => panic!("Unexpected outcome: {:?}", outcome),
+        }
+
+        // A tx writing three shards is not supported.
+        let mut tx_proposal = cross_shard_proposal(5);
+        tx_proposal.tx.writes.add_nonce(addr(4), Nonce::from(1));
+        let parts = CrossShardTxProposal::split(tx_proposal, ShardId::new(0, 3));
+        assert_eq!(parts.len(), 3);
+        match coordinator.add(parts[0].clone()) {
+            CrossShardOutcome::Aborted { reason, .. } => {
+                assert_eq!(reason, CrossShardAbort::UnsupportedShards(3))
+            }
+            outcome => panic!("Unexpected outcome: {:?}", outcome),
+        }
+        assert_eq!(coordinator.pending_len(), 0);
+    }
+
+    #[test]
+    fn test_storage_commits_shard_slice() {
+        let db = DB::load_test();
+        let mut init = TxWriteData::default();
+        init.add_nonce(addr(2), Nonce::from(1));
+        init.add_nonce(addr(3), Nonce::from(1));
+        let update = update_tx_state(db.as_ref(), H256::zero(), &init).unwrap();
+        let mut tx = Transaction::new();
+        tx.update_state(&update).unwrap();
+        db.write_sync(tx).unwrap();
+
+        let writes = cross_shard_proposal(1).tx.writes;
+        let full_root = update_tx_state(db.as_ref(), update.root, &writes)
+            .unwrap()
+            .root;
+        for &(id, in_shard, out_shard) in &[(0, 2, 3), (1, 3, 2)] {
+            let mut trie = StorageTxTrie::new(
+                ShardId::new(id, 2),
+                InShardData::new(db.clone(), update.root),
+                OutShardData::default(),
+            );
+            let shard_update = trie.apply_writes(&writes).unwrap();
+            // Every shard derives the same state root, but only keeps its own accounts.
+            assert_eq!(shard_update.root, full_root);
+            assert!(shard_update.state_nodes.contains_key(&addr(in_shard)));
+            assert!(!shard_update.state_nodes.contains_key(&addr(out_shard)));
+        }
+    }
+}
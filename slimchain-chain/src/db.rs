@@ -1,13 +1,17 @@
 use crate::{
-    block::BlockTrait,
+    block::{BlockHeader, BlockTrait, PrunedBlock},
+    block_proposal::BlockProposal,
     loader::{BlockLoaderTrait, TxLoaderTrait},
     role::Role,
 };
+use futures::{prelude::*, stream};
 use kvdb::{DBKey, DBTransaction, KeyValueDB};
+use once_cell::sync::OnceCell;
 use serde::{Deserialize, Serialize};
 use slimchain_common::{
     basic::{AccountData, Address, BlockHeight, StateValue, H256},
-    error::{Context as _, Error, Result},
+    digest::Digestible,
+    error::{ensure, Context as _, Error, Result},
     tx::TxTrait,
 };
 use slimchain_tx_state::{TrieNode, TxStateUpdate, TxStateView};
@@ -15,9 +19,32 @@ use slimchain_utils::{
     record_event,
     serde::{binary_decode, binary_encode},
 };
-use std::{path::Path, sync::Arc};
+use std::{
+    ffi::OsString,
+    fmt, fs,
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, RwLock,
+    },
+    time::{Duration, Instant},
+};
+
+pub mod backup;
+
+pub mod integrity;
+
+pub mod journal;
+use journal::Journal;
+
+pub mod read_pool;
+use read_pool::{ReadPool, DEFAULT_READ_THREADS};
 
-pub const TOTAL_COLS: u32 = 5;
+pub mod write_metrics;
+use write_metrics::{PendingWrite, WriteStats};
+
+pub const TOTAL_COLS: u32 = 10;
 // store meta data
 pub const META_DB_COL: u32 = 0;
 // store block height <-> block
@@ -28,6 +55,31 @@ pub const TX_DB_COL: u32 = 2;
 pub const STATE_DB_COL: u32 = 3;
 // store log_idx <-> log
 pub const LOG_DB_COL: u32 = 4;
+// store tx_hash <-> (block height, index in block)
+pub const TX_LOC_DB_COL: u32 = 5;
+// store (caller address, block height, index in block) <-> tx_hash
+pub const ADDR_TX_DB_COL: u32 = 6;
+// store block hash <-> block height
+pub const BLOCK_HASH_DB_COL: u32 = 7;
+// store tx_hash <-> pending tx proposal
+pub const MEMPOOL_DB_COL: u32 = 8;
+// store block height <-> pruned block
+pub const PRUNED_BLOCK_DB_COL: u32 = 9;
+
+pub const DB_SNAPSHOT_ARCHIVE_VERSION: u32 = 1;
+
+// Each column is a column family of RocksDB with its own block cache and write buffers, sized by
+// the memory budget in MiB. The columns missing here use the default budget of kvdb-rocksdb.
+const COL_MEMORY_BUDGETS: &[(u32, usize)] = &[
+    // read by the tx executions, the proofs and the state fetches of the peers
+    (STATE_DB_COL, 512),
+    (BLOCK_DB_COL, 128),
+    // mostly written once and read by the tx receipts
+    (TX_DB_COL, 64),
+];
+
+// number of blocks read from the database in one blocking task
+const ITER_BLOCKS_BATCH_SIZE: u64 = 64;
 
 #[inline]
 pub fn h256_to_db_key(input: H256) -> DBKey {
@@ -56,38 +108,321 @@ pub fn u64_to_db_key(input: u64) -> DBKey {
     key
 }
 
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct DBSnapshotManifest {
+    pub version: u32,
+    pub height: BlockHeight,
+    pub state_root: H256,
+    pub latest_block_header: BlockHeader,
+    pub block_count: usize,
+    pub tx_count: usize,
+    pub state_node_count: usize,
+}
+
+#[derive(Serialize, Deserialize)]
+struct DBSnapshotArchive {
+    manifest: DBSnapshotManifest,
+    // (col, key, value) with the value kept in its on-disk encoding.
+    entries: Vec<(u32, Vec<u8>, Vec<u8>)>,
+}
+
+#[inline]
+pub fn addr_tx_to_db_key(addr: Address, height: BlockHeight, idx: usize) -> DBKey {
+    // use big endian so that the keys are sorted by height and index within one address.
+    let mut key = DBKey::new();
+    key.extend_from_slice(addr.as_bytes());
+    key.extend_from_slice(&height.to_be_bytes()[..]);
+    key.extend_from_slice(&(idx as u64).to_be_bytes()[..]);
+    key
+}
+
+/// A range of keys `[start, end)` within a column. A missing bound is unbounded.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct KeyRange {
+    pub col: u32,
+    pub start: Option<Vec<u8>>,
+    pub end: Option<Vec<u8>>,
+}
+
+impl KeyRange {
+    pub fn col(col: u32) -> Self {
+        Self {
+            col,
+            start: None,
+            end: None,
+        }
+    }
+
+    pub fn contains(&self, key: &[u8]) -> bool {
+        self.start.as_deref().map_or(true, |start| key >= start)
+            && self.end.as_deref().map_or(true, |end| key < end)
+    }
+}
+
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ColumnStats {
+    pub keys: u64,
+    /// Total size of the keys and the values.
+    pub data_size: u64,
+}
+
+#[derive(Debug, Default, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct DbStats {
+    /// Total size of the keys and the values in all columns.
+    pub live_data_size: u64,
+    /// Total size of the database files. Zero for the in-memory database.
+    pub total_file_size: u64,
+    pub block_count: u64,
+    pub tx_count: u64,
+    pub state_node_count: u64,
+    /// Indexed by the column id.
+    pub columns: Vec<ColumnStats>,
+}
+
 pub struct DB {
-    db: Box<dyn KeyValueDB>,
+    db: Arc<dyn KeyValueDB>,
+    /// The secondary instance following a database opened by another process, see
+    /// [`DB::open_read_only`].
+    secondary: Option<Arc<kvdb_rocksdb::Database>>,
+    path: Option<PathBuf>,
+    /// The write-ahead journal of the on-disk database, see [`journal`].
+    journal: Option<Journal>,
+    read_threads: usize,
+    /// Spawned on the first async read, see [`read_pool`].
+    read_pool: OnceCell<ReadPool>,
+    /// Shared by the writes, which are applied concurrently, so that a backup can hold them
+    /// back while it pins the view of every column, see [`backup`].
+    write_gate: RwLock<()>,
+    pending_writes: Arc<AtomicUsize>,
 }
 
 pub type DBPtr = Arc<DB>;
 
+/// Where the database is stored.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DbBackend {
+    /// RocksDB on the disk.
+    RocksDb,
+    /// In the memory, lost once the node exits.
+    Memory,
+}
+
+impl Default for DbBackend {
+    fn default() -> Self {
+        Self::RocksDb
+    }
+}
+
+/// The error of writing to a database opened by [`DB::open_read_only`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct ReadOnly;
+
+impl fmt::Display for ReadOnly {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "The database is opened read-only.")
+    }
+}
+
+impl std::error::Error for ReadOnly {}
+
+/// The error of loading a block whose body is pruned by the headers storage mode. Its
+/// [`PrunedBlock`] is still available by [`DB::get_pruned_block`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct BlockPruned {
+    pub height: BlockHeight,
+}
+
+impl fmt::Display for BlockPruned {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Block {} is pruned.", self.height)
+    }
+}
+
+impl std::error::Error for BlockPruned {}
+
+/// The journal of the database at `path` is kept next to it, e.g., `storage.db.journal`.
+pub fn journal_path(path: &Path) -> PathBuf {
+    let mut journal_path = OsString::from(path.as_os_str());
+    journal_path.push(".journal");
+    journal_path.into()
+}
+
 impl DB {
-    pub fn open_or_create(path: &Path, enable_statistics: bool) -> Result<Arc<Self>> {
+    /// Open the database at `path`, and apply the transactions left in its journal by a crash.
+    /// The async reads run on `read_threads` threads. Unless `enable_journal`, the writes skip
+    /// the journal, so a crash may leave a commit partially applied.
+    pub fn open_or_create(
+        path: &Path,
+        enable_statistics: bool,
+        enable_journal: bool,
+        read_threads: usize,
+    ) -> Result<Arc<Self>> {
         info!("Open database at {}", path.display());
         let mut cfg = kvdb_rocksdb::DatabaseConfig::with_columns(TOTAL_COLS);
         cfg.enable_statistics = enable_statistics;
+        cfg.memory_budget = COL_MEMORY_BUDGETS.iter().copied().collect();
         let db = kvdb_rocksdb::Database::open(&cfg, &path.to_string_lossy())?;
-        Ok(Arc::new(Self { db: Box::new(db) }))
+        let journal = Journal::open(&journal_path(path))?;
+        let recovered = journal
+            .recover(|tx| db.write(tx))
+            .context("Failed to recover the database from the journal.")?;
+        if recovered > 0 {
+            warn!(
+                "Recover {} transactions from the journal at {}.",
+                recovered,
+                journal.path().display()
+            );
+            record_event!("db_journal_recover", "txs": recovered);
+        }
+        // The one left by a run with the journal is still recovered above.
+        let journal = if enable_journal { Some(journal) } else { None };
+        Ok(Self::with_backend(
+            Arc::new(db),
+            Some(path.to_path_buf()),
+            journal,
+            read_threads,
+        ))
+    }
+
+    /// Open the database at `path` read-only, e.g., to inspect the one of a running node. It
+    /// follows the database as a secondary instance of RocksDB, which sees the data written
+    /// after it is opened once [`DB::catch_up`] is called. The writes fail with [`ReadOnly`].
+    ///
+    /// The transactions left in the journal are not applied, so the data of a commit cut by a
+    /// crash is missing until the node restarts.
+    pub fn open_read_only(path: &Path) -> Result<Arc<Self>> {
+        static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+        info!("Open database at {} read-only", path.display());
+        ensure!(path.exists(), "Database {} does not exist.", path.display());
+        // The secondary instance keeps its own info logs.
+        let log_dir = std::env::temp_dir().join(format!(
+            "slimchain-db-secondary-{}-{}",
+            std::process::id(),
+            NEXT_ID.fetch_add(1, Ordering::SeqCst)
+        ));
+        let mut cfg = kvdb_rocksdb::DatabaseConfig::with_columns(TOTAL_COLS);
+        cfg.memory_budget = COL_MEMORY_BUDGETS.iter().copied().collect();
+        cfg.secondary = Some(log_dir.to_string_lossy().into_owned());
+        let db = Arc::new(kvdb_rocksdb::Database::open(&cfg, &path.to_string_lossy())?);
+        let mut this = Self::new(
+            db.clone(),
+            Some(path.to_path_buf()),
+            None,
+            DEFAULT_READ_THREADS,
+        );
+        this.secondary = Some(db);
+        Ok(Arc::new(this))
+    }
+
+    /// Whether the database is opened by [`DB::open_read_only`].
+    pub fn is_read_only(&self) -> bool {
+        self.secondary.is_some()
+    }
+
+    /// Refresh the view of a read-only database with the data written since it is opened or
+    /// last caught up. A no-op for the other databases, which always see their own writes.
+    pub fn catch_up(&self) -> Result<()> {
+        match self.secondary.as_ref() {
+            Some(db) => db
+                .try_catch_up_with_primary()
+                .context("Failed to catch up with the primary database."),
+            None => Ok(()),
+        }
+    }
+
+    /// Create an empty in-memory database, e.g., for the tests and the simulations. Its
+    /// transactions are applied atomically, but nothing is persisted.
+    pub fn open_memory(read_threads: usize) -> Arc<Self> {
+        Self::with_backend(
+            Arc::new(kvdb_memorydb::create(TOTAL_COLS)),
+            None,
+            None,
+            read_threads,
+        )
+    }
+
+    /// Open the database of `role` in `dir` on `backend`.
+    pub fn open_with_backend(
+        backend: DbBackend,
+        dir: &Path,
+        role: Role,
+        enable_statistics: bool,
+        enable_journal: bool,
+        read_threads: usize,
+    ) -> Result<Arc<Self>> {
+        match backend {
+            DbBackend::RocksDb => Self::open_or_create_in_dir(
+                dir,
+                role,
+                enable_statistics,
+                enable_journal,
+                read_threads,
+            ),
+            DbBackend::Memory => {
+                info!("Open in-memory database");
+                Ok(Self::open_memory(read_threads))
+            }
+        }
     }
 
     pub fn open_or_create_in_dir(
         dir: &Path,
         role: Role,
         enable_statistics: bool,
+        enable_journal: bool,
+        read_threads: usize,
     ) -> Result<Arc<Self>> {
         let db_file = match role {
             Role::Client => "client.db",
             Role::Miner => "miner.db",
             Role::Storage(_) => "storage.db",
+            Role::Observer => "observer.db",
         };
-        Self::open_or_create(&dir.join(db_file), enable_statistics)
+        Self::open_or_create(
+            &dir.join(db_file),
+            enable_statistics,
+            enable_journal,
+            read_threads,
+        )
     }
 
     #[cfg(test)]
     pub fn load_test() -> Arc<Self> {
-        let db = kvdb_memorydb::create(TOTAL_COLS);
-        Arc::new(Self { db: Box::new(db) })
+        Self::open_memory(read_pool::DEFAULT_READ_THREADS)
+    }
+
+    #[cfg(test)]
+    pub fn load_test_with_backend(db: Box<dyn KeyValueDB>, read_threads: usize) -> Arc<Self> {
+        Self::with_backend(Arc::from(db), None, None, read_threads)
+    }
+
+    fn with_backend(
+        db: Arc<dyn KeyValueDB>,
+        path: Option<PathBuf>,
+        journal: Option<Journal>,
+        read_threads: usize,
+    ) -> Arc<Self> {
+        Arc::new(Self::new(db, path, journal, read_threads))
+    }
+
+    fn new(
+        db: Arc<dyn KeyValueDB>,
+        path: Option<PathBuf>,
+        journal: Option<Journal>,
+        read_threads: usize,
+    ) -> Self {
+        Self {
+            db,
+            secondary: None,
+            path,
+            journal,
+            read_threads,
+            read_pool: OnceCell::new(),
+            write_gate: RwLock::new(()),
+            pending_writes: Arc::new(AtomicUsize::new(0)),
+        }
     }
 
     pub fn get_object<T: for<'de> Deserialize<'de>>(
@@ -123,20 +458,438 @@ impl DB {
         self.get_object(LOG_DB_COL, &u64_to_db_key(idx))
     }
 
+    pub fn get_tx_location(&self, tx_hash: H256) -> Result<Option<(BlockHeight, usize)>> {
+        self.get_object(TX_LOC_DB_COL, &h256_to_db_key(tx_hash))
+    }
+
+    /// Get the height of the committed block with `blk_hash`. The genesis block is not
+    /// indexed.
+    pub fn get_block_height_by_hash(&self, blk_hash: H256) -> Result<Option<BlockHeight>> {
+        self.get_object(BLOCK_HASH_DB_COL, &h256_to_db_key(blk_hash))
+    }
+
+    /// Get the block at `height` whose body is pruned. Return `None` if it is not pruned.
+    pub fn get_pruned_block(&self, height: BlockHeight) -> Result<Option<PrunedBlock>> {
+        self.get_object(PRUNED_BLOCK_DB_COL, &block_height_to_db_key(height))
+    }
+
+    /// The height up to which the block bodies are pruned, or zero if none.
+    pub fn get_pruned_height(&self) -> Result<BlockHeight> {
+        Ok(self.get_meta_object("pruned-height")?.unwrap_or_default())
+    }
+
+    /// Get at most `limit` txs sent by `addr` since `from_height`, ordered by block height and
+    /// the index within the block.
+    pub fn get_txs_by_address(
+        &self,
+        addr: Address,
+        from_height: BlockHeight,
+        limit: usize,
+    ) -> Result<Vec<(BlockHeight, H256)>> {
+        let addr_len = addr.as_bytes().len();
+        let mut out = Vec::new();
+        for (k, v) in self.db.iter_with_prefix(ADDR_TX_DB_COL, addr.as_bytes()) {
+            if out.len() >= limit {
+                break;
+            }
+            let mut height_bytes = [0u8; 8];
+            height_bytes.copy_from_slice(&k[addr_len..addr_len + 8]);
+            let height = BlockHeight::from(u64::from_be_bytes(height_bytes));
+            if height < from_height {
+                continue;
+            }
+            out.push((height, binary_decode(&v[..])?));
+        }
+        Ok(out)
+    }
+
+    /// Get all the pending tx proposals persisted by the mempool, in no particular order.
+    pub fn get_mempool_entries<T: for<'de> Deserialize<'de>>(&self) -> Result<Vec<T>> {
+        self.db
+            .iter(MEMPOOL_DB_COL)
+            .map(|(_, v)| binary_decode(&v[..]))
+            .collect()
+    }
+
+    /// Get a state node in its on-disk encoding.
+    pub fn get_state_node_bin(&self, node_address: H256) -> Result<Option<Vec<u8>>> {
+        self.db
+            .get(STATE_DB_COL, &h256_to_db_key(node_address))
+            .map_err(Error::msg)
+    }
+
+    pub fn get_latest_block_header(&self) -> Result<Option<BlockHeader>> {
+        self.get_meta_object("latest-block-header")
+    }
+
     pub fn get_table_size(&self, col: u32) -> usize {
         self.db.iter(col).map(|(k, v)| k.len() + v.len()).sum()
     }
 
+    pub fn column_stats(&self, col: u32, range: Option<&KeyRange>) -> ColumnStats {
+        let mut stats = ColumnStats::default();
+        for (k, v) in self.db.iter(col) {
+            if range.map_or(true, |range| range.contains(&k)) {
+                stats.keys += 1;
+                stats.data_size += (k.len() + v.len()) as u64;
+            }
+        }
+        stats
+    }
+
+    /// Collect the space usage of the database.
+    ///
+    /// It iterates all columns, so avoid calling it on the hot path.
+    pub fn storage_stats(&self) -> Result<DbStats> {
+        let columns: Vec<_> = (0..TOTAL_COLS)
+            .map(|col| self.column_stats(col, None))
+            .collect();
+        let total_file_size = match self.path.as_ref() {
+            Some(path) => dir_size(path)?,
+            None => 0,
+        };
+        Ok(DbStats {
+            live_data_size: columns.iter().map(|c| c.data_size).sum(),
+            total_file_size,
+            block_count: columns[BLOCK_DB_COL as usize].keys,
+            tx_count: columns[TX_DB_COL as usize].keys,
+            state_node_count: columns[STATE_DB_COL as usize].keys,
+            columns,
+        })
+    }
+
+    /// Record the space usage of the keys in `range`, or of all columns if missing, so that
+    /// the background compaction of RocksDB can be tracked. kvdb does not expose the manual
+    /// compaction, so this compacts nothing itself. The range is scanned, so keep it off the
+    /// hot paths.
+    pub fn record_compaction_stats(&self, range: Option<KeyRange>) -> Result<()> {
+        match range {
+            Some(range) => {
+                let stats = self.column_stats(range.col, Some(&range));
+                debug!(col = range.col, ?stats, "Database range stats.");
+                record_event!("db_compaction_stats", "col": range.col, "keys": stats.keys, "data_size": stats.data_size);
+            }
+            None => {
+                let stats = self.storage_stats()?;
+                debug!(?stats, "Database stats.");
+                record_event!("db_compaction_stats", "live_data_size": stats.live_data_size, "total_file_size": stats.total_file_size);
+            }
+        }
+        Ok(())
+    }
+
+    /// Write `tx` to the backend, through the journal if any.
+    fn write(&self, tx: DBTransaction) -> Result<()> {
+        if self.is_read_only() {
+            return Err(ReadOnly.into());
+        }
+        match self.journal.as_ref() {
+            Some(journal) => journal.write(tx, |tx| self.db.write(tx)),
+            None => self.db.write(tx).map_err(Error::msg),
+        }
+    }
+
+    /// Apply `tx` queued at `queued_at`, and record its [`WriteStats`], which are returned along
+    /// with the result of the write.
+    fn write_tx(&self, tx: Transaction, queued_at: Instant) -> (Result<()>, WriteStats) {
+        let _write_guard = self.write_gate.read().unwrap_or_else(|e| e.into_inner());
+        let mut stats = WriteStats::new(&tx.inner, tx.encode_time);
+        let begin = Instant::now();
+        stats.queue_wait = begin.saturating_duration_since(queued_at);
+        let res = self.write(tx.inner);
+        stats.backend_time = begin.elapsed();
+        stats.record();
+        (res, stats)
+    }
+
     pub fn write_sync(&self, tx: Transaction) -> Result<()> {
-        self.db.write(tx.inner).map_err(Error::msg)
+        let _pending = PendingWrite::new(&self.pending_writes);
+        self.write_tx(tx, Instant::now()).0
     }
 
     pub async fn write_async(self: &Arc<Self>, tx: Transaction) -> Result<()> {
+        self.write_async_with_stats(tx).await?.0
+    }
+
+    /// [`DB::write_async`] returning the [`WriteStats`] of the write as well.
+    async fn write_async_with_stats(
+        self: &Arc<Self>,
+        tx: Transaction,
+    ) -> Result<(Result<()>, WriteStats)> {
         let this = self.clone();
-        tokio::task::spawn_blocking(move || this.db.write(tx.inner))
-            .await?
-            .map_err(Error::msg)
+        let pending = PendingWrite::new(&self.pending_writes);
+        let queued_at = Instant::now();
+        Ok(tokio::task::spawn_blocking(move || {
+            let _pending = pending;
+            this.write_tx(tx, queued_at)
+        })
+        .await?)
+    }
+
+    /// The number of the writes queued or being applied.
+    pub fn pending_writes(&self) -> usize {
+        self.pending_writes.load(Ordering::SeqCst)
+    }
+
+    fn read_pool(&self) -> Result<&ReadPool> {
+        self.read_pool
+            .get_or_try_init(|| ReadPool::new(self.read_threads))
+    }
+
+    /// Run the reads in `f` on the read threads of the database, so that they do not block
+    /// the async runtime.
+    pub async fn read_async<T: Send + 'static>(
+        self: &Arc<Self>,
+        f: impl FnOnce(&Self) -> Result<T> + Send + 'static,
+    ) -> Result<T> {
+        let this = self.clone();
+        self.read_pool()?.spawn(move || f(&this)).await?
+    }
+
+    pub async fn get_block_async<Block>(self: &Arc<Self>, height: BlockHeight) -> Result<Block>
+    where
+        Block: BlockTrait + for<'de> Deserialize<'de> + 'static,
+    {
+        self.read_async(move |db| db.get_block(height)).await
+    }
+
+    pub async fn get_tx_async<Tx>(self: &Arc<Self>, tx_hash: H256) -> Result<Tx>
+    where
+        Tx: TxTrait + for<'de> Deserialize<'de> + 'static,
+    {
+        self.read_async(move |db| db.get_tx(tx_hash)).await
+    }
+
+    /// Load the state nodes in their on-disk encoding, or `None` for the ones missing.
+    pub async fn load_nodes_async(
+        self: &Arc<Self>,
+        node_addresses: Vec<H256>,
+    ) -> Result<Vec<Option<Vec<u8>>>> {
+        self.read_async(move |db| {
+            node_addresses
+                .into_iter()
+                .map(|node_address| db.get_state_node_bin(node_address))
+                .collect()
+        })
+        .await
+    }
+
+    fn iter_by_height<T: Send + 'static>(
+        self: &Arc<Self>,
+        range: Range<BlockHeight>,
+        load_fn: impl Fn(&Arc<Self>, BlockHeight) -> Result<T> + Copy + Send + 'static,
+    ) -> impl Stream<Item = Result<T>> {
+        let db = self.clone();
+        let Range { start, end } = range;
+        let batches = (start.0..end.0)
+            .step_by(ITER_BLOCKS_BATCH_SIZE as usize)
+            .map(move |batch_start| {
+                (
+                    batch_start,
+                    std::cmp::min(batch_start + ITER_BLOCKS_BATCH_SIZE, end.0),
+                )
+            });
+
+        stream::iter(batches)
+            .then(move |(batch_start, batch_end)| {
+                let db = db.clone();
+                async move {
+                    let items = tokio::task::spawn_blocking(move || {
+                        (batch_start..batch_end)
+                            .map(|height| {
+                                let height = BlockHeight::from(height);
+                                load_fn(&db, height).with_context(|| {
+                                    format!(
+                                        "Missing block at height {} in range {}..{}.",
+                                        height, start, end
+                                    )
+                                })
+                            })
+                            .collect::<Vec<_>>()
+                    })
+                    .await;
+
+                    match items {
+                        Ok(items) => items,
+                        Err(e) => vec![Err(Error::from(e))],
+                    }
+                }
+            })
+            .flat_map(stream::iter)
+    }
+
+    /// Iterate the blocks within `range` in order.
+    ///
+    /// A missing height inside the range yields an error item instead of being skipped.
+    pub fn iter_blocks<Block>(
+        self: &Arc<Self>,
+        range: Range<BlockHeight>,
+    ) -> impl Stream<Item = Result<Block>>
+    where
+        Block: BlockTrait + for<'de> Deserialize<'de> + 'static,
+    {
+        self.iter_by_height(range, |db, height| db.get_block(height))
     }
+
+    /// Iterate the block proposals within `range` in order, joining in the tx bodies.
+    ///
+    /// Only storage nodes store the tx bodies and the state nodes needed by this.
+    pub fn iter_block_proposals<Block, Tx>(
+        self: &Arc<Self>,
+        range: Range<BlockHeight>,
+    ) -> impl Stream<Item = Result<BlockProposal<Block, Tx>>>
+    where
+        Block: BlockTrait + for<'de> Deserialize<'de> + 'static,
+        Tx: TxTrait + for<'de> Deserialize<'de> + 'static,
+    {
+        self.iter_by_height(range, |db, height| BlockProposal::from_db(db, height))
+    }
+
+    /// Export blocks `1..=up_to_height`, their tx bodies (if stored) and the state node store
+    /// into an archive at `path`.
+    ///
+    /// Blocks are immutable once committed and state nodes are addressed by their hashes, so
+    /// concurrent commits above `up_to_height` only add entries that the archive can safely
+    /// carry along. The trie rooted at the exported state root is always complete.
+    pub fn export_snapshot<Block: BlockTrait + for<'de> Deserialize<'de>>(
+        &self,
+        path: &Path,
+        up_to_height: BlockHeight,
+    ) -> Result<DBSnapshotManifest> {
+        info!(
+            "Export database snapshot at height {} to {}",
+            up_to_height,
+            path.display()
+        );
+        ensure!(
+            !up_to_height.is_zero(),
+            "Cannot export the database snapshot at the genesis block."
+        );
+        let latest_height = self
+            .get_latest_block_header()?
+            .context("Failed to get the latest block header from the database.")?
+            .height;
+        ensure!(
+            up_to_height <= latest_height,
+            "Cannot export the database snapshot beyond the latest block (height: {}).",
+            latest_height
+        );
+
+        let mut entries = Vec::new();
+        let mut block_count = 0;
+        let mut tx_count = 0;
+        let mut latest_block: Option<Block> = None;
+        let mut height = BlockHeight::from(1);
+        while height <= up_to_height {
+            let key = block_height_to_db_key(height);
+            let bin = self
+                .db
+                .get(BLOCK_DB_COL, &key)
+                .map_err(Error::msg)?
+                .with_context(|| {
+                    format!("Failed to get block from the database. height: {}", height)
+                })?;
+            let block: Block = binary_decode(&bin[..])?;
+            for &tx_hash in block.tx_list().iter() {
+                let tx_key = h256_to_db_key(tx_hash);
+                if let Some(tx_bin) = self.db.get(TX_DB_COL, &tx_key).map_err(Error::msg)? {
+                    entries.push((TX_DB_COL, tx_key.to_vec(), tx_bin));
+                    tx_count += 1;
+                }
+            }
+            entries.push((
+                BLOCK_HASH_DB_COL,
+                h256_to_db_key(block.to_digest()).to_vec(),
+                binary_encode(&height)?,
+            ));
+            entries.push((BLOCK_DB_COL, key.to_vec(), bin));
+            block_count += 1;
+            latest_block = Some(block);
+            height = height.next_height();
+        }
+
+        let mut state_node_count = 0;
+        for (k, v) in self.db.iter(STATE_DB_COL) {
+            entries.push((STATE_DB_COL, k.into_vec(), v.into_vec()));
+            state_node_count += 1;
+        }
+
+        let latest_block_header = latest_block
+            .context("Failed to get the latest block.")?
+            .block_header()
+            .clone();
+        let manifest = DBSnapshotManifest {
+            version: DB_SNAPSHOT_ARCHIVE_VERSION,
+            height: up_to_height,
+            state_root: latest_block_header.state_root,
+            latest_block_header,
+            block_count,
+            tx_count,
+            state_node_count,
+        };
+        let archive = DBSnapshotArchive {
+            manifest: manifest.clone(),
+            entries,
+        };
+        fs::write(path, binary_encode(&archive)?)
+            .with_context(|| format!("Failed to write the archive to {}.", path.display()))?;
+        Ok(manifest)
+    }
+
+    /// Populate a fresh database from an archive created by `export_snapshot`.
+    pub fn import_snapshot(&self, path: &Path) -> Result<DBSnapshotManifest> {
+        info!("Import database snapshot from {}", path.display());
+        ensure!(
+            self.get_latest_block_header()?.is_none(),
+            "Cannot import the database snapshot into a non-empty database."
+        );
+        let bin = fs::read(path)
+            .with_context(|| format!("Failed to read the archive from {}.", path.display()))?;
+        let DBSnapshotArchive { manifest, entries } = binary_decode(&bin[..])?;
+        ensure!(
+            manifest.version == DB_SNAPSHOT_ARCHIVE_VERSION,
+            "Unsupported database snapshot version (expect: {}, actual: {}).",
+            DB_SNAPSHOT_ARCHIVE_VERSION,
+            manifest.version
+        );
+        ensure!(
+            manifest.height == manifest.latest_block_header.height
+                && manifest.state_root == manifest.latest_block_header.state_root,
+            "Inconsistent database snapshot manifest."
+        );
+
+        let mut tx = Transaction::with_capacity(entries.len() + 1);
+        for (col, key, value) in entries {
+            ensure!(
+                col == BLOCK_DB_COL
+                    || col == BLOCK_HASH_DB_COL
+                    || col == TX_DB_COL
+                    || col == STATE_DB_COL,
+                "Unexpected column {} in the database snapshot.",
+                col
+            );
+            tx.inner.put_vec(col, &key, value);
+        }
+        tx.insert_latest_block_header(&manifest.latest_block_header)?;
+        self.write_sync(tx)?;
+        Ok(manifest)
+    }
+}
+
+fn dir_size(path: &Path) -> Result<u64> {
+    let mut size = 0;
+    for entry in fs::read_dir(path)
+        .with_context(|| format!("Failed to read the directory {}.", path.display()))?
+    {
+        let entry = entry?;
+        let meta = entry.metadata()?;
+        if meta.is_dir() {
+            size += dir_size(&entry.path())?;
+        } else {
+            size += meta.len();
+        }
+    }
+    Ok(size)
 }
 
 impl Drop for DB {
@@ -158,8 +911,12 @@ impl Drop for DB {
 impl<Block: BlockTrait + for<'de> Deserialize<'de>> BlockLoaderTrait<Block> for DB {
     #[tracing::instrument(level = "debug", skip(self), err)]
     fn get_non_genesis_block(&self, height: BlockHeight) -> Result<Block> {
-        self.get_existing_object(BLOCK_DB_COL, &block_height_to_db_key(height))
-            .with_context(|| format!("Failed to get block from the database. height: {}", height))
+        let block = self.get_object(BLOCK_DB_COL, &block_height_to_db_key(height));
+        match block {
+            Ok(None) if height <= self.get_pruned_height()? => Err(BlockPruned { height }.into()),
+            res => res.and_then(|block| block.context("Object not available in the database.")),
+        }
+        .with_context(|| format!("Failed to get block from the database. height: {}", height))
     }
 }
 
@@ -202,6 +959,8 @@ impl TxStateView for DB {
 #[derive(Default)]
 pub struct Transaction {
     inner: DBTransaction,
+    /// The time spent encoding the objects inserted.
+    encode_time: Duration,
 }
 
 impl Transaction {
@@ -212,11 +971,14 @@ impl Transaction {
     pub fn with_capacity(cap: usize) -> Self {
         Self {
             inner: DBTransaction::with_capacity(cap),
+            encode_time: Duration::default(),
         }
     }
 
     pub fn insert_object<T: Serialize>(&mut self, col: u32, key: &DBKey, value: &T) -> Result<()> {
+        let begin = Instant::now();
         let bin = binary_encode(value)?;
+        self.encode_time += begin.elapsed();
         self.inner.put_vec(col, key, bin);
         Ok(())
     }
@@ -229,18 +991,86 @@ impl Transaction {
         self.insert_object(LOG_DB_COL, &u64_to_db_key(idx), value)
     }
 
+    /// Insert `block` along with the index from its hash to its height.
     pub fn insert_block<Block: BlockTrait + Serialize>(&mut self, block: &Block) -> Result<()> {
+        let height = block.block_height();
         self.insert_object(
-            BLOCK_DB_COL,
-            &block_height_to_db_key(block.block_height()),
-            block,
-        )
+            BLOCK_HASH_DB_COL,
+            &h256_to_db_key(block.to_digest()),
+            &height,
+        )?;
+        self.insert_object(BLOCK_DB_COL, &block_height_to_db_key(height), block)
+    }
+
+    /// Replace the body of `block` with its [`PrunedBlock`], keeping the index from its hash. The
+    /// tx locations are kept if indexed.
+    pub fn prune_block<Block: BlockTrait>(&mut self, block: &Block) -> Result<()> {
+        let key = block_height_to_db_key(block.block_height());
+        self.inner.delete(BLOCK_DB_COL, &key);
+        self.insert_object(PRUNED_BLOCK_DB_COL, &key, &PrunedBlock::from_block(block))
+    }
+
+    pub fn insert_pruned_height(&mut self, height: BlockHeight) -> Result<()> {
+        self.insert_meta_object("pruned-height", &height)
+    }
+
+    pub fn insert_latest_block_header(&mut self, header: &BlockHeader) -> Result<()> {
+        self.insert_meta_object("latest-block-header", header)
     }
 
     pub fn insert_tx<Tx: TxTrait + Serialize>(&mut self, tx_hash: H256, tx: &Tx) -> Result<()> {
         self.insert_object(TX_DB_COL, &h256_to_db_key(tx_hash), tx)
     }
 
+    pub fn insert_tx_location(
+        &mut self,
+        tx_hash: H256,
+        height: BlockHeight,
+        idx: usize,
+    ) -> Result<()> {
+        self.insert_object(TX_LOC_DB_COL, &h256_to_db_key(tx_hash), &(height, idx))
+    }
+
+    pub fn insert_tx_locations<Block: BlockTrait>(&mut self, block: &Block) -> Result<()> {
+        let height = block.block_height();
+        for (idx, &tx_hash) in block.tx_list().iter().enumerate() {
+            self.insert_tx_location(tx_hash, height, idx)?;
+        }
+        Ok(())
+    }
+
+    pub fn insert_address_txs<Block: BlockTrait, Tx: TxTrait>(
+        &mut self,
+        block: &Block,
+        txs: &[Tx],
+    ) -> Result<()> {
+        let height = block.block_height();
+        for (idx, (&tx_hash, tx)) in block.tx_list().iter().zip(txs.iter()).enumerate() {
+            self.insert_object(
+                ADDR_TX_DB_COL,
+                &addr_tx_to_db_key(tx.tx_caller(), height, idx),
+                &tx_hash,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Insert a state node already in its on-disk encoding.
+    pub fn insert_state_node_bin(&mut self, node_address: H256, bin: Vec<u8>) {
+        self.inner
+            .put_vec(STATE_DB_COL, &h256_to_db_key(node_address), bin);
+    }
+
+    /// Insert a pending tx proposal of the mempool already in its on-disk encoding.
+    pub fn insert_mempool_entry_bin(&mut self, tx_id: H256, bin: Vec<u8>) {
+        self.inner
+            .put_vec(MEMPOOL_DB_COL, &h256_to_db_key(tx_id), bin);
+    }
+
+    pub fn delete_mempool_entry(&mut self, tx_id: H256) {
+        self.delete_object(MEMPOOL_DB_COL, &h256_to_db_key(tx_id))
+    }
+
     pub fn update_state(&mut self, update: &TxStateUpdate) -> Result<()> {
         for (&addr, node) in update.acc_nodes.iter() {
             self.insert_object(STATE_DB_COL, &h256_to_db_key(addr), node)?;
@@ -263,3 +1093,499 @@ impl Transaction {
         self.delete_object(LOG_DB_COL, &u64_to_db_key(idx))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        consensus::raft::Block,
+        test_utils::{commit_test_block, test_block_tx},
+    };
+    use slimchain_common::{
+        basic::{Nonce, StateKey, H160},
+        digest::Digestible,
+        rw_set::TxWriteData,
+    };
+    use slimchain_tx_state::TxStateReadContext;
+
+    fn create_test_db(max_height: u64) -> DBPtr {
+        let db = DB::load_test();
+        let acc_addr = Address::from(H160::from_low_u64_be(1));
+        let mut prev_blk = Block::genesis_block();
+        for i in 1..=max_height {
+            let mut writes = TxWriteData::default();
+            writes.add_nonce(acc_addr, Nonce::from(i));
+            writes.add_value(
+                acc_addr,
+                StateKey::from(H256::from_low_u64_be(i)),
+                StateValue::from(i),
+            );
+            prev_blk = commit_test_block(&db, &prev_blk, &writes);
+        }
+        db
+    }
+
+    #[test]
+    fn test_snapshot_export_import() {
+        let db = create_test_db(25);
+        let dir =
+            std::env::temp_dir().join(format!("slimchain-db-snapshot-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let archive = dir.join("snapshot.bin");
+
+        let manifest = db.export_snapshot::<Block>(&archive, 20.into()).unwrap();
+        let expect_blk: Block = db.get_block(20.into()).unwrap();
+        assert_eq!(manifest.height, BlockHeight::from(20));
+        assert_eq!(&manifest.latest_block_header, expect_blk.block_header());
+        assert_eq!(manifest.block_count, 20);
+
+        let db2 = DB::load_test();
+        assert_eq!(db2.import_snapshot(&archive).unwrap(), manifest);
+        assert_eq!(
+            db2.get_latest_block_header().unwrap().as_ref(),
+            Some(expect_blk.block_header())
+        );
+        assert!(db2.import_snapshot(&archive).is_err());
+        for db in &[&db, &db2] {
+            assert_eq!(
+                Some(BlockHeight::from(20)),
+                db.get_block_height_by_hash(expect_blk.to_digest()).unwrap()
+            );
+        }
+        assert_eq!(None, db2.get_block_height_by_hash(H256::zero()).unwrap());
+
+        let acc_addr = Address::from(H160::from_low_u64_be(1));
+        let root = expect_blk.state_root();
+        let mut ctx1 = TxStateReadContext::new(db.clone(), root);
+        let mut ctx2 = TxStateReadContext::new(db2.clone(), root);
+        assert_eq!(
+            ctx1.get_nonce(acc_addr).unwrap(),
+            ctx2.get_nonce(acc_addr).unwrap()
+        );
+        for i in &[1, 5, 10, 20, 21] {
+            let key = StateKey::from(H256::from_low_u64_be(*i));
+            assert_eq!(
+                ctx1.get_value(acc_addr, key).unwrap(),
+                ctx2.get_value(acc_addr, key).unwrap()
+            );
+        }
+        assert!(BlockLoaderTrait::<Block>::get_block(&db2, 21.into()).is_err());
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_journal_crash_recovery() {
+        use journal::CrashPoint;
+
+        let dir = std::env::temp_dir().join(format!("slimchain-db-journal-{}", std::process::id()));
+        fs::remove_dir_all(&dir).ok();
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("storage.db");
+        let acc_addr = Address::from(H160::from_low_u64_be(1));
+
+        let commit = |db: &DBPtr, prev_blk: &Block, crash: Option<CrashPoint>| -> Block {
+            let mut writes = TxWriteData::default();
+            writes.add_nonce(acc_addr, Nonce::from(prev_blk.block_height().0 + 1));
+            let (blk, tx) = test_block_tx(db, prev_blk, &writes, &[]);
+            match crash {
+                Some(point) => {
+                    db.journal.as_ref().unwrap().inject_crash(point);
+                    assert!(db.write_sync(tx).is_err());
+                }
+                None => db.write_sync(tx).unwrap(),
+            }
+            blk
+        };
+        let check = |db: &DBPtr, blk: &Block| {
+            assert_eq!(
+                db.get_latest_block_header().unwrap().as_ref(),
+                Some(blk.block_header())
+            );
+            let stored: Block = db.get_block(blk.block_height()).unwrap();
+            assert_eq!(&stored, blk);
+            let mut ctx = TxStateReadContext::new(db.clone(), blk.state_root());
+            assert_eq!(
+                ctx.get_nonce(acc_addr).unwrap(),
+                Nonce::from(blk.block_height().0)
+            );
+            assert_eq!(fs::metadata(journal_path(&path)).unwrap().len(), 0);
+        };
+
+        let db = DB::open_or_create(&path, false, true, DEFAULT_READ_THREADS).unwrap();
+        let blk1 = commit(&db, &Block::genesis_block(), None);
+        let blk2 = commit(&db, &blk1, None);
+        check(&db, &blk2);
+
+        // The process dies after block 3 is journaled, before it is applied.
+        let blk3 = commit(&db, &blk2, Some(CrashPoint::BeforeApply));
+        assert_eq!(
+            db.get_latest_block_header().unwrap().unwrap().height,
+            2.into()
+        );
+        assert!(BlockLoaderTrait::<Block>::get_block(db.as_ref(), 3.into()).is_err());
+        drop(db);
+        let db = DB::open_or_create(&path, false, true, DEFAULT_READ_THREADS).unwrap();
+        check(&db, &blk3);
+
+        // The process dies after block 4 is applied, before the journal is truncated.
+        let blk4 = commit(&db, &blk3, Some(CrashPoint::BeforeTruncate));
+        drop(db);
+        let db = DB::open_or_create(&path, false, true, DEFAULT_READ_THREADS).unwrap();
+        check(&db, &blk4);
+        let blk5 = commit(&db, &blk4, None);
+        check(&db, &blk5);
+
+        // The journal left by a crash is still applied once it is disabled.
+        let blk6 = commit(&db, &blk5, Some(CrashPoint::BeforeApply));
+        drop(db);
+        let db = DB::open_or_create(&path, false, false, DEFAULT_READ_THREADS).unwrap();
+        assert!(db.journal.is_none());
+        check(&db, &blk6);
+        let blk7 = commit(&db, &blk6, None);
+        check(&db, &blk7);
+        drop(db);
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_open_read_only() {
+        let dir =
+            std::env::temp_dir().join(format!("slimchain-db-read-only-{}", std::process::id()));
+        fs::remove_dir_all(&dir).ok();
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("storage.db");
+        let acc_addr = Address::from(H160::from_low_u64_be(1));
+
+        let commit = |db: &DBPtr, prev_blk: &Block| -> Block {
+            let mut writes = TxWriteData::default();
+            writes.add_nonce(acc_addr, Nonce::from(prev_blk.block_height().0 + 1));
+            commit_test_block(db, prev_blk, &writes)
+        };
+
+        let db = DB::open_or_create(&path, false, true, DEFAULT_READ_THREADS).unwrap();
+        let blk1 = commit(&db, &Block::genesis_block());
+        let reader = DB::open_read_only(&path).unwrap();
+        assert!(reader.is_read_only());
+        assert!(!db.is_read_only());
+        assert_eq!(
+            reader.get_latest_block_header().unwrap().as_ref(),
+            Some(blk1.block_header())
+        );
+
+        let blk2 = commit(&db, &blk1);
+        let blk3 = commit(&db, &blk2);
+        reader.catch_up().unwrap();
+        assert_eq!(
+            reader.get_latest_block_header().unwrap().as_ref(),
+            Some(blk3.block_header())
+        );
+        let stored: Block = reader.get_block(blk3.block_height()).unwrap();
+        assert_eq!(stored, blk3);
+        let mut ctx = TxStateReadContext::new(reader.clone(), blk3.state_root());
+        assert_eq!(ctx.get_nonce(acc_addr).unwrap(), Nonce::from(3));
+        let opts = integrity::IntegrityOptions {
+            check_state: true,
+            ..Default::default()
+        };
+        let report = reader
+            .check_integrity::<Block, slimchain_common::tx::RawTx>(
+                &opts,
+                crate::consensus::raft::verify_consensus,
+            )
+            .unwrap();
+        assert!(report.is_ok(), "{:?}", report.violations);
+
+        let mut tx = Transaction::new();
+        tx.insert_latest_block_header(blk1.block_header()).unwrap();
+        let err = reader.write_sync(tx).unwrap_err();
+        assert!(err.downcast_ref::<ReadOnly>().is_some());
+        assert_eq!(
+            db.get_latest_block_header().unwrap().as_ref(),
+            Some(blk3.block_header())
+        );
+
+        drop(reader);
+        drop(db);
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_storage_stats() {
+        let db = create_test_db(10);
+        let stats = db.storage_stats().unwrap();
+        assert_eq!(stats.block_count, 10);
+        assert_eq!(stats.tx_count, 0);
+        assert!(stats.state_node_count > 0);
+        assert_eq!(stats.columns.len(), TOTAL_COLS as usize);
+        assert_eq!(
+            stats.live_data_size,
+            (0..TOTAL_COLS)
+                .map(|col| db.get_table_size(col) as u64)
+                .sum::<u64>()
+        );
+        assert_eq!(stats.total_file_size, 0);
+
+        let range = KeyRange {
+            col: BLOCK_DB_COL,
+            start: Some(block_height_to_db_key(3.into()).to_vec()),
+            end: None,
+        };
+        assert!(range.contains(&block_height_to_db_key(3.into())));
+        assert!(!range.contains(&block_height_to_db_key(2.into())));
+        db.record_compaction_stats(Some(range)).unwrap();
+        db.record_compaction_stats(None).unwrap();
+    }
+
+    #[test]
+    fn test_txs_by_address() {
+        let db = DB::load_test();
+        let addr1 = Address::from(H160::from_low_u64_be(1));
+        let addr2 = Address::from(H160::from_low_u64_be(2));
+        let mut tx = Transaction::new();
+        for height in (1..=10u64).rev() {
+            for idx in 0..3 {
+                let tx_hash = H256::from_low_u64_be(height * 10 + idx as u64);
+                let addr = if idx == 1 { addr2 } else { addr1 };
+                tx.insert_object(
+                    ADDR_TX_DB_COL,
+                    &addr_tx_to_db_key(addr, height.into(), idx),
+                    &tx_hash,
+                )
+                .unwrap();
+            }
+        }
+        db.write_sync(tx).unwrap();
+
+        let all = db.get_txs_by_address(addr1, 0.into(), usize::MAX).unwrap();
+        let expect: Vec<_> = (1..=10u64)
+            .flat_map(|h| {
+                vec![
+                    (h.into(), H256::from_low_u64_be(h * 10)),
+                    (h.into(), H256::from_low_u64_be(h * 10 + 2)),
+                ]
+            })
+            .collect();
+        assert_eq!(all, expect);
+
+        let page = db.get_txs_by_address(addr1, 4.into(), 3).unwrap();
+        assert_eq!(&page[..], &expect[6..9]);
+        let page = db.get_txs_by_address(addr2, 9.into(), 10).unwrap();
+        assert_eq!(
+            page,
+            vec![
+                (9.into(), H256::from_low_u64_be(91)),
+                (10.into(), H256::from_low_u64_be(101))
+            ]
+        );
+        assert!(db
+            .get_txs_by_address(addr1, 11.into(), 10)
+            .unwrap()
+            .is_empty());
+        assert!(db
+            .get_txs_by_address(addr1, 0.into(), 0)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_iter_blocks() {
+        let db = create_test_db(100);
+
+        let blocks: Vec<Block> = db
+            .iter_blocks(BlockHeight::from(0)..BlockHeight::from(101))
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(blocks.len(), 101);
+        for (i, blk) in blocks.iter().enumerate() {
+            assert_eq!(blk.block_height(), BlockHeight::from(i as u64));
+        }
+
+        let blocks: Vec<Block> = db
+            .iter_blocks(BlockHeight::from(10)..BlockHeight::from(10))
+            .try_collect()
+            .await
+            .unwrap();
+        assert!(blocks.is_empty());
+
+        let mut tx = Transaction::new();
+        tx.delete_object(BLOCK_DB_COL, &block_height_to_db_key(70.into()));
+        db.write_sync(tx).unwrap();
+
+        let res: Vec<Result<Block>> = db
+            .iter_blocks(BlockHeight::from(60)..BlockHeight::from(80))
+            .collect()
+            .await;
+        assert_eq!(res.len(), 20);
+        for (i, item) in res.iter().enumerate() {
+            if i == 10 {
+                let err = item.as_ref().unwrap_err();
+                assert!(format!("{}", err).contains("height 70"));
+            } else {
+                assert!(item.is_ok());
+            }
+        }
+    }
+
+    /// An in-memory backend whose reads take `delay` and writes take `write_delay` each.
+    struct SlowBackend {
+        inner: kvdb_memorydb::InMemory,
+        delay: std::time::Duration,
+        write_delay: std::time::Duration,
+    }
+
+    impl parity_util_mem::MallocSizeOf for SlowBackend {
+        fn size_of(&self, _ops: &mut parity_util_mem::MallocSizeOfOps) -> usize {
+            0
+        }
+    }
+
+    impl KeyValueDB for SlowBackend {
+        fn get(&self, col: u32, key: &[u8]) -> std::io::Result<Option<kvdb::DBValue>> {
+            std::thread::sleep(self.delay);
+            self.inner.get(col, key)
+        }
+
+        fn get_by_prefix(&self, col: u32, prefix: &[u8]) -> Option<Box<[u8]>> {
+            self.inner.get_by_prefix(col, prefix)
+        }
+
+        fn write(&self, transaction: DBTransaction) -> std::io::Result<()> {
+            std::thread::sleep(self.write_delay);
+            self.inner.write(transaction)
+        }
+
+        fn iter<'a>(&'a self, col: u32) -> Box<dyn Iterator<Item = kvdb::KeyValuePair> + 'a> {
+            self.inner.iter(col)
+        }
+
+        fn iter_with_prefix<'a>(
+            &'a self,
+            col: u32,
+            prefix: &'a [u8],
+        ) -> Box<dyn Iterator<Item = kvdb::KeyValuePair> + 'a> {
+            self.inner.iter_with_prefix(col, prefix)
+        }
+
+        fn restore(&self, new_db: &str) -> std::io::Result<()> {
+            self.inner.restore(new_db)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_read_async() {
+        const READS: u64 = 4;
+        let delay = std::time::Duration::from_millis(200);
+        for &read_threads in &[1, READS as usize] {
+            let db = DB::load_test_with_backend(
+                Box::new(SlowBackend {
+                    inner: kvdb_memorydb::create(TOTAL_COLS),
+                    delay,
+                    write_delay: Duration::default(),
+                }),
+                read_threads,
+            );
+            let mut tx = Transaction::new();
+            for i in 1..=READS {
+                let mut blk = Block::genesis_block();
+                blk.block_header_mut().height = i.into();
+                tx.insert_block(&blk).unwrap();
+            }
+            db.write_sync(tx).unwrap();
+
+            // The unrelated reads only wait for each other if there is a single read thread.
+            let begin = std::time::Instant::now();
+            let blocks: Vec<Block> =
+                future::try_join_all((1..=READS).map(|i| db.get_block_async(BlockHeight::from(i))))
+                    .await
+                    .unwrap();
+            let elapsed = begin.elapsed();
+            for (i, blk) in (1..=READS).zip(blocks.iter()) {
+                assert_eq!(blk.block_height(), BlockHeight::from(i));
+            }
+            if read_threads == 1 {
+                assert!(elapsed >= delay * READS as u32);
+            } else {
+                assert!(elapsed < delay * 2);
+            }
+        }
+
+        let db = DB::load_test();
+        let nodes = db
+            .load_nodes_async(vec![H256::repeat_byte(1)])
+            .await
+            .unwrap();
+        assert_eq!(nodes, vec![None]);
+        assert!(db
+            .get_tx_async::<slimchain_common::tx::RawTx>(H256::repeat_byte(1))
+            .await
+            .is_err());
+    }
+
+    #[test]
+    fn test_write_queue_wait() {
+        let write_delay = Duration::from_millis(200);
+        let db = DB::load_test_with_backend(
+            Box::new(SlowBackend {
+                inner: kvdb_memorydb::create(TOTAL_COLS),
+                delay: Duration::default(),
+                write_delay,
+            }),
+            1,
+        );
+        let block_tx = |i: u64| {
+            let mut tx = Transaction::new();
+            let mut blk = Block::genesis_block();
+            blk.block_header_mut().height = i.into();
+            tx.insert_block(&blk).unwrap();
+            tx
+        };
+        let check_backend = |stats: &WriteStats| {
+            assert!(stats.backend_time >= write_delay);
+            assert!(stats.backend_time < write_delay * 3 / 2);
+            assert_eq!(stats.entries[BLOCK_DB_COL as usize], 1);
+            assert_eq!(stats.entries[BLOCK_HASH_DB_COL as usize], 1);
+            assert_eq!(stats.entries.iter().sum::<usize>(), 2);
+            assert!(stats.payload_size > 0);
+        };
+
+        // The writes are applied concurrently, so neither waits for the other.
+        let begin = Instant::now();
+        let writers: Vec<_> = (1..=2)
+            .map(|i| {
+                let db = db.clone();
+                std::thread::spawn(move || db.write_tx(block_tx(i), Instant::now()))
+            })
+            .collect();
+        for writer in writers {
+            let (res, stats) = writer.join().unwrap();
+            res.unwrap();
+            assert!(stats.queue_wait < write_delay / 2);
+            check_backend(&stats);
+        }
+        assert!(begin.elapsed() < write_delay * 3 / 2);
+
+        // The second write waits for the only blocking thread from its enqueue, and the wait is
+        // not taken as the time of the backend.
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .max_blocking_threads(1)
+            .build()
+            .unwrap();
+        let (res1, res2) = rt.block_on(future::join(
+            db.write_async_with_stats(block_tx(3)),
+            db.write_async_with_stats(block_tx(4)),
+        ));
+        let (res1, stats1) = res1.unwrap();
+        let (res2, stats2) = res2.unwrap();
+        res1.unwrap();
+        res2.unwrap();
+        assert_eq!(db.pending_writes(), 0);
+        let mut stats = [stats1, stats2];
+        stats.sort_by_key(|s| s.queue_wait);
+        assert!(stats[0].queue_wait < write_delay / 2);
+        assert!(stats[1].queue_wait >= write_delay * 3 / 4);
+        stats.iter().for_each(check_backend);
+    }
+}
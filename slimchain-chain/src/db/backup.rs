@@ -0,0 +1,284 @@
+//! Back up the database of a running node, and restore the backups.
+//!
+//! A backup is a RocksDB database in `<dest>/db` along with its [`BackupManifest`] in
+//! `<dest>/manifest.toml`. The backup copies the entries through the iterators of the backend,
+//! which are created together while the writes are held back, so that it is a consistent view
+//! of the database at a block even though the writes resume while it is being copied.
+//!
+//! Every backup reads all the entries of the database, along with the ones of the previous
+//! backup in the destination if any, so it takes time linear in the size of the database. Only
+//! the entries changed since the previous backup are written, though. kvdb-rocksdb does not
+//! expose the RocksDB checkpoints, which would hard link the SST files instead.
+
+use super::{DB, TOTAL_COLS};
+use chrono::{DateTime, Utc};
+use kvdb::{DBTransaction, KeyValueDB};
+use serde::{Deserialize, Serialize};
+use slimchain_common::{
+    basic::BlockHeight,
+    error::{ensure, Context as _, Result},
+};
+use slimchain_utils::{record_event, toml};
+use std::{cmp::Ordering, fs, path::Path, time::Instant};
+
+pub const BACKUP_MANIFEST_FILE: &str = "manifest.toml";
+pub const BACKUP_DB_DIR: &str = "db";
+
+/// The entries written to the backup in one transaction.
+const BATCH_ENTRIES: usize = 1024;
+
+type KeyValue = (Box<[u8]>, Box<[u8]>);
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct BackupManifest {
+    /// The height of the latest block included in the backup.
+    pub height: BlockHeight,
+    pub created_at: DateTime<Utc>,
+    /// The entries written to the backup.
+    pub copied: usize,
+    /// The entries kept from the previous backup in the destination, which are still read and
+    /// compared.
+    pub unchanged: usize,
+    /// The entries of the previous backup removed from the database since.
+    pub deleted: usize,
+}
+
+impl BackupManifest {
+    pub fn load(dir: &Path) -> Result<Self> {
+        let path = dir.join(BACKUP_MANIFEST_FILE);
+        let content = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read the backup manifest {}.", path.display()))?;
+        toml::from_str(&content)
+            .with_context(|| format!("Invalid backup manifest {}.", path.display()))
+    }
+
+    fn save(&self, dir: &Path) -> Result<()> {
+        let path = dir.join(BACKUP_MANIFEST_FILE);
+        let tmp_path = path.with_extension("toml.tmp");
+        fs::write(&tmp_path, toml::to_string(self)?)?;
+        fs::rename(&tmp_path, &path)?;
+        Ok(())
+    }
+}
+
+impl DB {
+    /// Back up the database into `dest` while the node keeps running. It reads the whole
+    /// database, and the previous backup in `dest` if any, of which only the entries changed
+    /// since are written. Return the manifest of the backup.
+    pub fn create_backup(&self, dest: &Path) -> Result<BackupManifest> {
+        let begin = Instant::now();
+        fs::create_dir_all(dest)?;
+        // A backup cut in the middle has no manifest, so that it is never restored.
+        let manifest_path = dest.join(BACKUP_MANIFEST_FILE);
+        if manifest_path.exists() {
+            fs::remove_file(&manifest_path)?;
+        }
+
+        // The iterators pin the view of the backend once created.
+        let (height, cols) = {
+            let _write_guard = self.write_gate.write().unwrap_or_else(|e| e.into_inner());
+            let height = self
+                .get_latest_block_header()?
+                .map_or_else(BlockHeight::default, |header| header.height);
+            let cols: Vec<_> = (0..TOTAL_COLS).map(|col| self.db.iter(col)).collect();
+            (height, cols)
+        };
+
+        let db_path = dest.join(BACKUP_DB_DIR);
+        let cfg = kvdb_rocksdb::DatabaseConfig::with_columns(TOTAL_COLS);
+        let backup = kvdb_rocksdb::Database::open(&cfg, &db_path.to_string_lossy())?;
+        let mut manifest = BackupManifest {
+            height,
+            created_at: Utc::now(),
+            copied: 0,
+            unchanged: 0,
+            deleted: 0,
+        };
+        for (col, entries) in (0..TOTAL_COLS).zip(cols) {
+            sync_col(&backup, col, entries, &mut manifest)?;
+        }
+        drop(backup);
+        manifest.save(dest)?;
+
+        info!(
+            height = manifest.height.0,
+            copied = manifest.copied,
+            unchanged = manifest.unchanged,
+            deleted = manifest.deleted,
+            "Back up the database to {}.",
+            dest.display()
+        );
+        record_event!("db_backup", "height": manifest.height.0, "copied": manifest.copied, "unchanged": manifest.unchanged, "deleted": manifest.deleted, "time_ms": begin.elapsed().as_millis() as u64);
+        Ok(manifest)
+    }
+
+    /// Restore the backup in `src` to the database at `dest`, which must not exist. The database
+    /// is then opened by [`DB::open_or_create`].
+    pub fn restore_backup(src: &Path, dest: &Path) -> Result<BackupManifest> {
+        let manifest = BackupManifest::load(src)?;
+        ensure!(
+            !dest.exists(),
+            "Database {} already exists.",
+            dest.display()
+        );
+        fs::create_dir_all(dest)?;
+        for entry in fs::read_dir(src.join(BACKUP_DB_DIR))? {
+            let entry = entry?;
+            if entry.file_type()?.is_file() {
+                fs::copy(entry.path(), dest.join(entry.file_name()))?;
+            }
+        }
+        info!(
+            height = manifest.height.0,
+            "Restore the backup in {} to {}.",
+            src.display(),
+            dest.display()
+        );
+        Ok(manifest)
+    }
+}
+
+/// Make the column `col` of `backup` equal to `entries`, by walking both of them in the key
+/// order.
+fn sync_col(
+    backup: &kvdb_rocksdb::Database,
+    col: u32,
+    entries: impl Iterator<Item = KeyValue>,
+    manifest: &mut BackupManifest,
+) -> Result<()> {
+    let mut old_entries = backup.iter(col).peekable();
+    let mut tx = DBTransaction::new();
+    for (key, value) in entries {
+        loop {
+            match old_entries.peek().map(|(old_key, _)| old_key.cmp(&key)) {
+                Some(Ordering::Less) => {
+                    if let Some((old_key, _)) = old_entries.next() {
+                        tx.delete(col, &old_key);
+                        manifest.deleted += 1;
+                    }
+                }
+                Some(Ordering::Equal) => {
+                    match old_entries.next() {
+                        Some((_, old_value)) if old_value == value => manifest.unchanged += 1,
+                        _ => {
+                            tx.put(col, &key, &value);
+                            manifest.copied += 1;
+                        }
+                    }
+                    break;
+                }
+                _ => {
+                    tx.put(col, &key, &value);
+                    manifest.copied += 1;
+                    break;
+                }
+            }
+        }
+        if tx.ops.len() >= BATCH_ENTRIES {
+            backup.write(std::mem::take(&mut tx))?;
+        }
+    }
+    for (old_key, _) in old_entries {
+        tx.delete(col, &old_key);
+        manifest.deleted += 1;
+    }
+    backup.write(tx)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        block::BlockTrait,
+        consensus::raft::{verify_consensus, Block},
+        db::{integrity::IntegrityOptions, read_pool::DEFAULT_READ_THREADS, DBPtr},
+        test_utils::commit_test_block,
+    };
+    use slimchain_common::{
+        basic::{Address, Nonce, H160},
+        rw_set::TxWriteData,
+        tx::RawTx,
+    };
+    use std::sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    };
+
+    fn commit(db: &DBPtr, prev_blk: &Block) -> Block {
+        let height = prev_blk.block_height().next_height();
+        let acc_addr = Address::from(H160::from_low_u64_be(height.0));
+        let mut writes = TxWriteData::default();
+        writes.add_nonce(acc_addr, Nonce::from(height.0));
+        commit_test_block(db, prev_blk, &writes)
+    }
+
+    fn check_restored(backup_dir: &Path, dest: &Path) -> BackupManifest {
+        let manifest = DB::restore_backup(backup_dir, dest).unwrap();
+        let db = DB::open_or_create(dest, false, true, DEFAULT_READ_THREADS).unwrap();
+        let opts = IntegrityOptions {
+            check_state: true,
+            ..Default::default()
+        };
+        let report = db
+            .check_integrity::<Block, RawTx>(&opts, verify_consensus)
+            .unwrap();
+        assert!(report.is_ok(), "{:?}", report.violations);
+        assert_eq!(report.latest_height, manifest.height);
+        manifest
+    }
+
+    #[test]
+    fn test_backup() {
+        let dir = std::env::temp_dir().join(format!("slimchain-db-backup-{}", std::process::id()));
+        fs::remove_dir_all(&dir).ok();
+        fs::create_dir_all(&dir).unwrap();
+        let backup_dir = dir.join("backup");
+
+        let db =
+            DB::open_or_create(&dir.join("storage.db"), false, true, DEFAULT_READ_THREADS).unwrap();
+        let mut blk = Block::genesis_block();
+        for _ in 0..10 {
+            blk = commit(&db, &blk);
+        }
+
+        // Keep committing while backing up.
+        let stop = Arc::new(AtomicBool::new(false));
+        let committer = {
+            let db = db.clone();
+            let stop = stop.clone();
+            std::thread::spawn(move || {
+                while !stop.load(Ordering::SeqCst) {
+                    blk = commit(&db, &blk);
+                }
+                blk
+            })
+        };
+        let manifest1 = db.create_backup(&backup_dir).unwrap();
+        stop.store(true, Ordering::SeqCst);
+        let blk = committer.join().unwrap();
+        assert!(manifest1.height >= 10.into());
+        assert_eq!(manifest1.unchanged, 0);
+        assert_eq!(
+            check_restored(&backup_dir, &dir.join("restore1.db")),
+            manifest1
+        );
+
+        // Backing up again only writes the new blocks and states, and the latest header.
+        let blk = commit(&db, &blk);
+        let manifest2 = db.create_backup(&backup_dir).unwrap();
+        assert_eq!(manifest2.height, blk.block_height());
+        assert_eq!(manifest2.unchanged, manifest1.copied - 1);
+        assert_eq!(manifest2.deleted, 0);
+        assert_eq!(
+            check_restored(&backup_dir, &dir.join("restore2.db")),
+            manifest2
+        );
+
+        // A database is never restored over.
+        assert!(DB::restore_backup(&backup_dir, &dir.join("restore2.db")).is_err());
+
+        drop(db);
+        fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,662 @@
+//! Check the integrity of the database, e.g., of a storage node restarted after an unclean
+//! shutdown, before it rejoins the network.
+//!
+//! The read errors of the backend fail the check, while the data found missing or inconsistent
+//! are collected in the [`IntegrityReport`], so that the operator can repair them or resync.
+
+use super::{block_height_to_db_key, h256_to_db_key, BLOCK_DB_COL, DB, TX_DB_COL};
+use crate::block::BlockTrait;
+use serde::{Deserialize, Serialize};
+use slimchain_common::{
+    basic::{AccountData, Address, BlockHeight, ShardId, StateValue, H160, H256},
+    collections::HashSet,
+    digest::Digestible,
+    error::{Error, Result},
+    tx::TxTrait,
+};
+use slimchain_merkle_trie::{traits::Value, u4::U4};
+use slimchain_tx_state::TrieNode;
+use slimchain_utils::serde::binary_decode;
+use std::fmt;
+
+const ADDRESS_NIBBLES: usize = 40;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct IntegrityOptions {
+    /// Whether to check the tx bodies of the blocks, which are only stored by the storage
+    /// nodes. Default true.
+    pub check_txs: bool,
+    /// Whether to walk the state trie at the latest block and check every node reachable. It
+    /// reads all the state nodes. Default false.
+    pub check_state: bool,
+    /// The shard of the storage node. The state tries of the accounts out of the shard are not
+    /// stored, so they are skipped. Default the full shard.
+    pub shard_id: ShardId,
+}
+
+impl Default for IntegrityOptions {
+    fn default() -> Self {
+        Self {
+            check_txs: true,
+            check_state: false,
+            shard_id: ShardId::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub enum IntegrityViolation {
+    /// The block below the latest block header is missing.
+    MissingBlock {
+        height: BlockHeight,
+    },
+    /// The block fails to decode.
+    CorruptedBlock {
+        height: BlockHeight,
+        error: String,
+    },
+    /// The block does not follow the previous one, by the hash chaining or the consensus.
+    InvalidChain {
+        height: BlockHeight,
+        error: String,
+    },
+    /// The hash of the block is not indexed to its height.
+    InvalidBlockHashIndex {
+        height: BlockHeight,
+        blk_hash: H256,
+    },
+    /// The latest block header is different from the header of the block at its height.
+    InvalidLatestBlockHeader {
+        height: BlockHeight,
+    },
+    MissingTx {
+        height: BlockHeight,
+        tx_hash: H256,
+    },
+    /// The tx fails to decode, or its digest is different from its hash.
+    CorruptedTx {
+        height: BlockHeight,
+        tx_hash: H256,
+        error: String,
+    },
+    /// `parent` is the node referring to the missing one, or `None` for the state root.
+    MissingStateNode {
+        node_address: H256,
+        parent: Option<H256>,
+    },
+    /// The state node fails to decode, or its digest is different from its address.
+    CorruptedStateNode {
+        node_address: H256,
+        error: String,
+    },
+}
+
+impl fmt::Display for IntegrityViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingBlock { height } => write!(f, "Missing block {}.", height),
+            Self::CorruptedBlock { height, error } => {
+                write!(f, "Corrupted block {}. Error: {}", height, error)
+            }
+            Self::InvalidChain { height, error } => {
+                write!(
+                    f,
+                    "Block {} does not follow the previous one. Error: {}",
+                    height, error
+                )
+            }
+            Self::InvalidBlockHashIndex { height, blk_hash } => write!(
+                f,
+                "Hash {} of block {} is not indexed to its height.",
+                blk_hash, height
+            ),
+            Self::InvalidLatestBlockHeader { height } => write!(
+                f,
+                "The latest block header is different from block {}.",
+                height
+            ),
+            Self::MissingTx { height, tx_hash } => {
+                write!(f, "Missing tx {} of block {}.", tx_hash, height)
+            }
+            Self::CorruptedTx {
+                height,
+                tx_hash,
+                error,
+            } => write!(
+                f,
+                "Corrupted tx {} of block {}. Error: {}",
+                tx_hash, height, error
+            ),
+            Self::MissingStateNode {
+                node_address,
+                parent: Some(parent),
+            } => write!(
+                f,
+                "Missing state node {} referred by {}.",
+                node_address, parent
+            ),
+            Self::MissingStateNode {
+                node_address,
+                parent: None,
+            } => write!(f, "Missing state root {}.", node_address),
+            Self::CorruptedStateNode {
+                node_address,
+                error,
+            } => write!(f, "Corrupted state node {}. Error: {}", node_address, error),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct IntegrityReport {
+    /// The height of the latest block header, up to which the blocks are checked.
+    pub latest_height: BlockHeight,
+    pub blocks_checked: u64,
+    pub txs_checked: u64,
+    pub state_nodes_checked: u64,
+    pub violations: Vec<IntegrityViolation>,
+}
+
+impl IntegrityReport {
+    pub fn is_ok(&self) -> bool {
+        self.violations.is_empty()
+    }
+}
+
+/// The address of the account leaf at the end of `path`, or `None` if it is malformed.
+fn path_to_address(path: &[U4]) -> Option<Address> {
+    if path.len() != ADDRESS_NIBBLES {
+        return None;
+    }
+    let bytes: Vec<u8> = path
+        .chunks(2)
+        .map(|pair| (u8::from(pair[0]) << 4) | u8::from(pair[1]))
+        .collect();
+    Some(Address::from(H160::from_slice(&bytes)))
+}
+
+impl DB {
+    /// Walk the blocks from the genesis to the latest block header, verifying their hash
+    /// chaining, their consensus by `verify_consensus_fn` and their tx bodies, and optionally
+    /// the state at the latest block. See [`IntegrityOptions`].
+    pub fn check_integrity<Block, Tx>(
+        &self,
+        opts: &IntegrityOptions,
+        verify_consensus_fn: impl Fn(&Block, &Block) -> Result<()>,
+    ) -> Result<IntegrityReport>
+    where
+        Block: BlockTrait + for<'de> Deserialize<'de>,
+        Tx: TxTrait + for<'de> Deserialize<'de>,
+    {
+        let mut report = IntegrityReport::default();
+        let latest_header = match self.get_latest_block_header()? {
+            Some(header) => header,
+            None => return Ok(report),
+        };
+        report.latest_height = latest_header.height;
+
+        // The block following a missing or corrupted one is not checked against it.
+        let mut prev_blk = Some(Block::genesis_block());
+        let mut height = BlockHeight::from(1);
+        while height <= latest_header.height {
+            let blk = self.check_block::<Block, Tx>(
+                height,
+                prev_blk.as_ref(),
+                opts,
+                &verify_consensus_fn,
+                &mut report,
+            )?;
+            if let Some(blk) = blk.as_ref() {
+                if height == latest_header.height && blk.block_header() != &latest_header {
+                    report
+                        .violations
+                        .push(IntegrityViolation::InvalidLatestBlockHeader { height });
+                }
+            }
+            prev_blk = blk;
+            height = height.next_height();
+        }
+
+        if opts.check_state {
+            self.check_state(latest_header.state_root, opts.shard_id, &mut report)?;
+        }
+        Ok(report)
+    }
+
+    fn check_block<Block, Tx>(
+        &self,
+        height: BlockHeight,
+        prev_blk: Option<&Block>,
+        opts: &IntegrityOptions,
+        verify_consensus_fn: &impl Fn(&Block, &Block) -> Result<()>,
+        report: &mut IntegrityReport,
+    ) -> Result<Option<Block>>
+    where
+        Block: BlockTrait + for<'de> Deserialize<'de>,
+        Tx: TxTrait + for<'de> Deserialize<'de>,
+    {
+        let bin = match self
+            .db
+            .get(BLOCK_DB_COL, &block_height_to_db_key(height))
+            .map_err(Error::msg)?
+        {
+            Some(bin) => bin,
+            None => {
+                report
+                    .violations
+                    .push(IntegrityViolation::MissingBlock { height });
+                return Ok(None);
+            }
+        };
+        let blk: Block = match binary_decode(&bin[..]) {
+            Ok(blk) => blk,
+            Err(e) => {
+                report.violations.push(IntegrityViolation::CorruptedBlock {
+                    height,
+                    error: e.to_string(),
+                });
+                return Ok(None);
+            }
+        };
+        report.blocks_checked += 1;
+
+        if let Some(prev_blk) = prev_blk {
+            let res = blk
+                .verify_block_header(prev_blk)
+                .and_then(|_| verify_consensus_fn(&blk, prev_blk));
+            if let Err(e) = res {
+                report.violations.push(IntegrityViolation::InvalidChain {
+                    height,
+                    error: e.to_string(),
+                });
+            }
+        }
+
+        let blk_hash = blk.to_digest();
+        if !matches!(self.get_block_height_by_hash(blk_hash), Ok(Some(h)) if h == height) {
+            report
+                .violations
+                .push(IntegrityViolation::InvalidBlockHashIndex { height, blk_hash });
+        }
+
+        if opts.check_txs {
+            for &tx_hash in blk.tx_list().iter() {
+                report.txs_checked += 1;
+                let bin = match self
+                    .db
+                    .get(TX_DB_COL, &h256_to_db_key(tx_hash))
+                    .map_err(Error::msg)?
+                {
+                    Some(bin) => bin,
+                    None => {
+                        report
+                            .violations
+                            .push(IntegrityViolation::MissingTx { height, tx_hash });
+                        continue;
+                    }
+                };
+                let error = match binary_decode::<Tx>(&bin[..]) {
+                    Ok(tx) if tx.to_digest() == tx_hash => continue,
+                    Ok(tx) => format!("Invalid digest {}.", tx.to_digest()),
+                    Err(e) => e.to_string(),
+                };
+                report.violations.push(IntegrityViolation::CorruptedTx {
+                    height,
+                    tx_hash,
+                    error,
+                });
+            }
+        }
+
+        Ok(Some(blk))
+    }
+
+    /// Walk the account trie at `root`, and the state tries of the accounts in `shard_id`.
+    fn check_state(
+        &self,
+        root: H256,
+        shard_id: ShardId,
+        report: &mut IntegrityReport,
+    ) -> Result<()> {
+        if root.is_zero() {
+            return Ok(());
+        }
+        let mut visited = HashSet::new();
+
+        // (node, its parent, the nibbles of the path to it)
+        let mut acc_nodes = vec![(root, None, Vec::new())];
+        // (state root, the account leaf referring to it)
+        let mut state_roots = Vec::new();
+        while let Some((node_address, parent, path)) = acc_nodes.pop() {
+            let node = match self.check_state_node::<AccountData>(
+                node_address,
+                parent,
+                &mut visited,
+                report,
+            )? {
+                Some(node) => node,
+                None => continue,
+            };
+            match node {
+                TrieNode::Extension(n) => {
+                    let mut path = path;
+                    path.extend(n.nibbles.iter());
+                    acc_nodes.push((n.child, Some(node_address), path));
+                }
+                TrieNode::Branch(n) => {
+                    for (i, child) in n.children.iter().enumerate() {
+                        if let Some(child) = *child {
+                            let mut path = path.clone();
+                            path.push(U4::from(i));
+                            acc_nodes.push((child, Some(node_address), path));
+                        }
+                    }
+                }
+                TrieNode::Leaf(n) => {
+                    let mut path = path;
+                    path.extend(n.nibbles.iter());
+                    let in_shard =
+                        path_to_address(&path).map_or(true, |addr| shard_id.contains(addr));
+                    if in_shard && !n.value.acc_state_root.is_zero() {
+                        state_roots.push((n.value.acc_state_root, node_address));
+                    }
+                }
+            }
+        }
+
+        for (state_root, acc_leaf) in state_roots {
+            let mut nodes = vec![(state_root, acc_leaf)];
+            while let Some((node_address, parent)) = nodes.pop() {
+                let node = match self.check_state_node::<StateValue>(
+                    node_address,
+                    Some(parent),
+                    &mut visited,
+                    report,
+                )? {
+                    Some(node) => node,
+                    None => continue,
+                };
+                match node {
+                    TrieNode::Extension(n) => nodes.push((n.child, node_address)),
+                    TrieNode::Branch(n) => {
+                        nodes.extend(n.children.iter().flatten().map(|&c| (c, node_address)))
+                    }
+                    TrieNode::Leaf(_) => {}
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Load and verify the state node, or `None` if it is checked already or invalid.
+    fn check_state_node<V: Value>(
+        &self,
+        node_address: H256,
+        parent: Option<H256>,
+        visited: &mut HashSet<H256>,
+        report: &mut IntegrityReport,
+    ) -> Result<Option<TrieNode<V>>>
+    where
+        TrieNode<V>: for<'de> Deserialize<'de>,
+    {
+        if !visited.insert(node_address) {
+            return Ok(None);
+        }
+        report.state_nodes_checked += 1;
+        let bin = match self.get_state_node_bin(node_address)? {
+            Some(bin) => bin,
+            None => {
+                report
+                    .violations
+                    .push(IntegrityViolation::MissingStateNode {
+                        node_address,
+                        parent,
+                    });
+                return Ok(None);
+            }
+        };
+        let error = match binary_decode::<TrieNode<V>>(&bin[..]) {
+            Ok(node) if node.to_digest() == node_address => return Ok(Some(node)),
+            Ok(node) => format!("Invalid digest {}.", node.to_digest()),
+            Err(e) => e.to_string(),
+        };
+        report
+            .violations
+            .push(IntegrityViolation::CorruptedStateNode {
+                node_address,
+                error,
+            });
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        consensus::raft::{verify_consensus, Block},
+        db::{DBPtr, STATE_DB_COL},
+        test_utils::test_block_tx,
+    };
+    use kvdb::DBTransaction;
+    use slimchain_common::{
+        basic::{Nonce, StateKey},
+        rw_set::TxWriteData,
+        tx::RawTx,
+        tx_req::TxRequest,
+    };
+    use slimchain_merkle_trie::prelude::*;
+    use slimchain_tx_state::trie_view::AccountTrieView;
+
+    const MAX_HEIGHT: u64 = 3;
+
+    fn acc(i: u64) -> Address {
+        Address::from(H160::from_low_u64_be(i))
+    }
+
+    fn test_tx(i: u64) -> RawTx {
+        RawTx {
+            caller: acc(i),
+            input: TxRequest::Call {
+                nonce: Nonce::from(i),
+                address: acc(i),
+                data: Vec::new(),
+            },
+            block_height: BlockHeight::from(i - 1),
+            state_root: H256::zero(),
+            reads: Default::default(),
+            writes: Default::default(),
+            gas_used: i,
+            fee: 0,
+        }
+    }
+
+    /// Block `i` writes the slot of account `i` and has tx `i`.
+    fn create_test_db() -> (DBPtr, Vec<Block>) {
+        let db = DB::load_test();
+        let mut prev_blk = Block::genesis_block();
+        let mut blocks = Vec::new();
+        for i in 1..=MAX_HEIGHT {
+            let mut writes = TxWriteData::default();
+            writes.add_nonce(acc(i), Nonce::from(i));
+            writes.add_value(
+                acc(i),
+                StateKey(H256::from_low_u64_be(i)),
+                StateValue(H256::from_low_u64_be(i * 100)),
+            );
+            let (blk, tx) = test_block_tx(&db, &prev_blk, &writes, &[test_tx(i)]);
+            db.write_sync(tx).unwrap();
+            blocks.push(blk.clone());
+            prev_blk = blk;
+        }
+        (db, blocks)
+    }
+
+    fn check(db: &DB, check_state: bool) -> IntegrityReport {
+        let opts = IntegrityOptions {
+            check_state,
+            ..Default::default()
+        };
+        db.check_integrity::<Block, RawTx>(&opts, verify_consensus)
+            .unwrap()
+    }
+
+    fn corrupt(db: &DB, f: impl FnOnce(&mut DBTransaction)) {
+        let mut tx = DBTransaction::new();
+        f(&mut tx);
+        db.db.write(tx).unwrap();
+    }
+
+    #[test]
+    fn test_check_integrity() {
+        let (db, _blocks) = create_test_db();
+        let report = check(&db, true);
+        assert!(report.is_ok(), "{:?}", report.violations);
+        assert_eq!(report.latest_height, BlockHeight::from(MAX_HEIGHT));
+        assert_eq!(report.blocks_checked, MAX_HEIGHT);
+        assert_eq!(report.txs_checked, MAX_HEIGHT);
+        assert!(report.state_nodes_checked > MAX_HEIGHT);
+
+        let report = DB::load_test()
+            .check_integrity::<Block, RawTx>(&Default::default(), verify_consensus)
+            .unwrap();
+        assert_eq!(report, IntegrityReport::default());
+    }
+
+    #[test]
+    fn test_check_integrity_txs() {
+        let (db, blocks) = create_test_db();
+        let tx_hash = blocks[1].tx_list()[0];
+        corrupt(&db, |tx| tx.delete(TX_DB_COL, &h256_to_db_key(tx_hash)));
+        let tx_hash3 = blocks[2].tx_list()[0];
+        corrupt(&db, |tx| {
+            let other = slimchain_utils::serde::binary_encode(&test_tx(9)).unwrap();
+            tx.put_vec(TX_DB_COL, &h256_to_db_key(tx_hash3), other);
+        });
+
+        let report = check(&db, false);
+        assert_eq!(report.violations.len(), 2);
+        assert_eq!(
+            report.violations[0],
+            IntegrityViolation::MissingTx {
+                height: BlockHeight::from(2),
+                tx_hash,
+            }
+        );
+        assert!(matches!(
+            report.violations[1],
+            IntegrityViolation::CorruptedTx { height, tx_hash, .. }
+                if height == BlockHeight::from(3) && tx_hash == tx_hash3
+        ));
+
+        // The txs are not checked on the nodes without the tx bodies.
+        let opts = IntegrityOptions {
+            check_txs: false,
+            ..Default::default()
+        };
+        let report = db
+            .check_integrity::<Block, RawTx>(&opts, verify_consensus)
+            .unwrap();
+        assert!(report.is_ok());
+        assert_eq!(report.txs_checked, 0);
+    }
+
+    #[test]
+    fn test_check_integrity_blocks() {
+        // A missing block, where the next one is not checked against it.
+        let (db, _blocks) = create_test_db();
+        corrupt(&db, |tx| {
+            tx.delete(BLOCK_DB_COL, &block_height_to_db_key(BlockHeight::from(2)))
+        });
+        let report = check(&db, false);
+        assert_eq!(
+            report.violations,
+            vec![IntegrityViolation::MissingBlock {
+                height: BlockHeight::from(2)
+            }]
+        );
+        assert_eq!(report.blocks_checked, MAX_HEIGHT - 1);
+
+        // A block replaced by a forged one breaks the chain at both ends.
+        let (db, blocks) = create_test_db();
+        let mut forged = blocks[1].clone();
+        forged.block_header_mut().prev_blk_hash = H256::repeat_byte(1);
+        corrupt(&db, |tx| {
+            let bin = slimchain_utils::serde::binary_encode(&forged).unwrap();
+            tx.put_vec(
+                BLOCK_DB_COL,
+                &block_height_to_db_key(BlockHeight::from(2)),
+                bin,
+            );
+        });
+        let report = check(&db, false);
+        let heights: Vec<_> = report
+            .violations
+            .iter()
+            .map(|v| match v {
+                IntegrityViolation::InvalidChain { height, .. } => ("chain", height.0),
+                IntegrityViolation::InvalidBlockHashIndex { height, .. } => ("index", height.0),
+                v => panic!("Unexpected violation: {}", v),
+            })
+            .collect();
+        assert_eq!(heights, vec![("chain", 2), ("index", 2), ("chain", 3)]);
+
+        // A corrupted latest block.
+        let (db, _blocks) = create_test_db();
+        let height = BlockHeight::from(MAX_HEIGHT);
+        corrupt(&db, |tx| {
+            tx.put_vec(BLOCK_DB_COL, &block_height_to_db_key(height), vec![0xff])
+        });
+        let report = check(&db, false);
+        assert!(matches!(
+            report.violations[..],
+            [IntegrityViolation::CorruptedBlock { height: h, .. }] if h == height
+        ));
+    }
+
+    #[test]
+    fn test_check_integrity_state() {
+        let (db, blocks) = create_test_db();
+        let root = blocks.last().unwrap().state_root();
+        let acc_state_root = read_trie_without_proof(&AccountTrieView::new(&*db), root, &acc(1))
+            .unwrap()
+            .unwrap()
+            .acc_state_root;
+        corrupt(&db, |tx| {
+            tx.delete(STATE_DB_COL, &h256_to_db_key(acc_state_root))
+        });
+
+        // The state is only walked if asked.
+        assert!(check(&db, false).is_ok());
+        let report = check(&db, true);
+        assert!(matches!(
+            report.violations[..],
+            [IntegrityViolation::MissingStateNode { node_address, parent: Some(_) }]
+                if node_address == acc_state_root
+        ));
+
+        // The state tries out of the shard are not stored.
+        let opts = IntegrityOptions {
+            check_state: true,
+            shard_id: ShardId::new(0, 2),
+            ..Default::default()
+        };
+        let report = db
+            .check_integrity::<Block, RawTx>(&opts, verify_consensus)
+            .unwrap();
+        assert!(report.is_ok(), "{:?}", report.violations);
+
+        // Another node stored in its place.
+        let bin = db.get_state_node_bin(root).unwrap().unwrap();
+        corrupt(&db, |tx| {
+            tx.put_vec(STATE_DB_COL, &h256_to_db_key(acc_state_root), bin)
+        });
+        let report = check(&db, true);
+        assert!(matches!(
+            report.violations[..],
+            [IntegrityViolation::CorruptedStateNode { node_address, .. }]
+                if node_address == acc_state_root
+        ));
+    }
+}
@@ -0,0 +1,329 @@
+//! The write-ahead journal of the database transactions, so that a crash of the process in the
+//! middle of a commit, e.g., between the block data and the latest block header, does not leave
+//! the database inconsistent.
+//!
+//! Each transaction is appended to the journal and synced to the disk before it is applied to
+//! the backend, and the journal is truncated once it is applied. The transactions left in the
+//! journal are applied again when the database is opened. Applying a transaction again is a
+//! no-op, so a transaction applied right before the crash is simply applied twice.
+//!
+//! A record is `len (u32 LE) | payload | blake2 hash of payload | COMMIT_MARKER`, where the
+//! payload is the encoded ops of the transaction. A torn record at the end is ignored, as its
+//! transaction is never applied.
+
+use kvdb::{DBOp, DBTransaction};
+use serde::{Deserialize, Serialize};
+use slimchain_common::{
+    basic::H256,
+    digest::{blake2b_hash_to_h256, default_blake2},
+    error::{Context as _, Result},
+};
+use slimchain_utils::serde::{binary_decode, binary_encode};
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+const COMMIT_MARKER: &[u8; 4] = b"SCMT";
+const LEN_SIZE: usize = 4;
+const HASH_SIZE: usize = 32;
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+enum JournalOp {
+    Insert {
+        col: u32,
+        key: Vec<u8>,
+        value: Vec<u8>,
+    },
+    Delete {
+        col: u32,
+        key: Vec<u8>,
+    },
+    DeletePrefix {
+        col: u32,
+        prefix: Vec<u8>,
+    },
+}
+
+fn encode_tx(tx: &DBTransaction) -> Result<Vec<u8>> {
+    let ops: Vec<JournalOp> = tx
+        .ops
+        .iter()
+        .map(|op| match op {
+            DBOp::Insert { col, key, value } => JournalOp::Insert {
+                col: *col,
+                key: key.to_vec(),
+                value: value.clone(),
+            },
+            DBOp::Delete { col, key } => JournalOp::Delete {
+                col: *col,
+                key: key.to_vec(),
+            },
+            DBOp::DeletePrefix { col, prefix } => JournalOp::DeletePrefix {
+                col: *col,
+                prefix: prefix.to_vec(),
+            },
+        })
+        .collect();
+    binary_encode(&ops)
+}
+
+fn decode_tx(bin: &[u8]) -> Result<DBTransaction> {
+    let ops: Vec<JournalOp> = binary_decode(bin)?;
+    let mut tx = DBTransaction::with_capacity(ops.len());
+    for op in ops {
+        match op {
+            JournalOp::Insert { col, key, value } => tx.put_vec(col, &key, value),
+            JournalOp::Delete { col, key } => tx.delete(col, &key),
+            JournalOp::DeletePrefix { col, prefix } => tx.delete_prefix(col, &prefix),
+        }
+    }
+    Ok(tx)
+}
+
+fn payload_hash(payload: &[u8]) -> H256 {
+    blake2b_hash_to_h256(default_blake2().hash(payload))
+}
+
+/// Decode the complete records in `bin`, stopping at the first torn one.
+fn decode_records(bin: &[u8]) -> Result<Vec<DBTransaction>> {
+    let mut out = Vec::new();
+    let mut rest = bin;
+    while rest.len() >= LEN_SIZE {
+        let mut len_bytes = [0u8; LEN_SIZE];
+        len_bytes.copy_from_slice(&rest[..LEN_SIZE]);
+        let len = u32::from_le_bytes(len_bytes) as usize;
+        let record_len = LEN_SIZE + len + HASH_SIZE + COMMIT_MARKER.len();
+        if rest.len() < record_len {
+            break;
+        }
+        let payload = &rest[LEN_SIZE..LEN_SIZE + len];
+        let hash = &rest[LEN_SIZE + len..LEN_SIZE + len + HASH_SIZE];
+        let marker = &rest[LEN_SIZE + len + HASH_SIZE..record_len];
+        if marker != COMMIT_MARKER || hash != payload_hash(payload).as_bytes() {
+            break;
+        }
+        out.push(decode_tx(payload)?);
+        rest = &rest[record_len..];
+    }
+    if !rest.is_empty() {
+        warn!(
+            "Ignore the torn record of {} bytes in the journal.",
+            rest.len()
+        );
+    }
+    Ok(out)
+}
+
+/// Where a write stops in the crash-injection tests, as if the process is killed there.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum CrashPoint {
+    /// After the transaction is journaled, before it is applied.
+    BeforeApply,
+    /// After the transaction is applied, before the journal is truncated.
+    BeforeTruncate,
+}
+
+pub struct Journal {
+    path: PathBuf,
+    file: Mutex<File>,
+    #[cfg(test)]
+    crash_point: Mutex<Option<CrashPoint>>,
+}
+
+impl Journal {
+    pub fn open(path: &Path) -> Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .open(path)
+            .with_context(|| format!("Failed to open the journal at {}.", path.display()))?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            file: Mutex::new(file),
+            #[cfg(test)]
+            crash_point: Mutex::new(None),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Stop the next write at `point`.
+    #[cfg(test)]
+    pub fn inject_crash(&self, point: CrashPoint) {
+        *self.crash_point.lock().unwrap() = Some(point);
+    }
+
+    #[cfg(test)]
+    fn crash_at(&self, point: CrashPoint) -> Result<()> {
+        let mut crash_point = self.crash_point.lock().unwrap();
+        if *crash_point == Some(point) {
+            *crash_point = None;
+            slimchain_common::error::bail!("Crash injected at {:?}.", point);
+        }
+        Ok(())
+    }
+
+    #[cfg(not(test))]
+    #[inline]
+    fn crash_at(&self, _point: CrashPoint) -> Result<()> {
+        Ok(())
+    }
+
+    /// Journal `tx`, apply it by `apply` and then truncate the journal. The writes are
+    /// serialized, so that the journal holds at most the transaction being applied.
+    pub fn write(
+        &self,
+        tx: DBTransaction,
+        apply: impl FnOnce(DBTransaction) -> io::Result<()>,
+    ) -> Result<()> {
+        let payload = encode_tx(&tx)?;
+        let mut record =
+            Vec::with_capacity(LEN_SIZE + payload.len() + HASH_SIZE + COMMIT_MARKER.len());
+        record.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        record.extend_from_slice(&payload);
+        record.extend_from_slice(payload_hash(&payload).as_bytes());
+        record.extend_from_slice(COMMIT_MARKER);
+
+        let mut file = self.file.lock().unwrap_or_else(|e| e.into_inner());
+        file.seek(SeekFrom::End(0))?;
+        file.write_all(&record)
+            .and_then(|_| file.sync_data())
+            .context("Failed to write the journal.")?;
+        self.crash_at(CrashPoint::BeforeApply)?;
+
+        // A transaction failing to be applied is dropped from the journal as well, so that it
+        // is not applied on the restart after the later ones.
+        let res = apply(tx).context("Failed to apply the transaction.");
+        self.crash_at(CrashPoint::BeforeTruncate)?;
+
+        file.set_len(0).context("Failed to truncate the journal.")?;
+        res
+    }
+
+    /// Apply the transactions left in the journal by `apply` in order and truncate it. Return
+    /// the number of the transactions applied.
+    pub fn recover(&self, apply: impl Fn(DBTransaction) -> io::Result<()>) -> Result<usize> {
+        let mut file = self.file.lock().unwrap_or_else(|e| e.into_inner());
+        let mut bin = Vec::new();
+        file.seek(SeekFrom::Start(0))?;
+        file.read_to_end(&mut bin)
+            .with_context(|| format!("Failed to read the journal at {}.", self.path.display()))?;
+        let txs = decode_records(&bin).context("Corrupted journal.")?;
+        let count = txs.len();
+        for tx in txs {
+            apply(tx).context("Failed to apply the journaled transaction.")?;
+        }
+        if !bin.is_empty() {
+            file.set_len(0)
+                .and_then(|_| file.sync_data())
+                .context("Failed to truncate the journal.")?;
+        }
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kvdb::KeyValueDB;
+
+    fn journal_path(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("slimchain-journal-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        std::fs::remove_file(&path).ok();
+        path
+    }
+
+    fn test_tx(i: u8) -> DBTransaction {
+        let mut tx = DBTransaction::new();
+        tx.put_vec(0, &[i], vec![i; 4]);
+        tx.put_vec(1, &[i, 1], vec![i]);
+        tx.delete(0, &[i - 1]);
+        tx.delete_prefix(1, &[i - 1]);
+        tx
+    }
+
+    fn dump(db: &dyn KeyValueDB) -> Vec<(u32, Vec<u8>, Vec<u8>)> {
+        let mut out = Vec::new();
+        for col in 0..2 {
+            for (k, v) in db.iter(col) {
+                out.push((col, k.to_vec(), v.to_vec()));
+            }
+        }
+        out.sort();
+        out
+    }
+
+    #[test]
+    fn test_journal_encoding() {
+        let tx = test_tx(2);
+        let bin = encode_tx(&tx).unwrap();
+        assert_eq!(encode_tx(&decode_tx(&bin).unwrap()).unwrap(), bin);
+    }
+
+    #[test]
+    fn test_journal_recover() {
+        let path = journal_path("recover");
+        let journal = Journal::open(&path).unwrap();
+        let db = kvdb_memorydb::create(2);
+
+        journal.write(test_tx(1), |tx| db.write(tx)).unwrap();
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), 0);
+        assert_eq!(journal.recover(|tx| db.write(tx)).unwrap(), 0);
+
+        // The transaction journaled before the crash is applied on the recovery.
+        journal.inject_crash(CrashPoint::BeforeApply);
+        assert!(journal.write(test_tx(2), |tx| db.write(tx)).is_err());
+        let expect = {
+            let expect = kvdb_memorydb::create(2);
+            expect.write(test_tx(1)).unwrap();
+            expect.write(test_tx(2)).unwrap();
+            dump(&expect)
+        };
+        assert_ne!(dump(&db), expect);
+        drop(journal);
+
+        let journal = Journal::open(&path).unwrap();
+        assert_eq!(journal.recover(|tx| db.write(tx)).unwrap(), 1);
+        assert_eq!(dump(&db), expect);
+        assert_eq!(journal.recover(|tx| db.write(tx)).unwrap(), 0);
+
+        // Applying it once more changes nothing.
+        journal.inject_crash(CrashPoint::BeforeTruncate);
+        assert!(journal.write(test_tx(3), |tx| db.write(tx)).is_err());
+        let applied = dump(&db);
+        assert_eq!(journal.recover(|tx| db.write(tx)).unwrap(), 1);
+        assert_eq!(dump(&db), applied);
+    }
+
+    #[test]
+    fn test_journal_torn_record() {
+        let path = journal_path("torn");
+        let journal = Journal::open(&path).unwrap();
+        let db = kvdb_memorydb::create(2);
+        journal.inject_crash(CrashPoint::BeforeApply);
+        assert!(journal.write(test_tx(1), |tx| db.write(tx)).is_err());
+        drop(journal);
+
+        // A record torn by the crash, or whose content is corrupted, is ignored.
+        let bin = std::fs::read(&path).unwrap();
+        let mut torn = bin.clone();
+        torn.extend_from_slice(&bin[..bin.len() - 1]);
+        std::fs::write(&path, &torn).unwrap();
+        let journal = Journal::open(&path).unwrap();
+        assert_eq!(journal.recover(|tx| db.write(tx)).unwrap(), 1);
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), 0);
+
+        let mut corrupted = bin;
+        corrupted[LEN_SIZE] ^= 1;
+        std::fs::write(&path, &corrupted).unwrap();
+        assert_eq!(journal.recover(|_| unreachable!()).unwrap(), 0);
+    }
+}
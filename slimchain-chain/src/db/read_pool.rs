@@ -0,0 +1,64 @@
+//! A dedicated pool of threads reading the database, so that the slow reads neither block the
+//! async runtime nor wait behind the writes and the tx executions on the blocking pool of
+//! tokio.
+
+use futures::{channel::oneshot, prelude::*};
+use slimchain_common::error::{anyhow, Context as _, Result};
+use std::{
+    panic::{catch_unwind, AssertUnwindSafe},
+    sync::{mpsc, Arc, Mutex},
+    thread,
+};
+
+/// The default number of the read threads of a database.
+pub const DEFAULT_READ_THREADS: usize = 4;
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+pub struct ReadPool {
+    job_tx: Mutex<mpsc::Sender<Job>>,
+}
+
+impl ReadPool {
+    /// Spawn `threads` read threads, at least one. They exit once the pool is dropped.
+    pub fn new(threads: usize) -> Result<Self> {
+        let (job_tx, job_rx) = mpsc::channel::<Job>();
+        let job_rx = Arc::new(Mutex::new(job_rx));
+        for i in 0..threads.max(1) {
+            let job_rx = job_rx.clone();
+            thread::Builder::new()
+                .name(format!("db-read-{}", i))
+                .spawn(move || loop {
+                    let job = match job_rx.lock().unwrap_or_else(|e| e.into_inner()).recv() {
+                        Ok(job) => job,
+                        Err(_) => break,
+                    };
+                    // A panicking read fails its own future only.
+                    catch_unwind(AssertUnwindSafe(job)).ok();
+                })
+                .context("Failed to spawn the database read thread.")?;
+        }
+        Ok(Self {
+            job_tx: Mutex::new(job_tx),
+        })
+    }
+
+    /// Run `f` on one of the read threads.
+    pub fn spawn<T: Send + 'static>(
+        &self,
+        f: impl FnOnce() -> T + Send + 'static,
+    ) -> impl Future<Output = Result<T>> {
+        let (res_tx, res_rx) = oneshot::channel();
+        let sent = self
+            .job_tx
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .send(Box::new(move || {
+                res_tx.send(f()).ok();
+            }));
+        async move {
+            sent.map_err(|_| anyhow!("The database read threads are gone."))?;
+            res_rx.await.context("The database read is aborted.")
+        }
+    }
+}
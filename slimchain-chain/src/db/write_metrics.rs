@@ -0,0 +1,217 @@
+//! The metrics of the database writes, breaking the latency of a write down into the encoding of
+//! its entries, the wait from its enqueue to its start and the write of the backend.
+//!
+//! The histograms of [`REGISTRY`] are always observed, which takes no allocation once they are
+//! created on the first write. The `record_time!` entries are only built if the metrics sink
+//! keeps them, see [`metrics_enabled`].
+
+use super::TOTAL_COLS;
+use kvdb::{DBOp, DBTransaction};
+use once_cell::sync::Lazy;
+use slimchain_utils::{
+    metrics::{metrics_enabled, serde_json},
+    prometheus::{Gauge, Histogram, DEFAULT_BUCKETS, REGISTRY},
+    record_time,
+};
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+/// The upper bounds of the buckets of the payload size in bytes.
+const PAYLOAD_BUCKETS: [f64; 10] = [1e2, 1e3, 1e4, 3e4, 1e5, 3e5, 1e6, 3e6, 1e7, 1e8];
+
+/// The upper bounds of the buckets of the entries of a column in a write.
+const ENTRIES_BUCKETS: [f64; 10] = [0., 1., 4., 16., 64., 256., 1024., 4096., 16384., 65536.];
+
+/// The names of the columns in the metrics, indexed by the column id.
+pub const COL_NAMES: [&str; TOTAL_COLS as usize] = [
+    "meta",
+    "block",
+    "tx",
+    "state",
+    "log",
+    "tx_loc",
+    "addr_tx",
+    "block_hash",
+    "mempool",
+    "pruned_block",
+];
+
+static DB_WRITE_METRICS: Lazy<DbWriteMetrics> = Lazy::new(DbWriteMetrics::new);
+
+struct DbWriteMetrics {
+    payload_size: Arc<Histogram>,
+    entries: Vec<Arc<Histogram>>,
+    encode_time: Arc<Histogram>,
+    queue_wait: Arc<Histogram>,
+    backend_time: Arc<Histogram>,
+    pending_writes: Arc<Gauge>,
+}
+
+impl DbWriteMetrics {
+    fn new() -> Self {
+        let time = |name, help| REGISTRY.histogram(name, help, &DEFAULT_BUCKETS, &[]);
+        Self {
+            payload_size: REGISTRY.histogram(
+                "slimchain_db_write_payload_bytes",
+                "The size of the keys and the values in a database write.",
+                &PAYLOAD_BUCKETS,
+                &[],
+            ),
+            entries: COL_NAMES
+                .iter()
+                .map(|&col| {
+                    REGISTRY.histogram(
+                        "slimchain_db_write_entries",
+                        "The entries of a column in a database write.",
+                        &ENTRIES_BUCKETS,
+                        &[("col", col)],
+                    )
+                })
+                .collect(),
+            encode_time: time(
+                "slimchain_db_write_encode_seconds",
+                "The time to encode the entries of a database write.",
+            ),
+            queue_wait: time(
+                "slimchain_db_write_queue_wait_seconds",
+                "The time from the enqueue of a database write to its start.",
+            ),
+            backend_time: time(
+                "slimchain_db_write_backend_seconds",
+                "The time of the backend to apply a database write, including its journal.",
+            ),
+            pending_writes: REGISTRY.gauge(
+                "slimchain_db_pending_writes",
+                "The database writes queued or being applied.",
+                &[],
+            ),
+        }
+    }
+}
+
+/// The breakdown of a database write.
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub struct WriteStats {
+    /// The size of the keys and the values.
+    pub payload_size: usize,
+    /// The entries inserted or deleted, indexed by the column id.
+    pub entries: [usize; TOTAL_COLS as usize],
+    pub encode_time: Duration,
+    /// From the enqueue of the write, e.g., waiting for a blocking thread or a backup, to its
+    /// start.
+    pub queue_wait: Duration,
+    pub backend_time: Duration,
+}
+
+impl WriteStats {
+    pub fn new(tx: &DBTransaction, encode_time: Duration) -> Self {
+        let mut stats = Self {
+            encode_time,
+            ..Self::default()
+        };
+        for op in &tx.ops {
+            let (col, size) = match op {
+                DBOp::Insert { col, key, value } => (*col, key.len() + value.len()),
+                DBOp::Delete { col, key } => (*col, key.len()),
+                DBOp::DeletePrefix { col, prefix } => (*col, prefix.len()),
+            };
+            stats.payload_size += size;
+            if let Some(entries) = stats.entries.get_mut(col as usize) {
+                *entries += 1;
+            }
+        }
+        stats
+    }
+
+    pub fn record(&self) {
+        let metrics = &*DB_WRITE_METRICS;
+        metrics.payload_size.observe(self.payload_size as f64);
+        for (histogram, &entries) in metrics.entries.iter().zip(self.entries.iter()) {
+            histogram.observe(entries as f64);
+        }
+        metrics.encode_time.observe_duration(self.encode_time);
+        metrics.queue_wait.observe_duration(self.queue_wait);
+        metrics.backend_time.observe_duration(self.backend_time);
+
+        if metrics_enabled() {
+            let entries: serde_json::Map<_, _> = COL_NAMES
+                .iter()
+                .zip(self.entries.iter())
+                .filter(|(_, &entries)| entries > 0)
+                .map(|(&col, &entries)| (col.to_string(), entries.into()))
+                .collect();
+            record_time!("db_write_encode", self.encode_time, "payload_size": self.payload_size, "entries": entries);
+            record_time!("db_write_queue_wait", self.queue_wait);
+            record_time!("db_write_backend", self.backend_time, "payload_size": self.payload_size);
+        }
+    }
+}
+
+/// A write queued or being applied, counted in the pending-write gauge until it is dropped.
+pub struct PendingWrite {
+    pending: Arc<AtomicUsize>,
+}
+
+impl PendingWrite {
+    pub fn new(pending: &Arc<AtomicUsize>) -> Self {
+        let depth = pending.fetch_add(1, Ordering::SeqCst) + 1;
+        DB_WRITE_METRICS.pending_writes.set(depth as f64);
+        Self {
+            pending: pending.clone(),
+        }
+    }
+}
+
+impl Drop for PendingWrite {
+    fn drop(&mut self) {
+        let depth = self.pending.fetch_sub(1, Ordering::SeqCst) - 1;
+        DB_WRITE_METRICS.pending_writes.set(depth as f64);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{
+        ADDR_TX_DB_COL, BLOCK_DB_COL, BLOCK_HASH_DB_COL, LOG_DB_COL, MEMPOOL_DB_COL, META_DB_COL,
+        PRUNED_BLOCK_DB_COL, STATE_DB_COL, TX_DB_COL, TX_LOC_DB_COL,
+    };
+
+    #[test]
+    fn test_write_stats() {
+        let mut tx = DBTransaction::new();
+        tx.put_vec(STATE_DB_COL, &[1; 32], vec![0; 100]);
+        tx.put_vec(STATE_DB_COL, &[2; 32], vec![0; 50]);
+        tx.put_vec(META_DB_COL, b"latest", vec![0; 10]);
+        tx.delete(MEMPOOL_DB_COL, &[3; 32]);
+        let stats = WriteStats::new(&tx, Duration::from_millis(1));
+        assert_eq!(stats.payload_size, 32 + 100 + 32 + 50 + 6 + 10 + 32);
+        let mut entries = [0; TOTAL_COLS as usize];
+        entries[STATE_DB_COL as usize] = 2;
+        entries[META_DB_COL as usize] = 1;
+        entries[MEMPOOL_DB_COL as usize] = 1;
+        assert_eq!(stats.entries, entries);
+        assert_eq!(stats.encode_time, Duration::from_millis(1));
+
+        let cols = [
+            (META_DB_COL, "meta"),
+            (BLOCK_DB_COL, "block"),
+            (TX_DB_COL, "tx"),
+            (STATE_DB_COL, "state"),
+            (LOG_DB_COL, "log"),
+            (TX_LOC_DB_COL, "tx_loc"),
+            (ADDR_TX_DB_COL, "addr_tx"),
+            (BLOCK_HASH_DB_COL, "block_hash"),
+            (MEMPOOL_DB_COL, "mempool"),
+            (PRUNED_BLOCK_DB_COL, "pruned_block"),
+        ];
+        for &(col, name) in &cols {
+            assert_eq!(COL_NAMES[col as usize], name);
+        }
+    }
+}
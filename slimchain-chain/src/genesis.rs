@@ -0,0 +1,229 @@
+use crate::db::{DBPtr, Transaction};
+use chrono::{DateTime, Utc};
+use once_cell::sync::{Lazy, OnceCell};
+use serde::Deserialize;
+use slimchain_common::{
+    basic::{Address, Code, Nonce, StateKey, StateValue, H256},
+    error::{anyhow, Result},
+    rw_set::TxWriteData,
+};
+use slimchain_tx_state::{update_tx_state, MemTxState, TxStateUpdate};
+use slimchain_utils::config::{join_path, ConfigErrors, ValidateConfig};
+use std::collections::{BTreeMap, HashSet};
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct GenesisAccountConfig {
+    /// The account address.
+    pub address: Address,
+    /// The initial nonce. Default 0.
+    #[serde(default)]
+    pub nonce: Nonce,
+    /// The contract code in hex. Default empty.
+    #[serde(
+        default,
+        deserialize_with = "slimchain_utils::config::deserialize_from_hex"
+    )]
+    pub code: Vec<u8>,
+    /// The initial contract storage.
+    #[serde(default)]
+    pub values: BTreeMap<StateKey, StateValue>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct GenesisConfig {
+    /// The timestamp of the genesis block in RFC 3339.
+    pub time_stamp: DateTime<Utc>,
+    /// The initial difficulty used by PoW. Default to `pow.init_diff`.
+    pub init_diff: Option<u64>,
+    /// The accounts in the genesis state.
+    pub accounts: Vec<GenesisAccountConfig>,
+}
+
+impl Default for GenesisConfig {
+    fn default() -> Self {
+        Self {
+            time_stamp: DateTime::parse_from_rfc3339("2020-08-01T00:00:00Z")
+                .expect("Failed to parse the timestamp.")
+                .with_timezone(&Utc),
+            init_diff: None,
+            accounts: Vec::new(),
+        }
+    }
+}
+
+impl ValidateConfig for GenesisConfig {
+    fn validate_at(&self, path: &str, errors: &mut ConfigErrors) {
+        errors.ensure(
+            self.init_diff != Some(0),
+            &join_path(path, "init_diff"),
+            "Should be positive.",
+        );
+        let mut addresses = HashSet::new();
+        for (i, acc) in self.accounts.iter().enumerate() {
+            errors.ensure(
+                addresses.insert(acc.address),
+                &join_path(path, &format!("accounts[{}].address", i)),
+                format_args!("Duplicate account {}.", acc.address),
+            );
+        }
+    }
+}
+
+static GLOBAL_GENESIS_CONFIG: OnceCell<(GenesisConfig, H256)> = OnceCell::new();
+static DEFAULT_GENESIS_CONFIG: Lazy<(GenesisConfig, H256)> =
+    Lazy::new(|| (GenesisConfig::default(), H256::zero()));
+
+impl GenesisConfig {
+    pub fn state_update(&self) -> Result<TxStateUpdate> {
+        let mut writes = TxWriteData::default();
+        for acc in &self.accounts {
+            writes.add_nonce(acc.address, acc.nonce);
+            if !acc.code.is_empty() {
+                writes.add_code(acc.address, Code::from(acc.code.clone()));
+            }
+            for (&key, &value) in acc.values.iter() {
+                writes.add_value(acc.address, key, value);
+            }
+        }
+
+        let empty_view = MemTxState::new();
+        update_tx_state(&empty_view, H256::zero(), &writes)
+    }
+
+    pub fn state_root(&self) -> Result<H256> {
+        Ok(self.state_update()?.root)
+    }
+
+    /// Build the genesis state trie and write it to the database.
+    /// It should be called before the node loads the snapshot.
+    pub fn write_genesis_state(&self, db: &DBPtr) -> Result<H256> {
+        let update = self.state_update()?;
+        let mut tx = Transaction::new();
+        tx.update_state(&update)?;
+        db.write_sync(tx)?;
+        Ok(update.root)
+    }
+
+    pub fn install_as_global(self) -> Result<()> {
+        let state_root = self.state_root()?;
+        GLOBAL_GENESIS_CONFIG
+            .set((self, state_root))
+            .map_err(|_| anyhow!("Failed to set GenesisConfig."))
+    }
+
+    pub fn get() -> &'static Self {
+        &Self::get_inner().0
+    }
+
+    pub fn get_state_root() -> H256 {
+        Self::get_inner().1
+    }
+
+    fn get_inner() -> &'static (Self, H256) {
+        GLOBAL_GENESIS_CONFIG
+            .get()
+            .unwrap_or_else(|| &*DEFAULT_GENESIS_CONFIG)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{block::BlockTrait, consensus, db::DB};
+    use slimchain_common::{basic::H160, digest::Digestible};
+    use slimchain_tx_state::TxStateReadContext;
+
+    fn test_cfg() -> GenesisConfig {
+        serde_json::from_value(serde_json::json!({
+            "time_stamp": "2021-01-01T00:00:00Z",
+            "init_diff": 1000,
+            "accounts": [
+                {
+                    "address": "0x0000000000000000000000000000000000000001",
+                    "nonce": "0x1",
+                },
+                {
+                    "address": "0x0000000000000000000000000000000000000002",
+                    "code": "6080",
+                    "values": {
+                        "0x0000000000000000000000000000000000000000000000000000000000000001":
+                            "0x0000000000000000000000000000000000000000000000000000000000000002",
+                    },
+                },
+            ],
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_default_genesis() {
+        let cfg = GenesisConfig::default();
+        assert_eq!(cfg.state_root().unwrap(), H256::zero());
+        let blk = consensus::raft::Block::genesis_from_config(&cfg, H256::zero());
+        assert_eq!(blk, consensus::raft::Block::genesis_block());
+        let blk = consensus::pow::Block::genesis_from_config(&cfg, H256::zero());
+        assert_eq!(blk, consensus::pow::Block::genesis_block());
+    }
+
+    #[test]
+    fn test_genesis_from_config() {
+        let cfg1 = test_cfg();
+        let cfg2 = test_cfg();
+        let root1 = cfg1.state_root().unwrap();
+        let root2 = cfg2.state_root().unwrap();
+        assert!(!root1.is_zero());
+        assert_eq!(root1, root2);
+
+        let raft_blk1 = consensus::raft::Block::genesis_from_config(&cfg1, root1);
+        let raft_blk2 = consensus::raft::Block::genesis_from_config(&cfg2, root2);
+        assert_eq!(raft_blk1.to_digest(), raft_blk2.to_digest());
+        assert_eq!(raft_blk1.state_root(), root1);
+        assert_eq!(raft_blk1.time_stamp(), cfg1.time_stamp);
+
+        let pow_blk1 = consensus::pow::Block::genesis_from_config(&cfg1, root1);
+        let pow_blk2 = consensus::pow::Block::genesis_from_config(&cfg2, root2);
+        assert_eq!(pow_blk1.to_digest(), pow_blk2.to_digest());
+        assert_ne!(
+            pow_blk1.to_digest(),
+            consensus::pow::Block::genesis_block().to_digest()
+        );
+
+        let mut cfg3 = test_cfg();
+        cfg3.accounts.pop();
+        assert_ne!(cfg3.state_root().unwrap(), root1);
+    }
+
+    #[test]
+    fn test_validate_genesis_config() {
+        assert!(test_cfg().validate_config("genesis").is_ok());
+
+        let mut cfg = test_cfg();
+        cfg.init_diff = Some(0);
+        let dup = cfg.accounts[0].clone();
+        cfg.accounts.push(dup);
+        let errors = cfg.validate_config("genesis").unwrap_err();
+        assert!(errors.contains("genesis.init_diff"));
+        assert!(errors.contains("genesis.accounts[2].address"));
+        assert_eq!(2, errors.errors().len());
+    }
+
+    #[test]
+    fn test_write_genesis_state() {
+        let cfg = test_cfg();
+        let db = DB::load_test();
+        let root = cfg.write_genesis_state(&db).unwrap();
+        assert_eq!(root, cfg.state_root().unwrap());
+
+        let addr1 = Address::from(H160::from_low_u64_be(1));
+        let addr2 = Address::from(H160::from_low_u64_be(2));
+        let mut ctx = TxStateReadContext::new(db, root);
+        assert_eq!(ctx.get_nonce(addr1).unwrap(), Nonce::from(1));
+        assert_eq!(ctx.get_code(addr2).unwrap(), Code::from(vec![0x60, 0x80]));
+        assert_eq!(
+            ctx.get_value(addr2, StateKey::from(H256::from_low_u64_be(1)))
+                .unwrap(),
+            StateValue::from(H256::from_low_u64_be(2))
+        );
+    }
+}
@@ -2,17 +2,32 @@
 extern crate tracing;
 
 pub mod access_map;
+pub mod assemble;
 pub mod behavior;
 pub mod block;
 pub mod block_proposal;
+pub mod checkpoint;
+pub mod commit_event;
 pub mod config;
 pub mod conflict_check;
 pub mod consensus;
+pub mod cross_shard;
 pub mod db;
+pub mod genesis;
 pub mod latest;
+pub mod light;
 pub mod loader;
+pub mod mempool;
+pub mod metrics;
+pub mod nonce_check;
+pub mod remote_state;
+pub mod replay;
 pub mod role;
 pub mod snapshot;
+pub mod tx_queue;
+pub mod tx_status;
 
+#[cfg(test)]
+mod test_utils;
 #[cfg(test)]
 mod tests;
@@ -0,0 +1,246 @@
+//! A light client following the block headers without the txs or the state, and verifying the
+//! state values served by the storage nodes against the state roots of the headers followed.
+//!
+//! It only depends on the block and the proof types, so it does not need a runtime or a
+//! database.
+
+use crate::{
+    block::{BlockHeader, BlockTrait},
+    block_proposal::BlockProposal,
+    consensus::{pow, raft},
+};
+use serde::{Deserialize, Serialize};
+use slimchain_common::{
+    basic::{Address, BlockHeight, StateKey, H256},
+    collections::HashMap,
+    digest::Digestible,
+    ed25519::{PubSigPair, PublicKey},
+    error::{bail, ensure, Context as _, Result},
+    tx::TxTrait,
+};
+use slimchain_tx_state::{TxReadProof, TxStateReadContext, TxStateView};
+use std::sync::Arc;
+
+/// A block header with what is needed to verify its consensus.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct LightHeader<Block> {
+    pub block: Block,
+    /// The signature of the raft leader over the block digest. Unused for PoW.
+    #[serde(default)]
+    pub proposer: Option<PubSigPair>,
+}
+
+impl<Block> LightHeader<Block> {
+    pub fn new(block: Block, proposer: Option<PubSigPair>) -> Self {
+        Self { block, proposer }
+    }
+}
+
+impl<Block: BlockTrait, Tx: TxTrait> From<&BlockProposal<Block, Tx>> for LightHeader<Block> {
+    fn from(blk_proposal: &BlockProposal<Block, Tx>) -> Self {
+        Self::new(
+            blk_proposal.get_block().clone(),
+            blk_proposal.get_proposer().copied(),
+        )
+    }
+}
+
+pub trait LightBlockTrait: BlockTrait {
+    /// Verify the consensus of `header` following `prev_blk`, where the headers are signed by
+    /// one of `proposer_keys` if the consensus requires so.
+    fn verify_light_consensus(
+        header: &LightHeader<Self>,
+        prev_blk: &Self,
+        proposer_keys: &[PublicKey],
+    ) -> Result<()>;
+}
+
+impl LightBlockTrait for pow::Block {
+    fn verify_light_consensus(
+        header: &LightHeader<Self>,
+        prev_blk: &Self,
+        _proposer_keys: &[PublicKey],
+    ) -> Result<()> {
+        pow::verify_consensus(&header.block, prev_blk)
+    }
+}
+
+impl LightBlockTrait for raft::Block {
+    fn verify_light_consensus(
+        header: &LightHeader<Self>,
+        _prev_blk: &Self,
+        proposer_keys: &[PublicKey],
+    ) -> Result<()> {
+        let proposer = match header.proposer.as_ref() {
+            Some(proposer) => proposer,
+            None => bail!("Block header without the raft leader signature."),
+        };
+        ensure!(
+            proposer_keys.contains(proposer.public()),
+            "Block header signed by an unknown raft leader."
+        );
+        proposer.verify(header.block.to_digest())
+    }
+}
+
+pub struct LightClient<Block: LightBlockTrait> {
+    latest: Block,
+    proposer_keys: Vec<PublicKey>,
+    /// The digests of the headers followed.
+    header_hashes: HashMap<BlockHeight, H256>,
+}
+
+impl<Block: LightBlockTrait> LightClient<Block> {
+    pub fn new(genesis: Block) -> Self {
+        let mut header_hashes = HashMap::new();
+        header_hashes.insert(genesis.block_height(), genesis.block_header().to_digest());
+        Self {
+            latest: genesis,
+            proposer_keys: Vec::new(),
+            header_hashes,
+        }
+    }
+
+    /// Trust the headers signed by one of `proposer_keys`, i.e., the raft leaders.
+    pub fn with_proposer_keys(mut self, proposer_keys: Vec<PublicKey>) -> Self {
+        self.proposer_keys = proposer_keys;
+        self
+    }
+
+    pub fn latest(&self) -> &Block {
+        &self.latest
+    }
+
+    pub fn latest_height(&self) -> BlockHeight {
+        self.latest.block_height()
+    }
+
+    /// Follow the header next to the latest one.
+    pub fn apply_header(&mut self, header: LightHeader<Block>) -> Result<()> {
+        let height = header.block.block_height();
+        header
+            .block
+            .verify_block_header(&self.latest)
+            .with_context(|| format!("Invalid block header {}.", height))?;
+        Block::verify_light_consensus(&header, &self.latest, &self.proposer_keys)
+            .with_context(|| format!("Invalid consensus of block header {}.", height))?;
+
+        self.header_hashes
+            .insert(height, header.block.block_header().to_digest());
+        self.latest = header.block;
+        Ok(())
+    }
+
+    /// Verify the value of `key` in the storage of `account` at `header` by `proof`, and
+    /// return the value proven. `header` should be one of the headers followed.
+    pub fn verify_state_value(
+        &self,
+        header: &BlockHeader,
+        account: Address,
+        key: StateKey,
+        proof: &TxReadProof,
+    ) -> Result<H256> {
+        ensure!(
+            self.header_hashes.get(&header.height) == Some(&header.to_digest()),
+            "Block header {} is not followed.",
+            header.height
+        );
+        let value = proof.verify_value(account, key, header.state_root)?;
+        Ok(value.0)
+    }
+}
+
+/// Generate the proof of the value of `key` in the storage of `account` at `state_root`, which
+/// is verified by [`LightClient::verify_state_value`].
+pub fn state_value_proof(
+    state_view: Arc<dyn TxStateView + Sync + Send>,
+    state_root: H256,
+    account: Address,
+    key: StateKey,
+) -> Result<TxReadProof> {
+    let mut read_ctx = TxStateReadContext::new(state_view, state_root);
+    read_ctx.get_value(account, key)?;
+    read_ctx.generate_proof()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{db::DB, test_utils::commit_test_block};
+    use slimchain_common::{
+        basic::{StateValue, H160},
+        ed25519::Keypair,
+        rw_set::TxWriteData,
+    };
+
+    #[test]
+    fn test_light_client() {
+        let mut rng = rand::thread_rng();
+        let keypair = Keypair::generate(&mut rng);
+        let other_keypair = Keypair::generate(&mut rng);
+        let db = DB::load_test();
+        let acc = Address::from(H160::from_low_u64_be(1));
+
+        let genesis = raft::Block::genesis_block();
+        let mut client = LightClient::new(genesis.clone()).with_proposer_keys(vec![keypair.public]);
+
+        let mut prev_blk = genesis;
+        for i in 1..=10u64 {
+            // Each block writes the slot of its height.
+            let key = StateKey(H256::from_low_u64_be(i));
+            let mut writes = TxWriteData::default();
+            writes.add_value(acc, key, StateValue(H256::from_low_u64_be(i * 100)));
+            let blk = commit_test_block(&db, &prev_blk, &writes);
+            let sig = PubSigPair::create(&keypair, blk.to_digest());
+
+            // The headers unsigned or signed by an unknown leader are rejected.
+            assert!(client
+                .apply_header(LightHeader::new(blk.clone(), None))
+                .is_err());
+            let forged = PubSigPair::create(&other_keypair, blk.to_digest());
+            assert!(client
+                .apply_header(LightHeader::new(blk.clone(), Some(forged)))
+                .is_err());
+            let mut tampered = blk.clone();
+            tampered.block_header_mut().state_root = H256::repeat_byte(1);
+            assert!(client
+                .apply_header(LightHeader::new(tampered, Some(sig)))
+                .is_err());
+
+            client
+                .apply_header(LightHeader::new(blk.clone(), Some(sig)))
+                .unwrap();
+            assert_eq!(client.latest_height(), BlockHeight::from(i));
+
+            let proof = state_value_proof(db.clone(), blk.state_root(), acc, key).unwrap();
+            let value = client
+                .verify_state_value(blk.block_header(), acc, key, &proof)
+                .unwrap();
+            assert_eq!(value, H256::from_low_u64_be(i * 100));
+
+            // The slots written by the earlier blocks are still proven by the new header.
+            let prev_key = StateKey(H256::from_low_u64_be(i - 1));
+            let proof = state_value_proof(db.clone(), blk.state_root(), acc, prev_key).unwrap();
+            let value = client
+                .verify_state_value(blk.block_header(), acc, prev_key, &proof)
+                .unwrap();
+            assert_eq!(value, H256::from_low_u64_be((i - 1) * 100));
+
+            // The proof fails against another header, or a header not followed.
+            assert!(client
+                .verify_state_value(prev_blk.block_header(), acc, prev_key, &proof)
+                .is_err());
+            let mut unknown = blk.block_header().clone();
+            unknown.state_root = prev_blk.state_root();
+            assert!(client
+                .verify_state_value(&unknown, acc, prev_key, &proof)
+                .is_err());
+
+            prev_blk = blk;
+        }
+
+        let json = serde_json::to_string(&LightHeader::new(prev_blk.clone(), None)).unwrap();
+        let header: LightHeader<raft::Block> = serde_json::from_str(&json).unwrap();
+        assert_eq!(header.block, prev_blk);
+    }
+}
@@ -0,0 +1,500 @@
+use crate::{
+    config::MempoolConfig,
+    db::{DBPtr, Transaction as DBTransaction},
+};
+use serde::{Deserialize, Serialize};
+use slimchain_common::{
+    basic::{Address, BlockHeight, Nonce, H256},
+    collections::HashMap,
+    error::{anyhow, Result},
+    tx::TxTrait,
+};
+use slimchain_tx_state::TxProposal;
+use slimchain_utils::{record_event, serde::binary_encode};
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    sync::mpsc as std_mpsc,
+    thread,
+    time::{Duration, Instant},
+};
+
+/// The number of batched changes that makes [`Mempool::flush_due`] flush.
+const FLUSH_BATCH_SIZE: usize = 256;
+/// The time since the last flush that makes [`Mempool::flush_due`] flush.
+const FLUSH_INTERVAL: Duration = Duration::from_millis(200);
+
+/// A pending tx proposal in the [`Mempool`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MempoolEntry<Tx: TxTrait> {
+    /// The arrival order in the pool, which is kept across restarts.
+    pub seq: u64,
+    /// The block height current when the tx proposal was received.
+    pub recv_height: BlockHeight,
+    pub tx_proposal: TxProposal<Tx>,
+}
+
+impl<Tx: TxTrait> MempoolEntry<Tx> {
+    /// The fee signed by the caller of the tx.
+    pub fn fee(&self) -> u64 {
+        self.tx_proposal.tx.fee()
+    }
+
+    fn caller_nonce(&self) -> (Address, Nonce) {
+        let tx = &self.tx_proposal.tx;
+        (tx.tx_caller(), tx.tx_input().nonce())
+    }
+
+    // The smallest one is evicted first.
+    fn eviction_key(&self) -> (u64, Reverse<u64>) {
+        (self.fee(), Reverse(self.seq))
+    }
+}
+
+/// Why a tx proposal is not admitted to the [`Mempool`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum MempoolReject {
+    /// The same tx is already pending.
+    DuplicateTx,
+    /// A tx with the same caller and nonce and no lower fee is already pending.
+    DuplicateNonce,
+    /// The pool is full of the txs with higher fees.
+    MempoolFull,
+}
+
+impl MempoolReject {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::DuplicateTx => "duplicate_tx",
+            Self::DuplicateNonce => "duplicate_nonce",
+            Self::MempoolFull => "mempool_full",
+        }
+    }
+}
+
+impl fmt::Display for MempoolReject {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+type WriteReq = (DBTransaction, Option<std_mpsc::Sender<Result<()>>>);
+
+/// Write the flushed batches of a persistent pool to the database in order on a thread of its
+/// own, so that the callers polling the pool never wait for the disk.
+struct MempoolWriter {
+    tx: Option<std_mpsc::Sender<WriteReq>>,
+    handle: Option<thread::JoinHandle<()>>,
+}
+
+impl MempoolWriter {
+    fn spawn(db: DBPtr) -> Result<Self> {
+        let (tx, rx) = std_mpsc::channel::<WriteReq>();
+        let handle = thread::Builder::new()
+            .name("mempool-writer".to_string())
+            .spawn(move || {
+                for (batch, done) in rx {
+                    let res = db.write_sync(batch);
+                    match done {
+                        Some(done) => {
+                            done.send(res).ok();
+                        }
+                        None => {
+                            if let Err(e) = res {
+                                warn!("Failed to persist the mempool. Error: {}", e);
+                            }
+                        }
+                    }
+                }
+            })?;
+        Ok(Self {
+            tx: Some(tx),
+            handle: Some(handle),
+        })
+    }
+
+    fn send(&self, req: WriteReq) -> Result<()> {
+        self.tx
+            .as_ref()
+            .and_then(|tx| tx.send(req).ok())
+            .ok_or_else(|| anyhow!("The mempool writer is stopped."))
+    }
+}
+
+impl Drop for MempoolWriter {
+    // Wait for the batches sent so far to be written.
+    fn drop(&mut self) {
+        self.tx.take();
+        if let Some(handle) = self.handle.take() {
+            handle.join().ok();
+        }
+    }
+}
+
+struct MempoolStore<Tx: TxTrait> {
+    writer: MempoolWriter,
+    encode: fn(&MempoolEntry<Tx>) -> Result<Vec<u8>>,
+    // The changes not flushed yet.
+    batch: Option<DBTransaction>,
+    batch_len: usize,
+    last_flush: Instant,
+}
+
+impl<Tx: TxTrait> MempoolStore<Tx> {
+    fn batch(&mut self) -> &mut DBTransaction {
+        self.batch_len += 1;
+        self.batch.get_or_insert_with(DBTransaction::new)
+    }
+
+    fn take_batch(&mut self) -> Option<DBTransaction> {
+        self.batch_len = 0;
+        self.last_flush = Instant::now();
+        self.batch.take()
+    }
+}
+
+/// The pending tx proposals indexed by their tx ids and by their callers and nonces.
+///
+/// Once `max_size` is reached, a new tx proposal evicts the one with the lowest fee, and the
+/// latest arrival among the ones with the same fee. If the new one would be evicted itself, it
+/// is rejected instead. A tx proposal replaces the pending one with the same caller and nonce
+/// only if it pays a higher fee.
+///
+/// A persistent pool mirrors the insertions and the removals to [`MEMPOOL_DB_COL`], so that
+/// [`Mempool::open`] restores it after a restart. The changes are batched in memory and written
+/// in order by a background writer on [`Mempool::flush_due`] and [`Mempool::flush_in_background`],
+/// or synchronously on [`Mempool::flush`]. Dropping the pool waits for the pending writes.
+///
+/// [`MEMPOOL_DB_COL`]: crate::db::MEMPOOL_DB_COL
+pub struct Mempool<Tx: TxTrait> {
+    max_size: Option<usize>,
+    store: Option<MempoolStore<Tx>>,
+    next_seq: u64,
+    entries: HashMap<H256, MempoolEntry<Tx>>,
+    by_nonce: HashMap<(Address, Nonce), H256>,
+    by_seq: BTreeMap<u64, H256>,
+    by_eviction: BTreeSet<((u64, Reverse<u64>), H256)>,
+}
+
+impl<Tx: TxTrait> Mempool<Tx> {
+    /// Create an empty pool in memory.
+    pub fn new(cfg: &MempoolConfig) -> Self {
+        Self {
+            max_size: cfg.max_size,
+            store: None,
+            next_seq: 0,
+            entries: HashMap::new(),
+            by_nonce: HashMap::new(),
+            by_seq: BTreeMap::new(),
+            by_eviction: BTreeSet::new(),
+        }
+    }
+
+    /// Create the pool, which restores the tx proposals persisted in `db` if `cfg.persist` is
+    /// set.
+    pub fn open(cfg: &MempoolConfig, db: &DBPtr) -> Result<Self>
+    where
+        Tx: Serialize + for<'de> Deserialize<'de>,
+    {
+        let mut pool = Self::new(cfg);
+        if !cfg.persist {
+            return Ok(pool);
+        }
+
+        let mut entries: Vec<MempoolEntry<Tx>> = db.get_mempool_entries()?;
+        entries.sort_by_key(|entry| entry.seq);
+        let restored = entries.len();
+        pool.next_seq = entries.last().map_or(0, |entry| entry.seq + 1);
+        for entry in entries {
+            pool.index(entry);
+        }
+        pool.store = Some(MempoolStore {
+            writer: MempoolWriter::spawn(db.clone())?,
+            encode: binary_encode::<MempoolEntry<Tx>>,
+            batch: None,
+            batch_len: 0,
+            last_flush: Instant::now(),
+        });
+
+        // `max_size` may be lowered since the last run.
+        while pool.max_size.map_or(false, |max| pool.len() > max) {
+            pool.evict_one();
+        }
+        pool.flush()?;
+
+        info!(
+            "Restore {} pending tx proposals from the mempool.",
+            pool.len()
+        );
+        record_event!("mempool_restore", "restored": restored, "size": pool.len());
+        Ok(pool)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn contains(&self, tx_id: H256) -> bool {
+        self.entries.contains_key(&tx_id)
+    }
+
+    pub fn get(&self, tx_id: H256) -> Option<&MempoolEntry<Tx>> {
+        self.entries.get(&tx_id)
+    }
+
+    pub fn get_by_nonce(&self, caller: Address, nonce: Nonce) -> Option<&MempoolEntry<Tx>> {
+        self.by_nonce
+            .get(&(caller, nonce))
+            .and_then(|tx_id| self.entries.get(tx_id))
+    }
+
+    /// Iterate the pending tx proposals in their arrival order.
+    pub fn iter(&self) -> impl Iterator<Item = &MempoolEntry<Tx>> {
+        self.by_seq
+            .values()
+            .filter_map(move |tx_id| self.entries.get(tx_id))
+    }
+
+    /// Admit `tx_proposal` received at `recv_height`. Return the ids of the tx proposals it
+    /// evicts or replaces.
+    pub fn insert(
+        &mut self,
+        recv_height: BlockHeight,
+        tx_proposal: TxProposal<Tx>,
+    ) -> Result<Vec<H256>, MempoolReject> {
+        let tx_id = tx_proposal.tx.id();
+        if self.entries.contains_key(&tx_id) {
+            return Err(MempoolReject::DuplicateTx);
+        }
+
+        let entry = MempoolEntry {
+            seq: self.next_seq,
+            recv_height,
+            tx_proposal,
+        };
+        let mut removed = Vec::new();
+
+        if let Some(&old_id) = self.by_nonce.get(&entry.caller_nonce()) {
+            if self.entries[&old_id].fee() >= entry.fee() {
+                return Err(MempoolReject::DuplicateNonce);
+            }
+            self.remove(old_id);
+            removed.push(old_id);
+        }
+
+        if self.max_size.map_or(false, |max| self.len() >= max) {
+            let worst = self.by_eviction.iter().next().map(|(key, _)| *key);
+            if worst.map_or(true, |worst| entry.eviction_key() < worst) {
+                return Err(MempoolReject::MempoolFull);
+            }
+            removed.extend(self.evict_one());
+        }
+
+        self.next_seq += 1;
+        self.persist(&entry);
+        self.index(entry);
+        Ok(removed)
+    }
+
+    /// Remove the tx proposal with `tx_id`, e.g., once it is included in a block or expired.
+    pub fn remove(&mut self, tx_id: H256) -> Option<MempoolEntry<Tx>> {
+        let entry = self.entries.remove(&tx_id)?;
+        self.by_nonce.remove(&entry.caller_nonce());
+        self.by_seq.remove(&entry.seq);
+        self.by_eviction.remove(&(entry.eviction_key(), tx_id));
+        if let Some(store) = self.store.as_mut() {
+            store.batch().delete_mempool_entry(tx_id);
+        }
+        Some(entry)
+    }
+
+    /// Write the changes since the last flush to the database of a persistent pool, and wait
+    /// for them and the earlier flushes to be written.
+    pub fn flush(&mut self) -> Result<()> {
+        let store = match self.store.as_mut() {
+            Some(store) => store,
+            None => return Ok(()),
+        };
+        let batch = match store.take_batch() {
+            Some(batch) => batch,
+            None => return Ok(()),
+        };
+        let (done_tx, done_rx) = std_mpsc::channel();
+        store.writer.send((batch, Some(done_tx)))?;
+        done_rx
+            .recv()
+            .map_err(|_| anyhow!("The mempool writer is stopped."))?
+    }
+
+    /// Hand the changes since the last flush to the background writer without waiting.
+    pub fn flush_in_background(&mut self) {
+        if let Some(store) = self.store.as_mut() {
+            if let Some(batch) = store.take_batch() {
+                if let Err(e) = store.writer.send((batch, None)) {
+                    warn!("Failed to persist the mempool. Error: {}", e);
+                }
+            }
+        }
+    }
+
+    /// Like [`Mempool::flush_in_background`], but only once enough changes are batched or
+    /// enough time has passed since the last flush.
+    pub fn flush_due(&mut self) {
+        let due = self.store.as_ref().map_or(false, |store| {
+            store.batch_len >= FLUSH_BATCH_SIZE
+                || (store.batch_len > 0 && store.last_flush.elapsed() >= FLUSH_INTERVAL)
+        });
+        if due {
+            self.flush_in_background();
+        }
+    }
+
+    fn index(&mut self, entry: MempoolEntry<Tx>) {
+        let tx_id = entry.tx_proposal.tx.id();
+        self.by_nonce.insert(entry.caller_nonce(), tx_id);
+        self.by_seq.insert(entry.seq, tx_id);
+        self.by_eviction.insert((entry.eviction_key(), tx_id));
+        self.entries.insert(tx_id, entry);
+    }
+
+    fn persist(&mut self, entry: &MempoolEntry<Tx>) {
+        if let Some(store) = self.store.as_mut() {
+            match (store.encode)(entry) {
+                Ok(bin) => store
+                    .batch()
+                    .insert_mempool_entry_bin(entry.tx_proposal.tx.id(), bin),
+                Err(e) => warn!("Failed to encode the mempool entry. Error: {}", e),
+            }
+        }
+    }
+
+    fn evict_one(&mut self) -> Option<H256> {
+        let (_, tx_id) = *self.by_eviction.iter().next()?;
+        self.remove(tx_id);
+        record_event!("mempool_evict", "tx_id": tx_id);
+        Some(tx_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{db::DB, test_utils::DummyTx};
+    use slimchain_common::basic::H160;
+
+    fn proposal(id: u64, caller: u64, nonce: u64, fee: u64) -> TxProposal<DummyTx> {
+        DummyTx::new(id, caller, nonce)
+            .with_fee(fee)
+            .into_proposal()
+    }
+
+    fn cfg(max_size: Option<usize>, persist: bool) -> MempoolConfig {
+        MempoolConfig { max_size, persist }
+    }
+
+    fn callers(pool: &Mempool<DummyTx>) -> Vec<u64> {
+        pool.iter()
+            .map(|entry| entry.tx_proposal.tx.caller.0.to_low_u64_be())
+            .collect()
+    }
+
+    #[test]
+    fn test_eviction_order() {
+        let mut pool = Mempool::new(&cfg(Some(3), false));
+        let ids: Vec<_> = [(1, 10), (2, 5), (3, 5)]
+            .iter()
+            .map(|&(caller, fee)| {
+                let p = proposal(caller, caller, 0, fee);
+                let id = p.tx.id();
+                assert_eq!(pool.insert(0.into(), p), Ok(vec![]));
+                id
+            })
+            .collect();
+
+        // the same fee as the cheapest ones: the new arrival is the first to go.
+        assert_eq!(
+            pool.insert(0.into(), proposal(4, 4, 0, 5)),
+            Err(MempoolReject::MempoolFull)
+        );
+        // among the cheapest, the latest arrival is evicted.
+        assert_eq!(
+            pool.insert(0.into(), proposal(5, 5, 0, 6)),
+            Ok(vec![ids[2]])
+        );
+        assert_eq!(
+            pool.insert(0.into(), proposal(6, 6, 0, 20)),
+            Ok(vec![ids[1]])
+        );
+        assert_eq!(callers(&pool), vec![1, 5, 6]);
+
+        assert_eq!(
+            pool.insert(0.into(), proposal(1, 1, 0, 10)),
+            Err(MempoolReject::DuplicateTx)
+        );
+        assert_eq!(
+            pool.insert(0.into(), proposal(7, 1, 0, 8)),
+            Err(MempoolReject::DuplicateNonce)
+        );
+        let replacement = proposal(8, 1, 0, 11);
+        let replacement_id = replacement.tx.id();
+        assert_eq!(pool.insert(0.into(), replacement), Ok(vec![ids[0]]));
+        assert_eq!(callers(&pool), vec![5, 6, 1]);
+
+        let caller = Address::from(H160::from_low_u64_be(1));
+        let entry = pool.get_by_nonce(caller, 0.into()).unwrap();
+        assert_eq!(entry.tx_proposal.tx.id(), replacement_id);
+        assert_eq!(pool.get(replacement_id).unwrap().fee(), 11);
+        assert!(pool.remove(replacement_id).is_some());
+        assert!(pool.get_by_nonce(caller, 0.into()).is_none());
+        assert_eq!(pool.len(), 2);
+    }
+
+    #[test]
+    fn test_restore() {
+        let db = DB::load_test();
+        let mut pool = Mempool::open(&cfg(None, true), &db).unwrap();
+        let ids: Vec<_> = (0..4)
+            .map(|i| {
+                let p = proposal(i, i, 0, 1);
+                let id = p.tx.id();
+                pool.insert(i.into(), p).unwrap();
+                id
+            })
+            .collect();
+        pool.remove(ids[1]);
+        pool.flush().unwrap();
+
+        // the unflushed changes are lost.
+        pool.remove(ids[2]);
+        drop(pool);
+
+        let mut pool: Mempool<DummyTx> = Mempool::open(&cfg(None, true), &db).unwrap();
+        assert_eq!(callers(&pool), vec![0, 2, 3]);
+        assert_eq!(pool.get(ids[3]).unwrap().recv_height, 3.into());
+        pool.insert(4.into(), proposal(4, 4, 0, 1)).unwrap();
+        assert_eq!(pool.iter().last().unwrap().seq, 4);
+        pool.flush().unwrap();
+
+        let pool: Mempool<DummyTx> = Mempool::open(&cfg(Some(2), true), &db).unwrap();
+        assert_eq!(callers(&pool), vec![0, 2]);
+        let pool: Mempool<DummyTx> = Mempool::open(&cfg(None, true), &db).unwrap();
+        assert_eq!(callers(&pool), vec![0, 2]);
+
+        let pool: Mempool<DummyTx> = Mempool::open(&cfg(None, false), &db).unwrap();
+        assert!(pool.is_empty());
+
+        // the background writes finish before the pool is dropped.
+        let mut pool: Mempool<DummyTx> = Mempool::open(&cfg(None, true), &db).unwrap();
+        pool.remove(ids[0]);
+        pool.flush_in_background();
+        pool.flush_due();
+        drop(pool);
+        let pool: Mempool<DummyTx> = Mempool::open(&cfg(None, true), &db).unwrap();
+        assert_eq!(callers(&pool), vec![2]);
+    }
+}
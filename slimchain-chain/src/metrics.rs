@@ -0,0 +1,429 @@
+use crate::db::{DBPtr, DbStats};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use slimchain_common::{
+    basic::{BlockHeight, H256},
+    collections::HashMap,
+    error::Result,
+};
+use slimchain_utils::{
+    prometheus::{MetricsWriter, REGISTRY},
+    record_event,
+};
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex, MutexGuard,
+    },
+    time::{Duration, Instant},
+};
+use tokio::task::JoinHandle;
+
+/// Max number of in-flight txs tracked for the commit latency.
+/// Txs received beyond this are not measured.
+const MAX_TRACKED_TXS: usize = 1_000_000;
+
+/// Collect the storage statistics every this number of blocks on storage nodes.
+pub const DB_STATS_INTERVAL: u64 = 64;
+
+pub static CHAIN_METRICS: Lazy<ChainMetrics> = Lazy::new(ChainMetrics::new);
+
+#[derive(Debug, Default, Copy, Clone)]
+struct Summary {
+    count: u64,
+    sum: u64,
+    min: u64,
+    max: u64,
+}
+
+impl Summary {
+    fn add(&mut self, value: u64) {
+        if self.count == 0 || value < self.min {
+            self.min = value;
+        }
+        if value > self.max {
+            self.max = value;
+        }
+        self.count += 1;
+        self.sum += value;
+    }
+
+    fn report(&self) -> SummaryReport {
+        SummaryReport {
+            count: self.count,
+            mean: if self.count == 0 {
+                0.
+            } else {
+                self.sum as f64 / self.count as f64
+            },
+            min: self.min,
+            max: self.max,
+        }
+    }
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SummaryReport {
+    pub count: u64,
+    pub mean: f64,
+    pub min: u64,
+    pub max: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChainMetricsReport {
+    /// Time since the metrics were created or reset.
+    pub uptime_in_ms: u64,
+    pub committed_blocks: u64,
+    pub committed_txs: u64,
+    /// Committed txs per second over the uptime.
+    pub tps: f64,
+    /// Time from receiving a tx to committing it.
+    pub tx_latency_in_us: SummaryReport,
+    pub txs_per_block: SummaryReport,
+    /// Time between two consecutive block commits.
+    pub block_interval_in_us: SummaryReport,
+    /// The number of received txs which are not committed yet.
+    pub in_flight_txs: usize,
+    pub pending_queue_depth: usize,
+    pub max_pending_queue_depth: usize,
+    /// The block height at which `db_stats` was collected.
+    pub db_stats_height: Option<BlockHeight>,
+    pub db_stats: Option<DbStats>,
+}
+
+struct ChainMetricsInner {
+    start: Instant,
+    tx_begin: HashMap<H256, Instant>,
+    committed_blocks: u64,
+    committed_txs: u64,
+    tx_latency: Summary,
+    txs_per_block: Summary,
+    block_interval: Summary,
+    last_commit: Option<Instant>,
+    db_stats: Option<(BlockHeight, DbStats)>,
+}
+
+impl ChainMetricsInner {
+    fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            tx_begin: HashMap::new(),
+            committed_blocks: 0,
+            committed_txs: 0,
+            tx_latency: Summary::default(),
+            txs_per_block: Summary::default(),
+            block_interval: Summary::default(),
+            last_commit: None,
+            db_stats: None,
+        }
+    }
+}
+
+impl ChainMetricsReport {
+    /// Write the report as the `slimchain_chain_*` and `slimchain_db_*` series.
+    pub fn write_prometheus(&self, w: &mut MetricsWriter) {
+        w.counter(
+            "slimchain_chain_committed_blocks_total",
+            "The committed blocks.",
+            &[],
+            self.committed_blocks as f64,
+        );
+        w.counter(
+            "slimchain_chain_committed_txs_total",
+            "The committed txs.",
+            &[],
+            self.committed_txs as f64,
+        );
+        w.gauge(
+            "slimchain_chain_tps",
+            "The committed txs per second over the uptime.",
+            &[],
+            self.tps,
+        );
+        w.gauge(
+            "slimchain_chain_in_flight_txs",
+            "The received txs which are not committed yet.",
+            &[],
+            self.in_flight_txs as f64,
+        );
+        w.gauge(
+            "slimchain_chain_pending_queue_depth",
+            "The pending tx proposals.",
+            &[],
+            self.pending_queue_depth as f64,
+        );
+        w.gauge(
+            "slimchain_chain_max_pending_queue_depth",
+            "The max pending tx proposals over the uptime.",
+            &[],
+            self.max_pending_queue_depth as f64,
+        );
+
+        let summaries = [
+            (
+                "slimchain_chain_tx_latency_seconds",
+                "The time from receiving a tx to committing it.",
+                &self.tx_latency_in_us,
+                1e6,
+            ),
+            (
+                "slimchain_chain_block_interval_seconds",
+                "The time between two consecutive block commits.",
+                &self.block_interval_in_us,
+                1e6,
+            ),
+            (
+                "slimchain_chain_txs_per_block",
+                "The txs in each committed block.",
+                &self.txs_per_block,
+                1.,
+            ),
+        ];
+        for &(name, help, summary, unit) in summaries.iter() {
+            for &(stat, value) in [
+                ("mean", summary.mean),
+                ("min", summary.min as f64),
+                ("max", summary.max as f64),
+            ]
+            .iter()
+            {
+                w.gauge(name, help, &[("stat", stat)], value / unit);
+            }
+        }
+
+        let (height, stats) = match (self.db_stats_height, &self.db_stats) {
+            (Some(height), Some(stats)) => (height, stats),
+            _ => return,
+        };
+        w.gauge(
+            "slimchain_db_stats_height",
+            "The block height at which the storage statistics were collected.",
+            &[],
+            height.0 as f64,
+        );
+        let db_gauges = [
+            (
+                "slimchain_db_live_data_size_bytes",
+                "The size of the keys and the values in all columns.",
+                stats.live_data_size,
+            ),
+            (
+                "slimchain_db_total_file_size_bytes",
+                "The size of the database files.",
+                stats.total_file_size,
+            ),
+            (
+                "slimchain_db_blocks",
+                "The stored blocks.",
+                stats.block_count,
+            ),
+            ("slimchain_db_txs", "The stored txs.", stats.tx_count),
+            (
+                "slimchain_db_state_nodes",
+                "The stored state trie nodes.",
+                stats.state_node_count,
+            ),
+        ];
+        for &(name, help, value) in db_gauges.iter() {
+            w.gauge(name, help, &[], value as f64);
+        }
+        for (id, column) in stats.columns.iter().enumerate() {
+            let id = id.to_string();
+            w.gauge(
+                "slimchain_db_column_keys",
+                "The keys in each column.",
+                &[("column", &id)],
+                column.keys as f64,
+            );
+            w.gauge(
+                "slimchain_db_column_data_size_bytes",
+                "The size of the keys and the values in each column.",
+                &[("column", &id)],
+                column.data_size as f64,
+            );
+        }
+    }
+}
+
+/// In-process chain throughput and latency metrics.
+pub struct ChainMetrics {
+    inner: Mutex<ChainMetricsInner>,
+    pending_queue_depth: AtomicUsize,
+    max_pending_queue_depth: AtomicUsize,
+}
+
+impl ChainMetrics {
+    pub fn new() -> Self {
+        Self {
+            inner: Mutex::new(ChainMetricsInner::new()),
+            pending_queue_depth: AtomicUsize::new(0),
+            max_pending_queue_depth: AtomicUsize::new(0),
+        }
+    }
+
+    fn inner(&self) -> MutexGuard<'_, ChainMetricsInner> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Record that the tx `tx_id` is received.
+    pub fn record_tx_begin(&self, tx_id: H256) {
+        let mut inner = self.inner();
+        if inner.tx_begin.len() < MAX_TRACKED_TXS {
+            inner.tx_begin.entry(tx_id).or_insert_with(Instant::now);
+        }
+    }
+
+    /// Record that a block containing `tx_ids` is committed.
+    pub fn record_block_commit(&self, tx_ids: &[H256]) {
+        let now = Instant::now();
+        let mut inner = self.inner();
+        for tx_id in tx_ids {
+            if let Some(begin) = inner.tx_begin.remove(tx_id) {
+                inner.tx_latency.add((now - begin).as_micros() as u64);
+            }
+        }
+        if let Some(last_commit) = inner.last_commit.replace(now) {
+            inner
+                .block_interval
+                .add((now - last_commit).as_micros() as u64);
+        }
+        inner.txs_per_block.add(tx_ids.len() as u64);
+        inner.committed_blocks += 1;
+        inner.committed_txs += tx_ids.len() as u64;
+    }
+
+    /// Record the current number of pending tx proposals.
+    pub fn set_pending_queue_depth(&self, depth: usize) {
+        self.pending_queue_depth.store(depth, Ordering::Relaxed);
+        self.max_pending_queue_depth
+            .fetch_max(depth, Ordering::Relaxed);
+    }
+
+    /// Record the storage statistics collected at `height`.
+    pub fn set_db_stats(&self, height: BlockHeight, stats: DbStats) {
+        self.inner().db_stats = Some((height, stats));
+    }
+
+    pub fn snapshot(&self) -> ChainMetricsReport {
+        let inner = self.inner();
+        let uptime = inner.start.elapsed();
+        let tps = if uptime.as_secs_f64() > 0. {
+            inner.committed_txs as f64 / uptime.as_secs_f64()
+        } else {
+            0.
+        };
+        ChainMetricsReport {
+            uptime_in_ms: uptime.as_millis() as u64,
+            committed_blocks: inner.committed_blocks,
+            committed_txs: inner.committed_txs,
+            tps,
+            tx_latency_in_us: inner.tx_latency.report(),
+            txs_per_block: inner.txs_per_block.report(),
+            block_interval_in_us: inner.block_interval.report(),
+            in_flight_txs: inner.tx_begin.len(),
+            pending_queue_depth: self.pending_queue_depth.load(Ordering::Relaxed),
+            max_pending_queue_depth: self.max_pending_queue_depth.load(Ordering::Relaxed),
+            db_stats_height: inner.db_stats.as_ref().map(|(height, _)| *height),
+            db_stats: inner.db_stats.as_ref().map(|(_, stats)| stats.clone()),
+        }
+    }
+
+    pub fn reset(&self) {
+        *self.inner() = ChainMetricsInner::new();
+        self.pending_queue_depth.store(0, Ordering::Relaxed);
+        self.max_pending_queue_depth.store(0, Ordering::Relaxed);
+    }
+}
+
+impl Default for ChainMetrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Collect the storage statistics of `db` and record them in [`CHAIN_METRICS`].
+pub async fn update_db_stats(db: &DBPtr, height: BlockHeight) -> Result<()> {
+    let db = db.clone();
+    let stats = tokio::task::spawn_blocking(move || db.storage_stats()).await??;
+    record_event!("db_stats", "height": height.0, "live_data_size": stats.live_data_size, "total_file_size": stats.total_file_size);
+    CHAIN_METRICS.set_db_stats(height, stats);
+    Ok(())
+}
+
+/// Serve the snapshot of [`CHAIN_METRICS`] in the Prometheus [`REGISTRY`].
+pub fn register_chain_metrics_collector() {
+    REGISTRY.register_collector("chain", |w| CHAIN_METRICS.snapshot().write_prometheus(w));
+}
+
+/// Spawn a task recording the snapshot of [`CHAIN_METRICS`] as the `chain_metrics` event
+/// every `interval`.
+pub fn spawn_chain_metrics_reporter(interval: Duration) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        // The first tick completes immediately.
+        ticker.tick().await;
+        loop {
+            ticker.tick().await;
+            let report = CHAIN_METRICS.snapshot();
+            record_event!("chain_metrics", "report": report);
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use slimchain_utils::prometheus::parse_samples;
+
+    #[test]
+    fn test_chain_metrics() {
+        let metrics = ChainMetrics::new();
+        let tx1 = H256::from_low_u64_be(1);
+        let tx2 = H256::from_low_u64_be(2);
+        let tx3 = H256::from_low_u64_be(3);
+
+        metrics.record_tx_begin(tx1);
+        metrics.record_tx_begin(tx2);
+        metrics.set_pending_queue_depth(2);
+        assert_eq!(metrics.snapshot().in_flight_txs, 2);
+
+        // tx3 is not received by this node.
+        metrics.record_block_commit(&[tx1, tx3]);
+        metrics.set_pending_queue_depth(1);
+        metrics.record_block_commit(&[tx2]);
+        metrics.set_pending_queue_depth(0);
+
+        let report = metrics.snapshot();
+        assert_eq!(report.committed_blocks, 2);
+        assert_eq!(report.committed_txs, 3);
+        assert_eq!(report.tx_latency_in_us.count, 2);
+        assert_eq!(report.txs_per_block.count, 2);
+        assert_eq!(report.txs_per_block.min, 1);
+        assert_eq!(report.txs_per_block.max, 2);
+        assert!((report.txs_per_block.mean - 1.5).abs() < f64::EPSILON);
+        assert_eq!(report.block_interval_in_us.count, 1);
+        assert_eq!(report.in_flight_txs, 0);
+        assert_eq!(report.pending_queue_depth, 0);
+        assert_eq!(report.max_pending_queue_depth, 2);
+        assert!(report.db_stats.is_none());
+
+        metrics.set_db_stats(2.into(), DbStats::default());
+        let report = metrics.snapshot();
+        assert_eq!(report.db_stats_height, Some(2.into()));
+        assert_eq!(report.db_stats, Some(DbStats::default()));
+
+        let mut w = MetricsWriter::default();
+        report.write_prometheus(&mut w);
+        let samples = parse_samples(&w.render()).unwrap();
+        let find = |name: &str| samples.iter().find(|s| s.name == name).map(|s| s.value);
+        assert_eq!(Some(3.), find("slimchain_chain_committed_txs_total"));
+        assert_eq!(Some(2.), find("slimchain_db_stats_height"));
+
+        metrics.reset();
+        let report = metrics.snapshot();
+        assert_eq!(report.committed_blocks, 0);
+        assert_eq!(report.tx_latency_in_us, SummaryReport::default());
+        assert_eq!(report.max_pending_queue_depth, 0);
+    }
+}
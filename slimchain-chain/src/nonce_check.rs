@@ -0,0 +1,348 @@
+//! Check the nonces of the tx requests against the state at the intake of a storage node, so
+//! that the replayed txs and the ones far ahead of their callers are turned away before they
+//! take an execution slot.
+//!
+//! The check reads the latest committed state, which lags behind the txs in flight. It is thus
+//! only a filter: a tx passing it may still fail at execution, while a tx rejected by it leaves
+//! nothing behind on the node, so that it can be resubmitted once the state catches up.
+
+use crate::db::DBPtr;
+use slimchain_common::{
+    basic::{Address, BlockHeight, Nonce, H256},
+    collections::HashMap,
+    tx_req::SignedTxRequest,
+};
+use slimchain_merkle_trie::prelude::*;
+use slimchain_tx_state::trie_view::AccountTrieView;
+use slimchain_utils::prometheus::{Counter, REGISTRY};
+use std::{collections::BTreeMap, fmt, sync::Arc};
+
+/// Why the nonce of a tx request is rejected. `next` is the nonce expected next from the
+/// caller by the state checked.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum NonceRejection {
+    /// The nonce is already used by a committed tx.
+    Replay { nonce: Nonce, next: Nonce },
+    /// The nonce is more than the max gap ahead of the next one.
+    Gap { nonce: Nonce, next: Nonce },
+}
+
+impl NonceRejection {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Replay { .. } => "replay",
+            Self::Gap { .. } => "gap",
+        }
+    }
+}
+
+impl fmt::Display for NonceRejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Replay { nonce, next } => write!(
+                f,
+                "Replay: nonce {} is already used (next: {}).",
+                nonce, next
+            ),
+            Self::Gap { nonce, next } => write!(
+                f,
+                "Nonce {} is too far ahead (next: {}). Resubmit it later.",
+                nonce, next
+            ),
+        }
+    }
+}
+
+impl std::error::Error for NonceRejection {}
+
+/// Check `nonce` against `next`, the nonce expected next, allowing at most `max_gap` nonces
+/// in between.
+pub fn check_nonce(nonce: Nonce, next: Nonce, max_gap: u64) -> Result<(), NonceRejection> {
+    if nonce < next {
+        Err(NonceRejection::Replay { nonce, next })
+    } else if nonce > next.saturating_add(max_gap.into()) {
+        Err(NonceRejection::Gap { nonce, next })
+    } else {
+        Ok(())
+    }
+}
+
+pub struct NonceChecker {
+    db: DBPtr,
+    max_gap: u64,
+    replay_counter: Arc<Counter>,
+    gap_counter: Arc<Counter>,
+}
+
+impl NonceChecker {
+    pub fn new(db: DBPtr, max_gap: u64) -> Self {
+        let counter = |reason| {
+            REGISTRY.counter(
+                "slimchain_nonce_check_rejected_total",
+                "The tx requests rejected at the intake by their nonces.",
+                &[("reason", reason)],
+            )
+        };
+        Self {
+            db,
+            max_gap,
+            replay_counter: counter("replay"),
+            gap_counter: counter("gap"),
+        }
+    }
+
+    pub fn max_gap(&self) -> u64 {
+        self.max_gap
+    }
+
+    /// The nonce expected next from `caller` at `state_root`, or `None` if the account cannot
+    /// be read locally, e.g., out of the shard or pruned while being read.
+    pub fn next_nonce(&self, state_root: H256, caller: Address) -> Option<Nonce> {
+        let view = AccountTrieView::new(self.db.as_ref());
+        match read_trie_without_proof(&view, state_root, &caller) {
+            Ok(acc_data) => Some(acc_data.map_or_else(Nonce::zero, |d| d.nonce)),
+            Err(e) => {
+                debug!("Skip the nonce check of {}. Error: {}", caller, e);
+                None
+            }
+        }
+    }
+
+    /// Check the nonce of `req` against the state at `state_root`. The requests whose callers
+    /// cannot be read locally pass, and are left to the execution.
+    pub fn check(&self, state_root: H256, req: &SignedTxRequest) -> Result<(), NonceRejection> {
+        let next = match self.next_nonce(state_root, req.caller_address()) {
+            Some(next) => next,
+            None => return Ok(()),
+        };
+        check_nonce(req.input.nonce(), next, self.max_gap).map_err(|rejection| {
+            match rejection {
+                NonceRejection::Replay { .. } => self.replay_counter.inc(),
+                NonceRejection::Gap { .. } => self.gap_counter.inc(),
+            }
+            rejection
+        })
+    }
+}
+
+/// The tx requests taken back by [`ParkedTxReqs::release`].
+#[derive(Debug, Default)]
+pub struct ReleasedTxReqs {
+    /// The ones to be executed.
+    pub ready: Vec<SignedTxRequest>,
+    /// The ids of the ones dropped, with the reasons.
+    pub dropped: Vec<(H256, &'static str)>,
+}
+
+/// The tx requests too far ahead of their callers, parked until the state catches up instead
+/// of being rejected.
+pub struct ParkedTxReqs {
+    max_size: usize,
+    max_blocks: u64,
+    len: usize,
+    reqs: HashMap<Address, BTreeMap<Nonce, (BlockHeight, SignedTxRequest)>>,
+}
+
+impl ParkedTxReqs {
+    /// Park at most `max_size` requests, each for at most `max_blocks` blocks.
+    pub fn new(max_size: usize, max_blocks: u64) -> Self {
+        Self {
+            max_size,
+            max_blocks,
+            len: 0,
+            reqs: HashMap::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Park `req` received at `height`. It fails if it is full, or if the nonce of the caller
+    /// is already parked.
+    pub fn park(&mut self, height: BlockHeight, req: SignedTxRequest) -> bool {
+        if self.len >= self.max_size {
+            return false;
+        }
+        let parked = self.reqs.entry(req.caller_address()).or_default();
+        let nonce = req.input.nonce();
+        if parked.contains_key(&nonce) {
+            return false;
+        }
+        parked.insert(nonce, (height, req));
+        self.len += 1;
+        true
+    }
+
+    /// Release the requests within the gap of the state at `state_root` of block `height`.
+    /// The ones replayed meanwhile or parked for too long are dropped.
+    pub fn release(
+        &mut self,
+        checker: &NonceChecker,
+        height: BlockHeight,
+        state_root: H256,
+    ) -> ReleasedTxReqs {
+        let mut out = ReleasedTxReqs::default();
+        let max_blocks = self.max_blocks;
+        self.reqs.retain(|&caller, parked| {
+            let next = checker.next_nonce(state_root, caller);
+            let nonces: Vec<Nonce> = parked.keys().copied().collect();
+            for nonce in nonces {
+                let res = next.map_or(Ok(()), |next| check_nonce(nonce, next, checker.max_gap));
+                let expired = height.0.saturating_sub(parked[&nonce].0 .0) > max_blocks;
+                if matches!(res, Err(NonceRejection::Gap { .. })) && !expired {
+                    continue;
+                }
+                let (_, req) = parked.remove(&nonce).expect("The nonce is parked.");
+                match res {
+                    Ok(()) => out.ready.push(req),
+                    Err(NonceRejection::Replay { .. }) => out.dropped.push((req.id(), "replay")),
+                    Err(NonceRejection::Gap { .. }) => out.dropped.push((req.id(), "expired")),
+                }
+            }
+            !parked.is_empty()
+        });
+        self.len -= out.ready.len() + out.dropped.len();
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{Transaction, DB};
+    use slimchain_common::{basic::H160, ed25519::Keypair, rw_set::TxWriteData, tx_req::TxRequest};
+    use slimchain_tx_state::update_tx_state;
+
+    fn set_nonce(db: &DBPtr, root: H256, caller: Address, nonce: u64) -> H256 {
+        let mut writes = TxWriteData::default();
+        writes.add_nonce(caller, nonce.into());
+        let update = update_tx_state(db.as_ref(), root, &writes).unwrap();
+        let mut tx = Transaction::new();
+        tx.update_state(&update).unwrap();
+        db.write_sync(tx).unwrap();
+        update.root
+    }
+
+    fn tx_req(keypair: &Keypair, nonce: u64) -> SignedTxRequest {
+        TxRequest::Call {
+            nonce: nonce.into(),
+            address: Address::from(H160::from_low_u64_be(1)),
+            data: Vec::new(),
+        }
+        .sign(keypair, 0)
+    }
+
+    #[test]
+    fn test_nonce_check() {
+        let db = DB::load_test();
+        let keypair = Keypair::generate(&mut rand::thread_rng());
+        let caller = tx_req(&keypair, 0).caller_address();
+        let checker = NonceChecker::new(db.clone(), 4);
+        let replays = checker.replay_counter.get();
+        let gaps = checker.gap_counter.get();
+
+        // A new account expects nonce 0.
+        assert_eq!(
+            checker.next_nonce(H256::zero(), caller),
+            Some(Nonce::zero())
+        );
+        assert!(checker.check(H256::zero(), &tx_req(&keypair, 0)).is_ok());
+        assert!(checker.check(H256::zero(), &tx_req(&keypair, 4)).is_ok());
+
+        let root = set_nonce(&db, H256::zero(), caller, 3);
+        for nonce in 0..3 {
+            assert_eq!(
+                checker.check(root, &tx_req(&keypair, nonce)),
+                Err(NonceRejection::Replay {
+                    nonce: nonce.into(),
+                    next: 3.into(),
+                })
+            );
+        }
+        for nonce in 3..=7 {
+            assert!(checker.check(root, &tx_req(&keypair, nonce)).is_ok());
+        }
+        let rejection = checker.check(root, &tx_req(&keypair, 8)).unwrap_err();
+        assert_eq!(rejection.as_str(), "gap");
+
+        assert!(checker.replay_counter.get() - replays >= 3);
+        assert!(checker.gap_counter.get() - gaps >= 1);
+
+        // The accounts not readable locally are left to the execution.
+        assert_eq!(checker.next_nonce(H256::repeat_byte(1), caller), None);
+        assert!(checker
+            .check(H256::repeat_byte(1), &tx_req(&keypair, 0))
+            .is_ok());
+    }
+
+    #[test]
+    fn test_nonce_check_race() {
+        let db = DB::load_test();
+        let keypair = Keypair::generate(&mut rand::thread_rng());
+        let caller = tx_req(&keypair, 0).caller_address();
+        let checker = NonceChecker::new(db.clone(), 2);
+
+        // The tx is checked against the state of block 1, while block 2 commits the txs before
+        // it. It is rejected as a gap, and stays resubmittable.
+        let root1 = set_nonce(&db, H256::zero(), caller, 1);
+        let root2 = set_nonce(&db, root1, caller, 3);
+        let req = tx_req(&keypair, 4);
+        assert_eq!(checker.check(root1, &req).unwrap_err().as_str(), "gap");
+        assert!(checker.check(root2, &req).is_ok());
+
+        // A tx committed by block 2 is not rejected as a replay by the stale state, and is
+        // left to the execution.
+        assert!(checker.check(root1, &tx_req(&keypair, 2)).is_ok());
+        assert_eq!(
+            checker
+                .check(root2, &tx_req(&keypair, 2))
+                .unwrap_err()
+                .as_str(),
+            "replay"
+        );
+    }
+
+    #[test]
+    fn test_parked_tx_reqs() {
+        let db = DB::load_test();
+        let keypair = Keypair::generate(&mut rand::thread_rng());
+        let caller = tx_req(&keypair, 0).caller_address();
+        let checker = NonceChecker::new(db.clone(), 2);
+        let mut parked = ParkedTxReqs::new(3, 2);
+
+        let root1 = set_nonce(&db, H256::zero(), caller, 1);
+        assert!(parked.park(1.into(), tx_req(&keypair, 5)));
+        assert!(parked.park(1.into(), tx_req(&keypair, 8)));
+        assert!(!parked.park(1.into(), tx_req(&keypair, 8)));
+        assert!(parked.park(1.into(), tx_req(&keypair, 9)));
+        assert!(!parked.park(1.into(), tx_req(&keypair, 10)));
+        assert_eq!(parked.len(), 3);
+
+        let out = parked.release(&checker, 1.into(), root1);
+        assert!(out.ready.is_empty() && out.dropped.is_empty());
+
+        // Nonce 5 is within the gap of block 2, while nonce 8 and 9 wait.
+        let root2 = set_nonce(&db, root1, caller, 3);
+        let out = parked.release(&checker, 2.into(), root2);
+        assert_eq!(out.ready, vec![tx_req(&keypair, 5)]);
+        assert!(out.dropped.is_empty());
+        assert_eq!(parked.len(), 2);
+
+        // Nonce 8 is used by another tx meanwhile, while nonce 9 is the next one.
+        let root4 = set_nonce(&db, root2, caller, 9);
+        let out = parked.release(&checker, 4.into(), root4);
+        assert_eq!(out.ready, vec![tx_req(&keypair, 9)]);
+        assert_eq!(out.dropped, vec![(tx_req(&keypair, 8).id(), "replay")]);
+        assert!(parked.is_empty());
+
+        assert!(parked.park(4.into(), tx_req(&keypair, 20)));
+        let out = parked.release(&checker, 7.into(), root4);
+        assert_eq!(out.dropped, vec![(tx_req(&keypair, 20).id(), "expired")]);
+        assert!(parked.is_empty());
+    }
+}
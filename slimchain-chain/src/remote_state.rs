@@ -0,0 +1,328 @@
+//! Fetch the state nodes missing from the local state on demand while executing a tx, e.g.,
+//! the accounts out of the shard of a storage node, instead of failing the tx.
+//!
+//! The txs are executed on the worker threads of [`TxEngine`](slimchain_tx_engine::TxEngine),
+//! which block on the async fetches. The fetches of a tx are bounded by a timeout each and a
+//! budget of nodes, so a tx waits at most `max_nodes_per_task` times the timeout.
+
+use futures::future::BoxFuture;
+use serde::Deserialize;
+use slimchain_common::{
+    basic::{AccountData, Address, StateValue, H256},
+    collections::HashMap,
+    digest::Digestible,
+    error::{bail, ensure, Context as _, Result},
+};
+use slimchain_tx_state::{TrieNode, TxStateView};
+use slimchain_utils::{record_event, serde::binary_decode};
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+use tokio::runtime::Handle;
+
+/// Return the encoded state nodes for the hashes, or `None` for the ones no peer has.
+pub type FetchStateNodesFn =
+    Arc<dyn Fn(Vec<H256>) -> BoxFuture<'static, Result<Vec<Option<Vec<u8>>>>> + Send + Sync>;
+
+/// The fetched state nodes, evicted in the order they are fetched.
+struct StateNodeCache {
+    capacity: usize,
+    nodes: HashMap<H256, Arc<Vec<u8>>>,
+    order: VecDeque<H256>,
+}
+
+impl StateNodeCache {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            nodes: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    fn get(&self, hash: &H256) -> Option<Arc<Vec<u8>>> {
+        self.nodes.get(hash).cloned()
+    }
+
+    fn insert(&mut self, hash: H256, bin: Arc<Vec<u8>>) {
+        if self.capacity == 0 || self.nodes.insert(hash, bin).is_some() {
+            return;
+        }
+        self.order.push_back(hash);
+        while self.order.len() > self.capacity {
+            if let Some(evicted) = self.order.pop_front() {
+                self.nodes.remove(&evicted);
+            }
+        }
+    }
+}
+
+/// Fetch the state nodes from the peers, shared by the state views of all the txs.
+pub struct RemoteStateFetcher {
+    runtime: Handle,
+    fetch_fn: FetchStateNodesFn,
+    timeout: Duration,
+    max_nodes_per_task: usize,
+    cache: Mutex<StateNodeCache>,
+}
+
+impl RemoteStateFetcher {
+    /// Create the fetcher running `fetch_fn` on the current tokio runtime.
+    ///
+    /// Each fetch waits at most `timeout`, and a tx fetches at most `max_nodes_per_task` nodes.
+    /// Up to `cache_size` nodes fetched are kept for the later txs.
+    pub fn new(
+        fetch_fn: FetchStateNodesFn,
+        timeout: Duration,
+        max_nodes_per_task: usize,
+        cache_size: usize,
+    ) -> Self {
+        Self {
+            runtime: Handle::current(),
+            fetch_fn,
+            timeout,
+            max_nodes_per_task,
+            cache: Mutex::new(StateNodeCache::new(cache_size)),
+        }
+    }
+
+    /// The state view of the tx `tx_id`, which reads `local` first.
+    pub fn state_view(
+        self: &Arc<Self>,
+        local: Arc<dyn TxStateView + Sync + Send>,
+        tx_id: H256,
+    ) -> RemoteFallbackStateView {
+        RemoteFallbackStateView {
+            local,
+            fetcher: self.clone(),
+            tx_id,
+            fetched: AtomicUsize::new(0),
+            cache_hits: AtomicUsize::new(0),
+        }
+    }
+
+    fn cached(&self, hash: &H256) -> Option<Arc<Vec<u8>>> {
+        self.cache
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(hash)
+    }
+
+    fn cache(&self, hash: H256, bin: Arc<Vec<u8>>) {
+        self.cache
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(hash, bin);
+    }
+
+    /// Block the calling thread until the node is fetched. It must not be called from within
+    /// the async runtime.
+    fn fetch_blocking(&self, hash: H256) -> Result<Vec<u8>> {
+        ensure!(
+            Handle::try_current().is_err(),
+            "Cannot fetch state node {} from within the async runtime.",
+            hash
+        );
+        let fut = (self.fetch_fn)(vec![hash]);
+        let mut nodes = self
+            .runtime
+            .block_on(tokio::time::timeout(self.timeout, fut))
+            .with_context(|| format!("Timeout to fetch state node {} from the peers.", hash))??;
+        ensure!(
+            nodes.len() == 1,
+            "Expect 1 state node from the peers, but got {}.",
+            nodes.len()
+        );
+        match nodes.pop().flatten() {
+            Some(bin) => Ok(bin),
+            None => bail!("No peer has state node {}.", hash),
+        }
+    }
+}
+
+/// Read the state from a local [`TxStateView`], and fetch the nodes it fails to read by
+/// [`RemoteStateFetcher`]. Each node fetched is checked against its hash.
+pub struct RemoteFallbackStateView {
+    local: Arc<dyn TxStateView + Sync + Send>,
+    fetcher: Arc<RemoteStateFetcher>,
+    tx_id: H256,
+    fetched: AtomicUsize,
+    cache_hits: AtomicUsize,
+}
+
+impl RemoteFallbackStateView {
+    /// The number of the nodes requested from the peers so far, which counts against the
+    /// budget.
+    pub fn fetched(&self) -> usize {
+        self.fetched.load(Ordering::Acquire)
+    }
+
+    fn remote_node<V>(&self, node_address: H256) -> Result<TrieNode<V>>
+    where
+        TrieNode<V>: Digestible + for<'de> Deserialize<'de>,
+    {
+        if let Some(bin) = self.fetcher.cached(&node_address) {
+            self.cache_hits.fetch_add(1, Ordering::AcqRel);
+            return binary_decode(&bin[..]);
+        }
+
+        let budget = self.fetcher.max_nodes_per_task;
+        ensure!(
+            self.fetched.fetch_add(1, Ordering::AcqRel) < budget,
+            "Tx {} exceeds the budget of {} state nodes fetched.",
+            self.tx_id,
+            budget
+        );
+        let bin = self.fetcher.fetch_blocking(node_address)?;
+        let node: TrieNode<V> = binary_decode(&bin[..])?;
+        ensure!(
+            node.to_digest() == node_address,
+            "Invalid state node {} from the peers.",
+            node_address
+        );
+        self.fetcher.cache(node_address, Arc::new(bin));
+        Ok(node)
+    }
+}
+
+impl TxStateView for RemoteFallbackStateView {
+    fn account_trie_node(&self, node_address: H256) -> Result<TrieNode<AccountData>> {
+        self.local.account_trie_node(node_address).or_else(|e| {
+            self.remote_node(node_address)
+                .with_context(|| format!("Failed to read it locally. Error: {}", e))
+        })
+    }
+
+    fn state_trie_node(
+        &self,
+        acc_address: Address,
+        node_address: H256,
+    ) -> Result<TrieNode<StateValue>> {
+        self.local
+            .state_trie_node(acc_address, node_address)
+            .or_else(|e| {
+                self.remote_node(node_address)
+                    .with_context(|| format!("Failed to read it locally. Error: {}", e))
+            })
+    }
+}
+
+impl Drop for RemoteFallbackStateView {
+    fn drop(&mut self) {
+        let fetched = self.fetched();
+        let cache_hits = self.cache_hits.load(Ordering::Acquire);
+        if fetched > 0 || cache_hits > 0 {
+            record_event!("tx_state_fetch", "tx_id": self.tx_id, "fetched": fetched, "cache_hits": cache_hits);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{Transaction, DB};
+    use futures::prelude::*;
+    use slimchain_common::{
+        basic::{Nonce, H160},
+        rw_set::TxWriteData,
+    };
+    use slimchain_merkle_trie::prelude::*;
+    use slimchain_tx_state::{trie_view::AccountTrieView, update_tx_state};
+    use slimchain_utils::serde::binary_encode;
+
+    #[test]
+    fn test_remote_fallback() {
+        let remote_db = DB::load_test();
+        let mut writes = TxWriteData::default();
+        let addrs: Vec<Address> = (1..=8)
+            .map(|i| Address::from(H160::from_low_u64_be(i)))
+            .collect();
+        for (i, &addr) in addrs.iter().enumerate() {
+            writes.add_nonce(addr, Nonce::from(i as u64 + 1));
+        }
+        let update = update_tx_state(remote_db.as_ref(), H256::zero(), &writes).unwrap();
+        let root = update.root;
+        let mut tx = Transaction::new();
+        tx.update_state(&update).unwrap();
+        remote_db.write_sync(tx).unwrap();
+
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let requests = Arc::new(AtomicUsize::new(0));
+        let fetch_fn: FetchStateNodesFn = {
+            let remote_db = remote_db.clone();
+            let requests = requests.clone();
+            Arc::new(move |hashes: Vec<H256>| {
+                requests.fetch_add(1, Ordering::SeqCst);
+                let nodes = hashes
+                    .into_iter()
+                    .map(|hash| remote_db.get_state_node_bin(hash))
+                    .collect::<Result<Vec<_>>>();
+                async move { nodes }.boxed()
+            })
+        };
+        let fetcher = {
+            let _guard = rt.enter();
+            Arc::new(RemoteStateFetcher::new(
+                fetch_fn,
+                Duration::from_secs(1),
+                64,
+                1024,
+            ))
+        };
+
+        let local_db = DB::load_test();
+        let view = fetcher.state_view(local_db.clone(), H256::repeat_byte(1));
+        for (i, &addr) in addrs.iter().enumerate() {
+            let acc = read_trie_without_proof(&AccountTrieView::new(&view), root, &addr)
+                .unwrap()
+                .unwrap();
+            assert_eq!(acc.nonce, Nonce::from(i as u64 + 1));
+        }
+        let fetched = view.fetched();
+        assert!(fetched > 0);
+        assert_eq!(requests.load(Ordering::SeqCst), fetched);
+
+        // The later txs read the cached nodes.
+        let view = fetcher.state_view(local_db.clone(), H256::repeat_byte(2));
+        read_trie_without_proof(&AccountTrieView::new(&view), root, &addrs[0]).unwrap();
+        assert_eq!(view.fetched(), 0);
+
+        // The fetches beyond the budget fail the reads.
+        let fetcher = {
+            let _guard = rt.enter();
+            Arc::new(RemoteStateFetcher::new(
+                Arc::new(|_hashes: Vec<H256>| async { Ok(vec![None]) }.boxed()),
+                Duration::from_secs(1),
+                1,
+                0,
+            ))
+        };
+        let view = fetcher.state_view(local_db.clone(), H256::repeat_byte(3));
+        assert!(view.account_trie_node(root).is_err());
+        assert!(view.account_trie_node(root).is_err());
+        assert_eq!(view.fetched(), 2);
+
+        // The forged nodes are rejected.
+        let forged = binary_encode(&remote_db.account_trie_node(root).unwrap()).unwrap();
+        let fetcher = {
+            let _guard = rt.enter();
+            Arc::new(RemoteStateFetcher::new(
+                Arc::new(move |_hashes: Vec<H256>| {
+                    let forged = forged.clone();
+                    async move { Ok(vec![Some(forged)]) }.boxed()
+                }),
+                Duration::from_secs(1),
+                64,
+                0,
+            ))
+        };
+        let view = fetcher.state_view(local_db, H256::repeat_byte(4));
+        assert!(view.account_trie_node(H256::repeat_byte(5)).is_err());
+        assert!(view.account_trie_node(root).is_ok());
+    }
+}
@@ -0,0 +1,383 @@
+//! Replay the committed blocks from the database of a storage node, to re-derive their state
+//! roots independently, e.g., to detect a corrupted database or an engine drifting from the
+//! one which executed the txs.
+//!
+//! Each tx is re-executed on the state it was executed on, i.e., at its own state root. The
+//! writes are then applied on the state of the parent block in the order of the block.
+
+use crate::{
+    block::BlockTrait,
+    db::DBPtr,
+    loader::{BlockLoaderTrait, TxLoaderTrait},
+};
+use serde::Deserialize;
+use slimchain_common::{
+    basic::{BlockHeight, H256},
+    digest::Digestible,
+    error::{ensure, Context as _, Result},
+    rw_set::TxWriteData,
+    tx::TxTrait,
+};
+use slimchain_tx_engine::{TxEngineWorker, TxTaskId};
+use slimchain_tx_state::{update_tx_state, TxStateView};
+use std::{fmt, ops::Range, sync::Arc};
+
+/// The state needed to replay a block is no longer in the database, e.g., on a node restored
+/// from a snapshot archive, which only carries the state at the height exported.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct PrunedState {
+    pub height: BlockHeight,
+    pub state_root: H256,
+}
+
+impl fmt::Display for PrunedState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Cannot replay block {}: the state at root {} is pruned from the database.",
+            self.height, self.state_root
+        )
+    }
+}
+
+impl std::error::Error for PrunedState {}
+
+/// The first place where the replay differs from what is stored.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum ReplayDivergence {
+    /// The tx body stored does not match the hash in the block.
+    TxBody {
+        idx: usize,
+        expect: H256,
+        actual: H256,
+    },
+    /// The tx fails to be re-executed.
+    TxExec {
+        idx: usize,
+        tx_id: H256,
+        error: String,
+    },
+    TxReads {
+        idx: usize,
+        tx_id: H256,
+        expect: H256,
+        actual: H256,
+    },
+    TxWrites {
+        idx: usize,
+        tx_id: H256,
+        expect: H256,
+        actual: H256,
+    },
+    TxGas {
+        idx: usize,
+        tx_id: H256,
+        expect: u64,
+        actual: u64,
+    },
+    StateRoot {
+        expect: H256,
+        actual: H256,
+    },
+}
+
+impl ReplayDivergence {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::TxBody { .. } => "tx_body",
+            Self::TxExec { .. } => "tx_exec",
+            Self::TxReads { .. } => "tx_reads",
+            Self::TxWrites { .. } => "tx_writes",
+            Self::TxGas { .. } => "tx_gas",
+            Self::StateRoot { .. } => "state_root",
+        }
+    }
+}
+
+impl fmt::Display for ReplayDivergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TxBody {
+                idx,
+                expect,
+                actual,
+            } => write!(
+                f,
+                "Tx #{} with invalid body (expect: {}, actual: {}).",
+                idx, expect, actual
+            ),
+            Self::TxExec { idx, tx_id, error } => {
+                write!(
+                    f,
+                    "Tx #{} ({}) failed to execute. Error: {}",
+                    idx, tx_id, error
+                )
+            }
+            Self::TxReads {
+                idx,
+                tx_id,
+                expect,
+                actual,
+            } => write!(
+                f,
+                "Tx #{} ({}) with different reads (expect: {}, actual: {}).",
+                idx, tx_id, expect, actual
+            ),
+            Self::TxWrites {
+                idx,
+                tx_id,
+                expect,
+                actual,
+            } => write!(
+                f,
+                "Tx #{} ({}) with different writes (expect: {}, actual: {}).",
+                idx, tx_id, expect, actual
+            ),
+            Self::TxGas {
+                idx,
+                tx_id,
+                expect,
+                actual,
+            } => write!(
+                f,
+                "Tx #{} ({}) with different gas (expect: {}, actual: {}).",
+                idx, tx_id, expect, actual
+            ),
+            Self::StateRoot { expect, actual } => write!(
+                f,
+                "Different state root (expect: {}, actual: {}).",
+                expect, actual
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ReplayReport {
+    pub height: BlockHeight,
+    pub tx_count: usize,
+    /// The state root stored in the block.
+    pub state_root: H256,
+    /// The state root re-derived, or `None` if the replay stops at a tx.
+    pub computed_state_root: Option<H256>,
+    pub divergence: Option<ReplayDivergence>,
+}
+
+impl ReplayReport {
+    pub fn is_ok(&self) -> bool {
+        self.divergence.is_none()
+    }
+}
+
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct ReplayRangeReport {
+    pub blocks: u64,
+    pub txs: usize,
+    /// The report of the first block diverged, after which the replay stops.
+    pub divergence: Option<ReplayReport>,
+}
+
+fn ensure_state(db: &DBPtr, height: BlockHeight, state_root: H256) -> Result<()> {
+    if state_root.is_zero() || db.get_state_node_bin(state_root)?.is_some() {
+        Ok(())
+    } else {
+        Err(PrunedState { height, state_root }.into())
+    }
+}
+
+/// Replay the block at `height` from `db` by `executor`, and report the first divergence.
+///
+/// It fails with [`PrunedState`] if the state the block is built on is missing.
+#[tracing::instrument(level = "debug", skip(db, executor), err)]
+pub fn replay_block<Block, Tx, ExecTx>(
+    db: &DBPtr,
+    height: BlockHeight,
+    executor: &dyn TxEngineWorker<Output = ExecTx>,
+) -> Result<ReplayReport>
+where
+    Block: BlockTrait + for<'de> Deserialize<'de>,
+    Tx: TxTrait + for<'de> Deserialize<'de>,
+    ExecTx: TxTrait,
+{
+    ensure!(!height.is_zero(), "Cannot replay the genesis block.");
+    let block: Block = db.get_block(height)?;
+    let prev_block: Block = db.get_block(height.prev_height())?;
+    ensure_state(db, height, prev_block.state_root())?;
+
+    let tx_list = block.tx_list();
+    let mut report = ReplayReport {
+        height,
+        tx_count: tx_list.len(),
+        state_root: block.state_root(),
+        computed_state_root: None,
+        divergence: None,
+    };
+
+    let state_view: Arc<dyn TxStateView + Sync + Send> = db.clone();
+    let mut writes = TxWriteData::default();
+    for (idx, &tx_hash) in tx_list.iter().enumerate() {
+        let tx: Tx = db.get_tx(tx_hash)?;
+        let tx_digest = tx.to_digest();
+        if tx_digest != tx_hash {
+            report.divergence = Some(ReplayDivergence::TxBody {
+                idx,
+                expect: tx_hash,
+                actual: tx_digest,
+            });
+            return Ok(report);
+        }
+
+        ensure_state(db, height, tx.tx_state_root())?;
+        let tx_id = tx.id();
+        let output = match executor.reexecute(
+            TxTaskId::next_id(),
+            tx.tx_block_height(),
+            state_view.clone(),
+            tx.tx_state_root(),
+            tx.tx_caller(),
+            tx.tx_input().clone(),
+        ) {
+            Ok(output) => output,
+            Err(e) => {
+                report.divergence = Some(ReplayDivergence::TxExec {
+                    idx,
+                    tx_id,
+                    error: format!("{:?}", e),
+                });
+                return Ok(report);
+            }
+        };
+
+        let (expect, actual) = (tx.tx_reads().to_digest(), output.tx_reads().to_digest());
+        if expect != actual {
+            report.divergence = Some(ReplayDivergence::TxReads {
+                idx,
+                tx_id,
+                expect,
+                actual,
+            });
+            return Ok(report);
+        }
+        let (expect, actual) = (tx.tx_writes().to_digest(), output.tx_writes().to_digest());
+        if expect != actual {
+            report.divergence = Some(ReplayDivergence::TxWrites {
+                idx,
+                tx_id,
+                expect,
+                actual,
+            });
+            return Ok(report);
+        }
+        if tx.gas_used() != output.gas_used() {
+            report.divergence = Some(ReplayDivergence::TxGas {
+                idx,
+                tx_id,
+                expect: tx.gas_used(),
+                actual: output.gas_used(),
+            });
+            return Ok(report);
+        }
+
+        writes.merge(output.tx_writes());
+    }
+
+    let computed_state_root = update_tx_state(db.as_ref(), prev_block.state_root(), &writes)
+        .with_context(|| format!("Failed to apply the writes of block {}.", height))?
+        .root;
+    report.computed_state_root = Some(computed_state_root);
+    if computed_state_root != report.state_root {
+        report.divergence = Some(ReplayDivergence::StateRoot {
+            expect: report.state_root,
+            actual: computed_state_root,
+        });
+    }
+    Ok(report)
+}
+
+/// Replay the blocks within `range` in order by [`replay_block`], until the first divergence.
+/// `progress` is called with the report of each block replayed.
+pub fn replay_range<Block, Tx, ExecTx>(
+    db: &DBPtr,
+    range: Range<BlockHeight>,
+    executor: &dyn TxEngineWorker<Output = ExecTx>,
+    mut progress: impl FnMut(&ReplayReport),
+) -> Result<ReplayRangeReport>
+where
+    Block: BlockTrait + for<'de> Deserialize<'de>,
+    Tx: TxTrait + for<'de> Deserialize<'de>,
+    ExecTx: TxTrait,
+{
+    let mut summary = ReplayRangeReport::default();
+    for height in range.start.0..range.end.0 {
+        let report = replay_block::<Block, Tx, ExecTx>(db, height.into(), executor)?;
+        progress(&report);
+        summary.blocks += 1;
+        summary.txs += report.tx_count;
+        if !report.is_ok() {
+            summary.divergence = Some(report);
+            break;
+        }
+    }
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        consensus::raft::Block,
+        db::{Transaction, DB},
+        test_utils::next_test_block,
+    };
+    use slimchain_common::{ed25519::Keypair, tx::SignedTx};
+    use slimchain_tx_engine_simple::SimpleTxEngineWorker;
+
+    #[test]
+    fn test_replay_empty_blocks() {
+        let db = DB::load_test();
+        let executor = SimpleTxEngineWorker::new(Keypair::generate(&mut rand::thread_rng()));
+        let genesis_state_root = Block::genesis_block().state_root();
+
+        // Block 1 keeps the state, while block 2 claims a state never written.
+        let mut prev_blk = Block::genesis_block();
+        for &state_root in &[genesis_state_root, H256::repeat_byte(1)] {
+            let blk = next_test_block(&prev_blk, state_root);
+            let mut tx = Transaction::new();
+            tx.insert_block(&blk).unwrap();
+            db.write_sync(tx).unwrap();
+            prev_blk = blk;
+        }
+
+        let mut replayed = Vec::new();
+        let summary = replay_range::<Block, SignedTx, _>(&db, 1.into()..3.into(), &executor, |r| {
+            replayed.push(r.height)
+        })
+        .unwrap();
+        assert_eq!(replayed, vec![BlockHeight::from(1), BlockHeight::from(2)]);
+        assert_eq!(summary.blocks, 2);
+        let report = summary.divergence.unwrap();
+        assert_eq!(report.height, BlockHeight::from(2));
+        assert_eq!(
+            report.divergence,
+            Some(ReplayDivergence::StateRoot {
+                expect: H256::repeat_byte(1),
+                actual: genesis_state_root,
+            })
+        );
+
+        // Block 3 is built on the state of block 2, which is not in the database.
+        let blk = next_test_block(&prev_blk, prev_blk.state_root());
+        let mut tx = Transaction::new();
+        tx.insert_block(&blk).unwrap();
+        db.write_sync(tx).unwrap();
+        let err = replay_block::<Block, SignedTx, _>(&db, 3.into(), &executor).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<PrunedState>(),
+            Some(&PrunedState {
+                height: 3.into(),
+                state_root: H256::repeat_byte(1),
+            })
+        );
+        assert!(replay_block::<Block, SignedTx, _>(&db, 0.into(), &executor).is_err());
+    }
+}
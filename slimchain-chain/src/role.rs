@@ -10,6 +10,7 @@ pub enum Role {
     Client,
     Miner,
     Storage(ShardId),
+    Observer,
 }
 
 impl Default for Role {
@@ -29,6 +30,7 @@ impl<'de> Deserialize<'de> for Role {
             Client,
             Miner,
             Storage,
+            Observer,
         }
 
         impl Default for RoleType {
@@ -77,6 +79,20 @@ impl<'de> Deserialize<'de> for Role {
 
                 Ok(Self::Miner)
             }
+            RoleType::Observer => {
+                if data.shard_id.is_some() {
+                    return Err(SerdeError::custom(
+                        "Field shard_id is only valid for storage node.",
+                    ));
+                }
+                if data.shard_total.is_some() {
+                    return Err(SerdeError::custom(
+                        "Field shard_total is only valid for storage node.",
+                    ));
+                }
+
+                Ok(Self::Observer)
+            }
             RoleType::Storage => match (data.shard_id, data.shard_total) {
                 (Some(id), Some(total)) => Ok(Self::Storage(ShardId::new(id, total))),
                 (None, None) => Ok(Self::Storage(ShardId::default())),
@@ -93,6 +109,7 @@ impl fmt::Display for Role {
             Self::Client => write!(f, "Client"),
             Self::Miner => write!(f, "Miner"),
             Self::Storage(ShardId { id, total }) => write!(f, "Storage-{}-{}", id, total),
+            Self::Observer => write!(f, "Observer"),
         }
     }
 }
@@ -106,6 +123,7 @@ impl Role {
         match input {
             "Client" => return Ok(Self::Client),
             "Miner" => return Ok(Self::Miner),
+            "Observer" => return Ok(Self::Observer),
             _ => {}
         }
 
@@ -139,6 +157,15 @@ mod tests {
         };
         assert_eq!(Role::Miner, Config::from_toml(input).get("role").unwrap());
 
+        let input = toml::toml! {
+            [role]
+            role = "observer"
+        };
+        assert_eq!(
+            Role::Observer,
+            Config::from_toml(input).get("role").unwrap()
+        );
+
         let input = toml::toml! {
             [role]
             role = "storage"
@@ -167,6 +194,14 @@ mod tests {
         };
         assert!(Config::from_toml(input).get::<Role>("role").is_err());
 
+        let input = toml::toml! {
+            [role]
+            role = "observer"
+            shard_id = 1
+            shard_total = 2
+        };
+        assert!(Config::from_toml(input).get::<Role>("role").is_err());
+
         let input = toml::toml! {
             [role]
             role = "storage"
@@ -190,6 +225,8 @@ mod tests {
         assert_eq!(role, Role::from_user_agent(&role.to_user_agent()).unwrap());
         let role = Role::Storage(ShardId::default());
         assert_eq!(role, Role::from_user_agent(&role.to_user_agent()).unwrap());
+        let role = Role::Observer;
+        assert_eq!(role, Role::from_user_agent(&role.to_user_agent()).unwrap());
         assert!(Role::from_user_agent("").is_err());
         assert!(Role::from_user_agent("foo").is_err());
         assert!(Role::from_user_agent("Storage").is_err());
@@ -7,7 +7,7 @@ use crate::{
 };
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use slimchain_common::{
-    basic::{BlockHeight, ShardId, H256},
+    basic::{BlockHeight, ShardId},
     error::{Context as _, Result},
 };
 use slimchain_tx_state::{InShardData, OutShardData, StorageTxTrie, TxTrie, TxTrieTrait};
@@ -52,11 +52,12 @@ impl<Block: BlockTrait, TxTrie: TxTrieTrait> Snapshot<Block, TxTrie> {
     }
 
     pub fn get_block(&self, height: BlockHeight) -> Option<&Block> {
-        let idx = height - self.access_map.oldest_block_height();
-        if idx.is_negative() {
+        let oldest_height = self.access_map.oldest_block_height();
+        if height < oldest_height {
             None
         } else {
-            self.recent_blocks.get(idx.0 as usize)
+            self.recent_blocks
+                .get(height.distance(oldest_height) as usize)
         }
     }
 
@@ -111,11 +112,8 @@ impl<Block: BlockTrait + for<'de> Deserialize<'de>> Snapshot<Block, TxTrie> {
             Ok(Self::new(recent_blocks, tx_trie, access_map))
         } else {
             let genesis_block = Block::genesis_block();
-            Ok(Self::genesis_snapshot(
-                Default::default(),
-                genesis_block,
-                state_len,
-            ))
+            let tx_trie = TxTrie::from_root_hash(genesis_block.state_root());
+            Ok(Self::genesis_snapshot(tx_trie, genesis_block, state_len))
         }
     }
 }
@@ -197,12 +195,12 @@ impl<Block: BlockTrait + for<'de> Deserialize<'de>> Snapshot<Block, StorageTxTri
             assert_eq!(height, access_map.latest_block_height());
             Ok(Self::new(recent_blocks, tx_trie, access_map))
         } else {
+            let genesis_block = Block::genesis_block();
             let tx_trie = StorageTxTrie::new(
                 shard_id,
-                InShardData::new(db.clone(), H256::zero()),
+                InShardData::new(db.clone(), genesis_block.state_root()),
                 OutShardData::default(),
             );
-            let genesis_block = Block::genesis_block();
             Ok(Self::genesis_snapshot(tx_trie, genesis_block, state_len))
         }
     }
@@ -218,7 +216,7 @@ pub fn load_recent_blocks<Block: BlockTrait + for<'de> Deserialize<'de>>(
     while height.0 > 0 && out.len() < state_len {
         let blk = db.get_block(height)?;
         out.push_front(blk);
-        height.0 -= 1;
+        height = height.prev_height();
     }
 
     if out.len() < state_len {
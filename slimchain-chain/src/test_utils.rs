@@ -0,0 +1,140 @@
+use crate::{
+    block::{BlockTrait, BlockTxList},
+    consensus::raft::Block,
+    db::{Transaction, DB},
+};
+use serde::{Deserialize, Serialize};
+use slimchain_common::{
+    basic::{Address, BlockHeight, H160, H256},
+    digest::Digestible,
+    error::Result,
+    rw_set::{TxReadSet, TxWriteData},
+    tx::{RawTx, TxTrait},
+    tx_req::TxRequest,
+};
+use slimchain_tx_state::{update_tx_state, TxProposal};
+
+/// A tx of the unit tests, identified by `id`, which is also its digest.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub(crate) struct DummyTx {
+    pub(crate) id: u64,
+    pub(crate) caller: Address,
+    pub(crate) input: TxRequest,
+    pub(crate) reads: TxReadSet,
+    pub(crate) writes: TxWriteData,
+    pub(crate) gas_used: u64,
+    pub(crate) fee: u64,
+}
+
+impl DummyTx {
+    /// A tx creating an empty contract, sent by the address `caller` with `nonce`.
+    pub(crate) fn new(id: u64, caller: u64, nonce: u64) -> Self {
+        Self {
+            id,
+            caller: Address::from(H160::from_low_u64_be(caller)),
+            input: TxRequest::Create {
+                nonce: nonce.into(),
+                code: Default::default(),
+            },
+            reads: Default::default(),
+            writes: Default::default(),
+            gas_used: 0,
+            fee: 0,
+        }
+    }
+
+    pub(crate) fn with_fee(mut self, fee: u64) -> Self {
+        self.fee = fee;
+        self
+    }
+
+    pub(crate) fn into_proposal(self) -> TxProposal<Self> {
+        TxProposal::new(self, Default::default())
+    }
+}
+
+impl Default for DummyTx {
+    fn default() -> Self {
+        Self::new(0, 0, 0)
+    }
+}
+
+impl Digestible for DummyTx {
+    fn to_digest(&self) -> H256 {
+        H256::from_low_u64_be(self.id)
+    }
+}
+
+impl TxTrait for DummyTx {
+    fn id(&self) -> H256 {
+        self.to_digest()
+    }
+    fn tx_caller(&self) -> Address {
+        self.caller
+    }
+    fn tx_input(&self) -> &TxRequest {
+        &self.input
+    }
+    fn tx_block_height(&self) -> BlockHeight {
+        unreachable!();
+    }
+    fn tx_state_root(&self) -> H256 {
+        unreachable!();
+    }
+    fn tx_reads(&self) -> &TxReadSet {
+        &self.reads
+    }
+    fn tx_writes(&self) -> &TxWriteData {
+        &self.writes
+    }
+    fn gas_used(&self) -> u64 {
+        self.gas_used
+    }
+    fn fee(&self) -> u64 {
+        self.fee
+    }
+    fn verify_sig(&self) -> Result<()> {
+        unreachable!();
+    }
+}
+
+/// The block after `prev_blk` with `state_root` and no tx.
+pub(crate) fn next_test_block(prev_blk: &Block, state_root: H256) -> Block {
+    let mut blk = prev_blk.clone();
+    let header = blk.block_header_mut();
+    header.height = prev_blk.block_height().next_height();
+    header.prev_blk_hash = prev_blk.to_digest();
+    header.tx_list = BlockTxList::default();
+    header.state_root = state_root;
+    blk
+}
+
+/// Build the block after `prev_blk` with `writes` applied to its state and the tx bodies `txs`,
+/// along with the transaction committing it and the latest block header like a storage node
+/// does. More can be added to the transaction before it is written.
+pub(crate) fn test_block_tx(
+    db: &DB,
+    prev_blk: &Block,
+    writes: &TxWriteData,
+    txs: &[RawTx],
+) -> (Block, Transaction) {
+    let update = update_tx_state(db, prev_blk.state_root(), writes).unwrap();
+    let mut blk = next_test_block(prev_blk, update.root);
+    blk.block_header_mut().tx_list = BlockTxList(txs.iter().map(|tx| tx.to_digest()).collect());
+
+    let mut tx = Transaction::new();
+    tx.update_state(&update).unwrap();
+    for tx_body in txs {
+        tx.insert_tx(tx_body.to_digest(), tx_body).unwrap();
+    }
+    tx.insert_block(&blk).unwrap();
+    tx.insert_latest_block_header(blk.block_header()).unwrap();
+    (blk, tx)
+}
+
+/// Commit the block after `prev_blk` with `writes` applied to its state, see [`test_block_tx`].
+pub(crate) fn commit_test_block(db: &DB, prev_blk: &Block, writes: &TxWriteData) -> Block {
+    let (blk, tx) = test_block_tx(db, prev_blk, writes, &[]);
+    db.write_sync(tx).unwrap();
+    blk
+}
@@ -1,36 +1,85 @@
 use crate::{
     behavior::{
-        commit_block, commit_block_storage_node, propose_block, verify_block, TxExecuteStream,
+        commit_block, commit_block_storage_node, commit_blocks_storage_node, propose_block,
+        verify_block, verify_tx_gas, TxExecuteStream,
     },
-    block_proposal::BlockProposal,
-    config::{ChainConfig, MinerConfig},
+    block::{BlockTrait, PrunedBlock},
+    block_proposal::{BlockProposal, BlockProposalTrie},
+    config::{ChainConfig, MinerConfig, ProposerKeypair, ProposerPeerConfig, StorageMode},
     conflict_check::ConflictCheck,
     consensus::{
         raft::{create_new_block, verify_consensus, Block},
         Consensus,
     },
-    db::DB,
-    latest::LatestTxCount,
-    snapshot::Snapshot,
+    db::{read_pool::DEFAULT_READ_THREADS, BlockPruned, DBPtr, DbBackend, BLOCK_DB_COL, DB},
+    latest::{LatestBlockHeader, LatestTxCount},
+    loader::BlockLoaderTrait,
+    replay::replay_range,
+    snapshot::{load_recent_blocks, Snapshot},
 };
 use futures::{channel::mpsc::unbounded, prelude::*};
 use rand::SeedableRng;
 use slimchain_common::{
-    basic::{ShardId, U256},
+    basic::{Address, Nonce, ShardId, StateKey, StateValue, H160, H256, U256},
+    digest::Digestible,
     ed25519::Keypair,
-    tx::SignedTx,
+    error::Result,
+    rw_set::TxWriteData,
+    tx::{RawTx, SignedTx},
     tx_req::{caller_address_from_pk, TxRequest},
 };
 use slimchain_tx_engine::TxEngine;
 use slimchain_tx_engine_simple::SimpleTxEngineWorker;
-use slimchain_tx_state::{StorageTxTrie, TxTrie};
+use slimchain_tx_state::{update_tx_state, StorageTxTrie, TxStateReadContext, TxTrie};
 use slimchain_utils::{
     contract::{contract_address, Contract, Token},
     init_tracing_for_test,
 };
-use std::{path::PathBuf, time::Duration};
+use std::{
+    fs,
+    path::PathBuf,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+
+const TEST_BACKENDS: &[DbBackend] = &[DbBackend::Memory, DbBackend::RocksDb];
+
+/// The databases of one test on `backend`. The on-disk ones are created in a temp dir, which is
+/// removed once dropped.
+struct TestDBs {
+    backend: DbBackend,
+    dir: PathBuf,
+}
+
+impl TestDBs {
+    fn new(backend: DbBackend) -> Self {
+        static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+        let dir = std::env::temp_dir().join(format!(
+            "slimchain-test-{}-{}",
+            std::process::id(),
+            NEXT_ID.fetch_add(1, Ordering::SeqCst)
+        ));
+        Self { backend, dir }
+    }
+
+    fn open(&self, name: &str) -> DBPtr {
+        match self.backend {
+            DbBackend::Memory => DB::open_memory(DEFAULT_READ_THREADS),
+            DbBackend::RocksDb => {
+                fs::create_dir_all(&self.dir).unwrap();
+                DB::open_or_create(&self.dir.join(name), false, true, DEFAULT_READ_THREADS).unwrap()
+            }
+        }
+    }
+}
 
-async fn test_chain_cycle(chain_cfg: &ChainConfig, miner_cfg: &MinerConfig) {
+impl Drop for TestDBs {
+    fn drop(&mut self) {
+        fs::remove_dir_all(&self.dir).ok();
+    }
+}
+
+async fn test_chain_cycle(backend: DbBackend, chain_cfg: &ChainConfig, miner_cfg: &MinerConfig) {
     let contract_file = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
         .parent()
         .unwrap()
@@ -47,9 +96,10 @@ async fn test_chain_cycle(chain_cfg: &ChainConfig, miner_cfg: &MinerConfig) {
         Box::new(SimpleTxEngineWorker::new(Keypair::generate(&mut rng)))
     });
 
-    let client_db = DB::load_test();
-    let storage_db = DB::load_test();
-    let miner_db = DB::load_test();
+    let dbs = TestDBs::new(backend);
+    let client_db = dbs.open("client.db");
+    let storage_db = dbs.open("storage.db");
+    let miner_db = dbs.open("miner.db");
 
     let mut client_snapshot =
         Snapshot::<Block, TxTrie>::load_from_db(&client_db, chain_cfg.state_len).unwrap();
@@ -92,13 +142,15 @@ async fn test_chain_cycle(chain_cfg: &ChainConfig, miner_cfg: &MinerConfig) {
     }
 
     for tx_req in tx_reqs {
-        let signed_tx_req = tx_req.sign(&keypair);
+        let signed_tx_req = tx_req.sign(&keypair, 0);
         req_tx.send(signed_tx_req).await.unwrap();
         let blk_proposal = propose_block(
             chain_cfg,
             miner_cfg,
             &mut miner_snapshot,
             &mut tx_rx,
+            &mut Vec::new(),
+            false,
             create_new_block,
         )
         .await
@@ -120,8 +172,12 @@ async fn test_chain_cycle(chain_cfg: &ChainConfig, miner_cfg: &MinerConfig) {
         )
         .await
         .unwrap();
+        if chain_cfg.full_validation {
+            verify_tx_gas(&storage_db, &blk_proposal).await.unwrap();
+        }
 
         commit_block(
+            chain_cfg,
             &blk_proposal,
             &miner_db,
             &miner_blk_latest,
@@ -130,6 +186,7 @@ async fn test_chain_cycle(chain_cfg: &ChainConfig, miner_cfg: &MinerConfig) {
         .await
         .unwrap();
         commit_block(
+            chain_cfg,
             &blk_proposal,
             &client_db,
             &client_blk_latest,
@@ -137,7 +194,17 @@ async fn test_chain_cycle(chain_cfg: &ChainConfig, miner_cfg: &MinerConfig) {
         )
         .await
         .unwrap();
+        if chain_cfg.index_tx_location {
+            let blk = blk_proposal.get_block();
+            for (idx, &tx_hash) in blk.tx_list().iter().enumerate() {
+                assert_eq!(
+                    client_db.get_tx_location(tx_hash).unwrap(),
+                    Some((blk.block_height(), idx))
+                );
+            }
+        }
         commit_block_storage_node(
+            chain_cfg,
             &blk_proposal,
             &storage_update,
             &storage_db,
@@ -146,9 +213,34 @@ async fn test_chain_cycle(chain_cfg: &ChainConfig, miner_cfg: &MinerConfig) {
         )
         .await
         .unwrap();
+        if chain_cfg.index_tx_location {
+            let blk = blk_proposal.get_block();
+            for (idx, &tx_hash) in blk.tx_list().iter().enumerate() {
+                assert_eq!(
+                    storage_db.get_tx_location(tx_hash).unwrap(),
+                    Some((blk.block_height(), idx))
+                );
+            }
+        }
     }
 
-    let client2_db = DB::load_test();
+    let replay_executor = SimpleTxEngineWorker::new(Keypair::generate(&mut rng));
+    let mut replayed = 0;
+    let replay = replay_range::<Block, SignedTx, _>(
+        &storage_db,
+        1.into()..7.into(),
+        &replay_executor,
+        |report| {
+            assert_eq!(report.computed_state_root, Some(report.state_root));
+            replayed += 1;
+        },
+    )
+    .unwrap();
+    assert_eq!(replayed, 6);
+    assert_eq!(replay.txs, 6);
+    assert!(replay.divergence.is_none());
+
+    let client2_db = dbs.open("client2.db");
     let mut client2_snapshot =
         Snapshot::<Block, TxTrie>::load_from_db(&client2_db, chain_cfg.state_len).unwrap();
     let client2_blk_latest = client2_snapshot.to_latest_block_header();
@@ -165,6 +257,7 @@ async fn test_chain_cycle(chain_cfg: &ChainConfig, miner_cfg: &MinerConfig) {
         .await
         .unwrap();
         commit_block(
+            chain_cfg,
             &blk_proposal,
             &client_db,
             &client2_blk_latest,
@@ -211,17 +304,50 @@ async fn test_chain_cycle(chain_cfg: &ChainConfig, miner_cfg: &MinerConfig) {
     assert_eq!(client_tx_latest.get(), client2_tx_latest.get());
     assert_eq!(client_tx_latest.get(), miner_tx_latest.get());
     assert_eq!(client_tx_latest.get(), storage_tx_latest.get());
+
+    if chain_cfg.index_addresses {
+        let txs = storage_db
+            .get_txs_by_address(caller_address, 0.into(), usize::MAX)
+            .unwrap();
+        let heights: Vec<_> = txs.iter().map(|(h, _)| h.0).collect();
+        assert_eq!(heights, vec![1, 2, 3, 4, 5, 6]);
+        for (height, tx_hash) in &txs {
+            let blk: Block = storage_db.get_block(*height).unwrap();
+            assert_eq!(blk.tx_list().0, vec![*tx_hash]);
+        }
+
+        let page = storage_db
+            .get_txs_by_address(caller_address, 3.into(), 2)
+            .unwrap();
+        assert_eq!(&page[..], &txs[2..4]);
+        assert!(client_db
+            .get_txs_by_address(caller_address, 0.into(), usize::MAX)
+            .unwrap()
+            .is_empty());
+    }
 }
 
 #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
 async fn test1() {
     let _guard = init_tracing_for_test();
 
+    let proposer_keypair = Keypair::generate(&mut rand::thread_rng());
     let miner_cfg = MinerConfig {
         compress_trie: true,
         max_txs: 1,
+        max_block_gas: None,
         min_txs: 1,
         max_block_interval: Duration::from_millis(100),
+        max_tx_age_blocks: None,
+        nonce_ordering: false,
+        deterministic_assembly: false,
+        proposer_keypair: Some(ProposerKeypair(
+            Keypair::from_bytes(&proposer_keypair.to_bytes()).unwrap(),
+        )),
+        proposer_id: Some(1),
+        verify_threads: 0,
+        mempool: Default::default(),
+        cross_shard_timeout: Duration::from_secs(5),
     };
 
     for state_len in 1..=3 {
@@ -230,9 +356,29 @@ async fn test1() {
                 conflict_check,
                 state_len,
                 consensus: Consensus::Raft,
+                index_tx_location: true,
+                index_addresses: true,
+                checkpoint_interval: Some(2),
+                fast_sync: false,
+                verify_proposer: true,
+                proposer_keys: vec![proposer_keypair.public],
+                proposer_peers: vec![ProposerPeerConfig {
+                    peer_id: 1,
+                    public_key: proposer_keypair.public,
+                }],
+                tx_status_ttl: Duration::from_secs(60),
+                tx_sig: Default::default(),
+                full_validation: true,
+                db_read_threads: 4,
+                db_backend: DbBackend::default(),
+                disable_db_journal: false,
+                storage_mode: StorageMode::Full,
+                full_block_window: 1024,
             };
-            warn!(state_len, ?conflict_check);
-            test_chain_cycle(&chain_cfg, &miner_cfg).await;
+            for &backend in TEST_BACKENDS {
+                warn!(state_len, ?conflict_check, ?backend);
+                test_chain_cycle(backend, &chain_cfg, &miner_cfg).await;
+            }
         }
     }
 }
@@ -244,8 +390,17 @@ async fn test2() {
     let miner_cfg = MinerConfig {
         compress_trie: false,
         max_txs: 1,
+        max_block_gas: None,
         min_txs: 1,
         max_block_interval: Duration::from_millis(100),
+        max_tx_age_blocks: None,
+        nonce_ordering: false,
+        deterministic_assembly: false,
+        proposer_keypair: None,
+        proposer_id: None,
+        verify_threads: 0,
+        mempool: Default::default(),
+        cross_shard_timeout: Duration::from_secs(5),
     };
 
     for state_len in 1..=3 {
@@ -253,8 +408,248 @@ async fn test2() {
             conflict_check: ConflictCheck::SSI,
             state_len,
             consensus: Consensus::Raft,
+            index_tx_location: false,
+            index_addresses: false,
+            checkpoint_interval: None,
+            fast_sync: false,
+            verify_proposer: false,
+            proposer_keys: Vec::new(),
+            proposer_peers: Vec::new(),
+            tx_status_ttl: Duration::from_secs(60),
+            tx_sig: Default::default(),
+            full_validation: false,
+            db_read_threads: 4,
+            db_backend: DbBackend::default(),
+            disable_db_journal: false,
+            storage_mode: StorageMode::Full,
+            full_block_window: 1024,
         };
-        warn!(state_len);
-        test_chain_cycle(&chain_cfg, &miner_cfg).await;
+        for &backend in TEST_BACKENDS {
+            warn!(state_len, ?backend);
+            test_chain_cycle(backend, &chain_cfg, &miner_cfg).await;
+        }
     }
 }
+
+#[tokio::test]
+async fn test_commit_blocks_storage_node() {
+    let chain_cfg = ChainConfig {
+        conflict_check: ConflictCheck::SSI,
+        state_len: 2,
+        consensus: Consensus::Raft,
+        index_tx_location: true,
+        index_addresses: false,
+        checkpoint_interval: None,
+        fast_sync: false,
+        verify_proposer: false,
+        proposer_keys: Vec::new(),
+        proposer_peers: Vec::new(),
+        tx_status_ttl: Duration::from_secs(60),
+        tx_sig: Default::default(),
+        full_validation: false,
+        db_read_threads: 4,
+        db_backend: DbBackend::Memory,
+        disable_db_journal: false,
+        storage_mode: StorageMode::Full,
+        full_block_window: 1024,
+    };
+    let acc_addr = Address::from(H160::from_low_u64_be(1));
+    let key = StateKey::from(H256::from_low_u64_be(1));
+
+    // The blocks committed one by one, each overwriting the same key.
+    let seq_db = DB::load_test();
+    let seq_blk_latest = LatestBlockHeader::new_from_block(&Block::genesis_block());
+    let mut blk_proposals: Vec<(BlockProposal<Block, RawTx>, _)> = Vec::new();
+    let mut prev_blk = Block::genesis_block();
+    for i in 1..=6u64 {
+        let mut writes = TxWriteData::default();
+        writes.add_nonce(acc_addr, Nonce::from(i));
+        writes.add_value(acc_addr, key, StateValue::from(i));
+        let update = update_tx_state(seq_db.as_ref(), prev_blk.state_root(), &writes).unwrap();
+
+        let mut blk = prev_blk.clone();
+        blk.block_header_mut().height = i.into();
+        blk.block_header_mut().prev_blk_hash = prev_blk.to_digest();
+        blk.block_header_mut().state_root = update.root;
+        let blk_proposal = BlockProposal::new(
+            blk.clone(),
+            Vec::new(),
+            BlockProposalTrie::Trie(Default::default()),
+        );
+        commit_block_storage_node(
+            &chain_cfg,
+            &blk_proposal,
+            &update,
+            &seq_db,
+            &seq_blk_latest,
+            &LatestTxCount::new(0),
+        )
+        .await
+        .unwrap();
+        blk_proposals.push((blk_proposal, update));
+        prev_blk = blk;
+    }
+
+    let batch_db = DB::load_test();
+    let batch_blk_latest = LatestBlockHeader::new_from_block(&Block::genesis_block());
+    commit_blocks_storage_node(
+        &chain_cfg,
+        &blk_proposals,
+        &batch_db,
+        &batch_blk_latest,
+        &LatestTxCount::new(0),
+    )
+    .await
+    .unwrap();
+    assert_eq!(batch_blk_latest.get_height(), seq_blk_latest.get_height());
+    assert_eq!(
+        batch_db.get_latest_block_header().unwrap(),
+        seq_db.get_latest_block_header().unwrap()
+    );
+
+    let read = |db: &DBPtr, root: H256| -> Result<(Nonce, StateValue)> {
+        let mut read_ctx = TxStateReadContext::new(db.clone(), root);
+        Ok((
+            read_ctx.get_nonce(acc_addr)?,
+            read_ctx.get_value(acc_addr, key)?,
+        ))
+    };
+    // The states of the last `state_len` blocks are kept, and the ones before are not.
+    for (i, (blk_proposal, _)) in blk_proposals.iter().enumerate() {
+        let root = blk_proposal.get_block().state_root();
+        let expect = read(&seq_db, root).unwrap();
+        assert_eq!(
+            expect,
+            (Nonce::from(i as u64 + 1), StateValue::from(i as u64 + 1))
+        );
+        if i + chain_cfg.state_len >= blk_proposals.len() {
+            assert_eq!(read(&batch_db, root).unwrap(), expect);
+        } else {
+            assert!(read(&batch_db, root).is_err());
+        }
+    }
+    assert!(
+        batch_db.storage_stats().unwrap().state_node_count
+            < seq_db.storage_stats().unwrap().state_node_count
+    );
+}
+
+#[tokio::test]
+async fn test_commit_block_headers_mode() {
+    let mut chain_cfg = ChainConfig {
+        conflict_check: ConflictCheck::SSI,
+        state_len: 2,
+        consensus: Consensus::Raft,
+        index_tx_location: true,
+        index_addresses: false,
+        checkpoint_interval: None,
+        fast_sync: false,
+        verify_proposer: false,
+        proposer_keys: Vec::new(),
+        proposer_peers: Vec::new(),
+        tx_status_ttl: Duration::from_secs(60),
+        tx_sig: Default::default(),
+        full_validation: false,
+        db_read_threads: 4,
+        db_backend: DbBackend::Memory,
+        disable_db_journal: false,
+        storage_mode: StorageMode::Headers,
+        full_block_window: 4,
+    };
+
+    let full_db = DB::load_test();
+    let db = DB::load_test();
+    let blk_latest = LatestBlockHeader::new_from_block(&Block::genesis_block());
+    let mut blocks = Vec::new();
+    let mut prev_blk = Block::genesis_block();
+    for i in 1..=20u64 {
+        let mut blk = prev_blk.clone();
+        blk.block_header_mut().height = i.into();
+        blk.block_header_mut().prev_blk_hash = prev_blk.to_digest();
+        *blk.tx_list_mut() = (0..4).map(|j| H256::from_low_u64_be(i * 10 + j)).collect();
+        let blk_proposal: BlockProposal<Block, RawTx> = BlockProposal::new(
+            blk.clone(),
+            Vec::new(),
+            BlockProposalTrie::Trie(Default::default()),
+        );
+        commit_block(
+            &chain_cfg,
+            &blk_proposal,
+            &db,
+            &blk_latest,
+            &LatestTxCount::new(0),
+        )
+        .await
+        .unwrap();
+        let full_cfg = ChainConfig {
+            storage_mode: StorageMode::Full,
+            ..chain_cfg.clone()
+        };
+        commit_block(
+            &full_cfg,
+            &blk_proposal,
+            &full_db,
+            &LatestBlockHeader::new_from_block(&prev_blk),
+            &LatestTxCount::new(0),
+        )
+        .await
+        .unwrap();
+        // The stored blocks no longer grow once the window is full.
+        assert_eq!(db.storage_stats().unwrap().block_count, i.min(4));
+        blocks.push(blk.clone());
+        prev_blk = blk;
+    }
+    assert_eq!(full_db.storage_stats().unwrap().block_count, 20);
+    assert!(
+        db.storage_stats().unwrap().columns[BLOCK_DB_COL as usize].data_size
+            < full_db.storage_stats().unwrap().columns[BLOCK_DB_COL as usize].data_size
+    );
+    assert_eq!(db.get_pruned_height().unwrap(), 16.into());
+
+    for blk in &blocks {
+        let height = blk.block_height();
+        if height.0 <= 16 {
+            let err = BlockLoaderTrait::<Block>::get_block(db.as_ref(), height).unwrap_err();
+            assert_eq!(
+                err.downcast_ref::<BlockPruned>(),
+                Some(&BlockPruned { height })
+            );
+            assert_eq!(
+                db.get_pruned_block(height).unwrap(),
+                Some(PrunedBlock::from_block(blk))
+            );
+        } else {
+            let stored: Block = db.get_block(height).unwrap();
+            assert_eq!(&stored, blk);
+            assert!(db.get_pruned_block(height).unwrap().is_none());
+        }
+        assert_eq!(
+            db.get_block_height_by_hash(blk.to_digest()).unwrap(),
+            Some(height)
+        );
+    }
+    let recent_blocks = load_recent_blocks::<Block>(&db, 20.into(), chain_cfg.state_len).unwrap();
+    assert_eq!(
+        recent_blocks,
+        blocks[18..].iter().cloned().collect::<im::Vector<_>>()
+    );
+
+    // Widening the window keeps the blocks not pruned yet.
+    chain_cfg.full_block_window = 8;
+    let mut blk = prev_blk.clone();
+    blk.block_header_mut().height = 21.into();
+    blk.block_header_mut().prev_blk_hash = prev_blk.to_digest();
+    let blk_proposal: BlockProposal<Block, RawTx> =
+        BlockProposal::new(blk, Vec::new(), BlockProposalTrie::Trie(Default::default()));
+    commit_block(
+        &chain_cfg,
+        &blk_proposal,
+        &db,
+        &blk_latest,
+        &LatestTxCount::new(0),
+    )
+    .await
+    .unwrap();
+    assert_eq!(db.get_pruned_height().unwrap(), 16.into());
+    assert_eq!(db.storage_stats().unwrap().block_count, 5);
+}
@@ -0,0 +1,586 @@
+use crate::{
+    assemble::order_by_nonce,
+    mempool::{Mempool, MempoolReject},
+    metrics::CHAIN_METRICS,
+    tx_status::TX_STATUS,
+};
+use futures::{prelude::*, stream::Fuse};
+use slimchain_common::{
+    basic::{Address, BlockHeight, Nonce, H256},
+    collections::{HashMap, HashSet},
+    tx::TxTrait,
+};
+use slimchain_tx_state::TxProposal;
+use slimchain_utils::{backpressure::PipelineStage, record_event};
+use std::{
+    cmp::Reverse,
+    collections::VecDeque,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+/// A queue of pending tx proposals. Each tx proposal is tagged with the block height
+/// current when it was received, so that the stale ones can be purged.
+///
+/// The tx proposals are yielded in the order of their fees, from the highest, and then in the
+/// order of their arrivals.
+///
+/// If nonce ordering is enabled, tx proposals from the same caller are yielded in
+/// the order of their nonces. A tx proposal is held back until all lower nonces
+/// from the same caller have been committed or yielded for the block in progress. The nonce of
+/// the caller in the committed state is taken from the write sets of its tx proposals, and is
+/// advanced by [`PendingTxQueue::settle_popped`]. The tx proposals below it are dropped. Among
+/// the ones ready, a caller keeps the places of its tx proposals by the fees, which are filled in
+/// the order of the nonces.
+///
+/// The yielded tx proposals are kept until the block taking them is settled by
+/// [`PendingTxQueue::settle_popped`], or put back by [`PendingTxQueue::requeue_popped`] if the
+/// block fails. If a [`Mempool`] is attached, it admits the received tx proposals and keeps each
+/// one until it is settled.
+///
+/// If a [`PipelineStage`] is attached, the tx proposals leaving the queue are counted off it,
+/// while the producers count the ones they send.
+pub struct PendingTxQueue<Tx: TxTrait, S> {
+    inner: Fuse<S>,
+    pending: VecDeque<(BlockHeight, TxProposal<Tx>)>,
+    pending_dirty: bool,
+    current_height: BlockHeight,
+    nonce_ordering: bool,
+    held: Vec<(BlockHeight, TxProposal<Tx>)>,
+    held_dirty: bool,
+    // The nonces expected next by the committed state.
+    next_nonces: HashMap<Address, Nonce>,
+    // The nonces expected next after the tx proposals yielded since the last settle.
+    popped_nonces: HashMap<Address, Nonce>,
+    mempool: Option<Mempool<Tx>>,
+    popped: Vec<TxProposal<Tx>>,
+    stage: Option<Arc<PipelineStage>>,
+}
+
+impl<Tx, S> PendingTxQueue<Tx, S>
+where
+    Tx: TxTrait,
+    S: Stream<Item = TxProposal<Tx>> + Unpin,
+{
+    pub fn new(inner: S, current_height: BlockHeight) -> Self {
+        Self {
+            inner: inner.fuse(),
+            pending: VecDeque::new(),
+            pending_dirty: false,
+            current_height,
+            nonce_ordering: false,
+            held: Vec::new(),
+            held_dirty: false,
+            next_nonces: HashMap::new(),
+            popped_nonces: HashMap::new(),
+            mempool: None,
+            popped: Vec::new(),
+            stage: None,
+        }
+    }
+
+    pub fn with_nonce_ordering(mut self, nonce_ordering: bool) -> Self {
+        self.nonce_ordering = nonce_ordering;
+        self
+    }
+
+    /// Back the queue by `mempool`, whose tx proposals are queued first in their arrival order.
+    pub fn with_mempool(mut self, mempool: Mempool<Tx>) -> Self {
+        for entry in mempool.iter() {
+            self.pending
+                .push_back((entry.recv_height, entry.tx_proposal.clone()));
+        }
+        self.held_dirty = !self.pending.is_empty();
+        self.pending_dirty = !self.pending.is_empty();
+        self.mempool = Some(mempool);
+        self
+    }
+
+    /// Count the queued tx proposals on `stage` from now on.
+    pub fn with_pipeline_stage(mut self, stage: Arc<PipelineStage>) -> Self {
+        stage.readmit(self.len());
+        self.stage = Some(stage);
+        self
+    }
+
+    fn leave_stage(&self, n: usize) {
+        if let Some(stage) = self.stage.as_ref() {
+            stage.pop(n);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.pending.len() + self.held.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty() && self.held.is_empty()
+    }
+
+    fn recv_ready(&mut self, cx: &mut Context<'_>) {
+        while let Poll::Ready(Some(tx_proposal)) = Pin::new(&mut self.inner).poll_next(cx) {
+            if let Some(mempool) = self.mempool.as_mut() {
+                let tx_id = tx_proposal.tx.id();
+                match mempool.insert(self.current_height, tx_proposal.clone()) {
+                    Ok(removed) => self.drop_queued(&removed, "mempool_evicted"),
+                    Err(reason) => {
+                        debug!("Drop tx proposal with {}.", reason);
+                        record_event!("discard_tx", "tx_id": tx_id, "reason": reason.as_str());
+                        // The status belongs to the pending copy of a duplicate.
+                        if reason != MempoolReject::DuplicateTx {
+                            TX_STATUS.record_failure(tx_id, reason.as_str());
+                        }
+                        self.leave_stage(1);
+                        continue;
+                    }
+                }
+            }
+
+            if self.nonce_ordering {
+                self.held.push((self.current_height, tx_proposal));
+                self.held_dirty = true;
+            } else {
+                self.pending.push_back((self.current_height, tx_proposal));
+                self.pending_dirty = true;
+            }
+        }
+    }
+
+    fn drop_queued(&mut self, tx_ids: &[H256], reason: &'static str) {
+        if tx_ids.is_empty() {
+            return;
+        }
+
+        let before = self.len();
+        let retain_fn = |(_, tx_proposal): &(BlockHeight, TxProposal<Tx>)| {
+            let tx_id = tx_proposal.tx.id();
+            let dropped = tx_ids.contains(&tx_id);
+            if dropped {
+                record_event!("discard_tx", "tx_id": tx_id, "reason": reason);
+                TX_STATUS.record_failure(tx_id, reason);
+            }
+            !dropped
+        };
+        self.pending.retain(&retain_fn);
+        self.held.retain(&retain_fn);
+        self.leave_stage(before - self.len());
+    }
+
+    // Persist the mempool changes off the caller. Unless `force`d, wait for a batch to build
+    // up, e.g., when polled for a tx.
+    fn flush_mempool(&mut self, force: bool) {
+        if let Some(mempool) = self.mempool.as_mut() {
+            if force {
+                mempool.flush_in_background();
+            } else {
+                mempool.flush_due();
+            }
+        }
+    }
+
+    fn reorder_by_nonce(&mut self) {
+        if !self.held_dirty {
+            return;
+        }
+        self.held_dirty = false;
+
+        // A tx proposal executed against a newer state tells a newer nonce.
+        for (_, tx_proposal) in self.pending.iter().chain(self.held.iter()) {
+            let caller = tx_proposal.tx.tx_caller();
+            if let Some(nonce) = tx_proposal.write_trie.get_nonce(&caller) {
+                let next = self.next_nonces.entry(caller).or_insert(nonce);
+                if nonce > *next {
+                    *next = nonce;
+                }
+            }
+        }
+
+        let next_nonces = &self.next_nonces;
+        let popped_nonces = &self.popped_nonces;
+        let expected = |caller: Address| {
+            let committed = next_nonces.get(&caller).copied();
+            committed.max(popped_nonces.get(&caller).copied())
+        };
+        let ordering = order_by_nonce(
+            self.pending.drain(..).chain(self.held.drain(..)),
+            |(_, tx_proposal)| &tx_proposal.tx,
+            expected,
+        );
+
+        self.leave_stage(ordering.dropped.len());
+        for (_, tx_proposal) in ordering.dropped {
+            let caller = tx_proposal.tx.tx_caller();
+            let nonce = tx_proposal.tx.tx_input().nonce();
+            let reason = match expected(caller) {
+                Some(next) if nonce < next => "stale_nonce",
+                _ => "duplicate_nonce",
+            };
+            debug!("Drop tx proposal with {}.", reason);
+            record_event!("discard_tx", "tx_id": tx_proposal.tx.id(), "reason": reason);
+            if let Some(mempool) = self.mempool.as_mut() {
+                mempool.remove(tx_proposal.tx.id());
+            }
+        }
+
+        self.pending_dirty |= !ordering.included.is_empty();
+        self.pending.extend(ordering.included);
+        self.held = ordering.pending;
+    }
+
+    /// Sort the tx proposals ready by their fees. The sort is stable, so the ones with the same
+    /// fee keep their order.
+    fn sort_by_fee(&mut self) {
+        if !self.pending_dirty {
+            return;
+        }
+        self.pending_dirty = false;
+
+        self.pending
+            .make_contiguous()
+            .sort_by_key(|(_, tx_proposal)| Reverse(tx_proposal.tx.fee()));
+        if !self.nonce_ordering {
+            return;
+        }
+
+        let mut places: HashMap<Address, Vec<usize>> = HashMap::new();
+        for (idx, (_, tx_proposal)) in self.pending.iter().enumerate() {
+            places
+                .entry(tx_proposal.tx.tx_caller())
+                .or_default()
+                .push(idx);
+        }
+        let mut slots: Vec<_> = self.pending.drain(..).map(Some).collect();
+        for idxs in places.values().filter(|idxs| idxs.len() > 1) {
+            let mut group: Vec<_> = idxs.iter().filter_map(|&idx| slots[idx].take()).collect();
+            group.sort_by_key(|(_, tx_proposal)| tx_proposal.tx.tx_input().nonce());
+            for (&idx, item) in idxs.iter().zip(group) {
+                slots[idx] = Some(item);
+            }
+        }
+        self.pending = slots.into_iter().flatten().collect();
+    }
+
+    fn pop_ready(&mut self) -> Option<TxProposal<Tx>> {
+        let (_, tx_proposal) = self.pending.pop_front()?;
+        self.leave_stage(1);
+        self.popped.push(tx_proposal.clone());
+        if self.nonce_ordering {
+            let tx = &tx_proposal.tx;
+            // The nonce is chosen by the caller, so it may be the max one.
+            self.popped_nonces.insert(
+                tx.tx_caller(),
+                tx.tx_input().nonce().saturating_add(Nonce::from(1)),
+            );
+        }
+        Some(tx_proposal)
+    }
+
+    /// Put back the tx proposals deferred from the last block, so that they are
+    /// retried first in the next block among the ones with the same fee.
+    pub fn requeue(&mut self, tx_proposals: Vec<TxProposal<Tx>>) {
+        if let Some(stage) = self.stage.as_ref() {
+            stage.readmit(tx_proposals.len());
+        }
+        if !self.popped.is_empty() {
+            let ids: HashSet<H256> = tx_proposals.iter().map(|p| p.tx.id()).collect();
+            self.popped.retain(|p| !ids.contains(&p.tx.id()));
+        }
+        for tx_proposal in tx_proposals.into_iter().rev() {
+            if self.nonce_ordering {
+                let caller = tx_proposal.tx.tx_caller();
+                let nonce = tx_proposal.tx.tx_input().nonce();
+                if let Some(next) = self.popped_nonces.get_mut(&caller) {
+                    if nonce < *next {
+                        *next = nonce;
+                    }
+                }
+                self.held_dirty = true;
+            }
+            self.pending.push_front((self.current_height, tx_proposal));
+            self.pending_dirty = true;
+        }
+    }
+
+    /// Settle the tx proposals yielded since the last call, except the requeued ones, once they
+    /// are committed in the new block. Their callers are expected to go on from the next nonces,
+    /// and they leave the mempool.
+    pub fn settle_popped(&mut self) {
+        let popped = self.take_popped();
+        if self.nonce_ordering {
+            for tx_proposal in &popped {
+                let tx = &tx_proposal.tx;
+                let nonce = tx.tx_input().nonce().saturating_add(Nonce::from(1));
+                let next = self.next_nonces.entry(tx.tx_caller()).or_insert(nonce);
+                if nonce > *next {
+                    *next = nonce;
+                }
+            }
+            self.held_dirty = true;
+        }
+    }
+
+    /// Like [`PendingTxQueue::settle_popped`], but for the tx proposals leaving the node without
+    /// being committed, e.g., forwarded to the leader. Their nonces are not taken as used.
+    pub fn forget_popped(&mut self) {
+        self.take_popped();
+    }
+
+    /// Put back the tx proposals yielded since the last [`PendingTxQueue::settle_popped`], e.g.,
+    /// once the block taking them fails to commit.
+    pub fn requeue_popped(&mut self) {
+        let popped = std::mem::take(&mut self.popped);
+        self.popped_nonces.clear();
+        self.requeue(popped);
+    }
+
+    fn take_popped(&mut self) -> Vec<TxProposal<Tx>> {
+        let popped = std::mem::take(&mut self.popped);
+        self.popped_nonces.clear();
+        if let Some(mempool) = self.mempool.as_mut() {
+            for tx_proposal in &popped {
+                mempool.remove(tx_proposal.tx.id());
+            }
+        }
+        self.flush_mempool(true);
+        popped
+    }
+
+    /// Drop the tx proposals received more than `max_age_blocks` blocks before
+    /// `current_height`. Return the number of dropped tx proposals.
+    pub fn purge_expired(&mut self, current_height: BlockHeight, max_age_blocks: u64) -> usize {
+        self.current_height = current_height;
+
+        let before = self.len();
+        let mut expired_ids = Vec::new();
+        let mut retain_fn = |(recv_height, tx_proposal): &(BlockHeight, TxProposal<Tx>)| {
+            let expired = *recv_height < current_height
+                && current_height.distance(*recv_height) > max_age_blocks;
+            if expired {
+                record_event!("tx_expired", "tx_id": tx_proposal.tx.id(), "recv_height": recv_height.0, "height": current_height.0);
+                TX_STATUS.record_failure(tx_proposal.tx.id(), "tx_expired");
+                expired_ids.push(tx_proposal.tx.id());
+            }
+            !expired
+        };
+        self.pending.retain(&mut retain_fn);
+        self.held.retain(&mut retain_fn);
+        if let Some(mempool) = self.mempool.as_mut() {
+            for tx_id in expired_ids {
+                mempool.remove(tx_id);
+            }
+        }
+        self.flush_mempool(true);
+        let after = self.len();
+        self.leave_stage(before - after);
+        record_event!("tx_pending_purge", "height": current_height.0, "before": before, "after": after);
+        before - after
+    }
+}
+
+impl<Tx, S> Stream for PendingTxQueue<Tx, S>
+where
+    Tx: TxTrait,
+    S: Stream<Item = TxProposal<Tx>> + Unpin,
+{
+    type Item = TxProposal<Tx>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        this.recv_ready(cx);
+        if this.nonce_ordering {
+            this.reorder_by_nonce();
+        }
+        this.sort_by_fee();
+        this.flush_mempool(false);
+
+        let tx_proposal = this.pop_ready();
+        CHAIN_METRICS.set_pending_queue_depth(this.len());
+
+        if let Some(tx_proposal) = tx_proposal {
+            Poll::Ready(Some(tx_proposal))
+        } else if this.inner.is_done() {
+            Poll::Ready(None)
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::DummyTx;
+    use futures::channel::mpsc;
+    use slimchain_common::{basic::H160, rw_set::TxWriteData};
+    use slimchain_tx_state::{MemTxState, TxWriteSetTrie};
+
+    fn send_tx(tx_tx: &mpsc::UnboundedSender<TxProposal<DummyTx>>, id: u64) {
+        tx_tx
+            .unbounded_send(DummyTx::new(id, id, 0).into_proposal())
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_purge_expired() {
+        const MAX_AGE: u64 = 2;
+        let (tx_tx, tx_rx) = mpsc::unbounded();
+        let mut queue = PendingTxQueue::new(tx_rx, 0.into());
+
+        for i in 0..4 {
+            send_tx(&tx_tx, i);
+        }
+        assert_eq!(queue.next().await.unwrap().tx.id, 0);
+        assert_eq!(queue.len(), 3);
+
+        assert_eq!(queue.purge_expired(1.into(), MAX_AGE), 0);
+        send_tx(&tx_tx, 4);
+        assert_eq!(queue.next().await.unwrap().tx.id, 1);
+        assert_eq!(queue.len(), 3);
+
+        // txs received at height 0 survive exactly MAX_AGE commits.
+        assert_eq!(queue.purge_expired(2.into(), MAX_AGE), 0);
+        assert_eq!(queue.len(), 3);
+        assert_eq!(queue.purge_expired(3.into(), MAX_AGE), 2);
+        assert_eq!(queue.len(), 1);
+
+        // the tx received at height 1.
+        assert_eq!(queue.purge_expired(3.into(), MAX_AGE), 0);
+        assert_eq!(queue.purge_expired(4.into(), MAX_AGE), 1);
+        assert!(queue.is_empty());
+
+        send_tx(&tx_tx, 5);
+        drop(tx_tx);
+        assert_eq!(queue.next().await.unwrap().tx.id, 5);
+        assert!(queue.next().await.is_none());
+    }
+
+    fn send_fee_tx(
+        tx_tx: &mpsc::UnboundedSender<TxProposal<DummyTx>>,
+        id: u64,
+        caller: u64,
+        nonce: u64,
+        fee: u64,
+    ) {
+        let tx = DummyTx::new(id, caller, nonce).with_fee(fee);
+        tx_tx.unbounded_send(tx.into_proposal()).unwrap();
+    }
+
+    async fn next_block<S>(queue: &mut PendingTxQueue<DummyTx, S>, max_txs: usize) -> Vec<u64>
+    where
+        S: Stream<Item = TxProposal<DummyTx>> + Unpin,
+    {
+        let mut ids = Vec::with_capacity(max_txs);
+        for _ in 0..max_txs {
+            ids.push(queue.next().await.unwrap().tx.id);
+        }
+        ids
+    }
+
+    #[tokio::test]
+    async fn test_fee_priority() {
+        let (tx_tx, tx_rx) = mpsc::unbounded();
+        let mut queue = PendingTxQueue::new(tx_rx, 0.into());
+
+        // A backlog of the low-fee txs.
+        for i in 0..10 {
+            send_fee_tx(&tx_tx, i, i, 0, 1);
+        }
+        assert_eq!(next_block(&mut queue, 4).await, vec![0, 1, 2, 3]);
+
+        // The high-fee tx submitted late lands in the next block ahead of the backlog.
+        send_fee_tx(&tx_tx, 10, 10, 0, 100);
+        send_fee_tx(&tx_tx, 11, 11, 0, 50);
+        assert_eq!(next_block(&mut queue, 4).await, vec![10, 11, 4, 5]);
+
+        // The deferred ones are retried first among the same fee only.
+        let deferred = queue.next().await.unwrap();
+        assert_eq!(deferred.tx.id, 6);
+        queue.requeue(vec![deferred]);
+        send_fee_tx(&tx_tx, 12, 12, 0, 2);
+        assert_eq!(next_block(&mut queue, 4).await, vec![12, 6, 7, 8]);
+
+        // The block failing to commit puts back its txs ahead of the same fee.
+        queue.settle_popped();
+        send_fee_tx(&tx_tx, 13, 13, 0, 1);
+        assert_eq!(next_block(&mut queue, 2).await, vec![9, 13]);
+        queue.requeue_popped();
+        send_fee_tx(&tx_tx, 14, 14, 0, 1);
+        assert_eq!(next_block(&mut queue, 3).await, vec![9, 13, 14]);
+    }
+
+    #[tokio::test]
+    async fn test_fee_priority_with_nonce_ordering() {
+        let (tx_tx, tx_rx) = mpsc::unbounded();
+        let mut queue = PendingTxQueue::new(tx_rx, 0.into()).with_nonce_ordering(true);
+
+        // The high-fee tx of caller 1 cannot go ahead of its lower nonce, which takes its place.
+        send_fee_tx(&tx_tx, 1, 1, 0, 1);
+        send_fee_tx(&tx_tx, 2, 2, 0, 10);
+        send_fee_tx(&tx_tx, 3, 1, 1, 50);
+        send_fee_tx(&tx_tx, 4, 3, 0, 20);
+        assert_eq!(next_block(&mut queue, 4).await, vec![1, 4, 2, 3]);
+
+        // So does the one waiting for a lower nonce to arrive.
+        send_fee_tx(&tx_tx, 5, 1, 3, 100);
+        send_fee_tx(&tx_tx, 6, 2, 1, 5);
+        assert_eq!(next_block(&mut queue, 1).await, vec![6]);
+        send_fee_tx(&tx_tx, 7, 1, 2, 1);
+        assert_eq!(next_block(&mut queue, 2).await, vec![7, 5]);
+    }
+
+    #[tokio::test]
+    async fn test_committed_nonce() {
+        let (tx_tx, tx_rx) = mpsc::unbounded();
+        let mut queue = PendingTxQueue::new(tx_rx, 0.into()).with_nonce_ordering(true);
+
+        // The txs of caller 1 are executed against the state where it has used the nonces up to 2.
+        let caller = Address::from(H160::from_low_u64_be(1));
+        let mut writes = TxWriteData::default();
+        writes.add_nonce(caller, Nonce::from(3));
+        let mut state = MemTxState::new();
+        state.apply_writes(&writes).unwrap();
+        let write_trie =
+            TxWriteSetTrie::new(&state.state_view(), state.state_root(), &writes).unwrap();
+        for &(id, nonce) in &[(1, 2), (2, 4), (3, 3)] {
+            let tx = DummyTx::new(id, 1, nonce);
+            tx_tx
+                .unbounded_send(TxProposal::new(tx, write_trie.clone()))
+                .unwrap();
+        }
+        assert_eq!(next_block(&mut queue, 2).await, vec![3, 2]);
+
+        // The nonces are not used until the block is committed.
+        queue.requeue_popped();
+        assert_eq!(next_block(&mut queue, 2).await, vec![3, 2]);
+        queue.settle_popped();
+
+        send_fee_tx(&tx_tx, 4, 1, 4, 0);
+        send_fee_tx(&tx_tx, 5, 1, 5, 0);
+        assert_eq!(next_block(&mut queue, 1).await, vec![5]);
+
+        // Nor by the ones forwarded away.
+        queue.forget_popped();
+        send_fee_tx(&tx_tx, 6, 1, 5, 0);
+        assert_eq!(next_block(&mut queue, 1).await, vec![6]);
+    }
+
+    #[tokio::test]
+    async fn test_pipeline_stage() {
+        let stage = Arc::new(PipelineStage::new("test_tx_queue", 0));
+        let (tx_tx, tx_rx) = mpsc::unbounded();
+        let mut queue = PendingTxQueue::new(tx_rx, 0.into()).with_pipeline_stage(stage.clone());
+
+        for i in 0..4 {
+            stage.push(1).await;
+            send_tx(&tx_tx, i);
+        }
+        let first = queue.next().await.unwrap();
+        assert_eq!(stage.depth(), 3);
+        queue.requeue(vec![first]);
+        assert_eq!(stage.depth(), 4);
+
+        assert_eq!(queue.purge_expired(3.into(), 2), 4);
+        assert_eq!(stage.depth(), 0);
+    }
+}
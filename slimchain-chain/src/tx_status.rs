@@ -0,0 +1,224 @@
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use slimchain_common::{
+    basic::{BlockHeight, H256},
+    collections::HashMap,
+};
+use std::{
+    sync::{Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+/// Max number of txs whose status is tracked. Txs received beyond this are not tracked.
+const MAX_TRACKED_TXS: usize = 1_000_000;
+
+/// How often the expired entries are purged.
+const PURGE_INTERVAL: Duration = Duration::from_secs(1);
+
+pub static TX_STATUS: Lazy<TxStatusTracker> = Lazy::new(TxStatusTracker::new);
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub enum TxStatus {
+    /// Received by this node.
+    Submitted,
+    /// Sent to a storage node for execution.
+    Forwarded,
+    /// The tx proposal is received by the leader.
+    Executed,
+    /// Included in the block at `height` as its `position`-th tx.
+    Committed {
+        height: BlockHeight,
+        position: usize,
+    },
+    Failed {
+        reason: String,
+    },
+    Unknown,
+}
+
+impl TxStatus {
+    pub fn is_terminal(&self) -> bool {
+        matches!(self, Self::Committed { .. } | Self::Failed { .. })
+    }
+
+    fn rank(&self) -> u8 {
+        match self {
+            Self::Unknown => 0,
+            Self::Submitted => 1,
+            Self::Forwarded => 2,
+            Self::Executed => 3,
+            Self::Failed { .. } => 4,
+            Self::Committed { .. } => 5,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct TxStatusEntry {
+    status: TxStatus,
+    updated: Instant,
+}
+
+#[derive(Debug)]
+struct TxStatusTrackerInner {
+    entries: HashMap<H256, TxStatusEntry>,
+    ttl: Duration,
+    last_purge: Instant,
+}
+
+impl TxStatusTrackerInner {
+    fn is_expired(&self, entry: &TxStatusEntry, now: Instant) -> bool {
+        entry.status.is_terminal() && now.duration_since(entry.updated) >= self.ttl
+    }
+
+    fn purge_expired(&mut self, now: Instant) {
+        if now.duration_since(self.last_purge) < PURGE_INTERVAL {
+            return;
+        }
+        self.last_purge = now;
+        let ttl = self.ttl;
+        self.entries.retain(|_, entry| {
+            !entry.status.is_terminal() || now.duration_since(entry.updated) < ttl
+        });
+    }
+}
+
+/// In-process status of the txs seen by this node.
+/// The committed and failed txs are dropped after the configured time.
+#[derive(Debug)]
+pub struct TxStatusTracker {
+    inner: Mutex<TxStatusTrackerInner>,
+}
+
+impl TxStatusTracker {
+    pub fn new() -> Self {
+        Self {
+            inner: Mutex::new(TxStatusTrackerInner {
+                entries: HashMap::new(),
+                ttl: Duration::from_secs(60),
+                last_purge: Instant::now(),
+            }),
+        }
+    }
+
+    fn inner(&self) -> MutexGuard<'_, TxStatusTrackerInner> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Set how long the committed and failed txs are kept.
+    pub fn set_ttl(&self, ttl: Duration) {
+        self.inner().ttl = ttl;
+    }
+
+    /// Record the new status of `tx_id`. The status never goes back. A committed tx stays
+    /// committed, while a failed one can still be committed, e.g., if it is resubmitted.
+    pub fn record(&self, tx_id: H256, status: TxStatus) {
+        let now = Instant::now();
+        let mut inner = self.inner();
+        inner.purge_expired(now);
+
+        if let Some(entry) = inner.entries.get(&tx_id) {
+            if !matches!(status, TxStatus::Committed { .. })
+                && (entry.status.is_terminal() || status.rank() < entry.status.rank())
+            {
+                return;
+            }
+        } else if inner.entries.len() >= MAX_TRACKED_TXS {
+            return;
+        }
+
+        inner.entries.insert(
+            tx_id,
+            TxStatusEntry {
+                status,
+                updated: now,
+            },
+        );
+    }
+
+    pub fn record_failure(&self, tx_id: H256, reason: impl ToString) {
+        self.record(
+            tx_id,
+            TxStatus::Failed {
+                reason: reason.to_string(),
+            },
+        );
+    }
+
+    /// Record that the block at `height` containing `tx_ids` is committed.
+    pub fn record_block_commit(&self, height: BlockHeight, tx_ids: &[H256]) {
+        for (position, &tx_id) in tx_ids.iter().enumerate() {
+            self.record(tx_id, TxStatus::Committed { height, position });
+        }
+    }
+
+    pub fn get(&self, tx_id: H256) -> TxStatus {
+        let now = Instant::now();
+        let inner = self.inner();
+        match inner.entries.get(&tx_id) {
+            Some(entry) if !inner.is_expired(entry, now) => entry.status.clone(),
+            _ => TxStatus::Unknown,
+        }
+    }
+}
+
+impl Default for TxStatusTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tx_status() {
+        let tracker = TxStatusTracker::new();
+        let tx1 = H256::from_low_u64_be(1);
+        let tx2 = H256::from_low_u64_be(2);
+        let tx3 = H256::from_low_u64_be(3);
+        assert_eq!(TxStatus::Unknown, tracker.get(tx1));
+
+        tracker.record(tx1, TxStatus::Submitted);
+        tracker.record(tx2, TxStatus::Submitted);
+        tracker.record(tx1, TxStatus::Executed);
+        // The late ack of the forwarding does not override the execution.
+        tracker.record(tx1, TxStatus::Forwarded);
+        assert_eq!(TxStatus::Executed, tracker.get(tx1));
+
+        tracker.record_failure(tx2, "tx_conflict");
+        tracker.record(tx2, TxStatus::Executed);
+        assert_eq!(
+            TxStatus::Failed {
+                reason: "tx_conflict".to_string()
+            },
+            tracker.get(tx2)
+        );
+
+        tracker.record_block_commit(5u64.into(), &[tx3, tx1]);
+        tracker.record_failure(tx1, "tx_expired");
+        assert_eq!(
+            TxStatus::Committed {
+                height: 5u64.into(),
+                position: 1
+            },
+            tracker.get(tx1)
+        );
+        assert_eq!(
+            TxStatus::Committed {
+                height: 5u64.into(),
+                position: 0
+            },
+            tracker.get(tx3)
+        );
+
+        // Only the terminal entries expire.
+        let tx4 = H256::from_low_u64_be(4);
+        tracker.record(tx4, TxStatus::Forwarded);
+        tracker.set_ttl(Duration::from_millis(0));
+        assert_eq!(TxStatus::Unknown, tracker.get(tx1));
+        assert_eq!(TxStatus::Unknown, tracker.get(tx2));
+        assert_eq!(TxStatus::Forwarded, tracker.get(tx4));
+    }
+}
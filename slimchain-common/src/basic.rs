@@ -1,5 +1,8 @@
 pub use primitive_types::*;
 
+pub mod hex_str;
+pub use hex_str::parse_h256;
+
 pub mod address;
 pub use address::*;
 
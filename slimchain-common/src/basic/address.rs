@@ -1,5 +1,11 @@
-use crate::basic::{H160, H256};
+use crate::basic::{
+    hex_str::{deserialize_from_str, strip_hex_prefix},
+    H160, H256,
+};
 use crate::digest::Digestible;
+use crate::error::{ensure, Error, Result};
+use core::{fmt, str::FromStr};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 #[derive(
     Debug,
@@ -11,11 +17,8 @@ use crate::digest::Digestible;
     Ord,
     PartialOrd,
     Hash,
-    serde::Serialize,
-    serde::Deserialize,
     derive_more::Deref,
     derive_more::DerefMut,
-    derive_more::Display,
     derive_more::From,
     derive_more::Into,
 )]
@@ -26,3 +29,88 @@ impl Digestible for Address {
         self.0.as_bytes().to_digest()
     }
 }
+
+/// The `0x`-prefixed lowercase hex string of the 20 bytes.
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:#x}", self.0)
+    }
+}
+
+/// Parse the hex string with or without the `0x` prefix.
+impl FromStr for Address {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let bytes = hex::decode(strip_hex_prefix(s))?;
+        ensure!(
+            bytes.len() == H160::len_bytes(),
+            "Invalid address {:?}. Expect {} bytes, but got {}.",
+            s,
+            H160::len_bytes(),
+            bytes.len()
+        );
+        Ok(Self(H160::from_slice(&bytes)))
+    }
+}
+
+/// The hex string in the human-readable formats, and the same bytes as `H160` in the others.
+impl Serialize for Address {
+    fn serialize<S: Serializer>(&self, serializer: S) -> core::result::Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.collect_str(self)
+        } else {
+            serializer.serialize_newtype_struct("Address", &self.0)
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Address {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> core::result::Result<Self, D::Error> {
+        if deserializer.is_human_readable() {
+            deserialize_from_str(deserializer)
+        } else {
+            H160::deserialize(deserializer).map(Self)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_address_str() {
+        let addr = Address(H160::from_low_u64_be(0xabcd));
+        let hex_str = "0x000000000000000000000000000000000000abcd";
+        assert_eq!(hex_str, alloc::format!("{}", addr));
+        assert_eq!(addr, hex_str.parse().unwrap());
+        assert_eq!(addr, hex_str[2..].parse().unwrap());
+        assert_eq!(
+            addr,
+            hex_str.to_uppercase().replace("0X", "0x").parse().unwrap()
+        );
+        assert!("0xabcd".parse::<Address>().is_err());
+        assert!("0x00000000000000000000000000000000000000zz"
+            .parse::<Address>()
+            .is_err());
+    }
+
+    #[test]
+    fn test_address_serde() {
+        let addr = Address(H160::from_low_u64_be(0xabcd));
+        let json = serde_json::to_string(&addr).unwrap();
+        assert_eq!("\"0x000000000000000000000000000000000000abcd\"", json);
+        assert_eq!(addr, serde_json::from_str::<Address>(&json).unwrap());
+        assert_eq!(
+            addr,
+            serde_json::from_str::<Address>("\"000000000000000000000000000000000000abcd\"")
+                .unwrap()
+        );
+
+        // The same binary encoding as `H160`.
+        let bin = postcard::to_allocvec(&addr).unwrap();
+        assert_eq!(postcard::to_allocvec(&addr.0).unwrap(), bin);
+        assert_eq!(addr, postcard::from_bytes::<Address>(&bin).unwrap());
+    }
+}
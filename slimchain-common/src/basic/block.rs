@@ -53,6 +53,34 @@ impl BlockHeight {
     pub fn is_zero(self) -> bool {
         self.0 == 0
     }
+
+    /// `n` blocks after `self`, or `None` if it overflows.
+    pub fn checked_add(self, n: u64) -> Option<Self> {
+        self.0.checked_add(n).map(Self)
+    }
+
+    /// `n` blocks before `self`, or `None` if it is before the genesis block.
+    pub fn checked_sub(self, n: u64) -> Option<Self> {
+        self.0.checked_sub(n).map(Self)
+    }
+
+    pub fn saturating_add(self, n: u64) -> Self {
+        Self(self.0.saturating_add(n))
+    }
+
+    /// `n` blocks before `self`, or the genesis block if there are not that many.
+    pub fn saturating_sub(self, n: u64) -> Self {
+        Self(self.0.saturating_sub(n))
+    }
+
+    /// The number of blocks between `self` and `other`, in either order.
+    pub fn distance(self, other: Self) -> u64 {
+        if self >= other {
+            self.0 - other.0
+        } else {
+            other.0 - self.0
+        }
+    }
 }
 
 #[derive(
@@ -110,3 +138,34 @@ impl SubAssign<BlockDistance> for BlockHeight {
         *self = *self - rhs
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_block_height_arithmetic() {
+        let zero = BlockHeight::from(0);
+        let max = BlockHeight::from(u64::MAX);
+        assert_eq!(None, zero.checked_sub(1));
+        assert_eq!(Some(zero), BlockHeight::from(1).checked_sub(1));
+        assert_eq!(None, max.checked_add(1));
+        assert_eq!(Some(max), BlockHeight::from(u64::MAX - 1).checked_add(1));
+        assert_eq!(
+            Some(BlockHeight::from(5)),
+            BlockHeight::from(5).checked_add(0)
+        );
+
+        assert_eq!(zero, zero.saturating_sub(1));
+        assert_eq!(zero, BlockHeight::from(3).saturating_sub(u64::MAX));
+        assert_eq!(BlockHeight::from(2), BlockHeight::from(3).saturating_sub(1));
+        assert_eq!(max, max.saturating_add(1));
+        assert_eq!(max, BlockHeight::from(1).saturating_add(u64::MAX));
+
+        assert_eq!(0, zero.distance(zero));
+        assert_eq!(3, BlockHeight::from(2).distance(BlockHeight::from(5)));
+        assert_eq!(3, BlockHeight::from(5).distance(BlockHeight::from(2)));
+        assert_eq!(u64::MAX, zero.distance(max));
+        assert_eq!(u64::MAX, max.distance(zero));
+    }
+}
@@ -0,0 +1,87 @@
+//! The `0x`-prefixed hex strings of the basic types, used by their `Display`/`FromStr` and by
+//! their human-readable serde encoding. `H256` already comes with the same serde encoding from
+//! `primitive-types`, so only the parsing is provided here.
+
+use crate::{
+    basic::H256,
+    error::{ensure, Result},
+};
+use core::{fmt, marker::PhantomData, str::FromStr};
+use serde::{
+    de::{Error as DeError, Visitor},
+    Deserializer,
+};
+
+/// Strip the optional `0x` prefix.
+pub(crate) fn strip_hex_prefix(input: &str) -> &str {
+    input
+        .strip_prefix("0x")
+        .or_else(|| input.strip_prefix("0X"))
+        .unwrap_or(input)
+}
+
+/// Parse `H256` from the hex string with or without the `0x` prefix.
+pub fn parse_h256(input: &str) -> Result<H256> {
+    let bytes = hex::decode(strip_hex_prefix(input))?;
+    ensure!(
+        bytes.len() == H256::len_bytes(),
+        "Expect {} bytes, but got {}.",
+        H256::len_bytes(),
+        bytes.len()
+    );
+    Ok(H256::from_slice(&bytes))
+}
+
+/// Deserialize `T` from a string with its `FromStr`.
+pub(crate) fn deserialize_from_str<'de, T, D>(deserializer: D) -> core::result::Result<T, D::Error>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+    D: Deserializer<'de>,
+{
+    struct StrVisitor<T>(PhantomData<T>);
+
+    impl<'de, T> Visitor<'de> for StrVisitor<T>
+    where
+        T: FromStr,
+        T::Err: fmt::Display,
+    {
+        type Value = T;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            write!(formatter, "a hex string")
+        }
+
+        fn visit_str<E: DeError>(self, v: &str) -> core::result::Result<T, E> {
+            v.parse().map_err(E::custom)
+        }
+    }
+
+    deserializer.deserialize_str(StrVisitor(PhantomData))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_h256() {
+        let hash = H256::repeat_byte(0xab);
+        let hex_str = "abababababababababababababababababababababababababababababababab";
+        assert_eq!(hash, parse_h256(hex_str).unwrap());
+        assert_eq!(hash, parse_h256(&alloc::format!("0x{}", hex_str)).unwrap());
+        assert!(parse_h256("0xabab").is_err());
+        assert!(parse_h256("0xzz").is_err());
+    }
+
+    #[test]
+    fn test_h256_json() {
+        let hash = H256::repeat_byte(0xab);
+        let json = serde_json::to_string(&hash).unwrap();
+        assert_eq!(alloc::format!("\"{:#x}\"", hash), json);
+        assert_eq!(hash, serde_json::from_str::<H256>(&json).unwrap());
+
+        let bin = postcard::to_allocvec(&hash).unwrap();
+        assert_eq!(hash, postcard::from_bytes::<H256>(&bin).unwrap());
+    }
+}
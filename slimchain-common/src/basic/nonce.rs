@@ -1,6 +1,15 @@
-use crate::basic::{H256, U256};
+use crate::basic::{
+    hex_str::{deserialize_from_str, strip_hex_prefix},
+    H256, U256,
+};
 use crate::digest::Digestible;
-use core::ops::{Add, AddAssign, Sub, SubAssign};
+use crate::error::{anyhow, Error, Result};
+use core::{
+    fmt,
+    ops::{Add, AddAssign, Sub, SubAssign},
+    str::FromStr,
+};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 #[derive(
     Debug,
@@ -12,11 +21,8 @@ use core::ops::{Add, AddAssign, Sub, SubAssign};
     Ord,
     PartialOrd,
     Hash,
-    serde::Serialize,
-    serde::Deserialize,
     derive_more::Deref,
     derive_more::DerefMut,
-    derive_more::Display,
     derive_more::From,
     derive_more::Into,
 )]
@@ -51,6 +57,38 @@ impl Nonce {
     pub fn zero() -> Self {
         Self(U256::zero())
     }
+
+    /// `self + rhs`, or `None` if it overflows.
+    pub fn checked_add(self, rhs: Nonce) -> Option<Self> {
+        self.0.checked_add(rhs.0).map(Self)
+    }
+
+    /// `self - rhs`, or `None` if it underflows.
+    pub fn checked_sub(self, rhs: Nonce) -> Option<Self> {
+        self.0.checked_sub(rhs.0).map(Self)
+    }
+
+    pub fn saturating_add(self, rhs: Nonce) -> Self {
+        Self(self.0.saturating_add(rhs.0))
+    }
+
+    pub fn saturating_sub(self, rhs: Nonce) -> Self {
+        Self(self.0.saturating_sub(rhs.0))
+    }
+
+    /// The difference between `self` and `other` in either order, capped at `u64::MAX`.
+    pub fn distance(self, other: Self) -> u64 {
+        let diff = if self >= other {
+            self.0 - other.0
+        } else {
+            other.0 - self.0
+        };
+        if diff > U256::from(u64::MAX) {
+            u64::MAX
+        } else {
+            diff.low_u64()
+        }
+    }
 }
 
 impl Add for Nonce {
@@ -80,3 +118,99 @@ impl SubAssign for Nonce {
         self.0 -= rhs.0
     }
 }
+
+/// The `0x`-prefixed lowercase hex string without the leading zeros, e.g., `0x0` and `0x1f`.
+impl fmt::Display for Nonce {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:#x}", self.0)
+    }
+}
+
+/// Parse the hex string with or without the `0x` prefix.
+impl FromStr for Nonce {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let digits = strip_hex_prefix(s);
+        if digits.is_empty() {
+            return Err(anyhow!("Invalid nonce {:?}.", s));
+        }
+        U256::from_str_radix(digits, 16)
+            .map(Self)
+            .map_err(|e| anyhow!("Invalid nonce {:?}. {}", s, e))
+    }
+}
+
+/// The hex string in the human-readable formats, and the same as `U256` in the others.
+impl Serialize for Nonce {
+    fn serialize<S: Serializer>(&self, serializer: S) -> core::result::Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.collect_str(self)
+        } else {
+            serializer.serialize_newtype_struct("Nonce", &self.0)
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Nonce {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> core::result::Result<Self, D::Error> {
+        if deserializer.is_human_readable() {
+            deserialize_from_str(deserializer)
+        } else {
+            U256::deserialize(deserializer).map(Self)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nonce_str() {
+        assert_eq!("0x0", alloc::format!("{}", Nonce::zero()));
+        assert_eq!("0x1f", alloc::format!("{}", Nonce::from(31)));
+        assert_eq!(Nonce::from(31), "0x1f".parse().unwrap());
+        assert_eq!(Nonce::from(31), "1F".parse().unwrap());
+        assert_eq!(Nonce::zero(), "0x0".parse().unwrap());
+        assert!("0x".parse::<Nonce>().is_err());
+        assert!("0xzz".parse::<Nonce>().is_err());
+    }
+
+    #[test]
+    fn test_nonce_arithmetic() {
+        let zero = Nonce::zero();
+        let one = Nonce::from(1);
+        let max = Nonce::from(U256::MAX);
+        assert_eq!(None, zero.checked_sub(one));
+        assert_eq!(Some(zero), one.checked_sub(one));
+        assert_eq!(None, max.checked_add(one));
+        assert_eq!(Some(max), Nonce::from(U256::MAX - 1).checked_add(one));
+
+        assert_eq!(zero, zero.saturating_sub(one));
+        assert_eq!(Nonce::from(1), Nonce::from(2).saturating_sub(one));
+        assert_eq!(max, max.saturating_add(one));
+        assert_eq!(max, one.saturating_add(max));
+
+        assert_eq!(0, one.distance(one));
+        assert_eq!(3, Nonce::from(2).distance(Nonce::from(5)));
+        assert_eq!(3, Nonce::from(5).distance(Nonce::from(2)));
+        assert_eq!(u64::MAX, zero.distance(Nonce::from(u64::MAX)));
+        assert_eq!(u64::MAX, zero.distance(max));
+        assert_eq!(u64::MAX, max.distance(zero));
+    }
+
+    #[test]
+    fn test_nonce_serde() {
+        let nonce = Nonce::from(0x1234);
+        let json = serde_json::to_string(&nonce).unwrap();
+        assert_eq!("\"0x1234\"", json);
+        assert_eq!(nonce, serde_json::from_str::<Nonce>(&json).unwrap());
+        assert_eq!(nonce, serde_json::from_str::<Nonce>("\"1234\"").unwrap());
+
+        // The same binary encoding as `U256`.
+        let bin = postcard::to_allocvec(&nonce).unwrap();
+        assert_eq!(postcard::to_allocvec(&nonce.0).unwrap(), bin);
+        assert_eq!(nonce, postcard::from_bytes::<Nonce>(&bin).unwrap());
+    }
+}
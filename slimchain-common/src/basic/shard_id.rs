@@ -18,6 +18,14 @@ impl ShardId {
         Self { id, total }
     }
 
+    /// The shard of `total` holding `addr`.
+    pub fn from_address(addr: Address, total: u64) -> Self {
+        Self {
+            id: addr.to_low_u64_be() % total,
+            total,
+        }
+    }
+
     pub fn contains(&self, addr: Address) -> bool {
         addr.to_low_u64_be() % self.total == self.id
     }
@@ -48,5 +56,9 @@ mod tests {
         let shard_id = ShardId::new(1, 2);
         assert!(shard_id.contains(H160::repeat_byte(0xff).into()));
         assert!(!shard_id.contains(H160::repeat_byte(0x00).into()));
+        assert_eq!(
+            ShardId::from_address(H160::repeat_byte(0xff).into(), 2),
+            shard_id
+        );
     }
 }
@@ -11,6 +11,7 @@ pub mod collections;
 pub mod digest;
 pub mod ed25519;
 pub mod rw_set;
+pub mod secp256k1;
 pub mod tx;
 pub mod tx_req;
 pub mod utils;
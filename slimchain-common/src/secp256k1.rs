@@ -0,0 +1,209 @@
+use crate::{
+    basic::H256,
+    digest::{blake2b_hash_to_h256, default_blake2, Digestible},
+    error::{anyhow, ensure, Result},
+};
+use core::fmt;
+use serde::{
+    de::{Error as DeError, SeqAccess, Visitor},
+    ser::SerializeTuple,
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+
+pub use libsecp256k1;
+pub use libsecp256k1::{Message, PublicKey, SecretKey, Signature};
+
+pub const PUBLIC_KEY_LENGTH: usize = 33;
+pub const SIGNATURE_LENGTH: usize = 64;
+
+#[derive(Debug, Clone)]
+pub struct Keypair {
+    pub secret: SecretKey,
+    pub public: PublicKey,
+}
+
+impl Keypair {
+    pub fn from_secret_key(secret: SecretKey) -> Self {
+        Self {
+            public: PublicKey::from_secret_key(&secret),
+            secret,
+        }
+    }
+
+    /// Create the keypair from the 32-byte secret key, e.g., exported from an Ethereum wallet.
+    pub fn from_secret_bytes(bytes: &[u8; 32]) -> Result<Self> {
+        let secret = SecretKey::parse(bytes).map_err(|e| anyhow!("{:?}", e))?;
+        Ok(Self::from_secret_key(secret))
+    }
+}
+
+/// The public key in the compressed form and the signature over a message hash.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct PubSigPair {
+    #[serde(with = "crate::secp256k1::pk_serde_impl")]
+    pub pk: PublicKey,
+    #[serde(with = "crate::secp256k1::sig_serde_impl")]
+    pub sig: Signature,
+}
+
+impl PubSigPair {
+    pub fn create(keypair: &Keypair, msg_hash: H256) -> Self {
+        let (sig, _) =
+            libsecp256k1::sign(&Message::parse(msg_hash.as_fixed_bytes()), &keypair.secret);
+        Self {
+            pk: keypair.public,
+            sig,
+        }
+    }
+
+    /// Verify the signature, which must be in the lower-S form like the Ethereum ones.
+    pub fn verify(&self, msg_hash: H256) -> Result<()> {
+        ensure!(
+            libsecp256k1::verify(
+                &Message::parse(msg_hash.as_fixed_bytes()),
+                &self.sig,
+                &self.pk
+            ),
+            "Invalid secp256k1 signature."
+        );
+        Ok(())
+    }
+
+    pub fn public(&self) -> &PublicKey {
+        &self.pk
+    }
+
+    pub fn signature(&self) -> &Signature {
+        &self.sig
+    }
+}
+
+impl Digestible for PubSigPair {
+    fn to_digest(&self) -> H256 {
+        let mut hash_state = default_blake2().to_state();
+        hash_state.update(&self.pk.serialize_compressed()[..]);
+        hash_state.update(&self.sig.serialize()[..]);
+        let hash = hash_state.finalize();
+        blake2b_hash_to_h256(hash)
+    }
+}
+
+fn serialize_bytes<S: Serializer>(
+    bytes: &[u8],
+    serializer: S,
+) -> core::result::Result<S::Ok, S::Error> {
+    let mut tup = serializer.serialize_tuple(bytes.len())?;
+    for b in bytes {
+        tup.serialize_element(b)?;
+    }
+    tup.end()
+}
+
+/// Deserialize the bytes written by [`serialize_bytes`] into `buf`.
+fn deserialize_bytes<'de, D: Deserializer<'de>>(
+    deserializer: D,
+    buf: &mut [u8],
+) -> core::result::Result<(), D::Error> {
+    struct BytesVisitor<'a>(&'a mut [u8]);
+
+    impl<'de, 'a> Visitor<'de> for BytesVisitor<'a> {
+        type Value = ();
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            write!(formatter, "{} bytes", self.0.len())
+        }
+
+        fn visit_seq<V: SeqAccess<'de>>(self, mut seq: V) -> core::result::Result<(), V::Error> {
+            for (i, b) in self.0.iter_mut().enumerate() {
+                *b = seq
+                    .next_element()?
+                    .ok_or_else(|| DeError::invalid_length(i, &"more bytes"))?;
+            }
+            Ok(())
+        }
+    }
+
+    let len = buf.len();
+    deserializer.deserialize_tuple(len, BytesVisitor(buf))
+}
+
+pub mod pk_serde_impl {
+    use super::*;
+
+    pub fn serialize<S>(value: &PublicKey, serializer: S) -> core::result::Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serialize_bytes(&value.serialize_compressed()[..], serializer)
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> core::result::Result<PublicKey, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let mut bytes = [0u8; PUBLIC_KEY_LENGTH];
+        deserialize_bytes(deserializer, &mut bytes[..])?;
+        PublicKey::parse_compressed(&bytes).map_err(|e| DeError::custom(format_args!("{:?}", e)))
+    }
+}
+
+pub mod sig_serde_impl {
+    use super::*;
+
+    pub fn serialize<S>(value: &Signature, serializer: S) -> core::result::Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serialize_bytes(&value.serialize()[..], serializer)
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> core::result::Result<Signature, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let mut bytes = [0u8; SIGNATURE_LENGTH];
+        deserialize_bytes(deserializer, &mut bytes[..])?;
+        Signature::parse_standard(&bytes).map_err(|e| DeError::custom(format_args!("{:?}", e)))
+    }
+}
+
+#[cfg(test)]
+pub(crate) fn random_keypair() -> Keypair {
+    loop {
+        if let Ok(keypair) = Keypair::from_secret_bytes(&rand::random()) {
+            return keypair;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_serde_pk_sig() {
+        let keypair = random_keypair();
+        let pk_sig = PubSigPair::create(&keypair, H256::zero());
+        let bin = postcard::to_allocvec(&pk_sig).unwrap();
+        assert_eq!(PUBLIC_KEY_LENGTH + SIGNATURE_LENGTH, bin.len());
+        assert_eq!(
+            postcard::from_bytes::<PubSigPair>(&bin[..]).unwrap(),
+            pk_sig
+        );
+    }
+
+    #[test]
+    fn test_sign_and_verify() {
+        let hash = H256::repeat_byte(0x12);
+        let keypair = random_keypair();
+        let pk_sig = PubSigPair::create(&keypair, hash);
+        pk_sig.verify(hash).unwrap();
+        assert!(pk_sig.verify(H256::repeat_byte(0x13)).is_err());
+
+        let other = PubSigPair {
+            pk: random_keypair().public,
+            ..pk_sig
+        };
+        assert!(other.verify(hash).is_err());
+    }
+}
@@ -20,6 +20,16 @@ pub trait TxTrait: Digestible + Clone + Sized + Send + Sync {
     fn tx_reads(&self) -> &TxReadSet;
     fn tx_writes(&self) -> &TxWriteData;
 
+    /// The gas used when executing the tx.
+    fn gas_used(&self) -> u64 {
+        0
+    }
+
+    /// The fee signed by the caller for the priority of the tx.
+    fn fee(&self) -> u64 {
+        0
+    }
+
     fn id(&self) -> H256 {
         tx_id_from_caller_and_input(self.tx_caller(), self.tx_input())
     }
@@ -48,11 +58,23 @@ mod tests {
             state_root: H256::zero(),
             reads: TxReadSet::default(),
             writes: TxWriteData::default(),
+            gas_used: 21_000,
+            fee: 100,
         };
 
         let mut rng = rand::thread_rng();
         let keypair = Keypair::generate(&mut rng);
         let signed_tx = raw_tx.sign(&keypair);
         signed_tx.verify_sig().unwrap();
+        assert_eq!(21_000, signed_tx.gas_used());
+        assert_eq!(100, signed_tx.fee());
+
+        let mut forged_tx = signed_tx.clone();
+        forged_tx.raw_tx.gas_used = 0;
+        assert!(forged_tx.verify_sig().is_err());
+
+        let mut forged_tx = signed_tx.clone();
+        forged_tx.raw_tx.fee = 1_000;
+        assert!(forged_tx.verify_sig().is_err());
     }
 }
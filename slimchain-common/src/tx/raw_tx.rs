@@ -17,6 +17,9 @@ pub struct RawTx {
     pub state_root: H256,
     pub reads: TxReadSet,
     pub writes: TxWriteData,
+    pub gas_used: u64,
+    /// The fee signed by the caller in the tx request.
+    pub fee: u64,
 }
 
 impl Digestible for RawTx {
@@ -28,6 +31,8 @@ impl Digestible for RawTx {
         hash_state.update(self.state_root.as_bytes());
         hash_state.update(self.reads.to_digest().as_bytes());
         hash_state.update(self.writes.to_digest().as_bytes());
+        hash_state.update(self.gas_used.to_digest().as_bytes());
+        hash_state.update(self.fee.to_digest().as_bytes());
         let hash = hash_state.finalize();
         blake2b_hash_to_h256(hash)
     }
@@ -58,6 +63,14 @@ impl TxTrait for RawTx {
         &self.writes
     }
 
+    fn gas_used(&self) -> u64 {
+        self.gas_used
+    }
+
+    fn fee(&self) -> u64 {
+        self.fee
+    }
+
     fn verify_sig(&self) -> Result<()> {
         Ok(())
     }
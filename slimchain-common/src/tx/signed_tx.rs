@@ -50,6 +50,14 @@ impl TxTrait for SignedTx {
         self.raw_tx.tx_writes()
     }
 
+    fn gas_used(&self) -> u64 {
+        self.raw_tx.gas_used()
+    }
+
+    fn fee(&self) -> u64 {
+        self.raw_tx.fee()
+    }
+
     fn verify_sig(&self) -> Result<()> {
         let hash = self.raw_tx.to_digest();
         self.pk_sig.verify(hash)
@@ -1,17 +1,30 @@
 use crate::{
-    basic::{Address, Code, Nonce, H256},
+    basic::{Address, Code, Nonce, H160, H256},
     digest::{blake2, blake2b_hash_to_h160, blake2b_hash_to_h256, default_blake2, Digestible},
-    ed25519::{Keypair, PubSigPair, PublicKey},
-    error::Result,
+    ed25519::{self, Keypair, PublicKey},
+    error::{bail, ensure, Result},
+    secp256k1,
 };
 use alloc::vec::Vec;
-use serde::{Deserialize, Serialize};
+use core::{
+    fmt,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+};
+use serde::{ser::SerializeStruct, Deserialize, Deserializer, Serialize, Serializer};
+use sha3::{Digest, Keccak256};
 
 pub fn caller_address_from_pk(pk: &PublicKey) -> Address {
     let hash = blake2(20).hash(&pk.to_bytes()[..]);
     blake2b_hash_to_h160(hash).into()
 }
 
+/// The Ethereum address of `pk`, i.e., the last 20 bytes of the keccak256 hash of the
+/// uncompressed public key without the prefix.
+pub fn caller_address_from_secp256k1_pk(pk: &secp256k1::PublicKey) -> Address {
+    let hash = Keccak256::digest(&pk.serialize()[1..]);
+    H160::from_slice(&hash[12..]).into()
+}
+
 pub(crate) fn tx_id_from_caller_and_input(caller: Address, input: &TxRequest) -> H256 {
     let mut hash_state = default_blake2().to_state();
     hash_state.update(caller.to_digest().as_bytes());
@@ -58,6 +71,18 @@ impl Digestible for TxRequest {
     }
 }
 
+/// The version of the layout of [`TxRequest::signing_payload`].
+pub const SIGNING_PAYLOAD_VERSION: u8 = 1;
+
+/// The length of [`TxRequest::signing_payload`] in bytes.
+pub const SIGNING_PAYLOAD_LEN: usize = 1 + 8 + 20 + 32 + 1 + 20 + 32;
+
+/// The version of the layout of [`TxRequest::signing_payload_with_fee`] with a non-zero fee.
+pub const SIGNING_PAYLOAD_WITH_FEE_VERSION: u8 = 2;
+
+/// The length of [`TxRequest::signing_payload_with_fee`] with a non-zero fee in bytes.
+pub const SIGNING_PAYLOAD_WITH_FEE_LEN: usize = SIGNING_PAYLOAD_LEN + 8;
+
 impl TxRequest {
     pub fn nonce(&self) -> Nonce {
         match self {
@@ -65,39 +90,430 @@ impl TxRequest {
         }
     }
 
-    pub fn sign(self, keypair: &Keypair) -> SignedTxRequest {
-        let hash = self.to_digest();
+    /// The canonical bytes signed by `caller` for `chain_id`, which do not depend on the serde
+    /// encoding of the tx requests. The layout of version 1 is as follows, where the integers
+    /// are big-endian.
+    ///
+    /// | Bytes     | Field                                                          |
+    /// |-----------|----------------------------------------------------------------|
+    /// | 0         | [`SIGNING_PAYLOAD_VERSION`]                                    |
+    /// | 1..9      | chain id, `u64`                                                |
+    /// | 9..29     | caller address                                                 |
+    /// | 29..61    | nonce, `U256`                                                  |
+    /// | 61        | 0 for [`TxRequest::Create`], and 1 for [`TxRequest::Call`]     |
+    /// | 62..82    | the called address, or all zeros for [`TxRequest::Create`]     |
+    /// | 82..114   | the 32-byte blake2b hash of the code or the call data          |
+    pub fn signing_payload(&self, caller: Address, chain_id: u64) -> Vec<u8> {
+        self.signing_payload_with_fee(caller, chain_id, 0)
+    }
+
+    /// The signing payload binding `fee` as well. A zero fee keeps the version 1 layout of
+    /// [`TxRequest::signing_payload`], so that the existing signatures stay valid. Otherwise,
+    /// the version byte is [`SIGNING_PAYLOAD_WITH_FEE_VERSION`], and the fee follows as a
+    /// big-endian `u64` in bytes 114..122.
+    pub fn signing_payload_with_fee(&self, caller: Address, chain_id: u64, fee: u64) -> Vec<u8> {
+        let mut out = Vec::with_capacity(SIGNING_PAYLOAD_WITH_FEE_LEN);
+        out.push(if fee == 0 {
+            SIGNING_PAYLOAD_VERSION
+        } else {
+            SIGNING_PAYLOAD_WITH_FEE_VERSION
+        });
+        out.extend_from_slice(&chain_id.to_be_bytes());
+        out.extend_from_slice(caller.as_bytes());
+        let mut nonce = [0u8; 32];
+        self.nonce().to_big_endian(&mut nonce);
+        out.extend_from_slice(&nonce);
+        let input = match self {
+            TxRequest::Create { code, .. } => {
+                out.push(0);
+                out.extend_from_slice(H160::zero().as_bytes());
+                &code[..]
+            }
+            TxRequest::Call { address, data, .. } => {
+                out.push(1);
+                out.extend_from_slice(address.as_bytes());
+                &data[..]
+            }
+        };
+        out.extend_from_slice(default_blake2().hash(input).as_bytes());
+        if fee == 0 {
+            debug_assert_eq!(SIGNING_PAYLOAD_LEN, out.len());
+        } else {
+            out.extend_from_slice(&fee.to_be_bytes());
+            debug_assert_eq!(SIGNING_PAYLOAD_WITH_FEE_LEN, out.len());
+        }
+        out
+    }
+
+    /// The 32-byte blake2b hash of [`TxRequest::signing_payload`], which is what gets signed.
+    pub fn signing_digest(&self, caller: Address, chain_id: u64) -> H256 {
+        self.signing_digest_with_fee(caller, chain_id, 0)
+    }
+
+    /// The 32-byte blake2b hash of [`TxRequest::signing_payload_with_fee`].
+    pub fn signing_digest_with_fee(&self, caller: Address, chain_id: u64, fee: u64) -> H256 {
+        let payload = self.signing_payload_with_fee(caller, chain_id, fee);
+        blake2b_hash_to_h256(default_blake2().hash(&payload))
+    }
+
+    pub fn sign(self, keypair: &Keypair, chain_id: u64) -> SignedTxRequest {
+        self.sign_with_fee(keypair, chain_id, 0)
+    }
+
+    /// Sign the tx request offering `fee` for its priority.
+    pub fn sign_with_fee(self, keypair: &Keypair, chain_id: u64, fee: u64) -> SignedTxRequest {
+        let caller = caller_address_from_pk(&keypair.public);
+        let hash = self.signing_digest_with_fee(caller, chain_id, fee);
+        SignedTxRequest {
+            input: self,
+            sig: TxSignature::Ed25519(ed25519::PubSigPair::create(keypair, hash)),
+            fee,
+        }
+    }
+
+    pub fn sign_secp256k1(self, keypair: &secp256k1::Keypair, chain_id: u64) -> SignedTxRequest {
+        self.sign_secp256k1_with_fee(keypair, chain_id, 0)
+    }
+
+    pub fn sign_secp256k1_with_fee(
+        self,
+        keypair: &secp256k1::Keypair,
+        chain_id: u64,
+        fee: u64,
+    ) -> SignedTxRequest {
+        let caller = caller_address_from_secp256k1_pk(&keypair.public);
+        let hash = self.signing_digest_with_fee(caller, chain_id, fee);
         SignedTxRequest {
             input: self,
-            pk_sig: PubSigPair::create(keypair, hash),
+            sig: TxSignature::Secp256k1(secp256k1::PubSigPair::create(keypair, hash)),
+            fee,
         }
     }
 }
 
-#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+/// How the signatures of the tx requests are verified.
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TxSigConfig {
+    /// The chain id in the signing payloads. The tx requests signed for the other chains are
+    /// rejected. Default 0.
+    pub chain_id: u64,
+    /// Whether to also accept the legacy signatures over the tx request digests, which are not
+    /// bound to any chain. Only meant for migrating the existing clients. Default false.
+    pub accept_legacy: bool,
+}
+
+static GLOBAL_TX_SIG_CONFIG_INSTALLED: AtomicBool = AtomicBool::new(false);
+static GLOBAL_CHAIN_ID: AtomicU64 = AtomicU64::new(0);
+static GLOBAL_ACCEPT_LEGACY: AtomicBool = AtomicBool::new(false);
+
+impl TxSigConfig {
+    /// Install as the config of [`SignedTxRequest::verify`] and [`verify_batch`].
+    pub fn install_as_global(self) -> Result<()> {
+        ensure!(
+            !GLOBAL_TX_SIG_CONFIG_INSTALLED.swap(true, Ordering::SeqCst),
+            "Failed to set TxSigConfig."
+        );
+        GLOBAL_CHAIN_ID.store(self.chain_id, Ordering::SeqCst);
+        GLOBAL_ACCEPT_LEGACY.store(self.accept_legacy, Ordering::SeqCst);
+        Ok(())
+    }
+
+    pub fn get() -> Self {
+        Self {
+            chain_id: GLOBAL_CHAIN_ID.load(Ordering::SeqCst),
+            accept_legacy: GLOBAL_ACCEPT_LEGACY.load(Ordering::SeqCst),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub enum SignatureScheme {
+    Ed25519,
+    Secp256k1,
+}
+
+impl fmt::Display for SignatureScheme {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Ed25519 => write!(f, "ed25519"),
+            Self::Secp256k1 => write!(f, "secp256k1"),
+        }
+    }
+}
+
+/// The public key and the signature of a [`SignedTxRequest`] in one of the
+/// [`SignatureScheme`]s.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub enum TxSignature {
+    Ed25519(ed25519::PubSigPair),
+    Secp256k1(secp256k1::PubSigPair),
+}
+
+impl TxSignature {
+    pub fn scheme(&self) -> SignatureScheme {
+        match self {
+            Self::Ed25519(_) => SignatureScheme::Ed25519,
+            Self::Secp256k1(_) => SignatureScheme::Secp256k1,
+        }
+    }
+
+    pub fn verify(&self, msg_hash: H256) -> Result<()> {
+        match self {
+            Self::Ed25519(pk_sig) => pk_sig.verify(msg_hash),
+            Self::Secp256k1(pk_sig) => pk_sig.verify(msg_hash),
+        }
+    }
+
+    /// The address of the signer, derived from the public key in the way of the scheme.
+    pub fn caller_address(&self) -> Address {
+        match self {
+            Self::Ed25519(pk_sig) => caller_address_from_pk(pk_sig.public()),
+            Self::Secp256k1(pk_sig) => caller_address_from_secp256k1_pk(pk_sig.public()),
+        }
+    }
+}
+
+impl Digestible for TxSignature {
+    fn to_digest(&self) -> H256 {
+        match self {
+            // The same as before the other schemes were added.
+            Self::Ed25519(pk_sig) => pk_sig.to_digest(),
+            Self::Secp256k1(pk_sig) => {
+                let mut hash_state = default_blake2().to_state();
+                hash_state.update(b"Secp256k1");
+                hash_state.update(pk_sig.to_digest().as_bytes());
+                blake2b_hash_to_h256(hash_state.finalize())
+            }
+        }
+    }
+}
+
+/// A signed tx request.
+///
+/// In the binary formats, the Ed25519 requests are laid out as before the other schemes were
+/// added, i.e., a [`TxRequest`] variant followed by the signature. The other schemes are then
+/// tagged by the variant indices following those of [`TxRequest`], and so are the requests with
+/// a non-zero fee in any scheme. In the human-readable formats, the signature field is named
+/// after the scheme, where the Ed25519 one keeps the name `pk_sig`, and a zero fee is omitted.
+#[derive(Debug, Clone, Eq, PartialEq)]
 pub struct SignedTxRequest {
     pub input: TxRequest,
-    pub pk_sig: PubSigPair,
+    pub sig: TxSignature,
+    /// The fee offered by the caller for the priority of the tx, which is signed along with the
+    /// input. 0 for none.
+    pub fee: u64,
+}
+
+#[derive(Deserialize)]
+#[serde(rename = "SignedTxRequest")]
+enum SignedTxRequestBinary {
+    Create {
+        nonce: Nonce,
+        code: Code,
+        pk_sig: ed25519::PubSigPair,
+    },
+    Call {
+        nonce: Nonce,
+        address: Address,
+        data: Vec<u8>,
+        pk_sig: ed25519::PubSigPair,
+    },
+    Secp256k1 {
+        input: TxRequest,
+        pk_sig: secp256k1::PubSigPair,
+    },
+    WithFee {
+        input: TxRequest,
+        sig: TxSignature,
+        fee: u64,
+    },
+}
+
+/// The index of [`SignedTxRequestBinary::Secp256k1`].
+const SECP256K1_VARIANT_INDEX: u32 = 2;
+
+/// The index of [`SignedTxRequestBinary::WithFee`].
+const WITH_FEE_VARIANT_INDEX: u32 = 3;
+
+fn is_zero(fee: &u64) -> bool {
+    *fee == 0
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename = "SignedTxRequest")]
+struct SignedTxRequestHumanReadable<I> {
+    input: I,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pk_sig: Option<ed25519::PubSigPair>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    secp256k1_sig: Option<secp256k1::PubSigPair>,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    fee: u64,
+}
+
+impl Serialize for SignedTxRequest {
+    fn serialize<S>(&self, serializer: S) -> core::result::Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        if serializer.is_human_readable() {
+            let (pk_sig, secp256k1_sig) = match self.sig {
+                TxSignature::Ed25519(pk_sig) => (Some(pk_sig), None),
+                TxSignature::Secp256k1(pk_sig) => (None, Some(pk_sig)),
+            };
+            return SignedTxRequestHumanReadable {
+                input: &self.input,
+                pk_sig,
+                secp256k1_sig,
+                fee: self.fee,
+            }
+            .serialize(serializer);
+        }
+
+        if self.fee != 0 {
+            use serde::ser::SerializeStructVariant;
+            let mut state = serializer.serialize_struct_variant(
+                "SignedTxRequest",
+                WITH_FEE_VARIANT_INDEX,
+                "WithFee",
+                3,
+            )?;
+            state.serialize_field("input", &self.input)?;
+            state.serialize_field("sig", &self.sig)?;
+            state.serialize_field("fee", &self.fee)?;
+            return state.end();
+        }
+
+        match &self.sig {
+            TxSignature::Ed25519(pk_sig) => {
+                let mut state = serializer.serialize_struct("SignedTxRequest", 2)?;
+                state.serialize_field("input", &self.input)?;
+                state.serialize_field("pk_sig", pk_sig)?;
+                state.end()
+            }
+            TxSignature::Secp256k1(pk_sig) => {
+                use serde::ser::SerializeStructVariant;
+                let mut state = serializer.serialize_struct_variant(
+                    "SignedTxRequest",
+                    SECP256K1_VARIANT_INDEX,
+                    "Secp256k1",
+                    2,
+                )?;
+                state.serialize_field("input", &self.input)?;
+                state.serialize_field("pk_sig", pk_sig)?;
+                state.end()
+            }
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for SignedTxRequest {
+    fn deserialize<D>(deserializer: D) -> core::result::Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        use serde::de::Error;
+
+        if deserializer.is_human_readable() {
+            let req = SignedTxRequestHumanReadable::<TxRequest>::deserialize(deserializer)?;
+            let sig = match (req.pk_sig, req.secp256k1_sig) {
+                (Some(pk_sig), None) => TxSignature::Ed25519(pk_sig),
+                (None, Some(pk_sig)) => TxSignature::Secp256k1(pk_sig),
+                _ => {
+                    return Err(D::Error::custom(
+                        "expected exactly one of pk_sig and secp256k1_sig",
+                    ))
+                }
+            };
+            return Ok(Self {
+                input: req.input,
+                sig,
+                fee: req.fee,
+            });
+        }
+
+        Ok(match SignedTxRequestBinary::deserialize(deserializer)? {
+            SignedTxRequestBinary::Create {
+                nonce,
+                code,
+                pk_sig,
+            } => Self {
+                input: TxRequest::Create { nonce, code },
+                sig: TxSignature::Ed25519(pk_sig),
+                fee: 0,
+            },
+            SignedTxRequestBinary::Call {
+                nonce,
+                address,
+                data,
+                pk_sig,
+            } => Self {
+                input: TxRequest::Call {
+                    nonce,
+                    address,
+                    data,
+                },
+                sig: TxSignature::Ed25519(pk_sig),
+                fee: 0,
+            },
+            SignedTxRequestBinary::Secp256k1 { input, pk_sig } => Self {
+                input,
+                sig: TxSignature::Secp256k1(pk_sig),
+                fee: 0,
+            },
+            SignedTxRequestBinary::WithFee { input, sig, fee } => Self { input, sig, fee },
+        })
+    }
 }
 
 impl Digestible for SignedTxRequest {
     fn to_digest(&self) -> H256 {
         let mut hash_state = default_blake2().to_state();
         hash_state.update(self.input.to_digest().as_bytes());
-        hash_state.update(self.pk_sig.to_digest().as_bytes());
+        hash_state.update(self.sig.to_digest().as_bytes());
+        // The same as before the fees were added for the requests without one.
+        if self.fee != 0 {
+            hash_state.update(self.fee.to_digest().as_bytes());
+        }
         let hash = hash_state.finalize();
         blake2b_hash_to_h256(hash)
     }
 }
 
 impl SignedTxRequest {
+    pub fn scheme(&self) -> SignatureScheme {
+        self.sig.scheme()
+    }
+
+    /// The digest signed for `chain_id`. See [`TxRequest::signing_payload_with_fee`].
+    pub fn signing_digest(&self, chain_id: u64) -> H256 {
+        self.input
+            .signing_digest_with_fee(self.caller_address(), chain_id, self.fee)
+    }
+
+    /// Verify the signature in its scheme with the global [`TxSigConfig`].
     pub fn verify(&self) -> Result<()> {
-        let hash = self.input.to_digest();
-        self.pk_sig.verify(hash)
+        self.verify_with(&TxSigConfig::get())
+    }
+
+    pub fn verify_with(&self, cfg: &TxSigConfig) -> Result<()> {
+        if self.sig.verify(self.signing_digest(cfg.chain_id)).is_ok() {
+            return Ok(());
+        }
+        // The legacy signatures do not cover the fee.
+        if cfg.accept_legacy && self.fee == 0 && self.sig.verify(self.input.to_digest()).is_ok() {
+            return Ok(());
+        }
+        bail!(
+            "Invalid {} signature for chain {}.",
+            self.scheme(),
+            cfg.chain_id
+        );
     }
 
     pub fn caller_address(&self) -> Address {
-        caller_address_from_pk(&self.pk_sig.public())
+        self.sig.caller_address()
     }
 
     pub fn id(&self) -> H256 {
@@ -105,10 +521,57 @@ impl SignedTxRequest {
     }
 }
 
+/// Verify the signatures of `reqs` with the global [`TxSigConfig`], and return the result of
+/// each one in the same order, so that a bad signature does not reject the others.
+///
+/// With `std`, the Ed25519 signatures are checked at once with the batch verification. If the
+/// batch fails, or for the other schemes, the signatures are checked one by one in parallel.
+pub fn verify_batch(reqs: &[SignedTxRequest]) -> Vec<Result<()>> {
+    let cfg = TxSigConfig::get();
+
+    #[cfg(feature = "std")]
+    {
+        use rayon::prelude::*;
+
+        let ed25519_verified = verify_ed25519_batch(reqs, cfg.chain_id);
+        reqs.par_iter()
+            .map(|req| match req.sig {
+                TxSignature::Ed25519(_) if ed25519_verified => Ok(()),
+                _ => req.verify_with(&cfg),
+            })
+            .collect()
+    }
+
+    #[cfg(not(feature = "std"))]
+    {
+        reqs.iter().map(|req| req.verify_with(&cfg)).collect()
+    }
+}
+
+/// Whether all the Ed25519 signatures in `reqs` for `chain_id` pass the batch verification.
+/// Return false if there are not enough of them to be worth it.
+#[cfg(feature = "std")]
+fn verify_ed25519_batch(reqs: &[SignedTxRequest], chain_id: u64) -> bool {
+    let mut hashes = Vec::with_capacity(reqs.len());
+    let mut sigs = Vec::with_capacity(reqs.len());
+    let mut pks = Vec::with_capacity(reqs.len());
+    for req in reqs {
+        if let TxSignature::Ed25519(pk_sig) = &req.sig {
+            hashes.push(req.signing_digest(chain_id));
+            sigs.push(pk_sig.sig);
+            pks.push(pk_sig.pk);
+        }
+    }
+    if hashes.len() < 2 {
+        return false;
+    }
+    let msgs: Vec<&[u8]> = hashes.iter().map(|hash| hash.as_bytes()).collect();
+    ed25519::ed25519_dalek::verify_batch(&msgs[..], &sigs[..], &pks[..]).is_ok()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::basic::H160;
 
     #[test]
     fn test_sign_verify_tx_req() {
@@ -120,7 +583,7 @@ mod tests {
 
         let mut rng = rand::thread_rng();
         let keypair = Keypair::generate(&mut rng);
-        let signed_tx_req = tx_req.sign(&keypair);
+        let signed_tx_req = tx_req.sign(&keypair, 0);
         signed_tx_req.verify().unwrap();
     }
 
@@ -134,12 +597,364 @@ mod tests {
 
         let mut rng = rand::thread_rng();
         let keypair = Keypair::generate(&mut rng);
-        let signed_tx_req = tx_req.sign(&keypair);
+        let signed_tx_req = tx_req.sign(&keypair, 0);
+
+        let bin = postcard::to_allocvec(&signed_tx_req).unwrap();
+        assert_eq!(
+            postcard::from_bytes::<SignedTxRequest>(&bin[..]).unwrap(),
+            signed_tx_req
+        );
+    }
+
+    fn call_tx_req() -> TxRequest {
+        TxRequest::Call {
+            nonce: 1.into(),
+            address: H160::repeat_byte(0xf).into(),
+            data: b"data".to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_sign_verify_secp256k1_tx_req() {
+        let keypair = secp256k1::random_keypair();
+        let signed_tx_req = call_tx_req().sign_secp256k1(&keypair, 0);
+        signed_tx_req.verify().unwrap();
+        assert_eq!(SignatureScheme::Secp256k1, signed_tx_req.scheme());
+        assert_eq!(
+            caller_address_from_secp256k1_pk(&keypair.public),
+            signed_tx_req.caller_address()
+        );
+
+        let mut tampered = signed_tx_req;
+        tampered.input = TxRequest::Call {
+            nonce: 2.into(),
+            address: H160::repeat_byte(0xf).into(),
+            data: b"data".to_vec(),
+        };
+        assert!(tampered.verify().is_err());
+    }
+
+    #[test]
+    fn test_secp256k1_caller_address() {
+        // The well-known Ethereum account of the secret key 0x00..01.
+        let mut secret = [0u8; 32];
+        secret[31] = 1;
+        let keypair = secp256k1::Keypair::from_secret_bytes(&secret).unwrap();
+        assert_eq!(
+            Address::from(H160::from_slice(
+                &hex::decode("7e5f4552091a69125d5dfcb7b8c2659029395bdf").unwrap()
+            )),
+            caller_address_from_secp256k1_pk(&keypair.public)
+        );
+    }
+
+    #[test]
+    fn test_signing_payload() {
+        let caller = Address::from(H160::repeat_byte(0x11));
+        let payload = call_tx_req().signing_payload(caller, 42);
+        assert_eq!(
+            "01000000000000002a1111111111111111111111111111111111111111\
+             0000000000000000000000000000000000000000000000000000000000000001\
+             010f0f0f0f0f0f0f0f0f0f0f0f0f0f0f0f0f0f0f0f\
+             a035872d6af8639ede962dfe7536b0c150b590f3234a922fb7064cd11971b58e",
+            hex::encode(&payload)
+        );
+        assert_eq!(
+            "8382dbb5ffd25ab7b62d60093732d3fa1b194660979bb8e031ae6a9bda3e221b",
+            hex::encode(call_tx_req().signing_digest(caller, 42))
+        );
+
+        let create_tx_req = TxRequest::Create {
+            nonce: 0.into(),
+            code: b"code".to_vec().into(),
+        };
+        assert_eq!(
+            "01000000000000002a1111111111111111111111111111111111111111\
+             0000000000000000000000000000000000000000000000000000000000000000\
+             000000000000000000000000000000000000000000\
+             6470fd21983eae8d706f1edd5e2dc5afe095980f8fb7bd4ebfd33550d8730246",
+            hex::encode(create_tx_req.signing_payload(caller, 42))
+        );
+        assert_eq!(
+            "3a40f8eebeec741106be251668ee9e2423d05d254be61a4a04a8f57eb842bd14",
+            hex::encode(create_tx_req.signing_digest(caller, 42))
+        );
+    }
+
+    #[test]
+    fn test_chain_id_mismatch() {
+        let cfg = TxSigConfig {
+            chain_id: 42,
+            accept_legacy: false,
+        };
+        let ed25519_req = call_tx_req().sign(&Keypair::generate(&mut rand::thread_rng()), 42);
+        let secp256k1_req = call_tx_req().sign_secp256k1(&secp256k1::random_keypair(), 42);
+        for req in &[ed25519_req, secp256k1_req] {
+            req.verify_with(&cfg).unwrap();
+            assert!(req
+                .verify_with(&TxSigConfig {
+                    chain_id: 43,
+                    ..cfg
+                })
+                .is_err());
+        }
+    }
+
+    #[test]
+    fn test_legacy_sig() {
+        let keypair = Keypair::generate(&mut rand::thread_rng());
+        let legacy = SignedTxRequest {
+            input: call_tx_req(),
+            sig: TxSignature::Ed25519(ed25519::PubSigPair::create(
+                &keypair,
+                call_tx_req().to_digest(),
+            )),
+            fee: 0,
+        };
+        let mut cfg = TxSigConfig {
+            chain_id: 42,
+            accept_legacy: false,
+        };
+        assert!(legacy.verify_with(&cfg).is_err());
+        cfg.accept_legacy = true;
+        legacy.verify_with(&cfg).unwrap();
+        call_tx_req().sign(&keypair, 42).verify_with(&cfg).unwrap();
+    }
 
+    #[derive(Serialize)]
+    struct LegacySignedTxRequest<'a> {
+        input: &'a TxRequest,
+        pk_sig: &'a ed25519::PubSigPair,
+    }
+
+    #[test]
+    fn test_tx_req_serde_compat() {
+        let keypair = Keypair::generate(&mut rand::thread_rng());
+        for tx_req in [
+            call_tx_req(),
+            TxRequest::Create {
+                nonce: 1.into(),
+                code: b"code".to_vec().into(),
+            },
+        ] {
+            let signed_tx_req = tx_req.sign(&keypair, 0);
+            let pk_sig = match &signed_tx_req.sig {
+                TxSignature::Ed25519(pk_sig) => pk_sig,
+                _ => unreachable!(),
+            };
+            let legacy = postcard::to_allocvec(&LegacySignedTxRequest {
+                input: &signed_tx_req.input,
+                pk_sig,
+            })
+            .unwrap();
+            assert_eq!(legacy, postcard::to_allocvec(&signed_tx_req).unwrap());
+            assert_eq!(
+                postcard::from_bytes::<SignedTxRequest>(&legacy[..]).unwrap(),
+                signed_tx_req
+            );
+        }
+
+        let signed_tx_req = call_tx_req().sign_secp256k1(&secp256k1::random_keypair(), 0);
         let bin = postcard::to_allocvec(&signed_tx_req).unwrap();
         assert_eq!(
             postcard::from_bytes::<SignedTxRequest>(&bin[..]).unwrap(),
             signed_tx_req
         );
     }
+
+    #[test]
+    fn test_tx_req_json() {
+        let signed_tx_req = call_tx_req().sign(&Keypair::generate(&mut rand::thread_rng()), 0);
+        let json = serde_json::to_value(&signed_tx_req).unwrap();
+        assert!(json.get("pk_sig").is_some());
+        assert!(json.get("secp256k1_sig").is_none());
+
+        let signed_tx_req = call_tx_req().sign_secp256k1(&secp256k1::random_keypair(), 0);
+        let json = serde_json::to_string(&signed_tx_req).unwrap();
+        assert_eq!(
+            serde_json::from_str::<SignedTxRequest>(&json).unwrap(),
+            signed_tx_req
+        );
+        assert!(
+            serde_json::from_str::<SignedTxRequest>(&json.replace("secp256k1_sig", "unknown"))
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_tx_req_fee() {
+        let caller = Address::from(H160::repeat_byte(0x11));
+        let payload = call_tx_req().signing_payload_with_fee(caller, 42, 0x0102);
+        assert_eq!(SIGNING_PAYLOAD_WITH_FEE_LEN, payload.len());
+        assert_eq!(SIGNING_PAYLOAD_WITH_FEE_VERSION, payload[0]);
+        assert_eq!(
+            &call_tx_req().signing_payload(caller, 42)[1..],
+            &payload[1..SIGNING_PAYLOAD_LEN]
+        );
+        assert_eq!(
+            "0000000000000102",
+            hex::encode(&payload[SIGNING_PAYLOAD_LEN..])
+        );
+        assert_eq!(
+            call_tx_req().signing_payload(caller, 42),
+            call_tx_req().signing_payload_with_fee(caller, 42, 0)
+        );
+
+        let keypair = Keypair::generate(&mut rand::thread_rng());
+        let reqs = [
+            call_tx_req().sign_with_fee(&keypair, 0, 100),
+            call_tx_req().sign_secp256k1_with_fee(&secp256k1::random_keypair(), 0, 100),
+        ];
+        for req in &reqs {
+            req.verify().unwrap();
+            assert_eq!(100, req.fee);
+
+            // The fee cannot be inflated, or stripped to pass as a legacy signature.
+            let mut tampered = req.clone();
+            tampered.fee = 1_000;
+            assert!(tampered.verify().is_err());
+            tampered.fee = 0;
+            assert!(tampered
+                .verify_with(&TxSigConfig {
+                    chain_id: 0,
+                    accept_legacy: true,
+                })
+                .is_err());
+            assert_ne!(req.to_digest(), tampered.to_digest());
+            assert_eq!(req.id(), tampered.id());
+
+            let bin = postcard::to_allocvec(req).unwrap();
+            assert_eq!(WITH_FEE_VARIANT_INDEX as u8, bin[0]);
+            assert_eq!(
+                &postcard::from_bytes::<SignedTxRequest>(&bin[..]).unwrap(),
+                req
+            );
+
+            let json = serde_json::to_value(req).unwrap();
+            assert_eq!(Some(100), json.get("fee").and_then(|fee| fee.as_u64()));
+            assert_eq!(
+                &serde_json::from_value::<SignedTxRequest>(json).unwrap(),
+                req
+            );
+        }
+
+        let json = serde_json::to_value(&call_tx_req().sign(&keypair, 0)).unwrap();
+        assert!(json.get("fee").is_none());
+    }
+
+    #[test]
+    fn test_cross_scheme_tx_req() {
+        use core::convert::TryFrom;
+
+        let ed25519_req = call_tx_req().sign(&Keypair::generate(&mut rand::thread_rng()), 0);
+        let secp256k1_req = call_tx_req().sign_secp256k1(&secp256k1::random_keypair(), 0);
+        assert_ne!(ed25519_req.to_digest(), secp256k1_req.to_digest());
+        assert_ne!(ed25519_req.caller_address(), secp256k1_req.caller_address());
+        let (ed25519_pk_sig, secp256k1_pk_sig) = match (ed25519_req.sig, secp256k1_req.sig) {
+            (TxSignature::Ed25519(a), TxSignature::Secp256k1(b)) => (a, b),
+            _ => unreachable!(),
+        };
+
+        // The secp256k1 signature presented under the Ed25519 tag is either not decoded or
+        // not verified.
+        let pk = PublicKey::from_bytes(&secp256k1_pk_sig.pk.serialize_compressed()[1..]);
+        let sig = ed25519::Signature::try_from(&secp256k1_pk_sig.sig.serialize()[..]);
+        if let (Ok(pk), Ok(sig)) = (pk, sig) {
+            let forged = SignedTxRequest {
+                input: call_tx_req(),
+                sig: TxSignature::Ed25519(ed25519::PubSigPair { pk, sig }),
+                fee: 0,
+            };
+            assert!(forged.verify().is_err());
+        }
+
+        // So is the Ed25519 signature presented under the secp256k1 tag.
+        let mut pk_bytes = [0x02; secp256k1::PUBLIC_KEY_LENGTH];
+        pk_bytes[1..].copy_from_slice(&ed25519_pk_sig.pk.to_bytes()[..]);
+        let pk = secp256k1::PublicKey::parse_compressed(&pk_bytes);
+        let sig = secp256k1::Signature::parse_standard(&ed25519_pk_sig.sig.to_bytes());
+        if let (Ok(pk), Ok(sig)) = (pk, sig) {
+            let forged = SignedTxRequest {
+                input: call_tx_req(),
+                sig: TxSignature::Secp256k1(secp256k1::PubSigPair { pk, sig }),
+                fee: 0,
+            };
+            assert!(forged.verify().is_err());
+        }
+
+        // Swapping the tags of the genuine ones changes the signed content.
+        let mut bin = postcard::to_allocvec(&secp256k1_req).unwrap();
+        assert_eq!(SECP256K1_VARIANT_INDEX as u8, bin[0]);
+        bin[0] = 1;
+        if let Ok(forged) = postcard::from_bytes::<SignedTxRequest>(&bin[..]) {
+            assert_eq!(SignatureScheme::Ed25519, forged.scheme());
+            assert!(forged.verify().is_err());
+        }
+    }
+
+    #[test]
+    fn test_verify_batch() {
+        let keypair = Keypair::generate(&mut rand::thread_rng());
+        let secp256k1_keypair = secp256k1::random_keypair();
+        let mut reqs: Vec<SignedTxRequest> = (0..16u64)
+            .map(|i| {
+                let tx_req = TxRequest::Call {
+                    nonce: i.into(),
+                    address: H160::repeat_byte(0xf).into(),
+                    data: b"data".to_vec(),
+                };
+                if i % 4 == 0 {
+                    tx_req.sign_secp256k1(&secp256k1_keypair, 0)
+                } else {
+                    tx_req.sign(&keypair, 0)
+                }
+            })
+            .collect();
+        assert!(verify_batch(&reqs).iter().all(Result::is_ok));
+
+        // The tampered ones fail alone.
+        reqs[3].input = call_tx_req();
+        reqs[4].input = call_tx_req();
+        let results = verify_batch(&reqs);
+        for (i, res) in results.iter().enumerate() {
+            assert_eq!(i == 3 || i == 4, res.is_err(), "{}", i);
+        }
+        assert!(verify_batch(&[]).is_empty());
+        assert!(verify_batch(&reqs[3..4])[0].is_err());
+    }
+
+    /// The cost of verifying 1k signatures one by one and in a batch. Run by
+    /// `cargo test -p slimchain-common --release bench_verify_batch -- --ignored --nocapture`.
+    #[cfg(feature = "std")]
+    #[test]
+    #[ignore]
+    fn bench_verify_batch() {
+        use std::time::Instant;
+
+        let keypair = Keypair::generate(&mut rand::thread_rng());
+        let reqs: Vec<SignedTxRequest> = (0..1000u64)
+            .map(|i| {
+                TxRequest::Call {
+                    nonce: i.into(),
+                    address: H160::repeat_byte(0xf).into(),
+                    data: b"data".to_vec(),
+                }
+                .sign(&keypair, 0)
+            })
+            .collect();
+
+        let begin = Instant::now();
+        let sequential: Vec<_> = reqs.iter().map(SignedTxRequest::verify).collect();
+        let sequential_time = begin.elapsed();
+        let begin = Instant::now();
+        let batch = verify_batch(&reqs);
+        let batch_time = begin.elapsed();
+        std::println!(
+            "verify 1k signatures. sequential: {:?}, batch: {:?}",
+            sequential_time,
+            batch_time
+        );
+        assert!(sequential.iter().all(Result::is_ok));
+        assert!(batch.iter().all(Result::is_ok));
+    }
 }
@@ -25,9 +25,21 @@ macro_rules! create_id_type {
         pub struct $name(pub $inner_type);
 
         impl $name {
-            pub fn next_id() -> Self {
+            fn id_counter() -> &'static $atomic_type {
                 static ID_CNT: $atomic_type = <$atomic_type>::new(0);
-                Self(ID_CNT.fetch_add(1, core::sync::atomic::Ordering::SeqCst))
+                &ID_CNT
+            }
+
+            pub fn next_id() -> Self {
+                Self(Self::id_counter().fetch_add(1, core::sync::atomic::Ordering::SeqCst))
+            }
+
+            /// Restart the ids from 0, so that the tests asserting on specific ids do not depend
+            /// on the ids taken by the others.
+            #[cfg(test)]
+            #[allow(dead_code)]
+            pub fn reset_for_test() {
+                Self::id_counter().store(0, core::sync::atomic::Ordering::SeqCst);
             }
         }
     };
@@ -221,6 +233,24 @@ macro_rules! create_tx_read_data {
 
 #[cfg(test)]
 mod tests {
+    create_id_type_u64!(TestId);
+
+    #[test]
+    fn test_create_id_type() {
+        TestId::reset_for_test();
+        assert_eq!(TestId(0), TestId::next_id());
+        assert_eq!(TestId(1), TestId::next_id());
+        TestId::reset_for_test();
+        assert_eq!(TestId(0), TestId::next_id());
+
+        let id = TestId(u64::from(u32::MAX) + 1);
+        assert_eq!("4294967296", alloc::format!("{}", id));
+        let json = serde_json::to_string(&id).unwrap();
+        assert_eq!(id, serde_json::from_str::<TestId>(&json).unwrap());
+        let bin = postcard::to_allocvec(&id).unwrap();
+        assert_eq!(id, postcard::from_bytes::<TestId>(&bin).unwrap());
+    }
+
     #[test]
     fn test_create_tx_write_set() {
         let _ = create_tx_write_set! {
@@ -1,2 +1,3 @@
+pub mod observer;
 pub mod pow;
 pub mod raft;
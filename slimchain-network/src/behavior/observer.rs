@@ -0,0 +1,121 @@
+use futures::{
+    channel::{mpsc, oneshot},
+    prelude::*,
+};
+use serde::{Deserialize, Serialize};
+use slimchain_chain::{
+    behavior::{commit_block_observer_node, verify_block_observer_node},
+    block::BlockTrait,
+    block_proposal::BlockProposal,
+    config::ObserverConfig,
+    db::DBPtr,
+    latest::{LatestBlockHeaderPtr, LatestTxCountPtr},
+    loader::BlockLoaderTrait,
+};
+use slimchain_common::{
+    error::{bail, Result},
+    tx::TxTrait,
+};
+use slimchain_utils::ordered_stream::OrderedStream;
+use tokio::task::JoinHandle;
+
+/// Load the latest block followed by the observer node.
+pub fn load_observer_latest_block<Block>(db: &DBPtr) -> Result<Block>
+where
+    Block: BlockTrait + for<'de> Deserialize<'de>,
+{
+    match db.get_latest_block_header()? {
+        Some(header) => db.get_block(header.height),
+        None => Ok(Block::genesis_block()),
+    }
+}
+
+/// Import the block proposals on observer nodes by verifying the consensus and the
+/// header chaining only.
+pub struct ObserverImportWorker<Block: BlockTrait + 'static, Tx: TxTrait + 'static> {
+    handle: Option<JoinHandle<()>>,
+    blk_tx: mpsc::UnboundedSender<BlockProposal<Block, Tx>>,
+    shutdown_tx: Option<oneshot::Sender<()>>,
+}
+
+impl<Block, Tx> ObserverImportWorker<Block, Tx>
+where
+    Block: BlockTrait + Serialize + 'static,
+    Tx: TxTrait + Serialize + 'static,
+{
+    pub fn new(
+        observer_cfg: ObserverConfig,
+        mut last_block: Block,
+        latest_block_header: LatestBlockHeaderPtr,
+        latest_tx_count: LatestTxCountPtr,
+        db: DBPtr,
+        verify_consensus_fn: fn(&Block, &Block) -> Result<()>,
+    ) -> Self {
+        let (blk_tx, blk_rx) = mpsc::unbounded::<BlockProposal<Block, Tx>>();
+        let mut blk_rx = OrderedStream::new(
+            blk_rx.map(|blk| (blk.get_block_height(), blk)),
+            last_block.block_height().next_height(),
+            |height| height.next_height(),
+        );
+        let (shutdown_tx, mut shutdown_rx) = oneshot::channel();
+
+        let handle: JoinHandle<()> = tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    _ = &mut shutdown_rx => break,
+                    Some(blk_proposal) = blk_rx.next() => {
+                        if let Err(e) = verify_block_observer_node(&last_block, &blk_proposal, verify_consensus_fn) {
+                            error!("Failed to import block. Error: {}", e);
+                            continue;
+                        }
+
+                        if let Err(e) = commit_block_observer_node(
+                            &observer_cfg,
+                            &blk_proposal,
+                            &db,
+                            &latest_block_header,
+                            &latest_tx_count,
+                        )
+                        .await
+                        {
+                            panic!("Failed to commit the block. Error: {}", e);
+                        }
+
+                        last_block = blk_proposal.get_block().clone();
+                    }
+                }
+            }
+        });
+
+        Self {
+            handle: Some(handle),
+            blk_tx,
+            shutdown_tx: Some(shutdown_tx),
+        }
+    }
+
+    pub fn get_blk_tx(&self) -> mpsc::UnboundedSender<BlockProposal<Block, Tx>> {
+        self.blk_tx.clone()
+    }
+
+    pub fn add_block_proposal(&mut self, block_proposal: BlockProposal<Block, Tx>) {
+        if let Err(e) = self.blk_tx.start_send(block_proposal) {
+            error!("Failed to send block proposal. Error: {}", e);
+        }
+    }
+
+    pub async fn shutdown(&mut self) -> Result<()> {
+        self.blk_tx.close_channel();
+        if let Some(shutdown_tx) = self.shutdown_tx.take() {
+            shutdown_tx.send(()).ok();
+        } else {
+            bail!("Already shutdown.");
+        }
+        if let Some(handler) = self.handle.take() {
+            handler.await?;
+        } else {
+            bail!("Already shutdown.");
+        }
+        Ok(())
+    }
+}
@@ -4,6 +4,9 @@ pub use client::*;
 pub mod miner;
 pub use miner::*;
 
+pub mod observer;
+pub use observer::*;
+
 pub mod storage;
 pub use storage::*;
 
@@ -14,13 +17,16 @@ use futures::{
 };
 use serde::Serialize;
 use slimchain_chain::{
-    behavior::{commit_block, commit_block_storage_node, propose_block, verify_block},
+    behavior::{
+        commit_block, commit_block_storage_node, propose_block, verify_block, verify_tx_gas,
+    },
     block_proposal::BlockProposal,
     config::{ChainConfig, MinerConfig},
     consensus::pow::{create_new_block, verify_consensus, Block},
     db::{DBPtr, Transaction as DBTx},
     latest::{LatestBlockHeaderPtr, LatestTxCountPtr},
     snapshot::Snapshot,
+    tx_queue::PendingTxQueue,
 };
 use slimchain_common::{
     error::{bail, Result},
@@ -64,13 +70,19 @@ impl<Tx: TxTrait + Serialize> BlockImportWorker<Tx> {
                     _ = &mut shutdown_rx => break,
                     Some(blk_proposal) = blk_rx.next() => {
                         let snapshot_backup = snapshot.clone();
-                        let state_update = match verify_block(
+                        let res = match verify_block(
                             &chain_cfg,
                             &mut snapshot,
                             &blk_proposal,
                             verify_consensus,
                         ).await
                         {
+                            Ok(state_update) if storage_node && chain_cfg.full_validation => {
+                                verify_tx_gas(&db, &blk_proposal).await.map(|_| state_update)
+                            }
+                            res => res,
+                        };
+                        let state_update = match res {
                             Ok(state_update) => state_update,
                             Err(e) => {
                                 error!("Failed to import block. Error: {}", e);
@@ -81,6 +93,7 @@ impl<Tx: TxTrait + Serialize> BlockImportWorker<Tx> {
 
                         let commit_res = if storage_node {
                             commit_block_storage_node(
+                                &chain_cfg,
                                 &blk_proposal,
                                 &state_update,
                                 &db,
@@ -89,8 +102,14 @@ impl<Tx: TxTrait + Serialize> BlockImportWorker<Tx> {
                             )
                             .await
                         } else {
-                            commit_block(&blk_proposal, &db, &latest_block_header, &latest_tx_count)
-                                .await
+                            commit_block(
+                                &chain_cfg,
+                                &blk_proposal,
+                                &db,
+                                &latest_block_header,
+                                &latest_tx_count,
+                            )
+                            .await
                         };
 
                         if let Err(e) = commit_res {
@@ -147,19 +166,22 @@ pub struct BlockProposalWorker<Tx: TxTrait + 'static> {
 impl<Tx: TxTrait + Serialize> BlockProposalWorker<Tx> {
     pub fn new(
         chain_cfg: ChainConfig,
-        miner_cfg: MinerConfig,
+        mut miner_cfg: MinerConfig,
         mut snapshot: Snapshot<Block, TxTrie>,
         latest_block_header: LatestBlockHeaderPtr,
         latest_tx_count: LatestTxCountPtr,
         db: DBPtr,
     ) -> Self {
         let (tx_tx, tx_rx) = mpsc::unbounded::<TxProposal<Tx>>();
-        let mut tx_rx = tx_rx.fuse().peekable();
+        let mut tx_rx = PendingTxQueue::new(tx_rx, latest_block_header.get_height())
+            .with_nonce_ordering(miner_cfg.nonce_ordering)
+            .peekable();
 
         let (mut blk_tx, blk_rx) = mpsc::unbounded::<BlockProposal<Block, Tx>>();
         let blk_rx = blk_rx.fuse();
 
         let (shutdown_tx, mut shutdown_rx) = oneshot::channel();
+        let block_limits = miner_cfg.watch_block_limits();
 
         let handle: JoinHandle<()> = tokio::spawn(async move {
             loop {
@@ -173,15 +195,21 @@ impl<Tx: TxTrait + Serialize> BlockProposalWorker<Tx> {
                 }
 
                 let snapshot_backup = snapshot.clone();
-                let blk_proposal = match propose_block(
+                miner_cfg.set_block_limits(*block_limits.borrow());
+                let mut deferred_tx_proposals = Vec::new();
+                let blk_proposal = propose_block(
                     &chain_cfg,
                     &miner_cfg,
                     &mut snapshot,
                     &mut tx_rx,
+                    &mut deferred_tx_proposals,
+                    false,
                     create_new_block,
                 )
-                .await
-                {
+                .await;
+                tx_rx.get_mut().requeue(deferred_tx_proposals);
+
+                let blk_proposal = match blk_proposal {
                     Ok(blk_proposal) => blk_proposal,
                     Err(e) => {
                         snapshot_backup.write_async(&db).await.ok();
@@ -191,13 +219,24 @@ impl<Tx: TxTrait + Serialize> BlockProposalWorker<Tx> {
 
                 match blk_proposal {
                     Some(blk_proposal) => {
-                        if let Err(e) =
-                            commit_block(&blk_proposal, &db, &latest_block_header, &latest_tx_count)
-                                .await
+                        if let Err(e) = commit_block(
+                            &chain_cfg,
+                            &blk_proposal,
+                            &db,
+                            &latest_block_header,
+                            &latest_tx_count,
+                        )
+                        .await
                         {
                             snapshot_backup.write_async(&db).await.ok();
                             panic!("Failed to commit the new block. Error: {}", e);
                         }
+                        tx_rx.get_mut().settle_popped();
+                        if let Some(max_age) = miner_cfg.max_tx_age_blocks {
+                            tx_rx
+                                .get_mut()
+                                .purge_expired(blk_proposal.get_block_height(), max_age);
+                        }
                         if let Err(e) = blk_tx.start_send(blk_proposal) {
                             snapshot_backup.write_async(&db).await.ok();
                             panic!("Failed to send the block proposal. Error: {}", e);
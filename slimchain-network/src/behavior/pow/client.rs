@@ -2,6 +2,7 @@ use super::BlockImportWorker;
 use crate::p2p::{
     config::NetworkConfig,
     control::Shutdown,
+    direct_query::{DirectQuery, DirectQueryEvent, DirectQueryHandle},
     discovery::{Discovery, DiscoveryEvent, QueryId as DiscoveryQueryId},
     http::{ClientHttpServer, TxHttpRequest},
     pubsub::{PubSub, PubSubEvent, PubSubTopic},
@@ -15,7 +16,7 @@ use libp2p::{swarm::NetworkBehaviourEventProcess, NetworkBehaviour, PeerId};
 use serde::Serialize;
 use slimchain_chain::{
     block_proposal::BlockProposal, config::ChainConfig, consensus::pow::Block, db::DBPtr,
-    latest::LatestTxCount, role::Role, snapshot::Snapshot,
+    latest::LatestTxCount, metrics::CHAIN_METRICS, role::Role, snapshot::Snapshot,
 };
 use slimchain_common::{
     basic::H256, collections::HashMap, error::Result, tx::TxTrait, tx_req::SignedTxRequest,
@@ -28,6 +29,7 @@ use std::time::Duration;
 pub struct ClientBehavior<Tx: TxTrait + Serialize + 'static> {
     discv: Discovery,
     pubsub: PubSub<TxProposal<Tx>, BlockProposal<Block, Tx>>,
+    direct_query: DirectQuery<Block>,
     http_server: ClientHttpServer,
     rpc_client: RpcInstant<SignedTxRequest, ()>,
     #[behaviour(ignore)]
@@ -46,7 +48,14 @@ impl<Tx: TxTrait + Serialize + 'static> ClientBehavior<Tx> {
         let mut discv = Discovery::new(keypair.public(), Role::Client, net_cfg.mdns).await?;
         discv.add_address_from_net_config(net_cfg);
         let mut pubsub = PubSub::new(keypair, &[PubSubTopic::BlockProposal], &[])?;
+        pubsub.set_compression(net_cfg.pubsub_compression);
+        pubsub.set_peer_score_config(net_cfg.peer_score);
+        pubsub.authorize_peers_from_net_config(net_cfg);
+        pubsub.set_metrics_interval(net_cfg.pubsub_metrics_interval);
+        pubsub.register_prometheus_collector();
         pubsub.add_peers_from_net_config(net_cfg);
+        let mut direct_query = DirectQuery::new(Role::Client, db.clone(), &net_cfg.direct_query);
+        direct_query.add_peers_from_net_config(net_cfg);
         let mut rpc_client = create_request_response_client("/tx_req/1");
 
         for peer in &net_cfg.peers {
@@ -68,15 +77,18 @@ impl<Tx: TxTrait + Serialize + 'static> ClientBehavior<Tx> {
             |snapshot| snapshot.write_db_tx(),
         );
 
-        let http_server = ClientHttpServer::new(
+        let http_server = ClientHttpServer::new_with_health(
             &net_cfg.http_listen,
+            peer_id,
             move || latest_tx_count.get(),
             move || latest_block_header.get_height(),
+            pubsub.shared_peers_report(),
         )?;
 
         Ok(Self {
             discv,
             pubsub,
+            direct_query,
             http_server,
             rpc_client,
             worker,
@@ -85,6 +97,11 @@ impl<Tx: TxTrait + Serialize + 'static> ClientBehavior<Tx> {
         })
     }
 
+    /// The handle to query the peers directly from outside of the swarm.
+    pub fn direct_query_handle(&self) -> DirectQueryHandle<Block> {
+        self.direct_query.handle()
+    }
+
     pub fn discv_mut(&mut self) -> &mut Discovery {
         &mut self.discv
     }
@@ -105,6 +122,12 @@ impl<Tx: TxTrait + Serialize> NetworkBehaviourEventProcess<TxHttpRequest> for Cl
     }
 }
 
+impl<Tx: TxTrait + Serialize> NetworkBehaviourEventProcess<DirectQueryEvent>
+    for ClientBehavior<Tx>
+{
+    fn inject_event(&mut self, _: DirectQueryEvent) {}
+}
+
 impl<Tx: TxTrait + Serialize> NetworkBehaviourEventProcess<DiscoveryEvent> for ClientBehavior<Tx> {
     fn inject_event(&mut self, event: DiscoveryEvent) {
         match event {
@@ -117,7 +140,8 @@ impl<Tx: TxTrait + Serialize> NetworkBehaviourEventProcess<DiscoveryEvent> for C
 
                 match peer {
                     Ok(peer_id) => {
-                        record_event!("tx_begin", "tx_id": tx_req.id());
+                        record_event!("tx_begin", "tx_id": tx_req_id);
+                        CHAIN_METRICS.record_tx_begin(tx_req_id);
                         let rpc_query_id = self.rpc_client.send_request(&peer_id, tx_req);
                         self.pending_rpc_queries.insert(rpc_query_id, tx_req_id);
                     }
@@ -158,7 +182,7 @@ impl<Tx: TxTrait + Serialize>
     for ClientBehavior<Tx>
 {
     fn inject_event(&mut self, event: PubSubEvent<TxProposal<Tx>, BlockProposal<Block, Tx>>) {
-        if let PubSubEvent::BlockProposal(input) = event {
+        if let PubSubEvent::BlockProposal(input, _) = event {
             trace!(
                 height = input.get_block_height().0,
                 txs = input.get_txs().len(),
@@ -2,8 +2,9 @@ use super::BlockProposalWorker;
 use crate::p2p::{
     config::NetworkConfig,
     control::Shutdown,
+    direct_query::{DirectQuery, DirectQueryEvent, DirectQueryHandle},
     discovery::{Discovery, DiscoveryEvent},
-    pubsub::{PubSub, PubSubEvent, PubSubTopic},
+    pubsub::{MessageOutcome, PubSub, PubSubEvent, PubSubTopic},
 };
 use async_trait::async_trait;
 use libp2p::{
@@ -30,6 +31,7 @@ use std::task::{Context, Poll};
 pub struct MinerBehavior<Tx: TxTrait + Serialize + 'static> {
     discv: Discovery,
     pubsub: PubSub<TxProposal<Tx>, BlockProposal<Block, Tx>>,
+    direct_query: DirectQuery<Block>,
     #[behaviour(ignore)]
     worker: BlockProposalWorker<Tx>,
 }
@@ -44,7 +46,14 @@ impl<Tx: TxTrait + Serialize + 'static> MinerBehavior<Tx> {
         let keypair = net_cfg.keypair.to_libp2p_keypair();
         let mut discv = Discovery::new(keypair.public(), Role::Miner, net_cfg.mdns).await?;
         discv.add_address_from_net_config(net_cfg);
-        let pubsub = PubSub::new(keypair, &[PubSubTopic::TxProposal], &[])?;
+        let mut pubsub = PubSub::new(keypair, &[PubSubTopic::TxProposal], &[])?;
+        pubsub.set_compression(net_cfg.pubsub_compression);
+        pubsub.set_peer_score_config(net_cfg.peer_score);
+        pubsub.authorize_peers_from_net_config(net_cfg);
+        pubsub.set_metrics_interval(net_cfg.pubsub_metrics_interval);
+        pubsub.register_prometheus_collector();
+        let mut direct_query = DirectQuery::new(Role::Miner, db.clone(), &net_cfg.direct_query);
+        direct_query.add_peers_from_net_config(net_cfg);
         let snapshot = Snapshot::<Block, TxTrie>::load_from_db(&db, chain_cfg.state_len)?;
         let latest_block_header = snapshot.to_latest_block_header();
         let latest_tx_count = LatestTxCount::new(0);
@@ -60,10 +69,20 @@ impl<Tx: TxTrait + Serialize + 'static> MinerBehavior<Tx> {
         Ok(Self {
             discv,
             pubsub,
+            direct_query,
             worker,
         })
     }
 
+    pub fn discv_mut(&mut self) -> &mut Discovery {
+        &mut self.discv
+    }
+
+    /// The handle to query the peers directly from outside of the swarm.
+    pub fn direct_query_handle(&self) -> DirectQueryHandle<Block> {
+        self.direct_query.handle()
+    }
+
     fn poll_inner<T>(
         &mut self,
         cx: &mut Context,
@@ -79,6 +98,10 @@ impl<Tx: TxTrait + Serialize + 'static> MinerBehavior<Tx> {
     }
 }
 
+impl<Tx: TxTrait + Serialize> NetworkBehaviourEventProcess<DirectQueryEvent> for MinerBehavior<Tx> {
+    fn inject_event(&mut self, _: DirectQueryEvent) {}
+}
+
 impl<Tx: TxTrait + Serialize> NetworkBehaviourEventProcess<DiscoveryEvent> for MinerBehavior<Tx> {
     fn inject_event(&mut self, _: DiscoveryEvent) {}
 }
@@ -88,8 +111,28 @@ impl<Tx: TxTrait + Serialize>
     for MinerBehavior<Tx>
 {
     fn inject_event(&mut self, event: PubSubEvent<TxProposal<Tx>, BlockProposal<Block, Tx>>) {
-        if let PubSubEvent::TxProposal(input) = event {
-            record_event!("miner_recv_tx", "tx_id": input.tx.id());
+        if let PubSubEvent::TxProposal(input, info) = event {
+            let tx_id = input.tx.id();
+            // Check the signature before queueing, so that the invalid tx can be attributed to
+            // the peer sending it.
+            if let Err(e) = input.tx.verify_sig() {
+                warn!(
+                    ?tx_id,
+                    peer = %info.origin(),
+                    message_id = %info.message_id,
+                    "Received a tx with invalid sig. Error: {:?}",
+                    e
+                );
+                record_event!("discard_tx", "tx_id": tx_id, "reason": "invalid_sig", "detail": std::format!("{}", e), "peer": info.origin().to_string());
+                self.pubsub.report_message_outcome(
+                    info.propagation_source,
+                    MessageOutcome::InvalidContent,
+                );
+                return;
+            }
+            self.pubsub
+                .report_message_outcome(info.propagation_source, MessageOutcome::Valid);
+            record_event!("miner_recv_tx", "tx_id": tx_id, "peer": info.origin().to_string());
             self.worker.add_tx_proposal(input);
         }
     }
@@ -0,0 +1,139 @@
+use crate::{
+    behavior::observer::{load_observer_latest_block, ObserverImportWorker},
+    p2p::{
+        config::NetworkConfig,
+        control::Shutdown,
+        direct_query::{DirectQuery, DirectQueryEvent, DirectQueryHandle},
+        discovery::{Discovery, DiscoveryEvent},
+        http::{ClientHttpServer, TxHttpRequest},
+        pubsub::{PubSub, PubSubEvent, PubSubTopic},
+    },
+};
+use async_trait::async_trait;
+use libp2p::{swarm::NetworkBehaviourEventProcess, NetworkBehaviour, PeerId};
+use serde::Serialize;
+use slimchain_chain::{
+    block_proposal::BlockProposal,
+    config::ObserverConfig,
+    consensus::pow::{verify_consensus, Block},
+    db::DBPtr,
+    latest::{LatestBlockHeader, LatestTxCount},
+    role::Role,
+};
+use slimchain_common::{error::Result, tx::TxTrait};
+use slimchain_tx_state::TxProposal;
+
+#[derive(NetworkBehaviour)]
+pub struct ObserverBehavior<Tx: TxTrait + Serialize + 'static> {
+    discv: Discovery,
+    pubsub: PubSub<TxProposal<Tx>, BlockProposal<Block, Tx>>,
+    direct_query: DirectQuery<Block>,
+    http_server: ClientHttpServer,
+    #[behaviour(ignore)]
+    worker: ObserverImportWorker<Block, Tx>,
+}
+
+impl<Tx: TxTrait + Serialize + 'static> ObserverBehavior<Tx> {
+    pub async fn new(
+        db: DBPtr,
+        observer_cfg: &ObserverConfig,
+        net_cfg: &NetworkConfig,
+    ) -> Result<Self> {
+        let keypair = net_cfg.keypair.to_libp2p_keypair();
+        let peer_id = PeerId::from(keypair.public());
+        let mut discv = Discovery::new(keypair.public(), Role::Observer, net_cfg.mdns).await?;
+        discv.add_address_from_net_config(net_cfg);
+        let mut pubsub = PubSub::new(keypair, &[PubSubTopic::BlockProposal], &[])?;
+        pubsub.set_compression(net_cfg.pubsub_compression);
+        pubsub.set_peer_score_config(net_cfg.peer_score);
+        pubsub.authorize_peers_from_net_config(net_cfg);
+        pubsub.set_metrics_interval(net_cfg.pubsub_metrics_interval);
+        pubsub.register_prometheus_collector();
+        pubsub.add_peers_from_net_config(net_cfg);
+        let mut direct_query = DirectQuery::new(Role::Observer, db.clone(), &net_cfg.direct_query);
+        direct_query.add_peers_from_net_config(net_cfg);
+
+        let last_block: Block = load_observer_latest_block(&db)?;
+        let latest_block_header = LatestBlockHeader::new_from_block(&last_block);
+        let latest_tx_count = LatestTxCount::new(0);
+        let worker = ObserverImportWorker::new(
+            *observer_cfg,
+            last_block,
+            latest_block_header.clone(),
+            latest_tx_count.clone(),
+            db,
+            verify_consensus,
+        );
+
+        let http_server = ClientHttpServer::new_with_health(
+            &net_cfg.http_listen,
+            peer_id,
+            move || latest_tx_count.get(),
+            move || latest_block_header.get_height(),
+            pubsub.shared_peers_report(),
+        )?;
+
+        Ok(Self {
+            discv,
+            pubsub,
+            direct_query,
+            http_server,
+            worker,
+        })
+    }
+
+    /// The handle to query the peers directly from outside of the swarm.
+    pub fn direct_query_handle(&self) -> DirectQueryHandle<Block> {
+        self.direct_query.handle()
+    }
+
+    pub fn discv_mut(&mut self) -> &mut Discovery {
+        &mut self.discv
+    }
+
+    pub fn pubsub_mut(&mut self) -> &mut PubSub<TxProposal<Tx>, BlockProposal<Block, Tx>> {
+        &mut self.pubsub
+    }
+}
+
+impl<Tx: TxTrait + Serialize> NetworkBehaviourEventProcess<DirectQueryEvent>
+    for ObserverBehavior<Tx>
+{
+    fn inject_event(&mut self, _: DirectQueryEvent) {}
+}
+
+impl<Tx: TxTrait + Serialize> NetworkBehaviourEventProcess<DiscoveryEvent>
+    for ObserverBehavior<Tx>
+{
+    fn inject_event(&mut self, _: DiscoveryEvent) {}
+}
+
+impl<Tx: TxTrait + Serialize> NetworkBehaviourEventProcess<TxHttpRequest> for ObserverBehavior<Tx> {
+    fn inject_event(&mut self, tx_http_req: TxHttpRequest) {
+        let tx_req_id = tx_http_req.req.id();
+        warn!(%tx_req_id, "Observer node does not accept tx requests.");
+    }
+}
+
+impl<Tx: TxTrait + Serialize>
+    NetworkBehaviourEventProcess<PubSubEvent<TxProposal<Tx>, BlockProposal<Block, Tx>>>
+    for ObserverBehavior<Tx>
+{
+    fn inject_event(&mut self, event: PubSubEvent<TxProposal<Tx>, BlockProposal<Block, Tx>>) {
+        if let PubSubEvent::BlockProposal(input, _) = event {
+            trace!(
+                height = input.get_block_height().0,
+                txs = input.get_txs().len(),
+                "Recv block proposal."
+            );
+            self.worker.add_block_proposal(input);
+        }
+    }
+}
+
+#[async_trait]
+impl<Tx: TxTrait + Serialize> Shutdown for ObserverBehavior<Tx> {
+    async fn shutdown(&mut self) -> Result<()> {
+        self.worker.shutdown().await
+    }
+}
@@ -2,6 +2,7 @@ use super::BlockImportWorker;
 use crate::p2p::{
     config::NetworkConfig,
     control::Shutdown,
+    direct_query::{DirectQuery, DirectQueryEvent, DirectQueryHandle},
     discovery::{Discovery, DiscoveryEvent},
     pubsub::{PubSub, PubSubEvent, PubSubTopic},
     rpc::{
@@ -38,6 +39,7 @@ use std::{
 pub struct StorageBehavior<Tx: TxTrait + Serialize + 'static> {
     discv: Discovery,
     pubsub: PubSub<TxProposal<Tx>, BlockProposal<Block, Tx>>,
+    direct_query: DirectQuery<Block>,
     rpc_server: RpcInstant<SignedTxRequest, ()>,
     #[behaviour(ignore)]
     import_worker: BlockImportWorker<Tx>,
@@ -66,7 +68,15 @@ impl<Tx: TxTrait + Serialize + 'static> StorageBehavior<Tx> {
             &[PubSubTopic::BlockProposal],
             &[PubSubTopic::TxProposal],
         )?;
+        pubsub.set_compression(net_cfg.pubsub_compression);
+        pubsub.set_peer_score_config(net_cfg.peer_score);
+        pubsub.authorize_peers_from_net_config(net_cfg);
+        pubsub.set_metrics_interval(net_cfg.pubsub_metrics_interval);
+        pubsub.register_prometheus_collector();
         pubsub.add_peers_from_net_config(net_cfg);
+        let mut direct_query =
+            DirectQuery::new(Role::Storage(shard_id), db.clone(), &net_cfg.direct_query);
+        direct_query.add_peers_from_net_config(net_cfg);
         let rpc_server = create_request_response_server("/tx_req/1");
         let snapshot =
             Snapshot::<Block, StorageTxTrie>::load_from_db(&db, chain_cfg.state_len, shard_id)?;
@@ -90,6 +100,7 @@ impl<Tx: TxTrait + Serialize + 'static> StorageBehavior<Tx> {
         Ok(Self {
             discv,
             pubsub,
+            direct_query,
             rpc_server,
             import_worker,
             tx_req_tx,
@@ -98,6 +109,11 @@ impl<Tx: TxTrait + Serialize + 'static> StorageBehavior<Tx> {
         })
     }
 
+    /// The handle to query the peers directly from outside of the swarm.
+    pub fn direct_query_handle(&self) -> DirectQueryHandle<Block> {
+        self.direct_query.handle()
+    }
+
     pub fn discv_mut(&mut self) -> &mut Discovery {
         &mut self.discv
     }
@@ -121,6 +137,12 @@ impl<Tx: TxTrait + Serialize + 'static> StorageBehavior<Tx> {
     }
 }
 
+impl<Tx: TxTrait + Serialize> NetworkBehaviourEventProcess<DirectQueryEvent>
+    for StorageBehavior<Tx>
+{
+    fn inject_event(&mut self, _: DirectQueryEvent) {}
+}
+
 impl<Tx: TxTrait + Serialize> NetworkBehaviourEventProcess<DiscoveryEvent> for StorageBehavior<Tx> {
     fn inject_event(&mut self, _: DiscoveryEvent) {}
 }
@@ -133,6 +155,12 @@ impl<Tx: TxTrait + Serialize>
         if let Some((tx_req, channel)) = handle_request_response_server_event(event) {
             let tx_req_id = tx_req.id();
             record_event!("storage_recv_tx", "tx_id": tx_req_id);
+            if let Err(e) = tx_req.verify() {
+                record_event!("discard_tx", "tx_id": tx_req_id, "reason": "invalid_signature", "scheme": tx_req.scheme().to_string(), "detail": std::format!("{}", e));
+                // The client sees the dropped response as a failure.
+                warn!(%tx_req_id, "Discard the tx with an invalid signature. {}", e);
+                return;
+            }
             self.tx_req_tx
                 .start_send(tx_req)
                 .expect("Failed to send tx_req to TxEngine.");
@@ -148,7 +176,7 @@ impl<Tx: TxTrait + Serialize>
     for StorageBehavior<Tx>
 {
     fn inject_event(&mut self, event: PubSubEvent<TxProposal<Tx>, BlockProposal<Block, Tx>>) {
-        if let PubSubEvent::BlockProposal(input) = event {
+        if let PubSubEvent::BlockProposal(input, _) = event {
             trace!(
                 height = input.get_block_height().0,
                 txs = input.get_txs().len(),
@@ -1,7 +1,14 @@
+pub mod block_delivery;
 pub mod client;
 pub mod client_block_proposal;
 pub mod client_network;
 pub mod client_storage;
+pub mod leader_tracker;
+pub mod leader_transfer;
+pub mod membership;
 pub mod message;
+pub mod observer;
+pub mod snapshot_transfer;
 pub mod storage;
+pub mod storage_sync;
 pub mod utils;
@@ -0,0 +1,277 @@
+use crate::http::config::PeerId;
+use serde::{Deserialize, Serialize};
+use slimchain_common::{basic::BlockHeight, collections::HashMap, error::Result};
+use slimchain_utils::bytes::Bytes;
+use std::{
+    collections::BTreeMap,
+    sync::{Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+/// The delay before the first re-broadcast to a peer. It doubles after each failed attempt.
+const RETRY_BASE_DELAY: Duration = Duration::from_millis(200);
+
+/// The max delay between two re-broadcasts to a peer.
+const RETRY_MAX_DELAY: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Default, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct DeliveryStatus {
+    /// Peers which acked the block proposal.
+    pub acked: Vec<PeerId>,
+    /// Peers which have not acked the block proposal yet.
+    pub pending: Vec<PeerId>,
+}
+
+#[derive(Debug, Copy, Clone)]
+struct RetryState {
+    attempts: u32,
+    next_retry: Instant,
+}
+
+impl RetryState {
+    fn new(now: Instant) -> Self {
+        Self {
+            attempts: 0,
+            next_retry: now + RETRY_BASE_DELAY,
+        }
+    }
+
+    fn backoff(&mut self, now: Instant) {
+        self.attempts += 1;
+        let delay = RETRY_BASE_DELAY
+            .checked_mul(1 << self.attempts.min(16))
+            .map_or(RETRY_MAX_DELAY, |delay| delay.min(RETRY_MAX_DELAY));
+        self.next_retry = now + delay;
+    }
+}
+
+/// A batch of block proposals broadcast in one request.
+#[derive(Debug)]
+struct DeliveryEntry {
+    end_height: BlockHeight,
+    bytes: Bytes,
+    acked: Vec<PeerId>,
+    pending: HashMap<PeerId, RetryState>,
+}
+
+/// Track which peers acked the recent block proposals, so that the missed ones can be
+/// re-broadcast until acked or until they fall out of the window.
+#[derive(Debug)]
+pub struct BlockDeliveryTracker {
+    window: u64,
+    entries: Mutex<BTreeMap<BlockHeight, DeliveryEntry>>,
+}
+
+impl BlockDeliveryTracker {
+    /// Create a tracker keeping the block proposals within the latest `window` heights.
+    pub fn new(window: u64) -> Self {
+        Self {
+            window,
+            entries: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    fn entries(&self) -> MutexGuard<'_, BTreeMap<BlockHeight, DeliveryEntry>> {
+        self.entries.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Record the outcome of broadcasting the block proposals from `begin_height` to
+    /// `end_height` serialized as `bytes`.
+    pub fn record_broadcast(
+        &self,
+        begin_height: BlockHeight,
+        end_height: BlockHeight,
+        bytes: Bytes,
+        summary: &[(PeerId, Result<()>)],
+    ) {
+        self.record_broadcast_at(begin_height, end_height, bytes, summary, Instant::now());
+    }
+
+    fn record_broadcast_at(
+        &self,
+        begin_height: BlockHeight,
+        end_height: BlockHeight,
+        bytes: Bytes,
+        summary: &[(PeerId, Result<()>)],
+        now: Instant,
+    ) {
+        if self.window == 0 {
+            return;
+        }
+
+        let mut acked = Vec::new();
+        let mut pending = HashMap::new();
+        for (peer_id, resp) in summary {
+            if resp.is_ok() {
+                acked.push(*peer_id);
+            } else {
+                pending.insert(*peer_id, RetryState::new(now));
+            }
+        }
+
+        let mut entries = self.entries();
+        entries.insert(
+            begin_height,
+            DeliveryEntry {
+                end_height,
+                bytes,
+                acked,
+                pending,
+            },
+        );
+
+        let min_height = end_height.saturating_sub(self.window);
+        while let Some((&height, entry)) = entries.iter().next() {
+            if entry.end_height > min_height {
+                break;
+            }
+            if !entry.pending.is_empty() {
+                warn!(
+                    %height,
+                    peers = ?entry.pending.keys().collect::<Vec<_>>(),
+                    "Block proposal is out of the re-broadcast window before acked."
+                );
+            }
+            entries.remove(&height);
+        }
+    }
+
+    /// Return the batches of block proposals to be re-broadcast with their pending peers.
+    pub fn due_retries(&self) -> Vec<(BlockHeight, Bytes, Vec<PeerId>)> {
+        self.due_retries_at(Instant::now())
+    }
+
+    fn due_retries_at(&self, now: Instant) -> Vec<(BlockHeight, Bytes, Vec<PeerId>)> {
+        self.entries()
+            .iter()
+            .filter_map(|(&height, entry)| {
+                let peers: Vec<PeerId> = entry
+                    .pending
+                    .iter()
+                    .filter(|(_, state)| state.next_retry <= now)
+                    .map(|(&peer_id, _)| peer_id)
+                    .collect();
+                if peers.is_empty() {
+                    None
+                } else {
+                    Some((height, entry.bytes.clone(), peers))
+                }
+            })
+            .collect()
+    }
+
+    /// Record the outcome of re-broadcasting the batch starting at `begin_height`.
+    pub fn record_retry(&self, begin_height: BlockHeight, peer_id: PeerId, acked: bool) {
+        self.record_retry_at(begin_height, peer_id, acked, Instant::now());
+    }
+
+    fn record_retry_at(
+        &self,
+        begin_height: BlockHeight,
+        peer_id: PeerId,
+        acked: bool,
+        now: Instant,
+    ) {
+        let mut entries = self.entries();
+        let entry = match entries.get_mut(&begin_height) {
+            Some(entry) => entry,
+            None => return,
+        };
+        if acked {
+            if entry.pending.remove(&peer_id).is_some() {
+                entry.acked.push(peer_id);
+            }
+        } else if let Some(state) = entry.pending.get_mut(&peer_id) {
+            state.backoff(now);
+        }
+    }
+
+    /// The delivery status of the block proposal at `height`, if it is still in the window.
+    pub fn delivery_status(&self, height: BlockHeight) -> Option<DeliveryStatus> {
+        let entries = self.entries();
+        let (_, entry) = entries.range(..=height).next_back()?;
+        if entry.end_height < height {
+            return None;
+        }
+        let mut acked = entry.acked.clone();
+        acked.sort();
+        let mut pending: Vec<PeerId> = entry.pending.keys().copied().collect();
+        pending.sort();
+        Some(DeliveryStatus { acked, pending })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use slimchain_common::error::anyhow;
+
+    #[test]
+    fn test_block_delivery_tracker() {
+        let tracker = BlockDeliveryTracker::new(4);
+        let now = Instant::now();
+        let summary = vec![
+            (PeerId(1), Ok(())),
+            (PeerId(2), Err(anyhow!("Connection refused."))),
+        ];
+        tracker.record_broadcast_at(
+            1.into(),
+            2.into(),
+            Bytes::from_static(b"blk1-2"),
+            &summary,
+            now,
+        );
+
+        let status = tracker.delivery_status(2.into()).unwrap();
+        assert_eq!(vec![PeerId(1)], status.acked);
+        assert_eq!(vec![PeerId(2)], status.pending);
+        assert!(tracker.delivery_status(3.into()).is_none());
+
+        // Not due before the backoff.
+        assert!(tracker.due_retries_at(now).is_empty());
+        let due = tracker.due_retries_at(now + RETRY_BASE_DELAY);
+        assert_eq!(1, due.len());
+        assert_eq!(BlockHeight::from(1), due[0].0);
+        assert_eq!(&b"blk1-2"[..], &due[0].1[..]);
+        assert_eq!(vec![PeerId(2)], due[0].2);
+
+        // A failed retry doubles the backoff.
+        let retry_time = now + RETRY_BASE_DELAY;
+        tracker.record_retry_at(1.into(), PeerId(2), false, retry_time);
+        assert!(tracker
+            .due_retries_at(retry_time + RETRY_BASE_DELAY)
+            .is_empty());
+        assert_eq!(
+            1,
+            tracker
+                .due_retries_at(retry_time + RETRY_BASE_DELAY * 2)
+                .len()
+        );
+
+        tracker.record_retry_at(1.into(), PeerId(2), true, retry_time);
+        let status = tracker.delivery_status(1.into()).unwrap();
+        assert_eq!(vec![PeerId(1), PeerId(2)], status.acked);
+        assert!(status.pending.is_empty());
+        assert!(tracker.due_retries_at(now + RETRY_MAX_DELAY).is_empty());
+
+        // Old blocks fall out of the window.
+        tracker.record_broadcast_at(
+            7.into(),
+            7.into(),
+            Bytes::from_static(b"blk7"),
+            &summary,
+            now,
+        );
+        assert!(tracker.delivery_status(1.into()).is_none());
+        assert!(tracker.delivery_status(7.into()).is_some());
+    }
+
+    #[test]
+    fn test_disabled_tracker() {
+        let tracker = BlockDeliveryTracker::new(0);
+        let summary = vec![(PeerId(1), Err(anyhow!("Timeout.")))];
+        tracker.record_broadcast(1.into(), 1.into(), Bytes::new(), &summary);
+        assert!(tracker.delivery_status(1.into()).is_none());
+        assert!(tracker.due_retries().is_empty());
+    }
+}
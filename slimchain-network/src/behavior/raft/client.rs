@@ -3,36 +3,104 @@ use crate::{
         client_block_proposal::BlockProposalWorker,
         client_network::{ClientNodeNetwork, ClientNodeNetworkWorker},
         client_storage::ClientNodeStorage,
+        leader_tracker::LeaderTracker,
+        leader_transfer::{transfer_leadership, LeaderTransfer},
+        membership::{add_learner, add_member, raft_membership, remove_member, spawn_learner_sync},
         message::{NewBlockRequest, NewBlockResponse},
+        snapshot_transfer::{
+            SnapshotBeginRequest, SnapshotChunkRequest, SnapshotEndRequest, SnapshotReceiver,
+        },
+        storage_sync::fetch_blocks,
         utils::{get_current_leader, node_is_leader},
     },
     http::{
+        admin::{AdminRequest, AdminRequestVerifier, SignedAdminRequest},
+        body_limit::recover_body_limit,
         client_rpc::*,
         common::*,
-        config::{NetworkConfig, RaftConfig},
+        config::{NetworkConfig, PeerId, RaftConfig, BLOCK_ASSEMBLY_STAGE, BROADCAST_STAGE},
+        cors::with_cors,
+        health::{health_server, spawn_health_prober},
+        metrics::metrics_server,
+        node_info::{node_info_server, NodeInfo, NodeInfoCache},
         node_rpc::*,
+        rate_limit::{recover_rate_limited, RateLimited},
+        route_table::{SharedRouteTable, SignedRouteTableUpdate},
+        server::HttpServer,
+        ws_subscribe::{load_commit_events_from_db, ws_subscribe_server},
     },
 };
 use async_raft::{
     error::{InitializeError, RaftError},
     Raft,
 };
-use futures::{channel::oneshot, prelude::*, stream};
+use futures::{prelude::*, stream};
 use serde::{Deserialize, Serialize};
 use slimchain_chain::{
+    block::BlockTrait,
+    commit_event::COMMIT_EVENTS,
     config::{ChainConfig, MinerConfig},
+    consensus::raft::Block,
+    cross_shard::{CrossShardCoordinator, CrossShardOutcome, CrossShardTxProposal},
     db::DBPtr,
+    mempool::Mempool,
+    role::Role,
+    tx_status::{TxStatus, TX_STATUS},
 };
 use slimchain_common::{
-    error::{anyhow, bail, Error, Result},
+    digest::Digestible,
+    error::{anyhow, bail, Context as _, Error, Result},
     tx::TxTrait,
 };
 use slimchain_tx_state::TxProposal;
-use slimchain_utils::record_event;
-use std::{net::SocketAddr, sync::Arc};
+use slimchain_utils::{backpressure::Pipeline, record_event};
+use std::{
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
 use tokio::task::JoinHandle;
 use warp::Filter;
 
+/// Fill the body of the pruned `block` from the storage nodes, or leave it header only if
+/// none of them has it.
+async fn fetch_pruned_block<Tx>(
+    raft_network: &ClientNodeNetwork<Tx>,
+    block: BlockQueryResponse<Tx>,
+) -> BlockQueryResponse<Tx>
+where
+    Tx: TxTrait + Serialize + for<'de> Deserialize<'de> + 'static,
+{
+    let height = block.header.height;
+    let route_table = raft_network.route_table().load();
+    let res = fetch_blocks::<Tx>(
+        &route_table,
+        raft_network.peer_health(),
+        height,
+        height.next_height(),
+    )
+    .await
+    .map(|mut blk_proposals| blk_proposals.pop());
+    match res {
+        Ok(Some(blk_proposal)) if blk_proposal.get_block().to_digest() == block.hash => {
+            BlockQueryResponse {
+                hash: block.hash,
+                header: blk_proposal.get_block().block_header().clone(),
+                txs: None,
+                header_only: false,
+            }
+        }
+        Ok(_) => {
+            warn!(%height, "The storage nodes return another block. Reply the header only.");
+            block
+        }
+        Err(e) => {
+            warn!(%height, "Failed to fetch the pruned block. Reply the header only. Error: {}", e);
+            block
+        }
+    }
+}
+
 pub type ClientNodeRaft<Tx> =
     Raft<NewBlockRequest<Tx>, NewBlockResponse, ClientNodeNetwork<Tx>, ClientNodeStorage<Tx>>;
 
@@ -47,9 +115,15 @@ impl warp::reject::Reject for ClientNodeError {}
 pub struct ClientNode<Tx: TxTrait + Serialize + for<'de> Deserialize<'de> + 'static> {
     raft_storage: Arc<ClientNodeStorage<Tx>>,
     raft: Option<Arc<ClientNodeRaft<Tx>>>,
-    srv: Option<(oneshot::Sender<()>, JoinHandle<()>)>,
+    srv: Option<HttpServer>,
+    leader_listener: Option<JoinHandle<()>>,
+    learner_sync: Option<JoinHandle<()>>,
+    health_prober: Option<JoinHandle<()>>,
     proposal_worker: BlockProposalWorker<Tx>,
     network_worker: ClientNodeNetworkWorker<Tx>,
+    route_table: SharedRouteTable,
+    leader_transfer: Arc<LeaderTransfer>,
+    pipeline: Arc<Pipeline>,
 }
 
 impl<Tx: TxTrait + Serialize + for<'de> Deserialize<'de> + 'static> ClientNode<Tx> {
@@ -62,19 +136,62 @@ impl<Tx: TxTrait + Serialize + for<'de> Deserialize<'de> + 'static> ClientNode<T
     ) -> Result<Self> {
         let net_route_table = net_cfg.to_route_table();
         let peer_id = net_route_table.peer_id();
+        // The raft membership is fixed to the client nodes known at startup, except the learners.
         let all_peers = net_route_table.all_client_peer_ids();
+        let is_learner = net_route_table.is_learner(peer_id);
+        let route_table = SharedRouteTable::new(net_route_table);
 
-        let raft_storage = Arc::new(ClientNodeStorage::new(db, chain_cfg, net_cfg)?);
-        let raft_network = Arc::new(ClientNodeNetwork::new(net_route_table));
+        let tx_status_db = db.clone();
+        let block_query_db = db.clone();
+        let ws_subscribe_db = db.clone();
+        let mempool = Mempool::open(&miner_cfg.mempool, &db)?;
+        let raft_storage = Arc::new(ClientNodeStorage::new(
+            db,
+            chain_cfg,
+            net_cfg,
+            route_table.clone(),
+        )?);
+        let leader_tracker = Arc::new(LeaderTracker::new());
+        let leader_transfer = Arc::new(LeaderTransfer::new());
+        let node_info = NodeInfo::local::<Block>(Role::Client, &net_cfg.node_info);
+        let raft_network = Arc::new(ClientNodeNetwork::new(
+            route_table.clone(),
+            net_cfg.timeout,
+            net_cfg.broadcast,
+            raft_cfg.snapshot_transfer_chunk_size,
+            leader_tracker.clone(),
+            leader_transfer.clone(),
+            NodeInfoCache::new(node_info.clone(), &net_cfg.node_info),
+        ));
         let raft = Arc::new(ClientNodeRaft::new(
             peer_id.into(),
             raft_cfg.to_raft_config()?,
             raft_network.clone(),
             raft_storage.clone(),
         ));
+        let leader_listener = leader_tracker.spawn_raft_listener(raft.as_ref());
+        let learner_sync = spawn_learner_sync(raft.clone(), route_table.clone());
+        let health_prober = spawn_health_prober(
+            route_table.clone(),
+            raft_network.peer_health().clone(),
+            net_cfg.health_check,
+        );
 
-        let network_worker =
-            ClientNodeNetworkWorker::new(raft_network.clone(), raft_cfg.async_broadcast_storage);
+        let pipeline = Arc::new(net_cfg.backpressure.client_pipeline());
+        let assembly_stage = pipeline
+            .stage(BLOCK_ASSEMBLY_STAGE)
+            .cloned()
+            .expect("Missing the block assembly stage.");
+        let broadcast_stage = pipeline
+            .stage(BROADCAST_STAGE)
+            .cloned()
+            .expect("Missing the broadcast stage.");
+
+        let network_worker = ClientNodeNetworkWorker::new(
+            raft_network.clone(),
+            raft_cfg.async_broadcast_storage,
+            broadcast_stage.clone(),
+        );
 
         let proposal_worker = BlockProposalWorker::new(
             chain_cfg,
@@ -82,6 +199,10 @@ impl<Tx: TxTrait + Serialize + for<'de> Deserialize<'de> + 'static> ClientNode<T
             raft_storage.clone(),
             raft_network.clone(),
             raft.clone(),
+            leader_transfer.clone(),
+            mempool,
+            assembly_stage.clone(),
+            broadcast_stage,
             network_worker.get_block_proposal_tx(),
             raft_cfg.async_broadcast_storage,
         );
@@ -90,7 +211,9 @@ impl<Tx: TxTrait + Serialize + for<'de> Deserialize<'de> + 'static> ClientNode<T
             let network_worker_req_tx = network_worker.get_req_tx();
             let raft_storage_copy1 = raft_storage.clone();
             let raft_storage_copy2 = raft_storage.clone();
-            client_rpc_server(
+            client_rpc_server_with_rate_limit(
+                &net_cfg.tx_rate_limit,
+                Some(pipeline.clone()),
                 move |reqs: Vec<TxHttpRequest>| {
                     let mut network_worker_req_tx = network_worker_req_tx.clone();
                     async move {
@@ -106,18 +229,63 @@ impl<Tx: TxTrait + Serialize + for<'de> Deserialize<'de> + 'static> ClientNode<T
             )
         };
 
+        let body_limit = net_cfg.body_limit;
         let raft_rpc_srv = {
             let raft_copy = raft.clone();
             let append_rpc = warp::post()
                 .and(warp::path(RAFT_APPEND_ENTRIES_ROUTE_PATH))
-                .and(warp_body_binary())
-                .and_then(move |rpc| {
+                .and(warp_body_encoded())
+                .and_then(move |encoding, rpc| {
                     let raft_copy = raft_copy.clone();
                     async move {
                         raft_copy
                             .append_entries(rpc)
                             .await
-                            .map(|resp| warp_reply_binary(&resp))
+                            .map(|resp| warp_reply_encoded(encoding, &resp))
+                            .map_err(|e| warp::reject::custom(ClientNodeError::RaftError(e)))
+                    }
+                });
+
+            // The snapshot uploaded in chunks is reassembled before handed to raft.
+            let snapshot_receiver = Arc::new(SnapshotReceiver::new());
+            let receiver_copy = snapshot_receiver.clone();
+            let snapshot_begin_rpc = warp::post()
+                .and(warp::path(RAFT_SNAPSHOT_BEGIN_ROUTE_PATH))
+                .and(warp_body_encoded())
+                .and_then(move |encoding, req: SnapshotBeginRequest| {
+                    let res = receiver_copy.begin(req);
+                    async move {
+                        res.map(|offset| warp_reply_encoded(encoding, &offset))
+                            .map_err(|e| warp::reject::custom(ClientNodeError::Other(e)))
+                    }
+                });
+
+            let receiver_copy = snapshot_receiver.clone();
+            let snapshot_chunk_rpc = warp::post()
+                .and(warp::path(RAFT_SNAPSHOT_CHUNK_ROUTE_PATH))
+                .and(warp_body_encoded())
+                .and_then(move |encoding, req: SnapshotChunkRequest| {
+                    let res = receiver_copy.chunk(req);
+                    async move {
+                        res.map(|offset| warp_reply_encoded(encoding, &offset))
+                            .map_err(|e| warp::reject::custom(ClientNodeError::Other(e)))
+                    }
+                });
+
+            let raft_copy = raft.clone();
+            let snapshot_end_rpc = warp::post()
+                .and(warp::path(RAFT_SNAPSHOT_END_ROUTE_PATH))
+                .and(warp_body_encoded())
+                .and_then(move |encoding, req: SnapshotEndRequest| {
+                    let raft_copy = raft_copy.clone();
+                    let res = snapshot_receiver.end(req);
+                    async move {
+                        let rpc =
+                            res.map_err(|e| warp::reject::custom(ClientNodeError::Other(e)))?;
+                        raft_copy
+                            .install_snapshot(rpc)
+                            .await
+                            .map(|resp| warp_reply_encoded(encoding, &resp))
                             .map_err(|e| warp::reject::custom(ClientNodeError::RaftError(e)))
                     }
                 });
@@ -125,14 +293,14 @@ impl<Tx: TxTrait + Serialize + for<'de> Deserialize<'de> + 'static> ClientNode<T
             let raft_copy = raft.clone();
             let install_rpc = warp::post()
                 .and(warp::path(RAFT_INSTALL_SNAPSHOT_ROUTE_PATH))
-                .and(warp_body_binary())
-                .and_then(move |rpc| {
+                .and(warp_body_encoded())
+                .and_then(move |encoding, rpc| {
                     let raft_copy = raft_copy.clone();
                     async move {
                         raft_copy
                             .install_snapshot(rpc)
                             .await
-                            .map(|resp| warp_reply_binary(&resp))
+                            .map(|resp| warp_reply_encoded(encoding, &resp))
                             .map_err(|e| warp::reject::custom(ClientNodeError::RaftError(e)))
                     }
                 });
@@ -140,19 +308,24 @@ impl<Tx: TxTrait + Serialize + for<'de> Deserialize<'de> + 'static> ClientNode<T
             let raft_copy = raft.clone();
             let vote_rpc = warp::post()
                 .and(warp::path(RAFT_VOTE_ROUTE_PATH))
-                .and(warp_body_binary())
-                .and_then(move |rpc| {
+                .and(warp_body_encoded_with_limit(body_limit.raft_vote))
+                .and_then(move |encoding, rpc| {
                     let raft_copy = raft_copy.clone();
                     async move {
                         raft_copy
                             .vote(rpc)
                             .await
-                            .map(|resp| warp_reply_binary(&resp))
+                            .map(|resp| warp_reply_encoded(encoding, &resp))
                             .map_err(|e| warp::reject::custom(ClientNodeError::RaftError(e)))
                     }
                 });
 
-            append_rpc.or(install_rpc).or(vote_rpc)
+            append_rpc
+                .or(install_rpc)
+                .or(snapshot_begin_rpc)
+                .or(snapshot_chunk_rpc)
+                .or(snapshot_end_rpc)
+                .or(vote_rpc)
         };
 
         let leader_rpc_srv = {
@@ -169,65 +342,370 @@ impl<Tx: TxTrait + Serialize + for<'de> Deserialize<'de> + 'static> ClientNode<T
                     }
                 });
 
+            // The parts of the cross-shard txs are not shed, since one part dropped aborts the
+            // whole tx.
+            let raft_copy = raft.clone();
+            let tx_tx = proposal_worker.get_tx_tx();
+            let assembly_stage_copy = assembly_stage.clone();
+            let coordinator = Arc::new(Mutex::new(CrossShardCoordinator::<Tx>::new(
+                miner_cfg.cross_shard_timeout,
+            )));
+            let leader_cross_shard_req_rpc = warp::post()
+                .and(warp::path(CLIENT_LEADER_CROSS_SHARD_REQ_ROUTE_PATH))
+                .and(warp_body_encoded_with_limit(body_limit.tx_req))
+                .and_then(move |encoding, parts: Vec<CrossShardTxProposal<Tx>>| {
+                    let raft_copy = raft_copy.clone();
+                    let mut tx_tx_copy = tx_tx.clone();
+                    let assembly_stage = assembly_stage_copy.clone();
+                    let coordinator = coordinator.clone();
+                    async move {
+                        if !node_is_leader(raft_copy.as_ref()) {
+                            return Err(warp::reject::custom(ClientNodeError::Other(anyhow!(
+                                "not leader"
+                            ))));
+                        }
+
+                        let ready = {
+                            let mut coordinator =
+                                coordinator.lock().unwrap_or_else(|e| e.into_inner());
+                            let mut aborted = coordinator.expire(Instant::now());
+                            let mut ready = Vec::new();
+                            for part in parts {
+                                match coordinator.add(part) {
+                                    CrossShardOutcome::Pending => {}
+                                    CrossShardOutcome::Ready(tx) => ready.push(tx),
+                                    CrossShardOutcome::Aborted { tx_id, reason } => {
+                                        aborted.push((tx_id, reason))
+                                    }
+                                }
+                            }
+                            for (tx_id, reason) in aborted {
+                                record_event!("discard_tx", "tx_id": tx_id, "reason": "cross_shard_abort", "detail": reason.as_str());
+                                TX_STATUS.record_failure(tx_id, reason);
+                            }
+                            ready
+                        };
+
+                        for tx in &ready {
+                            record_event!("miner_recv_tx", "tx_id": tx.tx.id());
+                            TX_STATUS.record(tx.tx.id(), TxStatus::Executed);
+                        }
+                        assembly_stage.push(ready.len()).await;
+                        tx_tx_copy
+                            .send_all(&mut stream::iter(ready).map(Ok))
+                            .await
+                            .map(|_| warp_reply_encoded(encoding, &()))
+                            .map_err(|e| {
+                                warp::reject::custom(ClientNodeError::Other(Error::msg(e)))
+                            })
+                    }
+                });
+
             let raft_copy = raft.clone();
             let tx_tx = proposal_worker.get_tx_tx();
+            let pipeline = pipeline.clone();
             let leader_req_rpc = warp::post()
                 .and(warp::path(CLIENT_LEADER_REQ_ROUTE_PATH))
-                .and(warp_body_binary())
-                .and_then(move |txs: Vec<TxProposal<Tx>>| {
+                .and(warp_body_encoded_with_limit(body_limit.tx_req))
+                .and_then(move |encoding, txs: Vec<TxProposal<Tx>>| {
+                    // The storage nodes retry the shed ones.
+                    if let Err(saturated) = pipeline.check() {
+                        record_event!("tx_proposal_shed", "stage": saturated.stage, "count": txs.len());
+                        return future::Either::Left(future::err(warp::reject::custom(
+                            RateLimited {
+                                retry_after: saturated.retry_after,
+                            },
+                        )));
+                    }
+
                     for tx in &txs {
                         record_event!("miner_recv_tx", "tx_id": tx.tx.id());
+                        TX_STATUS.record(tx.tx.id(), TxStatus::Executed);
                     }
 
                     let raft_copy = raft_copy.clone();
                     let mut tx_tx_copy = tx_tx.clone();
+                    let assembly_stage = assembly_stage.clone();
+                    let tx_count = txs.len();
                     let mut input = stream::iter(txs).map(Ok);
-                    async move {
+                    future::Either::Right(async move {
                         if !node_is_leader(raft_copy.as_ref()) {
                             return Err(warp::reject::custom(ClientNodeError::Other(anyhow!(
                                 "not leader"
                             ))));
                         }
 
+                        assembly_stage.push(tx_count).await;
                         tx_tx_copy
                             .send_all(&mut input)
                             .await
-                            .map(|_| warp_reply_binary(&()))
+                            .map(|_| warp_reply_encoded(encoding, &()))
                             .map_err(|e| {
                                 warp::reject::custom(ClientNodeError::Other(Error::msg(e)))
                             })
+                    })
+                });
+
+            leader_id_rpc
+                .or(leader_req_rpc)
+                .or(leader_cross_shard_req_rpc)
+        };
+
+        let admin_rpc_srv = {
+            let route_table_copy = route_table.clone();
+            let admin_keys = net_cfg.route_update.admin_keys.clone();
+            let route_table_update_rpc = warp::post()
+                .and(warp::path(CLIENT_ROUTE_TABLE_UPDATE_ROUTE_PATH))
+                .and(warp_body_binary())
+                .and_then(move |update: SignedRouteTableUpdate| {
+                    let res = route_table_copy.apply_signed_update(update, &admin_keys);
+                    async move {
+                        res.map(|_| warp_reply_binary(&()))
+                            .map_err(|e| warp::reject::custom(ClientNodeError::Other(e)))
+                    }
+                });
+
+            // The raft admin requests are signed by the admin keys of the route table updates.
+            let admin_verifier = Arc::new(AdminRequestVerifier::new(
+                peer_id,
+                net_cfg.route_update.admin_keys.clone(),
+            ));
+
+            let raft_copy = raft.clone();
+            let membership_rpc = warp::get()
+                .and(warp::path(CLIENT_MEMBERSHIP_ROUTE_PATH))
+                .map(move || warp_reply_binary(&raft_membership(raft_copy.as_ref())));
+
+            // The new member has to be added to the route table first.
+            let raft_copy = raft.clone();
+            let route_table_copy = route_table.clone();
+            let admin_verifier_copy = admin_verifier.clone();
+            let add_member_rpc = warp::post()
+                .and(warp::path(CLIENT_ADD_MEMBER_ROUTE_PATH))
+                .and(warp_body_binary())
+                .and_then(move |req: SignedAdminRequest| {
+                    let raft_copy = raft_copy.clone();
+                    let route_table = route_table_copy.load();
+                    let req = admin_verifier_copy.accept(req);
+                    async move {
+                        let res = match req {
+                            Ok(AdminRequest::AddMember(peer_id)) => {
+                                add_member(raft_copy.as_ref(), &route_table, peer_id).await
+                            }
+                            Ok(req) => Err(anyhow!("Unexpected admin request {:?}.", req)),
+                            Err(e) => Err(e),
+                        };
+                        res.map(|resp| warp_reply_binary(&resp))
+                            .map_err(|e| warp::reject::custom(ClientNodeError::Other(e)))
+                    }
+                });
+
+            let raft_copy = raft.clone();
+            let admin_verifier_copy = admin_verifier.clone();
+            let remove_member_rpc = warp::post()
+                .and(warp::path(CLIENT_REMOVE_MEMBER_ROUTE_PATH))
+                .and(warp_body_binary())
+                .and_then(move |req: SignedAdminRequest| {
+                    let raft_copy = raft_copy.clone();
+                    let req = admin_verifier_copy.accept(req);
+                    async move {
+                        let res = match req {
+                            Ok(AdminRequest::RemoveMember(peer_id)) => {
+                                remove_member(raft_copy.as_ref(), peer_id).await
+                            }
+                            Ok(req) => Err(anyhow!("Unexpected admin request {:?}.", req)),
+                            Err(e) => Err(e),
+                        };
+                        res.map(|resp| warp_reply_binary(&resp))
+                            .map_err(|e| warp::reject::custom(ClientNodeError::Other(e)))
+                    }
+                });
+
+            // It returns once the target has taken over, so that the leader can be taken down.
+            let raft_copy = raft.clone();
+            let leader_transfer_copy = leader_transfer.clone();
+            let leader_transfer_timeout = raft_cfg.leader_transfer_timeout;
+            let admin_verifier_copy = admin_verifier.clone();
+            let transfer_leadership_rpc = warp::post()
+                .and(warp::path(CLIENT_TRANSFER_LEADERSHIP_ROUTE_PATH))
+                .and(warp_body_binary())
+                .and_then(move |req: SignedAdminRequest| {
+                    let raft_copy = raft_copy.clone();
+                    let leader_transfer = leader_transfer_copy.clone();
+                    let req = admin_verifier_copy.accept(req);
+                    async move {
+                        let res = match req {
+                            Ok(AdminRequest::TransferLeadership(target)) => {
+                                transfer_leadership(
+                                    raft_copy.as_ref(),
+                                    leader_transfer.as_ref(),
+                                    target,
+                                    leader_transfer_timeout,
+                                )
+                                .await
+                            }
+                            Ok(req) => Err(anyhow!("Unexpected admin request {:?}.", req)),
+                            Err(e) => Err(e),
+                        };
+                        res.map(|leader| warp_reply_binary(&leader))
+                            .map_err(|e| warp::reject::custom(ClientNodeError::Other(e)))
+                    }
+                });
+
+            // The learner has to be marked in the route table first.
+            let raft_copy = raft.clone();
+            let route_table_copy = route_table.clone();
+            let admin_verifier_copy = admin_verifier.clone();
+            let add_learner_rpc = warp::post()
+                .and(warp::path(CLIENT_ADD_LEARNER_ROUTE_PATH))
+                .and(warp_body_binary())
+                .and_then(move |req: SignedAdminRequest| {
+                    let raft_copy = raft_copy.clone();
+                    let route_table = route_table_copy.load();
+                    let req = admin_verifier_copy.accept(req);
+                    async move {
+                        let res = match req {
+                            Ok(AdminRequest::AddLearner(peer_id)) => {
+                                add_learner(raft_copy.as_ref(), &route_table, peer_id).await
+                            }
+                            Ok(req) => Err(anyhow!("Unexpected admin request {:?}.", req)),
+                            Err(e) => Err(e),
+                        };
+                        res.map(|_| warp_reply_binary(&()))
+                            .map_err(|e| warp::reject::custom(ClientNodeError::Other(e)))
                     }
                 });
 
-            leader_id_rpc.or(leader_req_rpc)
+            route_table_update_rpc
+                .or(membership_rpc)
+                .or(add_member_rpc)
+                .or(remove_member_rpc)
+                .or(transfer_leadership_rpc)
+                .or(add_learner_rpc)
+        };
+
+        // The committed txs no longer tracked are looked up in the tx location index by their
+        // tx hashes.
+        let tx_status_srv = {
+            let index_tx_location = chain_cfg.index_tx_location;
+            tx_status_server(move |tx_id| match TX_STATUS.get(tx_id) {
+                TxStatus::Unknown if index_tx_location => {
+                    Ok(match tx_status_db.get_tx_location(tx_id)? {
+                        Some((height, position)) => TxStatus::Committed { height, position },
+                        None => TxStatus::Unknown,
+                    })
+                }
+                status => Ok(status),
+            })
+        };
+
+        let health_srv = {
+            let raft_storage_copy = raft_storage.clone();
+            let raft_copy = raft.clone();
+            health_server(
+                peer_id,
+                Role::Client,
+                move || raft_storage_copy.latest_block_header().get_height(),
+                // Ready once a leader is known, so that the tx proposals can be forwarded.
+                move || raft_copy.metrics().borrow().current_leader.is_some(),
+            )
+        };
+
+        // The bodies pruned by the node are fetched from the storage nodes.
+        let block_query_srv = {
+            let raft_storage_copy = raft_storage.clone();
+            let raft_network_copy = raft_network.clone();
+            block_query_server(
+                false,
+                move |id, include_txs| {
+                    let db = block_query_db.clone();
+                    let raft_network = raft_network_copy.clone();
+                    async move {
+                        let block = load_block_from_db::<Block, Tx>(db, id, include_txs).await?;
+                        match block {
+                            Some(block) if block.header_only => {
+                                Ok(Some(fetch_pruned_block(&raft_network, block).await))
+                            }
+                            block => Ok(block),
+                        }
+                    }
+                },
+                move || raft_storage_copy.latest_block_header().get_height(),
+            )
+        };
+
+        // The tx bodies are not stored by the client nodes.
+        let ws_subscribe_srv = {
+            let raft_storage_copy = raft_storage.clone();
+            ws_subscribe_server(
+                &net_cfg.ws_subscribe,
+                &COMMIT_EVENTS,
+                move |height| {
+                    load_commit_events_from_db::<Block, Tx>(&ws_subscribe_db, height, false)
+                },
+                move || raft_storage_copy.latest_block_header().get_height(),
+            )
         };
 
+        // The inter-node routes below never send the CORS headers.
+        let client_facing_srv = with_cors(
+            &net_cfg.cors,
+            client_rpc_srv.or(tx_status_srv).or(block_query_srv).boxed(),
+        )?;
+
         info!("Create http server, listen on {}", net_cfg.http_listen);
         let listen_addr: SocketAddr = net_cfg.http_listen.parse()?;
-        let (srv_shutdown_tx, srv_shutdown_rx) = oneshot::channel::<()>();
-        let (_, srv) = warp::serve(
-            client_rpc_srv.or(warp::path(NODE_RPC_ROUTE_PATH).and(raft_rpc_srv.or(leader_rpc_srv))),
-        )
-        .bind_with_graceful_shutdown(listen_addr, async {
-            srv_shutdown_rx.await.ok();
-        });
-        let srv_handle = tokio::spawn(srv);
+        let srv = HttpServer::bind(
+            client_facing_srv
+                .or(ws_subscribe_srv)
+                .or(health_srv)
+                .or(metrics_server())
+                .or(node_info_server(node_info))
+                .or(warp::path(NODE_RPC_ROUTE_PATH)
+                    .and(warp_node_rpc_auth())
+                    .and(raft_rpc_srv.or(leader_rpc_srv).or(admin_rpc_srv))
+                    .recover(recover_unauthorized)
+                    .recover(recover_body_limit)
+                    .recover(recover_rate_limited)),
+            listen_addr,
+            &net_cfg.shutdown,
+        )?;
 
-        info!("Initialize Raft Node");
-        match raft.initialize(all_peers).await {
-            Ok(_) | Err(InitializeError::NotAllowed) => {}
-            Err(e) => return Err(Error::from(e)),
+        // A joining node or a learner waits to be added by the leader, since initializing it
+        // would start an election of a cluster of its own.
+        if raft_cfg.join || is_learner {
+            info!("Wait to join the Raft cluster");
+        } else {
+            info!("Initialize Raft Node");
+            match raft.initialize(all_peers).await {
+                Ok(_) | Err(InitializeError::NotAllowed) => {}
+                Err(e) => return Err(Error::from(e)),
+            }
         }
 
         Ok(Self {
             raft_storage,
             raft: Some(raft),
-            srv: Some((srv_shutdown_tx, srv_handle)),
+            srv: Some(srv),
+            leader_listener: Some(leader_listener),
+            learner_sync: Some(learner_sync),
+            health_prober,
             proposal_worker,
             network_worker,
+            route_table,
+            leader_transfer,
+            pipeline,
         })
     }
 
+    pub fn route_table(&self) -> &SharedRouteTable {
+        &self.route_table
+    }
+
+    pub fn pipeline(&self) -> &Arc<Pipeline> {
+        &self.pipeline
+    }
+
     pub async fn shutdown(&mut self) -> Result<()> {
         info!("Shutting down BlockProposalWorker...");
         self.proposal_worker.shutdown().await?;
@@ -241,17 +719,629 @@ impl<Tx: TxTrait + Serialize + for<'de> Deserialize<'de> + 'static> ClientNode<T
 
         self.raft_storage.save_to_db().await?;
 
-        info!("Shutting down NetworkWorker...");
-        self.network_worker.shutdown().await?;
-
-        info!("Shutting down HTTP Server...");
-        if let Some((shutdown_tx, handler)) = self.srv.take() {
-            shutdown_tx.send(()).ok();
-            handler.await?;
-        } else {
-            bail!("Already shutdown.");
+        if let Some(leader_listener) = self.leader_listener.take() {
+            leader_listener.abort();
+        }
+        if let Some(learner_sync) = self.learner_sync.take() {
+            learner_sync.abort();
+        }
+        if let Some(health_prober) = self.health_prober.take() {
+            health_prober.abort();
         }
 
+        // The in-flight requests are drained while the queued ones are sent.
+        info!("Shutting down NetworkWorker and HTTP Server...");
+        let srv = self.srv.take().context("Already shutdown.")?;
+        future::try_join(self.network_worker.shutdown(), srv.shutdown()).await?;
+
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::route_table::RouteTableUpdate;
+    use async_raft::raft::ClientWriteRequest;
+    use chrono::Utc;
+    use once_cell::sync::Lazy;
+    use slimchain_chain::{
+        block::{BlockHeader, BlockTrait, BlockTxList},
+        block_proposal::{BlockProposal, BlockProposalTrie},
+        db::DB,
+    };
+    use slimchain_common::{
+        basic::{BlockHeight, ShardId, H256},
+        digest::Digestible,
+        ed25519::Keypair,
+        tx::RawTx,
+        tx_req::{SignedTxRequest, TxRequest},
+        utils::hex,
+    };
+    use slimchain_utils::{config::Config, toml};
+    use std::{
+        collections::{BTreeSet, HashSet},
+        net::TcpListener,
+        sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+        time::Duration,
+    };
+    use warp::http::StatusCode;
+
+    static ADMIN_KEYPAIR: Lazy<Keypair> = Lazy::new(|| Keypair::generate(&mut rand::thread_rng()));
+    static ADMIN_SEQ: AtomicU64 = AtomicU64::new(0);
+
+    fn admin_req(target: PeerId, req: AdminRequest) -> SignedAdminRequest {
+        let seq = ADMIN_SEQ.fetch_add(1, Ordering::SeqCst);
+        SignedAdminRequest::sign(target, seq, req, &ADMIN_KEYPAIR).unwrap()
+    }
+
+    fn free_addr() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.local_addr().unwrap().to_string()
+    }
+
+    /// Start the client node `peer_id` knowing the client nodes in `addrs`, of which the ones in
+    /// `learners` are learners.
+    async fn start_node(
+        peer_id: usize,
+        addrs: &[String],
+        join: bool,
+        learners: &[usize],
+    ) -> ClientNode<RawTx> {
+        let peers: String = addrs
+            .iter()
+            .enumerate()
+            .map(|(i, addr)| {
+                format!(
+                    "[[network.peers]]\npeer_id = {}\naddress = \"{}\"\nrole = \"client\"\n\
+                     learner = {}\n",
+                    i + 1,
+                    addr,
+                    learners.contains(&(i + 1))
+                )
+            })
+            .collect();
+        let input = format!(
+            r#"
+[chain]
+conflict_check = "ssi"
+state_len = 4
+consensus = "raft"
+
+[miner]
+max_block_interval = 100
+
+[raft]
+election_timeout_min = 300
+election_timeout_max = 600
+heartbeat_interval = 50
+join = {}
+
+[network]
+peer_id = {}
+http_listen = "{}"
+
+[network.route_update]
+admin_keys = ["{}"]
+
+{}
+"#,
+            join,
+            peer_id,
+            addrs[peer_id - 1],
+            hex::encode(ADMIN_KEYPAIR.public.as_bytes()),
+            peers
+        );
+        let cfg = Config::from_toml(toml::from_str(&input).unwrap());
+        let chain_cfg: ChainConfig = cfg.get("chain").unwrap();
+        let miner_cfg: MinerConfig = cfg.get("miner").unwrap();
+        let raft_cfg: RaftConfig = cfg.get("raft").unwrap();
+        let net_cfg: NetworkConfig = cfg.get("network").unwrap();
+        let db = DB::open_memory(chain_cfg.db_read_threads);
+        ClientNode::new(db, &chain_cfg, &miner_cfg, &net_cfg, &raft_cfg)
+            .await
+            .unwrap()
+    }
+
+    /// Commit an empty block on top of the latest one through the leader.
+    async fn commit_empty_block(
+        raft: &ClientNodeRaft<RawTx>,
+        storage: &ClientNodeStorage<RawTx>,
+    ) -> Result<Block> {
+        let snapshot = storage.latest_snapshot().await;
+        let last_blk = snapshot
+            .get_latest_block()
+            .context("Failed to get the latest block.")?;
+        let header = BlockHeader::new(
+            last_blk.block_height().next_height(),
+            last_blk.to_digest(),
+            Utc::now(),
+            BlockTxList::default(),
+            last_blk.state_root(),
+        );
+        let blk = Block::new(header);
+        let blk_proposal = BlockProposal::new(
+            blk.clone(),
+            Vec::new(),
+            BlockProposalTrie::Diff(Default::default()),
+        );
+        let resp = raft
+            .client_write(ClientWriteRequest::new(NewBlockRequest(blk_proposal)))
+            .await
+            .map_err(|e| anyhow!("Failed to write the block. Error: {}", e))?;
+        match resp.data {
+            NewBlockResponse::Ok => Ok(blk),
+            NewBlockResponse::Err(e) => bail!(e),
+        }
+    }
+
+    async fn wait_for<F, Fut>(what: &str, mut f: F)
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = bool>,
+    {
+        let deadline = Instant::now() + Duration::from_secs(30);
+        while !f().await {
+            assert!(Instant::now() < deadline, "Timeout waiting for {}.", what);
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    }
+
+    async fn leader(addrs: &[String]) -> (PeerId, String) {
+        let leader = get_leader(&addrs[0]).await.unwrap();
+        (leader, addrs[leader.0 as usize - 1].clone())
+    }
+
+    async fn height(addr: &str) -> BlockHeight {
+        get_block_height(addr).await.unwrap_or_default()
+    }
+
+    fn peer_ids(ids: &[u64]) -> BTreeSet<PeerId> {
+        ids.iter().copied().map(PeerId).collect()
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_change_membership() {
+        let addrs: Vec<String> = (0..4).map(|_| free_addr()).collect();
+        let addrs = &addrs[..];
+        let mut nodes = Vec::new();
+        for peer_id in 1..=3 {
+            nodes.push(start_node(peer_id, &addrs[..3], false, &[]).await);
+        }
+        let mut new_node = start_node(4, addrs, true, &[]).await;
+        wait_for("the leader", move || async move {
+            get_leader(&addrs[0]).await.is_ok()
+        })
+        .await;
+
+        // Keep committing through whichever member leads.
+        let members: Vec<_> = nodes
+            .iter()
+            .chain(std::iter::once(&new_node))
+            .map(|node| (node.raft.clone().unwrap(), node.raft_storage.clone()))
+            .collect();
+        let (stop_tx, mut stop_rx) = tokio::sync::oneshot::channel::<()>();
+        let committer = tokio::spawn(async move {
+            loop {
+                for (raft, storage) in &members {
+                    if node_is_leader(raft.as_ref()) {
+                        commit_empty_block(raft.as_ref(), storage.as_ref())
+                            .await
+                            .ok();
+                    }
+                }
+                tokio::select! {
+                    _ = &mut stop_rx => break,
+                    _ = tokio::time::sleep(Duration::from_millis(50)) => {}
+                }
+            }
+        });
+        wait_for("the first blocks", move || async move {
+            height(&addrs[0]).await >= BlockHeight::from(3u64)
+        })
+        .await;
+
+        // Grow the cluster to 4.
+        for node in &nodes {
+            node.route_table()
+                .apply_update(RouteTableUpdate::AddPeer {
+                    peer_id: PeerId(4),
+                    address: addrs[3].clone(),
+                    role: Role::Client,
+                })
+                .unwrap();
+        }
+        let (leader_id, leader_addr) = leader(addrs).await;
+        let membership = add_raft_member(
+            &leader_addr,
+            &admin_req(leader_id, AdminRequest::AddMember(PeerId(4))),
+        )
+        .await
+        .unwrap();
+        assert_eq!(peer_ids(&[1, 2, 3, 4]), membership.members);
+        let added_at = height(&addrs[0]).await;
+        wait_for("the new member to follow", move || async move {
+            height(&addrs[3]).await > added_at
+        })
+        .await;
+        for addr in addrs {
+            let membership = get_raft_membership(addr).await.unwrap();
+            assert_eq!(peer_ids(&[1, 2, 3, 4]), membership.members);
+        }
+
+        // Shrink it back to 3.
+        let (leader_id, leader_addr) = leader(addrs).await;
+        let membership = remove_raft_member(
+            &leader_addr,
+            &admin_req(leader_id, AdminRequest::RemoveMember(PeerId(4))),
+        )
+        .await
+        .unwrap();
+        assert_eq!(peer_ids(&[1, 2, 3]), membership.members);
+        let removed_at = height(&addrs[0]).await;
+        wait_for("the blocks after the removal", move || async move {
+            height(&addrs[0]).await > removed_at
+        })
+        .await;
+        for addr in &addrs[..3] {
+            let membership = get_raft_membership(addr).await.unwrap();
+            assert_eq!(peer_ids(&[1, 2, 3]), membership.members);
+        }
+
+        stop_tx.send(()).ok();
+        committer.await.unwrap();
+        new_node.shutdown().await.unwrap();
+        for node in &mut nodes {
+            node.shutdown().await.unwrap();
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_transfer_leadership() {
+        let addrs: Vec<String> = (0..3).map(|_| free_addr()).collect();
+        let addrs = &addrs[..];
+        let mut nodes = Vec::new();
+        for peer_id in 1..=3 {
+            nodes.push(start_node(peer_id, addrs, false, &[]).await);
+        }
+        wait_for("the leader", move || async move {
+            get_leader(&addrs[0]).await.is_ok()
+        })
+        .await;
+
+        // Keep committing through whichever member leads, held back by the leader transfer like
+        // the block proposal worker.
+        let members: Vec<_> = nodes
+            .iter()
+            .map(|node| {
+                (
+                    node.raft.clone().unwrap(),
+                    node.raft_storage.clone(),
+                    node.leader_transfer.clone(),
+                )
+            })
+            .collect();
+        let (stop_tx, mut stop_rx) = tokio::sync::oneshot::channel::<()>();
+        let committer = tokio::spawn(async move {
+            let mut committed = Vec::new();
+            loop {
+                for (raft, storage, leader_transfer) in &members {
+                    let _proposing = leader_transfer.lock_proposals().await;
+                    if node_is_leader(raft.as_ref()) {
+                        if let Ok(blk) = commit_empty_block(raft.as_ref(), storage.as_ref()).await {
+                            committed.push((blk.block_height(), blk.to_digest()));
+                        }
+                    }
+                }
+                tokio::select! {
+                    _ = &mut stop_rx => break,
+                    _ = tokio::time::sleep(Duration::from_millis(20)) => {}
+                }
+            }
+            committed
+        });
+        wait_for("the first blocks", move || async move {
+            height(&addrs[0]).await >= BlockHeight::from(3u64)
+        })
+        .await;
+
+        let leader = get_leader(&addrs[0]).await.unwrap();
+        let target = PeerId(leader.0 % 3 + 1);
+        let new_leader = transfer_raft_leadership(
+            &addrs[leader.0 as usize - 1],
+            &admin_req(leader, AdminRequest::TransferLeadership(target)),
+            Duration::from_secs(15),
+        )
+        .await
+        .unwrap();
+        assert_eq!(target, new_leader);
+        for addr in addrs {
+            wait_for("the new leader to be known", move || async move {
+                get_leader(addr).await.ok() == Some(target)
+            })
+            .await;
+        }
+
+        let transferred_at = height(&addrs[0]).await;
+        wait_for("the blocks led by the target", move || async move {
+            height(&addrs[0]).await.0 >= transferred_at.0 + 3
+        })
+        .await;
+        stop_tx.send(()).ok();
+        let committed = committer.await.unwrap();
+
+        // Every block committed before, during and after the transfer is on every node.
+        let (last_height, _) = *committed.last().unwrap();
+        assert!(last_height > transferred_at);
+        for addr in addrs {
+            wait_for("the last block", move || async move {
+                height(addr).await >= last_height
+            })
+            .await;
+            for &(height, hash) in &committed {
+                let resp = get_block::<RawTx>(addr, BlockId::Height(height), false)
+                    .await
+                    .unwrap();
+                assert_eq!(hash, resp.hash);
+            }
+        }
+
+        for node in &mut nodes {
+            node.shutdown().await.unwrap();
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_learner() {
+        let addrs: Vec<String> = (0..4).map(|_| free_addr()).collect();
+        let addrs = &addrs[..];
+        let mut nodes = Vec::new();
+        for peer_id in 1..=3 {
+            nodes.push(start_node(peer_id, addrs, false, &[4]).await);
+        }
+        let mut learner = start_node(4, addrs, false, &[4]).await;
+        wait_for("the leader", move || async move {
+            get_leader(&addrs[0]).await.is_ok()
+        })
+        .await;
+
+        let members: Vec<_> = nodes
+            .iter()
+            .map(|node| (node.raft.clone().unwrap(), node.raft_storage.clone()))
+            .collect();
+        let (stop_tx, mut stop_rx) = tokio::sync::oneshot::channel::<()>();
+        let committer = tokio::spawn(async move {
+            loop {
+                for (raft, storage) in &members {
+                    if node_is_leader(raft.as_ref()) {
+                        commit_empty_block(raft.as_ref(), storage.as_ref())
+                            .await
+                            .ok();
+                    }
+                }
+                tokio::select! {
+                    _ = &mut stop_rx => break,
+                    _ = tokio::time::sleep(Duration::from_millis(50)) => {}
+                }
+            }
+        });
+
+        // The learner applies the blocks without being a member.
+        wait_for("the learner to follow", move || async move {
+            height(&addrs[3]).await >= BlockHeight::from(3u64)
+        })
+        .await;
+        for addr in addrs {
+            let membership = get_raft_membership(addr).await.unwrap();
+            assert_eq!(peer_ids(&[1, 2, 3]), membership.members);
+        }
+        assert_ne!(PeerId(4), get_leader(&addrs[3]).await.unwrap());
+
+        // Only the admin keys can attach a learner.
+        let other = Keypair::generate(&mut rand::thread_rng());
+        let (leader_id, leader_addr) = leader(addrs).await;
+        let req = SignedAdminRequest::sign(
+            leader_id,
+            u64::MAX,
+            AdminRequest::AddLearner(PeerId(4)),
+            &other,
+        )
+        .unwrap();
+        assert!(add_raft_learner(&leader_addr, &req).await.is_err());
+
+        // The commits go on without the learner.
+        learner.shutdown().await.unwrap();
+        let stopped_at = height(&addrs[0]).await;
+        wait_for("the blocks without the learner", move || async move {
+            height(&addrs[0]).await.0 >= stopped_at.0 + 5
+        })
+        .await;
+
+        // It catches up on restart.
+        let mut learner = start_node(4, addrs, false, &[4]).await;
+        let restarted_at = height(&addrs[0]).await;
+        wait_for("the learner to catch up", move || async move {
+            height(&addrs[3]).await >= restarted_at
+        })
+        .await;
+
+        stop_tx.send(()).ok();
+        committer.await.unwrap();
+        learner.shutdown().await.unwrap();
+        for node in &mut nodes {
+            node.shutdown().await.unwrap();
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_tx_req_shed_with_slow_commit() {
+        let client_addr = free_addr();
+        let imported_height = Arc::new(AtomicU64::new(0));
+        let committed = Arc::new(Mutex::new(HashSet::<H256>::new()));
+
+        // A storage node executing the txs at once, but slow to import the blocks.
+        let storage_srv = {
+            let client_addr = client_addr.clone();
+            let imported_height_copy = imported_height.clone();
+            let tx_req = warp::post()
+                .and(warp::path(NODE_RPC_ROUTE_PATH))
+                .and(warp::path(STORAGE_TX_REQ_ROUTE_PATH))
+                .and(warp_body_encoded())
+                .map(move |encoding, req: SignedTxRequest| {
+                    let tx = RawTx {
+                        caller: req.caller_address(),
+                        input: req.input.clone(),
+                        block_height: imported_height_copy.load(Ordering::SeqCst).into(),
+                        state_root: H256::zero(),
+                        reads: Default::default(),
+                        writes: Default::default(),
+                        gas_used: 0,
+                        fee: 0,
+                    };
+                    let client_addr = client_addr.clone();
+                    tokio::spawn(async move {
+                        let proposals = vec![TxProposal::new(tx, Default::default())];
+                        while send_reqs_to_leader(&client_addr, &proposals).await.is_err() {
+                            tokio::time::sleep(Duration::from_millis(20)).await;
+                        }
+                    });
+                    warp_reply_encoded(encoding, &())
+                });
+            let imported_height_copy = imported_height.clone();
+            let committed_copy = committed.clone();
+            let block_import = warp::post()
+                .and(warp::path(NODE_RPC_ROUTE_PATH))
+                .and(warp::path(STORAGE_BLOCK_IMPORT_ROUTE_PATH))
+                .and(warp_body_encoded())
+                .and_then(move |encoding, blks: Vec<BlockProposal<Block, RawTx>>| {
+                    let imported_height = imported_height_copy.clone();
+                    let committed = committed_copy.clone();
+                    async move {
+                        tokio::time::sleep(Duration::from_millis(50)).await;
+                        for blk in &blks {
+                            let mut committed = committed.lock().unwrap();
+                            committed.extend(blk.get_txs().iter().map(|tx| tx.id()));
+                            imported_height.fetch_max(blk.get_block_height().0, Ordering::SeqCst);
+                        }
+                        Ok::<_, warp::Rejection>(warp_reply_encoded(encoding, &()))
+                    }
+                });
+            let node_info =
+                NodeInfo::local::<Block>(Role::Storage(ShardId::default()), &Default::default());
+            tx_req.or(block_import).or(node_info_server(node_info))
+        };
+        let (storage_addr, srv) = warp::serve(storage_srv).bind_ephemeral(([127, 0, 0, 1], 0));
+        let srv_handle = tokio::spawn(srv);
+
+        let input = format!(
+            r#"
+[chain]
+conflict_check = "ssi"
+state_len = 4
+consensus = "raft"
+
+[miner]
+max_txs = 1
+min_txs = 1
+max_block_interval = 20
+
+[raft]
+election_timeout_min = 300
+election_timeout_max = 600
+heartbeat_interval = 50
+
+[network]
+peer_id = 1
+http_listen = "{}"
+
+[network.backpressure]
+max_pending_txs = 4
+retry_after = 2000
+
+[network.health_check]
+interval = 0
+
+[[network.peers]]
+peer_id = 1
+address = "{}"
+role = "client"
+
+[[network.peers]]
+peer_id = 2
+address = "{}"
+role = "storage"
+"#,
+            client_addr, client_addr, storage_addr
+        );
+        let cfg = Config::from_toml(toml::from_str(&input).unwrap());
+        let chain_cfg: ChainConfig = cfg.get("chain").unwrap();
+        let miner_cfg: MinerConfig = cfg.get("miner").unwrap();
+        let raft_cfg: RaftConfig = cfg.get("raft").unwrap();
+        let net_cfg: NetworkConfig = cfg.get("network").unwrap();
+        let db = DB::open_memory(chain_cfg.db_read_threads);
+        let mut node = ClientNode::<RawTx>::new(db, &chain_cfg, &miner_cfg, &net_cfg, &raft_cfg)
+            .await
+            .unwrap();
+        let addr = &client_addr;
+        wait_for("the leader", move || async move {
+            get_leader(addr).await.is_ok()
+        })
+        .await;
+
+        // Track the depth of the block assembly as the txs pile up.
+        let assembly_stage = node.pipeline().stage(BLOCK_ASSEMBLY_STAGE).unwrap().clone();
+        let max_depth = Arc::new(AtomicUsize::new(0));
+        let sampler = {
+            let assembly_stage = assembly_stage.clone();
+            let max_depth = max_depth.clone();
+            tokio::spawn(async move {
+                loop {
+                    max_depth.fetch_max(assembly_stage.depth(), Ordering::SeqCst);
+                    tokio::time::sleep(Duration::from_millis(1)).await;
+                }
+            })
+        };
+
+        let mut rng = rand::thread_rng();
+        let (mut accepted, mut shed) = (0, 0);
+        for _ in 0..100 {
+            let req = TxRequest::Create {
+                nonce: 0u64.into(),
+                code: Default::default(),
+            }
+            .sign(&Keypair::generate(&mut rng), 0);
+            match send_tx_request(addr, req).await {
+                Ok(()) => accepted += 1,
+                Err(e) => {
+                    assert!(
+                        matches!(
+                            network_error_of(&e),
+                            Some(NetworkError::HttpStatus { status, .. })
+                                if *status == StatusCode::TOO_MANY_REQUESTS
+                        ),
+                        "Unexpected error: {}",
+                        e
+                    );
+                    shed += 1;
+                }
+            }
+        }
+        assert!(accepted > 0);
+        assert!(shed > 0);
+
+        // All the accepted txs are committed, while the queue never grows beyond its capacity.
+        let committed_copy = committed.clone();
+        wait_for("the accepted txs to commit", move || {
+            let committed = committed_copy.lock().unwrap().len();
+            async move { committed >= accepted }
+        })
+        .await;
+        assert_eq!(accepted, committed.lock().unwrap().len());
+        sampler.abort();
+        assert!(max_depth.load(Ordering::SeqCst) <= 4);
+        assert_eq!(0, assembly_stage.depth());
+
+        node.shutdown().await.unwrap();
+        srv_handle.abort();
+    }
+}
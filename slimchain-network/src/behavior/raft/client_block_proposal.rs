@@ -2,10 +2,12 @@ use crate::behavior::raft::{
     client::ClientNodeRaft,
     client_network::ClientNodeNetwork,
     client_storage::ClientNodeStorage,
+    leader_transfer::LeaderTransfer,
     message::{NewBlockRequest, NewBlockResponse},
+    utils::node_is_leader,
 };
 use async_raft::{
-    error::ClientWriteError,
+    error::{ClientWriteError, RaftError},
     raft::{ClientWriteRequest, ClientWriteResponse},
 };
 use futures::{
@@ -18,16 +20,71 @@ use slimchain_chain::{
     block_proposal::BlockProposal,
     config::{ChainConfig, MinerConfig},
     consensus::raft::{create_new_block, Block},
+    latest::LatestBlockHeaderPtr,
+    mempool::Mempool,
+    tx_queue::PendingTxQueue,
+    tx_status::TX_STATUS,
 };
 use slimchain_common::{
     error::{bail, Result},
     tx::TxTrait,
 };
+use slimchain_tx_engine::verify::{
+    DefaultProposalVerifier, RejectedProposal, VerifiedProposal, VerifyEngine, VerifyTask,
+};
 use slimchain_tx_state::TxProposal;
-use slimchain_utils::record_event;
-use std::sync::Arc;
+use slimchain_utils::{backpressure::PipelineStage, record_event};
+use std::{sync::Arc, time::Duration};
 use tokio::task::JoinHandle;
 
+const STORAGE_PEER_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Verify the tx proposals from `input` by [`VerifyEngine`] against the latest block, and pass
+/// on the valid ones. It ends once `input` is closed and the pending ones are verified.
+///
+/// The rejected ones leave `assembly_stage` here, as they never reach the queue.
+fn spawn_verify_engine<Tx: TxTrait + Serialize + for<'de> Deserialize<'de> + 'static>(
+    threads: usize,
+    mut input: mpsc::UnboundedReceiver<TxProposal<Tx>>,
+    latest_block_header: LatestBlockHeaderPtr,
+    assembly_stage: Arc<PipelineStage>,
+) -> mpsc::UnboundedReceiver<TxProposal<Tx>> {
+    let (verified_tx, verified_rx) = mpsc::unbounded();
+    tokio::spawn(async move {
+        let mut engine = VerifyEngine::new(threads, || Box::new(DefaultProposalVerifier));
+        let mut input_closed = false;
+        loop {
+            tokio::select! {
+                tx_proposal = input.next(), if !input_closed => match tx_proposal {
+                    Some(tx_proposal) => {
+                        let header = latest_block_header.clone();
+                        engine.push_task(VerifyTask::new(tx_proposal, move || {
+                            header.get_height_and_state_root()
+                        }));
+                    }
+                    None => input_closed = true,
+                },
+                result = engine.pop_result(), if engine.remaining_tasks() > 0 => match result {
+                    Ok(VerifiedProposal { tx_proposal, .. }) => {
+                        if verified_tx.unbounded_send(tx_proposal).is_err() {
+                            break;
+                        }
+                    }
+                    Err(RejectedProposal { tx_proposal, reason, .. }) => {
+                        let tx_id = tx_proposal.tx.id();
+                        warn!("Received an invalid tx. Error: {}", reason);
+                        record_event!("discard_tx", "tx_id": tx_id, "reason": reason.as_str(), "detail": std::format!("{}", reason));
+                        TX_STATUS.record_failure(tx_id, reason.as_str());
+                        assembly_stage.pop(1);
+                    }
+                },
+                else => break,
+            }
+        }
+    });
+    verified_rx
+}
+
 pub struct BlockProposalWorker<Tx: TxTrait + Serialize + for<'de> Deserialize<'de> + 'static> {
     handle: Option<JoinHandle<()>>,
     tx_tx: mpsc::UnboundedSender<TxProposal<Tx>>,
@@ -41,15 +98,35 @@ impl<Tx: TxTrait + Serialize + for<'de> Deserialize<'de> + 'static> BlockProposa
         raft_storage: Arc<ClientNodeStorage<Tx>>,
         raft_network: Arc<ClientNodeNetwork<Tx>>,
         raft: Arc<ClientNodeRaft<Tx>>,
+        leader_transfer: Arc<LeaderTransfer>,
+        mempool: Mempool<Tx>,
+        assembly_stage: Arc<PipelineStage>,
+        broadcast_stage: Arc<PipelineStage>,
         mut block_proposal_broadcast_tx: mpsc::UnboundedSender<BlockProposal<Block, Tx>>,
         async_broadcast_storage: bool,
     ) -> Self {
         let (tx_tx, tx_rx) = mpsc::unbounded::<TxProposal<Tx>>();
-        let mut tx_rx = tx_rx.fuse().peekable();
+        let pre_verified = miner_cfg.verify_threads > 0;
+        let tx_rx = if pre_verified {
+            spawn_verify_engine(
+                miner_cfg.verify_threads,
+                tx_rx,
+                raft_storage.latest_block_header(),
+                assembly_stage.clone(),
+            )
+        } else {
+            tx_rx
+        };
+        let mut tx_rx = PendingTxQueue::new(tx_rx, raft_storage.latest_block_header().get_height())
+            .with_nonce_ordering(miner_cfg.nonce_ordering)
+            .with_mempool(mempool)
+            .with_pipeline_stage(assembly_stage)
+            .peekable();
         let (shutdown_tx, mut shutdown_rx) = oneshot::channel();
 
         let chain_cfg = chain_cfg.clone();
-        let miner_cfg = miner_cfg.clone();
+        let mut miner_cfg = miner_cfg.clone();
+        let block_limits = miner_cfg.watch_block_limits();
 
         let handle: JoinHandle<()> = tokio::spawn(async move {
             loop {
@@ -62,16 +139,73 @@ impl<Tx: TxTrait + Serialize + for<'de> Deserialize<'de> + 'static> BlockProposa
                     }
                 }
 
+                // The new blocks cannot reach any storage node, so leave the txs queued until
+                // one comes back.
+                if !raft_network.has_storage_peers() {
+                    warn!("No storage peer is available. Pause proposing the blocks.");
+                    record_event!("block_proposal_paused");
+                    let mut shutdown = false;
+                    while !raft_network.has_storage_peers() {
+                        tokio::select! {
+                            _ = &mut shutdown_rx => {
+                                shutdown = true;
+                                break;
+                            }
+                            _ = tokio::time::sleep(STORAGE_PEER_POLL_INTERVAL) => {}
+                        }
+                    }
+                    if shutdown {
+                        break;
+                    }
+                    info!("Storage peer is available. Resume proposing the blocks.");
+                    record_event!("block_proposal_resumed");
+                }
+
+                // Leave the txs queued rather than piling up the blocks not yet broadcast.
+                if broadcast_stage.is_saturated() {
+                    record_event!("block_proposal_throttled", "depth": broadcast_stage.depth());
+                    tokio::select! {
+                        _ = &mut shutdown_rx => break,
+                        _ = broadcast_stage.wait_for_room() => {}
+                    }
+                }
+
+                // Wait for the leader transfer in progress if any. The txs queued on a node no
+                // longer the leader go to the new one.
+                let _proposing = leader_transfer.lock_proposals().await;
+                if !node_is_leader(raft.as_ref()) {
+                    raft_storage.reset_miner_snapshot().await;
+                    let mut txs = Vec::with_capacity(tx_rx.size_hint().0);
+                    while let Some(Some(tx)) = tx_rx.next().now_or_never() {
+                        txs.push(tx);
+                    }
+                    tx_rx.get_mut().forget_popped();
+                    raft_network.forward_or_park_tx_proposals(txs).await;
+                    continue;
+                }
+
                 let mut snapshot = raft_storage.latest_snapshot().await;
-                let blk_proposal = match propose_block(
+                if let Some(max_age) = miner_cfg.max_tx_age_blocks {
+                    tx_rx
+                        .get_mut()
+                        .purge_expired(snapshot.current_height(), max_age);
+                }
+
+                miner_cfg.set_block_limits(*block_limits.borrow());
+                let mut deferred_tx_proposals = Vec::new();
+                let blk_proposal = propose_block(
                     &chain_cfg,
                     &miner_cfg,
                     &mut snapshot,
                     &mut tx_rx,
+                    &mut deferred_tx_proposals,
+                    pre_verified,
                     create_new_block,
                 )
-                .await
-                {
+                .await;
+                tx_rx.get_mut().requeue(deferred_tx_proposals);
+
+                let blk_proposal = match blk_proposal {
                     Ok(blk_proposal) => blk_proposal,
                     Err(e) => {
                         error!("Failed to build the new block. Error: {}", e);
@@ -88,21 +222,26 @@ impl<Tx: TxTrait + Serialize + for<'de> Deserialize<'de> + 'static> BlockProposa
                     .set_miner_snapshot(&blk_proposal, snapshot)
                     .await;
 
-                match raft
+                let write_res = raft
                     .client_write(ClientWriteRequest::new(NewBlockRequest(
                         blk_proposal.clone(),
                     )))
-                    .await
-                {
+                    .await;
+
+                // Only the txs taken by a committed block leave the queue. Otherwise, they are put
+                // back for the next block or go to the new leader.
+                match write_res {
                     Ok(ClientWriteResponse { data, .. }) => match data {
-                        NewBlockResponse::Ok => {}
+                        NewBlockResponse::Ok => tx_rx.get_mut().settle_popped(),
                         NewBlockResponse::Err(e) => {
                             error!("Raft write error from response. Error: {}", e);
+                            raft_storage.reset_miner_snapshot().await;
 
                             for tx in blk_proposal.get_txs() {
                                 let tx_id = tx.id();
-                                record_event!("discard_tx", "tx_id": tx_id, "reason": "raft_write_response", "detail": std::format!("{}", e));
+                                record_event!("requeue_tx", "tx_id": tx_id, "reason": "raft_write_response", "detail": std::format!("{}", e));
                             }
+                            tx_rx.get_mut().requeue_popped();
 
                             continue;
                         }
@@ -111,29 +250,19 @@ impl<Tx: TxTrait + Serialize + for<'de> Deserialize<'de> + 'static> BlockProposa
                         error!("Raft write should be forward to leader ({:?}).", leader);
                         raft_storage.reset_miner_snapshot().await;
 
-                        for tx in blk_proposal.get_txs() {
-                            let tx_id = tx.id();
-                            record_event!("discard_tx", "tx_id": tx_id, "reason": "raft_write_non_leader", "detail": std::format!("leader={:?}", leader));
-                        }
-
                         if let Some(leader_id) = leader {
-                            raft_network.set_leader(leader_id.into()).await;
+                            raft_network.set_leader(leader_id.into());
                         }
 
+                        tx_rx.get_mut().requeue_popped();
                         let mut txs = Vec::with_capacity(tx_rx.size_hint().0);
 
                         while let Some(Some(tx)) = tx_rx.next().now_or_never() {
                             txs.push(tx);
                         }
+                        tx_rx.get_mut().forget_popped();
 
-                        if let Err(e) = raft_network.forward_tx_proposal_to_leader(&txs).await {
-                            error!("Failed to forward buffered tx to leader. Error: {}", e);
-
-                            for tx in txs {
-                                let tx_id = tx.tx.id();
-                                record_event!("discard_tx", "tx_id": tx_id, "reason": "raft_forward_leader_error");
-                            }
-                        }
+                        raft_network.forward_or_park_tx_proposals(txs).await;
 
                         continue;
                     }
@@ -143,23 +272,29 @@ impl<Tx: TxTrait + Serialize + for<'de> Deserialize<'de> + 'static> BlockProposa
 
                         for tx in blk_proposal.get_txs() {
                             let tx_id = tx.id();
-                            record_event!("discard_tx", "tx_id": tx_id, "reason": "raft_write_error", "detail": std::format!("{}", e));
+                            record_event!("requeue_tx", "tx_id": tx_id, "reason": "raft_write_error", "detail": std::format!("{}", e));
                         }
+                        tx_rx.get_mut().requeue_popped();
 
-                        while let Some(Some(tx)) = tx_rx.next().now_or_never() {
-                            let tx_id = tx.tx.id();
-                            record_event!("discard_tx", "tx_id": tx_id, "reason": "raft_write_error_buffered_tx");
+                        // The requeued txs stay in the mempool for the next run.
+                        if matches!(e, RaftError::ShuttingDown) {
+                            break;
                         }
-
                         continue;
                     }
                 }
 
                 if async_broadcast_storage {
+                    broadcast_stage.push(1).await;
                     block_proposal_broadcast_tx.send(blk_proposal).await.ok();
                 } else {
+                    let blk_proposals = vec![blk_proposal];
+                    raft_network
+                        .broadcast_block_proposal_to_storage_node(&blk_proposals)
+                        .await
+                        .ok();
                     raft_network
-                        .broadcast_block_proposal_to_storage_node(&vec![blk_proposal])
+                        .broadcast_block_proposal_to_observer_node(&blk_proposals)
                         .await
                         .ok();
                 }
@@ -1,10 +1,20 @@
 use crate::{
-    behavior::raft::message::NewBlockRequest,
+    behavior::raft::{
+        block_delivery::{BlockDeliveryTracker, DeliveryStatus},
+        leader_tracker::LeaderTracker,
+        leader_transfer::LeaderTransfer,
+        message::NewBlockRequest,
+        snapshot_transfer::{SnapshotBeginRequest, SnapshotChunkRequest, SnapshotEndRequest},
+    },
     http::{
         client_rpc::TxHttpRequest,
         common::*,
-        config::{NetworkRouteTable, PeerId},
+        config::{BroadcastConfig, NetworkRouteTable, PeerId, RpcTimeoutConfig},
+        node_info::NodeInfoCache,
         node_rpc::*,
+        peer_health::PeerHealth,
+        route_table::SharedRouteTable,
+        rpc_metrics::RPC_METRICS,
     },
 };
 use async_raft::{
@@ -17,34 +27,80 @@ use async_raft::{
 use async_trait::async_trait;
 use futures::{
     channel::{mpsc, oneshot},
-    future,
     prelude::*,
+    stream,
 };
 use serde::{Deserialize, Serialize};
-use slimchain_chain::{block_proposal::BlockProposal, consensus::raft::Block, role::Role};
+use slimchain_chain::{
+    block_proposal::BlockProposal,
+    consensus::raft::Block,
+    metrics::CHAIN_METRICS,
+    role::Role,
+    tx_status::{TxStatus, TX_STATUS},
+};
 use slimchain_common::{
-    error::{anyhow, bail, Result},
+    basic::BlockHeight,
+    error::{anyhow, bail, ensure, Context as _, Result},
     tx::TxTrait,
 };
 use slimchain_tx_state::TxProposal;
-use slimchain_utils::{bytes::Bytes, record_event, serde::binary_encode};
-use std::{marker::PhantomData, sync::Arc};
-use tokio::{sync::RwLock, task::JoinHandle};
+use slimchain_utils::{
+    backpressure::PipelineStage, bytes::Bytes, record_event, serde::binary_encode,
+};
+use std::{marker::PhantomData, sync::Arc, time::Duration};
+use tokio::{
+    sync::Mutex,
+    task::JoinHandle,
+    time::{timeout_at, Instant},
+};
+
+/// Max number of attempts to forward the tx proposals to the leader.
+const MAX_FORWARD_LEADER_ATTEMPTS: usize = 3;
+
+/// Max number of tx proposals waiting for a new leader.
+const MAX_PARKED_TX_PROPOSALS: usize = 65_536;
+
+/// How often to retry the parked tx proposals while the leader stays the same.
+const PARKED_RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How often to check for the block proposals to be re-broadcast.
+const REBROADCAST_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Max number of tx requests forwarded to the storage nodes concurrently.
+const MAX_CONCURRENT_TX_FORWARDS: usize = 64;
+
+/// How long the shutdown waits for the queued tx requests and block proposals to be sent.
+const SHUTDOWN_DRAIN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The outcome of broadcasting the block proposals to each peer.
+pub type BroadcastSummary = Vec<(PeerId, Result<()>)>;
 
 pub async fn fetch_leader_id(route_table: &NetworkRouteTable) -> Result<PeerId> {
     let rand_client = route_table
         .random_peer(&Role::Client)
-        .ok_or_else(|| anyhow!("Failed to find the client node."))
+        .ok_or_else(|| NetworkError::NoPeerForRole(Role::Client).into())
         .and_then(|peer_id| route_table.peer_address(peer_id))?;
     get_leader(rand_client).await
 }
 
+/// The bytes of a snapshot chunk request besides its data, which are left out of the body
+/// limit of the target when shrinking the chunks.
+const SNAPSHOT_CHUNK_OVERHEAD: u64 = 1024;
+
 pub struct ClientNodeNetwork<Tx>
 where
     Tx: TxTrait + Serialize + for<'de> Deserialize<'de> + 'static,
 {
-    route_table: NetworkRouteTable,
-    leader_id: RwLock<Option<PeerId>>,
+    route_table: SharedRouteTable,
+    timeout_cfg: RpcTimeoutConfig,
+    broadcast_cfg: BroadcastConfig,
+    snapshot_chunk_size: usize,
+    leader_tracker: Arc<LeaderTracker>,
+    leader_transfer: Arc<LeaderTransfer>,
+    parked_tx_proposals: Mutex<Vec<TxProposal<Tx>>>,
+    block_delivery: BlockDeliveryTracker,
+    peer_health: PeerHealth,
+    node_info: NodeInfoCache,
     _marker: PhantomData<Tx>,
 }
 
@@ -52,10 +108,26 @@ impl<Tx> ClientNodeNetwork<Tx>
 where
     Tx: TxTrait + Serialize + for<'de> Deserialize<'de> + 'static,
 {
-    pub fn new(route_table: NetworkRouteTable) -> Self {
+    pub fn new(
+        route_table: SharedRouteTable,
+        timeout_cfg: RpcTimeoutConfig,
+        broadcast_cfg: BroadcastConfig,
+        snapshot_chunk_size: usize,
+        leader_tracker: Arc<LeaderTracker>,
+        leader_transfer: Arc<LeaderTransfer>,
+        node_info: NodeInfoCache,
+    ) -> Self {
         Self {
             route_table,
-            leader_id: RwLock::new(None),
+            timeout_cfg,
+            broadcast_cfg,
+            snapshot_chunk_size,
+            leader_tracker,
+            leader_transfer,
+            parked_tx_proposals: Mutex::new(Vec::new()),
+            block_delivery: BlockDeliveryTracker::new(broadcast_cfg.retry_window),
+            peer_health: PeerHealth::new(),
+            node_info,
             _marker: PhantomData,
         }
     }
@@ -65,70 +137,208 @@ where
         let TxHttpRequest { req, shard_id } = tx_req;
         let tx_req_id = req.id();
 
-        let storage_node_peer_id = match self.route_table.random_peer(&Role::Storage(shard_id)) {
-            Some(peer) => peer,
-            None => {
-                error!(%tx_req_id , "Failed to find the storage node. ShardId: {:?}", shard_id);
-                return;
-            }
-        };
-        debug_assert_ne!(storage_node_peer_id, self.route_table.peer_id());
-
-        let storage_node_addr = match self.route_table.peer_address(storage_node_peer_id) {
-            Ok(addr) => addr,
-            Err(_) => {
-                error!(%tx_req_id , "Failed to get the storage address. PeerId: {}", storage_node_peer_id);
-                return;
-            }
-        };
+        let route_table = self.route_table.load();
+        let storage_node_peer_ids = route_table.peers_for_role(&Role::Storage(shard_id));
+        if storage_node_peer_ids.is_empty() {
+            error!(%tx_req_id , "Failed to find the storage node. ShardId: {:?}", shard_id);
+            TX_STATUS.record_failure(tx_req_id, "no_storage_node");
+            return;
+        }
+        debug_assert!(!storage_node_peer_ids.contains(&route_table.peer_id()));
 
         record_event!("tx_begin", "tx_id": tx_req_id);
+        CHAIN_METRICS.record_tx_begin(tx_req_id);
+        TX_STATUS.record(tx_req_id, TxStatus::Submitted);
 
-        let resp: Result<()> = send_post_request_using_binary(
-            &format!(
-                "http://{}/{}/{}",
-                storage_node_addr, NODE_RPC_ROUTE_PATH, STORAGE_TX_REQ_ROUTE_PATH
-            ),
-            &req,
-        )
-        .await;
+        let resp = self
+            .peer_health
+            .send_with_failover(storage_node_peer_ids, |peer_id| {
+                let req = &req;
+                let route_table = &route_table;
+                async move {
+                    let storage_node_addr = route_table.peer_address(peer_id)?;
+                    self.node_info
+                        .ensure_compatible(peer_id, storage_node_addr)
+                        .await?;
+                    RPC_METRICS
+                        .track(
+                            peer_id,
+                            STORAGE_TX_REQ_ROUTE_PATH,
+                            send_post_request_using_binary_with_timeout::<_, ()>(
+                                &format!(
+                                    "http://{}/{}/{}",
+                                    storage_node_addr,
+                                    NODE_RPC_ROUTE_PATH,
+                                    STORAGE_TX_REQ_ROUTE_PATH
+                                ),
+                                req,
+                                self.timeout_cfg.forward_tx,
+                            ),
+                        )
+                        .await
+                }
+            })
+            .await;
 
-        if let Err(e) = resp {
-            error!(
-                %tx_req_id,
-                "Failed to forward TX to storage node. Error: {}", e
-            );
+        match resp {
+            Ok(_) => TX_STATUS.record(tx_req_id, TxStatus::Forwarded),
+            Err(e) => {
+                error!(
+                    %tx_req_id,
+                    "Failed to forward TX to storage node. Error: {}", e
+                );
+                TX_STATUS.record_failure(tx_req_id, format!("forward_failed: {}", e));
+            }
         }
     }
 
-    pub async fn set_leader(&self, leader_id: PeerId) {
-        *self.leader_id.write().await = Some(leader_id);
+    pub fn route_table(&self) -> &SharedRouteTable {
+        &self.route_table
+    }
+
+    pub fn peer_health(&self) -> &PeerHealth {
+        &self.peer_health
+    }
+
+    pub fn node_info(&self) -> &NodeInfoCache {
+        &self.node_info
     }
 
+    /// Whether any storage node is known and not marked down by the health probes. The HTTP
+    /// network has no connection events, so this stands in for the connected storage peers.
+    pub fn has_storage_peers(&self) -> bool {
+        self.route_table
+            .load()
+            .role_table()
+            .iter()
+            .filter(|(role, _)| matches!(role, Role::Storage(_)))
+            .flat_map(|(_, list)| list.iter())
+            .any(|&peer_id| !self.peer_health.is_down(peer_id))
+    }
+
+    pub fn leader_tracker(&self) -> &Arc<LeaderTracker> {
+        &self.leader_tracker
+    }
+
+    pub fn set_leader(&self, leader_id: PeerId) {
+        self.leader_tracker.set_leader(Some(leader_id));
+    }
+
+    /// Forward `tx_proposals` to the current leader. If it fails, refresh the leader and retry
+    /// up to [`MAX_FORWARD_LEADER_ATTEMPTS`] times, unless the error is not retryable.
     #[allow(clippy::ptr_arg)]
     #[tracing::instrument(level = "debug", skip(self, tx_proposals), err)]
     pub async fn forward_tx_proposal_to_leader(
         &self,
         tx_proposals: &Vec<TxProposal<Tx>>,
     ) -> Result<()> {
-        let leader_id = *self.leader_id.read().await;
-        let leader_id = match leader_id {
-            Some(id) => id,
-            None => {
-                let id = fetch_leader_id(&self.route_table).await?;
-                *self.leader_id.write().await = Some(id);
-                id
+        let mut last_err = None;
+        for attempt in 1..=MAX_FORWARD_LEADER_ATTEMPTS {
+            let route_table = self.route_table.load();
+            let leader_id = match self.leader_tracker.current_leader() {
+                Some(id) => id,
+                None => match self.fetch_leader_id_from_live_peer(&route_table).await {
+                    Ok(id) => {
+                        self.leader_tracker.set_leader(Some(id));
+                        id
+                    }
+                    Err(e) => {
+                        last_err = Some(e);
+                        continue;
+                    }
+                },
+            };
+            // A learner never leads, so the tx proposals are not sent to it on a stale answer.
+            if route_table.is_learner(leader_id) {
+                self.leader_tracker.invalidate(leader_id);
+                last_err = Some(anyhow!("Peer {} is a learner, not the leader.", leader_id));
+                continue;
             }
-        };
 
-        debug_assert_ne!(leader_id, self.route_table.peer_id());
-        let addr = self.route_table.peer_address(leader_id)?;
-        match send_reqs_to_leader(addr, tx_proposals).await {
-            Err(e) => {
-                *self.leader_id.write().await = None;
-                Err(e)
+            let res = match route_table.peer_address(leader_id) {
+                Ok(addr) => match self.node_info.ensure_compatible(leader_id, addr).await {
+                    Ok(()) => {
+                        RPC_METRICS
+                            .track(
+                                leader_id,
+                                CLIENT_LEADER_REQ_ROUTE_PATH,
+                                send_reqs_to_leader_with_timeout(
+                                    addr,
+                                    tx_proposals,
+                                    self.timeout_cfg.forward_tx_proposal,
+                                ),
+                            )
+                            .await
+                    }
+                    Err(e) => Err(e),
+                },
+                Err(e) => Err(e),
+            };
+
+            match res {
+                Ok(()) => return Ok(()),
+                Err(e) if !is_retryable_error(&e) => return Err(e),
+                Err(e) => {
+                    warn!(
+                        attempt,
+                        %leader_id,
+                        "Failed to forward tx proposals to leader. Error: {}", e
+                    );
+                    self.leader_tracker.invalidate(leader_id);
+                    last_err = Some(e);
+                }
             }
-            Ok(()) => Ok(()),
+        }
+        Err(last_err.unwrap_or_else(|| anyhow!("Failed to forward tx proposals to leader.")))
+    }
+
+    /// Like [`fetch_leader_id`], but skip the client nodes marked down.
+    async fn fetch_leader_id_from_live_peer(
+        &self,
+        route_table: &NetworkRouteTable,
+    ) -> Result<PeerId> {
+        let rand_client = self
+            .peer_health
+            .random_peer(route_table.peers_for_role(&Role::Client))
+            .ok_or_else(|| NetworkError::NoPeerForRole(Role::Client).into())
+            .and_then(|peer_id| route_table.peer_address(peer_id))?;
+        get_leader(rand_client).await
+    }
+
+    /// Forward `tx_proposals` to the leader. The undeliverable ones are parked and retried
+    /// once a new leader is observed, or every [`PARKED_RETRY_INTERVAL`].
+    pub async fn forward_or_park_tx_proposals(&self, tx_proposals: Vec<TxProposal<Tx>>) {
+        if tx_proposals.is_empty() {
+            return;
+        }
+
+        if let Err(e) = self.forward_tx_proposal_to_leader(&tx_proposals).await {
+            error!(
+                "Failed to forward tx proposals to leader. Park them for a retry. Error: {}",
+                e
+            );
+            let mut parked = self.parked_tx_proposals.lock().await;
+            for tx_proposal in tx_proposals {
+                let tx_id = tx_proposal.tx.id();
+                if parked.len() < MAX_PARKED_TX_PROPOSALS {
+                    record_event!("park_tx", "tx_id": tx_id);
+                    parked.push(tx_proposal);
+                } else {
+                    record_event!("discard_tx", "tx_id": tx_id, "reason": "raft_forward_leader_error");
+                }
+            }
+        }
+    }
+
+    /// Retry forwarding the parked tx proposals to the leader.
+    pub async fn retry_parked_tx_proposals(&self) {
+        let tx_proposals = std::mem::take(&mut *self.parked_tx_proposals.lock().await);
+        if !tx_proposals.is_empty() {
+            info!(
+                "Retry forwarding {} parked tx proposals to leader.",
+                tx_proposals.len()
+            );
+            self.forward_or_park_tx_proposals(tx_proposals).await;
         }
     }
 
@@ -137,56 +347,169 @@ where
     pub async fn broadcast_block_proposal_to_storage_node(
         &self,
         block_proposals: &Vec<BlockProposal<Block, Tx>>,
-    ) -> Result<()> {
+    ) -> Result<BroadcastSummary> {
+        let (bytes, summary) = self
+            .broadcast_block_proposal(
+                block_proposals,
+                |role| matches!(role, Role::Storage(_)),
+                STORAGE_BLOCK_IMPORT_ROUTE_PATH,
+            )
+            .await?;
+        if let (Some(first), Some(last)) = (block_proposals.first(), block_proposals.last()) {
+            self.block_delivery.record_broadcast(
+                first.get_block_height(),
+                last.get_block_height(),
+                bytes,
+                &summary,
+            );
+        }
+        Ok(summary)
+    }
+
+    #[allow(clippy::ptr_arg)]
+    #[tracing::instrument(level = "debug", skip(self, block_proposals), err)]
+    pub async fn broadcast_block_proposal_to_observer_node(
+        &self,
+        block_proposals: &Vec<BlockProposal<Block, Tx>>,
+    ) -> Result<BroadcastSummary> {
+        let (_, summary) = self
+            .broadcast_block_proposal(
+                block_proposals,
+                |role| matches!(role, Role::Observer),
+                OBSERVER_BLOCK_IMPORT_ROUTE_PATH,
+            )
+            .await?;
+        Ok(summary)
+    }
+
+    /// The delivery status of the block proposal at `height` to the storage nodes, if it is
+    /// still in the re-broadcast window.
+    pub fn delivery_status(&self, height: BlockHeight) -> Option<DeliveryStatus> {
+        self.block_delivery.delivery_status(height)
+    }
+
+    /// Re-broadcast the block proposals to the storage nodes which have not acked them and
+    /// whose backoff expired.
+    pub async fn rebroadcast_missed_block_proposals(&self) {
+        for (begin_block_height, bytes, peer_ids) in self.block_delivery.due_retries() {
+            debug!(%begin_block_height, ?peer_ids, "Re-broadcast block proposal.");
+            let summary = self
+                .broadcast_body(
+                    peer_ids,
+                    &BinaryBody::new(bytes),
+                    STORAGE_BLOCK_IMPORT_ROUTE_PATH,
+                )
+                .await;
+            for (peer_id, resp) in summary {
+                self.block_delivery
+                    .record_retry(begin_block_height, peer_id, resp.is_ok());
+            }
+        }
+    }
+
+    /// Broadcast `block_proposals` to the peers matching `role_filter`.
+    /// Return the encoded block proposals and the outcome for each peer.
+    #[allow(clippy::ptr_arg)]
+    async fn broadcast_block_proposal(
+        &self,
+        block_proposals: &Vec<BlockProposal<Block, Tx>>,
+        role_filter: impl Fn(&Role) -> bool,
+        route_path: &'static str,
+    ) -> Result<(Bytes, BroadcastSummary)> {
         if block_proposals.is_empty() {
-            return Ok(());
+            return Ok((Bytes::new(), Vec::new()));
         }
 
         let bytes = Bytes::from(binary_encode(block_proposals)?);
-        let reqs = self
+        let peer_ids: Vec<PeerId> = self
             .route_table
+            .load()
             .role_table()
             .iter()
-            .filter(|(role, _)| matches!(role, Role::Storage(_)))
-            .flat_map(|(_, list)| list.iter())
-            .filter_map(|&peer_id| match self.route_table.peer_address(peer_id) {
-                Ok(addr) => Some((
-                    peer_id,
-                    format!(
-                        "http://{}/{}/{}",
-                        addr, NODE_RPC_ROUTE_PATH, STORAGE_BLOCK_IMPORT_ROUTE_PATH
-                    ),
-                )),
-                Err(_) => {
-                    warn!("Failed to get the peer address. PeerId: {}", peer_id);
-                    None
-                }
-            })
-            .map(|(peer_id, uri)| {
-                let bytes = bytes.clone();
-                async move {
-                    (
-                        peer_id,
-                        send_post_request_using_binary_bytes::<()>(&uri, bytes).await,
-                    )
-                }
-            });
+            .filter(|(role, _)| role_filter(role))
+            .flat_map(|(_, list)| list.iter().copied())
+            .collect();
+        // The broadcast goes to all the peers. The ranking is only for diagnosis.
+        debug!(ranking = ?self.peer_health.rank(&peer_ids), "Broadcast block proposal to {}.", route_path);
+
+        // Compress once for all the peers.
+        let body = BinaryBody::new(bytes.clone());
+        let summary = self.broadcast_body(peer_ids, &body, route_path).await;
 
-        for (peer_id, resp) in future::join_all(reqs).await {
+        let begin_block_height = block_proposals
+            .first()
+            .expect("empty block proposals")
+            .get_block_height();
+        let end_block_height = block_proposals
+            .last()
+            .expect("empty block proposals")
+            .get_block_height();
+        let failed = summary.iter().filter(|(_, resp)| resp.is_err()).count();
+        for (peer_id, resp) in &summary {
             if let Err(e) = resp {
-                let begin_block_height = block_proposals
-                    .first()
-                    .expect("empty block proposals")
-                    .get_block_height();
-                let end_block_height = block_proposals
-                    .last()
-                    .expect("empty block proposals")
-                    .get_block_height();
-                error!(%begin_block_height, %end_block_height, %peer_id, "Failed to broadcast block proposal to storage node. Err: {:?}", e);
+                error!(%begin_block_height, %end_block_height, %peer_id, "Failed to broadcast block proposal to {}. Err: {:?}", route_path, e);
             }
         }
+        debug!(%begin_block_height, %end_block_height, peers = summary.len(), failed, "Broadcast block proposal to {} done.", route_path);
 
-        Ok(())
+        Ok((bytes, summary))
+    }
+
+    /// Send `body` to `peer_ids`, with at most `broadcast_cfg.concurrency` requests in flight.
+    /// Return the outcome for each peer.
+    async fn broadcast_body(
+        &self,
+        peer_ids: Vec<PeerId>,
+        body: &BinaryBody,
+        route_path: &'static str,
+    ) -> BroadcastSummary {
+        let deadline = Instant::now() + self.broadcast_cfg.deadline;
+        let route_table = self.route_table.load();
+        let route_table = &route_table;
+        let summary: BroadcastSummary = stream::iter(peer_ids)
+            .map(|peer_id| async move {
+                let addr = match route_table.peer_address(peer_id) {
+                    Ok(addr) => addr,
+                    Err(e) => return (peer_id, Err(e)),
+                };
+                if let Err(e) = self.node_info.ensure_compatible(peer_id, addr).await {
+                    return (peer_id, Err(e));
+                }
+                let uri = format!("http://{}/{}/{}", addr, NODE_RPC_ROUTE_PATH, route_path);
+                let resp = RPC_METRICS
+                    .track(peer_id, route_path, async {
+                        timeout_at(
+                            deadline,
+                            send_post_request_using_binary_body_with_timeout::<()>(
+                                &uri,
+                                body,
+                                self.timeout_cfg.broadcast,
+                            ),
+                        )
+                        .await
+                        .unwrap_or_else(|_| {
+                            Err(NetworkError::Timeout {
+                                uri: uri.clone(),
+                                timeout: self.broadcast_cfg.deadline,
+                            }
+                            .into())
+                        })
+                    })
+                    .await;
+                (peer_id, resp)
+            })
+            .buffer_unordered(self.broadcast_cfg.concurrency.max(1))
+            .collect()
+            .await;
+
+        for (peer_id, resp) in &summary {
+            if resp.is_ok() {
+                self.peer_health.record_success(*peer_id);
+            } else {
+                self.peer_health.record_failure(*peer_id);
+            }
+        }
+        summary
     }
 }
 
@@ -202,18 +525,40 @@ where
         rpc: AppendEntriesRequest<NewBlockRequest<Tx>>,
     ) -> Result<AppendEntriesResponse> {
         let peer_id = PeerId::from(target);
-        debug_assert_ne!(peer_id, self.route_table.peer_id());
-        let addr = self.route_table.peer_address(peer_id)?;
-        send_post_request_using_binary(
-            &format!(
-                "http://{}/{}/{}",
-                addr, NODE_RPC_ROUTE_PATH, RAFT_APPEND_ENTRIES_ROUTE_PATH
-            ),
-            &rpc,
-        )
-        .await
+        let route_table = self.route_table.load();
+        debug_assert_ne!(peer_id, route_table.peer_id());
+        // The target of the leader transfer times out without them.
+        ensure!(
+            !self.leader_transfer.is_held_back(peer_id),
+            "The append entries to peer {} are held back for the leader transfer.",
+            peer_id
+        );
+        let addr = route_table.peer_address(peer_id)?;
+        self.node_info.ensure_compatible(peer_id, addr).await?;
+        let matched = rpc.prev_log_index + rpc.entries.len() as u64;
+        let resp: AppendEntriesResponse = RPC_METRICS
+            .track(
+                peer_id,
+                RAFT_APPEND_ENTRIES_ROUTE_PATH,
+                send_post_request_using_binary_with_timeout(
+                    &format!(
+                        "http://{}/{}/{}",
+                        addr, NODE_RPC_ROUTE_PATH, RAFT_APPEND_ENTRIES_ROUTE_PATH
+                    ),
+                    &rpc,
+                    self.timeout_cfg.raft,
+                ),
+            )
+            .await?;
+        if resp.success {
+            self.leader_transfer.record_matched(peer_id, matched);
+        }
+        Ok(resp)
     }
 
+    /// Upload `rpc` to the target in chunks of `snapshot_chunk_size`. The upload starts by asking
+    /// the target for the data it already has, so a failed upload resumes when the same `rpc`
+    /// is retried.
     #[tracing::instrument(level = "debug", skip(self, rpc))]
     async fn install_snapshot(
         &self,
@@ -221,31 +566,122 @@ where
         rpc: InstallSnapshotRequest,
     ) -> Result<InstallSnapshotResponse> {
         let peer_id = PeerId::from(target);
-        debug_assert_ne!(peer_id, self.route_table.peer_id());
-        let addr = self.route_table.peer_address(peer_id)?;
-        send_post_request_using_binary(
-            &format!(
-                "http://{}/{}/{}",
-                addr, NODE_RPC_ROUTE_PATH, RAFT_INSTALL_SNAPSHOT_ROUTE_PATH
-            ),
-            &rpc,
-        )
-        .await
+        let route_table = self.route_table.load();
+        debug_assert_ne!(peer_id, route_table.peer_id());
+        let addr = route_table.peer_address(peer_id)?;
+        self.node_info.ensure_compatible(peer_id, addr).await?;
+
+        let begin = SnapshotBeginRequest::new(route_table.peer_id(), &rpc);
+        let session_id = begin.session_id;
+        let len = begin.len;
+        let mut offset: u64 = RPC_METRICS
+            .track(
+                peer_id,
+                RAFT_SNAPSHOT_BEGIN_ROUTE_PATH,
+                send_post_request_using_binary_with_timeout(
+                    &format!(
+                        "http://{}/{}/{}",
+                        addr, NODE_RPC_ROUTE_PATH, RAFT_SNAPSHOT_BEGIN_ROUTE_PATH
+                    ),
+                    &begin,
+                    self.timeout_cfg.raft,
+                ),
+            )
+            .await?;
+        if offset > 0 {
+            debug!(%peer_id, session_id, offset, len, "Resume snapshot upload.");
+        }
+
+        let mut chunk_size = self.snapshot_chunk_size.max(1) as u64;
+        while offset < len {
+            let end = (offset + chunk_size).min(len);
+            let chunk = SnapshotChunkRequest {
+                session_id,
+                offset,
+                data: rpc.data[offset as usize..end as usize].to_vec(),
+            };
+            let received: u64 = match RPC_METRICS
+                .track(
+                    peer_id,
+                    RAFT_SNAPSHOT_CHUNK_ROUTE_PATH,
+                    send_post_request_using_binary_with_timeout(
+                        &format!(
+                            "http://{}/{}/{}",
+                            addr, NODE_RPC_ROUTE_PATH, RAFT_SNAPSHOT_CHUNK_ROUTE_PATH
+                        ),
+                        &chunk,
+                        self.timeout_cfg.raft,
+                    ),
+                )
+                .await
+            {
+                Ok(received) => received,
+                Err(e) => {
+                    // Retry with the chunks fitting in the body limit of the target.
+                    let fitted = body_limit_of_error(&e)
+                        .map(|limit| limit.saturating_sub(SNAPSHOT_CHUNK_OVERHEAD))
+                        .filter(|&fitted| fitted > 0 && fitted < chunk_size);
+                    match fitted {
+                        Some(fitted) => {
+                            warn!(
+                                %peer_id,
+                                chunk_size,
+                                fitted,
+                                "Snapshot chunk exceeds the body limit. Shrink it."
+                            );
+                            chunk_size = fitted;
+                            continue;
+                        }
+                        None => return Err(e),
+                    }
+                }
+            };
+            ensure!(
+                received > offset && received <= len,
+                "Unexpected snapshot upload offset. Sent: {}. Received: {}.",
+                offset,
+                received
+            );
+            offset = received;
+        }
+
+        RPC_METRICS
+            .track(
+                peer_id,
+                RAFT_SNAPSHOT_END_ROUTE_PATH,
+                send_post_request_using_binary_with_timeout(
+                    &format!(
+                        "http://{}/{}/{}",
+                        addr, NODE_RPC_ROUTE_PATH, RAFT_SNAPSHOT_END_ROUTE_PATH
+                    ),
+                    &SnapshotEndRequest { session_id },
+                    self.timeout_cfg.raft,
+                ),
+            )
+            .await
     }
 
     #[tracing::instrument(level = "debug", skip(self, rpc))]
     async fn vote(&self, target: NodeId, rpc: VoteRequest) -> Result<VoteResponse> {
         let peer_id = PeerId::from(target);
-        debug_assert_ne!(peer_id, self.route_table.peer_id());
-        let addr = self.route_table.peer_address(peer_id)?;
-        send_post_request_using_binary(
-            &format!(
-                "http://{}/{}/{}",
-                addr, NODE_RPC_ROUTE_PATH, RAFT_VOTE_ROUTE_PATH
-            ),
-            &rpc,
-        )
-        .await
+        let route_table = self.route_table.load();
+        debug_assert_ne!(peer_id, route_table.peer_id());
+        let addr = route_table.peer_address(peer_id)?;
+        self.node_info.ensure_compatible(peer_id, addr).await?;
+        RPC_METRICS
+            .track(
+                peer_id,
+                RAFT_VOTE_ROUTE_PATH,
+                send_post_request_using_binary_with_timeout(
+                    &format!(
+                        "http://{}/{}/{}",
+                        addr, NODE_RPC_ROUTE_PATH, RAFT_VOTE_ROUTE_PATH
+                    ),
+                    &rpc,
+                    self.timeout_cfg.raft,
+                ),
+            )
+            .await
     }
 }
 
@@ -253,51 +689,78 @@ pub struct ClientNodeNetworkWorker<Tx>
 where
     Tx: TxTrait + Serialize + for<'de> Deserialize<'de> + 'static,
 {
-    req_handle: Option<JoinHandle<()>>,
+    req_handle: Option<JoinHandle<usize>>,
     req_tx: mpsc::UnboundedSender<TxHttpRequest>,
     req_shutdown_tx: Option<oneshot::Sender<()>>,
-    block_proposal_handle: Option<JoinHandle<()>>,
+    block_proposal_handle: Option<JoinHandle<usize>>,
     block_proposal_tx: mpsc::UnboundedSender<BlockProposal<Block, Tx>>,
     block_proposal_shutdown_tx: Option<oneshot::Sender<()>>,
+    parked_handle: Option<JoinHandle<()>>,
+    rebroadcast_handle: Option<JoinHandle<()>>,
 }
 
 impl<Tx> ClientNodeNetworkWorker<Tx>
 where
     Tx: TxTrait + Serialize + for<'de> Deserialize<'de> + 'static,
 {
-    pub fn new(network: Arc<ClientNodeNetwork<Tx>>, async_broadcast_storage: bool) -> Self {
+    /// `broadcast_stage` counts the block proposals sent by the async broadcast but not yet
+    /// delivered.
+    pub fn new(
+        network: Arc<ClientNodeNetwork<Tx>>,
+        async_broadcast_storage: bool,
+        broadcast_stage: Arc<PipelineStage>,
+    ) -> Self {
         let (req_tx, req_rx) = mpsc::unbounded();
-        let req_fut = {
-            let network = network.clone();
-            req_rx.for_each_concurrent(64, move |req| {
-                let network = network.clone();
-                async move { network.forward_tx_to_storage_node(req).await }
-            })
-        };
         let (req_shutdown_tx, req_shutdown_rx) = oneshot::channel();
+        let req_handle = tokio::spawn(forward_tx_requests(
+            network.clone(),
+            req_rx,
+            req_shutdown_rx,
+        ));
 
-        let req_handle = tokio::spawn(async move {
-            tokio::select! {
-                _ = req_shutdown_rx => {}
-                _ = req_fut => {}
-            }
-        });
-
-        let (block_proposal_tx, block_proposal_rx) = mpsc::unbounded();
-        let mut block_proposal_rx = block_proposal_rx.ready_chunks(8);
-        let (block_proposal_shutdown_tx, mut block_proposal_shutdown_rx) = oneshot::channel();
-
-        let block_proposal_handle = if async_broadcast_storage {
-            Some(tokio::spawn(async move {
+        let parked_handle = {
+            let network = network.clone();
+            let mut leader_rx = network.leader_tracker().subscribe();
+            tokio::spawn(async move {
+                let mut ticker = tokio::time::interval(PARKED_RETRY_INTERVAL);
                 loop {
                     tokio::select! {
-                        _ = &mut block_proposal_shutdown_rx => break,
-                        Some(block_proposals) = block_proposal_rx.next() => {
-                            network.broadcast_block_proposal_to_storage_node(&block_proposals).await.ok();
+                        changed = leader_rx.changed() => {
+                            if changed.is_err() {
+                                break;
+                            }
+                            let has_leader = leader_rx.borrow().is_some();
+                            if has_leader {
+                                network.retry_parked_tx_proposals().await;
+                            }
                         }
+                        // The leader may come back to accept them without any change observed.
+                        _ = ticker.tick() => network.retry_parked_tx_proposals().await,
                     }
                 }
-            }))
+            })
+        };
+
+        let rebroadcast_handle = {
+            let network = network.clone();
+            tokio::spawn(async move {
+                let mut ticker = tokio::time::interval(REBROADCAST_INTERVAL);
+                loop {
+                    ticker.tick().await;
+                    network.rebroadcast_missed_block_proposals().await;
+                }
+            })
+        };
+
+        let (block_proposal_tx, block_proposal_rx) = mpsc::unbounded();
+        let (block_proposal_shutdown_tx, block_proposal_shutdown_rx) = oneshot::channel();
+        let block_proposal_handle = if async_broadcast_storage {
+            Some(tokio::spawn(broadcast_block_proposals(
+                network,
+                block_proposal_rx,
+                block_proposal_shutdown_rx,
+                broadcast_stage,
+            )))
         } else {
             None
         };
@@ -309,6 +772,8 @@ where
             block_proposal_handle,
             block_proposal_tx,
             block_proposal_shutdown_tx: Some(block_proposal_shutdown_tx),
+            parked_handle: Some(parked_handle),
+            rebroadcast_handle: Some(rebroadcast_handle),
         }
     }
 
@@ -320,30 +785,401 @@ where
         self.block_proposal_tx.clone()
     }
 
-    pub async fn shutdown(&mut self) -> Result<()> {
+    /// Stop accepting new tx requests and block proposals, and wait for the queued ones to be
+    /// sent within [`SHUTDOWN_DRAIN_TIMEOUT`]. Return the ones left unsent.
+    pub async fn shutdown(&mut self) -> Result<ShutdownReport> {
+        let deadline = Instant::now() + SHUTDOWN_DRAIN_TIMEOUT;
+
         self.req_tx.close_channel();
-        if let Some(shutdown_tx) = self.req_shutdown_tx.take() {
-            shutdown_tx.send(()).ok();
+        self.block_proposal_tx.close_channel();
+        let req_shutdown_tx = self.req_shutdown_tx.take().context("Already shutdown.")?;
+        let block_proposal_shutdown_tx = self
+            .block_proposal_shutdown_tx
+            .take()
+            .context("Already shutdown.")?;
+
+        if let Some(handler) = self.parked_handle.take() {
+            handler.abort();
         } else {
             bail!("Already shutdown.");
         }
-        if let Some(handler) = self.req_handle.take() {
-            handler.await?;
+        if let Some(handler) = self.rebroadcast_handle.take() {
+            handler.abort();
         } else {
             bail!("Already shutdown.");
         }
 
-        self.block_proposal_tx.close_channel();
-        if let Some(shutdown_tx) = self.block_proposal_shutdown_tx.take() {
-            shutdown_tx.send(()).ok();
+        let mut report = ShutdownReport::default();
+        if let Some(handler) = self.req_handle.take() {
+            report.undelivered_tx_reqs = drain_until(deadline, handler, req_shutdown_tx).await?;
         } else {
             bail!("Already shutdown.");
         }
         if let Some(handler) = self.block_proposal_handle.take() {
-            handler.await?;
-        } else {
-            bail!("Already shutdown.");
+            report.undelivered_block_proposals =
+                drain_until(deadline, handler, block_proposal_shutdown_tx).await?;
+        }
+
+        if report != ShutdownReport::default() {
+            warn!("Shutdown before all the work was done. {:?}", report);
+            record_event!("client_network_shutdown", "undelivered_tx_reqs": report.undelivered_tx_reqs, "undelivered_block_proposals": report.undelivered_block_proposals);
+        }
+        Ok(report)
+    }
+}
+
+/// The work left undone by [`ClientNodeNetworkWorker::shutdown`].
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub struct ShutdownReport {
+    pub undelivered_tx_reqs: usize,
+    pub undelivered_block_proposals: usize,
+}
+
+/// Wait for the worker `handle` to drain its queue. Signal it to stop via `stop_tx` once
+/// `deadline` is reached. Return the number of the items it left.
+async fn drain_until(
+    deadline: Instant,
+    mut handle: JoinHandle<usize>,
+    stop_tx: oneshot::Sender<()>,
+) -> Result<usize> {
+    match timeout_at(deadline, &mut handle).await {
+        Ok(res) => Ok(res?),
+        Err(_) => {
+            stop_tx.send(()).ok();
+            Ok(handle.await?)
+        }
+    }
+}
+
+/// Forward the tx requests from `req_rx` until it is closed and drained, or `stop_rx` fires.
+/// Return the number of the tx requests left.
+async fn forward_tx_requests<Tx>(
+    network: Arc<ClientNodeNetwork<Tx>>,
+    mut req_rx: mpsc::UnboundedReceiver<TxHttpRequest>,
+    mut stop_rx: oneshot::Receiver<()>,
+) -> usize
+where
+    Tx: TxTrait + Serialize + for<'de> Deserialize<'de> + 'static,
+{
+    let mut in_flight = stream::FuturesUnordered::new();
+    let mut closed = false;
+    while !closed || !in_flight.is_empty() {
+        // The stop signal is checked first, and no received request is dropped by the
+        // branches losing the race.
+        tokio::select! {
+            biased;
+            _ = &mut stop_rx => break,
+            Some(()) = in_flight.next(), if !in_flight.is_empty() => {}
+            req = req_rx.next(), if !closed && in_flight.len() < MAX_CONCURRENT_TX_FORWARDS => {
+                match req {
+                    Some(req) => {
+                        let network = network.clone();
+                        in_flight.push(async move { network.forward_tx_to_storage_node(req).await });
+                    }
+                    None => closed = true,
+                }
+            }
+        }
+    }
+
+    let mut undelivered = in_flight.len();
+    while let Ok(Some(req)) = req_rx.try_next() {
+        TX_STATUS.record_failure(req.req.id(), "shutdown");
+        undelivered += 1;
+    }
+    undelivered
+}
+
+/// Broadcast the block proposals from `block_proposal_rx` until it is closed and drained,
+/// or `stop_rx` fires. Return the number of the block proposals left.
+async fn broadcast_block_proposals<Tx>(
+    network: Arc<ClientNodeNetwork<Tx>>,
+    block_proposal_rx: mpsc::UnboundedReceiver<BlockProposal<Block, Tx>>,
+    mut stop_rx: oneshot::Receiver<()>,
+    broadcast_stage: Arc<PipelineStage>,
+) -> usize
+where
+    Tx: TxTrait + Serialize + for<'de> Deserialize<'de> + 'static,
+{
+    let mut block_proposal_rx = block_proposal_rx.ready_chunks(MAX_BLOCK_PROPOSALS_PER_IMPORT);
+    loop {
+        tokio::select! {
+            biased;
+            _ = &mut stop_rx => break,
+            block_proposals = block_proposal_rx.next() => match block_proposals {
+                Some(block_proposals) => {
+                    network.broadcast_block_proposal_to_storage_node(&block_proposals).await.ok();
+                    network.broadcast_block_proposal_to_observer_node(&block_proposals).await.ok();
+                    broadcast_stage.pop(block_proposals.len());
+                }
+                None => return 0,
+            },
+        }
+    }
+
+    let mut undelivered = 0;
+    while let Ok(Some(_)) = block_proposal_rx.get_mut().try_next() {
+        undelivered += 1;
+    }
+    undelivered
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::{
+        config::{NetworkConfig, NodeInfoConfig},
+        node_info::{node_info_server, NodeInfo},
+        route_table::RouteTableUpdate,
+    };
+    use slimchain_common::{
+        basic::ShardId,
+        ed25519::Keypair,
+        tx::RawTx,
+        tx_req::{SignedTxRequest, TxRequest},
+    };
+    use slimchain_utils::{config::Config, toml};
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use warp::{http::StatusCode, Filter, Reply};
+
+    fn node_info(role: Role) -> NodeInfo {
+        NodeInfo::local::<Block>(role, &NodeInfoConfig::default())
+    }
+
+    #[tokio::test]
+    async fn test_forward_tx_to_added_storage_node() {
+        let (req_tx, mut req_rx) = mpsc::unbounded::<SignedTxRequest>();
+        let route = warp::post()
+            .and(warp::path(NODE_RPC_ROUTE_PATH))
+            .and(warp::path(STORAGE_TX_REQ_ROUTE_PATH))
+            .and(warp_body_binary())
+            .map(move |req: SignedTxRequest| {
+                req_tx.unbounded_send(req).ok();
+                warp_reply_binary(&())
+            })
+            .or(node_info_server(node_info(Role::Storage(
+                ShardId::default(),
+            ))));
+        let (addr, srv) = warp::serve(route).bind_ephemeral(([127, 0, 0, 1], 0));
+        let srv_handle = tokio::spawn(srv);
+
+        let input = toml::toml! {
+            [network]
+            peer_id = 1
+
+            [[network.peers]]
+            peer_id = 1
+            address = "127.0.0.1:8001"
+        };
+        let net_cfg: NetworkConfig = Config::from_toml(input).get("network").unwrap();
+        let network = ClientNodeNetwork::<RawTx>::new(
+            SharedRouteTable::new(net_cfg.to_route_table()),
+            net_cfg.timeout,
+            net_cfg.broadcast,
+            1024,
+            Arc::new(LeaderTracker::new()),
+            Arc::new(LeaderTransfer::new()),
+            NodeInfoCache::new(node_info(Role::Client), &net_cfg.node_info),
+        );
+
+        let keypair = Keypair::generate(&mut rand::thread_rng());
+        let tx_req = TxHttpRequest {
+            req: TxRequest::Create {
+                nonce: 0u64.into(),
+                code: Default::default(),
+            }
+            .sign(&keypair, 0),
+            shard_id: ShardId::default(),
+        };
+
+        // No storage node is known yet.
+        network.forward_tx_to_storage_node(tx_req.clone()).await;
+
+        network
+            .route_table()
+            .apply_update(RouteTableUpdate::AddPeer {
+                peer_id: PeerId(2),
+                address: addr.to_string(),
+                role: Role::Storage(ShardId::default()),
+            })
+            .unwrap();
+        network.forward_tx_to_storage_node(tx_req.clone()).await;
+        assert_eq!(Some(tx_req.req), req_rx.next().await);
+        assert!(req_rx.try_next().is_err());
+
+        srv_handle.abort();
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_drains_tx_requests() {
+        let received = Arc::new(AtomicUsize::new(0));
+        let received_copy = received.clone();
+        let route = warp::post()
+            .and(warp::path(NODE_RPC_ROUTE_PATH))
+            .and(warp::path(STORAGE_TX_REQ_ROUTE_PATH))
+            .and(warp_body_binary())
+            .and_then(move |_: SignedTxRequest| {
+                let received = received_copy.clone();
+                async move {
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                    received.fetch_add(1, Ordering::SeqCst);
+                    Ok::<_, warp::Rejection>(warp_reply_binary(&()))
+                }
+            })
+            .or(node_info_server(node_info(Role::Storage(
+                ShardId::default(),
+            ))));
+        let (addr, srv) = warp::serve(route).bind_ephemeral(([127, 0, 0, 1], 0));
+        let srv_handle = tokio::spawn(srv);
+
+        let input = toml::toml! {
+            [network]
+            peer_id = 1
+
+            [[network.peers]]
+            peer_id = 1
+            address = "127.0.0.1:8001"
+        };
+        let net_cfg: NetworkConfig = Config::from_toml(input).get("network").unwrap();
+        let route_table = SharedRouteTable::new(net_cfg.to_route_table());
+        route_table
+            .apply_update(RouteTableUpdate::AddPeer {
+                peer_id: PeerId(2),
+                address: addr.to_string(),
+                role: Role::Storage(ShardId::default()),
+            })
+            .unwrap();
+        let network = Arc::new(ClientNodeNetwork::<RawTx>::new(
+            route_table,
+            net_cfg.timeout,
+            net_cfg.broadcast,
+            1024,
+            Arc::new(LeaderTracker::new()),
+            Arc::new(LeaderTransfer::new()),
+            NodeInfoCache::new(node_info(Role::Client), &net_cfg.node_info),
+        ));
+        let mut worker = ClientNodeNetworkWorker::new(
+            network,
+            false,
+            Arc::new(PipelineStage::new("test_broadcast", 0)),
+        );
+
+        let keypair = Keypair::generate(&mut rand::thread_rng());
+        let req_tx = worker.get_req_tx();
+        for nonce in 0..50u64 {
+            let req = TxRequest::Create {
+                nonce: nonce.into(),
+                code: Default::default(),
+            }
+            .sign(&keypair, 0);
+            req_tx
+                .unbounded_send(TxHttpRequest {
+                    req,
+                    shard_id: ShardId::default(),
+                })
+                .unwrap();
+        }
+
+        let report = worker.shutdown().await.unwrap();
+        assert!(req_tx.is_closed());
+        assert_eq!(
+            50,
+            received.load(Ordering::SeqCst) + report.undelivered_tx_reqs
+        );
+        assert!(worker.shutdown().await.is_err());
+
+        srv_handle.abort();
+    }
+
+    #[tokio::test]
+    async fn test_retry_parked_tx_proposals_on_timer() {
+        let up = Arc::new(AtomicBool::new(false));
+        let received = Arc::new(AtomicUsize::new(0));
+        let up_copy = up.clone();
+        let received_copy = received.clone();
+        let route = warp::post()
+            .and(warp::path(NODE_RPC_ROUTE_PATH))
+            .and(warp::path(CLIENT_LEADER_REQ_ROUTE_PATH))
+            .and(warp_body_binary())
+            .map(move |txs: Vec<TxProposal<RawTx>>| {
+                if !up_copy.load(Ordering::SeqCst) {
+                    let resp = warp_reply_binary(&"Not ready.").into_response();
+                    return warp::reply::with_status(resp, StatusCode::BAD_REQUEST).into_response();
+                }
+                received_copy.fetch_add(txs.len(), Ordering::SeqCst);
+                warp_reply_binary(&()).into_response()
+            })
+            .or(node_info_server(node_info(Role::Client)));
+        let (addr, srv) = warp::serve(route).bind_ephemeral(([127, 0, 0, 1], 0));
+        let srv_handle = tokio::spawn(srv);
+
+        let input = toml::toml! {
+            [network]
+            peer_id = 1
+
+            [[network.peers]]
+            peer_id = 1
+            address = "127.0.0.1:8001"
+        };
+        let net_cfg: NetworkConfig = Config::from_toml(input).get("network").unwrap();
+        let route_table = SharedRouteTable::new(net_cfg.to_route_table());
+        route_table
+            .apply_update(RouteTableUpdate::AddPeer {
+                peer_id: PeerId(2),
+                address: addr.to_string(),
+                role: Role::Client,
+            })
+            .unwrap();
+        let leader_tracker = Arc::new(LeaderTracker::new());
+        leader_tracker.set_leader(Some(PeerId(2)));
+        let network = Arc::new(ClientNodeNetwork::<RawTx>::new(
+            route_table,
+            net_cfg.timeout,
+            net_cfg.broadcast,
+            1024,
+            leader_tracker.clone(),
+            Arc::new(LeaderTransfer::new()),
+            NodeInfoCache::new(node_info(Role::Client), &net_cfg.node_info),
+        ));
+        let mut worker = ClientNodeNetworkWorker::new(
+            network.clone(),
+            false,
+            Arc::new(PipelineStage::new("test_broadcast", 0)),
+        );
+
+        // The leader rejects them for now, so they are parked.
+        let tx = RawTx {
+            caller: Default::default(),
+            input: TxRequest::Create {
+                nonce: 0u64.into(),
+                code: Default::default(),
+            },
+            block_height: 0u64.into(),
+            state_root: Default::default(),
+            reads: Default::default(),
+            writes: Default::default(),
+            gas_used: 0,
+            fee: 0,
+        };
+        network
+            .forward_or_park_tx_proposals(vec![TxProposal::new(tx, Default::default())])
+            .await;
+        assert_eq!(0, received.load(Ordering::SeqCst));
+        assert_eq!(Some(PeerId(2)), leader_tracker.current_leader());
+
+        // They reach the same leader once it accepts them.
+        up.store(true, Ordering::SeqCst);
+        let deadline = Instant::now() + PARKED_RETRY_INTERVAL * 5;
+        while received.load(Ordering::SeqCst) == 0 {
+            assert!(
+                Instant::now() < deadline,
+                "The parked tx proposals are not retried."
+            );
+            tokio::time::sleep(Duration::from_millis(50)).await;
         }
-        Ok(())
+        assert_eq!(1, received.load(Ordering::SeqCst));
+
+        worker.shutdown().await.unwrap();
+        srv_handle.abort();
     }
 }
@@ -1,6 +1,13 @@
 use crate::{
-    behavior::raft::message::{NewBlockRequest, NewBlockResponse},
-    http::config::{NetworkConfig, PeerId},
+    behavior::raft::{
+        message::{NewBlockRequest, NewBlockResponse},
+        storage_sync::fetch_blocks,
+    },
+    http::{
+        config::{NetworkConfig, PeerId},
+        peer_health::PeerHealth,
+        route_table::SharedRouteTable,
+    },
 };
 use async_raft::{
     raft::{Entry, EntryPayload, MembershipConfig},
@@ -12,6 +19,7 @@ use itertools::process_results;
 use serde::{Deserialize, Serialize};
 use slimchain_chain::{
     behavior::{commit_block, verify_block},
+    block::{BlockHeader, BlockTrait},
     block_proposal::BlockProposal,
     config::ChainConfig,
     consensus::raft::{verify_consensus, Block},
@@ -22,20 +30,26 @@ use slimchain_chain::{
 use slimchain_common::{
     basic::H256,
     digest::Digestible,
-    error::{anyhow, Result},
+    error::{anyhow, ensure, Result},
     tx::TxTrait,
 };
 use slimchain_tx_state::TxTrie;
-use slimchain_utils::serde::{binary_decode, binary_encode};
+use slimchain_utils::{
+    record_event,
+    serde::{binary_decode, binary_encode},
+};
 use std::{collections::BTreeSet, io::Cursor, marker::PhantomData};
 use tokio::sync::{Mutex, RwLock};
 
+/// The raft snapshot of the client nodes. The blocks are kept in the database of each node, so
+/// it only holds the header of the latest committed block, which the nodes installing it catch
+/// up with.
 #[derive(Clone, Serialize, Deserialize)]
 struct RaftSnapshot {
     index: u64,
     term: u64,
     membership: MembershipConfig,
-    snapshot: Snapshot<Block, TxTrie>,
+    header: BlockHeader,
 }
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -57,6 +71,8 @@ pub struct ClientNodeStorage<Tx: TxTrait> {
     raft_snapshot: RwLock<Option<RaftSnapshot>>,
     raft_sm: RwLock<RaftStateMachine>,
     miner_snapshot: Mutex<Option<(H256, Snapshot<Block, TxTrie>)>>,
+    route_table: SharedRouteTable,
+    peer_health: PeerHealth,
     _marker: PhantomData<Tx>,
 }
 
@@ -64,7 +80,12 @@ impl<Tx> ClientNodeStorage<Tx>
 where
     Tx: TxTrait + Serialize + for<'de> Deserialize<'de> + 'static,
 {
-    pub fn new(db: DBPtr, chain_cfg: &ChainConfig, net_cfg: &NetworkConfig) -> Result<Self> {
+    pub fn new(
+        db: DBPtr,
+        chain_cfg: &ChainConfig,
+        net_cfg: &NetworkConfig,
+        route_table: SharedRouteTable,
+    ) -> Result<Self> {
         let snapshot = Snapshot::<Block, TxTrie>::load_from_db(&db, chain_cfg.state_len)?;
         let latest_block_header = snapshot.to_latest_block_header();
         let latest_tx_count = LatestTxCount::new(0);
@@ -86,6 +107,8 @@ where
                 snapshot,
             }),
             miner_snapshot: Mutex::new(None),
+            route_table,
+            peer_health: PeerHealth::new(),
             _marker: PhantomData,
         })
     }
@@ -129,6 +152,61 @@ where
         self.db.write_async(db_tx).await
     }
 
+    /// Fast-forward the local chain to the block `header` of the snapshot to be installed, by
+    /// importing the missing blocks fetched from the storage nodes with the catch-up protocol.
+    /// Return the snapshot of the chain state at `header`.
+    async fn fast_forward(&self, header: &BlockHeader) -> Result<Snapshot<Block, TxTrie>> {
+        let mut snapshot = self.raft_sm.read().await.snapshot.clone();
+        let local_height = snapshot.current_height();
+        let snapshot_height = header.height;
+        if snapshot_height <= local_height {
+            return Ok(snapshot);
+        }
+
+        let route_table = self.route_table.load();
+        let blk_proposals: Vec<BlockProposal<Block, Tx>> = fetch_blocks(
+            &route_table,
+            &self.peer_health,
+            local_height.next_height(),
+            snapshot_height.next_height(),
+        )
+        .await?;
+        ensure!(
+            blk_proposals
+                .last()
+                .map(|blk| blk.get_block().block_header())
+                == Some(header),
+            "The storage nodes do not have the block at height {} of the snapshot.",
+            snapshot_height
+        );
+
+        // The fetched blocks are linked to the header of the snapshot by their hashes, so the
+        // signatures of their proposers, which the storage nodes do not keep, are not needed.
+        let chain_cfg = ChainConfig {
+            verify_proposer: false,
+            ..self.chain_cfg.clone()
+        };
+        for blk_proposal in &blk_proposals {
+            verify_block(&chain_cfg, &mut snapshot, blk_proposal, verify_consensus).await?;
+            commit_block(
+                &chain_cfg,
+                blk_proposal,
+                &self.db,
+                &self.latest_block_header,
+                &self.latest_tx_count,
+            )
+            .await?;
+        }
+        snapshot.write_async(&self.db).await?;
+
+        info!(
+            "Fast-forward the chain from height {} to {} by the snapshot.",
+            local_height, snapshot_height
+        );
+        record_event!("raft_snapshot_fast_forward", "from": local_height.0, "to": snapshot_height.0, "fetched": blk_proposals.len());
+        Ok(snapshot)
+    }
+
     fn read_log(&self, idx: u64) -> Result<Entry<NewBlockRequest<Tx>>> {
         self.db
             .get_log_object(idx)?
@@ -311,6 +389,7 @@ where
         }
 
         if let Err(e) = commit_block(
+            &self.chain_cfg,
             &blk_proposal,
             &self.db,
             &self.latest_block_header,
@@ -341,8 +420,16 @@ where
 
     #[tracing::instrument(level = "debug", skip(self), err)]
     async fn do_log_compaction(&self) -> Result<CurrentSnapshotData<Self::Snapshot>> {
-        let sm_copy = self.raft_sm.read().await.clone();
-        let last_applied_log = sm_copy.last_applied_log;
+        let (last_applied_log, header) = {
+            let sm = self.raft_sm.read().await;
+            let header = sm
+                .snapshot
+                .get_latest_block()
+                .expect("Failed to get the latest block.")
+                .block_header()
+                .clone();
+            (sm.last_applied_log, header)
+        };
         let membership_config = {
             let log = self.raft_log.read().await;
             process_results(log.iter().rev().map(|idx| self.read_log(*idx)), |iter| {
@@ -396,7 +483,7 @@ where
                 index: last_applied_log,
                 term,
                 membership: membership_config.clone(),
-                snapshot: sm_copy.snapshot,
+                header,
             };
             snapshot_bytes = binary_encode(&snapshot)?;
             *current_snapshot = Some(snapshot);
@@ -425,6 +512,8 @@ where
         snapshot: Box<Self::Snapshot>,
     ) -> Result<()> {
         let new_snapshot: RaftSnapshot = binary_decode(snapshot.get_ref().as_slice())?;
+        // The snapshot is only acked once the local chain has caught up with it.
+        let chain_snapshot = self.fast_forward(&new_snapshot.header).await?;
 
         {
             let mut db_tx = DBTransaction::new();
@@ -474,12 +563,7 @@ where
         {
             let mut sm = self.raft_sm.write().await;
             sm.last_applied_log = new_snapshot.index;
-            sm.snapshot = new_snapshot.snapshot.clone();
-            self.latest_block_header.set_from_block(
-                sm.snapshot
-                    .get_latest_block()
-                    .expect("Failed to get the latest block."),
-            );
+            sm.snapshot = chain_snapshot;
         }
 
         {
@@ -506,3 +590,152 @@ where
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::{common::warp_reply_binary, node_rpc::*, route_table::RouteTableUpdate};
+    use slimchain_chain::{
+        block_proposal::BlockProposalTrie,
+        db::{read_pool::DEFAULT_READ_THREADS, DB},
+        loader::BlockLoaderTrait,
+        role::Role,
+    };
+    use slimchain_common::{
+        basic::{BlockHeight, ShardId},
+        tx::RawTx,
+    };
+    use slimchain_utils::{config::Config, toml};
+    use warp::Filter;
+
+    const STATE_LEN: usize = 2;
+
+    fn chain(len: usize) -> Vec<Block> {
+        let mut blocks = vec![Block::genesis_block()];
+        while blocks.len() < len {
+            let prev = blocks.last().unwrap();
+            let mut blk = prev.clone();
+            blk.block_header_mut().height = prev.block_height().next_height();
+            blk.block_header_mut().prev_blk_hash = prev.to_digest();
+            blocks.push(blk);
+        }
+        blocks
+    }
+
+    fn storage_peer(blocks: Vec<Block>) -> (String, tokio::task::JoinHandle<()>) {
+        let route = warp::path(NODE_RPC_ROUTE_PATH)
+            .and(warp::path(STORAGE_GET_BLOCKS_ROUTE_PATH))
+            .and(warp::query::<GetBlocksQuery>())
+            .map(move |query: GetBlocksQuery| {
+                let blk_proposals: Vec<BlockProposal<Block, RawTx>> = blocks
+                    [query.from as usize..query.to as usize]
+                    .iter()
+                    .map(|blk| {
+                        BlockProposal::new(
+                            blk.clone(),
+                            Vec::new(),
+                            BlockProposalTrie::Diff(Default::default()),
+                        )
+                    })
+                    .collect();
+                warp_reply_binary(&blk_proposals)
+            });
+        let (addr, srv) = warp::serve(route).bind_ephemeral(([127, 0, 0, 1], 0));
+        (addr.to_string(), tokio::spawn(srv))
+    }
+
+    #[tokio::test]
+    async fn test_install_snapshot_on_lagging_node() {
+        let blocks = chain(10);
+        let latest = blocks.last().unwrap().clone();
+        let (storage_addr, storage_srv) = storage_peer(blocks.clone());
+
+        let input = toml::toml! {
+            [chain]
+            conflict_check = "ssi"
+            state_len = 2
+            consensus = "raft"
+            index_tx_location = true
+
+            [network]
+            peer_id = 1
+
+            [[network.peers]]
+            peer_id = 1
+            address = "127.0.0.1:8001"
+            role = "client"
+        };
+        let cfg = Config::from_toml(input);
+        let chain_cfg: ChainConfig = cfg.get("chain").unwrap();
+        let net_cfg: NetworkConfig = cfg.get("network").unwrap();
+        let mut route_table = net_cfg.to_route_table();
+        route_table
+            .apply_update(RouteTableUpdate::AddPeer {
+                peer_id: PeerId(2),
+                address: storage_addr,
+                role: Role::Storage(ShardId::default()),
+            })
+            .unwrap();
+
+        // The node only has the genesis block, while the leader has compacted its log.
+        let db = DB::open_memory(DEFAULT_READ_THREADS);
+        let storage = ClientNodeStorage::<RawTx>::new(
+            db.clone(),
+            &chain_cfg,
+            &net_cfg,
+            SharedRouteTable::new(route_table),
+        )
+        .unwrap();
+
+        let install = |header: BlockHeader| {
+            let raft_snapshot = RaftSnapshot {
+                index: 12,
+                term: 1,
+                membership: MembershipConfig::new_initial(net_cfg.peer_id.into()),
+                header,
+            };
+            let bytes = binary_encode(&raft_snapshot).unwrap();
+            storage.finalize_snapshot_installation(
+                12,
+                1,
+                None,
+                "".into(),
+                Box::new(Cursor::new(bytes)),
+            )
+        };
+
+        // A snapshot of a block the storage nodes do not have is not installed.
+        let mut forked = latest.block_header().clone();
+        forked.state_root = H256::repeat_byte(1);
+        assert!(install(forked).await.is_err());
+        assert_eq!(
+            BlockHeight::from(0),
+            storage.latest_snapshot().await.current_height()
+        );
+
+        install(latest.block_header().clone()).await.unwrap();
+
+        assert_eq!(
+            latest.block_height(),
+            storage.latest_block_header().get_height()
+        );
+        assert_eq!(
+            latest.block_height(),
+            storage.latest_snapshot().await.current_height()
+        );
+        // The blocks up to the snapshot are fetched from the storage node.
+        for blk in &blocks[1..] {
+            let stored: Block = db.get_block(blk.block_height()).unwrap();
+            assert_eq!(blk.to_digest(), stored.to_digest());
+        }
+        // The chain is recovered from the database after a restart.
+        let restarted = Snapshot::<Block, TxTrie>::load_from_db(&db, STATE_LEN).unwrap();
+        assert_eq!(latest.block_height(), restarted.current_height());
+        assert_eq!(
+            latest.to_digest(),
+            restarted.get_latest_block().unwrap().to_digest()
+        );
+
+        storage_srv.abort();
+    }
+}
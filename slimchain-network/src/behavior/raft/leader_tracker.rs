@@ -0,0 +1,102 @@
+use crate::http::config::PeerId;
+use async_raft::{AppData, AppDataResponse, Raft, RaftNetwork, RaftStorage};
+use tokio::{sync::watch, task::JoinHandle};
+
+/// Track the current raft leader. It is shared between the raft client node, which updates it
+/// from the raft metrics, and the network, which forwards tx proposals to the leader.
+#[derive(Debug)]
+pub struct LeaderTracker {
+    tx: watch::Sender<Option<PeerId>>,
+    rx: watch::Receiver<Option<PeerId>>,
+}
+
+impl LeaderTracker {
+    pub fn new() -> Self {
+        let (tx, rx) = watch::channel(None);
+        Self { tx, rx }
+    }
+
+    pub fn current_leader(&self) -> Option<PeerId> {
+        *self.rx.borrow()
+    }
+
+    /// Update the current leader. The subscribers are notified only if the leader changes.
+    pub fn set_leader(&self, leader: Option<PeerId>) {
+        if *self.rx.borrow() != leader {
+            self.tx.send(leader).ok();
+        }
+    }
+
+    /// Clear the current leader if it is still `leader`.
+    pub fn invalidate(&self, leader: PeerId) {
+        if *self.rx.borrow() == Some(leader) {
+            self.tx.send(None).ok();
+        }
+    }
+
+    pub fn subscribe(&self) -> watch::Receiver<Option<PeerId>> {
+        self.rx.clone()
+    }
+
+    /// Spawn a task updating the tracker from the metrics of `raft`.
+    pub fn spawn_raft_listener<D, R, N, S>(
+        self: &std::sync::Arc<Self>,
+        raft: &Raft<D, R, N, S>,
+    ) -> JoinHandle<()>
+    where
+        D: AppData,
+        R: AppDataResponse,
+        N: RaftNetwork<D>,
+        S: RaftStorage<D, R>,
+    {
+        let tracker = self.clone();
+        let mut metrics = raft.metrics();
+        tokio::spawn(async move {
+            loop {
+                let leader = metrics.borrow().current_leader.map(PeerId::from);
+                tracker.set_leader(leader);
+                if metrics.changed().await.is_err() {
+                    break;
+                }
+            }
+        })
+    }
+}
+
+impl Default for LeaderTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_leader_tracker() {
+        let tracker = LeaderTracker::new();
+        assert_eq!(None, tracker.current_leader());
+
+        let mut rx = tracker.subscribe();
+        tracker.set_leader(Some(PeerId(1)));
+        rx.changed().await.unwrap();
+        assert_eq!(Some(PeerId(1)), *rx.borrow());
+
+        // Setting the same leader does not notify the subscribers.
+        tracker.set_leader(Some(PeerId(1)));
+        assert!(
+            tokio::time::timeout(Duration::from_millis(10), rx.changed())
+                .await
+                .is_err()
+        );
+
+        // A stale leader does not clear the new one.
+        tracker.set_leader(Some(PeerId(2)));
+        tracker.invalidate(PeerId(1));
+        assert_eq!(Some(PeerId(2)), tracker.current_leader());
+        tracker.invalidate(PeerId(2));
+        assert_eq!(None, tracker.current_leader());
+    }
+}
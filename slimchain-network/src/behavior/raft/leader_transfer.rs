@@ -0,0 +1,168 @@
+//! Move the raft leadership to another member, instead of waiting out an election timeout.
+
+use crate::{
+    behavior::raft::{client::ClientNodeRaft, utils::node_is_leader},
+    http::config::PeerId,
+};
+use serde::{Deserialize, Serialize};
+use slimchain_common::{
+    error::{anyhow, bail, ensure, Error, Result},
+    tx::TxTrait,
+};
+use slimchain_utils::record_event;
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use tokio::sync::{Mutex as AsyncMutex, MutexGuard};
+
+/// How often the transfer checks whether the target has caught up with the log.
+const CATCH_UP_CHECK_INTERVAL: Duration = Duration::from_millis(10);
+
+/// The state of the leadership transfer shared by the raft network, the block proposal worker
+/// and the admin route of a client node.
+#[derive(Debug, Default)]
+pub struct LeaderTransfer {
+    /// The peer whose append entries are held back.
+    target: Mutex<Option<PeerId>>,
+    /// The last log index known to be replicated to each peer.
+    matched: Mutex<HashMap<PeerId, u64>>,
+    /// Held while proposing a block, so that the transfer waits for the block in flight and
+    /// holds back the next ones.
+    proposals: AsyncMutex<()>,
+}
+
+impl LeaderTransfer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether the append entries to `peer_id` are held back.
+    pub fn is_held_back(&self, peer_id: PeerId) -> bool {
+        *self.target.lock().unwrap_or_else(|e| e.into_inner()) == Some(peer_id)
+    }
+
+    /// Record that `peer_id` has the log up to `index`.
+    pub fn record_matched(&self, peer_id: PeerId, index: u64) {
+        let mut matched = self.matched.lock().unwrap_or_else(|e| e.into_inner());
+        let entry = matched.entry(peer_id).or_default();
+        *entry = (*entry).max(index);
+    }
+
+    fn matched(&self, peer_id: PeerId) -> u64 {
+        let matched = self.matched.lock().unwrap_or_else(|e| e.into_inner());
+        matched.get(&peer_id).copied().unwrap_or_default()
+    }
+
+    fn set_target(&self, target: Option<PeerId>) {
+        *self.target.lock().unwrap_or_else(|e| e.into_inner()) = target;
+    }
+
+    /// Wait for the transfer in progress if any before proposing a block.
+    pub async fn lock_proposals(&self) -> MutexGuard<'_, ()> {
+        self.proposals.lock().await
+    }
+}
+
+/// Transfer the leadership of this node to the member `target`. The block proposals are held
+/// back meanwhile. Return once `target` is observed as the leader, or fail after `timeout`.
+///
+/// async-raft has no leadership transfer, so the append entries to `target` are held back once
+/// it has all the log, until it times out and wins an election. With more than three members,
+/// the others may still hear from this node and turn down the first elections.
+pub async fn transfer_leadership<Tx>(
+    raft: &ClientNodeRaft<Tx>,
+    transfer: &LeaderTransfer,
+    target: PeerId,
+    timeout: Duration,
+) -> Result<PeerId>
+where
+    Tx: TxTrait + Serialize + for<'de> Deserialize<'de> + 'static,
+{
+    ensure!(node_is_leader(raft), "not leader");
+    let (id, members) = {
+        let metrics = raft.metrics();
+        let metrics = metrics.borrow();
+        (metrics.id, metrics.membership_config.members.clone())
+    };
+    ensure!(
+        target != PeerId::from(id),
+        "Peer {} is the leader already.",
+        target
+    );
+    ensure!(
+        members.contains(&target.into()),
+        "Peer {} is not a member.",
+        target
+    );
+
+    let begin = Instant::now();
+    let deadline = tokio::time::Instant::from_std(begin + timeout);
+    let _proposals = tokio::time::timeout_at(deadline, transfer.lock_proposals())
+        .await
+        .map_err(|_| timeout_error(target))?;
+    info!("Transfer the leadership to peer {}.", target);
+    record_event!("raft_leader_transfer_begin", "target": target.0);
+
+    // The target only wins the election with the log as up-to-date as the leader's.
+    let last_log_index = raft.metrics().borrow().last_log_index;
+    while transfer.matched(target) < last_log_index {
+        if tokio::time::Instant::now() >= deadline {
+            return Err(timeout_error(target));
+        }
+        tokio::time::sleep(CATCH_UP_CHECK_INTERVAL).await;
+    }
+
+    transfer.set_target(Some(target));
+    let mut metrics = raft.metrics();
+    let res = tokio::time::timeout_at(deadline, async {
+        while metrics.borrow().current_leader != Some(target.into()) {
+            if metrics.changed().await.is_err() {
+                break;
+            }
+        }
+    })
+    .await;
+    transfer.set_target(None);
+
+    let leader = raft.metrics().borrow().current_leader.map(PeerId::from);
+    match res {
+        Ok(()) if leader == Some(target) => {
+            info!("Peer {} takes over the leadership.", target);
+            record_event!("raft_leader_transfer_end", "target": target.0, "time_ms": begin.elapsed().as_millis() as u64);
+            Ok(target)
+        }
+        _ => bail!(
+            "Failed to transfer the leadership to peer {}. Current leader: {:?}",
+            target,
+            leader
+        ),
+    }
+}
+
+fn timeout_error(target: PeerId) -> Error {
+    anyhow!("Timed out transferring the leadership to peer {}.", target)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_leader_transfer_state() {
+        let transfer = LeaderTransfer::new();
+        assert!(!transfer.is_held_back(PeerId(2)));
+        transfer.set_target(Some(PeerId(2)));
+        assert!(transfer.is_held_back(PeerId(2)));
+        assert!(!transfer.is_held_back(PeerId(3)));
+        transfer.set_target(None);
+        assert!(!transfer.is_held_back(PeerId(2)));
+
+        // The stale acks do not move the matched index back.
+        transfer.record_matched(PeerId(2), 5);
+        transfer.record_matched(PeerId(2), 3);
+        assert_eq!(5, transfer.matched(PeerId(2)));
+        assert_eq!(0, transfer.matched(PeerId(3)));
+    }
+}
@@ -0,0 +1,173 @@
+//! Change the members of the raft cluster of the client nodes while it keeps committing.
+
+use crate::{
+    behavior::raft::{client::ClientNodeRaft, utils::node_is_leader},
+    http::{
+        config::{NetworkRouteTable, PeerId},
+        node_rpc::RaftMembership,
+        route_table::SharedRouteTable,
+    },
+};
+use async_raft::error::ChangeConfigError;
+use serde::{Deserialize, Serialize};
+use slimchain_chain::role::Role;
+use slimchain_common::{
+    error::{ensure, Result},
+    tx::TxTrait,
+};
+use slimchain_utils::record_event;
+use std::{
+    collections::{BTreeSet, HashSet},
+    sync::Arc,
+};
+use tokio::task::JoinHandle;
+
+pub fn raft_membership<Tx>(raft: &ClientNodeRaft<Tx>) -> RaftMembership
+where
+    Tx: TxTrait + Serialize + for<'de> Deserialize<'de> + 'static,
+{
+    let to_peer_ids = |ids: &HashSet<u64>| -> BTreeSet<PeerId> {
+        ids.iter().copied().map(PeerId::from).collect()
+    };
+    let cfg = raft.metrics().borrow().membership_config.clone();
+    RaftMembership {
+        members: to_peer_ids(&cfg.members),
+        members_after_consensus: cfg.members_after_consensus.as_ref().map(to_peer_ids),
+    }
+}
+
+/// Add the client node `peer_id` in the route table to the cluster led by this node. It joins as
+/// a non-voter first, so that it catches up with the log before it counts in the quorum.
+pub async fn add_member<Tx>(
+    raft: &ClientNodeRaft<Tx>,
+    route_table: &NetworkRouteTable,
+    peer_id: PeerId,
+) -> Result<RaftMembership>
+where
+    Tx: TxTrait + Serialize + for<'de> Deserialize<'de> + 'static,
+{
+    ensure!(node_is_leader(raft), "not leader");
+    ensure!(
+        route_table.peers_for_role(&Role::Client).contains(&peer_id),
+        "Peer {} is not a client node in the route table.",
+        peer_id
+    );
+    ensure!(
+        !route_table.is_learner(peer_id),
+        "Peer {} is a learner.",
+        peer_id
+    );
+    let mut members = raft_membership(raft).members;
+    ensure!(
+        !members.contains(&peer_id),
+        "Peer {} is already a member.",
+        peer_id
+    );
+
+    info!("Add peer {} as a non-voter.", peer_id);
+    record_event!("raft_add_non_voter", "peer_id": peer_id.0);
+    // It returns once the node has caught up with the log.
+    raft.add_non_voter(peer_id.into()).await?;
+    members.insert(peer_id);
+    change_membership(raft, members).await
+}
+
+/// Add the learner `peer_id` in the route table to the cluster led by this node as a non-voter.
+/// It returns once the node has caught up with the log.
+pub async fn add_learner<Tx>(
+    raft: &ClientNodeRaft<Tx>,
+    route_table: &NetworkRouteTable,
+    peer_id: PeerId,
+) -> Result<()>
+where
+    Tx: TxTrait + Serialize + for<'de> Deserialize<'de> + 'static,
+{
+    ensure!(node_is_leader(raft), "not leader");
+    ensure!(
+        route_table.is_learner(peer_id),
+        "Peer {} is not a learner in the route table.",
+        peer_id
+    );
+    ensure!(
+        !raft_membership(raft).members.contains(&peer_id),
+        "Peer {} is a voting member.",
+        peer_id
+    );
+
+    info!("Add learner {} as a non-voter.", peer_id);
+    record_event!("raft_add_learner", "peer_id": peer_id.0);
+    match raft.add_non_voter(peer_id.into()).await {
+        Ok(()) | Err(ChangeConfigError::Noop) => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Spawn a task adding the learners in the route table whenever this node becomes the leader,
+/// as async-raft only keeps the non-voters on the leader. The learners down are left to catch up
+/// once they come back.
+pub fn spawn_learner_sync<Tx>(
+    raft: Arc<ClientNodeRaft<Tx>>,
+    route_table: SharedRouteTable,
+) -> JoinHandle<()>
+where
+    Tx: TxTrait + Serialize + for<'de> Deserialize<'de> + 'static,
+{
+    let mut metrics = raft.metrics();
+    tokio::spawn(async move {
+        let mut was_leader = false;
+        loop {
+            let is_leader = metrics.borrow().state.is_leader();
+            if is_leader && !was_leader {
+                let route_table = route_table.load();
+                for &peer_id in route_table.learners() {
+                    let raft = raft.clone();
+                    let route_table = route_table.clone();
+                    tokio::spawn(async move {
+                        if let Err(e) = add_learner(raft.as_ref(), &route_table, peer_id).await {
+                            warn!("Failed to add learner {}. Error: {}", peer_id, e);
+                        }
+                    });
+                }
+            }
+            was_leader = is_leader;
+            if metrics.changed().await.is_err() {
+                break;
+            }
+        }
+    })
+}
+
+/// Remove the member `peer_id` from the cluster led by this node. The leader can remove itself,
+/// after which it steps down.
+pub async fn remove_member<Tx>(raft: &ClientNodeRaft<Tx>, peer_id: PeerId) -> Result<RaftMembership>
+where
+    Tx: TxTrait + Serialize + for<'de> Deserialize<'de> + 'static,
+{
+    ensure!(node_is_leader(raft), "not leader");
+    let mut members = raft_membership(raft).members;
+    ensure!(
+        members.remove(&peer_id),
+        "Peer {} is not a member.",
+        peer_id
+    );
+    ensure!(!members.is_empty(), "Cannot remove the last member.");
+    change_membership(raft, members).await
+}
+
+async fn change_membership<Tx>(
+    raft: &ClientNodeRaft<Tx>,
+    members: BTreeSet<PeerId>,
+) -> Result<RaftMembership>
+where
+    Tx: TxTrait + Serialize + for<'de> Deserialize<'de> + 'static,
+{
+    info!("Change the members to {:?}.", members);
+    let ids: Vec<u64> = members.iter().map(|&peer_id| peer_id.into()).collect();
+    raft.change_membership(ids.iter().copied().collect())
+        .await?;
+    record_event!("raft_change_membership", "members": ids);
+    Ok(RaftMembership {
+        members,
+        members_after_consensus: None,
+    })
+}
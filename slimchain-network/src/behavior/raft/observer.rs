@@ -0,0 +1,153 @@
+use crate::{
+    behavior::observer::{load_observer_latest_block, ObserverImportWorker},
+    http::{
+        body_limit::recover_body_limit,
+        client_rpc::{block_query_server, client_rpc_server, load_block_from_db, TxHttpRequest},
+        common::*,
+        config::NetworkConfig,
+        cors::with_cors,
+        health::health_server,
+        metrics::metrics_server,
+        node_info::{node_info_server, NodeInfo},
+        node_rpc::*,
+        server::HttpServer,
+    },
+};
+use futures::{channel::mpsc, future, prelude::*, stream};
+use serde::{Deserialize, Serialize};
+use slimchain_chain::{
+    block_proposal::BlockProposal,
+    config::ObserverConfig,
+    consensus::raft::{verify_consensus, Block},
+    db::DBPtr,
+    latest::{LatestBlockHeader, LatestTxCount},
+    role::Role,
+};
+use slimchain_common::{
+    error::{bail, Error, Result},
+    tx::TxTrait,
+};
+use slimchain_utils::record_event;
+use std::net::SocketAddr;
+use warp::Filter;
+
+#[derive(Debug)]
+struct ObserverNodeReqError(mpsc::SendError);
+
+impl warp::reject::Reject for ObserverNodeReqError {}
+
+/// A node following the chain by the block proposals broadcast from the raft leader.
+/// It neither executes txs nor serves as a storage node.
+pub struct ObserverNode<Tx: TxTrait + 'static> {
+    srv: Option<HttpServer>,
+    import_worker: ObserverImportWorker<Block, Tx>,
+}
+
+impl<Tx: TxTrait + Serialize + for<'de> Deserialize<'de> + 'static> ObserverNode<Tx> {
+    pub async fn new(
+        db: DBPtr,
+        observer_cfg: &ObserverConfig,
+        net_cfg: &NetworkConfig,
+    ) -> Result<Self> {
+        let last_block: Block = load_observer_latest_block(&db)?;
+        let latest_block_header = LatestBlockHeader::new_from_block(&last_block);
+        let latest_tx_count = LatestTxCount::new(0);
+
+        let block_query_db = db.clone();
+        let import_worker = ObserverImportWorker::new(
+            *observer_cfg,
+            last_block,
+            latest_block_header.clone(),
+            latest_tx_count.clone(),
+            db,
+            verify_consensus,
+        );
+        let import_worker_blk_tx = import_worker.get_blk_tx();
+
+        let block_import_srv = warp::post()
+            .and(warp::path(OBSERVER_BLOCK_IMPORT_ROUTE_PATH))
+            .and(warp_body_encoded_with_limit(
+                net_cfg.body_limit.block_import(),
+            ))
+            .and_then(move |encoding, block_proposals: Vec<BlockProposal<Block, Tx>>| {
+                record_event!("observer_recv_block", "heights": block_proposals.iter().map(|b| b.get_block_height()).collect::<Vec<_>>());
+                let mut import_worker_blk_tx = import_worker_blk_tx.clone();
+                async move {
+                    import_worker_blk_tx
+                        .send_all(&mut stream::iter(block_proposals).map(Ok))
+                        .await
+                        .map(|_| warp_reply_encoded(encoding, &()))
+                        .map_err(|e| warp::reject::custom(ObserverNodeReqError(e)))
+                }
+            });
+
+        let health_srv = {
+            let latest_block_header = latest_block_header.clone();
+            health_server(
+                net_cfg.peer_id,
+                Role::Observer,
+                move || latest_block_header.get_height(),
+                || true,
+            )
+        };
+
+        let block_query_srv = {
+            let latest_block_header = latest_block_header.clone();
+            block_query_server(
+                false,
+                move |id, include_txs| {
+                    load_block_from_db::<Block, Tx>(block_query_db.clone(), id, include_txs)
+                },
+                move || latest_block_header.get_height(),
+            )
+        };
+
+        let client_rpc_srv = client_rpc_server(
+            |_reqs: Vec<TxHttpRequest>| {
+                future::err::<(), _>(Error::msg("Observer node does not accept tx requests."))
+            },
+            move || latest_tx_count.get(),
+            move || latest_block_header.get_height(),
+        );
+
+        let client_facing_srv =
+            with_cors(&net_cfg.cors, client_rpc_srv.or(block_query_srv).boxed())?;
+
+        info!("Create http server, listen on {}", net_cfg.http_listen);
+        let listen_addr: SocketAddr = net_cfg.http_listen.parse()?;
+        let srv = HttpServer::bind(
+            client_facing_srv
+                .or(health_srv)
+                .or(metrics_server())
+                .or(node_info_server(NodeInfo::local::<Block>(
+                    Role::Observer,
+                    &net_cfg.node_info,
+                )))
+                .or(warp::path(NODE_RPC_ROUTE_PATH)
+                    .and(warp_node_rpc_auth())
+                    .and(block_import_srv)
+                    .recover(recover_unauthorized)
+                    .recover(recover_body_limit)),
+            listen_addr,
+            &net_cfg.shutdown,
+        )?;
+
+        Ok(Self {
+            srv: Some(srv),
+            import_worker,
+        })
+    }
+
+    pub async fn shutdown(&mut self) -> Result<()> {
+        // Drain the in-flight block imports into the worker first.
+        info!("Shutting down HTTP Server...");
+        if let Some(srv) = self.srv.take() {
+            srv.shutdown().await?;
+        } else {
+            bail!("Already shutdown.");
+        }
+        info!("Shutting down ObserverImportWorker...");
+        self.import_worker.shutdown().await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,267 @@
+use crate::http::config::PeerId;
+use async_raft::raft::InstallSnapshotRequest;
+use serde::{Deserialize, Serialize};
+use slimchain_common::{
+    collections::HashMap,
+    error::{anyhow, ensure, Result},
+};
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+    sync::{Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+/// Drop the upload sessions without any progress for this time span.
+const SESSION_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Max number of concurrent upload sessions on the receiver.
+const MAX_SESSIONS: usize = 16;
+
+/// Start or resume uploading the data of an `InstallSnapshotRequest`.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct SnapshotBeginRequest {
+    pub session_id: u64,
+    pub term: u64,
+    pub leader_id: u64,
+    pub last_included_index: u64,
+    pub last_included_term: u64,
+    pub offset: u64,
+    pub done: bool,
+    /// The total length of the data.
+    pub len: u64,
+}
+
+impl SnapshotBeginRequest {
+    /// Create the request for `rpc` sent by `sender`. The session id is derived from them, so
+    /// that retrying the same `rpc` resumes the previous upload.
+    pub fn new(sender: PeerId, rpc: &InstallSnapshotRequest) -> Self {
+        let mut hasher = DefaultHasher::new();
+        (
+            sender.0,
+            rpc.term,
+            rpc.leader_id,
+            rpc.last_included_index,
+            rpc.last_included_term,
+            rpc.offset,
+            rpc.done,
+            rpc.data.len(),
+        )
+            .hash(&mut hasher);
+        Self {
+            session_id: hasher.finish(),
+            term: rpc.term,
+            leader_id: rpc.leader_id,
+            last_included_index: rpc.last_included_index,
+            last_included_term: rpc.last_included_term,
+            offset: rpc.offset,
+            done: rpc.done,
+            len: rpc.data.len() as u64,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct SnapshotChunkRequest {
+    pub session_id: u64,
+    /// The offset of `data` in the whole data of the session.
+    pub offset: u64,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct SnapshotEndRequest {
+    pub session_id: u64,
+}
+
+#[derive(Debug)]
+struct UploadSession {
+    begin: SnapshotBeginRequest,
+    data: Vec<u8>,
+    last_active: Instant,
+}
+
+/// Reassemble the chunked `InstallSnapshotRequest`s on the target node.
+#[derive(Debug, Default)]
+pub struct SnapshotReceiver {
+    sessions: Mutex<HashMap<u64, UploadSession>>,
+}
+
+impl SnapshotReceiver {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn sessions(&self) -> MutexGuard<'_, HashMap<u64, UploadSession>> {
+        self.sessions.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Start or resume a session. Return the length of the data received so far.
+    pub fn begin(&self, req: SnapshotBeginRequest) -> Result<u64> {
+        let now = Instant::now();
+        let mut sessions = self.sessions();
+        sessions
+            .retain(|_, session| now.duration_since(session.last_active) < SESSION_IDLE_TIMEOUT);
+
+        if let Some(session) = sessions.get_mut(&req.session_id) {
+            if session.begin == req {
+                session.last_active = now;
+                return Ok(session.data.len() as u64);
+            }
+        }
+
+        ensure!(
+            sessions.len() < MAX_SESSIONS || sessions.contains_key(&req.session_id),
+            "Too many snapshot upload sessions."
+        );
+        sessions.insert(
+            req.session_id,
+            UploadSession {
+                begin: req,
+                data: Vec::new(),
+                last_active: now,
+            },
+        );
+        Ok(0)
+    }
+
+    /// Append a chunk to its session. Chunks not at the end of the received data are ignored.
+    /// Return the length of the data received so far.
+    pub fn chunk(&self, req: SnapshotChunkRequest) -> Result<u64> {
+        let mut sessions = self.sessions();
+        let session = sessions
+            .get_mut(&req.session_id)
+            .ok_or_else(|| anyhow!("Unknown snapshot upload session {}.", req.session_id))?;
+        let received = session.data.len() as u64;
+        if req.offset == received {
+            ensure!(
+                received + req.data.len() as u64 <= session.begin.len,
+                "Snapshot chunk exceeds the data length."
+            );
+            session.data.extend_from_slice(&req.data);
+        }
+        session.last_active = Instant::now();
+        Ok(session.data.len() as u64)
+    }
+
+    /// Close a session and return the reassembled `InstallSnapshotRequest`.
+    pub fn end(&self, req: SnapshotEndRequest) -> Result<InstallSnapshotRequest> {
+        let mut sessions = self.sessions();
+        let session = sessions
+            .get(&req.session_id)
+            .ok_or_else(|| anyhow!("Unknown snapshot upload session {}.", req.session_id))?;
+        ensure!(
+            session.data.len() as u64 == session.begin.len,
+            "Snapshot upload session {} is incomplete. Received: {}. Expect: {}.",
+            req.session_id,
+            session.data.len(),
+            session.begin.len
+        );
+        let UploadSession { begin, data, .. } = sessions
+            .remove(&req.session_id)
+            .expect("session checked above");
+        Ok(InstallSnapshotRequest {
+            term: begin.term,
+            leader_id: begin.leader_id,
+            last_included_index: begin.last_included_index,
+            last_included_term: begin.last_included_term,
+            offset: begin.offset,
+            data,
+            done: begin.done,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot_req(data: Vec<u8>) -> InstallSnapshotRequest {
+        InstallSnapshotRequest {
+            term: 2,
+            leader_id: 1,
+            last_included_index: 100,
+            last_included_term: 2,
+            offset: 0,
+            data,
+            done: true,
+        }
+    }
+
+    fn chunk(
+        begin: &SnapshotBeginRequest,
+        data: &[u8],
+        offset: u64,
+        size: usize,
+    ) -> SnapshotChunkRequest {
+        let offset_usize = offset as usize;
+        let end = (offset_usize + size).min(data.len());
+        SnapshotChunkRequest {
+            session_id: begin.session_id,
+            offset,
+            data: data[offset_usize..end].to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_snapshot_transfer_resume() {
+        let data: Vec<u8> = (0..1000u32).map(|i| i as u8).collect();
+        let rpc = snapshot_req(data.clone());
+        let begin = SnapshotBeginRequest::new(PeerId(1), &rpc);
+        assert_eq!(begin, SnapshotBeginRequest::new(PeerId(1), &rpc));
+        assert_ne!(
+            begin.session_id,
+            SnapshotBeginRequest::new(PeerId(2), &rpc).session_id
+        );
+
+        let receiver = SnapshotReceiver::new();
+        assert_eq!(0, receiver.begin(begin.clone()).unwrap());
+        assert_eq!(300, receiver.chunk(chunk(&begin, &data, 0, 300)).unwrap());
+        // Duplicated or out of order chunks are ignored.
+        assert_eq!(300, receiver.chunk(chunk(&begin, &data, 0, 300)).unwrap());
+        assert_eq!(300, receiver.chunk(chunk(&begin, &data, 600, 300)).unwrap());
+        assert!(receiver
+            .end(SnapshotEndRequest {
+                session_id: begin.session_id
+            })
+            .is_err());
+
+        // The sender restarts and resumes from the reported offset.
+        let offset = receiver.begin(begin.clone()).unwrap();
+        assert_eq!(300, offset);
+        assert_eq!(
+            900,
+            receiver.chunk(chunk(&begin, &data, offset, 600)).unwrap()
+        );
+        assert_eq!(
+            1000,
+            receiver.chunk(chunk(&begin, &data, 900, 600)).unwrap()
+        );
+
+        let req = receiver
+            .end(SnapshotEndRequest {
+                session_id: begin.session_id,
+            })
+            .unwrap();
+        assert_eq!(data, req.data);
+        assert_eq!(rpc.last_included_index, req.last_included_index);
+        assert!(req.done);
+
+        // The session is closed.
+        assert!(receiver.chunk(chunk(&begin, &data, 0, 300)).is_err());
+    }
+
+    #[test]
+    fn test_snapshot_chunk_overflow() {
+        let rpc = snapshot_req(vec![0; 10]);
+        let begin = SnapshotBeginRequest::new(PeerId(1), &rpc);
+        let receiver = SnapshotReceiver::new();
+        receiver.begin(begin.clone()).unwrap();
+        let res = receiver.chunk(SnapshotChunkRequest {
+            session_id: begin.session_id,
+            offset: 0,
+            data: vec![0; 11],
+        });
+        assert!(res.is_err());
+    }
+}
@@ -1,45 +1,83 @@
-use super::client_network::fetch_leader_id;
+use super::{
+    client_network::fetch_leader_id,
+    storage_sync::{checkpoint_sync, fetch_missing_blocks, fetch_state_nodes},
+};
 use crate::http::{
+    admin::{AdminRequest, AdminRequestVerifier, SignedAdminRequest},
+    body_limit::recover_body_limit,
+    client_rpc::{
+        block_query_server, load_block_from_db, load_tx_receipt_from_db, tx_receipt_server,
+    },
     common::*,
-    config::{NetworkConfig, NetworkRouteTable, PeerId},
+    config::{
+        BackupConfig, NetworkConfig, NetworkRouteTable, NonceCheckConfig, NonceGapPolicy, PeerId,
+        EXECUTION_STAGE,
+    },
+    cors::with_cors,
+    health::health_server,
+    metrics::metrics_server,
+    node_info::{node_info_server, NodeInfo},
     node_rpc::*,
+    peer_health::PeerHealth,
+    rate_limit::{recover_rate_limited, RateLimited},
+    server::HttpServer,
+    tx_verify::TxReqVerifier,
+    ws_subscribe::{load_commit_events_from_db, ws_subscribe_server},
 };
 use futures::{
     channel::{mpsc, oneshot},
+    future,
     prelude::*,
     stream,
 };
+use once_cell::sync::OnceCell;
 use serde::{Deserialize, Serialize};
 use slimchain_chain::{
-    behavior::{commit_block_storage_node, verify_block, TxExecuteStream},
+    behavior::{
+        commit_block_storage_node, commit_blocks_storage_node, verify_block, verify_tx_gas,
+        TxExecuteStream,
+    },
     block_proposal::BlockProposal,
+    checkpoint::{Checkpoint, CheckpointData},
+    commit_event::COMMIT_EVENTS,
     config::ChainConfig,
     consensus::raft::{verify_consensus, Block},
-    db::DBPtr,
+    cross_shard::{is_cross_shard, CrossShardAbort, CrossShardTxProposal},
+    db::{integrity::IntegrityOptions, DBPtr},
     latest::{LatestBlockHeaderPtr, LatestTxCount, LatestTxCountPtr},
+    nonce_check::{NonceChecker, NonceRejection, ParkedTxReqs},
+    remote_state::{FetchStateNodesFn, RemoteStateFetcher},
+    role::Role,
     snapshot::Snapshot,
 };
 use slimchain_common::{
-    basic::ShardId,
-    error::{bail, Result},
+    basic::{BlockHeight, ShardId, H256},
+    collections::HashMap,
+    error::{anyhow, bail, Error, Result},
     tx::TxTrait,
     tx_req::SignedTxRequest,
 };
 use slimchain_tx_engine::TxEngine;
 use slimchain_tx_state::{StorageTxTrie, TxProposal};
-use slimchain_utils::{ordered_stream::OrderedStream, record_event};
+use slimchain_utils::{backpressure::PipelineStage, ordered_stream::OrderedStream, record_event};
 use std::{
     marker::PhantomData,
     net::SocketAddr,
     sync::{
         atomic::{AtomicBool, Ordering},
-        Arc,
+        Arc, Mutex,
     },
+    time::Duration,
 };
 use tokio::{sync::RwLock, task::JoinHandle};
-use warp::Filter;
+use warp::{http::StatusCode, Filter, Reply};
 
 const MAX_RETRIES: usize = 3;
+const MAX_BLOCK_PROPOSALS_PER_REQ: u64 = 16;
+/// How often the import worker checks for a gap before the buffered blocks.
+const CATCH_UP_CHECK_INTERVAL: Duration = Duration::from_millis(500);
+/// Max number of the blocks in order committed by the import worker in one write.
+const MAX_BLOCKS_PER_COMMIT: usize = 64;
 
 struct SendToLeader<Tx: TxTrait + Serialize> {
     route_table: NetworkRouteTable,
@@ -56,8 +94,7 @@ impl<Tx: TxTrait + Serialize> SendToLeader<Tx> {
         }
     }
 
-    #[allow(clippy::ptr_arg)]
-    async fn send_tx_proposals(&self, tx_proposals: &Vec<TxProposal<Tx>>) -> Result<()> {
+    async fn leader_addr(&self) -> Result<&String> {
         let leader_id = *self.leader_id.read().await;
         let leader_id = match leader_id {
             Some(id) => id,
@@ -67,8 +104,12 @@ impl<Tx: TxTrait + Serialize> SendToLeader<Tx> {
                 id
             }
         };
+        self.route_table.peer_address(leader_id)
+    }
 
-        let leader_addr = self.route_table.peer_address(leader_id)?;
+    #[allow(clippy::ptr_arg)]
+    async fn send_tx_proposals(&self, tx_proposals: &Vec<TxProposal<Tx>>) -> Result<()> {
+        let leader_addr = self.leader_addr().await?;
         match send_reqs_to_leader(leader_addr, tx_proposals).await {
             Err(e) => {
                 *self.leader_id.write().await = None;
@@ -77,6 +118,143 @@ impl<Tx: TxTrait + Serialize> SendToLeader<Tx> {
             Ok(()) => Ok(()),
         }
     }
+
+    #[allow(clippy::ptr_arg)]
+    async fn send_cross_shard_tx_proposals(
+        &self,
+        parts: &Vec<CrossShardTxProposal<Tx>>,
+    ) -> Result<()> {
+        let leader_addr = self.leader_addr().await?;
+        match send_cross_shard_reqs_to_leader(leader_addr, parts).await {
+            Err(e) => {
+                *self.leader_id.write().await = None;
+                Err(e)
+            }
+            Ok(()) => Ok(()),
+        }
+    }
+
+    /// Send the parts of `shard_id` to one of its storage nodes to be prepared.
+    #[allow(clippy::ptr_arg)]
+    async fn send_cross_shard_prepare(
+        &self,
+        shard_id: ShardId,
+        parts: &Vec<CrossShardTxProposal<Tx>>,
+    ) -> Result<()> {
+        let peer_id = self
+            .route_table
+            .random_peer(&Role::Storage(shard_id))
+            .ok_or_else(|| {
+                anyhow!(
+                    "No storage node of shard {}/{} is known.",
+                    shard_id.id,
+                    shard_id.total
+                )
+            })?;
+        let peer_addr = self.route_table.peer_address(peer_id)?;
+        send_cross_shard_prepare(peer_addr, parts).await
+    }
+
+    /// Split the cross-shard tx proposals executed by a storage node of `home_shard`, send the
+    /// parts of `home_shard` to the raft leader, and the others to their shards to be prepared.
+    /// The txs whose parts fail to be sent are aborted by the leader after the timeout.
+    async fn send_cross_shard(&self, home_shard: ShardId, tx_proposals: Vec<TxProposal<Tx>>) {
+        let mut parts_by_shard: HashMap<ShardId, Vec<CrossShardTxProposal<Tx>>> = HashMap::new();
+        for tx_proposal in tx_proposals {
+            record_event!("storage_cross_shard_tx", "tx_id": tx_proposal.tx.id());
+            for part in CrossShardTxProposal::split(tx_proposal, home_shard) {
+                parts_by_shard.entry(part.shard_id).or_default().push(part);
+            }
+        }
+
+        for (shard_id, parts) in parts_by_shard {
+            let res = if shard_id == home_shard {
+                self.send_cross_shard_tx_proposals(&parts).await
+            } else {
+                self.send_cross_shard_prepare(shard_id, &parts).await
+            };
+            if let Err(e) = res {
+                error!(
+                    "Failed to send the cross-shard tx parts of shard {}/{}. Error: {}",
+                    shard_id.id, shard_id.total, e
+                );
+                for part in &parts {
+                    record_event!("discard_tx", "tx_id": part.tx_id, "reason": "storage_send_cross_shard", "detail": std::format!("{}", e));
+                }
+            }
+        }
+    }
+}
+
+/// Check the nonces of the tx requests at the intake, see
+/// [`nonce_check`](slimchain_chain::nonce_check).
+struct NonceIntake {
+    checker: NonceChecker,
+    gap_policy: NonceGapPolicy,
+    parked: Mutex<ParkedTxReqs>,
+    /// Unset until the latest block header is loaded, during which the requests all pass.
+    latest_block_header: Arc<OnceCell<LatestBlockHeaderPtr>>,
+}
+
+impl NonceIntake {
+    fn new(
+        db: &DBPtr,
+        cfg: &NonceCheckConfig,
+        latest_block_header: Arc<OnceCell<LatestBlockHeaderPtr>>,
+    ) -> Option<Arc<Self>> {
+        if !cfg.enabled {
+            return None;
+        }
+        Some(Arc::new(Self {
+            checker: NonceChecker::new(db.clone(), cfg.max_gap),
+            gap_policy: cfg.gap_policy,
+            parked: Mutex::new(ParkedTxReqs::new(cfg.max_parked, cfg.max_parked_blocks)),
+            latest_block_header,
+        }))
+    }
+
+    /// Return `req` if it passes, or `None` if it is parked.
+    fn admit(&self, req: SignedTxRequest) -> Result<Option<SignedTxRequest>, NonceRejection> {
+        let (height, state_root) = match self.latest_block_header.get() {
+            Some(header) => header.get_height_and_state_root(),
+            None => return Ok(Some(req)),
+        };
+        match self.checker.check(state_root, &req) {
+            Ok(()) => Ok(Some(req)),
+            Err(rejection @ NonceRejection::Gap { .. })
+                if self.gap_policy == NonceGapPolicy::Park =>
+            {
+                let mut parked = self.parked.lock().unwrap_or_else(|e| e.into_inner());
+                if parked.park(height, req) {
+                    Ok(None)
+                } else {
+                    Err(rejection)
+                }
+            }
+            Err(rejection) => Err(rejection),
+        }
+    }
+
+    /// Send the parked requests within the gap of the latest state to the execution.
+    fn release(&self, tx_req_tx: &mpsc::UnboundedSender<SignedTxRequest>) {
+        let (height, state_root) = match self.latest_block_header.get() {
+            Some(header) => header.get_height_and_state_root(),
+            None => return,
+        };
+        let released = {
+            let mut parked = self.parked.lock().unwrap_or_else(|e| e.into_inner());
+            if parked.is_empty() {
+                return;
+            }
+            parked.release(&self.checker, height, state_root)
+        };
+        for (tx_id, reason) in released.dropped {
+            record_event!("discard_tx", "tx_id": tx_id, "reason": "nonce_parked", "detail": reason);
+        }
+        for req in released.ready {
+            tx_req_tx.unbounded_send(req).ok();
+        }
+    }
 }
 
 struct TxExecWorker {
@@ -87,25 +265,46 @@ struct TxExecWorker {
 }
 
 impl TxExecWorker {
+    #[allow(clippy::too_many_arguments)]
     fn new<Tx: TxTrait + Serialize>(
         route_table: NetworkRouteTable,
+        shard_id: ShardId,
         engine: TxEngine<Tx>,
         db: &DBPtr,
         latest_block_header: &LatestBlockHeaderPtr,
+        tx_req_tx: mpsc::UnboundedSender<SignedTxRequest>,
+        tx_req_rx: mpsc::UnboundedReceiver<SignedTxRequest>,
+        exec_stage: Arc<PipelineStage>,
+        remote_state: Option<Arc<RemoteStateFetcher>>,
     ) -> Self {
         let send_to_leader = Arc::new(SendToLeader::new(route_table));
         let engine_shutdown_token = engine.shutdown_token();
-        let (tx_req_tx, tx_req_rx) = mpsc::unbounded::<SignedTxRequest>();
-        let tx_exec_fut = TxExecuteStream::new(tx_req_rx, engine, &db, &latest_block_header)
+        let mut tx_exec = TxExecuteStream::new(tx_req_rx, engine, &db, &latest_block_header)
+            .with_pipeline_stage(exec_stage);
+        if let Some(fetcher) = remote_state {
+            tx_exec = tx_exec.with_remote_state(fetcher);
+        }
+        let tx_exec_fut = tx_exec
             .ready_chunks(8)
             .for_each_concurrent(8, move |tx_proposals| {
                 let send_to_leader = send_to_leader.clone();
                 async move {
+                    let (cross_shard, tx_proposals): (Vec<_>, Vec<_>) =
+                        tx_proposals.into_iter().partition(|tx_proposal| {
+                            is_cross_shard(tx_proposal.tx.tx_writes(), shard_id.total)
+                        });
+                    if !cross_shard.is_empty() {
+                        send_to_leader.send_cross_shard(shard_id, cross_shard).await;
+                    }
+                    if tx_proposals.is_empty() {
+                        return;
+                    }
+
                     for i in 1..=MAX_RETRIES {
                         match send_to_leader.send_tx_proposals(&tx_proposals).await {
                             Ok(_) => break,
                             Err(e) => {
-                                if i == MAX_RETRIES {
+                                if i == MAX_RETRIES || !is_retryable_error(&e) {
                                     error!(
                                         "Failed to send tx_proposal to raft leader. Error: {}",
                                         e
@@ -114,6 +313,7 @@ impl TxExecWorker {
                                         let tx_id = tx.tx.id();
                                         record_event!("discard_tx", "tx_id": tx_id, "reason": "storage_send_to_leader", "detail": std::format!("{}", e));
                                     }
+                                    break;
                                 }
                             }
                         }
@@ -137,10 +337,6 @@ impl TxExecWorker {
         }
     }
 
-    fn get_tx_req_tx(&self) -> mpsc::UnboundedSender<SignedTxRequest> {
-        self.tx_req_tx.clone()
-    }
-
     async fn shutdown(&mut self) -> Result<()> {
         self.tx_req_tx.close_channel();
         self.engine_shutdown_token.store(true, Ordering::Release);
@@ -164,59 +360,169 @@ struct BlockImportWorker<Tx: TxTrait + 'static> {
     shutdown_tx: Option<oneshot::Sender<()>>,
 }
 
-impl<Tx: TxTrait + Serialize> BlockImportWorker<Tx> {
+impl<Tx: TxTrait + Serialize + for<'de> Deserialize<'de>> BlockImportWorker<Tx> {
+    #[allow(clippy::too_many_arguments)]
     fn new(
+        route_table: NetworkRouteTable,
+        shard_id: ShardId,
         chain_cfg: ChainConfig,
         mut snapshot: Snapshot<Block, StorageTxTrie>,
         latest_block_header: LatestBlockHeaderPtr,
         latest_tx_count: LatestTxCountPtr,
         db: DBPtr,
+        blk_tx: mpsc::UnboundedSender<BlockProposal<Block, Tx>>,
+        blk_rx: mpsc::UnboundedReceiver<BlockProposal<Block, Tx>>,
+        nonce_intake: Option<Arc<NonceIntake>>,
+        tx_req_tx: mpsc::UnboundedSender<SignedTxRequest>,
     ) -> Self {
-        let (blk_tx, blk_rx) = mpsc::unbounded::<BlockProposal<Block, Tx>>();
         let mut blk_rx = OrderedStream::new(
             blk_rx.map(|blk| (blk.get_block_height(), blk)),
             latest_block_header.get_height().next_height(),
             |height| height.next_height(),
         );
         let (shutdown_tx, mut shutdown_rx) = oneshot::channel();
+        let catch_up_blk_tx = blk_tx.clone();
 
         let handle: JoinHandle<()> = tokio::spawn(async move {
+            let route_table = Arc::new(route_table);
+            let peer_health = PeerHealth::new();
+            let catch_up_running = Arc::new(AtomicBool::new(false));
+            let mut catch_up: Option<JoinHandle<()>> = None;
+            let mut last_gap: Option<(BlockHeight, BlockHeight)> = None;
+            let mut catch_up_ticker = tokio::time::interval(CATCH_UP_CHECK_INTERVAL);
+
             loop {
                 tokio::select! {
                     _ = &mut shutdown_rx => break,
+                    _ = catch_up_ticker.tick() => {
+                        // A gap is only filled if it stays for two checks, since the blocks
+                        // may just arrive out of order.
+                        let gap = blk_rx
+                            .first_pending_key()
+                            .map(|&pending| (*blk_rx.current(), pending));
+                        if gap.is_none() || gap != last_gap {
+                            last_gap = gap;
+                            continue;
+                        }
+                        if catch_up_running.swap(true, Ordering::SeqCst) {
+                            continue;
+                        }
+                        let (from, to) = gap.expect("The gap is checked above.");
+                        let route_table = route_table.clone();
+                        let peer_health = peer_health.clone();
+                        let running = catch_up_running.clone();
+                        let blk_tx = catch_up_blk_tx.clone();
+                        catch_up = Some(tokio::spawn(async move {
+                            info!("Fetch the missing blocks from height {} to {}.", from, to);
+                            record_event!("storage_catch_up_begin", "from": from.0, "to": to.0);
+                            match fetch_missing_blocks(&route_table, shard_id, &peer_health, from, to, blk_tx).await {
+                                Ok(next_height) => {
+                                    record_event!("storage_catch_up_end", "from": from.0, "to": next_height.0);
+                                }
+                                Err(e) => {
+                                    warn!("Failed to fetch the missing blocks. Error: {}", e);
+                                }
+                            }
+                            running.store(false, Ordering::SeqCst);
+                        }));
+                    }
                     Some(blk_proposal) = blk_rx.next() => {
-                        let state_update = {
+                        // The blocks already in order, e.g., the ones fetched by the catch-up,
+                        // are committed in one write. A batch ends at a checkpoint, which is
+                        // taken from the snapshot after it. The full validation reads the state
+                        // of the previous block from the database, so it commits one by one.
+                        let max_blocks = if chain_cfg.full_validation {
+                            1
+                        } else {
+                            MAX_BLOCKS_PER_COMMIT
+                        };
+                        let mut blk_proposals = vec![blk_proposal];
+                        while blk_proposals.len() < max_blocks {
+                            let last = blk_proposals.last().expect("The batch is not empty.");
+                            if chain_cfg.should_checkpoint(last.get_block_height()) {
+                                break;
+                            }
+                            match blk_rx.next().now_or_never() {
+                                Some(Some(blk_proposal)) => blk_proposals.push(blk_proposal),
+                                _ => break,
+                            }
+                        }
+
+                        let mut verified = Vec::with_capacity(blk_proposals.len());
+                        for blk_proposal in blk_proposals {
                             let snapshot_backup = snapshot.clone();
-                            match verify_block(&chain_cfg, &mut snapshot, &blk_proposal, verify_consensus)
+                            let res = match verify_block(&chain_cfg, &mut snapshot, &blk_proposal, verify_consensus)
                                 .await
                             {
-                                Ok(state_update) => state_update,
+                                Ok(state_update) if chain_cfg.full_validation => {
+                                    verify_tx_gas(&db, &blk_proposal).await.map(|_| state_update)
+                                }
+                                res => res,
+                            };
+                            match res {
+                                Ok(state_update) => verified.push((blk_proposal, state_update)),
                                 Err(e) => {
                                     snapshot = snapshot_backup;
                                     error!("Failed to import block. Error: {}", e);
-                                    continue;
                                 }
                             }
+                        }
+                        let blk_proposal = match verified.last() {
+                            Some((blk_proposal, _)) => blk_proposal,
+                            None => continue,
                         };
 
-                        if let Err(e) = commit_block_storage_node(
-                            &blk_proposal,
-                            &state_update,
-                            &db,
-                            &latest_block_header,
-                            &latest_tx_count,
-                        )
-                        .await
-                        {
+                        let res = match verified.as_slice() {
+                            [(blk_proposal, state_update)] => {
+                                commit_block_storage_node(
+                                    &chain_cfg,
+                                    blk_proposal,
+                                    state_update,
+                                    &db,
+                                    &latest_block_header,
+                                    &latest_tx_count,
+                                )
+                                .await
+                            }
+                            _ => {
+                                commit_blocks_storage_node(
+                                    &chain_cfg,
+                                    &verified,
+                                    &db,
+                                    &latest_block_header,
+                                    &latest_tx_count,
+                                )
+                                .await
+                            }
+                        };
+                        if let Err(e) = res {
                             if let Ok(db_tx) = snapshot.write_db_tx() {
                                 db.write_async(db_tx).await.ok();
                             }
                             panic!("Failed to commit the block. Error: {}", e);
                         }
+                        if let Some(nonce_intake) = nonce_intake.as_ref() {
+                            nonce_intake.release(&tx_req_tx);
+                        }
+
+                        let height = blk_proposal.get_block_height();
+                        if chain_cfg.should_checkpoint(height) {
+                            match snapshot.write_checkpoint_db_tx() {
+                                Ok(db_tx) => match db.write_async(db_tx).await {
+                                    Ok(_) => record_event!("storage_checkpoint", "height": height.0),
+                                    Err(e) => error!("Failed to save the checkpoint. Error: {}", e),
+                                },
+                                Err(e) => error!("Failed to create the checkpoint. Error: {}", e),
+                            }
+                        }
                     }
                 }
             }
 
+            if let Some(catch_up) = catch_up {
+                catch_up.abort();
+            }
+
             db.write_async(
                 snapshot
                     .write_db_tx()
@@ -233,10 +539,6 @@ impl<Tx: TxTrait + Serialize> BlockImportWorker<Tx> {
         }
     }
 
-    fn get_blk_tx(&self) -> mpsc::UnboundedSender<BlockProposal<Block, Tx>> {
-        self.blk_tx.clone()
-    }
-
     async fn shutdown(&mut self) -> Result<()> {
         self.blk_tx.close_channel();
         if let Some(shutdown_tx) = self.shutdown_tx.take() {
@@ -258,8 +560,184 @@ struct StorageNodeReqError(mpsc::SendError);
 
 impl warp::reject::Reject for StorageNodeReqError {}
 
+#[derive(Debug)]
+struct StorageNodeServerError(Error);
+
+impl warp::reject::Reject for StorageNodeServerError {}
+
+fn checkpoint_srv<Tx: TxTrait + Serialize + for<'de> Deserialize<'de> + 'static>(
+    db: DBPtr,
+    state_len: usize,
+) -> warp::filters::BoxedFilter<(impl warp::Reply,)> {
+    let checkpoint_db = db.clone();
+    let checkpoint_route = warp::get()
+        .and(warp::path(STORAGE_CHECKPOINT_ROUTE_PATH))
+        .and_then(move || {
+            let res = Checkpoint::load_from_db(&checkpoint_db)
+                .map(|checkpoint| warp_reply_binary(&checkpoint))
+                .map_err(|e| warp::reject::custom(StorageNodeServerError(e)));
+            future::ready(res)
+        });
+
+    let checkpoint_data_db = db.clone();
+    let checkpoint_data_route = warp::get()
+        .and(warp::path(STORAGE_CHECKPOINT_DATA_ROUTE_PATH))
+        .and_then(move || {
+            let res = CheckpointData::<Block>::load_from_db(&checkpoint_data_db, state_len)
+                .and_then(|data| data.ok_or_else(|| anyhow!("No checkpoint is available.")))
+                .map(|data| warp_reply_binary(&data))
+                .map_err(|e| warp::reject::custom(StorageNodeServerError(e)));
+            future::ready(res)
+        });
+
+    let state_nodes_db = db.clone();
+    let state_nodes_route = warp::post()
+        .and(warp::path(STORAGE_STATE_NODES_ROUTE_PATH))
+        .and(warp_body_binary())
+        .and_then(move |hashes: Vec<H256>| {
+            let db = state_nodes_db.clone();
+            async move {
+                db.load_nodes_async(hashes)
+                    .await
+                    .map(|nodes| warp_reply_binary(&nodes))
+                    .map_err(|e| warp::reject::custom(StorageNodeServerError(e)))
+            }
+        });
+
+    let block_proposals_db = db.clone();
+    let block_proposals_route = warp::post()
+        .and(warp::path(STORAGE_BLOCK_PROPOSALS_ROUTE_PATH))
+        .and(warp_body_binary())
+        .and_then(move |from_height: BlockHeight| {
+            let db = block_proposals_db.clone();
+            async move {
+                let latest_height = db
+                    .get_latest_block_header()
+                    .map_err(|e| warp::reject::custom(StorageNodeServerError(e)))?
+                    .map_or(BlockHeight::from(0), |header| header.height);
+                let end = std::cmp::min(
+                    from_height.saturating_add(MAX_BLOCK_PROPOSALS_PER_REQ).0,
+                    latest_height.saturating_add(1).0,
+                );
+                let range = from_height..BlockHeight::from(std::cmp::max(from_height.0, end));
+                db.iter_block_proposals::<Block, Tx>(range)
+                    .try_collect::<Vec<_>>()
+                    .await
+                    .map(|blk_proposals| warp_reply_binary(&blk_proposals))
+                    .map_err(|e| warp::reject::custom(StorageNodeServerError(e)))
+            }
+        });
+
+    let get_blocks_db = db.clone();
+    let get_blocks_route = warp::get()
+        .and(warp::path(STORAGE_GET_BLOCKS_ROUTE_PATH))
+        .and(warp::query::<GetBlocksQuery>())
+        .and_then(move |query: GetBlocksQuery| {
+            let db = get_blocks_db.clone();
+            async move {
+                let latest_height = db
+                    .get_latest_block_header()
+                    .map_err(|e| warp::reject::custom(StorageNodeServerError(e)))?
+                    .map_or(BlockHeight::from(0), |header| header.height);
+                let end = std::cmp::min(
+                    std::cmp::min(
+                        query.to,
+                        query.from.saturating_add(MAX_BLOCK_PROPOSALS_PER_REQ),
+                    ),
+                    latest_height.saturating_add(1).0,
+                );
+                let range = BlockHeight::from(query.from)
+                    ..BlockHeight::from(std::cmp::max(query.from, end));
+                db.iter_block_proposals::<Block, Tx>(range)
+                    .try_collect::<Vec<_>>()
+                    .await
+                    .map(|blk_proposals| warp_reply_binary(&blk_proposals))
+                    .map_err(|e| warp::reject::custom(StorageNodeServerError(e)))
+            }
+        });
+
+    checkpoint_route
+        .or(checkpoint_data_route)
+        .or(state_nodes_route)
+        .or(block_proposals_route)
+        .or(get_blocks_route)
+        .boxed()
+}
+
+/// The admin route checking the integrity of the database of the storage node by the signed
+/// [`AdminRequest::CheckIntegrity`], which reads all the blocks and, if asked, all the state
+/// nodes.
+fn check_integrity_srv<Tx: TxTrait + for<'de> Deserialize<'de> + 'static>(
+    db: DBPtr,
+    shard_id: ShardId,
+    admin_verifier: Arc<AdminRequestVerifier>,
+) -> warp::filters::BoxedFilter<(impl warp::Reply,)> {
+    warp::post()
+        .and(warp::path(STORAGE_CHECK_INTEGRITY_ROUTE_PATH))
+        .and(warp_body_binary())
+        .and_then(move |req: SignedAdminRequest| {
+            let db = db.clone();
+            let opts = match admin_verifier.accept(req) {
+                Ok(AdminRequest::CheckIntegrity(opts)) => Ok(IntegrityOptions { shard_id, ..opts }),
+                Ok(req) => Err(anyhow!("Unexpected admin request {:?}.", req)),
+                Err(e) => Err(e),
+            };
+            async move {
+                let opts = opts.map_err(|e| warp::reject::custom(StorageNodeServerError(e)))?;
+                let report = tokio::task::spawn_blocking(move || {
+                    db.check_integrity::<Block, Tx>(&opts, verify_consensus)
+                })
+                .await
+                .map_err(Error::from)
+                .and_then(|res| res)
+                .map_err(|e| warp::reject::custom(StorageNodeServerError(e)))?;
+                if !report.is_ok() {
+                    warn!(
+                        "Found {} violations in the database.",
+                        report.violations.len()
+                    );
+                }
+                record_event!("db_integrity_check", "height": report.latest_height.0, "violations": report.violations.len());
+                Ok::<_, warp::Rejection>(warp_reply_binary(&report))
+            }
+        })
+        .boxed()
+}
+
+/// The admin route backing up the database of the storage node into the backup named by the
+/// signed [`AdminRequest::Backup`] in `backup_cfg.root` on its host, see
+/// [`DB::create_backup`](slimchain_chain::db::DB::create_backup). The node keeps importing the
+/// blocks meanwhile.
+fn backup_srv(
+    db: DBPtr,
+    admin_verifier: Arc<AdminRequestVerifier>,
+    backup_cfg: BackupConfig,
+) -> warp::filters::BoxedFilter<(impl warp::Reply,)> {
+    warp::post()
+        .and(warp::path(STORAGE_BACKUP_ROUTE_PATH))
+        .and(warp_body_binary())
+        .and_then(move |req: SignedAdminRequest| {
+            let db = db.clone();
+            let dest = match admin_verifier.accept(req) {
+                Ok(AdminRequest::Backup(name)) => backup_cfg.backup_dir(&name),
+                Ok(req) => Err(anyhow!("Unexpected admin request {:?}.", req)),
+                Err(e) => Err(e),
+            };
+            async move {
+                let dest = dest.map_err(|e| warp::reject::custom(StorageNodeServerError(e)))?;
+                let manifest = tokio::task::spawn_blocking(move || db.create_backup(&dest))
+                    .await
+                    .map_err(Error::from)
+                    .and_then(|res| res)
+                    .map_err(|e| warp::reject::custom(StorageNodeServerError(e)))?;
+                Ok::<_, warp::Rejection>(warp_reply_binary(&manifest))
+            }
+        })
+        .boxed()
+}
+
 pub struct StorageNode<Tx: TxTrait + 'static> {
-    srv: Option<(oneshot::Sender<()>, JoinHandle<()>)>,
+    srv: Option<HttpServer>,
     exec_worker: TxExecWorker,
     import_worker: BlockImportWorker<Tx>,
 }
@@ -272,83 +750,324 @@ impl<Tx: TxTrait + Serialize + for<'de> Deserialize<'de> + 'static> StorageNode<
         chain_cfg: &ChainConfig,
         net_cfg: &NetworkConfig,
     ) -> Result<Self> {
-        let snapshot =
-            Snapshot::<Block, StorageTxTrie>::load_from_db(&db, chain_cfg.state_len, shard_id)?;
-        let latest_block_header = snapshot.to_latest_block_header();
-        let latest_tx_count = LatestTxCount::new(0);
-
-        let exec_worker =
-            TxExecWorker::new(net_cfg.to_route_table(), engine, &db, &latest_block_header);
-        let exec_worker_tx_req_tx = exec_worker.get_tx_req_tx();
+        let route_table = net_cfg.to_route_table();
+        let (tx_req_tx, tx_req_rx) = mpsc::unbounded::<SignedTxRequest>();
+        let (blk_tx, blk_rx) = mpsc::unbounded::<BlockProposal<Block, Tx>>();
+        let pipeline = Arc::new(net_cfg.backpressure.storage_pipeline());
+        let exec_stage = pipeline
+            .stage(EXECUTION_STAGE)
+            .cloned()
+            .expect("Missing the execution stage.");
 
-        let import_worker = BlockImportWorker::new(
-            chain_cfg.clone(),
-            snapshot,
-            latest_block_header,
-            latest_tx_count,
-            db,
-        );
-        let import_worker_blk_tx = import_worker.get_blk_tx();
+        // The node is ready once the latest block header is loaded after the checkpoint sync.
+        let ready_block_header: Arc<OnceCell<LatestBlockHeaderPtr>> = Arc::new(OnceCell::new());
 
+        let exec_worker_tx_req_tx = tx_req_tx.clone();
+        let exec_worker_stage = exec_stage.clone();
+        let tx_verifier = TxReqVerifier::spawn(&net_cfg.tx_verify);
+        let nonce_intake = NonceIntake::new(&db, &net_cfg.nonce_check, ready_block_header.clone());
+        let exec_nonce_intake = nonce_intake.clone();
         let tx_exec_srv = warp::post()
             .and(warp::path(STORAGE_TX_REQ_ROUTE_PATH))
-            .and(warp_body_binary())
-            .and_then(move |req: SignedTxRequest| {
-                record_event!("storage_recv_tx", "tx_id": req.id());
+            .and(warp_body_encoded_with_limit(net_cfg.body_limit.tx_req))
+            .and_then(move |encoding, req: SignedTxRequest| {
+                let tx_id = req.id();
+                let scheme = req.scheme();
+                record_event!("storage_recv_tx", "tx_id": tx_id);
+                let tx_verifier = tx_verifier.clone();
+                let nonce_intake = exec_nonce_intake.clone();
                 let mut exec_worker_tx_req_tx = exec_worker_tx_req_tx.clone();
+                let exec_stage = exec_worker_stage.clone();
+                let shed = pipeline.check().err();
                 async move {
+                    // Shed before the verification, so that a saturated node spends nothing on it.
+                    if let Some(saturated) = shed {
+                        record_event!("tx_req_shed", "tx_id": tx_id, "stage": saturated.stage);
+                        return Err(warp::reject::custom(RateLimited {
+                            retry_after: saturated.retry_after,
+                        }));
+                    }
+
+                    // Reject the invalid signatures of any scheme before they reach the workers.
+                    let req = match tx_verifier.verify(req).await {
+                        Ok(req) => req,
+                        Err(e) => {
+                            record_event!("discard_tx", "tx_id": tx_id, "reason": "invalid_signature", "scheme": scheme.to_string(), "detail": std::format!("{}", e));
+                            let resp = warp_reply_encoded(encoding, &e.to_string());
+                            return Ok(warp::reply::with_status(resp, StatusCode::BAD_REQUEST)
+                                .into_response());
+                        }
+                    };
+                    // Reject the replayed nonces before they take an execution slot.
+                    let admitted = match nonce_intake.as_ref() {
+                        Some(nonce_intake) => nonce_intake.admit(req),
+                        None => Ok(Some(req)),
+                    };
+                    let req = match admitted {
+                        Ok(Some(req)) => req,
+                        Ok(None) => {
+                            record_event!("storage_park_tx", "tx_id": tx_id);
+                            return Ok(warp_reply_encoded(encoding, &()));
+                        }
+                        Err(rejection) => {
+                            record_event!("discard_tx", "tx_id": tx_id, "reason": "nonce_check", "kind": rejection.as_str(), "detail": std::format!("{}", rejection));
+                            let resp = warp_reply_encoded(encoding, &rejection.to_string());
+                            return Ok(warp::reply::with_status(resp, StatusCode::BAD_REQUEST)
+                                .into_response());
+                        }
+                    };
+                    // The ones let in as the execution saturates wait here rather than pile up in
+                    // front of it.
+                    exec_stage.wait_for_room().await;
                     exec_worker_tx_req_tx
                         .send(req)
                         .await
-                        .map(|_| warp_reply_binary(&()))
+                        .map(|_| warp_reply_encoded(encoding, &()))
                         .map_err(|e| warp::reject::custom(StorageNodeReqError(e)))
                 }
             });
 
+        // The parts of the cross-shard txs executed by the other shards are prepared here for
+        // the shard of this node, before they are forwarded to the raft leader.
+        let prepare_send_to_leader = Arc::new(SendToLeader::<Tx>::new(route_table.clone()));
+        let cross_shard_prepare_srv = warp::post()
+            .and(warp::path(STORAGE_CROSS_SHARD_PREPARE_ROUTE_PATH))
+            .and(warp_body_encoded_with_limit(net_cfg.body_limit.tx_req))
+            .and_then(move |encoding, parts: Vec<CrossShardTxProposal<Tx>>| {
+                let send_to_leader = prepare_send_to_leader.clone();
+                let parts: Vec<_> = parts
+                    .into_iter()
+                    .filter(|part| {
+                        let res = if part.shard_id == shard_id {
+                            part.prepare()
+                        } else {
+                            Err(CrossShardAbort::InvalidSlice(part.shard_id))
+                        };
+                        match res {
+                            Ok(()) => true,
+                            Err(reason) => {
+                                record_event!("discard_tx", "tx_id": part.tx_id, "reason": "storage_cross_shard_prepare", "detail": reason.as_str());
+                                false
+                            }
+                        }
+                    })
+                    .collect();
+                async move {
+                    if !parts.is_empty() {
+                        send_to_leader
+                            .send_cross_shard_tx_proposals(&parts)
+                            .await
+                            .map_err(|e| warp::reject::custom(StorageNodeServerError(e)))?;
+                    }
+                    Ok::<_, warp::Rejection>(warp_reply_encoded(encoding, &()))
+                }
+            });
+
+        let import_worker_blk_tx = blk_tx.clone();
         let block_import_srv = warp::post()
             .and(warp::path(STORAGE_BLOCK_IMPORT_ROUTE_PATH))
-            .and(warp_body_binary())
-            .and_then(move |block_proposals: Vec<BlockProposal<Block, Tx>>| {
+            .and(warp_body_encoded_with_limit(
+                net_cfg.body_limit.block_import(),
+            ))
+            .and_then(move |encoding, block_proposals: Vec<BlockProposal<Block, Tx>>| {
                 record_event!("storage_recv_block", "heights": block_proposals.iter().map(|b| b.get_block_height()).collect::<Vec<_>>());
                 let mut import_worker_blk_tx = import_worker_blk_tx.clone();
+                // The block proposals may be re-broadcast by the leader if it missed the ack.
+                // The already imported ones are dropped by the ordered stream of the import
+                // worker, so the duplicates are acked as well.
                 async move {
                     import_worker_blk_tx
                         .send_all(&mut stream::iter(block_proposals).map(Ok))
                         .await
-                        .map(|_| warp_reply_binary(&()))
+                        .map(|_| warp_reply_encoded(encoding, &()))
                         .map_err(|e| warp::reject::custom(StorageNodeReqError(e)))
                 }
             });
 
+        let health_srv = {
+            let block_header_copy1 = ready_block_header.clone();
+            let block_header_copy2 = ready_block_header.clone();
+            health_server(
+                route_table.peer_id(),
+                Role::Storage(shard_id),
+                move || {
+                    block_header_copy1
+                        .get()
+                        .map_or_else(BlockHeight::default, |header| header.get_height())
+                },
+                move || block_header_copy2.get().is_some(),
+            )
+        };
+        let block_query_srv = {
+            let block_query_db = db.clone();
+            let block_header_copy = ready_block_header.clone();
+            block_query_server(
+                true,
+                move |id, include_txs| {
+                    load_block_from_db::<Block, Tx>(block_query_db.clone(), id, include_txs)
+                },
+                move || {
+                    block_header_copy
+                        .get()
+                        .map_or_else(BlockHeight::default, |header| header.get_height())
+                },
+            )
+        };
+        // The tx locations are only indexed if `chain_cfg.index_tx_location` is set.
+        let tx_receipt_srv = {
+            let tx_receipt_db = db.clone();
+            let block_header_copy = ready_block_header.clone();
+            tx_receipt_server(
+                move |tx_hash, include_tx| {
+                    load_tx_receipt_from_db::<Block, Tx>(tx_receipt_db.clone(), tx_hash, include_tx)
+                },
+                move || {
+                    block_header_copy
+                        .get()
+                        .map_or_else(BlockHeight::default, |header| header.get_height())
+                },
+            )
+        };
+        let block_query_srv = with_cors(&net_cfg.cors, block_query_srv.or(tx_receipt_srv).boxed())?;
+        let ws_subscribe_srv = {
+            let ws_subscribe_db = db.clone();
+            let block_header_copy = ready_block_header.clone();
+            ws_subscribe_server(
+                &net_cfg.ws_subscribe,
+                &COMMIT_EVENTS,
+                move |height| {
+                    load_commit_events_from_db::<Block, Tx>(&ws_subscribe_db, height, true)
+                },
+                move || {
+                    block_header_copy
+                        .get()
+                        .map_or_else(BlockHeight::default, |header| header.get_height())
+                },
+            )
+        };
+
+        // The admin routes of the database are signed by the admin keys of the client nodes.
+        let admin_verifier = Arc::new(AdminRequestVerifier::new(
+            route_table.peer_id(),
+            net_cfg.route_update.admin_keys.clone(),
+        ));
+
+        // Start the HTTP server first so that blocks arriving during the checkpoint sync
+        // are buffered in the channel.
         info!("Create http server, listen on {}", net_cfg.http_listen);
         let listen_addr: SocketAddr = net_cfg.http_listen.parse()?;
-        let (srv_shutdown_tx, srv_shutdown_rx) = oneshot::channel::<()>();
-        let (_, srv) =
-            warp::serve(warp::path(NODE_RPC_ROUTE_PATH).and(tx_exec_srv.or(block_import_srv)))
-                .bind_with_graceful_shutdown(listen_addr, async {
-                    srv_shutdown_rx.await.ok();
-                });
-        let srv_handle = tokio::spawn(srv);
+        let srv = HttpServer::bind(
+            health_srv
+                .or(metrics_server())
+                .or(node_info_server(NodeInfo::local::<Block>(
+                    Role::Storage(shard_id),
+                    &net_cfg.node_info,
+                )))
+                .or(block_query_srv)
+                .or(ws_subscribe_srv)
+                .or(warp::path(NODE_RPC_ROUTE_PATH)
+                    .and(warp_node_rpc_auth())
+                    .and(
+                        tx_exec_srv
+                            .or(cross_shard_prepare_srv)
+                            .or(block_import_srv)
+                            .or(checkpoint_srv::<Tx>(db.clone(), chain_cfg.state_len))
+                            .or(check_integrity_srv::<Tx>(
+                                db.clone(),
+                                shard_id,
+                                admin_verifier.clone(),
+                            ))
+                            .or(backup_srv(
+                                db.clone(),
+                                admin_verifier.clone(),
+                                net_cfg.backup.clone(),
+                            )),
+                    )
+                    .recover(recover_unauthorized)
+                    .recover(recover_body_limit)
+                    .recover(recover_rate_limited)),
+            listen_addr,
+            &net_cfg.shutdown,
+        )?;
+
+        if chain_cfg.fast_sync && db.get_meta_object::<BlockHeight>("height")?.is_none() {
+            if let Err(e) = checkpoint_sync(&db, shard_id, &route_table, blk_tx.clone()).await {
+                srv.shutdown().await?;
+                return Err(e);
+            }
+        }
+
+        let snapshot =
+            Snapshot::<Block, StorageTxTrie>::load_from_db(&db, chain_cfg.state_len, shard_id)?;
+        let latest_block_header = snapshot.to_latest_block_header();
+        let latest_tx_count = LatestTxCount::new(0);
+        ready_block_header.set(latest_block_header.clone()).ok();
+
+        let state_fetch_cfg = &net_cfg.state_fetch;
+        let remote_state = if state_fetch_cfg.enabled {
+            let route_table = Arc::new(route_table.clone());
+            let peer_health = Arc::new(PeerHealth::new());
+            let fetch_fn: FetchStateNodesFn = Arc::new(move |hashes| {
+                let route_table = route_table.clone();
+                let peer_health = peer_health.clone();
+                async move { fetch_state_nodes(&route_table, &peer_health, hashes).await }.boxed()
+            });
+            Some(Arc::new(RemoteStateFetcher::new(
+                fetch_fn,
+                state_fetch_cfg.timeout,
+                state_fetch_cfg.max_nodes_per_tx,
+                state_fetch_cfg.cache_size,
+            )))
+        } else {
+            None
+        };
+
+        let import_worker_tx_req_tx = tx_req_tx.clone();
+        let exec_worker = TxExecWorker::new(
+            route_table.clone(),
+            shard_id,
+            engine,
+            &db,
+            &latest_block_header,
+            tx_req_tx,
+            tx_req_rx,
+            exec_stage,
+            remote_state,
+        );
+
+        let import_worker = BlockImportWorker::new(
+            route_table,
+            shard_id,
+            chain_cfg.clone(),
+            snapshot,
+            latest_block_header,
+            latest_tx_count,
+            db,
+            blk_tx,
+            blk_rx,
+            nonce_intake,
+            import_worker_tx_req_tx,
+        );
 
         Ok(Self {
-            srv: Some((srv_shutdown_tx, srv_handle)),
+            srv: Some(srv),
             exec_worker,
             import_worker,
         })
     }
 
     pub async fn shutdown(&mut self) -> Result<()> {
-        info!("Shutting down TxExecWorker...");
-        self.exec_worker.shutdown().await?;
-        info!("Shutting down BlockImportWorker...");
-        self.import_worker.shutdown().await?;
+        // Drain the in-flight tx requests and block imports into the workers first.
         info!("Shutting down HTTP Server...");
-        if let Some((shutdown_tx, handler)) = self.srv.take() {
-            shutdown_tx.send(()).ok();
-            handler.await?;
+        if let Some(srv) = self.srv.take() {
+            srv.shutdown().await?;
         } else {
             bail!("Already shutdown.");
         }
+        info!("Shutting down TxExecWorker...");
+        self.exec_worker.shutdown().await?;
+        info!("Shutting down BlockImportWorker...");
+        self.import_worker.shutdown().await?;
         Ok(())
     }
 }
@@ -0,0 +1,357 @@
+use crate::http::{
+    config::{NetworkRouteTable, PeerId},
+    node_rpc::*,
+    peer_health::PeerHealth,
+};
+use futures::{channel::mpsc, prelude::*};
+use serde::Deserialize;
+use slimchain_chain::{
+    block_proposal::BlockProposal,
+    checkpoint::{download_state_trie, Checkpoint, CheckpointData},
+    consensus::raft::Block,
+    db::DBPtr,
+    role::Role,
+};
+use slimchain_common::{
+    basic::{BlockHeight, ShardId, H256},
+    error::{anyhow, ensure, Result},
+    tx::TxTrait,
+};
+use slimchain_utils::record_event;
+
+const STATE_NODES_BATCH_SIZE: usize = 256;
+
+async fn find_latest_checkpoint(
+    route_table: &NetworkRouteTable,
+    shard_id: ShardId,
+) -> Option<(PeerId, Checkpoint)> {
+    let peers = route_table.role_table().get(&Role::Storage(shard_id))?;
+    let mut latest: Option<(PeerId, Checkpoint)> = None;
+    for &peer_id in peers {
+        if peer_id == route_table.peer_id() {
+            continue;
+        }
+
+        let peer_addr = match route_table.peer_address(peer_id) {
+            Ok(addr) => addr,
+            Err(_) => continue,
+        };
+        match get_checkpoint(peer_addr).await {
+            Ok(Some(checkpoint)) => {
+                if latest.map_or(true, |(_, cp)| checkpoint.height > cp.height) {
+                    latest = Some((peer_id, checkpoint));
+                }
+            }
+            Ok(None) => {}
+            Err(e) => warn!(
+                "Failed to get the checkpoint from {}. Error: {}",
+                peer_id, e
+            ),
+        }
+    }
+    latest
+}
+
+/// Sync an empty storage node from the latest checkpoint of the peers in the same shard.
+///
+/// The blocks after the checkpoint are sent to `blk_tx` to be imported as usual.
+/// Return false if no checkpoint is available from the peers.
+pub async fn checkpoint_sync<Tx>(
+    db: &DBPtr,
+    shard_id: ShardId,
+    route_table: &NetworkRouteTable,
+    blk_tx: mpsc::UnboundedSender<BlockProposal<Block, Tx>>,
+) -> Result<bool>
+where
+    Tx: TxTrait + for<'de> Deserialize<'de> + 'static,
+{
+    let (peer_id, checkpoint) = match find_latest_checkpoint(route_table, shard_id).await {
+        Some(latest) => latest,
+        None => {
+            info!("No checkpoint is available from the peers.");
+            return Ok(false);
+        }
+    };
+    let peer_addr = route_table.peer_address(peer_id)?.clone();
+    info!(
+        "Sync from the checkpoint at height {} of peer {}.",
+        checkpoint.height, peer_id
+    );
+    record_event!("checkpoint_sync_begin", "peer": peer_id.0, "height": checkpoint.height.0);
+
+    // The peer may have created a newer checkpoint since. Use whatever it returns.
+    let data: CheckpointData<Block> = get_checkpoint_data(&peer_addr).await?;
+    data.verify()?;
+    let checkpoint = data.checkpoint;
+
+    let state_db_tx =
+        download_state_trie(checkpoint.state_root, STATE_NODES_BATCH_SIZE, |hashes| {
+            let peer_addr = peer_addr.clone();
+            async move { get_state_nodes(&peer_addr, &hashes).await }
+        })
+        .await?;
+    db.write_async(state_db_tx).await?;
+    db.write_async(data.write_db_tx()?).await?;
+
+    let mut next_height = checkpoint.height.next_height();
+    loop {
+        let blk_proposals: Vec<BlockProposal<Block, Tx>> =
+            get_block_proposals(&peer_addr, next_height).await?;
+        let last_height = match blk_proposals.last() {
+            Some(blk_proposal) => blk_proposal.get_block_height(),
+            None => break,
+        };
+        for blk_proposal in blk_proposals {
+            blk_tx
+                .unbounded_send(blk_proposal)
+                .map_err(|e| e.into_send_error())?;
+        }
+        next_height = last_height.next_height();
+    }
+
+    info!(
+        "Synced to the checkpoint at height {}. Fetched blocks up to height {}.",
+        checkpoint.height,
+        next_height.saturating_sub(1)
+    );
+    record_event!("checkpoint_sync_end", "peer": peer_id.0, "height": checkpoint.height.0, "fetched_height": next_height.saturating_sub(1).0);
+    Ok(true)
+}
+
+/// Fetch the missing blocks in the heights `from..to` from the peers in the same shard,
+/// failing over to the next peer if one does not have them.
+///
+/// The blocks are sent to `blk_tx` to be imported as usual. Return the height after the last
+/// fetched block.
+pub async fn fetch_missing_blocks<Tx>(
+    route_table: &NetworkRouteTable,
+    shard_id: ShardId,
+    peer_health: &PeerHealth,
+    from: BlockHeight,
+    to: BlockHeight,
+    blk_tx: mpsc::UnboundedSender<BlockProposal<Block, Tx>>,
+) -> Result<BlockHeight>
+where
+    Tx: TxTrait + for<'de> Deserialize<'de> + 'static,
+{
+    let peers: Vec<PeerId> = route_table
+        .peers_for_role(&Role::Storage(shard_id))
+        .iter()
+        .copied()
+        .filter(|&peer_id| peer_id != route_table.peer_id())
+        .collect();
+
+    let mut next_height = from;
+    while next_height < to {
+        let (_, blk_proposals) = peer_health
+            .send_with_failover(&peers, |peer_id| async move {
+                let peer_addr = route_table.peer_address(peer_id)?;
+                let blk_proposals: Vec<BlockProposal<Block, Tx>> =
+                    get_blocks(peer_addr, next_height, to).await?;
+                ensure!(
+                    !blk_proposals.is_empty(),
+                    "Peer {} does not have the block at height {}.",
+                    peer_id,
+                    next_height
+                );
+                let mut expected = next_height;
+                for blk_proposal in &blk_proposals {
+                    ensure!(
+                        blk_proposal.get_block_height() == expected,
+                        "Peer {} returned the block at height {}. Expected: {}.",
+                        peer_id,
+                        blk_proposal.get_block_height(),
+                        expected
+                    );
+                    expected = expected.next_height();
+                }
+                Ok(blk_proposals)
+            })
+            .await?;
+
+        for blk_proposal in blk_proposals {
+            next_height = blk_proposal.get_block_height().next_height();
+            blk_tx
+                .unbounded_send(blk_proposal)
+                .map_err(|e| e.into_send_error())?;
+        }
+    }
+    Ok(next_height)
+}
+
+/// Fetch the blocks in the heights `from..to` from the storage nodes of any shard by
+/// [`fetch_missing_blocks`], as all of them keep the whole chain. E.g., the ones whose bodies
+/// are pruned by a client node, or the ones missing from a client node lagging behind.
+pub async fn fetch_blocks<Tx>(
+    route_table: &NetworkRouteTable,
+    peer_health: &PeerHealth,
+    from: BlockHeight,
+    to: BlockHeight,
+) -> Result<Vec<BlockProposal<Block, Tx>>>
+where
+    Tx: TxTrait + for<'de> Deserialize<'de> + 'static,
+{
+    let mut last_err = anyhow!("No storage node is available.");
+    for role in route_table.role_table().keys() {
+        let shard_id = match role {
+            Role::Storage(shard_id) => *shard_id,
+            _ => continue,
+        };
+        let (blk_tx, blk_rx) = mpsc::unbounded();
+        match fetch_missing_blocks(route_table, shard_id, peer_health, from, to, blk_tx).await {
+            Ok(_) => return Ok(blk_rx.collect().await),
+            Err(e) => {
+                warn!(
+                    "Failed to fetch the blocks from shard {:?}. Error: {}",
+                    shard_id, e
+                );
+                last_err = e;
+            }
+        }
+    }
+    Err(last_err)
+}
+
+/// Fetch the state nodes by `hashes` from the other storage nodes of any shard, in the order
+/// of their health until all of them are found. The ones no peer has are left `None`.
+pub async fn fetch_state_nodes(
+    route_table: &NetworkRouteTable,
+    peer_health: &PeerHealth,
+    hashes: Vec<H256>,
+) -> Result<Vec<Option<Vec<u8>>>> {
+    let peers: Vec<PeerId> = route_table
+        .role_table()
+        .iter()
+        .filter(|(role, _)| matches!(role, Role::Storage(_)))
+        .flat_map(|(_, peers)| peers.iter().copied())
+        .filter(|&peer_id| peer_id != route_table.peer_id())
+        .collect();
+
+    let mut nodes: Vec<Option<Vec<u8>>> = vec![None; hashes.len()];
+    for peer_id in peer_health.rank(&peers) {
+        let missing: Vec<usize> = (0..hashes.len()).filter(|&i| nodes[i].is_none()).collect();
+        if missing.is_empty() {
+            break;
+        }
+        let peer_addr = match route_table.peer_address(peer_id) {
+            Ok(addr) => addr,
+            Err(_) => continue,
+        };
+        let req: Vec<H256> = missing.iter().map(|&i| hashes[i]).collect();
+        match get_state_nodes(peer_addr, &req).await {
+            Ok(resp) if resp.len() == req.len() => {
+                peer_health.record_success(peer_id);
+                for (i, bin) in missing.into_iter().zip(resp.into_iter()) {
+                    nodes[i] = bin;
+                }
+            }
+            Ok(resp) => {
+                peer_health.record_failure(peer_id);
+                warn!(
+                    "Expect {} state nodes from {}, but got {}.",
+                    req.len(),
+                    peer_id,
+                    resp.len()
+                );
+            }
+            Err(e) => {
+                peer_health.record_failure(peer_id);
+                warn!(
+                    "Failed to fetch the state nodes from {}. Error: {}",
+                    peer_id, e
+                );
+            }
+        }
+    }
+    Ok(nodes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::{
+        common::warp_reply_binary, config::NetworkConfig, route_table::RouteTableUpdate,
+    };
+    use futures::prelude::*;
+    use slimchain_chain::{
+        block::BlockTrait, block_proposal::BlockProposalTrie, genesis::GenesisConfig,
+    };
+    use slimchain_common::{basic::H256, tx::RawTx};
+    use slimchain_utils::{config::Config, toml};
+    use warp::Filter;
+
+    fn block_proposal(height: u64) -> BlockProposal<Block, RawTx> {
+        let mut block = Block::genesis_from_config(&GenesisConfig::default(), H256::zero());
+        block.block_header_mut().height = height.into();
+        BlockProposal::new(
+            block,
+            Vec::new(),
+            BlockProposalTrie::Diff(Default::default()),
+        )
+    }
+
+    fn fake_peer(heights: std::ops::Range<u64>) -> (String, tokio::task::JoinHandle<()>) {
+        let route = warp::path(NODE_RPC_ROUTE_PATH)
+            .and(warp::path(STORAGE_GET_BLOCKS_ROUTE_PATH))
+            .and(warp::query::<GetBlocksQuery>())
+            .map(move |query: GetBlocksQuery| {
+                let blk_proposals: Vec<_> = (query.from..query.to)
+                    .filter(|h| heights.contains(h))
+                    .take(2)
+                    .map(block_proposal)
+                    .collect();
+                warp_reply_binary(&blk_proposals)
+            });
+        let (addr, srv) = warp::serve(route).bind_ephemeral(([127, 0, 0, 1], 0));
+        (addr.to_string(), tokio::spawn(srv))
+    }
+
+    #[tokio::test]
+    async fn test_fetch_missing_blocks() {
+        let (lagging_addr, lagging_srv) = fake_peer(0..3);
+        let (synced_addr, synced_srv) = fake_peer(0..10);
+
+        let input = toml::toml! {
+            [network]
+            peer_id = 1
+
+            [[network.peers]]
+            peer_id = 1
+            address = "127.0.0.1:8001"
+            role = "storage"
+        };
+        let net_cfg: NetworkConfig = Config::from_toml(input).get("network").unwrap();
+        let mut route_table = net_cfg.to_route_table();
+        for (peer_id, address) in vec![(PeerId(2), lagging_addr), (PeerId(3), synced_addr)] {
+            route_table
+                .apply_update(RouteTableUpdate::AddPeer {
+                    peer_id,
+                    address,
+                    role: Role::Storage(ShardId::default()),
+                })
+                .unwrap();
+        }
+
+        let peer_health = PeerHealth::new();
+        let (blk_tx, blk_rx) = mpsc::unbounded::<BlockProposal<Block, RawTx>>();
+        let next_height = fetch_missing_blocks(
+            &route_table,
+            ShardId::default(),
+            &peer_health,
+            5u64.into(),
+            8u64.into(),
+            blk_tx,
+        )
+        .await
+        .unwrap();
+        assert_eq!(BlockHeight::from(8u64), next_height);
+
+        let heights: Vec<u64> = blk_rx.map(|blk| blk.get_block_height().0).collect().await;
+        assert_eq!(vec![5, 6, 7], heights);
+        assert!(peer_health.failure_score(PeerId(2)) > 0.);
+        assert_eq!(0., peer_health.failure_score(PeerId(3)));
+
+        lagging_srv.abort();
+        synced_srv.abort();
+    }
+}
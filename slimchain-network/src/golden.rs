@@ -0,0 +1,195 @@
+//! Golden tests pinning the binary layout of the types sent between the nodes.
+//!
+//! Each case serializes a fixed instance by bincode, i.e., the layout encoded by
+//! [`binary_encode`] before the snappy compression, and compares it against the hex fixture
+//! `golden/<name>.hex`. A mismatch means that the wire format is changed. If it is intended,
+//! bump [`WIRE_FORMAT_VERSION`] and regenerate the fixtures by
+//!
+//! ```sh
+//! SLIMCHAIN_UPDATE_GOLDEN=1 cargo test -p slimchain-network golden
+//! ```
+//!
+//! To pin a new wire type, add a line to the `golden_tests!` below.
+
+use crate::{
+    behavior::raft::message::{NewBlockRequest, NewBlockResponse},
+    http::node_info::WIRE_FORMAT_VERSION,
+};
+use chrono::{TimeZone, Utc};
+use serde::{de::DeserializeOwned, Serialize};
+use slimchain_chain::{
+    block::{BlockHeader, BlockTxList},
+    block_proposal::{BlockProposal, BlockProposalTrie},
+    consensus::raft::Block,
+};
+use slimchain_common::{
+    basic::{Address, BlockHeight, StateKey, StateValue, H160, H256},
+    ed25519::{Keypair, PubSigPair, PublicKey, SecretKey},
+    rw_set::{TxReadSet, TxWriteData},
+    tx::{RawTx, SignedTx},
+    tx_req::TxRequest,
+    utils::hex,
+};
+use slimchain_tx_state::{TxProposal, TxTrieDiff, TxWriteSetTrie};
+use slimchain_utils::serde::{binary_decode, binary_encode};
+use std::{fmt::Write as _, path::Path};
+
+const FIXTURE_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/golden");
+const UPDATE_ENV: &str = "SLIMCHAIN_UPDATE_GOLDEN";
+const BYTES_PER_LINE: usize = 32;
+
+fn to_hex_lines(bytes: &[u8]) -> String {
+    bytes
+        .chunks(BYTES_PER_LINE)
+        .map(|chunk| format!("{}\n", hex::encode(chunk)))
+        .collect()
+}
+
+/// List the lines differing between the fixture and the actual bytes with their offsets.
+fn diff_hex_lines(expected: &str, actual: &str) -> String {
+    let expected: Vec<&str> = expected.lines().collect();
+    let actual: Vec<&str> = actual.lines().collect();
+    let mut out = String::new();
+    for i in 0..expected.len().max(actual.len()) {
+        let lhs = expected.get(i).copied().unwrap_or_default();
+        let rhs = actual.get(i).copied().unwrap_or_default();
+        if lhs != rhs {
+            writeln!(out, "{:06x} - {}", i * BYTES_PER_LINE, lhs).unwrap();
+            writeln!(out, "{:06x} + {}", i * BYTES_PER_LINE, rhs).unwrap();
+        }
+    }
+    out
+}
+
+fn check_golden<T: Serialize + DeserializeOwned>(name: &str, value: &T) {
+    let bytes = bincode::serialize(value).unwrap();
+    let decoded: T = binary_decode(&binary_encode(value).unwrap()).unwrap();
+    assert_eq!(
+        bytes,
+        bincode::serialize(&decoded).unwrap(),
+        "{} is changed by the round trip.",
+        name
+    );
+
+    let actual = to_hex_lines(&bytes);
+    let path = Path::new(FIXTURE_DIR).join(format!("{}.hex", name));
+    if std::env::var_os(UPDATE_ENV).is_some() {
+        std::fs::create_dir_all(FIXTURE_DIR).unwrap();
+        std::fs::write(&path, &actual).unwrap();
+        return;
+    }
+
+    let expected = std::fs::read_to_string(&path).unwrap_or_else(|e| {
+        panic!(
+            "Failed to read {}. Error: {}. Set {}=1 to create it.",
+            path.display(),
+            e,
+            UPDATE_ENV
+        )
+    });
+    assert!(
+        expected == actual,
+        "The wire format of {} is changed (- fixture, + actual):\n{}\
+         If it is intended, bump WIRE_FORMAT_VERSION (now {}) and set {}=1 to regenerate the \
+         fixtures.",
+        name,
+        diff_hex_lines(&expected, &actual),
+        WIRE_FORMAT_VERSION,
+        UPDATE_ENV,
+    );
+}
+
+fn keypair() -> Keypair {
+    let secret = SecretKey::from_bytes(&[0x07; 32]).unwrap();
+    let public = PublicKey::from(&secret);
+    Keypair { secret, public }
+}
+
+fn address(byte: u8) -> Address {
+    Address(H160::repeat_byte(byte))
+}
+
+fn sample_raw_tx() -> RawTx {
+    let mut reads = TxReadSet::default();
+    let acc_reads = reads.entry(address(0x03)).or_default();
+    acc_reads.set_nonce(true);
+    acc_reads.values.insert(StateKey(H256::repeat_byte(0x06)));
+
+    let mut writes = TxWriteData::default();
+    writes.add_nonce(address(0x03), 7.into());
+    writes.add_value(
+        address(0x03),
+        StateKey(H256::repeat_byte(0x08)),
+        StateValue(H256::repeat_byte(0x09)),
+    );
+
+    RawTx {
+        caller: address(0x01),
+        input: TxRequest::Call {
+            nonce: 2.into(),
+            address: address(0x03),
+            data: vec![0xde, 0xad, 0xbe, 0xef],
+        },
+        block_height: BlockHeight(4),
+        state_root: H256::repeat_byte(0x05),
+        reads,
+        writes,
+        gas_used: 21_000,
+        fee: 5,
+    }
+}
+
+/// Only the layout matters, so the signatures are over fixed hashes instead of the digests.
+fn sample_signed_tx() -> SignedTx {
+    SignedTx {
+        raw_tx: sample_raw_tx(),
+        pk_sig: PubSigPair::create(&keypair(), H256::repeat_byte(0x0a)),
+    }
+}
+
+fn sample_tx_proposal() -> TxProposal<SignedTx> {
+    TxProposal::new(
+        sample_signed_tx(),
+        TxWriteSetTrie::from_root_hash(H256::repeat_byte(0x0b)),
+    )
+}
+
+fn sample_tx_trie_diff() -> TxTrieDiff {
+    TxTrieDiff::from_root_hash(H256::repeat_byte(0x0c))
+}
+
+fn sample_block_proposal() -> BlockProposal<Block, SignedTx> {
+    let header = BlockHeader::new(
+        BlockHeight(13),
+        H256::repeat_byte(0x0e),
+        Utc.timestamp_millis(1_600_000_000_000),
+        BlockTxList::default(),
+        H256::repeat_byte(0x0f),
+    );
+    let mut blk_proposal = BlockProposal::new(
+        Block::new(header),
+        vec![sample_signed_tx()],
+        BlockProposalTrie::Diff(sample_tx_trie_diff()),
+    );
+    blk_proposal.sign(&keypair(), Some(1));
+    blk_proposal
+}
+
+macro_rules! golden_tests {
+    ($($name: ident => $value: expr,)*) => {
+        $(
+            #[test]
+            fn $name() {
+                check_golden(stringify!($name), &$value);
+            }
+        )*
+    };
+}
+
+golden_tests! {
+    tx_proposal => sample_tx_proposal(),
+    tx_trie_diff => sample_tx_trie_diff(),
+    block_proposal => sample_block_proposal(),
+    new_block_request => NewBlockRequest(sample_block_proposal()),
+    new_block_response => NewBlockResponse::Err("invalid block".to_string()),
+}
@@ -1,4 +1,17 @@
+pub mod admin;
+pub mod body_limit;
 pub mod client_rpc;
 pub mod common;
 pub mod config;
+pub mod cors;
+pub mod health;
+pub mod metrics;
+pub mod node_info;
 pub mod node_rpc;
+pub mod peer_health;
+pub mod rate_limit;
+pub mod route_table;
+pub mod rpc_metrics;
+pub mod server;
+pub mod tx_verify;
+pub mod ws_subscribe;
@@ -0,0 +1,227 @@
+use crate::http::config::PeerId;
+use serde::{Deserialize, Serialize};
+use slimchain_chain::db::integrity::IntegrityOptions;
+use slimchain_common::{
+    basic::H256,
+    digest::Digestible,
+    ed25519::{Keypair, PubSigPair, PublicKey},
+    error::{ensure, Result},
+};
+use slimchain_utils::{
+    chrono::{DateTime, Duration, Utc},
+    serde::binary_encode,
+};
+use std::sync::Mutex;
+
+/// Tell the admin requests apart from the other signed payloads, e.g., the route table updates.
+const ADMIN_REQUEST_DOMAIN: &[u8] = b"slimchain/admin/v1";
+
+/// How long a signed request is accepted. `seq` is only kept in memory, so this bounds the
+/// replay of the requests accepted before a restart.
+pub(crate) const SIGNED_REQUEST_TTL_SECS: i64 = 300;
+
+/// A change of the raft cluster of the client nodes, sent to the leader by the admin routes, or
+/// an operation on the database of a storage node.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub enum AdminRequest {
+    AddMember(PeerId),
+    RemoveMember(PeerId),
+    TransferLeadership(PeerId),
+    AddLearner(PeerId),
+    /// Check the integrity of the database, see
+    /// [`IntegrityOptions`](slimchain_chain::db::integrity::IntegrityOptions).
+    CheckIntegrity(IntegrityOptions),
+    /// Back up the database into the backup of this name in the configured backup root.
+    Backup(String),
+}
+
+/// An [`AdminRequest`] to the node `target` signed by one of the admin keys. `seq` must
+/// increase across the requests accepted by a node, so that a captured request cannot be
+/// replayed, and the request expires at `expires_at`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SignedAdminRequest {
+    pub target: PeerId,
+    pub seq: u64,
+    pub expires_at: DateTime<Utc>,
+    pub req: AdminRequest,
+    pub sig: PubSigPair,
+}
+
+impl SignedAdminRequest {
+    /// Sign `req` to `target`, which expires after [`SIGNED_REQUEST_TTL_SECS`].
+    pub fn sign(target: PeerId, seq: u64, req: AdminRequest, keypair: &Keypair) -> Result<Self> {
+        let expires_at = Utc::now() + Duration::seconds(SIGNED_REQUEST_TTL_SECS);
+        Self::sign_until(target, seq, expires_at, req, keypair)
+    }
+
+    pub fn sign_until(
+        target: PeerId,
+        seq: u64,
+        expires_at: DateTime<Utc>,
+        req: AdminRequest,
+        keypair: &Keypair,
+    ) -> Result<Self> {
+        let sig = PubSigPair::create(keypair, Self::digest(target, seq, expires_at, &req)?);
+        Ok(Self {
+            target,
+            seq,
+            expires_at,
+            req,
+            sig,
+        })
+    }
+
+    fn digest(
+        target: PeerId,
+        seq: u64,
+        expires_at: DateTime<Utc>,
+        req: &AdminRequest,
+    ) -> Result<H256> {
+        Ok(binary_encode(&(ADMIN_REQUEST_DOMAIN, target, seq, expires_at, req))?.to_digest())
+    }
+
+    /// Verify that the request is signed by one of the `admin_keys`.
+    pub fn verify(&self, admin_keys: &[PublicKey]) -> Result<()> {
+        ensure!(
+            admin_keys.contains(self.sig.public()),
+            "Admin request signed by an unknown key."
+        );
+        self.sig.verify(Self::digest(
+            self.target,
+            self.seq,
+            self.expires_at,
+            &self.req,
+        )?)
+    }
+}
+
+/// Accept the signed admin requests to the node `peer_id` of the admin keys in increasing
+/// `seq`.
+#[derive(Debug)]
+pub struct AdminRequestVerifier {
+    peer_id: PeerId,
+    admin_keys: Vec<PublicKey>,
+    last_seq: Mutex<Option<u64>>,
+}
+
+impl AdminRequestVerifier {
+    pub fn new(peer_id: PeerId, admin_keys: Vec<PublicKey>) -> Self {
+        Self {
+            peer_id,
+            admin_keys,
+            last_seq: Mutex::new(None),
+        }
+    }
+
+    /// Verify `req` and return the request in it. All the requests are rejected if no admin key
+    /// is configured.
+    pub fn accept(&self, req: SignedAdminRequest) -> Result<AdminRequest> {
+        ensure!(!self.admin_keys.is_empty(), "Admin requests are disabled.");
+        req.verify(&self.admin_keys)?;
+        ensure!(
+            req.target == self.peer_id,
+            "Admin request to peer {}, not {}.",
+            req.target,
+            self.peer_id
+        );
+        ensure!(
+            req.expires_at > Utc::now(),
+            "Admin request expired at {}.",
+            req.expires_at
+        );
+
+        let mut last_seq = self.last_seq.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(last_seq) = *last_seq {
+            ensure!(
+                req.seq > last_seq,
+                "Stale admin request. Seq: {}. Last seq: {}.",
+                req.seq,
+                last_seq
+            );
+        }
+        *last_seq = Some(req.seq);
+        info!(seq = req.seq, "Admin request accepted. {:?}", req.req);
+        Ok(req.req)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::route_table::{RouteTableUpdate, SignedRouteTableUpdate};
+    use slimchain_utils::serde::binary_decode;
+
+    #[test]
+    fn test_signed_admin_request() {
+        let mut rng = rand::thread_rng();
+        let admin = Keypair::generate(&mut rng);
+        let other = Keypair::generate(&mut rng);
+        let sign = |seq: u64, req: AdminRequest| {
+            SignedAdminRequest::sign(PeerId(1), seq, req, &admin).unwrap()
+        };
+
+        let signed = sign(1, AdminRequest::AddMember(PeerId(2)));
+        let decoded: SignedAdminRequest = binary_decode(&binary_encode(&signed).unwrap()).unwrap();
+        assert_eq!(signed, decoded);
+
+        assert!(AdminRequestVerifier::new(PeerId(1), Vec::new())
+            .accept(signed.clone())
+            .is_err());
+        assert!(AdminRequestVerifier::new(PeerId(1), vec![other.public])
+            .accept(signed.clone())
+            .is_err());
+        // Only the target accepts it.
+        assert!(AdminRequestVerifier::new(PeerId(2), vec![admin.public])
+            .accept(signed.clone())
+            .is_err());
+
+        let verifier = AdminRequestVerifier::new(PeerId(1), vec![admin.public]);
+        assert_eq!(
+            AdminRequest::AddMember(PeerId(2)),
+            verifier.accept(signed.clone()).unwrap()
+        );
+
+        // Replayed, tampered or expired requests are rejected.
+        assert!(verifier.accept(signed).is_err());
+        let mut tampered = sign(2, AdminRequest::AddMember(PeerId(3)));
+        tampered.req = AdminRequest::RemoveMember(PeerId(3));
+        assert!(verifier.accept(tampered).is_err());
+        let mut retargeted =
+            SignedAdminRequest::sign(PeerId(2), 2, AdminRequest::RemoveMember(PeerId(3)), &admin)
+                .unwrap();
+        retargeted.target = PeerId(1);
+        assert!(verifier.accept(retargeted).is_err());
+        let expired = SignedAdminRequest::sign_until(
+            PeerId(1),
+            2,
+            Utc::now() - Duration::seconds(1),
+            AdminRequest::RemoveMember(PeerId(3)),
+            &admin,
+        )
+        .unwrap();
+        assert!(verifier.accept(expired).is_err());
+
+        // The signature of a route table update of the same shape does not pass.
+        let update = SignedRouteTableUpdate::sign(
+            PeerId(1),
+            2,
+            RouteTableUpdate::RemovePeer { peer_id: PeerId(3) },
+            &admin,
+        )
+        .unwrap();
+        let replayed = SignedAdminRequest {
+            target: update.target,
+            seq: update.seq,
+            expires_at: update.expires_at,
+            req: AdminRequest::RemoveMember(PeerId(3)),
+            sig: update.sig,
+        };
+        assert!(verifier.accept(replayed).is_err());
+
+        let signed = sign(2, AdminRequest::RemoveMember(PeerId(3)));
+        assert_eq!(
+            AdminRequest::RemoveMember(PeerId(3)),
+            verifier.accept(signed).unwrap()
+        );
+    }
+}
@@ -0,0 +1,167 @@
+use super::config::BodyLimitConfig;
+use futures::prelude::*;
+use serde::{Deserialize, Serialize};
+use slimchain_utils::bytes::{Buf, BufMut, Bytes, BytesMut};
+use std::time::Duration;
+use warp::{http::StatusCode, reject::Reject, Filter, Rejection, Reply};
+
+/// Rejected as the request body exceeds the limit. Use [`recover_body_limit`] to reply it with
+/// 413, along with itself in JSON.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize, thiserror::Error)]
+#[error("Request body exceeds the limit of {limit} bytes.")]
+pub struct BodyLimitExceeded {
+    /// The limit in bytes.
+    pub limit: u64,
+}
+
+impl Reject for BodyLimitExceeded {}
+
+/// Rejected as the request body is not read in time. Use [`recover_body_limit`] to reply it
+/// with 408.
+#[derive(Debug)]
+pub struct BodyReadTimeout {
+    pub timeout: Duration,
+}
+
+impl Reject for BodyReadTimeout {}
+
+#[derive(Debug)]
+struct BodyReadError(warp::Error);
+
+impl Reject for BodyReadError {}
+
+/// Read the request body of at most `limit` bytes within the `read_timeout` in the global
+/// [`BodyLimitConfig`]. The body is rejected once it exceeds the limit, either declared in the
+/// `Content-Length` header or received, so that it is never buffered in full.
+pub fn warp_body_bytes_with_limit(
+    limit: u64,
+) -> impl Filter<Extract = (Bytes,), Error = Rejection> + Copy {
+    warp::header::optional::<u64>("content-length")
+        .and(warp::body::stream())
+        .and_then(move |content_length: Option<u64>, body| {
+            read_body_with_limit(
+                limit,
+                BodyLimitConfig::get().read_timeout,
+                content_length,
+                body,
+            )
+        })
+}
+
+async fn read_body_with_limit<B: Buf>(
+    limit: u64,
+    timeout: Duration,
+    content_length: Option<u64>,
+    body: impl Stream<Item = Result<B, warp::Error>>,
+) -> Result<Bytes, Rejection> {
+    if content_length.map_or(false, |len| len > limit) {
+        return Err(warp::reject::custom(BodyLimitExceeded { limit }));
+    }
+
+    let read = async move {
+        futures::pin_mut!(body);
+        let mut buf = BytesMut::with_capacity(content_length.unwrap_or(0) as usize);
+        while let Some(chunk) = body.next().await {
+            let chunk = chunk.map_err(|e| warp::reject::custom(BodyReadError(e)))?;
+            if (buf.len() + chunk.remaining()) as u64 > limit {
+                return Err(warp::reject::custom(BodyLimitExceeded { limit }));
+            }
+            buf.put(chunk);
+        }
+        Ok(buf.freeze())
+    };
+    tokio::time::timeout(timeout, read)
+        .await
+        .map_err(|_| warp::reject::custom(BodyReadTimeout { timeout }))?
+}
+
+pub async fn recover_body_limit(rejection: Rejection) -> Result<warp::reply::Response, Rejection> {
+    if let Some(exceeded) = rejection.find::<BodyLimitExceeded>() {
+        Ok(
+            warp::reply::with_status(warp::reply::json(exceeded), StatusCode::PAYLOAD_TOO_LARGE)
+                .into_response(),
+        )
+    } else if rejection.find::<BodyReadTimeout>().is_some() {
+        Ok(StatusCode::REQUEST_TIMEOUT.into_response())
+    } else {
+        Err(rejection)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::common::*;
+    use slimchain_common::error::Error;
+
+    fn route(
+        limit: u64,
+    ) -> impl Filter<Extract = (warp::reply::Response,), Error = Rejection> + Clone {
+        warp::post()
+            .and(warp_body_binary_with_limit(limit))
+            .map(|blocks: Vec<Vec<u8>>| warp_reply_binary(&blocks.len()).into_response())
+            .recover(recover_body_limit)
+            .unify()
+    }
+
+    #[tokio::test]
+    async fn test_body_limit() {
+        let block = vec![0u8; 1024];
+        let resp = warp::test::request()
+            .method("POST")
+            .body(slimchain_utils::serde::binary_encode(&vec![block.clone(); 2]).unwrap())
+            .reply(&route(4096))
+            .await;
+        assert_eq!(StatusCode::OK, resp.status());
+
+        let resp = warp::test::request()
+            .method("POST")
+            .body(slimchain_utils::serde::binary_encode(&vec![block; 8]).unwrap())
+            .reply(&route(4096))
+            .await;
+        assert_eq!(StatusCode::PAYLOAD_TOO_LARGE, resp.status());
+        let exceeded: BodyLimitExceeded = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(4096, exceeded.limit);
+    }
+
+    #[tokio::test]
+    async fn test_body_limit_without_content_length() {
+        // The chunks are rejected once they add up beyond the limit, without reading the rest.
+        let chunks =
+            stream::iter((0..1024).map(|_| Ok::<_, warp::Error>(Bytes::from(vec![0u8; 1024]))))
+                .chain(stream::pending());
+        let res = read_body_with_limit(4096, Duration::from_secs(5), None, chunks).await;
+        let rejection = res.unwrap_err();
+        assert_eq!(
+            Some(&BodyLimitExceeded { limit: 4096 }),
+            rejection.find::<BodyLimitExceeded>()
+        );
+
+        // The slow bodies are timed out.
+        let chunks = stream::iter(vec![Ok::<_, warp::Error>(Bytes::from(vec![0u8; 16]))])
+            .chain(stream::pending());
+        let res = read_body_with_limit(4096, Duration::from_millis(50), None, chunks).await;
+        assert!(res.unwrap_err().find::<BodyReadTimeout>().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_oversized_block_import() {
+        let (addr, srv) = warp::serve(route(64 << 10)).bind_ephemeral(([127, 0, 0, 1], 0));
+        let uri = format!("http://{}/", addr);
+        let srv_handle = tokio::spawn(srv);
+
+        // Rejected by the declared length, without reading the body.
+        let blocks = vec![vec![0u8; 64 << 10]; 4];
+        let err: Error = send_post_request_using_binary::<_, usize>(&uri, &blocks)
+            .await
+            .unwrap_err();
+        assert_eq!(Some(64 << 10), body_limit_of_error(&err));
+
+        let resp: usize = send_post_request_using_binary(&uri, &blocks[..0].to_vec())
+            .await
+            .unwrap();
+        assert_eq!(0, resp);
+
+        srv_handle.abort();
+    }
+}
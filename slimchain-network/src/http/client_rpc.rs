@@ -1,20 +1,37 @@
-use super::common::*;
+use super::{
+    common::*,
+    config::RateLimitConfig,
+    rate_limit::{recover_rate_limited, RateLimitKey, RateLimited, RateLimiter},
+};
 use futures::prelude::*;
 use serde::{Deserialize, Serialize};
+use slimchain_chain::{
+    block::{BlockHeader, BlockTrait},
+    db::{BlockPruned, DBPtr},
+    tx_status::{TxStatus, TX_STATUS},
+};
 use slimchain_common::{
-    basic::{BlockHeight, ShardId},
-    error::{Error, Result},
-    tx_req::SignedTxRequest,
+    basic::{self, BlockHeight, ShardId, H256},
+    digest::Digestible,
+    error::{ensure, Context as _, Error, Result},
+    tx::TxTrait,
+    tx_req::{verify_batch, SignedTxRequest},
+    utils::hex,
 };
-use slimchain_utils::record_event;
-use std::{iter, sync::Arc};
-use warp::Filter;
+use slimchain_utils::{backpressure::Pipeline, config::on_section_reload, record_event};
+use std::{iter, net::SocketAddr, sync::Arc};
+use warp::{http::StatusCode, Filter, Reply};
 
 const CLIENT_RPC_ROUTE_PATH: &str = "client_rpc";
 const TX_REQ_ROUTE_PATH: &str = "tx_req";
 const RECORD_EVENT_ROUTE_PATH: &str = "record_event";
 const TX_COUNT_ROUTE_PATH: &str = "tx_count";
 const BLOCK_HEIGHT_ROUTE_PATH: &str = "block_height";
+const TX_STATUS_ROUTE_PATH: &str = "tx_status";
+const BLOCK_ROUTE_PATH: &str = "block";
+const BLOCK_HEIGHT_PARAM_PATH: &str = "height";
+const BLOCK_HASH_PARAM_PATH: &str = "hash";
+const TX_RECEIPT_ROUTE_PATH: &str = "tx_receipt";
 
 #[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
 pub struct TxHttpRequest {
@@ -23,6 +40,59 @@ pub struct TxHttpRequest {
     pub shard_id: ShardId,
 }
 
+/// Identify a committed block in the block queries.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum BlockId {
+    Height(BlockHeight),
+    Hash(H256),
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct BlockQueryResponse<Tx> {
+    pub hash: H256,
+    /// The tx hashes are in `header.tx_list`.
+    pub header: BlockHeader,
+    /// The tx bodies, only if requested from a storage node.
+    pub txs: Option<Vec<Tx>>,
+    /// Whether the block is pruned by the node, so that `header.tx_list` is empty. See
+    /// [`StorageMode::Headers`](slimchain_chain::config::StorageMode::Headers).
+    pub header_only: bool,
+}
+
+/// The body of the 404 replies of the block queries, so that the pollers can back off until
+/// `latest_height` catches up.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct BlockNotFound {
+    pub latest_height: BlockHeight,
+}
+
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+struct BlockQueryParams {
+    include_txs: bool,
+}
+
+/// The proof that the tx `tx_hash` is committed in the block of `header`. Check it with
+/// [`verify_receipt`].
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct TxReceipt<Tx> {
+    pub tx_hash: H256,
+    pub height: BlockHeight,
+    /// The tx list is committed by its flat hash in the header hash, so the full
+    /// `header.tx_list` serves as the inclusion proof.
+    pub header: BlockHeader,
+    /// The index of the tx in `header.tx_list`.
+    pub index: usize,
+    /// The tx body, only if requested.
+    pub tx: Option<Tx>,
+}
+
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+struct TxReceiptParams {
+    include_tx: bool,
+}
+
 #[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
 pub struct RecordEventHttpRequest {
     pub info: String,
@@ -122,6 +192,99 @@ pub async fn get_block_height(endpoint: &str) -> Result<BlockHeight> {
     .await
 }
 
+pub async fn get_tx_status(endpoint: &str, tx_id: H256) -> Result<TxStatus> {
+    send_get_request_using_binary(&format!(
+        "http://{}/{}/{}/{}",
+        endpoint,
+        CLIENT_RPC_ROUTE_PATH,
+        TX_STATUS_ROUTE_PATH,
+        hex::encode(tx_id.as_bytes())
+    ))
+    .await
+}
+
+/// Get the committed block `id`, with the tx bodies if `include_txs` is set and the peer is a
+/// storage node.
+pub async fn get_block<Tx: for<'de> Deserialize<'de>>(
+    endpoint: &str,
+    id: BlockId,
+    include_txs: bool,
+) -> Result<BlockQueryResponse<Tx>> {
+    let id = match id {
+        BlockId::Height(height) => format!("{}/{}", BLOCK_HEIGHT_PARAM_PATH, height.0),
+        BlockId::Hash(hash) => {
+            format!("{}/{}", BLOCK_HASH_PARAM_PATH, hex::encode(hash.as_bytes()))
+        }
+    };
+    send_get_request_using_binary(&format!(
+        "http://{}/{}/{}/{}?include_txs={}",
+        endpoint, CLIENT_RPC_ROUTE_PATH, BLOCK_ROUTE_PATH, id, include_txs
+    ))
+    .await
+}
+
+/// Get the receipt of the committed tx `tx_hash` from a storage node, with the tx body if
+/// `include_tx` is set. The receipt is not verified. See [`verify_receipt`].
+pub async fn get_tx_receipt<Tx: for<'de> Deserialize<'de>>(
+    endpoint: &str,
+    tx_hash: H256,
+    include_tx: bool,
+) -> Result<TxReceipt<Tx>> {
+    send_get_request_using_binary(&format!(
+        "http://{}/{}/{}/{}?include_tx={}",
+        endpoint,
+        CLIENT_RPC_ROUTE_PATH,
+        TX_RECEIPT_ROUTE_PATH,
+        hex::encode(tx_hash.as_bytes()),
+        include_tx
+    ))
+    .await
+}
+
+/// Verify that `receipt` proves its tx committed in the block header with
+/// `trusted_header_hash`, i.e., the [`BlockHeader::to_digest`] obtained without trusting the
+/// node serving the receipt. The raft block hash is derived from, but not equal to, it.
+pub fn verify_receipt<Tx: Digestible>(
+    receipt: &TxReceipt<Tx>,
+    trusted_header_hash: H256,
+) -> Result<()> {
+    let header_hash = receipt.header.to_digest();
+    ensure!(
+        header_hash == trusted_header_hash,
+        "The block header does not match the trusted one. Expect: {}. Actual: {}.",
+        trusted_header_hash,
+        header_hash
+    );
+    ensure!(
+        receipt.header.height == receipt.height,
+        "The block header is at height {}, not {}.",
+        receipt.header.height,
+        receipt.height
+    );
+    ensure!(
+        receipt.header.tx_list.get(receipt.index) == Some(&receipt.tx_hash),
+        "The tx {} is not at index {} of the block.",
+        receipt.tx_hash,
+        receipt.index
+    );
+    if let Some(tx) = receipt.tx.as_ref() {
+        ensure!(
+            tx.to_digest() == receipt.tx_hash,
+            "The tx body does not match the tx hash {}.",
+            receipt.tx_hash
+        );
+    }
+    Ok(())
+}
+
+fn parse_h256(input: &str, name: &str) -> Result<H256> {
+    basic::parse_h256(input).with_context(|| format!("Invalid {}: {}.", name, input))
+}
+
+fn parse_tx_id(input: &str) -> Result<H256> {
+    parse_h256(input, "tx id")
+}
+
 #[derive(Debug)]
 struct ClientRpcServerError(Error);
 
@@ -135,15 +298,84 @@ pub fn client_rpc_server<TxReqOutput>(
 where
     TxReqOutput: TryFuture<Ok = (), Error = Error> + Send + 'static,
 {
+    client_rpc_server_with_rate_limit(
+        &RateLimitConfig::default(),
+        None,
+        tx_req_fn,
+        tx_count_fn,
+        block_height_fn,
+    )
+}
+
+/// Same as [`client_rpc_server`], while the tx requests are limited by their caller addresses
+/// as set in `tx_rate_limit`. The ones exceeding the limits are replied with 429. The limits
+/// follow the reloads of `network.tx_rate_limit` in the config.
+///
+/// If `pipeline` is set, the tx requests are also replied with 429 while any of its stages is
+/// saturated.
+pub fn client_rpc_server_with_rate_limit<TxReqOutput>(
+    tx_rate_limit: &RateLimitConfig,
+    pipeline: Option<Arc<Pipeline>>,
+    tx_req_fn: impl Fn(Vec<TxHttpRequest>) -> TxReqOutput + Send + Sync + 'static,
+    tx_count_fn: impl Fn() -> usize + Send + Sync + 'static,
+    block_height_fn: impl Fn() -> BlockHeight + Send + Sync + 'static,
+) -> warp::filters::BoxedFilter<(impl warp::Reply,)>
+where
+    TxReqOutput: TryFuture<Ok = (), Error = Error> + Send + 'static,
+{
+    let tx_rate_limiter = Arc::new(RateLimiter::new(TX_REQ_ROUTE_PATH, tx_rate_limit));
+    {
+        let tx_rate_limiter = tx_rate_limiter.clone();
+        on_section_reload("network.tx_rate_limit", move |cfg: RateLimitConfig| {
+            info!("Update the tx rate limit: {:?}", cfg);
+            tx_rate_limiter.update(&cfg);
+        });
+    }
     let tx_req_fn = Arc::new(tx_req_fn);
     let tx_req_route = warp::post()
         .and(warp::path(TX_REQ_ROUTE_PATH))
+        .and(warp::addr::remote())
         .and(warp_body_binary())
-        .and_then(move |reqs: Vec<TxHttpRequest>| {
-            tx_req_fn(reqs)
-                .map_ok(|_| warp_reply_binary(&()))
-                .map_err(|e| warp::reject::custom(ClientRpcServerError(e)))
-        });
+        .and_then(
+            move |remote: Option<SocketAddr>, reqs: Vec<TxHttpRequest>| {
+                let shed = pipeline
+                    .as_ref()
+                    .and_then(|pipeline| pipeline.check().err());
+                let limited = if let Some(saturated) = shed {
+                    record_event!("tx_req_shed", "stage": saturated.stage, "count": reqs.len());
+                    Some(RateLimited {
+                        retry_after: saturated.retry_after,
+                    })
+                } else if tx_rate_limiter.is_enabled() {
+                    let keys: Vec<RateLimitKey> = if reqs.is_empty() {
+                        vec![RateLimitKey::from_remote(remote)]
+                    } else {
+                        reqs.iter()
+                            .map(|req| RateLimitKey::Address(req.req.caller_address()))
+                            .collect()
+                    };
+                    tx_rate_limiter.check(&keys).err()
+                } else {
+                    None
+                };
+                match limited {
+                    Some(limited) => {
+                        future::Either::Left(future::err(warp::reject::custom(limited)))
+                    }
+                    None => {
+                        let tx_req_fn = tx_req_fn.clone();
+                        future::Either::Right(async move {
+                            let reqs = retain_verified_tx_reqs(reqs).await;
+                            tx_req_fn(reqs)
+                                .into_future()
+                                .await
+                                .map(|_| warp_reply_binary(&()))
+                                .map_err(|e| warp::reject::custom(ClientRpcServerError(e)))
+                        })
+                    }
+                }
+            },
+        );
     let record_event_route = warp::post()
         .and(warp::path(RECORD_EVENT_ROUTE_PATH))
         .and(warp::body::json())
@@ -172,5 +404,583 @@ where
                 .or(tx_count_route)
                 .or(block_height_route),
         )
+        .recover(recover_rate_limited)
         .boxed()
 }
+
+/// Verify the signatures of the submitted `reqs` together with [`verify_batch`] on a blocking
+/// thread, and keep the valid ones. The invalid ones are recorded as failed in the tx status.
+async fn retain_verified_tx_reqs(reqs: Vec<TxHttpRequest>) -> Vec<TxHttpRequest> {
+    let verified = tokio::task::spawn_blocking(move || {
+        let (reqs, shard_ids): (Vec<_>, Vec<_>) = reqs
+            .into_iter()
+            .map(|TxHttpRequest { req, shard_id }| (req, shard_id))
+            .unzip();
+        let results = verify_batch(&reqs);
+        reqs.into_iter()
+            .zip(shard_ids)
+            .zip(results)
+            .filter_map(|((req, shard_id), res)| match res {
+                Ok(()) => Some(TxHttpRequest { req, shard_id }),
+                Err(e) => {
+                    let tx_id = req.id();
+                    warn!(%tx_id, "Discard the tx with an invalid signature. {}", e);
+                    record_event!("discard_tx", "tx_id": tx_id, "reason": "invalid_signature", "scheme": req.scheme().to_string(), "detail": std::format!("{}", e));
+                    TX_STATUS.record_failure(tx_id, "invalid_signature");
+                    None
+                }
+            })
+            .collect()
+    })
+    .await;
+    verified.unwrap_or_else(|e| {
+        error!("Failed to verify the tx requests. Error: {}", e);
+        Vec::new()
+    })
+}
+
+/// The `tx_status/{tx_id}` route of the client RPC, where `tx_id` is hex encoded.
+pub fn tx_status_server(
+    tx_status_fn: impl Fn(H256) -> Result<TxStatus> + Send + Sync + 'static,
+) -> warp::filters::BoxedFilter<(impl warp::Reply,)> {
+    let tx_status_fn = Arc::new(tx_status_fn);
+    warp::get()
+        .and(warp::path(CLIENT_RPC_ROUTE_PATH))
+        .and(warp::path(TX_STATUS_ROUTE_PATH))
+        .and(warp::path::param::<String>())
+        .and(warp::path::end())
+        .and_then(move |tx_id: String| {
+            let res = parse_tx_id(&tx_id)
+                .and_then(|tx_id| tx_status_fn(tx_id))
+                .map(|status| warp_reply_binary(&status))
+                .map_err(|e| warp::reject::custom(ClientRpcServerError(e)));
+            future::ready(res)
+        })
+        .boxed()
+}
+
+/// Load the committed block `id` from `db` for [`block_query_server`]. Return `None` if it is
+/// beyond the latest block or unknown. The tx bodies are loaded only if `include_txs` is set.
+/// Only the header is returned if the block is pruned.
+pub async fn load_block_from_db<Block, Tx>(
+    db: DBPtr,
+    id: BlockId,
+    include_txs: bool,
+) -> Result<Option<BlockQueryResponse<Tx>>>
+where
+    Block: BlockTrait + for<'de> Deserialize<'de> + 'static,
+    Tx: TxTrait + for<'de> Deserialize<'de> + 'static,
+{
+    let height = db
+        .read_async(move |db| {
+            let latest_height = db
+                .get_latest_block_header()?
+                .map_or(BlockHeight::from(0), |header| header.height);
+            let height = match id {
+                BlockId::Height(height) => Some(height),
+                BlockId::Hash(hash) => db.get_block_height_by_hash(hash)?,
+            };
+            Ok(height.filter(|&height| height <= latest_height))
+        })
+        .await?;
+    let height = match height {
+        Some(height) => height,
+        None => return Ok(None),
+    };
+
+    let block: Block = match db.get_block_async(height).await {
+        Ok(block) => block,
+        Err(e) if e.downcast_ref::<BlockPruned>().is_some() => {
+            let pruned = db
+                .read_async(move |db| db.get_pruned_block(height))
+                .await?
+                .context("Pruned block not available in the database.")?;
+            return Ok(Some(BlockQueryResponse {
+                hash: pruned.hash,
+                header: pruned.to_block_header(),
+                txs: None,
+                header_only: true,
+            }));
+        }
+        Err(e) => return Err(e),
+    };
+    let txs = if include_txs {
+        let txs = block
+            .tx_list()
+            .iter()
+            .map(|&tx_hash| db.get_tx_async(tx_hash));
+        Some(future::try_join_all(txs).await?)
+    } else {
+        None
+    };
+    Ok(Some(BlockQueryResponse {
+        hash: block.to_digest(),
+        header: block.block_header().clone(),
+        txs,
+        header_only: false,
+    }))
+}
+
+/// Load the receipt of the committed tx `tx_hash` from `db` for [`tx_receipt_server`]. Return
+/// `None` if it is unknown, or the tx locations are not indexed. The tx body is loaded only if
+/// `include_tx` is set.
+pub async fn load_tx_receipt_from_db<Block, Tx>(
+    db: DBPtr,
+    tx_hash: H256,
+    include_tx: bool,
+) -> Result<Option<TxReceipt<Tx>>>
+where
+    Block: BlockTrait + for<'de> Deserialize<'de> + 'static,
+    Tx: TxTrait + for<'de> Deserialize<'de> + 'static,
+{
+    let (height, index) = match db.read_async(move |db| db.get_tx_location(tx_hash)).await? {
+        Some(loc) => loc,
+        None => return Ok(None),
+    };
+    let block: Block = db.get_block_async(height).await?;
+    let tx = if include_tx {
+        Some(db.get_tx_async(tx_hash).await?)
+    } else {
+        None
+    };
+    Ok(Some(TxReceipt {
+        tx_hash,
+        height,
+        header: block.block_header().clone(),
+        index,
+        tx,
+    }))
+}
+
+fn accepts_json(accept: Option<&str>) -> bool {
+    accept.map_or(false, |accept| {
+        accept
+            .split(',')
+            .any(|media_type| media_type.trim().starts_with("application/json"))
+    })
+}
+
+fn reply_encoded<T: Serialize>(json: bool, status: StatusCode, val: &T) -> warp::reply::Response {
+    let resp = if json {
+        warp::reply::json(val).into_response()
+    } else {
+        warp_reply_binary(val).into_response()
+    };
+    warp::reply::with_status(resp, status).into_response()
+}
+
+/// The `block/height/{height}` and `block/hash/{hash}` routes of the client RPC, where `hash`
+/// is hex encoded. Add `?include_txs=true` for the tx bodies, which are only returned if
+/// `serve_txs` is set, i.e., on the storage nodes. The replies are binary encoded, or JSON
+/// encoded if asked with the `Accept` header. The blocks not found are replied with 404 and
+/// [`BlockNotFound`].
+pub fn block_query_server<Tx, BlockFn, BlockFut>(
+    serve_txs: bool,
+    block_fn: BlockFn,
+    block_height_fn: impl Fn() -> BlockHeight + Send + Sync + 'static,
+) -> warp::filters::BoxedFilter<(impl warp::Reply,)>
+where
+    Tx: Serialize + Send + 'static,
+    BlockFn: Fn(BlockId, bool) -> BlockFut + Send + Sync + 'static,
+    BlockFut: Future<Output = Result<Option<BlockQueryResponse<Tx>>>> + Send + 'static,
+{
+    let by_height = warp::path(BLOCK_HEIGHT_PARAM_PATH)
+        .and(warp::path::param::<u64>())
+        .map(|height: u64| Ok::<_, Error>(BlockId::Height(height.into())));
+    let by_hash = warp::path(BLOCK_HASH_PARAM_PATH)
+        .and(warp::path::param::<String>())
+        .map(|hash: String| parse_h256(&hash, "block hash").map(BlockId::Hash));
+    let block_fn = Arc::new(block_fn);
+    let block_height_fn = Arc::new(block_height_fn);
+    warp::get()
+        .and(warp::path(CLIENT_RPC_ROUTE_PATH))
+        .and(warp::path(BLOCK_ROUTE_PATH))
+        .and(by_height.or(by_hash).unify())
+        .and(warp::path::end())
+        .and(warp::query::<BlockQueryParams>())
+        .and(warp::header::optional::<String>("accept"))
+        .and_then(
+            move |id: Result<BlockId>, params: BlockQueryParams, accept: Option<String>| {
+                let json = accepts_json(accept.as_deref());
+                let include_txs = serve_txs && params.include_txs;
+                let block = id.map(|id| block_fn(id, include_txs));
+                let block_height_fn = block_height_fn.clone();
+                async move {
+                    block?.await.map(|block| match block {
+                        Some(block) => reply_encoded(json, StatusCode::OK, &block),
+                        None => reply_encoded(
+                            json,
+                            StatusCode::NOT_FOUND,
+                            &BlockNotFound {
+                                latest_height: block_height_fn(),
+                            },
+                        ),
+                    })
+                }
+                .map_err(|e| warp::reject::custom(ClientRpcServerError(e)))
+            },
+        )
+        .boxed()
+}
+
+/// The `tx_receipt/{tx_hash}` route of the client RPC on the storage nodes, where `tx_hash` is
+/// hex encoded. Add `?include_tx=true` for the tx body. The replies are encoded like the block
+/// queries. The txs not found are replied with 404 and [`BlockNotFound`].
+pub fn tx_receipt_server<Tx, ReceiptFn, ReceiptFut>(
+    receipt_fn: ReceiptFn,
+    block_height_fn: impl Fn() -> BlockHeight + Send + Sync + 'static,
+) -> warp::filters::BoxedFilter<(impl warp::Reply,)>
+where
+    Tx: Serialize + Send + 'static,
+    ReceiptFn: Fn(H256, bool) -> ReceiptFut + Send + Sync + 'static,
+    ReceiptFut: Future<Output = Result<Option<TxReceipt<Tx>>>> + Send + 'static,
+{
+    let receipt_fn = Arc::new(receipt_fn);
+    let block_height_fn = Arc::new(block_height_fn);
+    warp::get()
+        .and(warp::path(CLIENT_RPC_ROUTE_PATH))
+        .and(warp::path(TX_RECEIPT_ROUTE_PATH))
+        .and(warp::path::param::<String>())
+        .and(warp::path::end())
+        .and(warp::query::<TxReceiptParams>())
+        .and(warp::header::optional::<String>("accept"))
+        .and_then(
+            move |tx_hash: String, params: TxReceiptParams, accept: Option<String>| {
+                let json = accepts_json(accept.as_deref());
+                let receipt = parse_h256(&tx_hash, "tx hash")
+                    .map(|tx_hash| receipt_fn(tx_hash, params.include_tx));
+                let block_height_fn = block_height_fn.clone();
+                async move {
+                    receipt?.await.map(|receipt| match receipt {
+                        Some(receipt) => reply_encoded(json, StatusCode::OK, &receipt),
+                        None => reply_encoded(
+                            json,
+                            StatusCode::NOT_FOUND,
+                            &BlockNotFound {
+                                latest_height: block_height_fn(),
+                            },
+                        ),
+                    })
+                }
+                .map_err(|e| warp::reject::custom(ClientRpcServerError(e)))
+            },
+        )
+        .boxed()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use slimchain_chain::block::BlockTxList;
+    use slimchain_utils::{
+        chrono::{TimeZone, Utc},
+        serde::{binary_decode, binary_encode},
+    };
+
+    fn block_server(serve_txs: bool) -> warp::filters::BoxedFilter<(impl warp::Reply,)> {
+        block_query_server(
+            serve_txs,
+            |id, include_txs| async move {
+                let height = match id {
+                    BlockId::Height(height) => height,
+                    BlockId::Hash(hash) if hash == H256::repeat_byte(1) => BlockHeight::from(1),
+                    BlockId::Hash(_) => return Ok(None),
+                };
+                if height > BlockHeight::from(1) {
+                    return Ok(None);
+                }
+                let tx_hash = H256::repeat_byte(2);
+                Ok(Some(BlockQueryResponse {
+                    hash: H256::repeat_byte(1),
+                    header: BlockHeader::new(
+                        height,
+                        H256::zero(),
+                        Utc::now(),
+                        BlockTxList(vec![tx_hash]),
+                        H256::zero(),
+                    ),
+                    txs: if include_txs {
+                        Some(vec![format!("{:?}", tx_hash)])
+                    } else {
+                        None
+                    },
+                    header_only: false,
+                }))
+            },
+            || BlockHeight::from(1),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_block_query() {
+        let route = block_server(true);
+
+        let resp = warp::test::request()
+            .path("/client_rpc/block/height/1")
+            .reply(&route)
+            .await;
+        assert_eq!(StatusCode::OK, resp.status());
+        let block: BlockQueryResponse<String> = binary_decode(resp.body()).unwrap();
+        assert_eq!(H256::repeat_byte(1), block.hash);
+        assert_eq!(BlockHeight::from(1), block.header.height);
+        assert_eq!(vec![H256::repeat_byte(2)], block.header.tx_list.0);
+        assert_eq!(None, block.txs);
+
+        let resp = warp::test::request()
+            .path(&format!(
+                "/client_rpc/block/hash/0x{}?include_txs=true",
+                hex::encode(H256::repeat_byte(1).as_bytes())
+            ))
+            .header("accept", "application/json")
+            .reply(&route)
+            .await;
+        assert_eq!(StatusCode::OK, resp.status());
+        let json_block: BlockQueryResponse<String> = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(block.header, json_block.header);
+        assert_eq!(Some(1), json_block.txs.map(|txs| txs.len()));
+
+        // The tx bodies are not served by the other nodes.
+        let resp = warp::test::request()
+            .path("/client_rpc/block/height/1?include_txs=true")
+            .reply(&block_server(false))
+            .await;
+        let block: BlockQueryResponse<String> = binary_decode(resp.body()).unwrap();
+        assert_eq!(None, block.txs);
+    }
+
+    #[tokio::test]
+    async fn test_block_not_found() {
+        let route = block_server(false);
+
+        let resp = warp::test::request()
+            .path("/client_rpc/block/height/2")
+            .reply(&route)
+            .await;
+        assert_eq!(StatusCode::NOT_FOUND, resp.status());
+        let not_found: BlockNotFound = binary_decode(resp.body()).unwrap();
+        assert_eq!(BlockHeight::from(1), not_found.latest_height);
+
+        let resp = warp::test::request()
+            .path(&format!(
+                "/client_rpc/block/hash/{}",
+                hex::encode(H256::zero().as_bytes())
+            ))
+            .header("accept", "application/json")
+            .reply(&route)
+            .await;
+        assert_eq!(StatusCode::NOT_FOUND, resp.status());
+        let not_found: BlockNotFound = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(BlockHeight::from(1), not_found.latest_height);
+
+        let resp = warp::test::request()
+            .path("/client_rpc/block/hash/xyz")
+            .reply(&route)
+            .await;
+        assert!(!resp.status().is_success());
+    }
+
+    fn receipt_server() -> warp::filters::BoxedFilter<(impl warp::Reply,)> {
+        tx_receipt_server(
+            |tx_hash, include_tx| async move {
+                let txs = vec!["tx0".to_string(), "tx1".to_string()];
+                let tx_list: BlockTxList = txs.iter().map(|tx| tx.to_digest()).collect();
+                let index = match tx_list.iter().position(|&h| h == tx_hash) {
+                    Some(index) => index,
+                    None => return Ok(None),
+                };
+                let header = BlockHeader::new(
+                    BlockHeight::from(1),
+                    H256::zero(),
+                    Utc.timestamp_millis(1000),
+                    tx_list,
+                    H256::repeat_byte(3),
+                );
+                Ok(Some(TxReceipt {
+                    tx_hash,
+                    height: header.height,
+                    header,
+                    index,
+                    tx: if include_tx {
+                        Some(txs[index].clone())
+                    } else {
+                        None
+                    },
+                }))
+            },
+            || BlockHeight::from(1),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_load_pruned_block() {
+        use slimchain_chain::{
+            consensus::raft::Block,
+            db::{read_pool::DEFAULT_READ_THREADS, Transaction, DB},
+        };
+        use slimchain_common::tx::RawTx;
+
+        let db = DB::open_memory(DEFAULT_READ_THREADS);
+        let mut blk1 = Block::genesis_block();
+        blk1.block_header_mut().height = 1.into();
+        blk1.block_header_mut().prev_blk_hash = Block::genesis_block().to_digest();
+        *blk1.tx_list_mut() = BlockTxList(vec![H256::repeat_byte(2)]);
+        let mut blk2 = blk1.clone();
+        blk2.block_header_mut().height = 2.into();
+        blk2.block_header_mut().prev_blk_hash = blk1.to_digest();
+        let mut tx = Transaction::new();
+        tx.insert_block(&blk1).unwrap();
+        tx.insert_block(&blk2).unwrap();
+        tx.insert_latest_block_header(blk2.block_header()).unwrap();
+        tx.prune_block(&blk1).unwrap();
+        tx.insert_pruned_height(1.into()).unwrap();
+        db.write_sync(tx).unwrap();
+
+        let load = |id| load_block_from_db::<Block, RawTx>(db.clone(), id, false);
+        let pruned = load(BlockId::Height(1.into())).await.unwrap().unwrap();
+        assert!(pruned.header_only);
+        assert_eq!(blk1.to_digest(), pruned.hash);
+        assert_eq!(blk1.state_root(), pruned.header.state_root);
+        assert!(pruned.header.tx_list.is_empty());
+        let by_hash = load(BlockId::Hash(blk1.to_digest())).await.unwrap();
+        assert_eq!(Some(pruned), by_hash);
+
+        let full = load(BlockId::Height(2.into())).await.unwrap().unwrap();
+        assert!(!full.header_only);
+        assert_eq!(blk2.block_header(), &full.header);
+        assert_eq!(None, load(BlockId::Height(3.into())).await.unwrap());
+    }
+
+    fn receipt_path(tx_hash: H256, include_tx: bool) -> String {
+        format!(
+            "/client_rpc/tx_receipt/{}?include_tx={}",
+            hex::encode(tx_hash.as_bytes()),
+            include_tx
+        )
+    }
+
+    #[tokio::test]
+    async fn test_tx_receipt() {
+        let route = receipt_server();
+        let tx_hash = "tx1".to_digest();
+
+        let resp = warp::test::request()
+            .path(&receipt_path(tx_hash, true))
+            .reply(&route)
+            .await;
+        assert_eq!(StatusCode::OK, resp.status());
+        let receipt: TxReceipt<String> = binary_decode(resp.body()).unwrap();
+        assert_eq!(1, receipt.index);
+        assert_eq!(Some("tx1".to_string()), receipt.tx);
+        let trusted_header_hash = receipt.header.to_digest();
+        verify_receipt(&receipt, trusted_header_hash).unwrap();
+
+        let resp = warp::test::request()
+            .path(&receipt_path(tx_hash, false))
+            .header("accept", "application/json")
+            .reply(&route)
+            .await;
+        let json_receipt: TxReceipt<String> = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(None, json_receipt.tx);
+        verify_receipt(&json_receipt, trusted_header_hash).unwrap();
+
+        let resp = warp::test::request()
+            .path(&receipt_path(H256::zero(), false))
+            .reply(&route)
+            .await;
+        assert_eq!(StatusCode::NOT_FOUND, resp.status());
+        let not_found: BlockNotFound = binary_decode(resp.body()).unwrap();
+        assert_eq!(BlockHeight::from(1), not_found.latest_height);
+    }
+
+    #[tokio::test]
+    async fn test_tampered_tx_receipt() {
+        let route = receipt_server();
+        let resp = warp::test::request()
+            .path(&receipt_path("tx0".to_digest(), true))
+            .reply(&route)
+            .await;
+        let receipt: TxReceipt<String> = binary_decode(resp.body()).unwrap();
+        let trusted_header_hash = receipt.header.to_digest();
+        verify_receipt(&receipt, trusted_header_hash).unwrap();
+
+        // The tampered proofs.
+        let mut tampered = receipt.clone();
+        tampered.index = 1;
+        assert!(verify_receipt(&tampered, trusted_header_hash).is_err());
+        let mut tampered = receipt.clone();
+        tampered.header.tx_list[0] = "tx2".to_digest();
+        tampered.tx_hash = "tx2".to_digest();
+        assert!(verify_receipt(&tampered, trusted_header_hash).is_err());
+        let mut tampered = receipt.clone();
+        tampered.tx = Some("tx2".to_string());
+        assert!(verify_receipt(&tampered, trusted_header_hash).is_err());
+        let mut tampered = receipt.clone();
+        tampered.height = BlockHeight::from(2);
+        assert!(verify_receipt(&tampered, trusted_header_hash).is_err());
+
+        // The wrong block headers.
+        let mut wrong = receipt.clone();
+        wrong.header.state_root = H256::zero();
+        assert!(verify_receipt(&wrong, trusted_header_hash).is_err());
+        assert!(verify_receipt(&receipt, H256::zero()).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_tx_req_with_invalid_signature() {
+        use slimchain_common::{basic::H160, ed25519::Keypair, tx_req::TxRequest};
+        use std::sync::Mutex;
+
+        let keypair = Keypair::generate(&mut rand::thread_rng());
+        let reqs: Vec<TxHttpRequest> = (0..4u64)
+            .map(|i| {
+                let mut req = TxRequest::Call {
+                    nonce: i.into(),
+                    address: H160::repeat_byte(0xf).into(),
+                    data: b"data".to_vec(),
+                }
+                .sign(&keypair, 0);
+                if i == 2 {
+                    req.input = TxRequest::Create {
+                        nonce: i.into(),
+                        code: b"code".to_vec().into(),
+                    };
+                }
+                TxHttpRequest {
+                    req,
+                    shard_id: ShardId::default(),
+                }
+            })
+            .collect();
+        let invalid_tx_id = reqs[2].req.id();
+
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let route = {
+            let received = received.clone();
+            client_rpc_server(
+                move |reqs: Vec<TxHttpRequest>| {
+                    received.lock().unwrap().extend(reqs);
+                    future::ok(())
+                },
+                || 0,
+                BlockHeight::default,
+            )
+        };
+        let resp = warp::test::request()
+            .method("POST")
+            .path("/client_rpc/tx_req")
+            .body(binary_encode(&reqs).unwrap())
+            .reply(&route)
+            .await;
+        assert_eq!(StatusCode::OK, resp.status());
+
+        let mut expected = reqs;
+        expected.remove(2);
+        assert_eq!(expected, *received.lock().unwrap());
+        assert_eq!(
+            TxStatus::Failed {
+                reason: "invalid_signature".to_string()
+            },
+            TX_STATUS.get(invalid_tx_id)
+        );
+    }
+}
@@ -1,100 +1,620 @@
-use futures::io::Cursor;
+use super::{
+    body_limit::{warp_body_bytes_with_limit, BodyLimitExceeded},
+    config::{
+        BodyLimitConfig, CompressionConfig, HttpClientConfig, NodeRpcAuthConfig,
+        NodeRpcEncodingConfig, PeerId,
+    },
+};
+use once_cell::sync::Lazy;
 use serde::{Deserialize, Serialize};
-use slimchain_common::error::{ensure, Error, Result};
+use slimchain_chain::role::Role;
+use slimchain_common::{
+    collections::HashSet,
+    error::{bail, Error, Result},
+};
 use slimchain_utils::{
     bytes::Bytes,
     serde::{binary_decode, binary_encode},
 };
+use std::{
+    borrow::Cow,
+    io::Read,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
 use warp::{
-    http::{self, HeaderValue, Response, StatusCode},
-    hyper,
+    http::{self, HeaderMap, HeaderValue, Method, Request, Response, StatusCode, Uri},
+    hyper::{self, client::HttpConnector, Body, Client},
     reject::Reject,
-    Filter, Rejection,
+    Filter, Rejection, Reply,
 };
 
-macro_rules! check_resp {
-    ($resp:ident) => {
-        ensure!(
-            $resp.status().is_success(),
-            "Failed to send http req. Status code: {}. Msg: {}.",
-            $resp.status(),
-            $resp
-                .body_string()
-                .await
-                .unwrap_or_else(|e| format!("(Failed to decode http response: {})", e)),
-        );
+/// The HTTP client shared by all the requests, which keeps the connections to the peers alive.
+/// It is created from the global [`HttpClientConfig`] on the first request.
+static HTTP_CLIENT: Lazy<Client<HttpConnector, Body>> =
+    Lazy::new(|| HttpClientConfig::get().build_client());
+
+/// The content encoding of the zstd compressed request bodies.
+const ZSTD_ENCODING: &str = "zstd";
+
+/// The content type of the postcard encoded bodies.
+const BINARY_CONTENT_TYPE: &str = "application/postcard";
+/// The content type of the JSON encoded bodies, which are only accepted by the node RPCs if
+/// enabled in [`NodeRpcEncodingConfig`].
+const JSON_CONTENT_TYPE: &str = "application/json";
+
+/// The authorities of the peers which advertise the support of zstd compressed request bodies
+/// with the `Accept-Encoding` header in their responses.
+static ZSTD_PEERS: Lazy<RwLock<HashSet<String>>> = Lazy::new(|| RwLock::new(HashSet::new()));
+
+fn peer_accepts_zstd(uri: &str) -> bool {
+    match uri
+        .parse::<Uri>()
+        .ok()
+        .and_then(|uri| uri.into_parts().authority)
+    {
+        Some(authority) => ZSTD_PEERS
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .contains(authority.as_str()),
+        None => false,
+    }
+}
+
+fn record_zstd_support(uri: &Uri, headers: &HeaderMap) {
+    let authority = match uri.authority() {
+        Some(authority) => authority.as_str(),
+        None => return,
     };
+    let accepts_zstd = headers
+        .get_all(http::header::ACCEPT_ENCODING)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|encoding| encoding.trim() == ZSTD_ENCODING);
+    if !accepts_zstd {
+        return;
+    }
+    if !ZSTD_PEERS
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .contains(authority)
+    {
+        ZSTD_PEERS
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(authority.to_string());
+    }
+}
+
+/// A binary request body along with its zstd compressed form if it is large enough.
+/// The compressed form is only sent to the peers supporting it. The others, including those
+/// not contacted yet, receive the uncompressed one.
+#[derive(Debug, Clone)]
+pub struct BinaryBody {
+    plain: Bytes,
+    compressed: Option<Bytes>,
+}
+
+impl BinaryBody {
+    /// Compress `plain` according to the global [`CompressionConfig`].
+    pub fn new(plain: Bytes) -> Self {
+        Self::with_config(plain, &HttpClientConfig::get().compression)
+    }
+
+    pub fn with_config(plain: Bytes, cfg: &CompressionConfig) -> Self {
+        let compressed = if cfg.enabled && plain.len() >= cfg.threshold {
+            match zstd::stream::encode_all(&plain[..], cfg.level) {
+                Ok(compressed) if compressed.len() < plain.len() => Some(Bytes::from(compressed)),
+                Ok(_) => None,
+                Err(e) => {
+                    warn!("Failed to compress the request body. Error: {}", e);
+                    None
+                }
+            }
+        } else {
+            None
+        };
+        Self { plain, compressed }
+    }
+
+    pub fn plain(&self) -> &Bytes {
+        &self.plain
+    }
+
+    pub fn compressed(&self) -> Option<&Bytes> {
+        self.compressed.as_ref()
+    }
+}
+
+/// The errors of the network operations, carried in the [`Error`]s of them. Use
+/// [`network_error_of`] and [`is_retryable_error`] to tell them apart.
+#[derive(Debug, thiserror::Error)]
+pub enum NetworkError {
+    #[error("Failed to connect to the peer. Uri: {uri}. Error: {source}.")]
+    Connect {
+        uri: String,
+        #[source]
+        source: hyper::Error,
+    },
+    #[error("Http req timed out after {timeout:?}. Uri: {uri}.")]
+    Timeout { uri: String, timeout: Duration },
+    #[error("Failed to send http req. Status code: {status}. Msg: {msg}.")]
+    HttpStatus { status: StatusCode, msg: String },
+    #[error("Http req body exceeds the limit of the peer. Limit: {limit:?}. Uri: {uri}.")]
+    BodyTooLarge { uri: String, limit: Option<u64> },
+    #[error("Failed to decode the http resp. Uri: {uri}. Error: {msg}.")]
+    Decode { uri: String, msg: String },
+    #[error("Failed to find the peer for role {0}.")]
+    NoPeerForRole(Role),
+    #[error("Failed to get peer address. PeerId: {0}.")]
+    UnknownPeer(PeerId),
+    #[error("Http req is not authorized by the peer. Uri: {uri}.")]
+    Unauthorized { uri: String },
+}
+
+impl NetworkError {
+    /// Whether the same request may succeed if sent again, possibly to another peer. The
+    /// requests rejected by the peer and the missing peers in the route table are not.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Self::Connect { .. } | Self::Timeout { .. } => true,
+            Self::HttpStatus { status, .. } => {
+                status.is_server_error()
+                    || *status == StatusCode::REQUEST_TIMEOUT
+                    || *status == StatusCode::TOO_MANY_REQUESTS
+            }
+            Self::BodyTooLarge { .. }
+            | Self::Decode { .. }
+            | Self::NoPeerForRole(_)
+            | Self::UnknownPeer(_)
+            | Self::Unauthorized { .. } => false,
+        }
+    }
+}
+
+/// The [`NetworkError`] of `err`, if any.
+pub fn network_error_of(err: &Error) -> Option<&NetworkError> {
+    err.downcast_ref::<NetworkError>()
+}
+
+/// Whether the failed operation of `err` may succeed if retried. The errors not classified as
+/// [`NetworkError`] are assumed to be.
+pub fn is_retryable_error(err: &Error) -> bool {
+    network_error_of(err).map_or(true, NetworkError::is_retryable)
+}
+
+/// The body limit in bytes of the peer, if `err` is caused by a request body exceeding it.
+pub fn body_limit_of_error(err: &Error) -> Option<u64> {
+    match network_error_of(err) {
+        Some(NetworkError::BodyTooLarge { limit, .. }) => *limit,
+        _ => None,
+    }
+}
+
+/// Whether `err` is caused by a request exceeding its timeout.
+pub fn is_timeout_error(err: &Error) -> bool {
+    matches!(network_error_of(err), Some(NetworkError::Timeout { .. }))
+}
+
+async fn send_request(
+    method: Method,
+    uri: &str,
+    content_type: Option<&'static str>,
+    content_encoding: Option<&'static str>,
+    body: Body,
+    timeout: Duration,
+) -> Result<Bytes> {
+    let mut builder = Request::builder().method(method).uri(uri);
+    if let Some(content_type) = content_type {
+        builder = builder.header(http::header::CONTENT_TYPE, content_type);
+    }
+    if let Some(content_encoding) = content_encoding {
+        builder = builder.header(http::header::CONTENT_ENCODING, content_encoding);
+    }
+    if let Some(token) = NodeRpcAuthConfig::token() {
+        builder = builder.header(http::header::AUTHORIZATION, bearer_auth(token));
+    }
+    let req = builder.body(body)?;
+    let req_uri = req.uri().clone();
+
+    let connect_error = |source| NetworkError::Connect {
+        uri: uri.to_string(),
+        source,
+    };
+    tokio::time::timeout(timeout, async move {
+        let resp = HTTP_CLIENT.request(req).await.map_err(connect_error)?;
+        record_zstd_support(&req_uri, resp.headers());
+        let status = resp.status();
+        let resp_bytes = hyper::body::to_bytes(resp.into_body())
+            .await
+            .map_err(connect_error)?;
+        if status == StatusCode::PAYLOAD_TOO_LARGE {
+            return Err(NetworkError::BodyTooLarge {
+                uri: uri.to_string(),
+                limit: serde_json::from_slice::<BodyLimitExceeded>(&resp_bytes)
+                    .ok()
+                    .map(|exceeded| exceeded.limit),
+            }
+            .into());
+        }
+        if status == StatusCode::UNAUTHORIZED {
+            return Err(NetworkError::Unauthorized {
+                uri: uri.to_string(),
+            }
+            .into());
+        }
+        if !status.is_success() {
+            return Err(NetworkError::HttpStatus {
+                status,
+                msg: String::from_utf8_lossy(&resp_bytes).into_owned(),
+            }
+            .into());
+        }
+        Ok::<_, Error>(resp_bytes)
+    })
+    .await
+    .map_err(|_| NetworkError::Timeout {
+        uri: uri.to_string(),
+        timeout,
+    })?
+}
+
+fn decode_resp_binary<Resp: for<'de> Deserialize<'de>>(uri: &str, buf: &[u8]) -> Result<Resp> {
+    binary_decode(buf).map_err(|e| {
+        NetworkError::Decode {
+            uri: uri.to_string(),
+            msg: e.to_string(),
+        }
+        .into()
+    })
+}
+
+fn decode_resp_json<Resp: for<'de> Deserialize<'de>>(uri: &str, buf: &[u8]) -> Result<Resp> {
+    serde_json::from_slice(buf).map_err(|e| {
+        NetworkError::Decode {
+            uri: uri.to_string(),
+            msg: e.to_string(),
+        }
+        .into()
+    })
+}
+
+fn default_timeout() -> Duration {
+    HttpClientConfig::get().request_timeout
 }
 
 pub async fn send_get_request_using_json<Resp: for<'de> Deserialize<'de>>(
     uri: &str,
 ) -> Result<Resp> {
-    let mut resp = surf::get(uri).await.map_err(Error::msg)?;
-    check_resp!(resp);
-    resp.body_json().await.map_err(Error::msg)
+    send_get_request_using_json_with_timeout(uri, default_timeout()).await
+}
+
+pub async fn send_get_request_using_json_with_timeout<Resp: for<'de> Deserialize<'de>>(
+    uri: &str,
+    timeout: Duration,
+) -> Result<Resp> {
+    let resp_bytes = send_request(Method::GET, uri, None, None, Body::empty(), timeout).await?;
+    decode_resp_json(uri, &resp_bytes)
 }
 
 pub async fn send_post_request_using_json<Req: Serialize, Resp: for<'de> Deserialize<'de>>(
     uri: &str,
     req: &Req,
 ) -> Result<Resp> {
-    let mut resp = surf::post(uri)
-        .body(surf::Body::from_json(&req).map_err(Error::msg)?)
-        .await
-        .map_err(Error::msg)?;
-    check_resp!(resp);
-    resp.body_json().await.map_err(Error::msg)
+    let body = Body::from(serde_json::to_vec(req)?);
+    let resp_bytes = send_request(
+        Method::POST,
+        uri,
+        Some(JSON_CONTENT_TYPE),
+        None,
+        body,
+        default_timeout(),
+    )
+    .await?;
+    decode_resp_json(uri, &resp_bytes)
 }
 
 pub async fn send_get_request_using_binary<Resp: for<'de> Deserialize<'de>>(
     uri: &str,
 ) -> Result<Resp> {
-    let mut resp = surf::get(uri).await.map_err(Error::msg)?;
-    check_resp!(resp);
-    let resp_bytes = resp.body_bytes().await.map_err(Error::msg)?;
-    binary_decode(&resp_bytes)
+    let resp_bytes = send_request(
+        Method::GET,
+        uri,
+        None,
+        None,
+        Body::empty(),
+        default_timeout(),
+    )
+    .await?;
+    decode_resp_binary(uri, &resp_bytes)
 }
 
 pub async fn send_post_request_using_binary<Req: Serialize, Resp: for<'de> Deserialize<'de>>(
     uri: &str,
     req: &Req,
 ) -> Result<Resp> {
-    let mut resp = surf::post(uri)
-        .body(surf::Body::from_bytes(binary_encode(req)?))
-        .await
-        .map_err(Error::msg)?;
-    check_resp!(resp);
-    let resp_bytes = resp.body_bytes().await.map_err(Error::msg)?;
-    binary_decode(&resp_bytes)
+    send_post_request_using_binary_with_timeout(uri, req, default_timeout()).await
+}
+
+pub async fn send_post_request_using_binary_with_timeout<
+    Req: Serialize,
+    Resp: for<'de> Deserialize<'de>,
+>(
+    uri: &str,
+    req: &Req,
+    timeout: Duration,
+) -> Result<Resp> {
+    send_post_request_using_binary_bytes_with_timeout(
+        uri,
+        Bytes::from(binary_encode(req)?),
+        timeout,
+    )
+    .await
 }
 
 pub async fn send_post_request_using_binary_bytes<Resp: for<'de> Deserialize<'de>>(
     uri: &str,
     req: Bytes,
 ) -> Result<Resp> {
-    let req_len = req.len();
-    let mut resp = surf::post(uri)
-        .body(surf::Body::from_reader(Cursor::new(req), Some(req_len)))
-        .await
-        .map_err(Error::msg)?;
-    check_resp!(resp);
-    let resp_bytes = resp.body_bytes().await.map_err(Error::msg)?;
-    binary_decode(&resp_bytes)
+    send_post_request_using_binary_bytes_with_timeout(uri, req, default_timeout()).await
+}
+
+pub async fn send_post_request_using_binary_bytes_with_timeout<Resp: for<'de> Deserialize<'de>>(
+    uri: &str,
+    req: Bytes,
+    timeout: Duration,
+) -> Result<Resp> {
+    let resp_bytes = send_request(
+        Method::POST,
+        uri,
+        Some(BINARY_CONTENT_TYPE),
+        None,
+        Body::from(req),
+        timeout,
+    )
+    .await?;
+    decode_resp_binary(uri, &resp_bytes)
+}
+
+/// Send `body` compressed if the peer supports it, or uncompressed otherwise.
+pub async fn send_post_request_using_binary_body_with_timeout<Resp: for<'de> Deserialize<'de>>(
+    uri: &str,
+    body: &BinaryBody,
+    timeout: Duration,
+) -> Result<Resp> {
+    let (req, content_encoding) = match body.compressed() {
+        Some(compressed) if peer_accepts_zstd(uri) => (compressed.clone(), Some(ZSTD_ENCODING)),
+        _ => (body.plain().clone(), None),
+    };
+    let resp_bytes = send_request(
+        Method::POST,
+        uri,
+        Some(BINARY_CONTENT_TYPE),
+        content_encoding,
+        Body::from(req),
+        timeout,
+    )
+    .await?;
+    decode_resp_binary(uri, &resp_bytes)
+}
+
+fn bearer_auth(token: &str) -> String {
+    format!("Bearer {}", token)
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
 }
 
 #[derive(Debug)]
-struct PostcardDecodeError(Error);
+struct Unauthorized;
+
+impl Reject for Unauthorized {}
+
+/// Check the `Authorization` header of the node RPCs against the token in the global
+/// [`NodeRpcAuthConfig`]. Use [`recover_unauthorized`] to reply the rejected requests with 401.
+pub fn warp_node_rpc_auth() -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp_node_rpc_auth_with_token(NodeRpcAuthConfig::token().map(String::from))
+}
+
+/// Check the `Authorization` header against `token`. Everything passes if `token` is `None`.
+pub fn warp_node_rpc_auth_with_token(
+    token: Option<String>,
+) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    let expected = token.map(|token| Arc::new(bearer_auth(&token)));
+    warp::header::optional::<String>("authorization")
+        .and_then(move |auth: Option<String>| {
+            let expected = expected.clone();
+            async move {
+                match expected {
+                    Some(expected)
+                        if !auth.map_or(false, |auth| {
+                            constant_time_eq(auth.as_bytes(), expected.as_bytes())
+                        }) =>
+                    {
+                        Err(warp::reject::custom(Unauthorized))
+                    }
+                    _ => Ok(()),
+                }
+            }
+        })
+        .untuple_one()
+}
+
+pub async fn recover_unauthorized(
+    rejection: Rejection,
+) -> std::result::Result<StatusCode, Rejection> {
+    if rejection.find::<Unauthorized>().is_some() {
+        Ok(StatusCode::UNAUTHORIZED)
+    } else {
+        Err(rejection)
+    }
+}
+
+#[derive(Debug)]
+struct BodyDecodeError(Error);
+
+impl Reject for BodyDecodeError {}
+
+/// The encoding of the node RPC bodies.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum RpcEncoding {
+    /// Postcard, which is used between the nodes.
+    Binary,
+    /// JSON, which is only meant for debugging, e.g., with curl.
+    Json,
+}
 
-impl Reject for PostcardDecodeError {}
+impl RpcEncoding {
+    fn from_content_type(content_type: Option<&str>) -> Self {
+        match content_type {
+            Some(content_type)
+                if content_type
+                    .split(';')
+                    .next()
+                    .map_or(false, |media_type| media_type.trim() == JSON_CONTENT_TYPE) =>
+            {
+                RpcEncoding::Json
+            }
+            _ => RpcEncoding::Binary,
+        }
+    }
+}
+
+fn decompress_body<'a>(
+    content_encoding: Option<&str>,
+    buf: &'a [u8],
+    limit: u64,
+) -> Result<Cow<'a, [u8]>> {
+    match content_encoding {
+        None | Some("identity") => Ok(Cow::Borrowed(buf)),
+        Some(ZSTD_ENCODING) => {
+            let mut decoded = Vec::new();
+            zstd::stream::read::Decoder::new(buf)?
+                .take(limit + 1)
+                .read_to_end(&mut decoded)?;
+            if decoded.len() as u64 > limit {
+                return Err(BodyLimitExceeded { limit }.into());
+            }
+            Ok(Cow::Owned(decoded))
+        }
+        Some(encoding) => bail!("Unsupported content encoding: {}.", encoding),
+    }
+}
+
+fn decode_body<T: for<'de> Deserialize<'de>>(
+    encoding: RpcEncoding,
+    content_encoding: Option<&str>,
+    buf: &[u8],
+    limit: u64,
+) -> Result<T> {
+    let buf = decompress_body(content_encoding, buf, limit)?;
+    match encoding {
+        RpcEncoding::Binary => binary_decode(&buf),
+        RpcEncoding::Json => serde_json::from_slice(&buf).map_err(Error::msg),
+    }
+}
+
+fn decode_binary_body<T: for<'de> Deserialize<'de>>(
+    content_encoding: Option<&str>,
+    buf: &[u8],
+) -> Result<T> {
+    decode_body(
+        RpcEncoding::Binary,
+        content_encoding,
+        buf,
+        BodyLimitConfig::get().default,
+    )
+}
+
+fn body_decode_rejection(err: Error) -> Rejection {
+    debug!("request decode body error: {}", err);
+    match err.downcast::<BodyLimitExceeded>() {
+        Ok(exceeded) => warp::reject::custom(exceeded),
+        Err(err) => warp::reject::custom(BodyDecodeError(err)),
+    }
+}
 
+/// Decode the binary request body, which may be zstd compressed as set in the
+/// `Content-Encoding` header. It is limited to the `default` size in the global
+/// [`BodyLimitConfig`].
 pub fn warp_body_binary<T: for<'de> Deserialize<'de> + Send>(
 ) -> impl Filter<Extract = (T,), Error = Rejection> + Copy {
-    warp::filters::body::bytes().and_then(|buf: Bytes| async move {
-        binary_decode(buf.as_ref()).map_err(|err| {
-            debug!("request decode body error: {}", err);
-            warp::reject::custom(PostcardDecodeError(err))
-        })
-    })
+    warp_body_binary_with_limit(BodyLimitConfig::get().default)
+}
+
+/// Like [`warp_body_binary`], but limited to `limit` bytes both before and after the
+/// decompression. Use [`recover_body_limit`] to reply the rejected requests with 413.
+pub fn warp_body_binary_with_limit<T: for<'de> Deserialize<'de> + Send>(
+    limit: u64,
+) -> impl Filter<Extract = (T,), Error = Rejection> + Copy {
+    warp::header::optional::<String>("content-encoding")
+        .and(warp_body_bytes_with_limit(limit))
+        .and_then(
+            move |content_encoding: Option<String>, buf: Bytes| async move {
+                decode_body(
+                    RpcEncoding::Binary,
+                    content_encoding.as_deref(),
+                    buf.as_ref(),
+                    limit,
+                )
+                .map_err(body_decode_rejection)
+            },
+        )
+}
+
+/// Like [`warp_body_binary`], but also decode the JSON request body if its `Content-Type` is
+/// `application/json` and it is enabled in the global [`NodeRpcEncodingConfig`]. Reply it
+/// with [`warp_reply_encoded`] in the same encoding.
+pub fn warp_body_encoded<T: for<'de> Deserialize<'de> + Send>(
+) -> impl Filter<Extract = (RpcEncoding, T), Error = Rejection> + Copy {
+    warp_body_encoded_with_limit(BodyLimitConfig::get().default)
+}
+
+/// Like [`warp_body_encoded`], but limited to `limit` bytes as [`warp_body_binary_with_limit`].
+pub fn warp_body_encoded_with_limit<T: for<'de> Deserialize<'de> + Send>(
+    limit: u64,
+) -> impl Filter<Extract = (RpcEncoding, T), Error = Rejection> + Copy {
+    warp_body_encoded_with_json(NodeRpcEncodingConfig::allow_json(), limit)
+}
+
+#[derive(Debug)]
+struct JsonNotAllowed;
+
+impl Reject for JsonNotAllowed {}
+
+async fn decode_encoded_body<T: for<'de> Deserialize<'de>>(
+    allow_json: bool,
+    limit: u64,
+    content_type: Option<String>,
+    content_encoding: Option<String>,
+    buf: Bytes,
+) -> std::result::Result<(RpcEncoding, T), Rejection> {
+    let encoding = RpcEncoding::from_content_type(content_type.as_deref());
+    if encoding == RpcEncoding::Json && !allow_json {
+        return Err(warp::reject::custom(JsonNotAllowed));
+    }
+    decode_body(encoding, content_encoding.as_deref(), buf.as_ref(), limit)
+        .map(|val| (encoding, val))
+        .map_err(body_decode_rejection)
+}
+
+/// Like [`warp_body_encoded_with_limit`], but the JSON request body is accepted only if
+/// `allow_json` is set.
+pub fn warp_body_encoded_with_json<T: for<'de> Deserialize<'de> + Send>(
+    allow_json: bool,
+    limit: u64,
+) -> impl Filter<Extract = (RpcEncoding, T), Error = Rejection> + Copy {
+    warp::header::optional::<String>("content-type")
+        .and(warp::header::optional::<String>("content-encoding"))
+        .and(warp_body_bytes_with_limit(limit))
+        .and_then(
+            move |content_type: Option<String>, content_encoding: Option<String>, buf: Bytes| {
+                decode_encoded_body(allow_json, limit, content_type, content_encoding, buf)
+            },
+        )
+        .untuple_one()
 }
 
 pub fn warp_reply_binary<T: Serialize>(val: &T) -> impl warp::Reply {
@@ -103,7 +623,12 @@ pub fn warp_reply_binary<T: Serialize>(val: &T) -> impl warp::Reply {
             let mut resp = Response::new(hyper::Body::from(buf));
             resp.headers_mut().insert(
                 http::header::CONTENT_TYPE,
-                HeaderValue::from_static("application/octet-stream"),
+                HeaderValue::from_static(BINARY_CONTENT_TYPE),
+            );
+            // Advertise that the zstd compressed request bodies are accepted.
+            resp.headers_mut().insert(
+                http::header::ACCEPT_ENCODING,
+                HeaderValue::from_static(ZSTD_ENCODING),
             );
             resp
         }
@@ -115,3 +640,431 @@ pub fn warp_reply_binary<T: Serialize>(val: &T) -> impl warp::Reply {
         }
     }
 }
+
+/// Reply `val` in `encoding`, i.e., the one of the request body.
+pub fn warp_reply_encoded<T: Serialize>(encoding: RpcEncoding, val: &T) -> warp::reply::Response {
+    match encoding {
+        RpcEncoding::Binary => warp_reply_binary(val).into_response(),
+        RpcEncoding::Json => warp::reply::json(val).into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use slimchain_chain::{
+        block_proposal::{BlockProposal, BlockProposalTrie},
+        consensus::raft::Block,
+        genesis::GenesisConfig,
+    };
+    use slimchain_common::{
+        basic::{Address, BlockHeight, H160, H256},
+        error::anyhow,
+        rw_set::{TxReadSet, TxWriteData},
+        tx::RawTx,
+        tx_req::TxRequest,
+    };
+    use std::{
+        convert::Infallible,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc, Mutex,
+        },
+    };
+    use warp::hyper::service::make_service_fn;
+
+    #[tokio::test]
+    async fn test_connection_reuse() {
+        let accepted = Arc::new(AtomicUsize::new(0));
+        let route = warp::post()
+            .and(warp_body_binary())
+            .map(|x: u64| warp_reply_binary(&(x + 1)));
+        let svc = warp::service(route);
+        let make_svc = {
+            let accepted = accepted.clone();
+            make_service_fn(move |_| {
+                accepted.fetch_add(1, Ordering::SeqCst);
+                let svc = svc.clone();
+                async move { Ok::<_, Infallible>(svc) }
+            })
+        };
+        let srv = hyper::Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_svc);
+        let uri = format!("http://{}/", srv.local_addr());
+        let srv_handle = tokio::spawn(srv);
+
+        let resp: u64 = send_post_request_using_binary(&uri, &1u64).await.unwrap();
+        assert_eq!(2, resp);
+        let resp: u64 = send_post_request_using_binary(&uri, &2u64).await.unwrap();
+        assert_eq!(3, resp);
+        assert_eq!(1, accepted.load(Ordering::SeqCst));
+
+        srv_handle.abort();
+    }
+
+    #[tokio::test]
+    async fn test_timeout_error() {
+        let route = warp::post()
+            .and(warp_body_binary())
+            .and_then(|x: u64| async move {
+                tokio::time::sleep(Duration::from_millis(x)).await;
+                Ok::<_, Rejection>(warp_reply_binary(&x))
+            });
+        let not_found = warp::get().map(|| StatusCode::NOT_FOUND);
+        let (addr, srv) = warp::serve(route.or(not_found)).bind_ephemeral(([127, 0, 0, 1], 0));
+        let uri = format!("http://{}/", addr);
+        let srv_handle = tokio::spawn(srv);
+
+        let resp: u64 =
+            send_post_request_using_binary_with_timeout(&uri, &0u64, Duration::from_secs(5))
+                .await
+                .unwrap();
+        assert_eq!(0, resp);
+
+        let err = send_post_request_using_binary_with_timeout::<_, u64>(
+            &uri,
+            &500u64,
+            Duration::from_millis(50),
+        )
+        .await
+        .unwrap_err();
+        assert!(is_timeout_error(&err));
+
+        let err = send_get_request_using_binary::<u64>(&uri)
+            .await
+            .unwrap_err();
+        assert!(!is_timeout_error(&err));
+        assert!(matches!(
+            err.downcast_ref::<NetworkError>(),
+            Some(NetworkError::HttpStatus { status, .. }) if *status == StatusCode::NOT_FOUND
+        ));
+
+        srv_handle.abort();
+    }
+
+    #[tokio::test]
+    async fn test_network_error_classification() {
+        let unauthorized = warp::path("unauthorized").map(|| StatusCode::UNAUTHORIZED);
+        let unavailable = warp::path("unavailable").map(|| StatusCode::SERVICE_UNAVAILABLE);
+        let garbage = warp::path("garbage").map(|| "garbage");
+        let (addr, srv) = warp::serve(unauthorized.or(unavailable).or(garbage))
+            .bind_ephemeral(([127, 0, 0, 1], 0));
+        let srv_handle = tokio::spawn(srv);
+
+        let err = send_get_request_using_json::<u64>(&format!("http://{}/unauthorized", addr))
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            network_error_of(&err),
+            Some(NetworkError::Unauthorized { .. })
+        ));
+        assert!(!is_retryable_error(&err));
+
+        let err = send_get_request_using_json::<u64>(&format!("http://{}/unavailable", addr))
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            network_error_of(&err),
+            Some(NetworkError::HttpStatus { status, .. }) if status.is_server_error()
+        ));
+        assert!(is_retryable_error(&err));
+
+        let err = send_get_request_using_json::<u64>(&format!("http://{}/garbage", addr))
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            network_error_of(&err),
+            Some(NetworkError::Decode { .. })
+        ));
+        assert!(!is_retryable_error(&err));
+
+        srv_handle.abort();
+        srv_handle.await.ok();
+        let err = send_get_request_using_json::<u64>(&format!("http://{}/garbage", addr))
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            network_error_of(&err),
+            Some(NetworkError::Connect { .. })
+        ));
+        assert!(is_retryable_error(&err));
+    }
+
+    #[test]
+    fn test_is_retryable() {
+        let status_error = |status| NetworkError::HttpStatus {
+            status,
+            msg: String::new(),
+        };
+        assert!(status_error(StatusCode::INTERNAL_SERVER_ERROR).is_retryable());
+        assert!(status_error(StatusCode::TOO_MANY_REQUESTS).is_retryable());
+        assert!(status_error(StatusCode::REQUEST_TIMEOUT).is_retryable());
+        assert!(!status_error(StatusCode::BAD_REQUEST).is_retryable());
+        assert!(!status_error(StatusCode::NOT_FOUND).is_retryable());
+        assert!(NetworkError::Timeout {
+            uri: String::new(),
+            timeout: Duration::from_secs(1),
+        }
+        .is_retryable());
+        assert!(!NetworkError::NoPeerForRole(Role::Client).is_retryable());
+        assert!(!NetworkError::UnknownPeer(PeerId(1)).is_retryable());
+        assert!(is_retryable_error(&anyhow!("Broken pipe.")));
+    }
+
+    #[tokio::test]
+    async fn test_compressed_block_proposal() {
+        // The payloads are repeated across the txs, but too far apart for the snappy framing
+        // used by the binary encoding to catch.
+        let payloads: Vec<Vec<u8>> = (0..64)
+            .map(|_| (0..4096).map(|_| rand::random()).collect())
+            .collect();
+        let txs: Vec<RawTx> = (0..1024u64)
+            .map(|i| RawTx {
+                caller: Address::default(),
+                input: TxRequest::Call {
+                    nonce: i.into(),
+                    address: H160::repeat_byte(0xf).into(),
+                    data: payloads[rand::random::<usize>() % payloads.len()].clone(),
+                },
+                block_height: 1.into(),
+                state_root: H256::zero(),
+                reads: TxReadSet::default(),
+                writes: TxWriteData::default(),
+                gas_used: 0,
+                fee: 0,
+            })
+            .collect();
+        let block = Block::genesis_from_config(&GenesisConfig::default(), H256::zero());
+        let proposal = BlockProposal::new(block, txs, BlockProposalTrie::Diff(Default::default()));
+        let plain = Bytes::from(binary_encode(&proposal).unwrap());
+        assert!(plain.len() > 2 * 1024 * 1024);
+
+        let cfg = CompressionConfig {
+            enabled: true,
+            ..Default::default()
+        };
+        let body = BinaryBody::with_config(plain.clone(), &cfg);
+        assert!(body.compressed().is_some());
+
+        let wire_sizes = Arc::new(Mutex::new(Vec::new()));
+        let route = {
+            let wire_sizes = wire_sizes.clone();
+            warp::post()
+                .and(warp::header::optional::<String>("content-encoding"))
+                .and(warp::body::bytes())
+                .map(move |content_encoding: Option<String>, buf: Bytes| {
+                    wire_sizes.lock().unwrap().push(buf.len());
+                    let proposal: BlockProposal<Block, RawTx> =
+                        decode_binary_body(content_encoding.as_deref(), &buf).unwrap();
+                    warp_reply_binary(&proposal)
+                })
+        };
+        let (addr, srv) = warp::serve(route).bind_ephemeral(([127, 0, 0, 1], 0));
+        let uri = format!("http://{}/", addr);
+        let srv_handle = tokio::spawn(srv);
+
+        // The peer has not advertised the support yet, so the first request is uncompressed.
+        let resp: BlockProposal<Block, RawTx> =
+            send_post_request_using_binary_body_with_timeout(&uri, &body, Duration::from_secs(30))
+                .await
+                .unwrap();
+        assert_eq!(proposal, resp);
+        let resp: BlockProposal<Block, RawTx> =
+            send_post_request_using_binary_body_with_timeout(&uri, &body, Duration::from_secs(30))
+                .await
+                .unwrap();
+        assert_eq!(proposal, resp);
+
+        let wire_sizes = wire_sizes.lock().unwrap().clone();
+        assert_eq!(
+            vec![plain.len(), body.compressed().unwrap().len()],
+            wire_sizes
+        );
+        assert!(wire_sizes[1] < wire_sizes[0]);
+
+        srv_handle.abort();
+    }
+
+    #[test]
+    fn test_decode_binary_body() {
+        let plain = binary_encode(&vec![1u64; 1024]).unwrap();
+        let compressed = zstd::stream::encode_all(&plain[..], 3).unwrap();
+        assert_eq!(
+            vec![1u64; 1024],
+            decode_binary_body::<Vec<u64>>(Some(ZSTD_ENCODING), &compressed).unwrap()
+        );
+        assert_eq!(
+            vec![1u64; 1024],
+            decode_binary_body::<Vec<u64>>(None, &plain).unwrap()
+        );
+        assert!(decode_binary_body::<Vec<u64>>(Some("gzip"), &plain).is_err());
+
+        // Compression is skipped if disabled or below the threshold.
+        let plain = Bytes::from(plain);
+        let body = BinaryBody::with_config(plain.clone(), &CompressionConfig::default());
+        assert!(body.compressed().is_none());
+        let cfg = CompressionConfig {
+            enabled: true,
+            threshold: plain.len() + 1,
+            level: 3,
+        };
+        let body = BinaryBody::with_config(plain, &cfg);
+        assert!(body.compressed().is_none());
+    }
+
+    fn encoded_block_import_route(
+        allow_json: bool,
+        received: Arc<Mutex<Vec<BlockProposal<Block, RawTx>>>>,
+    ) -> impl Filter<Extract = (warp::reply::Response,), Error = Rejection> + Clone {
+        warp::post()
+            .and(warp_body_encoded_with_json(allow_json))
+            .map(
+                move |encoding, block_proposals: Vec<BlockProposal<Block, RawTx>>| {
+                    let heights: Vec<_> = block_proposals
+                        .iter()
+                        .map(|blk| blk.get_block_height())
+                        .collect();
+                    received.lock().unwrap().extend(block_proposals);
+                    warp_reply_encoded(encoding, &heights)
+                },
+            )
+    }
+
+    fn small_block_proposals() -> Vec<BlockProposal<Block, RawTx>> {
+        (0..2u64)
+            .map(|i| {
+                let txs = vec![RawTx {
+                    caller: Address::default(),
+                    input: TxRequest::Call {
+                        nonce: i.into(),
+                        address: H160::repeat_byte(0xf).into(),
+                        data: vec![1, 2, 3],
+                    },
+                    block_height: i.into(),
+                    state_root: H256::zero(),
+                    reads: TxReadSet::default(),
+                    writes: TxWriteData::default(),
+                    gas_used: 0,
+                    fee: 0,
+                }];
+                let block = Block::genesis_from_config(&GenesisConfig::default(), H256::zero());
+                BlockProposal::new(block, txs, BlockProposalTrie::Diff(Default::default()))
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_encoded_body() {
+        let proposals = small_block_proposals();
+        let binary_received = Arc::new(Mutex::new(Vec::new()));
+        let json_received = Arc::new(Mutex::new(Vec::new()));
+
+        let resp = warp::test::request()
+            .method("POST")
+            .header("content-type", BINARY_CONTENT_TYPE)
+            .body(binary_encode(&proposals).unwrap())
+            .reply(&encoded_block_import_route(true, binary_received.clone()))
+            .await;
+        assert_eq!(StatusCode::OK, resp.status());
+        assert_eq!(BINARY_CONTENT_TYPE, resp.headers()["content-type"]);
+        let binary_resp: Vec<BlockHeight> = binary_decode(resp.body()).unwrap();
+
+        let resp = warp::test::request()
+            .method("POST")
+            .header("content-type", "application/json; charset=utf-8")
+            .body(serde_json::to_vec(&proposals).unwrap())
+            .reply(&encoded_block_import_route(true, json_received.clone()))
+            .await;
+        assert_eq!(StatusCode::OK, resp.status());
+        assert_eq!(JSON_CONTENT_TYPE, resp.headers()["content-type"]);
+        let json_resp: Vec<BlockHeight> = serde_json::from_slice(resp.body()).unwrap();
+
+        assert_eq!(binary_resp, json_resp);
+        assert_eq!(proposals, *binary_received.lock().unwrap());
+        assert_eq!(proposals, *json_received.lock().unwrap());
+
+        // The body without the content type, e.g., from the older peers, is binary.
+        let resp = warp::test::request()
+            .method("POST")
+            .body(binary_encode(&proposals).unwrap())
+            .reply(&encoded_block_import_route(false, Default::default()))
+            .await;
+        assert_eq!(StatusCode::OK, resp.status());
+        assert_eq!(
+            binary_resp,
+            binary_decode::<Vec<BlockHeight>>(resp.body()).unwrap()
+        );
+
+        // JSON is rejected unless allowed.
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let resp = warp::test::request()
+            .method("POST")
+            .header("content-type", JSON_CONTENT_TYPE)
+            .body(serde_json::to_vec(&proposals).unwrap())
+            .reply(&encoded_block_import_route(false, received.clone()))
+            .await;
+        assert!(!resp.status().is_success());
+        assert!(received.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_encoded_body_round_trip() {
+        let proposals = small_block_proposals();
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let route = encoded_block_import_route(true, received.clone());
+        let (addr, srv) = warp::serve(route).bind_ephemeral(([127, 0, 0, 1], 0));
+        let uri = format!("http://{}/", addr);
+        let srv_handle = tokio::spawn(srv);
+
+        let binary_resp: Vec<BlockHeight> = send_post_request_using_binary(&uri, &proposals)
+            .await
+            .unwrap();
+        let binary_received = std::mem::take(&mut *received.lock().unwrap());
+        let json_resp: Vec<BlockHeight> = send_post_request_using_json(&uri, &proposals)
+            .await
+            .unwrap();
+        let json_received = std::mem::take(&mut *received.lock().unwrap());
+
+        assert_eq!(binary_resp, json_resp);
+        assert_eq!(binary_received, json_received);
+        assert_eq!(proposals, json_received);
+
+        srv_handle.abort();
+    }
+
+    #[tokio::test]
+    async fn test_node_rpc_auth() {
+        let route = warp::path("rpc")
+            .and(warp_node_rpc_auth_with_token(Some("secret".into())))
+            .map(|| "ok")
+            .recover(recover_unauthorized);
+
+        let resp = warp::test::request().path("/rpc").reply(&route).await;
+        assert_eq!(StatusCode::UNAUTHORIZED, resp.status());
+
+        let resp = warp::test::request()
+            .path("/rpc")
+            .header("authorization", "Bearer wrong")
+            .reply(&route)
+            .await;
+        assert_eq!(StatusCode::UNAUTHORIZED, resp.status());
+
+        let resp = warp::test::request()
+            .path("/rpc")
+            .header("authorization", bearer_auth("secret"))
+            .reply(&route)
+            .await;
+        assert_eq!(StatusCode::OK, resp.status());
+        assert_eq!(&b"ok"[..], resp.body());
+
+        // Other rejections are not affected.
+        let resp = warp::test::request().path("/other").reply(&route).await;
+        assert_eq!(StatusCode::NOT_FOUND, resp.status());
+
+        // Without the token, every request passes.
+        let route = warp::path("rpc")
+            .and(warp_node_rpc_auth_with_token(None))
+            .map(|| "ok");
+        let resp = warp::test::request().path("/rpc").reply(&route).await;
+        assert_eq!(StatusCode::OK, resp.status());
+    }
+}
@@ -1,12 +1,27 @@
+use crate::http::{
+    common::NetworkError, node_rpc::MAX_BLOCK_PROPOSALS_PER_IMPORT, route_table::RouteTableUpdate,
+};
+use once_cell::sync::OnceCell;
 use rand::seq::IteratorRandom;
-use serde::{Deserialize, Serialize};
+use serde::{de::Error as DeError, Deserialize, Deserializer, Serialize};
 use slimchain_chain::role::Role;
 use slimchain_common::{
-    collections::HashMap,
-    error::{anyhow, Result},
+    collections::{HashMap, HashSet},
+    ed25519::PublicKey,
+    error::{anyhow, ensure, Result},
     utils::derive_more,
 };
-use std::sync::Arc;
+use slimchain_utils::{
+    backpressure::Pipeline,
+    config::{join_path, ConfigErrors, ValidateConfig},
+    rng::rng,
+};
+use std::{
+    path::{Component, Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+use warp::hyper::{client::HttpConnector, Body, Client};
 
 #[derive(
     Debug,
@@ -40,12 +55,698 @@ pub struct NetworkConfig {
     /// Known peers
     #[serde(default = "Vec::new")]
     pub peers: Vec<PeerConfig>,
+
+    /// The HTTP client used to send requests to the peers
+    #[serde(default)]
+    pub http_client: HttpClientConfig,
+
+    /// Timeouts of the node RPC calls
+    #[serde(default)]
+    pub timeout: RpcTimeoutConfig,
+
+    /// How to broadcast the block proposals to the peers
+    #[serde(default)]
+    pub broadcast: BroadcastConfig,
+
+    /// How to authenticate the node RPCs between the peers
+    #[serde(default)]
+    pub auth: NodeRpcAuthConfig,
+
+    /// Which encodings are accepted by the node RPCs
+    #[serde(default)]
+    pub node_rpc_encoding: NodeRpcEncodingConfig,
+
+    /// The counters of the node RPCs to each peer
+    #[serde(default)]
+    pub rpc_metrics: RpcMetricsConfig,
+
+    /// How to update the known peers at runtime
+    #[serde(default)]
+    pub route_update: RouteUpdateConfig,
+
+    /// How the client nodes probe the liveness of the peers
+    #[serde(default)]
+    pub health_check: HealthCheckConfig,
+
+    /// How the peers are checked to be compatible before the node RPCs
+    #[serde(default)]
+    pub node_info: NodeInfoConfig,
+
+    /// How to limit the tx submissions to the client nodes
+    #[serde(default)]
+    pub tx_rate_limit: RateLimitConfig,
+
+    /// The CORS headers of the client-facing routes
+    #[serde(default)]
+    pub cors: CorsConfig,
+
+    /// How large and how slow the node RPC request bodies can be
+    #[serde(default)]
+    pub body_limit: BodyLimitConfig,
+
+    /// How to serve the WebSocket subscriptions of the committed blocks and txs
+    #[serde(default)]
+    pub ws_subscribe: WsSubscribeConfig,
+
+    /// How the HTTP server is shut down
+    #[serde(default)]
+    pub shutdown: ShutdownConfig,
+
+    /// How the signatures of the incoming tx requests are verified
+    #[serde(default)]
+    pub tx_verify: TxVerifyConfig,
+
+    /// How many items may queue between the stages of the node pipeline
+    #[serde(default)]
+    pub backpressure: BackpressureConfig,
+
+    /// How the storage nodes fetch the state nodes missing locally while executing the txs
+    #[serde(default)]
+    pub state_fetch: StateFetchConfig,
+
+    /// How the storage nodes check the nonces of the incoming tx requests
+    #[serde(default)]
+    pub nonce_check: NonceCheckConfig,
+
+    /// Where the storage nodes write the backups requested by the admins
+    #[serde(default)]
+    pub backup: BackupConfig,
 }
 
 fn default_http_listen() -> String {
     "127.0.0.1:8000".into()
 }
 
+impl ValidateConfig for NetworkConfig {
+    fn validate_at(&self, path: &str, errors: &mut ConfigErrors) {
+        self.body_limit
+            .validate_at(&join_path(path, "body_limit"), errors);
+        errors.ensure(
+            self.tx_verify.max_batch_size >= 1,
+            &join_path(path, "tx_verify.max_batch_size"),
+            "Should be at least 1.",
+        );
+        errors.ensure(
+            self.backpressure.retry_after > Duration::from_millis(0),
+            &join_path(path, "backpressure.retry_after"),
+            "Should be positive.",
+        );
+        errors.ensure(
+            self.state_fetch.timeout > Duration::from_millis(0),
+            &join_path(path, "state_fetch.timeout"),
+            "Should be positive.",
+        );
+        if self.nonce_check.enabled && self.nonce_check.gap_policy == NonceGapPolicy::Park {
+            errors.ensure(
+                self.nonce_check.max_parked >= 1,
+                &join_path(path, "nonce_check.max_parked"),
+                "Should be at least 1.",
+            );
+        }
+        for (i, peer) in self.peers.iter().enumerate() {
+            errors.ensure(
+                !peer.learner || peer.role == Role::Client,
+                &join_path(path, &format!("peers[{}].learner", i)),
+                "Only a client node can be a learner.",
+            );
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Deserialize)]
+#[serde(default)]
+pub struct HttpClientConfig {
+    /// Max number of idle connections kept for each peer.
+    pub pool_max_idle_per_host: usize,
+    /// Close the idle connections after this time span in milliseconds.
+    #[serde(deserialize_with = "slimchain_utils::config::deserialize_duration_from_millis")]
+    pub pool_idle_timeout: Duration,
+    /// Timeout of each request in milliseconds.
+    #[serde(deserialize_with = "slimchain_utils::config::deserialize_duration_from_millis")]
+    pub request_timeout: Duration,
+    /// How to compress the request bodies of the node RPCs.
+    pub compression: CompressionConfig,
+}
+
+impl Default for HttpClientConfig {
+    fn default() -> Self {
+        Self {
+            pool_max_idle_per_host: 32,
+            pool_idle_timeout: Duration::from_secs(90),
+            request_timeout: Duration::from_secs(30),
+            compression: CompressionConfig::default(),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Deserialize)]
+#[serde(default)]
+pub struct CompressionConfig {
+    /// Compress the large request bodies with zstd if the peer supports it. Default false.
+    pub enabled: bool,
+    /// Only compress the request bodies with at least this number of bytes.
+    pub threshold: usize,
+    /// The zstd compression level.
+    pub level: i32,
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            threshold: 64 * 1024,
+            level: 3,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Deserialize)]
+#[serde(default)]
+pub struct RpcTimeoutConfig {
+    /// Timeout of forwarding a tx to the storage node in milliseconds.
+    #[serde(deserialize_with = "slimchain_utils::config::deserialize_duration_from_millis")]
+    pub forward_tx: Duration,
+    /// Timeout of forwarding tx proposals to the leader in milliseconds.
+    #[serde(deserialize_with = "slimchain_utils::config::deserialize_duration_from_millis")]
+    pub forward_tx_proposal: Duration,
+    /// Timeout of the raft RPCs in milliseconds.
+    #[serde(deserialize_with = "slimchain_utils::config::deserialize_duration_from_millis")]
+    pub raft: Duration,
+    /// Timeout of broadcasting the block proposals to each peer in milliseconds.
+    #[serde(deserialize_with = "slimchain_utils::config::deserialize_duration_from_millis")]
+    pub broadcast: Duration,
+}
+
+impl Default for RpcTimeoutConfig {
+    fn default() -> Self {
+        Self {
+            forward_tx: Duration::from_secs(5),
+            forward_tx_proposal: Duration::from_secs(5),
+            raft: Duration::from_secs(5),
+            broadcast: Duration::from_secs(10),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Deserialize)]
+#[serde(default)]
+pub struct BroadcastConfig {
+    /// Max number of peers receiving the block proposals at the same time.
+    pub concurrency: usize,
+    /// Time span in milliseconds after which the pending requests of a broadcast are dropped.
+    #[serde(deserialize_with = "slimchain_utils::config::deserialize_duration_from_millis")]
+    pub deadline: Duration,
+    /// Number of recent block heights whose proposals are kept and re-broadcast to the storage
+    /// nodes which missed them. 0 disables the re-broadcast.
+    pub retry_window: u64,
+}
+
+impl Default for BroadcastConfig {
+    fn default() -> Self {
+        Self {
+            concurrency: 16,
+            deadline: Duration::from_secs(30),
+            retry_window: 32,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Deserialize)]
+#[serde(default)]
+pub struct RpcMetricsConfig {
+    /// Count the node RPCs by the target peer and the route. Default false.
+    pub enabled: bool,
+    /// Record the counters to the metrics file every this time span in milliseconds.
+    #[serde(deserialize_with = "slimchain_utils::config::deserialize_duration_from_millis")]
+    pub report_interval: Duration,
+}
+
+impl Default for RpcMetricsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            report_interval: Duration::from_secs(10),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Deserialize)]
+#[serde(default)]
+pub struct HealthCheckConfig {
+    /// Probe the health of the peers every this time span in milliseconds. 0 disables it.
+    #[serde(deserialize_with = "slimchain_utils::config::deserialize_duration_from_millis")]
+    pub interval: Duration,
+    /// Timeout of each probe in milliseconds.
+    #[serde(deserialize_with = "slimchain_utils::config::deserialize_duration_from_millis")]
+    pub timeout: Duration,
+    /// Mark a peer down after this number of failed probes in a row.
+    pub failure_threshold: u32,
+}
+
+impl Default for HealthCheckConfig {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(1),
+            timeout: Duration::from_millis(500),
+            failure_threshold: 3,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct NodeInfoConfig {
+    /// The id of the network, which keeps the nodes of different networks apart. Empty for
+    /// none.
+    pub network_id: String,
+    /// Fetch the node info of a peer again after this time span in milliseconds.
+    #[serde(deserialize_with = "slimchain_utils::config::deserialize_duration_from_millis")]
+    pub refresh_interval: Duration,
+    /// Timeout of fetching the node info in milliseconds.
+    #[serde(deserialize_with = "slimchain_utils::config::deserialize_duration_from_millis")]
+    pub timeout: Duration,
+}
+
+impl Default for NodeInfoConfig {
+    fn default() -> Self {
+        Self {
+            network_id: String::new(),
+            refresh_interval: Duration::from_secs(300),
+            timeout: Duration::from_secs(1),
+        }
+    }
+}
+
+/// The token buckets of a [`RateLimiter`](crate::http::rate_limit::RateLimiter).
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct RateLimitConfig {
+    /// Max number of requests per second from all the sources. 0 means unlimited. Default 0.
+    pub global_rate: u32,
+    /// Max number of requests accepted at once from all the sources. Default `global_rate`.
+    pub global_burst: u32,
+    /// Max number of requests per second from each source. 0 means unlimited. Default 0.
+    pub per_key_rate: u32,
+    /// Max number of requests accepted at once from each source. Default `per_key_rate`.
+    pub per_key_burst: u32,
+}
+
+#[derive(Debug, Copy, Clone, Deserialize)]
+#[serde(default)]
+pub struct ShutdownConfig {
+    /// Wait for the in-flight requests to complete for up to this time span in milliseconds,
+    /// before closing the remaining connections.
+    #[serde(deserialize_with = "slimchain_utils::config::deserialize_duration_from_millis")]
+    pub drain_timeout: Duration,
+}
+
+impl Default for ShutdownConfig {
+    fn default() -> Self {
+        Self {
+            drain_timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Deserialize)]
+#[serde(default)]
+pub struct TxVerifyConfig {
+    /// Verify the queued tx requests together with the batch verification once more than this
+    /// number of them are queued. Otherwise, they are verified one by one.
+    pub batch_threshold: usize,
+    /// Max number of the tx requests verified in a batch.
+    pub max_batch_size: usize,
+}
+
+impl Default for TxVerifyConfig {
+    fn default() -> Self {
+        Self {
+            batch_threshold: 16,
+            max_batch_size: 1024,
+        }
+    }
+}
+
+/// The stage of a storage node where the tx requests wait to be executed and the results wait
+/// to be forwarded to the raft leader.
+pub const EXECUTION_STAGE: &str = "execution";
+/// The stage of the raft leader where the tx proposals wait for the block assembly.
+pub const BLOCK_ASSEMBLY_STAGE: &str = "block_assembly";
+/// The stage of the raft leader where the block proposals wait to be broadcast.
+pub const BROADCAST_STAGE: &str = "broadcast";
+
+/// The bounds of the queues between the stages of the node pipeline, see
+/// [`backpressure`](slimchain_utils::backpressure). 0 leaves a queue unbounded.
+#[derive(Debug, Copy, Clone, Deserialize)]
+#[serde(default)]
+pub struct BackpressureConfig {
+    /// Max number of the txs executed or waiting to be forwarded on a storage node.
+    pub max_executing_txs: usize,
+    /// Max number of the tx proposals waiting for the block assembly on the raft leader.
+    pub max_pending_txs: usize,
+    /// Max number of the block proposals waiting to be broadcast on the raft leader.
+    pub max_pending_blocks: usize,
+    /// The `retry-after` of the requests shed while the pipeline is saturated, in milliseconds.
+    #[serde(deserialize_with = "slimchain_utils::config::deserialize_duration_from_millis")]
+    pub retry_after: Duration,
+}
+
+impl Default for BackpressureConfig {
+    fn default() -> Self {
+        Self {
+            max_executing_txs: 10_000,
+            max_pending_txs: 50_000,
+            max_pending_blocks: 64,
+            retry_after: Duration::from_secs(1),
+        }
+    }
+}
+
+impl BackpressureConfig {
+    /// The pipeline of a raft client node, i.e., the block assembly and then the broadcast.
+    pub fn client_pipeline(&self) -> Pipeline {
+        let mut pipeline = Pipeline::new(self.retry_after);
+        pipeline.add_stage(BLOCK_ASSEMBLY_STAGE, self.max_pending_txs);
+        pipeline.add_stage(BROADCAST_STAGE, self.max_pending_blocks);
+        pipeline
+    }
+
+    /// The pipeline of a storage node, i.e., the execution.
+    pub fn storage_pipeline(&self) -> Pipeline {
+        let mut pipeline = Pipeline::new(self.retry_after);
+        pipeline.add_stage(EXECUTION_STAGE, self.max_executing_txs);
+        pipeline
+    }
+}
+
+#[derive(Debug, Copy, Clone, Deserialize)]
+#[serde(default)]
+pub struct StateFetchConfig {
+    /// Fetch the state nodes missing locally from the other storage nodes, e.g., of the
+    /// accounts out of the shard, instead of failing the txs. Default false.
+    pub enabled: bool,
+    /// Timeout of fetching a state node in milliseconds.
+    #[serde(deserialize_with = "slimchain_utils::config::deserialize_duration_from_millis")]
+    pub timeout: Duration,
+    /// Max number of the state nodes a tx may fetch.
+    pub max_nodes_per_tx: usize,
+    /// Max number of the state nodes fetched kept in memory.
+    pub cache_size: usize,
+}
+
+impl Default for StateFetchConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            timeout: Duration::from_secs(1),
+            max_nodes_per_tx: 64,
+            cache_size: 65_536,
+        }
+    }
+}
+
+/// What to do with a tx request whose nonce is too far ahead of its caller.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NonceGapPolicy {
+    /// Reject it, so that the caller resubmits it later.
+    Reject,
+    /// Park it on the storage node until the state catches up.
+    Park,
+}
+
+#[derive(Debug, Copy, Clone, Deserialize)]
+#[serde(default)]
+pub struct NonceCheckConfig {
+    /// Check the nonces of the tx requests against the latest state before executing them,
+    /// rejecting the replayed ones. Default false.
+    pub enabled: bool,
+    /// Max number of the nonces between the one expected next from the caller and a tx.
+    pub max_gap: u64,
+    /// What to do with the txs beyond the max gap.
+    pub gap_policy: NonceGapPolicy,
+    /// Max number of the txs parked. The ones beyond it are rejected.
+    pub max_parked: usize,
+    /// Drop a parked tx if it is still beyond the max gap after this number of blocks.
+    pub max_parked_blocks: u64,
+}
+
+impl Default for NonceCheckConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_gap: 64,
+            gap_policy: NonceGapPolicy::Reject,
+            max_parked: 10_000,
+            max_parked_blocks: 16,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default)]
+pub struct BackupConfig {
+    /// The directory on the host of the storage node holding the backups. A signed
+    /// `AdminRequest::Backup` names a backup in it. The backups are rejected if it is not set.
+    /// Default none.
+    pub root: Option<PathBuf>,
+}
+
+impl BackupConfig {
+    /// The directory of the backup `name`, which must be a single path component, so that the
+    /// backups stay in the root.
+    pub fn backup_dir(&self, name: &str) -> Result<PathBuf> {
+        let root = self
+            .root
+            .as_ref()
+            .ok_or_else(|| anyhow!("Backup is disabled."))?;
+        let mut components = Path::new(name).components();
+        ensure!(
+            matches!(
+                (components.next(), components.next()),
+                (Some(Component::Normal(_)), None)
+            ),
+            "Invalid backup name {:?}.",
+            name
+        );
+        Ok(root.join(name))
+    }
+}
+
+/// The CORS policy of the client-facing routes. See [`cors`](crate::http::cors).
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct CorsConfig {
+    /// Send the CORS headers and answer the preflights. Default false.
+    pub enabled: bool,
+    /// The allowed origins, e.g., `https://example.com`. `*` allows any origin.
+    pub allowed_origins: Vec<String>,
+    /// The allowed methods. Default `GET` and `POST`.
+    pub allowed_methods: Vec<String>,
+    /// The allowed request headers. Default `content-type`.
+    pub allowed_headers: Vec<String>,
+    /// How long the browsers cache the preflights in milliseconds. Default 1 hour.
+    #[serde(deserialize_with = "slimchain_utils::config::deserialize_duration_from_millis")]
+    pub max_age: Duration,
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            allowed_origins: Vec::new(),
+            allowed_methods: vec!["GET".to_string(), "POST".to_string()],
+            allowed_headers: vec!["content-type".to_string()],
+            max_age: Duration::from_secs(3600),
+        }
+    }
+}
+
+/// The limits of the node RPC request bodies, which are enforced before decoding them.
+/// See [`body_limit`](crate::http::body_limit).
+#[derive(Debug, Copy, Clone, Deserialize)]
+#[serde(default)]
+pub struct BodyLimitConfig {
+    /// Max size in bytes of the routes without their own limits. Default 1 GiB.
+    pub default: u64,
+    /// Max size in bytes of a block proposal. The block imports are limited to
+    /// [`MAX_BLOCK_PROPOSALS_PER_IMPORT`] times of it. Default 64 MiB.
+    pub max_block_bytes: u64,
+    /// Max size in bytes of the tx submissions. Default 16 MiB.
+    pub tx_req: u64,
+    /// Max size in bytes of a raft vote. Default 4 KiB.
+    pub raft_vote: u64,
+    /// Max time span in milliseconds to read a request body. Default 30s.
+    #[serde(deserialize_with = "slimchain_utils::config::deserialize_duration_from_millis")]
+    pub read_timeout: Duration,
+}
+
+impl Default for BodyLimitConfig {
+    fn default() -> Self {
+        Self {
+            default: 1 << 30,
+            max_block_bytes: 64 << 20,
+            tx_req: 16 << 20,
+            raft_vote: 4 << 10,
+            read_timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+impl ValidateConfig for BodyLimitConfig {
+    fn validate_at(&self, path: &str, errors: &mut ConfigErrors) {
+        let limits = [
+            ("default", self.default),
+            ("max_block_bytes", self.max_block_bytes),
+            ("tx_req", self.tx_req),
+            ("raft_vote", self.raft_vote),
+        ];
+        for (key, limit) in limits.iter() {
+            errors.ensure(*limit > 0, &join_path(path, key), "Should be positive.");
+        }
+        errors.ensure(
+            self.read_timeout > Duration::from_millis(0),
+            &join_path(path, "read_timeout"),
+            "Should be positive.",
+        );
+    }
+}
+
+impl BodyLimitConfig {
+    pub fn block_import(&self) -> u64 {
+        self.max_block_bytes
+            .saturating_mul(MAX_BLOCK_PROPOSALS_PER_IMPORT as u64)
+    }
+}
+
+#[derive(Debug, Copy, Clone, Deserialize)]
+#[serde(default)]
+pub struct WsSubscribeConfig {
+    /// Max number of the events queued for each connection. The connection is closed as
+    /// lagged once exceeded.
+    pub queue_len: usize,
+    /// Max number of the blocks replayed with `from_height`. 0 means unlimited.
+    pub max_replay_blocks: u64,
+}
+
+impl Default for WsSubscribeConfig {
+    fn default() -> Self {
+        Self {
+            queue_len: 1024,
+            max_replay_blocks: 1000,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default)]
+pub struct RouteUpdateConfig {
+    /// Hex encoded ed25519 public keys allowed to sign the route table updates and the admin
+    /// requests sent to the client and storage nodes. Both are rejected if it is empty.
+    #[serde(deserialize_with = "slimchain_utils::config::deserialize_public_keys_from_hex")]
+    pub admin_keys: Vec<PublicKey>,
+    /// Check the config file every this time span in milliseconds, and reload the known peers
+    /// from it once modified. 0 disables it. Default 0.
+    #[serde(deserialize_with = "slimchain_utils::config::deserialize_duration_from_millis")]
+    pub watch_interval: Duration,
+}
+
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default)]
+pub struct NodeRpcAuthConfig {
+    /// The token shared by all the nodes in the network, which is sent in the `Authorization`
+    /// header of the node RPCs. The client-facing tx submission does not require it.
+    /// The node RPCs are not authenticated if it is not set. Default none.
+    pub token: Option<String>,
+}
+
+static GLOBAL_NODE_RPC_AUTH_CONFIG: OnceCell<NodeRpcAuthConfig> = OnceCell::new();
+
+impl NodeRpcAuthConfig {
+    /// Install the config used to send and check the node RPCs.
+    /// It should be called before starting the node.
+    pub fn install_as_global(self) -> Result<()> {
+        GLOBAL_NODE_RPC_AUTH_CONFIG
+            .set(self)
+            .map_err(|_| anyhow!("Failed to set NodeRpcAuthConfig."))
+    }
+
+    pub fn token() -> Option<&'static str> {
+        GLOBAL_NODE_RPC_AUTH_CONFIG
+            .get()
+            .and_then(|cfg| cfg.token.as_deref())
+    }
+}
+
+#[derive(Debug, Default, Copy, Clone, Deserialize)]
+#[serde(default)]
+pub struct NodeRpcEncodingConfig {
+    /// Also accept the JSON encoded node RPCs, which are replied in JSON as well, so that the
+    /// nodes can be poked with curl when debugging. The postcard encoded ones are always
+    /// accepted. Default false.
+    pub allow_json: bool,
+}
+
+static GLOBAL_NODE_RPC_ENCODING_CONFIG: OnceCell<NodeRpcEncodingConfig> = OnceCell::new();
+
+impl NodeRpcEncodingConfig {
+    /// Install the config used to check the node RPCs.
+    /// It should be called before starting the node.
+    pub fn install_as_global(self) -> Result<()> {
+        GLOBAL_NODE_RPC_ENCODING_CONFIG
+            .set(self)
+            .map_err(|_| anyhow!("Failed to set NodeRpcEncodingConfig."))
+    }
+
+    pub fn allow_json() -> bool {
+        GLOBAL_NODE_RPC_ENCODING_CONFIG
+            .get()
+            .map_or(false, |cfg| cfg.allow_json)
+    }
+}
+
+static GLOBAL_BODY_LIMIT_CONFIG: OnceCell<BodyLimitConfig> = OnceCell::new();
+
+impl BodyLimitConfig {
+    /// Install the config used by the node RPC servers.
+    /// It should be called before starting the node.
+    pub fn install_as_global(self) -> Result<()> {
+        self.validate_config("network.body_limit")?;
+        GLOBAL_BODY_LIMIT_CONFIG
+            .set(self)
+            .map_err(|_| anyhow!("Failed to set BodyLimitConfig."))
+    }
+
+    pub fn get() -> Self {
+        GLOBAL_BODY_LIMIT_CONFIG.get().copied().unwrap_or_default()
+    }
+}
+
+static GLOBAL_HTTP_CLIENT_CONFIG: OnceCell<HttpClientConfig> = OnceCell::new();
+
+impl HttpClientConfig {
+    /// Install the config used by the shared HTTP client.
+    /// It should be called before sending any request.
+    pub fn install_as_global(self) -> Result<()> {
+        GLOBAL_HTTP_CLIENT_CONFIG
+            .set(self)
+            .map_err(|_| anyhow!("Failed to set HttpClientConfig."))
+    }
+
+    pub fn get() -> Self {
+        GLOBAL_HTTP_CLIENT_CONFIG.get().copied().unwrap_or_default()
+    }
+
+    pub fn build_client(&self) -> Client<HttpConnector, Body> {
+        let mut connector = HttpConnector::new();
+        connector.set_nodelay(true);
+        Client::builder()
+            .pool_max_idle_per_host(self.pool_max_idle_per_host)
+            .pool_idle_timeout(self.pool_idle_timeout)
+            .build(connector)
+    }
+}
+
 impl NetworkConfig {
     pub fn to_route_table(&self) -> NetworkRouteTable {
         let mut peer_table = HashMap::new();
@@ -61,10 +762,25 @@ impl NetworkConfig {
                 .push(peer.peer_id);
         }
 
+        let libp2p_peer_table = self
+            .peers
+            .iter()
+            .filter_map(|peer| Some((peer.peer_id, peer.libp2p_peer_id?)))
+            .collect();
+
+        let learners = self
+            .peers
+            .iter()
+            .filter(|peer| peer.learner)
+            .map(|peer| peer.peer_id)
+            .collect();
+
         NetworkRouteTable {
             peer_id: self.peer_id,
             peer_table,
             role_table,
+            libp2p_peer_table,
+            learners,
         }
     }
 }
@@ -74,6 +790,9 @@ pub struct NetworkRouteTable {
     peer_id: PeerId,
     peer_table: HashMap<PeerId, String>,
     role_table: HashMap<Role, Vec<PeerId>>,
+    libp2p_peer_table: HashMap<PeerId, libp2p::PeerId>,
+    /// The client nodes replicating the raft log as non-voters.
+    learners: HashSet<PeerId>,
 }
 
 impl NetworkRouteTable {
@@ -81,12 +800,21 @@ impl NetworkRouteTable {
         self.peer_id
     }
 
+    /// The client nodes voting in the raft cluster, i.e., except the learners.
     pub fn all_client_peer_ids(&self) -> std::collections::HashSet<async_raft::NodeId> {
-        if let Some(peers) = self.role_table.get(&Role::Client) {
-            peers.iter().map(|id| id.0).collect()
-        } else {
-            Default::default()
-        }
+        self.peers_for_role(&Role::Client)
+            .iter()
+            .filter(|id| !self.learners.contains(id))
+            .map(|id| id.0)
+            .collect()
+    }
+
+    pub fn is_learner(&self, peer_id: PeerId) -> bool {
+        self.learners.contains(&peer_id)
+    }
+
+    pub fn learners(&self) -> &HashSet<PeerId> {
+        &self.learners
     }
 
     pub fn peer_table(&self) -> &HashMap<PeerId, String> {
@@ -100,18 +828,103 @@ impl NetworkRouteTable {
     pub fn peer_address(&self, peer_id: PeerId) -> Result<&String> {
         self.peer_table
             .get(&peer_id)
-            .ok_or_else(|| anyhow!("Failed to get peer address. PeerId: {}.", peer_id))
+            .ok_or_else(|| NetworkError::UnknownPeer(peer_id).into())
+    }
+
+    pub fn libp2p_peer_id(&self, peer_id: PeerId) -> Option<libp2p::PeerId> {
+        self.libp2p_peer_table.get(&peer_id).copied()
+    }
+
+    /// The libp2p peer ids of all the peers, whose gossip messages are authorized.
+    pub fn authorized_libp2p_peers(&self) -> HashSet<libp2p::PeerId> {
+        self.libp2p_peer_table.values().copied().collect()
+    }
+
+    /// All the peers with `role`.
+    pub fn peers_for_role(&self, role: &Role) -> &[PeerId] {
+        self.role_table
+            .get(role)
+            .map_or(&[], |list| list.as_slice())
     }
 
     pub fn random_peer(&self, role: &Role) -> Option<PeerId> {
         match self.role_table.get(role) {
-            Some(list) => {
-                let mut rng = rand::thread_rng();
-                list.iter().choose(&mut rng).copied()
-            }
+            Some(list) => list.iter().choose(&mut rng()).copied(),
             None => None,
         }
     }
+
+    /// Apply `update` to the table. The peer of this node cannot be removed or change its role.
+    pub fn apply_update(&mut self, update: RouteTableUpdate) -> Result<()> {
+        match update {
+            RouteTableUpdate::AddPeer {
+                peer_id,
+                address,
+                role,
+            } => {
+                ensure!(
+                    !self.peer_table.contains_key(&peer_id),
+                    "Peer {} already exists.",
+                    peer_id
+                );
+                self.peer_table.insert(peer_id, address);
+                self.role_table
+                    .entry(role)
+                    .or_insert_with(Vec::new)
+                    .push(peer_id);
+            }
+            RouteTableUpdate::RemovePeer { peer_id } => {
+                ensure!(peer_id != self.peer_id, "Cannot remove this node.");
+                ensure!(
+                    self.peer_table.remove(&peer_id).is_some(),
+                    "Unknown peer {}.",
+                    peer_id
+                );
+                self.remove_from_role_table(peer_id);
+                self.libp2p_peer_table.remove(&peer_id);
+                self.learners.remove(&peer_id);
+            }
+            RouteTableUpdate::ChangeRole { peer_id, role } => {
+                ensure!(
+                    peer_id != self.peer_id,
+                    "Cannot change the role of this node."
+                );
+                ensure!(
+                    self.peer_table.contains_key(&peer_id),
+                    "Unknown peer {}.",
+                    peer_id
+                );
+                self.remove_from_role_table(peer_id);
+                self.role_table
+                    .entry(role)
+                    .or_insert_with(Vec::new)
+                    .push(peer_id);
+                if role != Role::Client {
+                    self.learners.remove(&peer_id);
+                }
+            }
+            RouteTableUpdate::SetLearner { peer_id, learner } => {
+                ensure!(
+                    self.peers_for_role(&Role::Client).contains(&peer_id),
+                    "Peer {} is not a client node.",
+                    peer_id
+                );
+                if learner {
+                    self.learners.insert(peer_id);
+                } else {
+                    self.learners.remove(&peer_id);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn remove_from_role_table(&mut self, peer_id: PeerId) {
+        for list in self.role_table.values_mut() {
+            list.retain(|&id| id != peer_id);
+        }
+        self.role_table.retain(|_, list| !list.is_empty());
+    }
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -120,6 +933,30 @@ pub struct PeerConfig {
     pub address: String,
     #[serde(flatten)]
     pub role: Role,
+    /// The base58 encoded libp2p peer id of the node, if it publishes gossip messages.
+    #[serde(default, deserialize_with = "deserialize_libp2p_peer_id")]
+    pub libp2p_peer_id: Option<libp2p::PeerId>,
+    /// Whether the client node is a raft learner, which applies the log like the other client
+    /// nodes without voting or counting toward the quorum. Default false.
+    #[serde(default)]
+    pub learner: bool,
+}
+
+fn deserialize_libp2p_peer_id<'de, D>(deserializer: D) -> Result<Option<libp2p::PeerId>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Option::<String>::deserialize(deserializer)?
+        .map(|id| id.parse().map_err(DeError::custom))
+        .transpose()
+}
+
+fn default_snapshot_transfer_chunk_size() -> usize {
+    1024 * 1024
+}
+
+fn default_leader_transfer_timeout() -> Duration {
+    Duration::from_secs(10)
 }
 
 // https://docs.rs/async-raft/0.6.0-alpha.1/async_raft/config/struct.Config.html
@@ -155,14 +992,96 @@ pub struct RaftConfig {
     pub replication_lag_threshold: Option<u64>,
     /// The snapshot policy to use for a Raft node.
     /// A snapshot will be generated once the log has grown the specified number of logs since the last snapshot.
+    /// Each block takes one log entry, so it is also the number of blocks between the snapshots.
+    /// The snapshot only holds the header of the latest block. The nodes lagging behind it fetch
+    /// the blocks up to it from the storage nodes.
+    ///
+    /// Defaults to 5000.
     pub snapshot_policy_logs_since_last: Option<u64>,
     /// The maximum snapshot chunk size allowed when transmitting snapshots (in bytes).
     ///
     /// Defaults to 3Mib.
     pub snapshot_max_chunk_size: Option<u64>,
+    /// The size of the chunks in which each snapshot chunk above is uploaded, so that a failed
+    /// upload resumes from the last received chunk.
+    ///
+    /// Defaults to 1Mib.
+    #[serde(default = "default_snapshot_transfer_chunk_size")]
+    pub snapshot_transfer_chunk_size: usize,
     /// How to broadcast the block to storage node
     #[serde(default)]
     pub async_broadcast_storage: bool,
+    /// Whether the node joins a running cluster, to which the leader adds it by the
+    /// `add_member` admin route, instead of initializing the cluster with the client nodes in
+    /// `network.peers`. Default false.
+    #[serde(default)]
+    pub join: bool,
+    /// How long the `transfer_leadership` admin route waits for the target to take over in
+    /// milliseconds, during which the leader proposes no block.
+    ///
+    /// Defaults to 10 seconds.
+    #[serde(
+        default = "default_leader_transfer_timeout",
+        deserialize_with = "slimchain_utils::config::deserialize_duration_from_millis"
+    )]
+    pub leader_transfer_timeout: Duration,
+}
+
+// The defaults of async-raft.
+const DEFAULT_ELECTION_TIMEOUT_MIN: u64 = 150;
+const DEFAULT_ELECTION_TIMEOUT_MAX: u64 = 300;
+const DEFAULT_HEARTBEAT_INTERVAL: u64 = 50;
+const DEFAULT_SNAPSHOT_MAX_CHUNK_SIZE: u64 = 3 * 1024 * 1024;
+
+impl ValidateConfig for RaftConfig {
+    fn validate_at(&self, path: &str, errors: &mut ConfigErrors) {
+        let election_timeout_min = self
+            .election_timeout_min
+            .unwrap_or(DEFAULT_ELECTION_TIMEOUT_MIN);
+        let election_timeout_max = self
+            .election_timeout_max
+            .unwrap_or(DEFAULT_ELECTION_TIMEOUT_MAX);
+        let heartbeat_interval = self
+            .heartbeat_interval
+            .unwrap_or(DEFAULT_HEARTBEAT_INTERVAL);
+        let snapshot_max_chunk_size = self
+            .snapshot_max_chunk_size
+            .unwrap_or(DEFAULT_SNAPSHOT_MAX_CHUNK_SIZE);
+        errors.ensure(
+            election_timeout_min < election_timeout_max,
+            &join_path(path, "election_timeout_min"),
+            format!(
+                "Should be smaller than `election_timeout_max` ({}).",
+                election_timeout_max
+            ),
+        );
+        errors.ensure(
+            heartbeat_interval < election_timeout_min,
+            &join_path(path, "heartbeat_interval"),
+            format!(
+                "Should be smaller than `election_timeout_min` ({}).",
+                election_timeout_min
+            ),
+        );
+        errors.ensure(
+            self.snapshot_policy_logs_since_last != Some(0),
+            &join_path(path, "snapshot_policy_logs_since_last"),
+            "Should be at least 1.",
+        );
+        errors.ensure(
+            self.snapshot_transfer_chunk_size >= 1,
+            &join_path(path, "snapshot_transfer_chunk_size"),
+            "Should be at least 1.",
+        );
+        errors.ensure(
+            self.snapshot_transfer_chunk_size as u64 <= snapshot_max_chunk_size,
+            &join_path(path, "snapshot_transfer_chunk_size"),
+            format!(
+                "Should not be larger than `snapshot_max_chunk_size` ({}).",
+                snapshot_max_chunk_size
+            ),
+        );
+    }
 }
 
 impl RaftConfig {
@@ -257,4 +1176,417 @@ mod tests {
         assert_eq!("127.0.0.1:8000", &peer.address);
         assert_eq!(Role::Storage(ShardId::new(1, 2)), peer.role);
     }
+
+    #[test]
+    fn test_deserialize_http_client_config() {
+        use slimchain_utils::{config::Config, toml};
+
+        let input = toml::toml! {
+            [http_client]
+            pool_max_idle_per_host = 4
+            request_timeout = 500
+
+            [http_client.compression]
+            enabled = true
+            level = 9
+        };
+        let cfg: HttpClientConfig = Config::from_toml(input).get("http_client").unwrap();
+        assert_eq!(4, cfg.pool_max_idle_per_host);
+        assert_eq!(Duration::from_secs(90), cfg.pool_idle_timeout);
+        assert_eq!(Duration::from_millis(500), cfg.request_timeout);
+        assert!(cfg.compression.enabled);
+        assert_eq!(64 * 1024, cfg.compression.threshold);
+        assert_eq!(9, cfg.compression.level);
+    }
+
+    #[test]
+    fn test_route_table_with_observer() {
+        use slimchain_common::basic::ShardId;
+
+        let net_cfg = NetworkConfig {
+            peer_id: PeerId(1),
+            http_listen: default_http_listen(),
+            peers: vec![
+                PeerConfig {
+                    peer_id: PeerId(1),
+                    address: "127.0.0.1:8001".into(),
+                    role: Role::Client,
+                    libp2p_peer_id: None,
+                    learner: false,
+                },
+                PeerConfig {
+                    peer_id: PeerId(2),
+                    address: "127.0.0.1:8002".into(),
+                    role: Role::Storage(ShardId::default()),
+                    libp2p_peer_id: None,
+                    learner: false,
+                },
+                PeerConfig {
+                    peer_id: PeerId(3),
+                    address: "127.0.0.1:8003".into(),
+                    role: Role::Observer,
+                    libp2p_peer_id: None,
+                    learner: false,
+                },
+            ],
+            http_client: HttpClientConfig::default(),
+            timeout: RpcTimeoutConfig::default(),
+            broadcast: BroadcastConfig::default(),
+            auth: NodeRpcAuthConfig::default(),
+            node_rpc_encoding: NodeRpcEncodingConfig::default(),
+            rpc_metrics: RpcMetricsConfig::default(),
+            route_update: RouteUpdateConfig::default(),
+            health_check: HealthCheckConfig::default(),
+            node_info: NodeInfoConfig::default(),
+            tx_rate_limit: RateLimitConfig::default(),
+            cors: CorsConfig::default(),
+            body_limit: BodyLimitConfig::default(),
+            ws_subscribe: WsSubscribeConfig::default(),
+            shutdown: ShutdownConfig::default(),
+            tx_verify: TxVerifyConfig::default(),
+            backpressure: BackpressureConfig::default(),
+            state_fetch: StateFetchConfig::default(),
+            nonce_check: NonceCheckConfig::default(),
+            backup: BackupConfig::default(),
+        };
+        let route_table = net_cfg.to_route_table();
+        for _ in 0..10 {
+            assert_eq!(
+                Some(PeerId(2)),
+                route_table.random_peer(&Role::Storage(ShardId::default()))
+            );
+        }
+        assert_eq!(Some(PeerId(3)), route_table.random_peer(&Role::Observer));
+        assert_eq!(
+            &[PeerId(2)],
+            route_table.peers_for_role(&Role::Storage(ShardId::default()))
+        );
+        assert!(route_table
+            .peers_for_role(&Role::Storage(ShardId::new(1, 2)))
+            .is_empty());
+        assert_eq!(1, route_table.all_client_peer_ids().len());
+    }
+
+    #[test]
+    fn test_libp2p_peer_id() {
+        use slimchain_utils::{config::Config, toml};
+
+        let libp2p_peer_id = libp2p::identity::Keypair::generate_ed25519()
+            .public()
+            .into_peer_id();
+        let input: toml::Value = toml::from_str(&format!(
+            r#"
+                [network]
+                peer_id = 1
+
+                [[network.peers]]
+                peer_id = 1
+                address = "127.0.0.1:8001"
+                libp2p_peer_id = "{}"
+
+                [[network.peers]]
+                peer_id = 2
+                address = "127.0.0.1:8002"
+            "#,
+            libp2p_peer_id.to_base58()
+        ))
+        .unwrap();
+        let net_cfg: NetworkConfig = Config::from_toml(input).get("network").unwrap();
+        let mut route_table = net_cfg.to_route_table();
+        assert_eq!(Some(libp2p_peer_id), route_table.libp2p_peer_id(PeerId(1)));
+        assert_eq!(None, route_table.libp2p_peer_id(PeerId(2)));
+        assert_eq!(
+            vec![libp2p_peer_id],
+            route_table
+                .authorized_libp2p_peers()
+                .into_iter()
+                .collect::<Vec<_>>()
+        );
+
+        route_table
+            .apply_update(RouteTableUpdate::RemovePeer { peer_id: PeerId(2) })
+            .unwrap();
+        assert_eq!(1, route_table.authorized_libp2p_peers().len());
+
+        let input = toml::toml! {
+            [peer]
+            peer_id = 3
+            address = "127.0.0.1:8003"
+            libp2p_peer_id = "invalid"
+        };
+        assert!(Config::from_toml(input).get::<PeerConfig>("peer").is_err());
+    }
+
+    #[test]
+    fn test_backup_dir() {
+        assert!(BackupConfig::default().backup_dir("daily").is_err());
+
+        let cfg = BackupConfig {
+            root: Some(PathBuf::from("/var/backup")),
+        };
+        assert_eq!(
+            PathBuf::from("/var/backup/daily"),
+            cfg.backup_dir("daily").unwrap()
+        );
+        for name in &["", ".", "..", "../daily", "a/b", "/etc"] {
+            assert!(cfg.backup_dir(name).is_err(), "{:?}", name);
+        }
+    }
+
+    #[test]
+    fn test_validate_network_config() {
+        use slimchain_utils::{config::Config, toml};
+
+        let net_cfg = |input: &str| -> NetworkConfig {
+            let input = format!("[network]\npeer_id = 1\n{}", input);
+            Config::from_toml(toml::from_str(&input).unwrap())
+                .get("network")
+                .unwrap()
+        };
+        assert!(net_cfg("").validate_config("network").is_ok());
+
+        let errors = net_cfg("[network.body_limit]\ntx_req = 0\nread_timeout = 0\n")
+            .validate_config("network")
+            .unwrap_err();
+        assert!(errors.contains("network.body_limit.tx_req"));
+        assert!(errors.contains("network.body_limit.read_timeout"));
+        assert_eq!(2, errors.errors().len());
+        assert!(net_cfg("[network.body_limit]\ndefault = 0\n")
+            .body_limit
+            .install_as_global()
+            .is_err());
+
+        let errors = net_cfg("[network.tx_verify]\nmax_batch_size = 0\n")
+            .validate_config("network")
+            .unwrap_err();
+        assert!(errors.contains("network.tx_verify.max_batch_size"));
+
+        let errors = net_cfg("[network.backpressure]\nretry_after = 0\n")
+            .validate_config("network")
+            .unwrap_err();
+        assert!(errors.contains("network.backpressure.retry_after"));
+
+        let errors = net_cfg("[network.state_fetch]\ntimeout = 0\n")
+            .validate_config("network")
+            .unwrap_err();
+        assert!(errors.contains("network.state_fetch.timeout"));
+
+        let cfg = net_cfg("[network.nonce_check]\nenabled = true\ngap_policy = \"park\"\n");
+        assert_eq!(cfg.nonce_check.gap_policy, NonceGapPolicy::Park);
+        assert!(cfg.validate_config("network").is_ok());
+        let errors = net_cfg(
+            "[network.nonce_check]\nenabled = true\ngap_policy = \"park\"\nmax_parked = 0\n",
+        )
+        .validate_config("network")
+        .unwrap_err();
+        assert!(errors.contains("network.nonce_check.max_parked"));
+        assert!(net_cfg("[network.nonce_check]\nmax_parked = 0\n")
+            .validate_config("network")
+            .is_ok());
+
+        let errors = net_cfg(
+            "[[network.peers]]\npeer_id = 2\naddress = \"127.0.0.1:8002\"\nrole = \"observer\"\n\
+             learner = true\n",
+        )
+        .validate_config("network")
+        .unwrap_err();
+        assert!(errors.contains("network.peers[0].learner"));
+    }
+
+    #[test]
+    fn test_validate_raft_config() {
+        use slimchain_utils::{config::Config, toml};
+
+        let raft_cfg = |input: &str| -> RaftConfig {
+            let input = format!("[raft]\n{}", input);
+            Config::from_toml(toml::from_str(&input).unwrap())
+                .get("raft")
+                .unwrap()
+        };
+        assert!(raft_cfg("").validate_config("raft").is_ok());
+
+        let errors = raft_cfg("election_timeout_min = 300\n")
+            .validate_config("raft")
+            .unwrap_err();
+        assert!(errors.contains("raft.election_timeout_min"));
+
+        let errors = raft_cfg("heartbeat_interval = 150\n")
+            .validate_config("raft")
+            .unwrap_err();
+        assert!(errors.contains("raft.heartbeat_interval"));
+
+        let errors = raft_cfg("snapshot_policy_logs_since_last = 0\n")
+            .validate_config("raft")
+            .unwrap_err();
+        assert!(errors.contains("raft.snapshot_policy_logs_since_last"));
+
+        let errors = raft_cfg("snapshot_max_chunk_size = 1024\n")
+            .validate_config("raft")
+            .unwrap_err();
+        assert!(errors.contains("raft.snapshot_transfer_chunk_size"));
+        assert!(
+            raft_cfg("snapshot_max_chunk_size = 1024\nsnapshot_transfer_chunk_size = 1024\n")
+                .validate_config("raft")
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn test_route_table_update() {
+        use slimchain_common::basic::ShardId;
+        use slimchain_utils::{config::Config, toml};
+
+        let input = toml::toml! {
+            [network]
+            peer_id = 1
+
+            [[network.peers]]
+            peer_id = 1
+            address = "127.0.0.1:8001"
+
+            [[network.peers]]
+            peer_id = 2
+            address = "127.0.0.1:8002"
+            role = "storage"
+        };
+        let net_cfg: NetworkConfig = Config::from_toml(input).get("network").unwrap();
+        let mut route_table = net_cfg.to_route_table();
+        let shard = Role::Storage(ShardId::default());
+
+        route_table
+            .apply_update(RouteTableUpdate::AddPeer {
+                peer_id: PeerId(3),
+                address: "127.0.0.1:8003".into(),
+                role: shard,
+            })
+            .unwrap();
+        assert_eq!(&[PeerId(2), PeerId(3)], route_table.peers_for_role(&shard));
+        assert_eq!(
+            "127.0.0.1:8003",
+            route_table.peer_address(PeerId(3)).unwrap()
+        );
+        assert!(route_table
+            .apply_update(RouteTableUpdate::AddPeer {
+                peer_id: PeerId(3),
+                address: "127.0.0.1:8004".into(),
+                role: shard,
+            })
+            .is_err());
+
+        route_table
+            .apply_update(RouteTableUpdate::ChangeRole {
+                peer_id: PeerId(2),
+                role: Role::Observer,
+            })
+            .unwrap();
+        assert_eq!(&[PeerId(3)], route_table.peers_for_role(&shard));
+        assert_eq!(&[PeerId(2)], route_table.peers_for_role(&Role::Observer));
+
+        route_table
+            .apply_update(RouteTableUpdate::RemovePeer { peer_id: PeerId(2) })
+            .unwrap();
+        assert!(route_table.peer_address(PeerId(2)).is_err());
+        assert!(route_table.role_table().get(&Role::Observer).is_none());
+
+        assert!(route_table
+            .apply_update(RouteTableUpdate::RemovePeer { peer_id: PeerId(1) })
+            .is_err());
+        assert!(route_table
+            .apply_update(RouteTableUpdate::ChangeRole {
+                peer_id: PeerId(4),
+                role: Role::Observer,
+            })
+            .is_err());
+    }
+
+    #[test]
+    fn test_route_table_learners() {
+        use slimchain_utils::{config::Config, toml};
+
+        let input = toml::toml! {
+            [network]
+            peer_id = 1
+
+            [[network.peers]]
+            peer_id = 1
+            address = "127.0.0.1:8001"
+
+            [[network.peers]]
+            peer_id = 2
+            address = "127.0.0.1:8002"
+            learner = true
+
+            [[network.peers]]
+            peer_id = 3
+            address = "127.0.0.1:8003"
+            role = "observer"
+        };
+        let net_cfg: NetworkConfig = Config::from_toml(input).get("network").unwrap();
+        let mut route_table = net_cfg.to_route_table();
+        assert!(route_table.is_learner(PeerId(2)));
+        assert!(!route_table.is_learner(PeerId(1)));
+        assert_eq!(2, route_table.peers_for_role(&Role::Client).len());
+        assert_eq!(
+            std::iter::once(1).collect::<std::collections::HashSet<_>>(),
+            route_table.all_client_peer_ids()
+        );
+
+        route_table
+            .apply_update(RouteTableUpdate::SetLearner {
+                peer_id: PeerId(2),
+                learner: false,
+            })
+            .unwrap();
+        assert!(!route_table.is_learner(PeerId(2)));
+        assert_eq!(2, route_table.all_client_peer_ids().len());
+        assert!(route_table
+            .apply_update(RouteTableUpdate::SetLearner {
+                peer_id: PeerId(3),
+                learner: true,
+            })
+            .is_err());
+
+        route_table
+            .apply_update(RouteTableUpdate::SetLearner {
+                peer_id: PeerId(2),
+                learner: true,
+            })
+            .unwrap();
+        route_table
+            .apply_update(RouteTableUpdate::RemovePeer { peer_id: PeerId(2) })
+            .unwrap();
+        assert!(route_table.learners().is_empty());
+    }
+
+    #[test]
+    fn test_seeded_random_peer() {
+        use slimchain_utils::{config::Config, rng, toml};
+
+        let mut input = "[network]\npeer_id = 1\n".to_string();
+        for peer_id in 1..=8 {
+            input.push_str(&format!(
+                "[[network.peers]]\npeer_id = {0}\naddress = \"127.0.0.1:80{0:02}\"\n\
+                 role = \"storage\"\n",
+                peer_id
+            ));
+        }
+        let net_cfg: NetworkConfig = Config::from_toml(toml::from_str(&input).unwrap())
+            .get("network")
+            .unwrap();
+        let route_table = net_cfg.to_route_table();
+        let role = Role::Storage(Default::default());
+        let picks = |seed: u64| -> Vec<PeerId> {
+            rng::set_thread_rng_seed(seed);
+            let picks = (0..32)
+                .map(|_| route_table.random_peer(&role).unwrap())
+                .collect();
+            rng::clear_thread_rng_seed();
+            picks
+        };
+
+        let first = picks(7);
+        assert_eq!(first, picks(7));
+        assert_ne!(first, picks(8));
+        assert!(first.iter().any(|&peer_id| peer_id != first[0]));
+    }
 }
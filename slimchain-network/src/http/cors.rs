@@ -0,0 +1,203 @@
+use crate::http::config::CorsConfig;
+use slimchain_common::error::{ensure, Context as _, Result};
+use warp::{
+    filters::BoxedFilter,
+    http::{header::HeaderName, Method, Uri},
+    Filter, Reply,
+};
+
+/// Build the CORS filter of `cfg`, after checking the configured values which would make
+/// warp panic. Return `None` if disabled.
+pub fn cors_from_config(cfg: &CorsConfig) -> Result<Option<warp::cors::Builder>> {
+    if !cfg.enabled {
+        return Ok(None);
+    }
+
+    let mut cors = warp::cors();
+    if cfg.allowed_origins.iter().any(|origin| origin == "*") {
+        cors = cors.allow_any_origin();
+    } else {
+        for origin in &cfg.allowed_origins {
+            let uri: Uri = origin
+                .parse()
+                .with_context(|| format!("Invalid CORS origin {:?}.", origin))?;
+            ensure!(
+                uri.scheme().is_some() && uri.authority().is_some(),
+                "Invalid CORS origin {:?}. It should be like https://example.com.",
+                origin
+            );
+        }
+        cors = cors.allow_origins(cfg.allowed_origins.iter().map(String::as_str));
+    }
+
+    let methods = cfg
+        .allowed_methods
+        .iter()
+        .map(|method| {
+            Method::from_bytes(method.as_bytes())
+                .with_context(|| format!("Invalid CORS method {:?}.", method))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let headers = cfg
+        .allowed_headers
+        .iter()
+        .map(|header| {
+            HeaderName::from_bytes(header.as_bytes())
+                .with_context(|| format!("Invalid CORS header {:?}.", header))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok(Some(
+        cors.allow_methods(methods)
+            .allow_headers(headers)
+            .max_age(cfg.max_age),
+    ))
+}
+
+/// Add the CORS headers of `cfg` to the replies of the client-facing `filter`. The preflights
+/// are answered without reaching `filter`, and the requests from the disallowed origins are
+/// rejected with 403. `filter` is left as it is if disabled.
+pub fn with_cors<R: Reply + 'static>(
+    cfg: &CorsConfig,
+    filter: BoxedFilter<(R,)>,
+) -> Result<BoxedFilter<(warp::reply::Response,)>> {
+    let filter = filter.map(Reply::into_response);
+    Ok(match cors_from_config(cfg)? {
+        Some(cors) => filter.with(cors).map(Reply::into_response).boxed(),
+        None => filter.boxed(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+    use warp::http::StatusCode;
+
+    const ORIGIN: &str = "https://demo.example.com";
+
+    fn cors_cfg() -> CorsConfig {
+        CorsConfig {
+            enabled: true,
+            allowed_origins: vec![ORIGIN.to_string()],
+            max_age: Duration::from_secs(600),
+            ..Default::default()
+        }
+    }
+
+    fn route(cfg: &CorsConfig, hits: Arc<AtomicUsize>) -> BoxedFilter<(warp::reply::Response,)> {
+        let handler = warp::path("block_height")
+            .map(move || {
+                hits.fetch_add(1, Ordering::SeqCst);
+                "42"
+            })
+            .boxed();
+        with_cors(cfg, handler).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_cors_simple_get() {
+        let hits = Arc::new(AtomicUsize::new(0));
+        let route = route(&cors_cfg(), hits.clone());
+        let resp = warp::test::request()
+            .path("/block_height")
+            .header("origin", ORIGIN)
+            .reply(&route)
+            .await;
+        assert_eq!(StatusCode::OK, resp.status());
+        assert_eq!(ORIGIN, resp.headers()["access-control-allow-origin"]);
+        assert_eq!("42", resp.body());
+        assert_eq!(1, hits.load(Ordering::SeqCst));
+
+        // The requests without an origin, e.g., from the other nodes, are left as they are.
+        let resp = warp::test::request()
+            .path("/block_height")
+            .reply(&route)
+            .await;
+        assert_eq!(StatusCode::OK, resp.status());
+        assert!(resp.headers().get("access-control-allow-origin").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_cors_preflight() {
+        let hits = Arc::new(AtomicUsize::new(0));
+        let route = route(&cors_cfg(), hits.clone());
+        let resp = warp::test::request()
+            .method("OPTIONS")
+            .path("/block_height")
+            .header("origin", ORIGIN)
+            .header("access-control-request-method", "POST")
+            .header("access-control-request-headers", "content-type")
+            .reply(&route)
+            .await;
+        assert_eq!(StatusCode::OK, resp.status());
+        assert_eq!(ORIGIN, resp.headers()["access-control-allow-origin"]);
+        assert_eq!("600", resp.headers()["access-control-max-age"]);
+        let methods = resp.headers()["access-control-allow-methods"]
+            .to_str()
+            .unwrap();
+        assert!(methods.contains("POST"));
+        assert!(methods.contains("GET"));
+        assert_eq!(0, hits.load(Ordering::SeqCst));
+
+        // The methods not allowed.
+        let resp = warp::test::request()
+            .method("OPTIONS")
+            .path("/block_height")
+            .header("origin", ORIGIN)
+            .header("access-control-request-method", "DELETE")
+            .reply(&route)
+            .await;
+        assert!(resp.headers().get("access-control-allow-origin").is_none());
+        assert_eq!(0, hits.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn test_cors_disallowed_origin() {
+        let hits = Arc::new(AtomicUsize::new(0));
+        let route = route(&cors_cfg(), hits.clone());
+        for method in &["GET", "OPTIONS"] {
+            let resp = warp::test::request()
+                .method(*method)
+                .path("/block_height")
+                .header("origin", "https://evil.example.com")
+                .header("access-control-request-method", "GET")
+                .reply(&route)
+                .await;
+            assert_eq!(StatusCode::FORBIDDEN, resp.status());
+            assert!(resp.headers().get("access-control-allow-origin").is_none());
+        }
+        assert_eq!(0, hits.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn test_cors_disabled() {
+        let hits = Arc::new(AtomicUsize::new(0));
+        let route = route(&CorsConfig::default(), hits.clone());
+        let resp = warp::test::request()
+            .path("/block_height")
+            .header("origin", ORIGIN)
+            .reply(&route)
+            .await;
+        assert_eq!(StatusCode::OK, resp.status());
+        assert!(resp.headers().get("access-control-allow-origin").is_none());
+        assert_eq!(1, hits.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_cors_config() {
+        let mut cfg = cors_cfg();
+        cfg.allowed_origins = vec!["*".to_string()];
+        assert!(cors_from_config(&cfg).unwrap().is_some());
+        cfg.allowed_origins = vec!["example.com".to_string()];
+        assert!(cors_from_config(&cfg).is_err());
+        let mut cfg = cors_cfg();
+        cfg.allowed_headers = vec!["bad header".to_string()];
+        assert!(cors_from_config(&cfg).is_err());
+    }
+}
@@ -0,0 +1,210 @@
+use crate::http::{
+    common::*,
+    config::{HealthCheckConfig, PeerId},
+    peer_health::PeerHealth,
+    route_table::SharedRouteTable,
+};
+use futures::prelude::*;
+use serde::{Deserialize, Serialize};
+use slimchain_chain::role::Role;
+use slimchain_common::{basic::BlockHeight, error::Result};
+use std::{sync::Arc, time::Duration};
+use tokio::task::JoinHandle;
+use warp::Filter;
+
+pub const HEALTH_ROUTE_PATH: &str = "health";
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct HealthReport {
+    pub peer_id: PeerId,
+    pub role: String,
+    pub block_height: BlockHeight,
+    /// Whether the node is ready to serve the requests of its role.
+    pub ready: bool,
+}
+
+pub async fn get_health(endpoint: &str, timeout: Duration) -> Result<HealthReport> {
+    send_get_request_using_json_with_timeout(
+        &format!("http://{}/{}", endpoint, HEALTH_ROUTE_PATH),
+        timeout,
+    )
+    .await
+}
+
+/// The `/health` route, which is not authenticated so that it can be used by the
+/// load balancers as well.
+pub fn health_server(
+    peer_id: PeerId,
+    role: Role,
+    block_height_fn: impl Fn() -> BlockHeight + Send + Sync + 'static,
+    ready_fn: impl Fn() -> bool + Send + Sync + 'static,
+) -> warp::filters::BoxedFilter<(impl warp::Reply,)> {
+    let role = role.to_string();
+    let block_height_fn = Arc::new(block_height_fn);
+    let ready_fn = Arc::new(ready_fn);
+    warp::get()
+        .and(warp::path(HEALTH_ROUTE_PATH))
+        .and(warp::path::end())
+        .map(move || {
+            warp::reply::json(&HealthReport {
+                peer_id,
+                role: role.clone(),
+                block_height: block_height_fn(),
+                ready: ready_fn(),
+            })
+        })
+        .boxed()
+}
+
+/// Spawn a task probing the health of all the peers in `route_table` every `cfg.interval`,
+/// and recording the outcomes in `peer_health`. A peer is up only if it answers within
+/// `cfg.timeout` and reports ready. Return `None` if disabled.
+pub fn spawn_health_prober(
+    route_table: SharedRouteTable,
+    peer_health: PeerHealth,
+    cfg: HealthCheckConfig,
+) -> Option<JoinHandle<()>> {
+    if cfg.interval == Duration::from_millis(0) {
+        return None;
+    }
+
+    Some(tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(cfg.interval);
+        loop {
+            ticker.tick().await;
+            let table = route_table.load();
+            let probes = table
+                .peer_table()
+                .iter()
+                .filter(|(peer_id, _)| **peer_id != table.peer_id())
+                .map(|(&peer_id, addr)| async move {
+                    let up = match get_health(addr, cfg.timeout).await {
+                        Ok(report) => report.ready,
+                        Err(e) => {
+                            debug!(%peer_id, "Health probe failed. Error: {}", e);
+                            false
+                        }
+                    };
+                    (peer_id, up)
+                });
+            for (peer_id, up) in future::join_all(probes).await {
+                peer_health.record_probe(peer_id, up, cfg.failure_threshold);
+            }
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::{config::NetworkConfig, route_table::RouteTableUpdate};
+    use slimchain_utils::{config::Config, toml};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use tokio::time::Instant;
+
+    fn fake_peer(peer_id: PeerId, answering: Arc<AtomicBool>) -> (String, JoinHandle<()>) {
+        let route = warp::get()
+            .and(warp::path(HEALTH_ROUTE_PATH))
+            .and_then(move || {
+                let answering = answering.load(Ordering::SeqCst);
+                async move {
+                    if !answering {
+                        tokio::time::sleep(Duration::from_secs(10)).await;
+                    }
+                    Ok::<_, warp::Rejection>(warp::reply::json(&HealthReport {
+                        peer_id,
+                        role: Role::Client.to_string(),
+                        block_height: BlockHeight::default(),
+                        ready: true,
+                    }))
+                }
+            });
+        let (addr, srv) = warp::serve(route).bind_ephemeral(([127, 0, 0, 1], 0));
+        (addr.to_string(), tokio::spawn(srv))
+    }
+
+    async fn wait_until(window: Duration, cond: impl Fn() -> bool) -> bool {
+        let deadline = Instant::now() + window;
+        while Instant::now() < deadline {
+            if cond() {
+                return true;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        cond()
+    }
+
+    #[tokio::test]
+    async fn test_health_prober() {
+        let flaky_answering = Arc::new(AtomicBool::new(true));
+        let (flaky_addr, flaky_srv) = fake_peer(PeerId(2), flaky_answering.clone());
+        let (stable_addr, stable_srv) = fake_peer(PeerId(3), Arc::new(AtomicBool::new(true)));
+
+        let input = toml::toml! {
+            [network]
+            peer_id = 1
+
+            [[network.peers]]
+            peer_id = 1
+            address = "127.0.0.1:8001"
+        };
+        let net_cfg: NetworkConfig = Config::from_toml(input).get("network").unwrap();
+        let route_table = SharedRouteTable::new(net_cfg.to_route_table());
+        for (peer_id, address) in vec![(PeerId(2), flaky_addr), (PeerId(3), stable_addr)] {
+            route_table
+                .apply_update(RouteTableUpdate::AddPeer {
+                    peer_id,
+                    address,
+                    role: Role::Client,
+                })
+                .unwrap();
+        }
+
+        let cfg = HealthCheckConfig {
+            interval: Duration::from_millis(50),
+            timeout: Duration::from_millis(200),
+            failure_threshold: 2,
+        };
+        // Allow some slack on top of the time taken by the failed probes.
+        let window = (cfg.interval + cfg.timeout) * (cfg.failure_threshold + 1) * 2;
+        let peer_health = PeerHealth::new();
+        let prober = spawn_health_prober(route_table, peer_health.clone(), cfg).unwrap();
+        let peers = [PeerId(2), PeerId(3)];
+
+        tokio::time::sleep(window).await;
+        assert!(!peer_health.is_down(PeerId(2)));
+        assert_eq!(2, peer_health.rank(&peers).len());
+
+        flaky_answering.store(false, Ordering::SeqCst);
+        assert!(wait_until(window, || peer_health.is_down(PeerId(2))).await);
+        assert_eq!(vec![PeerId(3)], peer_health.rank(&peers));
+        assert_eq!(Some(PeerId(3)), peer_health.random_peer(&peers));
+        assert!(!peer_health.is_down(PeerId(3)));
+
+        flaky_answering.store(true, Ordering::SeqCst);
+        assert!(wait_until(window, || !peer_health.is_down(PeerId(2))).await);
+        assert_eq!(2, peer_health.rank(&peers).len());
+        assert_eq!(2, peer_health.probe_transitions(PeerId(2)));
+        assert_eq!(0, peer_health.probe_transitions(PeerId(3)));
+
+        prober.abort();
+        flaky_srv.abort();
+        stable_srv.abort();
+    }
+
+    #[tokio::test]
+    async fn test_health_server() {
+        let route = health_server(PeerId(1), Role::Observer, || 5u64.into(), || false);
+        let resp = warp::test::request().path("/health").reply(&route).await;
+        let report: HealthReport = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(
+            HealthReport {
+                peer_id: PeerId(1),
+                role: Role::Observer.to_string(),
+                block_height: 5u64.into(),
+                ready: false,
+            },
+            report
+        );
+    }
+}
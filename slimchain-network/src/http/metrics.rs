@@ -0,0 +1,73 @@
+use slimchain_utils::prometheus::{CONTENT_TYPE, REGISTRY};
+use warp::Filter;
+
+pub const METRICS_ROUTE_PATH: &str = "metrics";
+
+/// The `/metrics` route rendering the Prometheus [`REGISTRY`], which is not authenticated so
+/// that it can be scraped like `/health`.
+pub fn metrics_server() -> warp::filters::BoxedFilter<(impl warp::Reply,)> {
+    warp::get()
+        .and(warp::path(METRICS_ROUTE_PATH))
+        .and(warp::path::end())
+        .map(|| warp::reply::with_header(REGISTRY.render(), "content-type", CONTENT_TYPE))
+        .boxed()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use slimchain_chain::metrics::register_chain_metrics_collector;
+    use slimchain_utils::prometheus::parse_samples;
+    use warp::{http::StatusCode, hyper};
+
+    #[tokio::test]
+    async fn test_scrape_metrics() {
+        REGISTRY.set_const_labels(&[("role", "storage"), ("shard", "0/1")]);
+        register_chain_metrics_collector();
+        REGISTRY
+            .counter(
+                "slimchain_test_requests_total",
+                "The test requests.",
+                &[("peer", "2")],
+            )
+            .inc_by(3);
+
+        let (addr, srv) = warp::serve(metrics_server()).bind_ephemeral(([127, 0, 0, 1], 0));
+        let srv_handle = tokio::spawn(srv);
+        let uri = format!("http://{}/{}", addr, METRICS_ROUTE_PATH);
+        let resp = hyper::Client::new()
+            .get(uri.parse().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::OK, resp.status());
+        assert_eq!(CONTENT_TYPE, resp.headers()["content-type"]);
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        let text = String::from_utf8(body.to_vec()).unwrap();
+        assert!(text.contains("# TYPE slimchain_test_requests_total counter\n"));
+
+        let samples = parse_samples(&text).unwrap();
+        let requests = samples
+            .iter()
+            .find(|s| s.name == "slimchain_test_requests_total")
+            .unwrap();
+        assert_eq!(3., requests.value);
+        assert_eq!("2", requests.labels["peer"]);
+        assert_eq!("storage", requests.labels["role"]);
+        assert_eq!("0/1", requests.labels["shard"]);
+        for name in &[
+            "slimchain_chain_committed_blocks_total",
+            "slimchain_chain_pending_queue_depth",
+        ] {
+            let sample = samples.iter().find(|s| s.name == *name).unwrap();
+            assert_eq!("storage", sample.labels["role"]);
+        }
+        let latency: Vec<_> = samples
+            .iter()
+            .filter(|s| s.name == "slimchain_chain_tx_latency_seconds")
+            .map(|s| s.labels["stat"].as_str())
+            .collect();
+        assert_eq!(vec!["mean", "min", "max"], latency);
+
+        srv_handle.abort();
+    }
+}
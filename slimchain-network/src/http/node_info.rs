@@ -0,0 +1,367 @@
+use crate::http::{
+    common::*,
+    config::{NodeInfoConfig, PeerId},
+};
+use serde::{Deserialize, Serialize};
+use slimchain_chain::{block::BlockTrait, role::Role};
+use slimchain_common::{
+    basic::H256,
+    collections::HashMap,
+    digest::Digestible,
+    error::{Context as _, Result},
+};
+use std::{
+    fmt,
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+use warp::Filter;
+
+pub const NODE_INFO_ROUTE_PATH: &str = "node_info";
+
+/// The node RPC API versions supported by this build. Bump `max` when the node RPCs change,
+/// and `min` when the older ones are no longer served.
+pub const NODE_RPC_API_VERSION: ApiVersionRange = ApiVersionRange { min: 1, max: 1 };
+
+/// The version of the binary layout of the types sent between the nodes, e.g., the tx
+/// proposals, the block proposals and the raft messages. Bump it whenever the golden fixtures
+/// in `slimchain-network/golden` are regenerated.
+pub const WIRE_FORMAT_VERSION: u32 = 3;
+
+/// An inclusive range of the node RPC API versions.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ApiVersionRange {
+    pub min: u32,
+    pub max: u32,
+}
+
+impl ApiVersionRange {
+    pub fn overlaps(&self, other: &Self) -> bool {
+        self.min <= other.max && other.min <= self.max
+    }
+}
+
+impl fmt::Display for ApiVersionRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[{}, {}]", self.min, self.max)
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct NodeInfo {
+    /// The software version of the node.
+    pub version: String,
+    pub api_version: ApiVersionRange,
+    /// The [`WIRE_FORMAT_VERSION`] of the node. It is 0 for the nodes built before it exists.
+    #[serde(default)]
+    pub wire_format_version: u32,
+    pub role: String,
+    pub network_id: String,
+    pub genesis_hash: H256,
+}
+
+impl NodeInfo {
+    pub fn new(role: Role, network_id: String, genesis_hash: H256) -> Self {
+        Self {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            api_version: NODE_RPC_API_VERSION,
+            wire_format_version: WIRE_FORMAT_VERSION,
+            role: role.to_string(),
+            network_id,
+            genesis_hash,
+        }
+    }
+
+    /// The info of this node, whose genesis block is `Block::genesis_block()`.
+    pub fn local<Block: BlockTrait>(role: Role, cfg: &NodeInfoConfig) -> Self {
+        Self::new(
+            role,
+            cfg.network_id.clone(),
+            Block::genesis_block().to_digest(),
+        )
+    }
+
+    /// Whether this node can talk to the `peer`, i.e., they share an API version, the wire
+    /// format, the network id and the genesis block.
+    pub fn check_compatible(&self, peer: &NodeInfo) -> Result<(), IncompatibleNodeInfo> {
+        if !self.api_version.overlaps(&peer.api_version) {
+            return Err(IncompatibleNodeInfo::ApiVersion {
+                local: self.api_version,
+                peer: peer.api_version,
+                peer_version: peer.version.clone(),
+            });
+        }
+        if self.wire_format_version != peer.wire_format_version {
+            return Err(IncompatibleNodeInfo::WireFormat {
+                local: self.wire_format_version,
+                peer: peer.wire_format_version,
+                peer_version: peer.version.clone(),
+            });
+        }
+        if self.network_id != peer.network_id {
+            return Err(IncompatibleNodeInfo::NetworkId {
+                local: self.network_id.clone(),
+                peer: peer.network_id.clone(),
+            });
+        }
+        if self.genesis_hash != peer.genesis_hash {
+            return Err(IncompatibleNodeInfo::GenesisHash {
+                local: self.genesis_hash,
+                peer: peer.genesis_hash,
+            });
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, thiserror::Error)]
+pub enum IncompatibleNodeInfo {
+    #[error("Incompatible API versions. Peer (v{peer_version}): {peer}. This node: {local}.")]
+    ApiVersion {
+        local: ApiVersionRange,
+        peer: ApiVersionRange,
+        peer_version: String,
+    },
+    #[error("Incompatible wire formats. Peer (v{peer_version}): {peer}. This node: {local}.")]
+    WireFormat {
+        local: u32,
+        peer: u32,
+        peer_version: String,
+    },
+    #[error("The peer is in the network {peer:?}, but this node is in {local:?}.")]
+    NetworkId { local: String, peer: String },
+    #[error("The peer has the genesis block {peer:?}, but this node has {local:?}.")]
+    GenesisHash { local: H256, peer: H256 },
+}
+
+pub async fn get_node_info(endpoint: &str, timeout: Duration) -> Result<NodeInfo> {
+    send_get_request_using_json_with_timeout(
+        &format!("http://{}/{}", endpoint, NODE_INFO_ROUTE_PATH),
+        timeout,
+    )
+    .await
+}
+
+/// The `/node_info` route, which is not authenticated so that the peers can check it before
+/// sending any node RPC.
+pub fn node_info_server(info: NodeInfo) -> warp::filters::BoxedFilter<(impl warp::Reply,)> {
+    warp::get()
+        .and(warp::path(NODE_INFO_ROUTE_PATH))
+        .and(warp::path::end())
+        .map(move || warp::reply::json(&info))
+        .boxed()
+}
+
+#[derive(Debug, Clone)]
+struct CachedNodeInfo {
+    compatible: Result<(), IncompatibleNodeInfo>,
+    fetched_at: Instant,
+}
+
+/// Cache the compatibility of the peers with this node. A peer is checked on the first
+/// contact, and again once its entry is older than `cfg.refresh_interval`.
+#[derive(Debug, Clone)]
+pub struct NodeInfoCache {
+    local: Arc<NodeInfo>,
+    refresh_interval: Duration,
+    timeout: Duration,
+    peers: Arc<Mutex<HashMap<PeerId, CachedNodeInfo>>>,
+}
+
+impl NodeInfoCache {
+    pub fn new(local: NodeInfo, cfg: &NodeInfoConfig) -> Self {
+        Self {
+            local: Arc::new(local),
+            refresh_interval: cfg.refresh_interval,
+            timeout: cfg.timeout,
+            peers: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub fn local(&self) -> &NodeInfo {
+        &self.local
+    }
+
+    fn peers(&self) -> MutexGuard<'_, HashMap<PeerId, CachedNodeInfo>> {
+        self.peers.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Return an error if `peer_id` at `addr` is incompatible with this node, or its node info
+    /// cannot be fetched. The latter is not cached, so that it is retried on the next contact.
+    pub async fn ensure_compatible(&self, peer_id: PeerId, addr: &str) -> Result<()> {
+        let cached = self.peers().get(&peer_id).cloned();
+        if let Some(cached) = cached {
+            if cached.fetched_at.elapsed() < self.refresh_interval {
+                return cached.compatible.map_err(Into::into);
+            }
+        }
+
+        let info = get_node_info(addr, self.timeout)
+            .await
+            .with_context(|| format!("Failed to get the node info of peer {}.", peer_id))?;
+        let compatible = self.local.check_compatible(&info);
+        match &compatible {
+            Ok(()) => debug!(%peer_id, version = %info.version, "Peer is compatible."),
+            Err(e) => error!(%peer_id, "Refuse to talk to the incompatible peer. {}", e),
+        }
+        self.peers().insert(
+            peer_id,
+            CachedNodeInfo {
+                compatible: compatible.clone(),
+                fetched_at: Instant::now(),
+            },
+        );
+        compatible.map_err(Into::into)
+    }
+
+    /// Drop the cached entry of `peer_id`, so that it is checked again on the next contact.
+    pub fn invalidate(&self, peer_id: PeerId) {
+        self.peers().remove(&peer_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use slimchain_common::basic::ShardId;
+    use tokio::task::JoinHandle;
+
+    fn local_info() -> NodeInfo {
+        NodeInfo::new(Role::Client, "test".to_string(), H256::repeat_byte(1))
+    }
+
+    fn fake_peer(info: NodeInfo) -> (String, JoinHandle<()>) {
+        let (addr, srv) = warp::serve(node_info_server(info)).bind_ephemeral(([127, 0, 0, 1], 0));
+        (addr.to_string(), tokio::spawn(srv))
+    }
+
+    fn cache() -> NodeInfoCache {
+        NodeInfoCache::new(
+            local_info(),
+            &NodeInfoConfig {
+                refresh_interval: Duration::from_secs(60),
+                ..Default::default()
+            },
+        )
+    }
+
+    #[tokio::test]
+    async fn test_node_info_server() {
+        let route = node_info_server(local_info());
+        let resp = warp::test::request().path("/node_info").reply(&route).await;
+        let info: NodeInfo = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(local_info(), info);
+        assert_eq!(env!("CARGO_PKG_VERSION"), info.version);
+        assert_eq!(NODE_RPC_API_VERSION, info.api_version);
+        assert_eq!(WIRE_FORMAT_VERSION, info.wire_format_version);
+    }
+
+    #[tokio::test]
+    async fn test_compatible_peer() {
+        let mut info = NodeInfo::new(
+            Role::Storage(ShardId::new(0, 1)),
+            "test".to_string(),
+            H256::repeat_byte(1),
+        );
+        // The peer running a newer build which still serves this API version.
+        info.version = "0.2.0".to_string();
+        info.api_version = ApiVersionRange { min: 1, max: 2 };
+        let (addr, srv) = fake_peer(info);
+
+        let cache = cache();
+        cache.ensure_compatible(PeerId(2), &addr).await.unwrap();
+
+        // Answered by the cache once the peer is gone.
+        srv.abort();
+        srv.await.ok();
+        cache.ensure_compatible(PeerId(2), &addr).await.unwrap();
+        cache.invalidate(PeerId(2));
+        assert!(cache.ensure_compatible(PeerId(2), &addr).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_incompatible_api_version_peer() {
+        let mut info = local_info();
+        info.api_version = ApiVersionRange { min: 2, max: 3 };
+        let (addr, srv) = fake_peer(info);
+
+        let cache = cache();
+        let err = cache.ensure_compatible(PeerId(2), &addr).await.unwrap_err();
+        assert_eq!(
+            Some(&IncompatibleNodeInfo::ApiVersion {
+                local: NODE_RPC_API_VERSION,
+                peer: ApiVersionRange { min: 2, max: 3 },
+                peer_version: env!("CARGO_PKG_VERSION").to_string(),
+            }),
+            err.downcast_ref::<IncompatibleNodeInfo>()
+        );
+
+        // The incompatible peers are cached as well.
+        srv.abort();
+        srv.await.ok();
+        let err = cache.ensure_compatible(PeerId(2), &addr).await.unwrap_err();
+        assert!(err.downcast_ref::<IncompatibleNodeInfo>().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_incompatible_wire_format_peer() {
+        let mut info = local_info();
+        info.wire_format_version = WIRE_FORMAT_VERSION + 1;
+        let (addr, srv) = fake_peer(info);
+        let err = cache()
+            .ensure_compatible(PeerId(2), &addr)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            Some(&IncompatibleNodeInfo::WireFormat {
+                local: WIRE_FORMAT_VERSION,
+                peer: WIRE_FORMAT_VERSION + 1,
+                peer_version: env!("CARGO_PKG_VERSION").to_string(),
+            }),
+            err.downcast_ref::<IncompatibleNodeInfo>()
+        );
+        srv.abort();
+
+        // The node info of the older builds comes without the wire format version.
+        let mut json = serde_json::to_value(local_info()).unwrap();
+        json.as_object_mut().unwrap().remove("wire_format_version");
+        let info: NodeInfo = serde_json::from_value(json).unwrap();
+        assert_eq!(0, info.wire_format_version);
+        assert!(matches!(
+            local_info().check_compatible(&info),
+            Err(IncompatibleNodeInfo::WireFormat { peer: 0, .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_mismatched_genesis_peer() {
+        let mut info = local_info();
+        info.genesis_hash = H256::repeat_byte(2);
+        let (addr, srv) = fake_peer(info);
+        let err = cache()
+            .ensure_compatible(PeerId(2), &addr)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            Some(&IncompatibleNodeInfo::GenesisHash {
+                local: H256::repeat_byte(1),
+                peer: H256::repeat_byte(2),
+            }),
+            err.downcast_ref::<IncompatibleNodeInfo>()
+        );
+        srv.abort();
+
+        let mut info = local_info();
+        info.network_id = "other".to_string();
+        let (addr, srv) = fake_peer(info);
+        let err = cache()
+            .ensure_compatible(PeerId(3), &addr)
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<IncompatibleNodeInfo>(),
+            Some(IncompatibleNodeInfo::NetworkId { .. })
+        ));
+        srv.abort();
+    }
+}
@@ -1,18 +1,90 @@
-use super::{common::*, config::PeerId};
-use serde::Serialize;
-use slimchain_common::error::Result;
+use super::{
+    admin::SignedAdminRequest,
+    common::*,
+    config::{HttpClientConfig, PeerId},
+    route_table::SignedRouteTableUpdate,
+};
+use serde::{Deserialize, Serialize};
+use slimchain_chain::{
+    block::BlockTrait,
+    block_proposal::BlockProposal,
+    checkpoint::{Checkpoint, CheckpointData},
+    cross_shard::CrossShardTxProposal,
+    db::{backup::BackupManifest, integrity::IntegrityReport},
+};
+use slimchain_common::{
+    basic::{BlockHeight, H256},
+    error::Result,
+    tx::TxTrait,
+};
+use slimchain_utils::{bytes::Bytes, serde::binary_encode};
+use std::{collections::BTreeSet, time::Duration};
 
 pub const NODE_RPC_ROUTE_PATH: &str = "node_rpc";
 
 pub const RAFT_APPEND_ENTRIES_ROUTE_PATH: &str = "raft_append_entries";
 pub const RAFT_INSTALL_SNAPSHOT_ROUTE_PATH: &str = "raft_install_snapshot";
 pub const RAFT_VOTE_ROUTE_PATH: &str = "raft_vote";
+pub const RAFT_SNAPSHOT_BEGIN_ROUTE_PATH: &str = "raft_snapshot_begin";
+pub const RAFT_SNAPSHOT_CHUNK_ROUTE_PATH: &str = "raft_snapshot_chunk";
+pub const RAFT_SNAPSHOT_END_ROUTE_PATH: &str = "raft_snapshot_end";
 
 pub const STORAGE_BLOCK_IMPORT_ROUTE_PATH: &str = "storage_block_import";
 pub const STORAGE_TX_REQ_ROUTE_PATH: &str = "storage_tx_req";
+pub const STORAGE_CHECKPOINT_ROUTE_PATH: &str = "storage_checkpoint";
+pub const STORAGE_CHECKPOINT_DATA_ROUTE_PATH: &str = "storage_checkpoint_data";
+pub const STORAGE_STATE_NODES_ROUTE_PATH: &str = "storage_state_nodes";
+pub const STORAGE_BLOCK_PROPOSALS_ROUTE_PATH: &str = "storage_block_proposals";
+pub const STORAGE_GET_BLOCKS_ROUTE_PATH: &str = "get_blocks";
+pub const STORAGE_CROSS_SHARD_PREPARE_ROUTE_PATH: &str = "storage_cross_shard_prepare";
+pub const STORAGE_CHECK_INTEGRITY_ROUTE_PATH: &str = "storage_check_integrity";
+pub const STORAGE_BACKUP_ROUTE_PATH: &str = "storage_backup";
+
+pub const OBSERVER_BLOCK_IMPORT_ROUTE_PATH: &str = "observer_block_import";
+
+/// Max number of the block proposals sent in one block import.
+pub const MAX_BLOCK_PROPOSALS_PER_IMPORT: usize = 8;
 
 pub const CLIENT_LEADER_ID_ROUTE_PATH: &str = "leader_id";
 pub const CLIENT_LEADER_REQ_ROUTE_PATH: &str = "leader_req";
+pub const CLIENT_LEADER_CROSS_SHARD_REQ_ROUTE_PATH: &str = "leader_cross_shard_req";
+pub const CLIENT_ROUTE_TABLE_UPDATE_ROUTE_PATH: &str = "route_table_update";
+pub const CLIENT_MEMBERSHIP_ROUTE_PATH: &str = "membership";
+pub const CLIENT_ADD_MEMBER_ROUTE_PATH: &str = "add_member";
+pub const CLIENT_REMOVE_MEMBER_ROUTE_PATH: &str = "remove_member";
+pub const CLIENT_TRANSFER_LEADERSHIP_ROUTE_PATH: &str = "transfer_leadership";
+pub const CLIENT_ADD_LEARNER_ROUTE_PATH: &str = "add_learner";
+
+/// The query of the block proposals in the heights `from..to`.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct GetBlocksQuery {
+    pub from: u64,
+    pub to: u64,
+}
+
+/// The members of the raft cluster of the client nodes.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct RaftMembership {
+    pub members: BTreeSet<PeerId>,
+    /// The members after the change in progress if any, which are agreed on with the current
+    /// ones before they take over.
+    pub members_after_consensus: Option<BTreeSet<PeerId>>,
+}
+
+/// Send the JSON encoded `req` to the node RPC `route` of the peer at `endpoint`, which must
+/// have `allow_json` set in its [`NodeRpcEncodingConfig`](super::config::NodeRpcEncodingConfig).
+/// It is meant for debugging.
+pub async fn send_node_rpc_using_json<Req: Serialize, Resp: for<'de> Deserialize<'de>>(
+    endpoint: &str,
+    route: &str,
+    req: &Req,
+) -> Result<Resp> {
+    send_post_request_using_json(
+        &format!("http://{}/{}/{}", endpoint, NODE_RPC_ROUTE_PATH, route),
+        req,
+    )
+    .await
+}
 
 pub async fn get_leader(endpoint: &str) -> Result<PeerId> {
     send_get_request_using_binary(&format!(
@@ -24,12 +96,246 @@ pub async fn get_leader(endpoint: &str) -> Result<PeerId> {
 
 #[allow(clippy::ptr_arg)]
 pub async fn send_reqs_to_leader<Req: Serialize>(endpoint: &str, reqs: &Vec<Req>) -> Result<()> {
-    send_post_request_using_binary(
+    send_reqs_to_leader_with_timeout(endpoint, reqs, HttpClientConfig::get().request_timeout).await
+}
+
+#[allow(clippy::ptr_arg)]
+pub async fn send_reqs_to_leader_with_timeout<Req: Serialize>(
+    endpoint: &str,
+    reqs: &Vec<Req>,
+    timeout: Duration,
+) -> Result<()> {
+    let body = BinaryBody::new(Bytes::from(binary_encode(reqs)?));
+    send_post_request_using_binary_body_with_timeout(
         &format!(
             "http://{}/{}/{}",
             endpoint, NODE_RPC_ROUTE_PATH, CLIENT_LEADER_REQ_ROUTE_PATH,
         ),
-        reqs,
+        &body,
+        timeout,
+    )
+    .await
+}
+
+/// Send the parts of the cross-shard txs to the raft leader at `endpoint`.
+#[allow(clippy::ptr_arg)]
+pub async fn send_cross_shard_reqs_to_leader<Tx: TxTrait + Serialize>(
+    endpoint: &str,
+    parts: &Vec<CrossShardTxProposal<Tx>>,
+) -> Result<()> {
+    send_post_request_using_binary(
+        &format!(
+            "http://{}/{}/{}",
+            endpoint, NODE_RPC_ROUTE_PATH, CLIENT_LEADER_CROSS_SHARD_REQ_ROUTE_PATH
+        ),
+        parts,
+    )
+    .await
+}
+
+/// Send the parts of the cross-shard txs to the storage node at `endpoint` of their shard,
+/// which prepares and forwards them to the raft leader.
+#[allow(clippy::ptr_arg)]
+pub async fn send_cross_shard_prepare<Tx: TxTrait + Serialize>(
+    endpoint: &str,
+    parts: &Vec<CrossShardTxProposal<Tx>>,
+) -> Result<()> {
+    send_post_request_using_binary(
+        &format!(
+            "http://{}/{}/{}",
+            endpoint, NODE_RPC_ROUTE_PATH, STORAGE_CROSS_SHARD_PREPARE_ROUTE_PATH
+        ),
+        parts,
     )
     .await
 }
+
+/// Get the members of the raft cluster known by the client node at `endpoint`.
+pub async fn get_raft_membership(endpoint: &str) -> Result<RaftMembership> {
+    send_get_request_using_binary(&format!(
+        "http://{}/{}/{}",
+        endpoint, NODE_RPC_ROUTE_PATH, CLIENT_MEMBERSHIP_ROUTE_PATH
+    ))
+    .await
+}
+
+/// Ask the raft leader at `endpoint` to add a client node to the cluster by the signed
+/// `AdminRequest::AddMember`. It returns once the node has caught up with the log and the
+/// membership is changed.
+pub async fn add_raft_member(endpoint: &str, req: &SignedAdminRequest) -> Result<RaftMembership> {
+    send_post_request_using_binary(
+        &format!(
+            "http://{}/{}/{}",
+            endpoint, NODE_RPC_ROUTE_PATH, CLIENT_ADD_MEMBER_ROUTE_PATH
+        ),
+        req,
+    )
+    .await
+}
+
+/// Ask the raft leader at `endpoint` to add a learner as a non-voter by the signed
+/// `AdminRequest::AddLearner`. It returns once the node has caught up with the log.
+pub async fn add_raft_learner(endpoint: &str, req: &SignedAdminRequest) -> Result<()> {
+    send_post_request_using_binary(
+        &format!(
+            "http://{}/{}/{}",
+            endpoint, NODE_RPC_ROUTE_PATH, CLIENT_ADD_LEARNER_ROUTE_PATH
+        ),
+        req,
+    )
+    .await
+}
+
+/// Ask the raft leader at `endpoint` to remove a client node from the cluster by the signed
+/// `AdminRequest::RemoveMember`.
+pub async fn remove_raft_member(
+    endpoint: &str,
+    req: &SignedAdminRequest,
+) -> Result<RaftMembership> {
+    send_post_request_using_binary(
+        &format!(
+            "http://{}/{}/{}",
+            endpoint, NODE_RPC_ROUTE_PATH, CLIENT_REMOVE_MEMBER_ROUTE_PATH
+        ),
+        req,
+    )
+    .await
+}
+
+/// Ask the raft leader at `endpoint` to transfer its leadership by the signed
+/// `AdminRequest::TransferLeadership`. It returns the new leader once the target takes over.
+/// `timeout` has to cover the leader transfer timeout of the leader.
+pub async fn transfer_raft_leadership(
+    endpoint: &str,
+    req: &SignedAdminRequest,
+    timeout: Duration,
+) -> Result<PeerId> {
+    send_post_request_using_binary_with_timeout(
+        &format!(
+            "http://{}/{}/{}",
+            endpoint, NODE_RPC_ROUTE_PATH, CLIENT_TRANSFER_LEADERSHIP_ROUTE_PATH
+        ),
+        req,
+        timeout,
+    )
+    .await
+}
+
+/// Ask the storage node at `endpoint` to check the integrity of its database by the signed
+/// `AdminRequest::CheckIntegrity`. `timeout` has to cover reading the database.
+pub async fn check_storage_integrity(
+    endpoint: &str,
+    req: &SignedAdminRequest,
+    timeout: Duration,
+) -> Result<IntegrityReport> {
+    send_post_request_using_binary_with_timeout(
+        &format!(
+            "http://{}/{}/{}",
+            endpoint, NODE_RPC_ROUTE_PATH, STORAGE_CHECK_INTEGRITY_ROUTE_PATH
+        ),
+        req,
+        timeout,
+    )
+    .await
+}
+
+/// Ask the storage node at `endpoint` to back up its database by the signed
+/// `AdminRequest::Backup`. It returns the manifest of the backup once written. `timeout` has to
+/// cover copying the database.
+pub async fn backup_storage(
+    endpoint: &str,
+    req: &SignedAdminRequest,
+    timeout: Duration,
+) -> Result<BackupManifest> {
+    send_post_request_using_binary_with_timeout(
+        &format!(
+            "http://{}/{}/{}",
+            endpoint, NODE_RPC_ROUTE_PATH, STORAGE_BACKUP_ROUTE_PATH
+        ),
+        req,
+        timeout,
+    )
+    .await
+}
+
+/// Send a `SignedRouteTableUpdate` to the client node at `endpoint`.
+pub async fn send_route_table_update(
+    endpoint: &str,
+    update: &SignedRouteTableUpdate,
+) -> Result<()> {
+    send_post_request_using_binary(
+        &format!(
+            "http://{}/{}/{}",
+            endpoint, NODE_RPC_ROUTE_PATH, CLIENT_ROUTE_TABLE_UPDATE_ROUTE_PATH
+        ),
+        update,
+    )
+    .await
+}
+
+pub async fn get_checkpoint(endpoint: &str) -> Result<Option<Checkpoint>> {
+    send_get_request_using_binary(&format!(
+        "http://{}/{}/{}",
+        endpoint, NODE_RPC_ROUTE_PATH, STORAGE_CHECKPOINT_ROUTE_PATH
+    ))
+    .await
+}
+
+pub async fn get_checkpoint_data<Block>(endpoint: &str) -> Result<CheckpointData<Block>>
+where
+    Block: BlockTrait + for<'de> Deserialize<'de>,
+{
+    send_get_request_using_binary(&format!(
+        "http://{}/{}/{}",
+        endpoint, NODE_RPC_ROUTE_PATH, STORAGE_CHECKPOINT_DATA_ROUTE_PATH
+    ))
+    .await
+}
+
+#[allow(clippy::ptr_arg)]
+pub async fn get_state_nodes(endpoint: &str, hashes: &Vec<H256>) -> Result<Vec<Option<Vec<u8>>>> {
+    send_post_request_using_binary(
+        &format!(
+            "http://{}/{}/{}",
+            endpoint, NODE_RPC_ROUTE_PATH, STORAGE_STATE_NODES_ROUTE_PATH
+        ),
+        hashes,
+    )
+    .await
+}
+
+pub async fn get_block_proposals<Block, Tx>(
+    endpoint: &str,
+    from_height: BlockHeight,
+) -> Result<Vec<BlockProposal<Block, Tx>>>
+where
+    Block: BlockTrait + for<'de> Deserialize<'de>,
+    Tx: TxTrait + for<'de> Deserialize<'de>,
+{
+    send_post_request_using_binary(
+        &format!(
+            "http://{}/{}/{}",
+            endpoint, NODE_RPC_ROUTE_PATH, STORAGE_BLOCK_PROPOSALS_ROUTE_PATH
+        ),
+        &from_height,
+    )
+    .await
+}
+
+/// Get the block proposals in the heights `from..to`. The peer returns at most a bounded number
+/// of them starting from `from`, or none if it does not have the block at `from`.
+pub async fn get_blocks<Block, Tx>(
+    endpoint: &str,
+    from: BlockHeight,
+    to: BlockHeight,
+) -> Result<Vec<BlockProposal<Block, Tx>>>
+where
+    Block: BlockTrait + for<'de> Deserialize<'de>,
+    Tx: TxTrait + for<'de> Deserialize<'de>,
+{
+    send_get_request_using_binary(&format!(
+        "http://{}/{}/{}?from={}&to={}",
+        endpoint, NODE_RPC_ROUTE_PATH, STORAGE_GET_BLOCKS_ROUTE_PATH, from.0, to.0
+    ))
+    .await
+}
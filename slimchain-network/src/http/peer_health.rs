@@ -0,0 +1,345 @@
+use crate::http::{common::is_retryable_error, config::PeerId};
+use rand::seq::{IteratorRandom, SliceRandom};
+use slimchain_common::{
+    collections::HashMap,
+    error::{anyhow, Result},
+};
+use slimchain_utils::{record_event, rng::rng};
+use std::{
+    future::Future,
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+/// The failure score of a peer is halved every this duration.
+const FAILURE_HALF_LIFE: Duration = Duration::from_secs(10);
+
+/// A peer is marked down after this number of consecutive failures.
+const DOWN_THRESHOLD: u32 = 3;
+
+/// How long a peer stays marked down after its last failure.
+const DOWN_DURATION: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Copy, Clone)]
+struct PeerFailure {
+    score: f64,
+    consecutive: u32,
+    last_failure: Instant,
+}
+
+impl PeerFailure {
+    fn score_at(&self, now: Instant) -> f64 {
+        let elapsed = now.saturating_duration_since(self.last_failure);
+        self.score * 0.5f64.powf(elapsed.as_secs_f64() / FAILURE_HALF_LIFE.as_secs_f64())
+    }
+
+    fn is_down_at(&self, now: Instant) -> bool {
+        self.consecutive >= DOWN_THRESHOLD
+            && now.saturating_duration_since(self.last_failure) < DOWN_DURATION
+    }
+}
+
+#[derive(Debug, Default, Copy, Clone)]
+struct PeerProbe {
+    consecutive_failures: u32,
+    down: bool,
+    transitions: u64,
+}
+
+/// Track the recent request failures and the health probes of the peers, shared by all the
+/// senders of a node.
+#[derive(Debug, Default, Clone)]
+pub struct PeerHealth {
+    failures: Arc<Mutex<HashMap<PeerId, PeerFailure>>>,
+    probes: Arc<Mutex<HashMap<PeerId, PeerProbe>>>,
+}
+
+impl PeerHealth {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn failures(&self) -> MutexGuard<'_, HashMap<PeerId, PeerFailure>> {
+        self.failures.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn probes(&self) -> MutexGuard<'_, HashMap<PeerId, PeerProbe>> {
+        self.probes.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn is_probed_down(&self, peer_id: PeerId) -> bool {
+        self.probes()
+            .get(&peer_id)
+            .map_or(false, |probe| probe.down)
+    }
+
+    /// Record the outcome of a health probe. The peer is marked down after `failure_threshold`
+    /// failed probes in a row, and up again after a successful one.
+    pub fn record_probe(&self, peer_id: PeerId, up: bool, failure_threshold: u32) {
+        let mut probes = self.probes();
+        let probe = probes.entry(peer_id).or_default();
+        if up {
+            probe.consecutive_failures = 0;
+            if probe.down {
+                probe.down = false;
+                probe.transitions += 1;
+                info!(%peer_id, "Peer is up.");
+                record_event!("peer_up", "peer_id": peer_id.0);
+            }
+        } else {
+            probe.consecutive_failures += 1;
+            if !probe.down && probe.consecutive_failures >= failure_threshold.max(1) {
+                probe.down = true;
+                probe.transitions += 1;
+                warn!(
+                    %peer_id,
+                    "Peer is down. Failed {} health probes in a row.", probe.consecutive_failures
+                );
+                record_event!("peer_down", "peer_id": peer_id.0);
+            }
+        }
+    }
+
+    /// The number of times the peer is marked down or up by the health probes.
+    pub fn probe_transitions(&self, peer_id: PeerId) -> u64 {
+        self.probes()
+            .get(&peer_id)
+            .map_or(0, |probe| probe.transitions)
+    }
+
+    pub fn record_success(&self, peer_id: PeerId) {
+        if let Some(failure) = self.failures().get_mut(&peer_id) {
+            failure.consecutive = 0;
+        }
+    }
+
+    pub fn record_failure(&self, peer_id: PeerId) {
+        self.record_failure_at(peer_id, Instant::now());
+    }
+
+    fn record_failure_at(&self, peer_id: PeerId, now: Instant) {
+        let mut failures = self.failures();
+        let failure = failures.entry(peer_id).or_insert(PeerFailure {
+            score: 0.,
+            consecutive: 0,
+            last_failure: now,
+        });
+        failure.score = failure.score_at(now) + 1.;
+        failure.consecutive += 1;
+        failure.last_failure = now;
+    }
+
+    pub fn failure_score(&self, peer_id: PeerId) -> f64 {
+        self.failures()
+            .get(&peer_id)
+            .map_or(0., |failure| failure.score_at(Instant::now()))
+    }
+
+    pub fn is_down(&self, peer_id: PeerId) -> bool {
+        let failed = self
+            .failures()
+            .get(&peer_id)
+            .map_or(false, |failure| failure.is_down_at(Instant::now()));
+        failed || self.is_probed_down(peer_id)
+    }
+
+    /// A random one of `peers` not marked down. Peers marked down are picked only if all of
+    /// them are down.
+    pub fn random_peer(&self, peers: &[PeerId]) -> Option<PeerId> {
+        let mut rng = rng();
+        peers
+            .iter()
+            .copied()
+            .filter(|&peer_id| !self.is_down(peer_id))
+            .choose(&mut rng)
+            .or_else(|| peers.iter().copied().choose(&mut rng))
+    }
+
+    /// Order `peers` by their recent failures. Peers without failures come first in a random order.
+    /// Peers marked down are skipped unless all of them are down.
+    pub fn rank(&self, peers: &[PeerId]) -> Vec<PeerId> {
+        self.rank_at(peers, Instant::now())
+    }
+
+    fn rank_at(&self, peers: &[PeerId], now: Instant) -> Vec<PeerId> {
+        let failures = self.failures();
+        let mut ranked: Vec<(PeerId, f64, bool)> = peers
+            .iter()
+            .map(|&peer_id| match failures.get(&peer_id) {
+                Some(failure) => (peer_id, failure.score_at(now), failure.is_down_at(now)),
+                None => (peer_id, 0., false),
+            })
+            .collect();
+        drop(failures);
+        {
+            let probes = self.probes();
+            for (peer_id, _, down) in &mut ranked {
+                *down = *down || probes.get(peer_id).map_or(false, |probe| probe.down);
+            }
+        }
+
+        ranked.shuffle(&mut rng());
+        ranked.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(std::cmp::Ordering::Equal));
+        if ranked.iter().any(|(_, _, down)| !down) {
+            ranked.retain(|(_, _, down)| !down);
+        }
+        ranked.into_iter().map(|(peer_id, _, _)| peer_id).collect()
+    }
+
+    /// Try `send` on `peers` in the ranked order until one of them succeeds.
+    /// Return the peer which handles the request and its response.
+    ///
+    /// A request rejected by the peer with a 4xx status is not retried on the other peers.
+    pub async fn send_with_failover<T, F, Fut>(
+        &self,
+        peers: &[PeerId],
+        mut send: F,
+    ) -> Result<(PeerId, T)>
+    where
+        F: FnMut(PeerId) -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let mut last_err = None;
+        for peer_id in self.rank(peers) {
+            match send(peer_id).await {
+                Ok(resp) => {
+                    self.record_success(peer_id);
+                    return Ok((peer_id, resp));
+                }
+                Err(e) if !is_retryable_error(&e) => return Err(e),
+                Err(e) => {
+                    warn!(%peer_id, "Request to peer failed. Error: {}", e);
+                    self.record_failure(peer_id);
+                    last_err = Some(e);
+                }
+            }
+        }
+        Err(last_err.unwrap_or_else(|| anyhow!("No peer is available.")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::common::NetworkError;
+    use slimchain_common::error::bail;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[tokio::test]
+    async fn test_route_around_dead_peer() {
+        let health = PeerHealth::new();
+        let peers = [PeerId(1), PeerId(2), PeerId(3)];
+        let dead_peer = PeerId(2);
+        let dead_peer_calls = AtomicUsize::new(0);
+
+        // Mock transport where the dead peer always fails.
+        let transport = |peer_id: PeerId| {
+            let dead_peer_calls = &dead_peer_calls;
+            async move {
+                if peer_id == dead_peer {
+                    dead_peer_calls.fetch_add(1, Ordering::SeqCst);
+                    bail!("Connection refused.");
+                }
+                Ok(peer_id)
+            }
+        };
+
+        for _ in 0..20 {
+            let (peer_id, resp) = health.send_with_failover(&peers, transport).await.unwrap();
+            assert_ne!(peer_id, dead_peer);
+            assert_eq!(peer_id, resp);
+        }
+        assert!(dead_peer_calls.load(Ordering::SeqCst) <= 1);
+        assert_eq!(Some(&dead_peer), health.rank(&peers).last());
+    }
+
+    #[tokio::test]
+    async fn test_all_peers_failed() {
+        let health = PeerHealth::new();
+        let peers = [PeerId(1), PeerId(2)];
+        let res = health
+            .send_with_failover(&peers, |_| async { Result::<()>::Err(anyhow!("Timeout.")) })
+            .await;
+        assert!(res.is_err());
+        assert!(health.failure_score(PeerId(1)) > 0.);
+        assert!(health.failure_score(PeerId(2)) > 0.);
+
+        let res = health.send_with_failover(&[], |_| async { Ok(()) }).await;
+        assert!(res.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_no_retry_on_client_error() {
+        let health = PeerHealth::new();
+        let peers = [PeerId(1), PeerId(2)];
+        let calls = AtomicUsize::new(0);
+        let res = health
+            .send_with_failover(&peers, |_| {
+                calls.fetch_add(1, Ordering::SeqCst);
+                async {
+                    Result::<()>::Err(
+                        NetworkError::HttpStatus {
+                            status: warp::http::StatusCode::BAD_REQUEST,
+                            msg: String::new(),
+                        }
+                        .into(),
+                    )
+                }
+            })
+            .await;
+        assert!(res.is_err());
+        assert_eq!(1, calls.load(Ordering::SeqCst));
+        assert_eq!(0., health.failure_score(PeerId(1)));
+        assert_eq!(0., health.failure_score(PeerId(2)));
+    }
+
+    #[test]
+    fn test_down_and_decay() {
+        let health = PeerHealth::new();
+        let peers = [PeerId(1), PeerId(2)];
+        let now = Instant::now();
+        for _ in 0..DOWN_THRESHOLD {
+            health.record_failure_at(PeerId(1), now);
+        }
+        assert_eq!(vec![PeerId(2)], health.rank_at(&peers, now));
+
+        // The peer is no longer down but still deprioritized.
+        let later = now + DOWN_DURATION;
+        assert_eq!(vec![PeerId(2), PeerId(1)], health.rank_at(&peers, later));
+        let score = health.failures()[&PeerId(1)].score_at(now + FAILURE_HALF_LIFE);
+        assert!((score - DOWN_THRESHOLD as f64 / 2.).abs() < 1e-6);
+
+        // If all the peers are down, try them anyway.
+        for _ in 0..DOWN_THRESHOLD {
+            health.record_failure_at(PeerId(2), now);
+        }
+        assert_eq!(2, health.rank_at(&peers, now).len());
+
+        health.record_success(PeerId(1));
+        assert!(!health.failures()[&PeerId(1)].is_down_at(now));
+    }
+
+    #[test]
+    fn test_probe_down_and_up() {
+        let health = PeerHealth::new();
+        let peers = [PeerId(1), PeerId(2)];
+        health.record_probe(PeerId(1), false, 2);
+        assert!(!health.is_down(PeerId(1)));
+        health.record_probe(PeerId(1), false, 2);
+        assert!(health.is_down(PeerId(1)));
+        assert_eq!(vec![PeerId(2)], health.rank(&peers));
+        for _ in 0..10 {
+            assert_eq!(Some(PeerId(2)), health.random_peer(&peers));
+        }
+        assert_eq!(Some(PeerId(1)), health.random_peer(&[PeerId(1)]));
+
+        // Staying down is not a transition.
+        health.record_probe(PeerId(1), false, 2);
+        assert_eq!(1, health.probe_transitions(PeerId(1)));
+
+        health.record_probe(PeerId(1), true, 2);
+        assert!(!health.is_down(PeerId(1)));
+        assert_eq!(2, health.rank(&peers).len());
+        assert_eq!(2, health.probe_transitions(PeerId(1)));
+    }
+}
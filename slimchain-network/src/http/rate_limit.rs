@@ -0,0 +1,349 @@
+use crate::http::{config::RateLimitConfig, rpc_metrics::RPC_METRICS};
+use slimchain_common::{basic::Address, collections::HashMap};
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+use warp::{
+    http::StatusCode,
+    reject::{Reject, Rejection},
+    Filter, Reply,
+};
+
+/// Max number of sources with their own token buckets. Once exceeded, the full buckets are
+/// dropped, since they are the same as the new ones.
+const MAX_TRACKED_KEYS: usize = 100_000;
+
+/// The source of the requests sharing a token bucket.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum RateLimitKey {
+    /// The caller address of the signed tx requests.
+    Address(Address),
+    /// The remote IP, for the requests without a caller.
+    Ip(IpAddr),
+    Unknown,
+}
+
+impl RateLimitKey {
+    pub fn from_remote(remote: Option<SocketAddr>) -> Self {
+        remote.map_or(Self::Unknown, |addr| Self::Ip(addr.ip()))
+    }
+}
+
+/// Rejected as the rate limit is exceeded. Use [`recover_rate_limited`] to reply it with 429.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct RateLimited {
+    pub retry_after: Duration,
+}
+
+impl Reject for RateLimited {}
+
+#[derive(Debug, Copy, Clone)]
+struct Limit {
+    rate: f64,
+    burst: f64,
+}
+
+impl Limit {
+    fn new(rate: u32, burst: u32) -> Option<Self> {
+        if rate == 0 {
+            return None;
+        }
+        let burst = if burst == 0 { rate } else { burst };
+        Some(Self {
+            rate: rate as f64,
+            burst: burst as f64,
+        })
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+struct TokenBucket {
+    tokens: f64,
+    last: Instant,
+}
+
+impl TokenBucket {
+    fn full(limit: Limit, now: Instant) -> Self {
+        Self {
+            tokens: limit.burst,
+            last: now,
+        }
+    }
+
+    fn refill(&mut self, limit: Limit, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * limit.rate).min(limit.burst);
+        self.last = now;
+    }
+
+    /// The time to wait before `n` tokens are available.
+    fn wait_time(&self, limit: Limit, n: f64) -> Duration {
+        if self.tokens >= n {
+            Duration::from_secs(0)
+        } else {
+            Duration::from_secs_f64((n - self.tokens) / limit.rate)
+        }
+    }
+}
+
+#[derive(Debug)]
+struct RateLimiterInner {
+    global_limit: Option<Limit>,
+    per_key_limit: Option<Limit>,
+    global: Option<TokenBucket>,
+    keys: HashMap<RateLimitKey, TokenBucket>,
+}
+
+/// Token buckets limiting the requests to `route`, one shared by all the sources and one for
+/// each source.
+#[derive(Debug)]
+pub struct RateLimiter {
+    route: &'static str,
+    inner: Mutex<RateLimiterInner>,
+}
+
+impl RateLimiter {
+    pub fn new(route: &'static str, cfg: &RateLimitConfig) -> Self {
+        let global_limit = Limit::new(cfg.global_rate, cfg.global_burst);
+        Self {
+            route,
+            inner: Mutex::new(RateLimiterInner {
+                global_limit,
+                per_key_limit: Limit::new(cfg.per_key_rate, cfg.per_key_burst),
+                global: global_limit.map(|limit| TokenBucket::full(limit, Instant::now())),
+                keys: HashMap::new(),
+            }),
+        }
+    }
+
+    fn inner(&self) -> MutexGuard<'_, RateLimiterInner> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        let inner = self.inner();
+        inner.global_limit.is_some() || inner.per_key_limit.is_some()
+    }
+
+    /// Replace the limits at once, e.g., on the reload of the config. The tokens left in the
+    /// buckets are kept within the new bursts.
+    pub fn update(&self, cfg: &RateLimitConfig) {
+        let now = Instant::now();
+        let mut inner = self.inner();
+        inner.global_limit = Limit::new(cfg.global_rate, cfg.global_burst);
+        inner.per_key_limit = Limit::new(cfg.per_key_rate, cfg.per_key_burst);
+        inner.global = match (inner.global_limit, inner.global) {
+            (Some(limit), Some(mut bucket)) => {
+                bucket.refill(limit, now);
+                Some(bucket)
+            }
+            (Some(limit), None) => Some(TokenBucket::full(limit, now)),
+            (None, _) => None,
+        };
+        match inner.per_key_limit {
+            Some(limit) => {
+                for bucket in inner.keys.values_mut() {
+                    bucket.refill(limit, now);
+                }
+            }
+            None => inner.keys.clear(),
+        }
+    }
+
+    /// Take a token for each request, where `keys` are the sources of the requests.
+    /// Either all the requests are accepted or none is.
+    pub fn check(&self, keys: &[RateLimitKey]) -> Result<(), RateLimited> {
+        if !self.is_enabled() {
+            return Ok(());
+        }
+        let res = self.try_acquire(keys, Instant::now());
+        RPC_METRICS.record_rate_limit(self.route, res.is_err());
+        res
+    }
+
+    fn try_acquire(&self, keys: &[RateLimitKey], now: Instant) -> Result<(), RateLimited> {
+        let mut inner = self.inner();
+        let mut retry_after = Duration::from_secs(0);
+
+        let RateLimiterInner {
+            global_limit,
+            per_key_limit,
+            global,
+            keys: buckets,
+        } = &mut *inner;
+
+        if let (Some(limit), Some(bucket)) = (*global_limit, global.as_mut()) {
+            bucket.refill(limit, now);
+            retry_after = retry_after.max(bucket.wait_time(limit, keys.len() as f64));
+        }
+
+        let mut counts: HashMap<RateLimitKey, usize> = HashMap::new();
+        if let Some(limit) = *per_key_limit {
+            for &key in keys {
+                *counts.entry(key).or_default() += 1;
+            }
+            if buckets.len() + counts.len() > MAX_TRACKED_KEYS {
+                buckets.retain(|_, bucket| {
+                    bucket.refill(limit, now);
+                    bucket.tokens < limit.burst
+                });
+            }
+            for (key, &n) in &counts {
+                let bucket = buckets
+                    .entry(*key)
+                    .or_insert_with(|| TokenBucket::full(limit, now));
+                bucket.refill(limit, now);
+                retry_after = retry_after.max(bucket.wait_time(limit, n as f64));
+            }
+        }
+
+        if retry_after > Duration::from_secs(0) {
+            return Err(RateLimited { retry_after });
+        }
+
+        if let Some(bucket) = global.as_mut() {
+            bucket.tokens -= keys.len() as f64;
+        }
+        for (key, n) in counts {
+            if let Some(bucket) = buckets.get_mut(&key) {
+                bucket.tokens -= n as f64;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Limit the requests by their remote IPs.
+pub fn warp_rate_limit_by_ip(
+    limiter: Arc<RateLimiter>,
+) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::addr::remote()
+        .and_then(move |remote: Option<SocketAddr>| {
+            let res = limiter
+                .check(&[RateLimitKey::from_remote(remote)])
+                .map_err(warp::reject::custom);
+            async move { res }
+        })
+        .untuple_one()
+}
+
+pub async fn recover_rate_limited(
+    rejection: Rejection,
+) -> std::result::Result<impl Reply, Rejection> {
+    if let Some(limited) = rejection.find::<RateLimited>() {
+        // Round up, so that the retry is not limited again.
+        let retry_after = (limited.retry_after.as_secs_f64().ceil() as u64).max(1);
+        Ok(warp::reply::with_header(
+            StatusCode::TOO_MANY_REQUESTS,
+            "retry-after",
+            retry_after.to_string(),
+        ))
+    } else {
+        Err(rejection)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use slimchain_common::basic::H160;
+
+    fn limiter(global_rate: u32, per_key_rate: u32) -> RateLimiter {
+        RateLimiter::new(
+            "test",
+            &RateLimitConfig {
+                global_rate,
+                global_burst: 0,
+                per_key_rate,
+                per_key_burst: 0,
+            },
+        )
+    }
+
+    #[test]
+    fn test_per_key_limit() {
+        let limiter = limiter(0, 2);
+        let now = Instant::now();
+        let alice = RateLimitKey::Address(H160::from_low_u64_be(1).into());
+        let bob = RateLimitKey::Address(H160::from_low_u64_be(2).into());
+
+        limiter.try_acquire(&[alice], now).unwrap();
+        limiter.try_acquire(&[alice], now).unwrap();
+        let limited = limiter.try_acquire(&[alice], now).unwrap_err();
+        assert_eq!(Duration::from_millis(500), limited.retry_after);
+        // Other callers are not affected.
+        limiter.try_acquire(&[bob, bob], now).unwrap();
+
+        let later = now + Duration::from_millis(500);
+        limiter.try_acquire(&[alice], later).unwrap();
+        // The batch is rejected as a whole, without taking any token.
+        assert!(limiter.try_acquire(&[bob, alice], later).is_err());
+        limiter.try_acquire(&[bob], later).unwrap();
+    }
+
+    #[test]
+    fn test_global_limit() {
+        let limiter = limiter(3, 2);
+        let now = Instant::now();
+        let keys: Vec<_> = (1..=4)
+            .map(|i| RateLimitKey::Address(H160::from_low_u64_be(i).into()))
+            .collect();
+
+        limiter.try_acquire(&keys[0..2], now).unwrap();
+        limiter.try_acquire(&keys[2..3], now).unwrap();
+        // Within the per key limit, but exceeding the global one.
+        let limited = limiter.try_acquire(&keys[3..4], now).unwrap_err();
+        assert!(limited.retry_after > Duration::from_secs(0));
+
+        let later = now + Duration::from_secs(1);
+        limiter.try_acquire(&keys[3..4], later).unwrap();
+        assert!(limiter.is_enabled());
+        assert!(!self::limiter(0, 0).is_enabled());
+    }
+
+    #[test]
+    fn test_update() {
+        let limiter = limiter(0, 0);
+        let now = Instant::now();
+        let alice = RateLimitKey::Address(H160::from_low_u64_be(1).into());
+        limiter.try_acquire(&[alice, alice, alice], now).unwrap();
+
+        limiter.update(&RateLimitConfig {
+            per_key_rate: 1,
+            ..Default::default()
+        });
+        assert!(limiter.is_enabled());
+        limiter.check(&[alice]).unwrap();
+        assert!(limiter.check(&[alice]).is_err());
+
+        limiter.update(&RateLimitConfig::default());
+        assert!(!limiter.is_enabled());
+        limiter.check(&[alice, alice]).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_recover_rate_limited() {
+        let limiter = Arc::new(limiter(1, 0));
+        let route = warp::path("rpc")
+            .and(warp_rate_limit_by_ip(limiter))
+            .map(|| "ok")
+            .recover(recover_rate_limited);
+
+        let resp = warp::test::request()
+            .path("/rpc")
+            .remote_addr(([127, 0, 0, 1], 8000).into())
+            .reply(&route)
+            .await;
+        assert_eq!(StatusCode::OK, resp.status());
+
+        let resp = warp::test::request()
+            .path("/rpc")
+            .remote_addr(([127, 0, 0, 2], 8000).into())
+            .reply(&route)
+            .await;
+        assert_eq!(StatusCode::TOO_MANY_REQUESTS, resp.status());
+        assert_eq!("1", resp.headers()["retry-after"]);
+    }
+}
@@ -0,0 +1,359 @@
+use crate::http::{
+    admin::SIGNED_REQUEST_TTL_SECS,
+    config::{NetworkConfig, NetworkRouteTable, PeerId},
+};
+use serde::{Deserialize, Serialize};
+use slimchain_chain::role::Role;
+use slimchain_common::{
+    basic::{ShardId, H256},
+    digest::Digestible,
+    ed25519::{Keypair, PubSigPair, PublicKey},
+    error::{ensure, Result},
+};
+use slimchain_utils::{
+    chrono::{self, DateTime, Utc},
+    config::Config,
+    record_event,
+    serde::binary_encode,
+};
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime},
+};
+use tokio::task::JoinHandle;
+
+#[derive(Serialize, Deserialize)]
+#[serde(remote = "Role")]
+enum RoleDef {
+    Client,
+    Miner,
+    Storage(ShardId),
+    Observer,
+}
+
+/// A change of the known peers applied by [`NetworkRouteTable::apply_update`].
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub enum RouteTableUpdate {
+    AddPeer {
+        peer_id: PeerId,
+        address: String,
+        #[serde(with = "RoleDef")]
+        role: Role,
+    },
+    RemovePeer {
+        peer_id: PeerId,
+    },
+    ChangeRole {
+        peer_id: PeerId,
+        #[serde(with = "RoleDef")]
+        role: Role,
+    },
+    /// Mark the client node as a raft learner or not.
+    SetLearner {
+        peer_id: PeerId,
+        learner: bool,
+    },
+}
+
+/// Tell the route table updates apart from the other signed payloads, e.g., the admin requests.
+const ROUTE_TABLE_UPDATE_DOMAIN: &[u8] = b"slimchain/route/v1";
+
+/// A [`RouteTableUpdate`] to the node `target` signed by one of the admin keys. `seq` must
+/// increase across the updates accepted by a node, so that a captured update cannot be
+/// replayed, and the update expires at `expires_at`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SignedRouteTableUpdate {
+    pub target: PeerId,
+    pub seq: u64,
+    pub expires_at: DateTime<Utc>,
+    pub update: RouteTableUpdate,
+    pub sig: PubSigPair,
+}
+
+impl SignedRouteTableUpdate {
+    /// Sign `update` to `target`, which expires after [`SIGNED_REQUEST_TTL_SECS`].
+    pub fn sign(
+        target: PeerId,
+        seq: u64,
+        update: RouteTableUpdate,
+        keypair: &Keypair,
+    ) -> Result<Self> {
+        let expires_at = Utc::now() + chrono::Duration::seconds(SIGNED_REQUEST_TTL_SECS);
+        Self::sign_until(target, seq, expires_at, update, keypair)
+    }
+
+    pub fn sign_until(
+        target: PeerId,
+        seq: u64,
+        expires_at: DateTime<Utc>,
+        update: RouteTableUpdate,
+        keypair: &Keypair,
+    ) -> Result<Self> {
+        let sig = PubSigPair::create(keypair, Self::digest(target, seq, expires_at, &update)?);
+        Ok(Self {
+            target,
+            seq,
+            expires_at,
+            update,
+            sig,
+        })
+    }
+
+    fn digest(
+        target: PeerId,
+        seq: u64,
+        expires_at: DateTime<Utc>,
+        update: &RouteTableUpdate,
+    ) -> Result<H256> {
+        Ok(
+            binary_encode(&(ROUTE_TABLE_UPDATE_DOMAIN, target, seq, expires_at, update))?
+                .to_digest(),
+        )
+    }
+
+    /// Verify that the update is signed by one of the `admin_keys`.
+    pub fn verify(&self, admin_keys: &[PublicKey]) -> Result<()> {
+        ensure!(
+            admin_keys.contains(self.sig.public()),
+            "Route table update signed by an unknown key."
+        );
+        self.sig.verify(Self::digest(
+            self.target,
+            self.seq,
+            self.expires_at,
+            &self.update,
+        )?)
+    }
+}
+
+#[derive(Debug)]
+struct SharedRouteTableInner {
+    table: Arc<NetworkRouteTable>,
+    last_seq: Option<u64>,
+}
+
+/// The route table shared by the node RPCs and updated at runtime.
+/// Readers take a snapshot by [`Self::load`], which is not affected by the later updates.
+#[derive(Debug, Clone)]
+pub struct SharedRouteTable {
+    inner: Arc<RwLock<SharedRouteTableInner>>,
+}
+
+impl SharedRouteTable {
+    pub fn new(table: NetworkRouteTable) -> Self {
+        Self {
+            inner: Arc::new(RwLock::new(SharedRouteTableInner {
+                table: Arc::new(table),
+                last_seq: None,
+            })),
+        }
+    }
+
+    pub fn load(&self) -> Arc<NetworkRouteTable> {
+        self.inner
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .table
+            .clone()
+    }
+
+    pub fn peer_id(&self) -> PeerId {
+        self.load().peer_id()
+    }
+
+    pub fn apply_update(&self, update: RouteTableUpdate) -> Result<()> {
+        let mut inner = self.inner.write().unwrap_or_else(|e| e.into_inner());
+        let mut table = NetworkRouteTable::clone(&inner.table);
+        table.apply_update(update.clone())?;
+        inner.table = Arc::new(table);
+        info!("Route table updated. {:?}", update);
+        Ok(())
+    }
+
+    /// Verify `update` against `admin_keys` and apply it.
+    pub fn apply_signed_update(
+        &self,
+        update: SignedRouteTableUpdate,
+        admin_keys: &[PublicKey],
+    ) -> Result<()> {
+        ensure!(!admin_keys.is_empty(), "Route table update is disabled.");
+        update.verify(admin_keys)?;
+
+        let mut inner = self.inner.write().unwrap_or_else(|e| e.into_inner());
+        ensure!(
+            update.target == inner.table.peer_id(),
+            "Route table update to peer {}, not {}.",
+            update.target,
+            inner.table.peer_id()
+        );
+        ensure!(
+            update.expires_at > Utc::now(),
+            "Route table update expired at {}.",
+            update.expires_at
+        );
+        if let Some(last_seq) = inner.last_seq {
+            ensure!(
+                update.seq > last_seq,
+                "Stale route table update. Seq: {}. Last seq: {}.",
+                update.seq,
+                last_seq
+            );
+        }
+        let mut table = NetworkRouteTable::clone(&inner.table);
+        table.apply_update(update.update.clone())?;
+        inner.table = Arc::new(table);
+        inner.last_seq = Some(update.seq);
+        info!(seq = update.seq, "Route table updated. {:?}", update.update);
+        Ok(())
+    }
+
+    /// Replace the whole table, e.g., with the one reloaded from the config file.
+    pub fn replace(&self, table: NetworkRouteTable) -> Result<()> {
+        let mut inner = self.inner.write().unwrap_or_else(|e| e.into_inner());
+        ensure!(
+            table.peer_id() == inner.table.peer_id(),
+            "Cannot change the peer id of this node from {} to {}.",
+            inner.table.peer_id(),
+            table.peer_id()
+        );
+        inner.table = Arc::new(table);
+        Ok(())
+    }
+}
+
+fn modified_time(path: &Path) -> Result<SystemTime> {
+    Ok(std::fs::metadata(path)?.modified()?)
+}
+
+fn reload_route_table(path: &Path) -> Result<NetworkRouteTable> {
+    let net_cfg: NetworkConfig = Config::load(path)?.get("network")?;
+    Ok(net_cfg.to_route_table())
+}
+
+/// Spawn a task checking `path` every `interval`, and replacing `route_table` with the peers
+/// in it once modified. The other configs in the file are not reloaded.
+pub fn spawn_route_table_watcher(
+    route_table: SharedRouteTable,
+    path: PathBuf,
+    interval: Duration,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut last_modified = modified_time(&path).ok();
+        let mut ticker = tokio::time::interval(interval);
+        // The first tick completes immediately.
+        ticker.tick().await;
+        loop {
+            ticker.tick().await;
+            let modified = match modified_time(&path) {
+                Ok(modified) => modified,
+                Err(e) => {
+                    warn!("Failed to check {}. Error: {}", path.display(), e);
+                    continue;
+                }
+            };
+            if last_modified == Some(modified) {
+                continue;
+            }
+            last_modified = Some(modified);
+
+            match reload_route_table(&path).and_then(|table| route_table.replace(table)) {
+                Ok(()) => {
+                    info!("Route table reloaded from {}.", path.display());
+                    record_event!("route_table_reload", "peers": route_table.load().peer_table().len());
+                }
+                Err(e) => {
+                    warn!(
+                        "Failed to reload route table from {}. Error: {}",
+                        path.display(),
+                        e
+                    );
+                }
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use slimchain_utils::{serde::binary_decode, toml};
+
+    fn route_table() -> NetworkRouteTable {
+        let input = toml::toml! {
+            [network]
+            peer_id = 1
+
+            [[network.peers]]
+            peer_id = 1
+            address = "127.0.0.1:8001"
+        };
+        let net_cfg: NetworkConfig = Config::from_toml(input).get("network").unwrap();
+        net_cfg.to_route_table()
+    }
+
+    #[test]
+    fn test_signed_route_table_update() {
+        let mut rng = rand::thread_rng();
+        let admin = Keypair::generate(&mut rng);
+        let other = Keypair::generate(&mut rng);
+        let shard = Role::Storage(ShardId::new(1, 2));
+        let update = |peer_id: u64| RouteTableUpdate::AddPeer {
+            peer_id: PeerId(peer_id),
+            address: format!("127.0.0.1:{}", 8000 + peer_id),
+            role: shard,
+        };
+
+        let signed = SignedRouteTableUpdate::sign(PeerId(1), 1, update(2), &admin).unwrap();
+        let decoded: SignedRouteTableUpdate =
+            binary_decode(&binary_encode(&signed).unwrap()).unwrap();
+        assert_eq!(signed, decoded);
+
+        let table = SharedRouteTable::new(route_table());
+        let snapshot = table.load();
+        assert!(table.apply_signed_update(signed.clone(), &[]).is_err());
+        assert!(table
+            .apply_signed_update(signed.clone(), &[other.public])
+            .is_err());
+        table
+            .apply_signed_update(signed.clone(), &[admin.public])
+            .unwrap();
+        assert_eq!(&[PeerId(2)], table.load().peers_for_role(&shard));
+        // The snapshot taken before stays unchanged.
+        assert!(snapshot.peers_for_role(&shard).is_empty());
+
+        // Replayed or tampered updates are rejected.
+        assert!(table.apply_signed_update(signed, &[admin.public]).is_err());
+        let mut tampered = SignedRouteTableUpdate::sign(PeerId(1), 2, update(3), &admin).unwrap();
+        tampered.update = update(4);
+        assert!(table
+            .apply_signed_update(tampered, &[admin.public])
+            .is_err());
+        let other_target = SignedRouteTableUpdate::sign(PeerId(2), 2, update(3), &admin).unwrap();
+        assert!(table
+            .apply_signed_update(other_target, &[admin.public])
+            .is_err());
+        let expired = SignedRouteTableUpdate::sign_until(
+            PeerId(1),
+            2,
+            Utc::now() - chrono::Duration::seconds(1),
+            update(3),
+            &admin,
+        )
+        .unwrap();
+        assert!(table.apply_signed_update(expired, &[admin.public]).is_err());
+
+        let signed = SignedRouteTableUpdate::sign(PeerId(1), 2, update(3), &admin).unwrap();
+        table.apply_signed_update(signed, &[admin.public]).unwrap();
+        assert_eq!(&[PeerId(2), PeerId(3)], table.load().peers_for_role(&shard));
+
+        table.replace(route_table()).unwrap();
+        assert!(table.load().peers_for_role(&shard).is_empty());
+        let input = toml::toml! {
+            [network]
+            peer_id = 2
+        };
+        let net_cfg: NetworkConfig = Config::from_toml(input).get("network").unwrap();
+        assert!(table.replace(net_cfg.to_route_table()).is_err());
+    }
+}
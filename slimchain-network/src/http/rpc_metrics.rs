@@ -0,0 +1,465 @@
+use crate::http::{
+    common::{network_error_of, NetworkError},
+    config::{PeerId, RpcMetricsConfig},
+};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use slimchain_common::{
+    collections::HashMap,
+    error::{Error, Result},
+};
+use slimchain_utils::{
+    prometheus::{MetricsWriter, REGISTRY},
+    record_event,
+};
+use std::{
+    collections::BTreeMap,
+    future::Future,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex, MutexGuard,
+    },
+    time::{Duration, Instant},
+};
+use tokio::task::JoinHandle;
+use warp::http::StatusCode;
+
+/// The upper bounds of the latency histogram buckets in microseconds.
+/// The last bucket counts the latencies above all of them.
+pub const LATENCY_BUCKETS_IN_US: [u64; 12] = [
+    1_000, 2_000, 5_000, 10_000, 20_000, 50_000, 100_000, 200_000, 500_000, 1_000_000, 2_000_000,
+    5_000_000,
+];
+
+pub static RPC_METRICS: Lazy<RpcMetrics> = Lazy::new(RpcMetrics::new);
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RpcRouteReport {
+    pub peer_id: PeerId,
+    pub route: String,
+    pub attempts: u64,
+    pub timeout_failures: u64,
+    pub connect_failures: u64,
+    /// The failures by the HTTP status code.
+    pub status_failures: BTreeMap<u16, u64>,
+    pub other_failures: u64,
+    /// The number of requests in each bucket of [`LATENCY_BUCKETS_IN_US`].
+    pub latency_histogram: Vec<u64>,
+    pub latency_sum_in_us: u64,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RateLimitReport {
+    pub route: String,
+    pub accepted: u64,
+    pub limited: u64,
+}
+
+#[derive(Debug, Default)]
+struct RouteCounters {
+    attempts: u64,
+    timeout_failures: u64,
+    connect_failures: u64,
+    status_failures: BTreeMap<u16, u64>,
+    other_failures: u64,
+    latency_histogram: [u64; LATENCY_BUCKETS_IN_US.len() + 1],
+    latency_sum_in_us: u64,
+}
+
+impl RouteCounters {
+    fn record(&mut self, latency: Duration, err: Option<&Error>) {
+        self.attempts += 1;
+        let latency_in_us = latency.as_micros() as u64;
+        let bucket = LATENCY_BUCKETS_IN_US
+            .iter()
+            .position(|&bound| latency_in_us <= bound)
+            .unwrap_or(LATENCY_BUCKETS_IN_US.len());
+        self.latency_histogram[bucket] += 1;
+        self.latency_sum_in_us += latency_in_us;
+
+        let err = match err {
+            Some(err) => err,
+            None => return,
+        };
+        match network_error_of(err) {
+            Some(NetworkError::Connect { .. }) => self.connect_failures += 1,
+            Some(NetworkError::Timeout { .. }) => self.timeout_failures += 1,
+            Some(NetworkError::HttpStatus { status, .. }) => {
+                *self.status_failures.entry(status.as_u16()).or_default() += 1
+            }
+            Some(NetworkError::Unauthorized { .. }) => {
+                *self
+                    .status_failures
+                    .entry(StatusCode::UNAUTHORIZED.as_u16())
+                    .or_default() += 1
+            }
+            Some(NetworkError::BodyTooLarge { .. }) => {
+                *self
+                    .status_failures
+                    .entry(StatusCode::PAYLOAD_TOO_LARGE.as_u16())
+                    .or_default() += 1
+            }
+            _ => self.other_failures += 1,
+        }
+    }
+}
+
+/// Process-wide counters of the node RPCs for each pair of the target peer and the route.
+/// Nothing is recorded unless enabled.
+pub struct RpcMetrics {
+    enabled: AtomicBool,
+    routes: Mutex<HashMap<(PeerId, &'static str), RouteCounters>>,
+    /// The accepted and limited requests of each rate limited route.
+    rate_limits: Mutex<BTreeMap<&'static str, (u64, u64)>>,
+}
+
+impl RpcMetrics {
+    pub fn new() -> Self {
+        Self {
+            enabled: AtomicBool::new(false),
+            routes: Mutex::new(HashMap::new()),
+            rate_limits: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    fn routes(&self) -> MutexGuard<'_, HashMap<(PeerId, &'static str), RouteCounters>> {
+        self.routes.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    /// Record a request to `route` of `peer_id` which took `latency`.
+    pub fn record(
+        &self,
+        peer_id: PeerId,
+        route: &'static str,
+        latency: Duration,
+        err: Option<&Error>,
+    ) {
+        if !self.is_enabled() {
+            return;
+        }
+        self.routes()
+            .entry((peer_id, route))
+            .or_default()
+            .record(latency, err);
+    }
+
+    /// Record a request to the rate limited `route` of this node.
+    pub fn record_rate_limit(&self, route: &'static str, limited: bool) {
+        if !self.is_enabled() {
+            return;
+        }
+        let mut rate_limits = self.rate_limits.lock().unwrap_or_else(|e| e.into_inner());
+        let (accepted, limited_count) = rate_limits.entry(route).or_default();
+        if limited {
+            *limited_count += 1;
+        } else {
+            *accepted += 1;
+        }
+    }
+
+    /// Run the request `req` to `route` of `peer_id` and record its outcome.
+    pub async fn track<T>(
+        &self,
+        peer_id: PeerId,
+        route: &'static str,
+        req: impl Future<Output = Result<T>>,
+    ) -> Result<T> {
+        if !self.is_enabled() {
+            return req.await;
+        }
+        let begin = Instant::now();
+        let resp = req.await;
+        self.record(peer_id, route, begin.elapsed(), resp.as_ref().err());
+        resp
+    }
+
+    /// The reports sorted by the peer and the route.
+    pub fn snapshot(&self) -> Vec<RpcRouteReport> {
+        let mut reports: Vec<RpcRouteReport> = self
+            .routes()
+            .iter()
+            .map(|(&(peer_id, route), counters)| RpcRouteReport {
+                peer_id,
+                route: route.to_string(),
+                attempts: counters.attempts,
+                timeout_failures: counters.timeout_failures,
+                connect_failures: counters.connect_failures,
+                status_failures: counters.status_failures.clone(),
+                other_failures: counters.other_failures,
+                latency_histogram: counters.latency_histogram.to_vec(),
+                latency_sum_in_us: counters.latency_sum_in_us,
+            })
+            .collect();
+        reports.sort_by(|a, b| (a.peer_id, &a.route).cmp(&(b.peer_id, &b.route)));
+        reports
+    }
+
+    /// The reports of the rate limited routes sorted by the route.
+    pub fn rate_limit_snapshot(&self) -> Vec<RateLimitReport> {
+        self.rate_limits
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .map(|(&route, &(accepted, limited))| RateLimitReport {
+                route: route.to_string(),
+                accepted,
+                limited,
+            })
+            .collect()
+    }
+
+    /// Write the counters as the `slimchain_rpc_*` series, labeled by the target peer and the
+    /// route.
+    pub fn write_prometheus(&self, w: &mut MetricsWriter) {
+        let bounds: Vec<f64> = LATENCY_BUCKETS_IN_US
+            .iter()
+            .map(|&bound| bound as f64 / 1e6)
+            .collect();
+        for report in self.snapshot() {
+            let peer = report.peer_id.to_string();
+            let labels = [("peer", peer.as_str()), ("route", report.route.as_str())];
+            w.counter(
+                "slimchain_rpc_requests_total",
+                "The node RPC requests sent to the peers.",
+                &labels,
+                report.attempts as f64,
+            );
+            let failures = [
+                ("timeout", report.timeout_failures),
+                ("connect", report.connect_failures),
+                ("other", report.other_failures),
+            ];
+            for &(kind, count) in failures.iter() {
+                w.counter(
+                    "slimchain_rpc_failures_total",
+                    "The failed node RPC requests by the kind of the failure.",
+                    &[labels[0], labels[1], ("kind", kind)],
+                    count as f64,
+                );
+            }
+            for (status, &count) in &report.status_failures {
+                w.counter(
+                    "slimchain_rpc_failures_total",
+                    "The failed node RPC requests by the kind of the failure.",
+                    &[
+                        labels[0],
+                        labels[1],
+                        ("kind", "status"),
+                        ("status", &status.to_string()),
+                    ],
+                    count as f64,
+                );
+            }
+            w.histogram(
+                "slimchain_rpc_latency_seconds",
+                "The latency of the node RPC requests.",
+                &labels,
+                &bounds,
+                &report.latency_histogram,
+                report.latency_sum_in_us as f64 / 1e6,
+            );
+        }
+        for report in self.rate_limit_snapshot() {
+            for &(outcome, count) in
+                [("accepted", report.accepted), ("limited", report.limited)].iter()
+            {
+                w.counter(
+                    "slimchain_rate_limit_requests_total",
+                    "The requests to the rate limited routes of this node.",
+                    &[("route", report.route.as_str()), ("outcome", outcome)],
+                    count as f64,
+                );
+            }
+        }
+    }
+
+    pub fn reset(&self) {
+        self.routes().clear();
+        self.rate_limits
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clear();
+    }
+}
+
+impl Default for RpcMetrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Serve [`RPC_METRICS`] in the Prometheus [`REGISTRY`].
+pub fn register_rpc_metrics_collector() {
+    REGISTRY.register_collector("rpc", |w| RPC_METRICS.write_prometheus(w));
+}
+
+/// Enable [`RPC_METRICS`] if set in `cfg`, and spawn a task recording its snapshot as the
+/// `rpc_metrics` event every `cfg.report_interval`.
+pub fn start_rpc_metrics(cfg: &RpcMetricsConfig) -> Option<JoinHandle<()>> {
+    if !cfg.enabled {
+        return None;
+    }
+    RPC_METRICS.set_enabled(true);
+    let interval = cfg.report_interval;
+    Some(tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        // The first tick completes immediately.
+        ticker.tick().await;
+        loop {
+            ticker.tick().await;
+            let report = RPC_METRICS.snapshot();
+            let rate_limits = RPC_METRICS.rate_limit_snapshot();
+            record_event!("rpc_metrics", "report": report, "rate_limits": rate_limits);
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use slimchain_common::error::anyhow;
+    use slimchain_utils::prometheus::parse_samples;
+
+    #[tokio::test]
+    async fn test_rpc_metrics() {
+        let metrics = RpcMetrics::new();
+        metrics
+            .track(PeerId(1), "vote", async { Ok(()) })
+            .await
+            .unwrap();
+        assert!(metrics.snapshot().is_empty());
+
+        metrics.set_enabled(true);
+        metrics
+            .track(PeerId(1), "vote", async { Ok(()) })
+            .await
+            .unwrap();
+        metrics.record(
+            PeerId(1),
+            "vote",
+            Duration::from_secs(10),
+            Some(
+                &NetworkError::Timeout {
+                    uri: String::new(),
+                    timeout: Duration::from_secs(10),
+                }
+                .into(),
+            ),
+        );
+        metrics.record(
+            PeerId(2),
+            "vote",
+            Duration::from_millis(3),
+            Some(
+                &NetworkError::HttpStatus {
+                    status: StatusCode::BAD_REQUEST,
+                    msg: String::new(),
+                }
+                .into(),
+            ),
+        );
+        metrics.record(
+            PeerId(1),
+            "append",
+            Duration::from_millis(1),
+            Some(&anyhow!("Broken pipe.")),
+        );
+
+        let report = metrics.snapshot();
+        assert_eq!(3, report.len());
+        assert_eq!(
+            (PeerId(1), "append"),
+            (report[0].peer_id, report[0].route.as_str())
+        );
+        assert_eq!(1, report[0].other_failures);
+        assert_eq!(1, report[0].latency_histogram[0]);
+
+        assert_eq!(
+            (PeerId(1), "vote"),
+            (report[1].peer_id, report[1].route.as_str())
+        );
+        assert_eq!(2, report[1].attempts);
+        assert_eq!(1, report[1].timeout_failures);
+        assert_eq!(1, report[1].latency_histogram[LATENCY_BUCKETS_IN_US.len()]);
+        assert_eq!(2, report[1].latency_histogram.iter().sum::<u64>());
+
+        assert_eq!(Some(&1), report[2].status_failures.get(&400));
+        assert_eq!(1, report[2].latency_histogram[2]);
+
+        metrics.record_rate_limit("tx_req", false);
+        metrics.record_rate_limit("tx_req", true);
+        metrics.record_rate_limit("tx_req", false);
+        assert_eq!(
+            vec![RateLimitReport {
+                route: "tx_req".to_string(),
+                accepted: 2,
+                limited: 1,
+            }],
+            metrics.rate_limit_snapshot()
+        );
+
+        let mut w = MetricsWriter::default();
+        metrics.write_prometheus(&mut w);
+        let samples = parse_samples(&w.render()).unwrap();
+        let find = |name: &str, labels: &[(&str, &str)]| {
+            samples
+                .iter()
+                .find(|s| {
+                    s.name == name
+                        && labels
+                            .iter()
+                            .all(|(k, v)| s.labels.get(*k).map(String::as_str) == Some(*v))
+                })
+                .map(|s| s.value)
+        };
+        let vote = [("peer", "1"), ("route", "vote")];
+        assert_eq!(Some(2.), find("slimchain_rpc_requests_total", &vote));
+        assert_eq!(
+            Some(1.),
+            find(
+                "slimchain_rpc_failures_total",
+                &[vote[0], vote[1], ("kind", "timeout")]
+            )
+        );
+        assert_eq!(
+            Some(1.),
+            find(
+                "slimchain_rpc_failures_total",
+                &[("peer", "2"), ("kind", "status"), ("status", "400")]
+            )
+        );
+        assert_eq!(
+            Some(1.),
+            find(
+                "slimchain_rpc_latency_seconds_bucket",
+                &[vote[0], vote[1], ("le", "5")]
+            )
+        );
+        assert_eq!(
+            Some(2.),
+            find(
+                "slimchain_rpc_latency_seconds_bucket",
+                &[vote[0], vote[1], ("le", "+Inf")]
+            )
+        );
+        assert_eq!(
+            Some(1.),
+            find(
+                "slimchain_rate_limit_requests_total",
+                &[("route", "tx_req"), ("outcome", "limited")]
+            )
+        );
+
+        metrics.reset();
+        assert!(metrics.snapshot().is_empty());
+        assert!(metrics.rate_limit_snapshot().is_empty());
+    }
+}
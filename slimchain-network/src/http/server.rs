@@ -0,0 +1,157 @@
+use crate::http::config::ShutdownConfig;
+use futures::{channel::oneshot, prelude::*};
+use slimchain_common::error::{Error, Result};
+use std::{
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::task::JoinHandle;
+use warp::{http::StatusCode, Filter, Rejection, Reply};
+
+/// A warp server which drains the in-flight requests when shut down.
+pub struct HttpServer {
+    local_addr: SocketAddr,
+    draining: Arc<AtomicBool>,
+    shutdown_tx: Option<oneshot::Sender<()>>,
+    handle: JoinHandle<()>,
+    drain_timeout: Duration,
+}
+
+impl HttpServer {
+    /// Serve `filter` at `listen_addr` in a spawned task.
+    pub fn bind<R: Reply>(
+        filter: impl Filter<Extract = (R,), Error = Rejection> + Clone + Send + Sync + 'static,
+        listen_addr: SocketAddr,
+        cfg: &ShutdownConfig,
+    ) -> Result<Self> {
+        let draining = Arc::new(AtomicBool::new(false));
+        let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+        let (local_addr, srv) = warp::serve(reply_while_draining(draining.clone()).or(filter))
+            .try_bind_with_graceful_shutdown(listen_addr, async {
+                shutdown_rx.await.ok();
+            })
+            .map_err(Error::msg)?;
+        Ok(Self {
+            local_addr,
+            draining,
+            shutdown_tx: Some(shutdown_tx),
+            handle: tokio::spawn(srv),
+            drain_timeout: cfg.drain_timeout,
+        })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Stop accepting new connections, and wait for the in-flight requests to complete for up to
+    /// `drain_timeout`. The remaining connections are closed afterwards. The requests arriving
+    /// on the open connections in the meantime are replied with 503.
+    pub async fn shutdown(mut self) -> Result<()> {
+        self.draining.store(true, Ordering::SeqCst);
+        if let Some(shutdown_tx) = self.shutdown_tx.take() {
+            shutdown_tx.send(()).ok();
+        }
+
+        match tokio::time::timeout(self.drain_timeout, &mut self.handle).await {
+            Ok(res) => res.map_err(Error::from),
+            Err(_) => {
+                warn!(
+                    "HTTP server is not drained in {:?}. Close the remaining connections.",
+                    self.drain_timeout
+                );
+                self.handle.abort();
+                self.handle.await.ok();
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Reply 503 with a `retry-after` header once `draining` is set, so that the callers retry the
+/// requests elsewhere or later. Otherwise, leave them to the filters `or`ed after it.
+fn reply_while_draining(
+    draining: Arc<AtomicBool>,
+) -> impl Filter<Extract = (warp::reply::Response,), Error = Rejection> + Clone {
+    warp::any()
+        .and_then(move || {
+            let res = if draining.load(Ordering::SeqCst) {
+                Ok(())
+            } else {
+                Err(warp::reject::not_found())
+            };
+            future::ready(res)
+        })
+        .untuple_one()
+        .map(|| {
+            warp::reply::with_header(StatusCode::SERVICE_UNAVAILABLE, "retry-after", "1")
+                .into_response()
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::common::send_get_request_using_json;
+    use tokio::time::Instant;
+
+    fn slow_route(
+        delay: Duration,
+    ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+        warp::path("slow").and_then(move || async move {
+            tokio::time::sleep(delay).await;
+            Ok::<_, Rejection>(warp::reply::json(&"done"))
+        })
+    }
+
+    fn bind(delay: Duration, drain_timeout: Duration) -> HttpServer {
+        HttpServer::bind(
+            slow_route(delay),
+            ([127, 0, 0, 1], 0).into(),
+            &ShutdownConfig { drain_timeout },
+        )
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_drain_in_flight_request() {
+        let srv = bind(Duration::from_millis(300), Duration::from_secs(5));
+        let uri = format!("http://{}/slow", srv.local_addr());
+        let req = tokio::spawn(async move { send_get_request_using_json::<String>(&uri).await });
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        srv.shutdown().await.unwrap();
+        assert_eq!("done", req.await.unwrap().unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_drain_timeout() {
+        let srv = bind(Duration::from_secs(10), Duration::from_millis(100));
+        let uri = format!("http://{}/slow", srv.local_addr());
+        let req = tokio::spawn(async move { send_get_request_using_json::<String>(&uri).await });
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let begin = Instant::now();
+        srv.shutdown().await.unwrap();
+        assert!(begin.elapsed() < Duration::from_secs(5));
+        assert!(req.await.unwrap().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_reply_while_draining() {
+        let draining = Arc::new(AtomicBool::new(false));
+        let route = reply_while_draining(draining.clone()).or(slow_route(Duration::from_millis(0)));
+
+        let resp = warp::test::request().path("/slow").reply(&route).await;
+        assert_eq!(StatusCode::OK, resp.status());
+
+        draining.store(true, Ordering::SeqCst);
+        let resp = warp::test::request().path("/slow").reply(&route).await;
+        assert_eq!(StatusCode::SERVICE_UNAVAILABLE, resp.status());
+        assert_eq!("1", resp.headers()["retry-after"]);
+    }
+}
@@ -0,0 +1,124 @@
+use crate::http::config::TxVerifyConfig;
+use futures::{
+    channel::{mpsc, oneshot},
+    prelude::*,
+};
+use slimchain_common::{
+    error::{anyhow, Result},
+    tx_req::{verify_batch, SignedTxRequest},
+};
+
+type VerifyRequest = (SignedTxRequest, oneshot::Sender<Result<SignedTxRequest>>);
+
+/// Verify the signatures of the tx requests on a blocking thread instead of the HTTP handlers.
+/// The requests arriving while a batch is being verified are queued for the next one, which
+/// uses [`verify_batch`] once more than `batch_threshold` of them are queued.
+#[derive(Clone)]
+pub struct TxReqVerifier {
+    req_tx: mpsc::UnboundedSender<VerifyRequest>,
+}
+
+impl TxReqVerifier {
+    /// Spawn the verifier, which stops once all the copies of it are dropped.
+    pub fn spawn(cfg: &TxVerifyConfig) -> Self {
+        let (req_tx, req_rx) = mpsc::unbounded();
+        tokio::spawn(verify_queued_tx_reqs(req_rx, *cfg));
+        Self { req_tx }
+    }
+
+    /// Return `req` back if its signature is valid.
+    pub async fn verify(&self, req: SignedTxRequest) -> Result<SignedTxRequest> {
+        let (resp_tx, resp_rx) = oneshot::channel();
+        self.req_tx
+            .unbounded_send((req, resp_tx))
+            .map_err(|_| anyhow!("TxReqVerifier is stopped."))?;
+        resp_rx
+            .await
+            .map_err(|_| anyhow!("TxReqVerifier is stopped."))?
+    }
+}
+
+async fn verify_queued_tx_reqs(
+    mut req_rx: mpsc::UnboundedReceiver<VerifyRequest>,
+    cfg: TxVerifyConfig,
+) {
+    while let Some(first) = req_rx.next().await {
+        let mut queued = vec![first];
+        while queued.len() < cfg.max_batch_size {
+            match req_rx.try_next() {
+                Ok(Some(req)) => queued.push(req),
+                _ => break,
+            }
+        }
+
+        let (reqs, resp_txs): (Vec<_>, Vec<_>) = queued.into_iter().unzip();
+        let batch = reqs.len() > cfg.batch_threshold;
+        let verified = tokio::task::spawn_blocking(move || {
+            let results = if batch {
+                verify_batch(&reqs)
+            } else {
+                reqs.iter().map(SignedTxRequest::verify).collect()
+            };
+            (reqs, results)
+        })
+        .await;
+
+        match verified {
+            Ok((reqs, results)) => {
+                for ((req, res), resp_tx) in reqs.into_iter().zip(results).zip(resp_txs) {
+                    resp_tx.send(res.map(|_| req)).ok();
+                }
+            }
+            Err(e) => error!("Failed to verify the tx requests. Error: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use slimchain_common::{
+        basic::H160,
+        ed25519::Keypair,
+        tx_req::{SignatureScheme, TxRequest},
+    };
+
+    fn tx_req(nonce: u64, keypair: &Keypair) -> SignedTxRequest {
+        TxRequest::Call {
+            nonce: nonce.into(),
+            address: H160::repeat_byte(0xf).into(),
+            data: b"data".to_vec(),
+        }
+        .sign(keypair, 0)
+    }
+
+    #[tokio::test]
+    async fn test_tx_req_verifier() {
+        let verifier = TxReqVerifier::spawn(&TxVerifyConfig {
+            batch_threshold: 4,
+            max_batch_size: 8,
+        });
+        let keypair = Keypair::generate(&mut rand::thread_rng());
+        let reqs: Vec<_> = (0..32u64)
+            .map(|i| {
+                let mut req = tx_req(i, &keypair);
+                if i % 10 == 3 {
+                    req.input = tx_req(i + 1, &keypair).input;
+                }
+                req
+            })
+            .collect();
+
+        let results = future::join_all(reqs.iter().cloned().map(|req| verifier.verify(req))).await;
+        for (i, (req, res)) in reqs.iter().zip(results).enumerate() {
+            match res {
+                Ok(verified) => {
+                    assert_ne!(3, i % 10);
+                    assert_eq!(req, &verified);
+                    assert_eq!(SignatureScheme::Ed25519, verified.scheme());
+                }
+                Err(_) => assert_eq!(3, i % 10),
+            }
+        }
+    }
+}
@@ -0,0 +1,509 @@
+use super::config::WsSubscribeConfig;
+use futures::prelude::*;
+use serde::Deserialize;
+use slimchain_chain::{
+    block::BlockTrait,
+    commit_event::{CommitEvent, CommitEventBroadcaster},
+    db::{BlockPruned, DB},
+    loader::BlockLoaderTrait,
+};
+use slimchain_common::{
+    basic::BlockHeight,
+    error::{bail, Context as _, Error, Result},
+    tx::TxTrait,
+};
+use std::{str::FromStr, sync::Arc};
+use tokio::sync::{broadcast, mpsc};
+use warp::{
+    ws::{Message, WebSocket},
+    Filter,
+};
+
+const WS_ROUTE_PATH: &str = "ws";
+const WS_SUBSCRIBE_ROUTE_PATH: &str = "subscribe";
+
+/// The close code sent to the subscribers not keeping up with the events.
+pub const WS_LAGGED_CLOSE_CODE: u16 = 4000;
+const WS_NORMAL_CLOSE_CODE: u16 = 1000;
+const WS_INTERNAL_ERROR_CLOSE_CODE: u16 = 1011;
+
+/// Which events to be sent to a subscriber.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct WsStreams {
+    pub new_blocks: bool,
+    pub tx_commits: bool,
+}
+
+impl Default for WsStreams {
+    fn default() -> Self {
+        Self {
+            new_blocks: true,
+            tx_commits: true,
+        }
+    }
+}
+
+impl WsStreams {
+    fn accepts(&self, event: &CommitEvent) -> bool {
+        match event {
+            CommitEvent::NewBlock { .. } => self.new_blocks,
+            CommitEvent::TxCommit { .. } => self.tx_commits,
+        }
+    }
+}
+
+impl FromStr for WsStreams {
+    type Err = Error;
+
+    fn from_str(input: &str) -> Result<Self> {
+        let mut streams = Self {
+            new_blocks: false,
+            tx_commits: false,
+        };
+        for stream in input.split(',').map(str::trim) {
+            match stream {
+                "new_blocks" => streams.new_blocks = true,
+                "tx_commits" => streams.tx_commits = true,
+                _ => bail!("Unknown stream: {}.", stream),
+            }
+        }
+        Ok(streams)
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct WsSubscribeParams {
+    streams: Option<String>,
+    from_height: Option<u64>,
+}
+
+#[derive(Debug)]
+struct WsSubscribeRequestError(Error);
+
+impl warp::reject::Reject for WsSubscribeRequestError {}
+
+/// Why the subscription ends.
+#[derive(Debug)]
+enum SubscriptionEnd {
+    Lagged,
+    Failed(Error),
+    Closed,
+}
+
+impl SubscriptionEnd {
+    fn close_message(&self) -> Message {
+        match self {
+            Self::Lagged => Message::close_with(WS_LAGGED_CLOSE_CODE, "lagged"),
+            Self::Failed(_) => Message::close_with(WS_INTERNAL_ERROR_CLOSE_CODE, "internal error"),
+            Self::Closed => Message::close_with(WS_NORMAL_CLOSE_CODE, "closed"),
+        }
+    }
+}
+
+/// Load the events of the committed block at `height` from `db` for [`ws_subscribe_server`].
+/// The tx ids are included only if `include_txs` is set, i.e., the tx bodies are in `db`. Only
+/// the [`CommitEvent::NewBlock`] is left of a block whose body is pruned.
+pub fn load_commit_events_from_db<Block, Tx>(
+    db: &DB,
+    height: BlockHeight,
+    include_txs: bool,
+) -> Result<Vec<CommitEvent>>
+where
+    Block: BlockTrait + for<'de> Deserialize<'de>,
+    Tx: TxTrait + for<'de> Deserialize<'de>,
+{
+    let block: Block = match db.get_block(height) {
+        Ok(block) => block,
+        Err(e) if e.downcast_ref::<BlockPruned>().is_some() => {
+            let pruned = db
+                .get_pruned_block(height)?
+                .context("Pruned block not available in the database.")?;
+            return Ok(vec![CommitEvent::NewBlock {
+                height: pruned.height,
+                hash: pruned.hash,
+                tx_count: pruned.tx_count,
+            }]);
+        }
+        Err(e) => return Err(e),
+    };
+    let txs: Option<Vec<Tx>> = if include_txs {
+        Some(block.tx_list().to_txs(db)?)
+    } else {
+        None
+    };
+    Ok(CommitEvent::from_block(&block, txs.as_deref()))
+}
+
+/// The `/ws/subscribe` route, where the committed blocks and/or txs are sent as the JSON text
+/// frames. Choose them with `?streams=new_blocks,tx_commits`, which defaults to both. Add
+/// `&from_height={height}` to replay the blocks since `height` using `replay_fn` before the
+/// live events from `broadcaster`. The subscribers falling behind by more than
+/// `cfg.queue_len` events are closed with [`WS_LAGGED_CLOSE_CODE`].
+pub fn ws_subscribe_server(
+    cfg: &WsSubscribeConfig,
+    broadcaster: &'static CommitEventBroadcaster,
+    replay_fn: impl Fn(BlockHeight) -> Result<Vec<CommitEvent>> + Send + Sync + 'static,
+    block_height_fn: impl Fn() -> BlockHeight + Send + Sync + 'static,
+) -> warp::filters::BoxedFilter<(impl warp::Reply,)> {
+    let cfg = *cfg;
+    let replay_fn = Arc::new(replay_fn);
+    let block_height_fn = Arc::new(block_height_fn);
+    let block_height_fn_copy = block_height_fn.clone();
+    warp::path(WS_ROUTE_PATH)
+        .and(warp::path(WS_SUBSCRIBE_ROUTE_PATH))
+        .and(warp::path::end())
+        .and(warp::query::<WsSubscribeParams>())
+        .and_then(move |params: WsSubscribeParams| {
+            let res = params
+                .streams
+                .as_deref()
+                .map_or_else(|| Ok(WsStreams::default()), WsStreams::from_str)
+                .and_then(|streams| {
+                    let from_height = params.from_height.map(BlockHeight::from);
+                    if let Some(from_height) = from_height {
+                        let latest_height = block_height_fn_copy();
+                        let replay_len = (latest_height.0 + 1).saturating_sub(from_height.0);
+                        if cfg.max_replay_blocks > 0 && replay_len > cfg.max_replay_blocks {
+                            bail!(
+                                "Cannot replay {} blocks, which exceeds {}.",
+                                replay_len,
+                                cfg.max_replay_blocks
+                            );
+                        }
+                    }
+                    Ok((streams, from_height))
+                })
+                .map_err(|e| warp::reject::custom(WsSubscribeRequestError(e)));
+            future::ready(res)
+        })
+        .and(warp::ws())
+        .map(move |(streams, from_height), ws: warp::ws::Ws| {
+            let replay_fn = replay_fn.clone();
+            let block_height_fn = block_height_fn.clone();
+            ws.on_upgrade(move |socket| {
+                serve_subscription(
+                    socket,
+                    streams,
+                    from_height,
+                    cfg.queue_len,
+                    broadcaster,
+                    replay_fn,
+                    block_height_fn,
+                )
+            })
+        })
+        .boxed()
+}
+
+async fn serve_subscription(
+    socket: WebSocket,
+    streams: WsStreams,
+    from_height: Option<BlockHeight>,
+    queue_len: usize,
+    broadcaster: &'static CommitEventBroadcaster,
+    replay_fn: Arc<impl Fn(BlockHeight) -> Result<Vec<CommitEvent>> + Send + Sync + 'static>,
+    block_height_fn: Arc<impl Fn() -> BlockHeight + Send + Sync + 'static>,
+) {
+    // Subscribe before reading the latest height, so that no event is missed in between.
+    let live_rx = broadcaster.subscribe();
+    let latest_height = block_height_fn();
+    let (queue_tx, mut queue_rx) = mpsc::channel(queue_len.max(1));
+    let mut producer = tokio::spawn(produce_events(
+        queue_tx,
+        live_rx,
+        streams,
+        from_height,
+        latest_height,
+        replay_fn,
+    ));
+
+    let (mut ws_tx, mut ws_rx) = socket.split();
+    let end = loop {
+        tokio::select! {
+            biased;
+            end = &mut producer => {
+                break end.unwrap_or_else(|e| SubscriptionEnd::Failed(Error::from(e)));
+            }
+            event = queue_rx.recv() => {
+                let event = match event {
+                    Some(event) => event,
+                    // The producer is about to end.
+                    None => continue,
+                };
+                let frame = match serde_json::to_string(&event) {
+                    Ok(frame) => frame,
+                    Err(e) => break SubscriptionEnd::Failed(Error::from(e)),
+                };
+                if ws_tx.send(Message::text(frame)).await.is_err() {
+                    producer.abort();
+                    return;
+                }
+            }
+            msg = ws_rx.next() => match msg {
+                Some(Ok(msg)) if !msg.is_close() => {}
+                _ => {
+                    producer.abort();
+                    return;
+                }
+            }
+        }
+    };
+
+    producer.abort();
+    match &end {
+        SubscriptionEnd::Lagged => warn!("WebSocket subscriber lagged behind. Close it."),
+        SubscriptionEnd::Failed(e) => error!("WebSocket subscription failed. Error: {:?}", e),
+        SubscriptionEnd::Closed => {}
+    }
+    ws_tx.send(end.close_message()).await.ok();
+    ws_tx.close().await.ok();
+}
+
+async fn produce_events(
+    queue_tx: mpsc::Sender<CommitEvent>,
+    mut live_rx: broadcast::Receiver<CommitEvent>,
+    streams: WsStreams,
+    from_height: Option<BlockHeight>,
+    latest_height: BlockHeight,
+    replay_fn: Arc<impl Fn(BlockHeight) -> Result<Vec<CommitEvent>> + Send + Sync + 'static>,
+) -> SubscriptionEnd {
+    let mut live_from = latest_height.next_height();
+    if let Some(from_height) = from_height {
+        let mut height = from_height;
+        while height <= latest_height {
+            let events = match replay_fn(height) {
+                Ok(events) => events,
+                Err(e) => return SubscriptionEnd::Failed(e),
+            };
+            for event in events.into_iter().filter(|e| streams.accepts(e)) {
+                // Wait for the queue during the replay, while the live events are buffered
+                // in the broadcast channel.
+                if queue_tx.send(event).await.is_err() {
+                    return SubscriptionEnd::Closed;
+                }
+            }
+            height = height.next_height();
+        }
+        live_from = live_from.max(from_height);
+    }
+
+    loop {
+        let event = match live_rx.recv().await {
+            Ok(event) => event,
+            Err(broadcast::error::RecvError::Lagged(_)) => return SubscriptionEnd::Lagged,
+            Err(broadcast::error::RecvError::Closed) => return SubscriptionEnd::Closed,
+        };
+        if event.height() < live_from || !streams.accepts(&event) {
+            continue;
+        }
+        match queue_tx.try_send(event) {
+            Ok(()) => {}
+            Err(mpsc::error::TrySendError::Full(_)) => return SubscriptionEnd::Lagged,
+            Err(mpsc::error::TrySendError::Closed(_)) => return SubscriptionEnd::Closed,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use once_cell::sync::Lazy;
+    use slimchain_common::basic::H256;
+    use std::{net::SocketAddr, time::Duration};
+    use tokio_tungstenite::{connect_async, tungstenite::Message as WsMessage};
+
+    fn new_block(height: u64) -> CommitEvent {
+        CommitEvent::NewBlock {
+            height: height.into(),
+            hash: H256::repeat_byte(height as u8),
+            tx_count: 1,
+        }
+    }
+
+    fn tx_commit(height: u64) -> CommitEvent {
+        CommitEvent::TxCommit {
+            height: height.into(),
+            position: 0,
+            tx_hash: H256::repeat_byte(0xff),
+            tx_id: None,
+        }
+    }
+
+    fn spawn_server(
+        cfg: WsSubscribeConfig,
+        broadcaster: &'static CommitEventBroadcaster,
+        latest_height: u64,
+    ) -> SocketAddr {
+        let route = ws_subscribe_server(
+            &cfg,
+            broadcaster,
+            |height| Ok(vec![new_block(height.0), tx_commit(height.0)]),
+            move || latest_height.into(),
+        );
+        let (addr, srv) = warp::serve(route).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(srv);
+        addr
+    }
+
+    async fn wait_for_subscriber(broadcaster: &CommitEventBroadcaster) {
+        while broadcaster.subscriber_count() == 0 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_ws_subscribe() {
+        static BROADCASTER: Lazy<CommitEventBroadcaster> =
+            Lazy::new(|| CommitEventBroadcaster::new(16));
+        let addr = spawn_server(WsSubscribeConfig::default(), &BROADCASTER, 2);
+
+        let (mut client, _) = connect_async(format!(
+            "ws://{}/ws/subscribe?streams=new_blocks&from_height=1",
+            addr
+        ))
+        .await
+        .unwrap();
+        wait_for_subscriber(&BROADCASTER).await;
+        // The replayed block is not sent twice.
+        BROADCASTER.publish(new_block(2));
+        BROADCASTER.publish(new_block(3));
+        BROADCASTER.publish(tx_commit(3));
+
+        for height in 1..=3 {
+            let msg = tokio::time::timeout(Duration::from_secs(5), client.next())
+                .await
+                .unwrap()
+                .unwrap()
+                .unwrap();
+            let event: CommitEvent = match msg {
+                WsMessage::Text(frame) => serde_json::from_str(&frame).unwrap(),
+                msg => panic!("Unexpected message: {:?}", msg),
+            };
+            assert_eq!(new_block(height), event);
+        }
+        assert!(
+            tokio::time::timeout(Duration::from_millis(100), client.next())
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_ws_subscribe_replay_pruned() {
+        use slimchain_chain::{
+            block::BlockTxList,
+            consensus::raft::Block,
+            db::{read_pool::DEFAULT_READ_THREADS, Transaction},
+        };
+        use slimchain_common::{digest::Digestible, tx::RawTx};
+
+        static BROADCASTER: Lazy<CommitEventBroadcaster> =
+            Lazy::new(|| CommitEventBroadcaster::new(16));
+        let db = DB::open_memory(DEFAULT_READ_THREADS);
+        let mut blocks = vec![Block::genesis_block()];
+        let mut tx = Transaction::new();
+        for height in 1..=3 {
+            let mut blk = blocks.last().unwrap().clone();
+            blk.block_header_mut().height = height.into();
+            blk.block_header_mut().prev_blk_hash = blocks.last().unwrap().to_digest();
+            *blk.tx_list_mut() = BlockTxList(vec![H256::repeat_byte(height as u8)]);
+            tx.insert_block(&blk).unwrap();
+            blocks.push(blk);
+        }
+        tx.insert_latest_block_header(blocks[3].block_header())
+            .unwrap();
+        // The block at height 1 is out of the full block window.
+        tx.prune_block(&blocks[1]).unwrap();
+        tx.insert_pruned_height(1.into()).unwrap();
+        db.write_sync(tx).unwrap();
+
+        let route = ws_subscribe_server(
+            &WsSubscribeConfig::default(),
+            &BROADCASTER,
+            move |height| load_commit_events_from_db::<Block, RawTx>(&db, height, false),
+            || 3.into(),
+        );
+        let (addr, srv) = warp::serve(route).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(srv);
+        let (mut client, _) = connect_async(format!("ws://{}/ws/subscribe?from_height=1", addr))
+            .await
+            .unwrap();
+
+        let mut expected = vec![CommitEvent::NewBlock {
+            height: 1.into(),
+            hash: blocks[1].to_digest(),
+            tx_count: 1,
+        }];
+        for blk in &blocks[2..] {
+            expected.extend(CommitEvent::from_block::<_, RawTx>(blk, None));
+        }
+        for expected in expected {
+            let msg = tokio::time::timeout(Duration::from_secs(5), client.next())
+                .await
+                .unwrap()
+                .unwrap()
+                .unwrap();
+            let event: CommitEvent = match msg {
+                WsMessage::Text(frame) => serde_json::from_str(&frame).unwrap(),
+                msg => panic!("Unexpected message: {:?}", msg),
+            };
+            assert_eq!(expected, event);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_ws_subscribe_lagged() {
+        static BROADCASTER: Lazy<CommitEventBroadcaster> =
+            Lazy::new(|| CommitEventBroadcaster::new(4));
+        let addr = spawn_server(WsSubscribeConfig::default(), &BROADCASTER, 0);
+
+        let (mut client, _) = connect_async(format!("ws://{}/ws/subscribe", addr))
+            .await
+            .unwrap();
+        wait_for_subscriber(&BROADCASTER).await;
+        for height in 1..=100 {
+            BROADCASTER.publish(new_block(height));
+        }
+
+        let close_frame = loop {
+            match tokio::time::timeout(Duration::from_secs(5), client.next())
+                .await
+                .unwrap()
+            {
+                Some(Ok(WsMessage::Close(frame))) => break frame.unwrap(),
+                Some(Ok(_)) => {}
+                msg => panic!("Unexpected message: {:?}", msg),
+            }
+        };
+        assert_eq!(WS_LAGGED_CLOSE_CODE, u16::from(close_frame.code));
+        assert_eq!("lagged", close_frame.reason);
+    }
+
+    #[tokio::test]
+    async fn test_ws_subscribe_bad_request() {
+        static BROADCASTER: Lazy<CommitEventBroadcaster> =
+            Lazy::new(|| CommitEventBroadcaster::new(4));
+        let cfg = WsSubscribeConfig {
+            max_replay_blocks: 10,
+            ..Default::default()
+        };
+        let addr = spawn_server(cfg, &BROADCASTER, 100);
+
+        assert!(
+            connect_async(format!("ws://{}/ws/subscribe?streams=foo", addr))
+                .await
+                .is_err()
+        );
+        assert!(
+            connect_async(format!("ws://{}/ws/subscribe?from_height=1", addr))
+                .await
+                .is_err()
+        );
+        assert!(
+            connect_async(format!("ws://{}/ws/subscribe?from_height=91", addr))
+                .await
+                .is_ok()
+        );
+    }
+}
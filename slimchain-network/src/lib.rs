@@ -4,3 +4,6 @@ extern crate tracing;
 pub mod behavior;
 pub mod http;
 pub mod p2p;
+
+#[cfg(test)]
+mod golden;
@@ -1,6 +1,9 @@
 pub mod config;
+pub mod connection;
 pub mod control;
+pub mod direct_query;
 pub mod discovery;
 pub mod http;
 pub mod pubsub;
+pub mod relay;
 pub mod rpc;
@@ -1,7 +1,12 @@
-use libp2p::{multiaddr::Multiaddr, PeerId};
+use libp2p::{
+    multiaddr::{Multiaddr, Protocol},
+    PeerId,
+};
+use once_cell::sync::OnceCell;
 use serde::{de::Error as DeError, Deserialize, Deserializer, Serialize, Serializer};
-use slimchain_common::error::{Error, Result};
-use std::fmt;
+use slimchain_common::error::{anyhow, Error, Result};
+use slimchain_utils::config::{join_path, ConfigErrors, ValidateConfig};
+use std::{fmt, time::Duration};
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct NetworkConfig {
@@ -20,6 +25,316 @@ pub struct NetworkConfig {
     /// Known peers
     #[serde(default = "Vec::new")]
     pub peers: Vec<PeerConfig>,
+    /// Bootnodes to discover the other peers from, as multiaddrs ending with `/p2p/<peer_id>`
+    #[serde(default, deserialize_with = "deserialize_p2p_addrs")]
+    pub bootnodes: Vec<PeerConfig>,
+    /// Bootstrap the peer discovery every this time span in milliseconds
+    #[serde(
+        default = "default_bootstrap_interval",
+        deserialize_with = "slimchain_utils::config::deserialize_duration_from_millis"
+    )]
+    pub bootstrap_interval: Duration,
+    /// How to compress the pubsub messages
+    #[serde(default)]
+    pub pubsub_compression: PubSubCompressionConfig,
+    /// When to ban the misbehaving pubsub peers
+    #[serde(default)]
+    pub peer_score: PeerScoreConfig,
+    /// Whether to only accept the pubsub messages from the known peers
+    #[serde(default)]
+    pub pubsub_allow_list: bool,
+    /// Record the pubsub metrics to the metrics file every this time span in milliseconds
+    #[serde(
+        default,
+        deserialize_with = "slimchain_utils::config::deserialize_duration_from_millis"
+    )]
+    pub pubsub_metrics_interval: Duration,
+    /// How to query the peers directly
+    #[serde(default)]
+    pub direct_query: DirectQueryConfig,
+    /// How to reach the peers behind NAT
+    #[serde(default)]
+    pub relay: RelayConfig,
+}
+
+impl ValidateConfig for NetworkConfig {
+    fn validate_at(&self, path: &str, errors: &mut ConfigErrors) {
+        errors.ensure(
+            self.bootstrap_interval > Duration::from_millis(0),
+            &join_path(path, "bootstrap_interval"),
+            "Should be positive.",
+        );
+        errors.ensure(
+            self.peer_score.ban_score < 0.0,
+            &join_path(path, "peer_score.ban_score"),
+            "Should be negative.",
+        );
+        errors.ensure(
+            self.direct_query.max_response_size > 0,
+            &join_path(path, "direct_query.max_response_size"),
+            "Should be positive.",
+        );
+        errors.ensure(
+            self.direct_query.max_concurrent_requests >= 1,
+            &join_path(path, "direct_query.max_concurrent_requests"),
+            "Should be at least 1.",
+        );
+        errors.ensure(
+            !self.relay.listen_via_relay || !self.relay.servers.is_empty(),
+            &join_path(path, "relay.servers"),
+            "Should not be empty when `listen_via_relay` is set.",
+        );
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct PubSubConfig {
+    /// The prefix of the gossipsub protocol id, followed by the network id and the version
+    /// of the wire format. Nodes with different ones cannot talk to each other.
+    pub protocol_id_prefix: String,
+    /// The id of the network, which keeps the nodes of different networks apart. Empty for
+    /// none.
+    pub network_id: String,
+    /// The gossipsub heartbeat interval in milliseconds.
+    #[serde(deserialize_with = "slimchain_utils::config::deserialize_duration_from_millis")]
+    pub heartbeat_interval: Duration,
+    /// The target number of the peers in the mesh of each topic.
+    pub mesh_n: usize,
+    /// Add peers to the mesh once it has fewer peers than this.
+    pub mesh_n_low: usize,
+    /// Remove peers from the mesh once it has more peers than this.
+    pub mesh_n_high: usize,
+    /// The number of the heartbeats for which the messages are cached.
+    pub history_length: usize,
+    /// The number of the heartbeats for which the cached messages are gossiped.
+    pub history_gossip: usize,
+    /// How long the ids of the seen messages are kept in milliseconds.
+    #[serde(deserialize_with = "slimchain_utils::config::deserialize_duration_from_millis")]
+    pub duplicate_cache_ttl: Duration,
+    /// Max size of a gossipsub transmission.
+    pub max_transmit_size: usize,
+    /// Messages larger than this are split into chunks. It should be smaller than
+    /// max_transmit_size.
+    pub max_message_size: usize,
+    /// The queue of the tx proposals not yet consumed.
+    pub tx_proposal_queue: PendingQueueConfig,
+    /// The queue of the block proposals not yet consumed.
+    pub block_proposal_queue: PendingQueueConfig,
+    /// The queue of the state sync announcements not yet consumed.
+    pub state_sync_queue: PendingQueueConfig,
+}
+
+impl Default for PubSubConfig {
+    fn default() -> Self {
+        Self {
+            protocol_id_prefix: "/slimchain/pubsub".to_string(),
+            network_id: String::new(),
+            heartbeat_interval: Duration::from_secs(30),
+            mesh_n: 6,
+            mesh_n_low: 5,
+            mesh_n_high: 12,
+            history_length: 5,
+            history_gossip: 3,
+            duplicate_cache_ttl: Duration::from_secs(1_800),
+            max_transmit_size: 50_000_000,
+            max_message_size: 45_000_000,
+            tx_proposal_queue: PendingQueueConfig {
+                capacity: 10_000,
+                overflow: OverflowPolicy::DropOldest,
+            },
+            block_proposal_queue: PendingQueueConfig::default(),
+            state_sync_queue: PendingQueueConfig::default(),
+        }
+    }
+}
+
+/// What to do with a new event once the queue is full.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OverflowPolicy {
+    /// Drop the oldest event in the queue to make room.
+    DropOldest,
+    /// Drop the new event.
+    DropNewest,
+    /// Hold the new event back until there is room. The gossipsub events cannot be left
+    /// unprocessed, so the new event is dropped like [`OverflowPolicy::DropNewest`].
+    Block,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct PendingQueueConfig {
+    /// Max number of the events in the queue. 0 means unbounded.
+    pub capacity: usize,
+    pub overflow: OverflowPolicy,
+}
+
+impl Default for PendingQueueConfig {
+    fn default() -> Self {
+        Self {
+            capacity: 0,
+            overflow: OverflowPolicy::DropNewest,
+        }
+    }
+}
+
+static GLOBAL_PUBSUB_CONFIG: OnceCell<PubSubConfig> = OnceCell::new();
+
+impl ValidateConfig for PubSubConfig {
+    fn validate_at(&self, path: &str, errors: &mut ConfigErrors) {
+        let num_errors = errors.errors().len();
+        errors.ensure(
+            self.max_message_size >= 1024,
+            &join_path(path, "max_message_size"),
+            "Should be at least 1024.",
+        );
+        errors.ensure(
+            self.max_message_size < self.max_transmit_size,
+            &join_path(path, "max_message_size"),
+            format!(
+                "Should be smaller than `max_transmit_size` ({}).",
+                self.max_transmit_size
+            ),
+        );
+        errors.ensure(
+            self.mesh_n_low <= self.mesh_n && self.mesh_n <= self.mesh_n_high,
+            &join_path(path, "mesh_n"),
+            format!(
+                "Should be within [`mesh_n_low`, `mesh_n_high`] ([{}, {}]).",
+                self.mesh_n_low, self.mesh_n_high
+            ),
+        );
+        errors.ensure(
+            self.history_gossip <= self.history_length,
+            &join_path(path, "history_gossip"),
+            format!(
+                "Should not be larger than `history_length` ({}).",
+                self.history_length
+            ),
+        );
+        // The rest of the gossipsub settings are checked by libp2p.
+        if errors.errors().len() == num_errors {
+            if let Err(e) = crate::p2p::pubsub::gossipsub_config(self) {
+                errors.push(path, e.to_string());
+            }
+        }
+    }
+}
+
+impl PubSubConfig {
+    pub fn validate(&self) -> Result<()> {
+        self.validate_config("pubsub")?;
+        Ok(())
+    }
+
+    pub fn install_as_global(self) -> Result<()> {
+        self.validate()?;
+        GLOBAL_PUBSUB_CONFIG
+            .set(self)
+            .map_err(|_| anyhow!("Failed to set PubSubConfig."))
+    }
+
+    pub fn get() -> Self {
+        GLOBAL_PUBSUB_CONFIG.get().cloned().unwrap_or_default()
+    }
+}
+
+#[derive(Debug, Copy, Clone, Deserialize)]
+#[serde(default)]
+pub struct PubSubCompressionConfig {
+    /// Compress the large messages with zstd. Default false.
+    /// Peers running the older versions cannot decode the messages once enabled.
+    pub enabled: bool,
+    /// Only compress the messages with at least this number of bytes.
+    pub threshold: usize,
+    /// The zstd compression level.
+    pub level: i32,
+}
+
+impl Default for PubSubCompressionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            threshold: 64 * 1024,
+            level: 3,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Deserialize)]
+#[serde(default)]
+pub struct PeerScoreConfig {
+    /// Ban the peers whose scores drop to this. Default -25, i.e., three malformed
+    /// messages in a short time.
+    pub ban_score: f64,
+    /// How long a banned peer is ignored in milliseconds. Default 10 minutes.
+    #[serde(deserialize_with = "slimchain_utils::config::deserialize_duration_from_millis")]
+    pub ban_duration: Duration,
+    /// The time for the scores to decay by half in milliseconds. Default 1 minute.
+    #[serde(deserialize_with = "slimchain_utils::config::deserialize_duration_from_millis")]
+    pub decay_half_life: Duration,
+}
+
+impl Default for PeerScoreConfig {
+    fn default() -> Self {
+        Self {
+            ban_score: -25.0,
+            ban_duration: Duration::from_secs(600),
+            decay_half_life: Duration::from_secs(60),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Deserialize)]
+#[serde(default)]
+pub struct DirectQueryConfig {
+    /// Fail the queries not answered within this time span in milliseconds.
+    #[serde(deserialize_with = "slimchain_utils::config::deserialize_duration_from_millis")]
+    pub request_timeout: Duration,
+    /// Max size of a response in bytes. Larger ones are rejected.
+    pub max_response_size: usize,
+    /// Max number of the queries in flight. Others wait until some are answered.
+    pub max_concurrent_requests: usize,
+}
+
+impl Default for DirectQueryConfig {
+    fn default() -> Self {
+        Self {
+            request_timeout: Duration::from_secs(10),
+            max_response_size: 16 * 1024 * 1024,
+            max_concurrent_requests: 64,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RelayConfig {
+    /// Whether to relay the connections for the other peers.
+    pub server: bool,
+    /// The relay servers, as multiaddrs ending with `/p2p/<peer_id>`. The peers failed to be
+    /// dialed directly are dialed via them.
+    #[serde(deserialize_with = "deserialize_p2p_addrs")]
+    pub servers: Vec<PeerConfig>,
+    /// Whether to listen via the relay servers, for the nodes not reachable from outside.
+    pub listen_via_relay: bool,
+}
+
+impl RelayConfig {
+    pub fn is_enabled(&self) -> bool {
+        self.server || !self.servers.is_empty()
+    }
+}
+
+impl Default for RelayConfig {
+    fn default() -> Self {
+        Self {
+            server: false,
+            servers: Vec::new(),
+            listen_via_relay: false,
+        }
+    }
 }
 
 fn default_listen() -> String {
@@ -41,6 +356,24 @@ fn default_mdns() -> bool {
     true
 }
 
+fn default_bootstrap_interval() -> Duration {
+    Duration::from_secs(300)
+}
+
+fn deserialize_p2p_addrs<'de, D>(deserializer: D) -> Result<Vec<PeerConfig>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let values = <Vec<String>>::deserialize(deserializer)?;
+    values
+        .into_iter()
+        .map(|value| {
+            let address: Multiaddr = value.parse().map_err(DeError::custom)?;
+            PeerConfig::from_p2p_addr(address).map_err(DeError::custom)
+        })
+        .collect()
+}
+
 #[derive(Clone)]
 pub struct KeypairConfig(pub libp2p::identity::ed25519::Keypair);
 
@@ -125,6 +458,18 @@ impl PeerConfig {
         Self { peer_id, address }
     }
 
+    /// Split a multiaddr ending with `/p2p/<peer_id>`.
+    pub fn from_p2p_addr(mut address: Multiaddr) -> Result<Self> {
+        match address.pop() {
+            Some(Protocol::P2p(hash)) => {
+                let peer_id = PeerId::from_multihash(hash)
+                    .map_err(|_| anyhow!("Invalid peer id in {}.", address))?;
+                Ok(Self::new(peer_id, address))
+            }
+            _ => Err(anyhow!("Missing /p2p/<peer_id> in the address.")),
+        }
+    }
+
     pub fn print_config_msg(&self) {
         println!(
             "To add the current peer in the other nodes. Add the followings to the config file."
@@ -195,6 +540,120 @@ mod tests {
         assert_eq!(keypair.keypair, keypair2.keypair);
     }
 
+    #[test]
+    fn test_pubsub_config() {
+        let cfg: PubSubConfig = toml::from_str(
+            r#"
+                protocol_id_prefix = "/slimchain/pubsub/test"
+                heartbeat_interval = 1000
+                mesh_n = 8
+                mesh_n_high = 16
+            "#,
+        )
+        .unwrap();
+        assert_eq!("/slimchain/pubsub/test", cfg.protocol_id_prefix);
+        assert_eq!(Duration::from_secs(1), cfg.heartbeat_interval);
+        assert_eq!((5, 8, 16), (cfg.mesh_n_low, cfg.mesh_n, cfg.mesh_n_high));
+        assert_eq!(45_000_000, cfg.max_message_size);
+        assert_eq!(0, cfg.block_proposal_queue.capacity);
+        cfg.validate().unwrap();
+        PubSubConfig::default().validate().unwrap();
+
+        let cfg = PubSubConfig {
+            max_transmit_size: 1_000_000,
+            ..Default::default()
+        };
+        assert!(cfg
+            .validate()
+            .unwrap_err()
+            .to_string()
+            .contains("max_transmit_size"));
+        let cfg = PubSubConfig {
+            mesh_n_low: 8,
+            ..Default::default()
+        };
+        assert!(cfg.validate().is_err());
+
+        let cfg: PubSubConfig = toml::from_str(
+            r#"
+                [tx_proposal_queue]
+                capacity = 100
+                overflow = "block"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            PendingQueueConfig {
+                capacity: 100,
+                overflow: OverflowPolicy::Block,
+            },
+            cfg.tx_proposal_queue
+        );
+    }
+
+    #[test]
+    fn test_validate_pubsub_config() {
+        let cfg = PubSubConfig {
+            max_message_size: 1000,
+            ..Default::default()
+        };
+        let errors = cfg.validate_config("pubsub").unwrap_err();
+        assert!(errors.contains("pubsub.max_message_size"));
+
+        let cfg = PubSubConfig {
+            mesh_n: 4,
+            ..Default::default()
+        };
+        let errors = cfg.validate_config("pubsub").unwrap_err();
+        assert!(errors.contains("pubsub.mesh_n"));
+
+        let cfg = PubSubConfig {
+            mesh_n_high: 5,
+            ..Default::default()
+        };
+        let errors = cfg.validate_config("pubsub").unwrap_err();
+        assert!(errors.contains("pubsub.mesh_n"));
+
+        let cfg = PubSubConfig {
+            history_gossip: 6,
+            ..Default::default()
+        };
+        let errors = cfg.validate_config("pubsub").unwrap_err();
+        assert!(errors.contains("pubsub.history_gossip"));
+        assert_eq!(1, errors.errors().len());
+    }
+
+    #[test]
+    fn test_validate_network_config() {
+        let keypair = KeypairConfig::generate().to_base58();
+        let net_cfg = |input: &str| -> NetworkConfig {
+            toml::from_str(&format!("keypair = \"{}\"\n{}", keypair, input)).unwrap()
+        };
+        assert!(net_cfg("").validate_config("network").is_ok());
+
+        let errors = net_cfg("bootstrap_interval = 0\n")
+            .validate_config("network")
+            .unwrap_err();
+        assert!(errors.contains("network.bootstrap_interval"));
+
+        let errors = net_cfg("[peer_score]\nban_score = 1.0\n")
+            .validate_config("network")
+            .unwrap_err();
+        assert!(errors.contains("network.peer_score.ban_score"));
+
+        let errors =
+            net_cfg("[direct_query]\nmax_response_size = 0\nmax_concurrent_requests = 0\n")
+                .validate_config("network")
+                .unwrap_err();
+        assert!(errors.contains("network.direct_query.max_response_size"));
+        assert!(errors.contains("network.direct_query.max_concurrent_requests"));
+
+        let errors = net_cfg("[relay]\nlisten_via_relay = true\n")
+            .validate_config("network")
+            .unwrap_err();
+        assert!(errors.contains("network.relay.servers"));
+    }
+
     #[test]
     fn test_peer_config() {
         use libp2p::identity::Keypair;
@@ -206,4 +665,36 @@ mod tests {
         let peer_config2 = toml::from_str::<PeerConfig>(&toml_value).unwrap();
         assert_eq!(peer_config, peer_config2);
     }
+
+    #[test]
+    fn test_bootnodes() {
+        let keypair = KeypairConfig::generate();
+        let peer_id = keypair.to_libp2p_keypair().public().into_peer_id();
+        let cfg: NetworkConfig = toml::from_str(&format!(
+            r#"
+                keypair = "{}"
+                bootnodes = ["/ip4/127.0.0.1/tcp/6000/p2p/{}"]
+            "#,
+            keypair.to_base58(),
+            peer_id
+        ))
+        .unwrap();
+        assert_eq!(
+            vec![PeerConfig::new(
+                peer_id,
+                "/ip4/127.0.0.1/tcp/6000".parse().unwrap()
+            )],
+            cfg.bootnodes
+        );
+        assert_eq!(Duration::from_secs(300), cfg.bootstrap_interval);
+
+        let res = toml::from_str::<NetworkConfig>(&format!(
+            r#"
+                keypair = "{}"
+                bootnodes = ["/ip4/127.0.0.1/tcp/6000"]
+            "#,
+            keypair.to_base58()
+        ));
+        assert!(res.is_err());
+    }
 }
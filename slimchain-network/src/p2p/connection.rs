@@ -0,0 +1,143 @@
+use libp2p::{
+    core::{connection::ConnectionId, ConnectedPoint},
+    swarm::{
+        protocols_handler::DummyProtocolsHandler, NetworkBehaviour, NetworkBehaviourAction,
+        PollParameters, ProtocolsHandler,
+    },
+    Multiaddr, PeerId,
+};
+use slimchain_common::collections::HashMap;
+use std::{
+    collections::VecDeque,
+    task::{Context, Poll},
+};
+
+/// Who dialed the connection.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ConnectionDirection {
+    /// Dialed by the peer.
+    Inbound,
+    /// Dialed by this node.
+    Outbound,
+}
+
+impl From<&ConnectedPoint> for ConnectionDirection {
+    fn from(endpoint: &ConnectedPoint) -> Self {
+        if endpoint.is_dialer() {
+            ConnectionDirection::Outbound
+        } else {
+            ConnectionDirection::Inbound
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ConnectionEvent {
+    /// The first connection to the peer is established.
+    Established {
+        peer_id: PeerId,
+        direction: ConnectionDirection,
+    },
+    /// The last connection to the peer is closed.
+    Closed {
+        peer_id: PeerId,
+        direction: ConnectionDirection,
+    },
+}
+
+/// Surface the connection churn of the swarm, which is otherwise only visible to the
+/// behaviours themselves. A peer with multiple connections is reported once, with the
+/// direction of its first connection.
+#[derive(Debug, Default)]
+pub struct ConnectionTracker {
+    peers: HashMap<PeerId, (ConnectionDirection, usize)>,
+    pending_events: VecDeque<ConnectionEvent>,
+}
+
+impl ConnectionTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn direction(&self, peer_id: &PeerId) -> Option<ConnectionDirection> {
+        self.peers.get(peer_id).map(|&(direction, _)| direction)
+    }
+
+    pub fn connected_peers(&self) -> usize {
+        self.peers.len()
+    }
+}
+
+impl NetworkBehaviour for ConnectionTracker {
+    type ProtocolsHandler = DummyProtocolsHandler;
+    type OutEvent = ConnectionEvent;
+
+    fn new_handler(&mut self) -> Self::ProtocolsHandler {
+        DummyProtocolsHandler::default()
+    }
+
+    fn addresses_of_peer(&mut self, _: &PeerId) -> Vec<Multiaddr> {
+        vec![]
+    }
+
+    fn inject_connected(&mut self, _: &PeerId) {}
+
+    fn inject_disconnected(&mut self, _: &PeerId) {}
+
+    fn inject_connection_established(
+        &mut self,
+        peer_id: &PeerId,
+        _: &ConnectionId,
+        endpoint: &ConnectedPoint,
+    ) {
+        let direction = ConnectionDirection::from(endpoint);
+        let entry = self.peers.entry(*peer_id).or_insert((direction, 0));
+        entry.1 += 1;
+        if entry.1 == 1 {
+            self.pending_events.push_back(ConnectionEvent::Established {
+                peer_id: *peer_id,
+                direction,
+            });
+        }
+    }
+
+    fn inject_connection_closed(&mut self, peer_id: &PeerId, _: &ConnectionId, _: &ConnectedPoint) {
+        let direction = match self.peers.get_mut(peer_id) {
+            Some(entry) if entry.1 > 1 => {
+                entry.1 -= 1;
+                return;
+            }
+            Some(entry) => entry.0,
+            None => return,
+        };
+        self.peers.remove(peer_id);
+        self.pending_events.push_back(ConnectionEvent::Closed {
+            peer_id: *peer_id,
+            direction,
+        });
+    }
+
+    fn inject_event(
+        &mut self,
+        _: PeerId,
+        _: ConnectionId,
+        _: <Self::ProtocolsHandler as ProtocolsHandler>::OutEvent,
+    ) {
+    }
+
+    fn poll(
+        &mut self,
+        _: &mut Context,
+        _: &mut impl PollParameters,
+    ) -> Poll<
+        NetworkBehaviourAction<
+            <Self::ProtocolsHandler as ProtocolsHandler>::InEvent,
+            Self::OutEvent,
+        >,
+    > {
+        match self.pending_events.pop_front() {
+            Some(event) => Poll::Ready(NetworkBehaviourAction::GenerateEvent(event)),
+            None => Poll::Pending,
+        }
+    }
+}
@@ -1,3 +1,4 @@
+use crate::p2p::{config::RelayConfig, relay::relayed_listen_addr};
 use async_trait::async_trait;
 use futures::{
     channel::{mpsc, oneshot},
@@ -6,12 +7,13 @@ use futures::{
 };
 use libp2p::{
     core::{muxing, transport},
+    dns,
     identity::Keypair,
     swarm::{
         protocols_handler::ProtocolsHandler, IntoProtocolsHandler, NetworkBehaviour, Swarm,
         SwarmEvent,
     },
-    Multiaddr, PeerId,
+    tcp, Multiaddr, PeerId, Transport,
 };
 use slimchain_common::error::{bail, Error, Result};
 use std::{pin::Pin, task::Poll, time::Duration};
@@ -23,10 +25,27 @@ const YAMUX_MAX_NUM_STREAM: usize = 8192;
 pub(crate) async fn build_transport(
     keypair: &Keypair,
 ) -> Result<transport::Boxed<(PeerId, muxing::StreamMuxerBox)>> {
-    use libp2p::{core::upgrade, dns, noise, tcp, yamux, Transport};
+    Ok(upgrade_transport(build_base_transport().await?, keypair))
+}
 
+pub(crate) async fn build_base_transport() -> Result<dns::DnsConfig<tcp::TcpConfig>> {
     let tcp = tcp::TcpConfig::new().nodelay(true);
-    let transport = dns::DnsConfig::system(tcp).await?;
+    Ok(dns::DnsConfig::system(tcp).await?)
+}
+
+pub(crate) fn upgrade_transport<T>(
+    transport: T,
+    keypair: &Keypair,
+) -> transport::Boxed<(PeerId, muxing::StreamMuxerBox)>
+where
+    T: Transport + Clone + Send + Sync + 'static,
+    T::Output: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    T::Error: Send + Sync + 'static,
+    T::Dial: Send + 'static,
+    T::Listener: Send + 'static,
+    T::ListenerUpgrade: Send + 'static,
+{
+    use libp2p::{core::upgrade, noise, yamux};
 
     let noise_keys = noise::Keypair::<noise::X25519Spec>::new()
         .into_authentic(keypair)
@@ -37,12 +56,12 @@ pub(crate) async fn build_transport(
     mux_cfg.set_max_num_streams(YAMUX_MAX_NUM_STREAM);
     mux_cfg.set_window_update_mode(yamux::WindowUpdateMode::on_read());
 
-    Ok(transport
+    transport
         .upgrade(upgrade::Version::V1Lazy)
         .authenticate(noise::NoiseConfig::xx(noise_keys).into_authenticated())
         .multiplex(mux_cfg)
         .timeout(Duration::from_secs(20))
-        .boxed())
+        .boxed()
 }
 
 #[async_trait]
@@ -72,15 +91,24 @@ where
     <<<Behaviour as NetworkBehaviour>::ProtocolsHandler as IntoProtocolsHandler>::Handler as ProtocolsHandler>::OutEvent: Send + 'static,
 {
     pub async fn new(key_pair: Keypair, behaviour: Behaviour) -> Result<Self> {
-        let peer_id = key_pair.public().into_peer_id();
         let transport = build_transport(&key_pair).await?;
+        Ok(Self::with_transport(key_pair, transport, behaviour))
+    }
+
+    /// Use the `transport` built elsewhere, e.g., with the relay enabled.
+    pub fn with_transport(
+        key_pair: Keypair,
+        transport: transport::Boxed<(PeerId, muxing::StreamMuxerBox)>,
+        behaviour: Behaviour,
+    ) -> Self {
+        let peer_id = key_pair.public().into_peer_id();
         let swarm = Swarm::new(transport, behaviour, peer_id);
 
-        Ok(Self {
+        Self {
             peer_id,
             key_pair,
             swarm,
-        })
+        }
     }
 
     pub async fn listen_on(&mut self, address: Multiaddr) -> Result<Multiaddr> {
@@ -105,6 +133,20 @@ where
         self.listen_on(address.parse()?).await
     }
 
+    /// Listen via the relay servers if `cfg.listen_via_relay` is set. The relayed addresses are
+    /// announced once the connections to the relay servers are established.
+    pub fn listen_via_relays(&mut self, cfg: &RelayConfig) -> Result<()> {
+        if !cfg.listen_via_relay {
+            return Ok(());
+        }
+        for server in &cfg.servers {
+            let address = relayed_listen_addr(server);
+            info!("Peer {} listening via {}", self.peer_id, address);
+            Swarm::listen_on(&mut self.swarm, address).map_err(Error::msg)?;
+        }
+        Ok(())
+    }
+
     pub fn peer_id(&self) -> &PeerId {
         &self.peer_id
     }
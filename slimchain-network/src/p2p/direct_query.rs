@@ -0,0 +1,462 @@
+use crate::p2p::{
+    config::{DirectQueryConfig, NetworkConfig},
+    control::Shutdown,
+    rpc::RpcProtocol,
+};
+use async_trait::async_trait;
+use core::marker::PhantomData;
+use futures::{
+    channel::{mpsc, oneshot},
+    prelude::*,
+};
+use libp2p::{
+    core::upgrade::{read_varint, write_varint},
+    request_response::{
+        ProtocolSupport, RequestId, RequestResponse, RequestResponseCodec, RequestResponseConfig,
+        RequestResponseEvent, RequestResponseMessage,
+    },
+    swarm::{NetworkBehaviourAction, NetworkBehaviourEventProcess, PollParameters},
+    Multiaddr, NetworkBehaviour, PeerId,
+};
+use serde::{Deserialize, Serialize};
+use slimchain_chain::{
+    block::BlockTrait,
+    db::{block_height_to_db_key, DBPtr, BLOCK_DB_COL},
+    role::Role,
+};
+use slimchain_common::{
+    basic::{BlockHeight, H256},
+    collections::HashMap,
+    error::{anyhow, ensure, Result},
+};
+use std::{
+    collections::VecDeque,
+    fmt, io, iter,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+const PROTOCOL_NAME: &str = "/direct_query/1";
+const CONN_KEEP_ALIVE: Duration = Duration::from_secs(30);
+/// Max size of a request in bytes.
+const MAX_REQUEST_SIZE: usize = 1024 * 1024;
+/// Max number of the trie nodes fetched in a request.
+const MAX_TRIE_NODES_PER_REQUEST: usize = 4096;
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub enum DirectQueryRequest {
+    GetBlock(BlockHeight),
+    /// Get the state nodes by their addresses.
+    GetTrieNodes(Vec<H256>),
+    GetStatus,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct PeerStatus {
+    pub role: String,
+    pub block_height: BlockHeight,
+    pub state_root: H256,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub enum DirectQueryResponse<Block> {
+    /// `None` if the block is not available.
+    Block(Option<Block>),
+    /// The state nodes in their on-disk encoding, in the order requested. `None` for those
+    /// not available.
+    TrieNodes(Vec<Option<Vec<u8>>>),
+    Status(PeerStatus),
+    /// Failed to serve the request.
+    Error(String),
+}
+
+/// Encode/decode the queries to/from the network using postcard.
+pub struct DirectQueryCodec<Block> {
+    max_response_size: usize,
+    _marker: PhantomData<Block>,
+}
+
+impl<Block> DirectQueryCodec<Block> {
+    pub fn new(max_response_size: usize) -> Self {
+        Self {
+            max_response_size,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<Block> Clone for DirectQueryCodec<Block> {
+    fn clone(&self) -> Self {
+        Self::new(self.max_response_size)
+    }
+}
+
+async fn read_message<T, Socket>(socket: &mut Socket, max_size: usize) -> io::Result<T>
+where
+    T: for<'de> Deserialize<'de>,
+    Socket: AsyncRead + Unpin + Send,
+{
+    let len = read_varint(socket).await?;
+    if len > max_size {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Message is too large. Size={}.", len),
+        ));
+    }
+    let mut buf = vec![0; len];
+    socket.read_exact(&mut buf).await?;
+    postcard::from_bytes(&buf).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+async fn write_message<T, Socket>(socket: &mut Socket, msg: &T) -> io::Result<()>
+where
+    T: Serialize,
+    Socket: AsyncWrite + Unpin + Send,
+{
+    let bin = postcard::to_allocvec(msg).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+    write_varint(socket, bin.len()).await?;
+    socket.write_all(bin.as_ref()).await?;
+    socket.close().await?;
+    Ok(())
+}
+
+#[async_trait]
+impl<Block> RequestResponseCodec for DirectQueryCodec<Block>
+where
+    Block: Serialize + for<'de> Deserialize<'de> + Send + 'static,
+{
+    type Protocol = RpcProtocol;
+    type Request = DirectQueryRequest;
+    type Response = DirectQueryResponse<Block>;
+
+    async fn read_request<Socket>(
+        &mut self,
+        _: &Self::Protocol,
+        socket: &mut Socket,
+    ) -> io::Result<Self::Request>
+    where
+        Socket: AsyncRead + Unpin + Send,
+    {
+        read_message(socket, MAX_REQUEST_SIZE).await
+    }
+
+    async fn read_response<Socket>(
+        &mut self,
+        _: &Self::Protocol,
+        socket: &mut Socket,
+    ) -> io::Result<Self::Response>
+    where
+        Socket: AsyncRead + Unpin + Send,
+    {
+        read_message(socket, self.max_response_size).await
+    }
+
+    async fn write_request<Socket>(
+        &mut self,
+        _: &Self::Protocol,
+        socket: &mut Socket,
+        request: Self::Request,
+    ) -> io::Result<()>
+    where
+        Socket: AsyncWrite + Unpin + Send,
+    {
+        write_message(socket, &request).await
+    }
+
+    async fn write_response<Socket>(
+        &mut self,
+        _: &Self::Protocol,
+        socket: &mut Socket,
+        response: Self::Response,
+    ) -> io::Result<()>
+    where
+        Socket: AsyncWrite + Unpin + Send,
+    {
+        write_message(socket, &response).await
+    }
+}
+
+struct QueryMsg<Block> {
+    peer: PeerId,
+    request: DirectQueryRequest,
+    ret: oneshot::Sender<Result<DirectQueryResponse<Block>>>,
+}
+
+/// Send queries to the [`DirectQuery`] behaviour from outside of the swarm.
+pub struct DirectQueryHandle<Block> {
+    tx: mpsc::UnboundedSender<QueryMsg<Block>>,
+}
+
+impl<Block> Clone for DirectQueryHandle<Block> {
+    fn clone(&self) -> Self {
+        Self {
+            tx: self.tx.clone(),
+        }
+    }
+}
+
+impl<Block> DirectQueryHandle<Block> {
+    pub async fn query(
+        &self,
+        peer: PeerId,
+        request: DirectQueryRequest,
+    ) -> Result<DirectQueryResponse<Block>> {
+        let (ret, rx) = oneshot::channel();
+        self.tx
+            .unbounded_send(QueryMsg { peer, request, ret })
+            .map_err(|_| anyhow!("DirectQuery: The swarm is closed."))?;
+        rx.await
+            .map_err(|_| anyhow!("DirectQuery: The query is dropped."))?
+    }
+}
+
+#[derive(Debug)]
+pub enum DirectQueryEvent {
+    /// An inbound query is answered.
+    Served {
+        peer: PeerId,
+        request: DirectQueryRequest,
+    },
+}
+
+/// Query the peers directly, for the data not worth gossiping, e.g., a missing block.
+/// The inbound queries are served from `db`.
+#[derive(NetworkBehaviour)]
+#[behaviour(poll_method = "poll_inner", out_event = "DirectQueryEvent")]
+pub struct DirectQuery<Block>
+where
+    Block: BlockTrait + Serialize + for<'de> Deserialize<'de> + fmt::Debug + 'static,
+{
+    request_response: RequestResponse<DirectQueryCodec<Block>>,
+    #[behaviour(ignore)]
+    role: Role,
+    #[behaviour(ignore)]
+    db: DBPtr,
+    #[behaviour(ignore)]
+    max_concurrent_requests: usize,
+    #[behaviour(ignore)]
+    query_tx: mpsc::UnboundedSender<QueryMsg<Block>>,
+    #[behaviour(ignore)]
+    query_rx: mpsc::UnboundedReceiver<QueryMsg<Block>>,
+    #[behaviour(ignore)]
+    queued_queries: VecDeque<QueryMsg<Block>>,
+    #[behaviour(ignore)]
+    pending_responses: HashMap<RequestId, oneshot::Sender<Result<DirectQueryResponse<Block>>>>,
+    #[behaviour(ignore)]
+    pending_events: VecDeque<DirectQueryEvent>,
+}
+
+impl<Block> DirectQuery<Block>
+where
+    Block: BlockTrait + Serialize + for<'de> Deserialize<'de> + fmt::Debug + 'static,
+{
+    pub fn new(role: Role, db: DBPtr, cfg: &DirectQueryConfig) -> Self {
+        let protocols = iter::once((RpcProtocol::new(PROTOCOL_NAME), ProtocolSupport::Full));
+        let mut rr_cfg = RequestResponseConfig::default();
+        rr_cfg.set_connection_keep_alive(CONN_KEEP_ALIVE);
+        rr_cfg.set_request_timeout(cfg.request_timeout);
+        let request_response = RequestResponse::new(
+            DirectQueryCodec::new(cfg.max_response_size),
+            protocols,
+            rr_cfg,
+        );
+        let (query_tx, query_rx) = mpsc::unbounded();
+
+        Self {
+            request_response,
+            role,
+            db,
+            max_concurrent_requests: cfg.max_concurrent_requests.max(1),
+            query_tx,
+            query_rx,
+            queued_queries: VecDeque::new(),
+            pending_responses: HashMap::new(),
+            pending_events: VecDeque::new(),
+        }
+    }
+
+    pub fn handle(&self) -> DirectQueryHandle<Block> {
+        DirectQueryHandle {
+            tx: self.query_tx.clone(),
+        }
+    }
+
+    pub fn add_address(&mut self, peer: &PeerId, address: Multiaddr) {
+        self.request_response.add_address(peer, address);
+    }
+
+    pub fn add_peers_from_net_config(&mut self, cfg: &NetworkConfig) {
+        for peer in &cfg.peers {
+            self.add_address(&peer.peer_id, peer.address.clone());
+        }
+    }
+
+    /// Send `request` to `peer`. It is queued if too many queries are in flight.
+    pub fn send_query(
+        &mut self,
+        peer: PeerId,
+        request: DirectQueryRequest,
+        ret: oneshot::Sender<Result<DirectQueryResponse<Block>>>,
+    ) {
+        self.queued_queries
+            .push_back(QueryMsg { peer, request, ret });
+        self.send_queued_queries();
+    }
+
+    /// Return whether any query is sent.
+    fn send_queued_queries(&mut self) -> bool {
+        let mut sent = false;
+        while self.pending_responses.len() < self.max_concurrent_requests {
+            let QueryMsg { peer, request, ret } = match self.queued_queries.pop_front() {
+                Some(msg) => msg,
+                None => break,
+            };
+            if ret.is_canceled() {
+                continue;
+            }
+            let id = self.request_response.send_request(&peer, request);
+            self.pending_responses.insert(id, ret);
+            sent = true;
+        }
+        sent
+    }
+
+    fn serve(&self, request: &DirectQueryRequest) -> Result<DirectQueryResponse<Block>> {
+        match request {
+            DirectQueryRequest::GetBlock(height) => {
+                let block = if height.is_zero() {
+                    Some(Block::genesis_block())
+                } else {
+                    self.db
+                        .get_object(BLOCK_DB_COL, &block_height_to_db_key(*height))?
+                };
+                Ok(DirectQueryResponse::Block(block))
+            }
+            DirectQueryRequest::GetTrieNodes(addrs) => {
+                ensure!(
+                    addrs.len() <= MAX_TRIE_NODES_PER_REQUEST,
+                    "Too many trie nodes requested. Max={}.",
+                    MAX_TRIE_NODES_PER_REQUEST
+                );
+                let nodes = addrs
+                    .iter()
+                    .map(|&addr| self.db.get_state_node_bin(addr))
+                    .collect::<Result<_>>()?;
+                Ok(DirectQueryResponse::TrieNodes(nodes))
+            }
+            DirectQueryRequest::GetStatus => {
+                let header = match self.db.get_latest_block_header()? {
+                    Some(header) => header,
+                    None => Block::genesis_block().block_header().clone(),
+                };
+                Ok(DirectQueryResponse::Status(PeerStatus {
+                    role: self.role.to_string(),
+                    block_height: header.height,
+                    state_root: header.state_root,
+                }))
+            }
+        }
+    }
+
+    fn poll_inner<T>(
+        &mut self,
+        cx: &mut Context,
+        _: &mut impl PollParameters,
+    ) -> Poll<NetworkBehaviourAction<T, DirectQueryEvent>> {
+        while let Poll::Ready(Some(msg)) = Pin::new(&mut self.query_rx).poll_next(cx) {
+            self.queued_queries.push_back(msg);
+        }
+        if self.send_queued_queries() {
+            // Poll again, so that the requests are sent by the request-response behaviour.
+            cx.waker().wake_by_ref();
+        }
+
+        if let Some(event) = self.pending_events.pop_front() {
+            return Poll::Ready(NetworkBehaviourAction::GenerateEvent(event));
+        }
+
+        Poll::Pending
+    }
+}
+
+impl<Block>
+    NetworkBehaviourEventProcess<
+        RequestResponseEvent<DirectQueryRequest, DirectQueryResponse<Block>>,
+    > for DirectQuery<Block>
+where
+    Block: BlockTrait + Serialize + for<'de> Deserialize<'de> + fmt::Debug + 'static,
+{
+    fn inject_event(
+        &mut self,
+        event: RequestResponseEvent<DirectQueryRequest, DirectQueryResponse<Block>>,
+    ) {
+        match event {
+            RequestResponseEvent::Message {
+                peer,
+                message:
+                    RequestResponseMessage::Request {
+                        request, channel, ..
+                    },
+            } => {
+                let response = self.serve(&request).unwrap_or_else(|e| {
+                    warn!(%peer, ?request, "DirectQuery: Failed to serve. Error: {}", e);
+                    DirectQueryResponse::Error(e.to_string())
+                });
+                if self
+                    .request_response
+                    .send_response(channel, response)
+                    .is_err()
+                {
+                    warn!(%peer, "DirectQuery: Failed to send the response.");
+                }
+                self.pending_events
+                    .push_back(DirectQueryEvent::Served { peer, request });
+            }
+            RequestResponseEvent::Message {
+                message:
+                    RequestResponseMessage::Response {
+                        request_id,
+                        response,
+                    },
+                ..
+            } => {
+                if let Some(ret) = self.pending_responses.remove(&request_id) {
+                    ret.send(Ok(response)).ok();
+                }
+            }
+            RequestResponseEvent::OutboundFailure {
+                peer,
+                request_id,
+                error,
+            } => {
+                if let Some(ret) = self.pending_responses.remove(&request_id) {
+                    ret.send(Err(anyhow!(
+                        "DirectQuery: Failed to query {}. Error: {:?}.",
+                        peer,
+                        error
+                    )))
+                    .ok();
+                }
+            }
+            RequestResponseEvent::InboundFailure { peer, error, .. } => {
+                warn!(%peer, "DirectQuery: Inbound error: {:?}", error);
+            }
+            RequestResponseEvent::ResponseSent { .. } => {}
+        }
+        self.send_queued_queries();
+    }
+}
+
+#[async_trait]
+impl<Block> Shutdown for DirectQuery<Block>
+where
+    Block: BlockTrait + Serialize + for<'de> Deserialize<'de> + fmt::Debug + 'static,
+{
+    async fn shutdown(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,114 @@
+use super::*;
+use crate::p2p::control::{Control, Swarmer};
+use libp2p::identity::Keypair;
+use serial_test::serial;
+use slimchain_chain::{
+    consensus::pow::Block,
+    db::{read_pool::DEFAULT_READ_THREADS, DB},
+};
+use slimchain_common::digest::Digestible;
+use slimchain_utils::init_tracing_for_test;
+
+async fn create_node(
+    role: Role,
+    cfg: &DirectQueryConfig,
+) -> (
+    PeerId,
+    Multiaddr,
+    DirectQueryHandle<Block>,
+    Control<DirectQuery<Block>>,
+) {
+    let keypair = Keypair::generate_ed25519();
+    let behaviour = DirectQuery::<Block>::new(role, DB::open_memory(DEFAULT_READ_THREADS), cfg);
+    let handle = behaviour.handle();
+    let mut swarmer = Swarmer::new(keypair.clone(), behaviour).await.unwrap();
+    let address = swarmer.listen_on_str("/ip4/127.0.0.1/tcp/0").await.unwrap();
+    let ctrl = swarmer.spawn();
+    (keypair.public().into_peer_id(), address, handle, ctrl)
+}
+
+#[tokio::test]
+#[serial]
+async fn test_direct_query() {
+    let _guard = init_tracing_for_test();
+
+    let cfg = DirectQueryConfig {
+        max_concurrent_requests: 1,
+        ..Default::default()
+    };
+    let (peer1, addr1, _handle1, ctrl1) = create_node(Role::Miner, &cfg).await;
+    let (_peer2, _addr2, handle2, mut ctrl2) = create_node(Role::Client, &cfg).await;
+    ctrl2
+        .call(move |swarm| swarm.behaviour_mut().add_address(&peer1, addr1))
+        .await
+        .unwrap();
+
+    let genesis = Block::genesis_block();
+    // More queries than the concurrent limit.
+    let (status, block, missing, nodes) = futures::join!(
+        handle2.query(peer1, DirectQueryRequest::GetStatus),
+        handle2.query(peer1, DirectQueryRequest::GetBlock(0u64.into())),
+        handle2.query(peer1, DirectQueryRequest::GetBlock(5u64.into())),
+        handle2.query(peer1, DirectQueryRequest::GetTrieNodes(vec![H256::zero()])),
+    );
+    match status.unwrap() {
+        DirectQueryResponse::Status(status) => assert_eq!(
+            PeerStatus {
+                role: Role::Miner.to_string(),
+                block_height: 0u64.into(),
+                state_root: genesis.block_header().state_root,
+            },
+            status
+        ),
+        resp => panic!("Unexpected response: {:?}", resp),
+    }
+    match block.unwrap() {
+        DirectQueryResponse::Block(Some(block)) => {
+            assert_eq!(genesis.to_digest(), block.to_digest())
+        }
+        resp => panic!("Unexpected response: {:?}", resp),
+    }
+    assert!(matches!(missing.unwrap(), DirectQueryResponse::Block(None)));
+    match nodes.unwrap() {
+        DirectQueryResponse::TrieNodes(nodes) => assert_eq!(vec![None], nodes),
+        resp => panic!("Unexpected response: {:?}", resp),
+    }
+
+    let resp = handle2
+        .query(
+            peer1,
+            DirectQueryRequest::GetTrieNodes(vec![H256::zero(); MAX_TRIE_NODES_PER_REQUEST + 1]),
+        )
+        .await
+        .unwrap();
+    assert!(matches!(resp, DirectQueryResponse::Error(_)));
+
+    ctrl1.shutdown().await.unwrap();
+    ctrl2.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+#[serial]
+async fn test_max_response_size() {
+    let _guard = init_tracing_for_test();
+
+    let (peer1, addr1, _handle1, ctrl1) =
+        create_node(Role::Miner, &DirectQueryConfig::default()).await;
+    let cfg = DirectQueryConfig {
+        max_response_size: 8,
+        ..Default::default()
+    };
+    let (_peer2, _addr2, handle2, mut ctrl2) = create_node(Role::Client, &cfg).await;
+    ctrl2
+        .call(move |swarm| swarm.behaviour_mut().add_address(&peer1, addr1))
+        .await
+        .unwrap();
+
+    assert!(handle2
+        .query(peer1, DirectQueryRequest::GetBlock(0u64.into()))
+        .await
+        .is_err());
+
+    ctrl1.shutdown().await.unwrap();
+    ctrl2.shutdown().await.unwrap();
+}
@@ -1,17 +1,23 @@
-use crate::p2p::config::NetworkConfig;
+use crate::p2p::{
+    config::{NetworkConfig, PeerConfig},
+    relay::{relayed_addr, Relay},
+};
 use futures::{channel::oneshot, prelude::*};
 use futures_timer::Delay;
 use libp2p::{
     identify::{Identify, IdentifyConfig, IdentifyEvent},
     identity::PublicKey,
     kad::{
-        record::store::MemoryStore as KadMemoryStore, record::Key as KadKey, GetProvidersOk,
-        Kademlia, KademliaConfig, KademliaEvent, QueryId as KadQueryId,
+        record::store::MemoryStore as KadMemoryStore, record::Key as KadKey, BootstrapOk,
+        GetProvidersOk, Kademlia, KademliaConfig, KademliaEvent, QueryId as KadQueryId,
         QueryResult as KadQueryResult,
     },
     mdns::{Mdns, MdnsConfig, MdnsEvent},
     ping::{Ping, PingConfig, PingEvent},
-    swarm::{toggle::Toggle, NetworkBehaviourAction, NetworkBehaviourEventProcess, PollParameters},
+    swarm::{
+        toggle::Toggle, DialPeerCondition, NetworkBehaviourAction, NetworkBehaviourEventProcess,
+        PollParameters,
+    },
     Multiaddr, NetworkBehaviour, PeerId,
 };
 use rand::seq::IteratorRandom;
@@ -21,6 +27,7 @@ use slimchain_common::{
     create_id_type_u64,
     error::{anyhow, Result},
 };
+use slimchain_utils::rng::rng;
 use std::{
     cmp,
     collections::VecDeque,
@@ -36,6 +43,7 @@ const KAD_MAX_INTERVAL: Duration = Duration::from_secs(60);
 const KAD_INIT_INTERVAL: Duration = Duration::from_secs(1);
 const PING_INTERVAL: Duration = Duration::from_secs(30);
 const PING_TIMEOUT: Duration = Duration::from_secs(45);
+const BOOTSTRAP_INTERVAL: Duration = Duration::from_secs(300);
 
 create_id_type_u64!(QueryId);
 
@@ -55,6 +63,7 @@ pub struct Discovery {
     identify: Identify,
     ping: Ping,
     mdns: Toggle<Mdns>,
+    relay: Toggle<Relay>,
     #[behaviour(ignore)]
     peer_id: PeerId,
     #[behaviour(ignore)]
@@ -75,6 +84,20 @@ pub struct Discovery {
     pending_events: VecDeque<DiscoveryEvent>,
     #[behaviour(ignore)]
     pending_queries_using_ret: HashMap<QueryId, oneshot::Sender<Result<PeerId>>>,
+    #[behaviour(ignore)]
+    bootstrap_interval: Duration,
+    #[behaviour(ignore)]
+    next_bootstrap: Delay,
+    #[behaviour(ignore)]
+    bootstrap_done: bool,
+    #[behaviour(ignore)]
+    bootstrap_waiters: Vec<oneshot::Sender<()>>,
+    #[behaviour(ignore)]
+    discovered_peers: HashSet<PeerId>,
+    #[behaviour(ignore)]
+    pending_dials: VecDeque<PeerId>,
+    #[behaviour(ignore)]
+    relay_servers: Vec<PeerConfig>,
 }
 
 impl Discovery {
@@ -117,6 +140,7 @@ impl Discovery {
             identify,
             ping,
             mdns: mdns.into(),
+            relay: None.into(),
             peer_id,
             peer_table: HashMap::new(),
             rev_peer_table: HashMap::new(),
@@ -127,6 +151,13 @@ impl Discovery {
             pending_retry_queries: DelayQueue::new(),
             pending_events: VecDeque::new(),
             pending_queries_using_ret: HashMap::new(),
+            bootstrap_interval: BOOTSTRAP_INTERVAL,
+            next_bootstrap: Delay::new(Duration::from_secs(0)),
+            bootstrap_done: false,
+            bootstrap_waiters: Vec::new(),
+            discovered_peers: HashSet::new(),
+            pending_dials: VecDeque::new(),
+            relay_servers: Vec::new(),
         })
     }
 
@@ -136,10 +167,68 @@ impl Discovery {
         }
     }
 
+    /// Add a bootnode and bootstrap from it right away.
+    pub fn add_bootnode(&mut self, peer_id: PeerId, address: Multiaddr) {
+        self.add_address(peer_id, address);
+        self.next_bootstrap = Delay::new(Duration::from_secs(0));
+    }
+
     pub fn add_address_from_net_config(&mut self, cfg: &NetworkConfig) {
         for peer in cfg.peers.iter() {
             self.add_address(peer.peer_id, peer.address.clone());
         }
+        for peer in cfg.bootnodes.iter() {
+            self.add_bootnode(peer.peer_id, peer.address.clone());
+        }
+        for server in cfg.relay.servers.iter() {
+            self.add_address(server.peer_id, server.address.clone());
+        }
+        self.relay_servers = cfg.relay.servers.clone();
+        self.set_bootstrap_interval(cfg.bootstrap_interval);
+    }
+
+    /// Add the relay behaviour built with the transport of the swarm.
+    pub fn set_relay(&mut self, relay: Option<Relay>) {
+        self.relay = relay.into();
+    }
+
+    pub fn set_bootstrap_interval(&mut self, interval: Duration) {
+        self.bootstrap_interval = interval;
+    }
+
+    /// Number of the peers in the routing table, which are dialed once discovered.
+    pub fn discovered_peer_count(&self) -> usize {
+        self.discovered_peers.len()
+    }
+
+    /// Whether a bootstrap has finished with at least one peer discovered.
+    pub fn bootstrap_done(&self) -> bool {
+        self.bootstrap_done
+    }
+
+    /// Notify `ret` once [`bootstrap_done`](Self::bootstrap_done).
+    pub fn wait_bootstrap_with_ret(&mut self, ret: oneshot::Sender<()>) {
+        if self.bootstrap_done {
+            ret.send(()).ok();
+        } else {
+            self.bootstrap_waiters.push(ret);
+        }
+    }
+
+    fn finish_bootstrap(&mut self) {
+        if self.discovered_peers.is_empty() {
+            return;
+        }
+        if !self.bootstrap_done {
+            info!(
+                "Bootstrap done with {} peers discovered.",
+                self.discovered_peers.len()
+            );
+            self.bootstrap_done = true;
+        }
+        for ret in self.bootstrap_waiters.drain(..) {
+            ret.send(()).ok();
+        }
     }
 
     pub fn report_known_peers(&self) {
@@ -159,8 +248,7 @@ impl Discovery {
 
     pub fn random_known_peer(&self, role: &Role) -> Option<PeerId> {
         if let Some(list) = self.peer_table.get(role) {
-            let mut rng = rand::thread_rng();
-            list.iter().choose(&mut rng).copied()
+            list.iter().choose(&mut rng()).copied()
         } else {
             None
         }
@@ -168,9 +256,8 @@ impl Discovery {
 
     pub fn random_known_peers(&self, role: &Role, amount: usize) -> Vec<PeerId> {
         if let Some(list) = self.peer_table.get(role) {
-            let mut rng = rand::thread_rng();
             list.iter()
-                .choose_multiple(&mut rng, amount)
+                .choose_multiple(&mut rng(), amount)
                 .into_iter()
                 .copied()
                 .collect()
@@ -258,6 +345,21 @@ impl Discovery {
             }
         }
 
+        if let Some(peer_id) = self.pending_dials.pop_front() {
+            return Poll::Ready(NetworkBehaviourAction::DialPeer {
+                peer_id,
+                condition: DialPeerCondition::Disconnected,
+            });
+        }
+
+        while Pin::new(&mut self.next_bootstrap).poll(cx).is_ready() {
+            match self.kad.bootstrap() {
+                Ok(_) => self.next_bootstrap = Delay::new(self.bootstrap_interval),
+                // Retry soon, as the peers may still be found by mdns.
+                Err(_) => self.next_bootstrap = Delay::new(KAD_INIT_INTERVAL),
+            }
+        }
+
         while Pin::new(&mut self.next_kad_query).poll(cx).is_ready() {
             self.kad.get_closest_peers(PeerId::random());
 
@@ -354,6 +456,10 @@ impl NetworkBehaviourEventProcess<MdnsEvent> for Discovery {
     }
 }
 
+impl NetworkBehaviourEventProcess<()> for Discovery {
+    fn inject_event(&mut self, _: ()) {}
+}
+
 impl NetworkBehaviourEventProcess<KademliaEvent> for Discovery {
     fn inject_event(&mut self, event: KademliaEvent) {
         match event {
@@ -391,6 +497,42 @@ impl NetworkBehaviourEventProcess<KademliaEvent> for Discovery {
                         .insert((query_id, role, deadline), RETRY_WAIT_INTERVAL);
                 }
             }
+            KademliaEvent::OutboundQueryCompleted {
+                result: KadQueryResult::Bootstrap(result),
+                ..
+            } => match result {
+                Ok(BootstrapOk { num_remaining, .. }) => {
+                    if num_remaining == 0 {
+                        self.finish_bootstrap();
+                    }
+                }
+                Err(e) => {
+                    warn!("Failed to bootstrap. Error: {:?}", e);
+                    self.finish_bootstrap();
+                }
+            },
+            KademliaEvent::RoutingUpdated {
+                peer,
+                is_new_peer,
+                old_peer,
+                ..
+            } => {
+                if let Some(old_peer) = old_peer {
+                    self.discovered_peers.remove(&old_peer);
+                }
+                if is_new_peer && self.discovered_peers.insert(peer) {
+                    trace!("Discovered peer {} from kad.", peer);
+                    // Tried after the direct addresses fail.
+                    if self.relay.is_enabled() {
+                        for server in &self.relay_servers {
+                            if server.peer_id != peer {
+                                self.kad.add_address(&peer, relayed_addr(server, peer));
+                            }
+                        }
+                    }
+                    self.pending_dials.push_back(peer);
+                }
+            }
             KademliaEvent::OutboundQueryCompleted {
                 result: KadQueryResult::StartProviding(Err(error)),
                 ..
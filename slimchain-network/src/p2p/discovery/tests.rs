@@ -1,6 +1,9 @@
 use super::*;
-use crate::p2p::control::{Control, Shutdown, Swarmer};
-use futures::channel::oneshot;
+use crate::p2p::{
+    control::{Control, Shutdown, Swarmer},
+    pubsub::{PubSub, PubSubEvent, PubSubTopic},
+};
+use futures::channel::{mpsc, oneshot};
 use libp2p::identity::Keypair;
 use serial_test::serial;
 use slimchain_common::basic::ShardId;
@@ -227,3 +230,135 @@ async fn test_without_mdns() {
     ctrl3.shutdown().await.unwrap();
     ctrl4.shutdown().await.unwrap();
 }
+
+#[derive(NetworkBehaviour)]
+struct DiscoveryPubSubTest {
+    discv: Discovery,
+    pubsub: PubSub<String, String>,
+    #[behaviour(ignore)]
+    event_tx: mpsc::UnboundedSender<PubSubEvent<String, String>>,
+}
+
+impl NetworkBehaviourEventProcess<DiscoveryEvent> for DiscoveryPubSubTest {
+    fn inject_event(&mut self, _: DiscoveryEvent) {}
+}
+
+impl NetworkBehaviourEventProcess<PubSubEvent<String, String>> for DiscoveryPubSubTest {
+    fn inject_event(&mut self, event: PubSubEvent<String, String>) {
+        self.event_tx.unbounded_send(event).ok();
+    }
+}
+
+#[async_trait::async_trait]
+impl Shutdown for DiscoveryPubSubTest {
+    async fn shutdown(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+async fn create_pubsub_node(
+    sub_topics: &[PubSubTopic],
+) -> (
+    PeerId,
+    Multiaddr,
+    Control<DiscoveryPubSubTest>,
+    mpsc::UnboundedReceiver<PubSubEvent<String, String>>,
+) {
+    let keypair = Keypair::generate_ed25519();
+    let (event_tx, event_rx) = mpsc::unbounded();
+    let discv = Discovery::new(keypair.public(), Role::Client, false)
+        .await
+        .unwrap();
+    let pubsub = PubSub::new(keypair.clone(), sub_topics, &[]).unwrap();
+    let mut swarmer = Swarmer::new(
+        keypair.clone(),
+        DiscoveryPubSubTest {
+            discv,
+            pubsub,
+            event_tx,
+        },
+    )
+    .await
+    .unwrap();
+    let address = swarmer.listen_on_str("/ip4/127.0.0.1/tcp/0").await.unwrap();
+    let ctrl = swarmer.spawn();
+    (keypair.public().into_peer_id(), address, ctrl, event_rx)
+}
+
+#[tokio::test]
+#[serial]
+async fn test_bootnode() {
+    let _guard = init_tracing_for_test();
+
+    // The bootnode does not subscribe, so that the gossip has to go through the discovered peers.
+    let (peer0, addr0, ctrl0, _event_rx0) = create_pubsub_node(&[]).await;
+    let (peer1, _addr1, mut ctrl1, _event_rx1) =
+        create_pubsub_node(&[PubSubTopic::TxProposal]).await;
+    let (_peer2, _addr2, mut ctrl2, mut event_rx2) =
+        create_pubsub_node(&[PubSubTopic::TxProposal]).await;
+
+    for ctrl in [&mut ctrl1, &mut ctrl2].iter_mut() {
+        let addr = addr0.clone();
+        ctrl.call(move |swarm| swarm.behaviour_mut().discv.add_bootnode(peer0, addr))
+            .await
+            .unwrap();
+        ctrl.call_with_sender(|swarm, ret| {
+            swarm.behaviour_mut().discv.wait_bootstrap_with_ret(ret)
+        })
+        .await
+        .unwrap();
+    }
+
+    // The third node learns the publisher from the bootnode.
+    loop {
+        ctrl2
+            .call(|swarm| swarm.behaviour_mut().discv.kad.bootstrap().ok())
+            .await
+            .unwrap();
+        let known = ctrl2
+            .call(move |swarm| {
+                swarm
+                    .behaviour()
+                    .pubsub
+                    .known_peers()
+                    .get(&PubSubTopic::TxProposal)
+                    .map_or(false, |peers| peers.contains(&peer1))
+            })
+            .await
+            .unwrap();
+        if known {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert!(
+        ctrl2
+            .call(|swarm| swarm.behaviour().discv.discovered_peer_count())
+            .await
+            .unwrap()
+            >= 2
+    );
+    assert!(ctrl2
+        .call(|swarm| swarm.behaviour().discv.bootstrap_done())
+        .await
+        .unwrap());
+
+    ctrl1
+        .call(|swarm| {
+            swarm
+                .behaviour_mut()
+                .pubsub
+                .publish_tx_proposal(&"tx".to_string())
+        })
+        .await
+        .unwrap()
+        .unwrap();
+    match tokio::time::timeout(Duration::from_secs(5), event_rx2.next()).await {
+        Ok(Some(PubSubEvent::TxProposal(input, _))) => assert_eq!("tx", input),
+        e => panic!("Unexpected event: {:?}", e),
+    }
+
+    ctrl0.shutdown().await.unwrap();
+    ctrl1.shutdown().await.unwrap();
+    ctrl2.shutdown().await.unwrap();
+}
@@ -1,4 +1,10 @@
-use crate::http::client_rpc::client_rpc_server;
+use crate::{
+    http::{
+        client_rpc::client_rpc_server, common::send_get_request_using_json,
+        health::HEALTH_ROUTE_PATH, metrics::metrics_server,
+    },
+    p2p::pubsub::{PubSubPeersReport, SharedPubSubPeersReport},
+};
 use futures::{channel::mpsc, future::BoxFuture, prelude::*, stream};
 use libp2p::{
     core::connection::ConnectionId,
@@ -8,18 +14,45 @@ use libp2p::{
     },
     Multiaddr, PeerId,
 };
-
+use serde::{Deserialize, Serialize};
 use slimchain_common::{
     basic::BlockHeight,
     error::{Error, Result},
 };
 use std::{
     net::SocketAddr,
+    sync::Arc,
     task::{Context, Poll},
 };
+use warp::{filters::BoxedFilter, Filter, Reply};
 
 pub use crate::http::client_rpc::TxHttpRequest;
 
+/// The health of a node, reported at `/health` by [`ClientHttpServer::new_with_health`].
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct NodeHealthReport {
+    pub peer_id: String,
+    pub block_height: BlockHeight,
+    pub pubsub: PubSubPeersReport,
+}
+
+pub async fn get_node_health(endpoint: &str) -> Result<NodeHealthReport> {
+    send_get_request_using_json(&format!("http://{}/{}", endpoint, HEALTH_ROUTE_PATH)).await
+}
+
+fn tx_req_channel() -> (
+    impl Fn(Vec<TxHttpRequest>) -> BoxFuture<'static, Result<()>> + Send + Sync + 'static,
+    mpsc::Receiver<TxHttpRequest>,
+) {
+    let (tx, rx) = mpsc::channel(1024);
+    let tx_req_fn = move |reqs: Vec<TxHttpRequest>| {
+        let mut tx = tx.clone();
+        let mut reqs = stream::iter(reqs).map(Ok);
+        async move { tx.send_all(&mut reqs).await.map_err(Error::msg) }.boxed()
+    };
+    (tx_req_fn, rx)
+}
+
 pub struct ClientHttpServer {
     srv: BoxFuture<'static, ()>,
     recv: mpsc::Receiver<TxHttpRequest>,
@@ -30,18 +63,51 @@ impl ClientHttpServer {
         endpoint: &str,
         tx_count_fn: impl Fn() -> usize + Send + Sync + 'static,
         block_height_fn: impl Fn() -> BlockHeight + Send + Sync + 'static,
+    ) -> Result<Self> {
+        let (tx_req_fn, rx) = tx_req_channel();
+        let route = client_rpc_server(tx_req_fn, tx_count_fn, block_height_fn);
+        Self::serve(endpoint, route, rx)
+    }
+
+    /// Same as [`ClientHttpServer::new`], while the node health, including the pubsub peers,
+    /// is also reported at `/health`.
+    pub fn new_with_health(
+        endpoint: &str,
+        peer_id: PeerId,
+        tx_count_fn: impl Fn() -> usize + Send + Sync + 'static,
+        block_height_fn: impl Fn() -> BlockHeight + Send + Sync + 'static,
+        pubsub_peers: SharedPubSubPeersReport,
+    ) -> Result<Self> {
+        let (tx_req_fn, rx) = tx_req_channel();
+        let block_height_fn = Arc::new(block_height_fn);
+        let health_block_height_fn = block_height_fn.clone();
+        let health_route = warp::get()
+            .and(warp::path(HEALTH_ROUTE_PATH))
+            .and(warp::path::end())
+            .map(move || {
+                warp::reply::json(&NodeHealthReport {
+                    peer_id: peer_id.to_base58(),
+                    block_height: health_block_height_fn(),
+                    pubsub: pubsub_peers.get(),
+                })
+            });
+        let route = client_rpc_server(tx_req_fn, tx_count_fn, move || block_height_fn())
+            .or(health_route)
+            .boxed();
+        Self::serve(endpoint, route, rx)
+    }
+
+    fn serve(
+        endpoint: &str,
+        route: BoxedFilter<(impl Reply + Send + 'static,)>,
+        recv: mpsc::Receiver<TxHttpRequest>,
     ) -> Result<Self> {
         info!("Create tx http server, listen on {}", endpoint);
         let listen_addr: SocketAddr = endpoint.parse()?;
-        let (tx, rx) = mpsc::channel(1024);
-        let tx_req_fn = move |reqs: Vec<TxHttpRequest>| {
-            let mut tx = tx.clone();
-            let mut reqs = stream::iter(reqs).map(Ok);
-            async move { tx.send_all(&mut reqs).await.map_err(Error::msg) }
-        };
-        let route = client_rpc_server(tx_req_fn, tx_count_fn, block_height_fn);
-        let srv = warp::serve(route).bind(listen_addr).boxed();
-        Ok(Self { srv, recv: rx })
+        let srv = warp::serve(route.or(metrics_server()))
+            .bind(listen_addr)
+            .boxed();
+        Ok(Self { srv, recv })
     }
 }
 
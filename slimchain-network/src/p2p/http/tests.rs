@@ -30,7 +30,7 @@ async fn test() {
             nonce: Default::default(),
             code: Default::default(),
         };
-        tx_req.sign(&keypair)
+        tx_req.sign(&keypair, 0)
     };
 
     let handler = tokio::spawn(async move {
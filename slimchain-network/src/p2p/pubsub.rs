@@ -1,41 +1,93 @@
-use crate::p2p::config::NetworkConfig;
+use crate::p2p::{
+    config::{
+        NetworkConfig, OverflowPolicy, PeerScoreConfig, PendingQueueConfig,
+        PubSubCompressionConfig, PubSubConfig,
+    },
+    connection::{ConnectionDirection, ConnectionEvent, ConnectionTracker},
+};
+use futures::channel::mpsc;
 use libp2p::{
     gossipsub::{
-        error::PublishError, Gossipsub, GossipsubConfigBuilder, GossipsubEvent, GossipsubMessage,
-        IdentTopic, MessageAuthenticity, MessageId, TopicHash,
+        error::PublishError, Gossipsub, GossipsubConfig, GossipsubConfigBuilder, GossipsubEvent,
+        GossipsubMessage, IdentTopic, MessageAuthenticity, MessageId, TopicHash,
     },
     identity::Keypair,
     swarm::{NetworkBehaviourAction, NetworkBehaviourEventProcess, PollParameters},
     NetworkBehaviour, PeerId,
 };
 use once_cell::sync::Lazy;
+use rand::Rng as _;
 use serde::{Deserialize, Serialize};
 use slimchain_common::{
+    basic::{BlockHeight, ShardId, H256},
     collections::{HashMap, HashSet},
     digest::Digestible,
-    error::{anyhow, ensure, Result},
+    error::{anyhow, bail, ensure, Error, Result},
+};
+use slimchain_utils::{
+    config::on_section_reload,
+    prometheus::{MetricsWriter, REGISTRY},
+    record_event,
+    rng::rng,
+    serde::{binary_decode, binary_encode},
 };
-use slimchain_utils::serde::{binary_decode, binary_encode};
 use std::{
     cmp,
-    collections::VecDeque,
+    collections::{BTreeMap, VecDeque},
+    io::Read,
+    sync::{Arc, Mutex},
     task::{Context, Poll},
-    time::Duration,
+    time::{Duration, Instant},
 };
+use tokio::time::Interval;
 use tokio_util::time::DelayQueue;
 
-const MAX_MESSAGE_SIZE: usize = 45_000_000;
-const MAX_TRANSMIT_SIZE: usize = 50_000_000;
-const DUPLICATE_CACHE_TTL: Duration = Duration::from_secs(1_800);
-const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(30);
 const CHECK_EXPLICIT_PEERS_TICKS: u64 = 2;
 const PUB_MAX_RETRIES: usize = 10;
 const PUB_INIT_RETRY_DELAY: Duration = Duration::from_secs(1);
 const PUB_MAX_RETRY_DELAY: Duration = Duration::from_secs(16);
+/// Max size of a decompressed message.
+const MAX_DECOMPRESSED_SIZE: u64 = 1 << 30;
+/// The format tags prepended to the messages. The messages from older peers come without a
+/// tag and are in the snappy framing format, which starts with `0xff`.
+const POSTCARD_FORMAT_TAG: u8 = 0x00;
+const POSTCARD_ZSTD_FORMAT_TAG: u8 = 0x01;
+const CHUNK_FORMAT_TAG: u8 = 0x02;
+/// Upper bound of the encoded size of a chunk other than its data.
+const CHUNK_HEADER_SIZE: usize = 128;
+const MAX_CHUNKS_PER_MESSAGE: usize = 8;
+/// Max number of the chunked messages being reassembled from each source. The oldest one of the
+/// source is dropped once exceeded.
+const MAX_PARTIAL_MESSAGES: usize = 4;
+/// Drop the chunked messages not completed within this time.
+const REASSEMBLY_TIMEOUT: Duration = Duration::from_secs(60);
+/// The score of a peer is capped by this, so that it cannot build up credit for later
+/// misbehaviour.
+const MAX_PEER_SCORE: f64 = 10.0;
+/// Forget the peers whose scores decay below this.
+const MIN_TRACKED_PEER_SCORE: f64 = 0.1;
+/// How often the peer scores are decayed, the expired bans are lifted, the incomplete chunked
+/// messages are purged, and the shared peers and metrics reports are refreshed.
+const HOUSEKEEPING_INTERVAL: Duration = Duration::from_secs(1);
+/// Log the events dropped from the full queue of a topic at most once in this time span.
+const DROPPED_EVENTS_LOG_INTERVAL: Duration = Duration::from_secs(1);
+/// The version of the pubsub wire format, appended to the gossipsub protocol id. Bump it on
+/// the changes which the older peers cannot cope with. The optional features are advertised
+/// by [`PubSubFeatures`] instead.
+pub const PUBSUB_PROTOCOL_VERSION: u32 = 1;
+/// The internal topic on which the peers announce their [`PubSubFeatures`].
+const CAPABILITIES_TOPIC: &str = "pubsub_capabilities";
+
+static CAPABILITIES_TOPIC_HASH: Lazy<TopicHash> =
+    Lazy::new(|| IdentTopic::new(CAPABILITIES_TOPIC).hash());
 
 static TOPIC_MAP: Lazy<HashMap<TopicHash, PubSubTopic>> = Lazy::new(|| {
-    let mut map = HashMap::with_capacity(2);
-    for &topic in &[PubSubTopic::TxProposal, PubSubTopic::BlockProposal] {
+    let mut map = HashMap::with_capacity(3);
+    for &topic in &[
+        PubSubTopic::TxProposal,
+        PubSubTopic::BlockProposal,
+        PubSubTopic::StateSync,
+    ] {
         map.insert(topic.into_topic_hash(), topic);
     }
     map
@@ -45,25 +97,450 @@ static TOPIC_MAP: Lazy<HashMap<TopicHash, PubSubTopic>> = Lazy::new(|| {
 pub enum PubSubTopic {
     TxProposal,
     BlockProposal,
+    StateSync,
 }
 
 impl PubSubTopic {
-    pub fn into_topic(self) -> IdentTopic {
+    pub fn name(self) -> &'static str {
+        match self {
+            PubSubTopic::TxProposal => "tx_proposal",
+            PubSubTopic::BlockProposal => "block_proposal",
+            PubSubTopic::StateSync => "state_sync",
+        }
+    }
+
+    /// The order to deliver the pending events, where the lower comes first. The block
+    /// proposals are not delayed by a flood of tx proposals.
+    fn priority(self) -> usize {
         match self {
-            PubSubTopic::TxProposal => IdentTopic::new("tx_proposal".to_string()),
-            PubSubTopic::BlockProposal => IdentTopic::new("block_proposal".to_string()),
+            PubSubTopic::BlockProposal => 0,
+            PubSubTopic::StateSync => 1,
+            PubSubTopic::TxProposal => 2,
         }
     }
 
+    pub fn into_topic(self) -> IdentTopic {
+        IdentTopic::new(self.name().to_string())
+    }
+
     pub fn into_topic_hash(self) -> TopicHash {
         self.into_topic().hash()
     }
 }
 
+/// The optional pubsub features supported by a peer, as a bitmask. The bits unknown to this
+/// node are kept, so that newer peers can add features.
+#[derive(Debug, Copy, Clone, Default, Hash, Eq, PartialEq, Serialize, Deserialize)]
+pub struct PubSubFeatures(u32);
+
+impl PubSubFeatures {
+    /// Decode the tagged messages, including the zstd compressed ones.
+    pub const COMPRESSION: Self = Self(1);
+    /// Reassemble the chunked messages.
+    pub const CHUNKING: Self = Self(1 << 1);
+
+    pub fn empty() -> Self {
+        Self(0)
+    }
+
+    /// The features supported by this node.
+    pub fn all() -> Self {
+        Self(Self::COMPRESSION.0 | Self::CHUNKING.0)
+    }
+
+    pub fn from_bits(bits: u32) -> Self {
+        Self(bits)
+    }
+
+    pub fn bits(self) -> u32 {
+        self.0
+    }
+
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+/// Published on [`CAPABILITIES_TOPIC`] once a peer subscribes to it. The nonce makes each
+/// announcement a new gossipsub message.
+#[derive(Debug, Serialize, Deserialize)]
+struct CapabilityAnnouncement {
+    version: u32,
+    features: PubSubFeatures,
+    nonce: u64,
+}
+
+/// Announced by a storage node of `shard_id` once the checkpoint at `height` is available
+/// to sync from.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct StateSyncAnnouncement {
+    pub shard_id: ShardId,
+    pub height: BlockHeight,
+    pub state_root: H256,
+}
+
+/// The peers known on a topic.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct TopicPeersReport {
+    /// The base58 encoded ids of the peers subscribing to the topic.
+    pub peers: Vec<String>,
+    /// The number of the peers in the mesh of this node.
+    pub mesh_peers: usize,
+}
+
+/// The known peers of each topic, keyed by the topic names.
+pub type PubSubPeersReport = BTreeMap<String, TopicPeersReport>;
+
+/// The latest [`PubSubPeersReport`], which can be read outside of the swarm.
+#[derive(Debug, Clone, Default)]
+pub struct SharedPubSubPeersReport(Arc<Mutex<PubSubPeersReport>>);
+
+impl SharedPubSubPeersReport {
+    pub fn get(&self) -> PubSubPeersReport {
+        self.0.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    fn set(&self, report: PubSubPeersReport) {
+        *self.0.lock().unwrap_or_else(|e| e.into_inner()) = report;
+    }
+}
+
+/// The traffic counters of a topic.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct TopicMetricsReport {
+    pub received: u64,
+    pub received_bytes: u64,
+    /// The duplicated chunks. The duplicates suppressed by the gossipsub message id cache
+    /// are not exposed by gossipsub.
+    pub duplicates: u64,
+    /// The messages or chunks published.
+    pub published: u64,
+    /// The publish failures by the kind, including the retried ones.
+    pub publish_failures: BTreeMap<String, u64>,
+    pub decode_failures: u64,
+    /// The events dropped as the pending queue is full.
+    pub dropped_events: u64,
+}
+
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct PubSubMetricsReport {
+    /// The counters of each topic, keyed by the topic names.
+    pub topics: BTreeMap<String, TopicMetricsReport>,
+    /// The messages reported as [`MessageOutcome::Duplicate`] by the application.
+    pub reported_duplicates: u64,
+    pub unauthorized_messages: u64,
+    /// The number of the connected peers subscribing to each topic, keyed by the topic names.
+    pub peer_counts: BTreeMap<String, usize>,
+}
+
+impl PubSubMetricsReport {
+    /// Write the report as the `slimchain_pubsub_*` series, labeled by the topic.
+    pub fn write_prometheus(&self, w: &mut MetricsWriter) {
+        for (topic, report) in &self.topics {
+            let labels = [("topic", topic.as_str())];
+            let counters = [
+                (
+                    "slimchain_pubsub_received_total",
+                    "The messages or chunks received.",
+                    report.received,
+                ),
+                (
+                    "slimchain_pubsub_received_bytes_total",
+                    "The bytes received.",
+                    report.received_bytes,
+                ),
+                (
+                    "slimchain_pubsub_duplicates_total",
+                    "The duplicated chunks received.",
+                    report.duplicates,
+                ),
+                (
+                    "slimchain_pubsub_published_total",
+                    "The messages or chunks published.",
+                    report.published,
+                ),
+                (
+                    "slimchain_pubsub_decode_failures_total",
+                    "The messages failed to decode.",
+                    report.decode_failures,
+                ),
+                (
+                    "slimchain_pubsub_dropped_events_total",
+                    "The events dropped as the pending queue is full.",
+                    report.dropped_events,
+                ),
+            ];
+            for &(name, help, value) in counters.iter() {
+                w.counter(name, help, &labels, value as f64);
+            }
+            for (kind, &count) in &report.publish_failures {
+                w.counter(
+                    "slimchain_pubsub_publish_failures_total",
+                    "The publish failures by the kind, including the retried ones.",
+                    &[labels[0], ("kind", kind.as_str())],
+                    count as f64,
+                );
+            }
+        }
+        for (topic, &count) in &self.peer_counts {
+            w.gauge(
+                "slimchain_pubsub_peers",
+                "The connected peers subscribing to the topic.",
+                &[("topic", topic.as_str())],
+                count as f64,
+            );
+        }
+        w.counter(
+            "slimchain_pubsub_reported_duplicates_total",
+            "The messages reported as duplicates by the application.",
+            &[],
+            self.reported_duplicates as f64,
+        );
+        w.counter(
+            "slimchain_pubsub_unauthorized_messages_total",
+            "The messages from the unauthorized peers.",
+            &[],
+            self.unauthorized_messages as f64,
+        );
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct SharedPubSubMetricsReport(Arc<Mutex<PubSubMetricsReport>>);
+
+impl SharedPubSubMetricsReport {
+    fn get(&self) -> PubSubMetricsReport {
+        self.0.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    fn set(&self, report: PubSubMetricsReport) {
+        *self.0.lock().unwrap_or_else(|e| e.into_inner()) = report;
+    }
+}
+
+#[derive(Debug, Default)]
+struct TopicMetrics {
+    received: u64,
+    received_bytes: u64,
+    duplicates: u64,
+    published: u64,
+    publish_failures: BTreeMap<&'static str, u64>,
+    decode_failures: u64,
+    dropped_events: u64,
+    /// The dropped events since the last log.
+    unlogged_dropped_events: u64,
+    last_dropped_events_log: Option<Instant>,
+}
+
+impl TopicMetrics {
+    fn report(&self) -> TopicMetricsReport {
+        TopicMetricsReport {
+            received: self.received,
+            received_bytes: self.received_bytes,
+            duplicates: self.duplicates,
+            published: self.published,
+            publish_failures: self
+                .publish_failures
+                .iter()
+                .map(|(&kind, &count)| (kind.to_string(), count))
+                .collect(),
+            decode_failures: self.decode_failures,
+            dropped_events: self.dropped_events,
+        }
+    }
+
+    fn record_publish_failure(&mut self, kind: &'static str) {
+        *self.publish_failures.entry(kind).or_default() += 1;
+    }
+}
+
+fn publish_error_kind(e: &PublishError) -> &'static str {
+    match e {
+        PublishError::Duplicate => "duplicate",
+        PublishError::SigningError(_) => "signing_error",
+        PublishError::InsufficientPeers => "insufficient_peers",
+        PublishError::MessageTooLarge => "message_too_large",
+        PublishError::TransformFailed(_) => "transform_failed",
+    }
+}
+
+/// The outcome of a message, reported against the peer propagating it.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum MessageOutcome {
+    Valid,
+    InvalidDecode,
+    InvalidContent,
+    Duplicate,
+}
+
+impl MessageOutcome {
+    fn score(self) -> f64 {
+        match self {
+            MessageOutcome::Valid => 1.0,
+            MessageOutcome::InvalidDecode => -10.0,
+            MessageOutcome::InvalidContent => -10.0,
+            MessageOutcome::Duplicate => -1.0,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct PeerScore {
+    score: f64,
+    updated: Instant,
+    banned_until: Option<Instant>,
+}
+
+impl PeerScore {
+    fn score_at(&self, half_life: Duration, now: Instant) -> f64 {
+        if half_life == Duration::from_millis(0) {
+            return self.score;
+        }
+        let elapsed = now.saturating_duration_since(self.updated);
+        self.score * 0.5f64.powf(elapsed.as_secs_f64() / half_life.as_secs_f64())
+    }
+}
+
+/// A part of the message too large to be published at once.
+#[derive(Debug, Serialize, Deserialize)]
+struct MessageChunk {
+    id: u64,
+    index: u32,
+    total: u32,
+    /// The digest of the whole message.
+    digest: H256,
+    data: Vec<u8>,
+}
+
+#[derive(Debug)]
+struct PartialMessage {
+    topic: PubSubTopic,
+    digest: H256,
+    chunks: Vec<Option<Vec<u8>>>,
+    received: usize,
+    created: Instant,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum PeerEventKind {
+    Connected,
+    Disconnected,
+    Subscribed(PubSubTopic),
+    Unsubscribed(PubSubTopic),
+}
+
+/// A change of the connection or the subscriptions of a peer, sent to the receivers from
+/// [`PubSub::subscribe_peer_events`].
+#[derive(Debug, Clone)]
+pub struct PeerEvent {
+    pub peer_id: PeerId,
+    pub kind: PeerEventKind,
+    /// None if the peer is not connected, e.g., for a late unsubscription.
+    pub direction: Option<ConnectionDirection>,
+    /// The number of the connected peers subscribing to each topic after the change.
+    pub topic_peer_counts: HashMap<PubSubTopic, usize>,
+}
+
+/// Where a received message comes from, to attribute it to the peers.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct MessageInfo {
+    /// The peer forwarding the message to this node.
+    pub propagation_source: PeerId,
+    /// The peer signing the message. None if the message is not signed.
+    pub original_author: Option<PeerId>,
+    /// For a chunked message, the id of the chunk completing it.
+    pub message_id: MessageId,
+}
+
+impl MessageInfo {
+    /// The peer to blame for the message, i.e., the author if known.
+    pub fn origin(&self) -> PeerId {
+        self.original_author.unwrap_or(self.propagation_source)
+    }
+}
+
 #[derive(Debug)]
 pub enum PubSubEvent<TxProposal, BlockProposal> {
-    TxProposal(TxProposal),
-    BlockProposal(BlockProposal),
+    TxProposal(TxProposal, MessageInfo),
+    BlockProposal(BlockProposal, MessageInfo),
+    StateSync(StateSyncAnnouncement, MessageInfo),
+}
+
+impl<TxProposal, BlockProposal> PubSubEvent<TxProposal, BlockProposal> {
+    pub fn topic(&self) -> PubSubTopic {
+        match self {
+            PubSubEvent::TxProposal(_, _) => PubSubTopic::TxProposal,
+            PubSubEvent::BlockProposal(_, _) => PubSubTopic::BlockProposal,
+            PubSubEvent::StateSync(_, _) => PubSubTopic::StateSync,
+        }
+    }
+
+    pub fn info(&self) -> &MessageInfo {
+        match self {
+            PubSubEvent::TxProposal(_, info)
+            | PubSubEvent::BlockProposal(_, info)
+            | PubSubEvent::StateSync(_, info) => info,
+        }
+    }
+}
+
+/// The events not yet polled, queued per topic. The events of a topic are yielded only after
+/// those of the topics with higher priorities, while the order within a topic is preserved.
+#[derive(Debug)]
+struct PendingEvents<TxProposal, BlockProposal> {
+    queues: [VecDeque<(PeerId, PubSubEvent<TxProposal, BlockProposal>)>; 3],
+    configs: [PendingQueueConfig; 3],
+}
+
+impl<TxProposal, BlockProposal> PendingEvents<TxProposal, BlockProposal> {
+    fn new(cfg: &PubSubConfig) -> Self {
+        let mut pending_events = Self {
+            queues: [VecDeque::new(), VecDeque::new(), VecDeque::new()],
+            configs: [PendingQueueConfig::default(); 3],
+        };
+        pending_events.set_configs(cfg);
+        pending_events
+    }
+
+    /// Replace the configs of the queues. The events already queued are kept, even if a
+    /// queue exceeds its new capacity.
+    fn set_configs(&mut self, cfg: &PubSubConfig) {
+        for &(topic, queue_cfg) in &[
+            (PubSubTopic::TxProposal, cfg.tx_proposal_queue),
+            (PubSubTopic::BlockProposal, cfg.block_proposal_queue),
+            (PubSubTopic::StateSync, cfg.state_sync_queue),
+        ] {
+            self.configs[topic.priority()] = queue_cfg;
+        }
+    }
+
+    /// Queue `event`, following the overflow policy of its topic if the queue is full.
+    /// Return whether an event is dropped.
+    fn push_back(&mut self, source: PeerId, event: PubSubEvent<TxProposal, BlockProposal>) -> bool {
+        let priority = event.topic().priority();
+        let cfg = self.configs[priority];
+        let queue = &mut self.queues[priority];
+        if cfg.capacity > 0 && queue.len() >= cfg.capacity {
+            match cfg.overflow {
+                OverflowPolicy::DropOldest => {
+                    queue.pop_front();
+                }
+                OverflowPolicy::DropNewest | OverflowPolicy::Block => return true,
+            }
+            queue.push_back((source, event));
+            return true;
+        }
+        queue.push_back((source, event));
+        false
+    }
+
+    fn pop_front(&mut self) -> Option<(PeerId, PubSubEvent<TxProposal, BlockProposal>)> {
+        self.queues.iter_mut().find_map(|queue| queue.pop_front())
+    }
+
+    fn clear_topic(&mut self, topic: PubSubTopic) {
+        self.queues[topic.priority()].clear();
+    }
 }
 
 #[derive(NetworkBehaviour)]
@@ -77,14 +554,59 @@ where
     BlockProposal: Send + 'static,
 {
     gossipsub: Gossipsub,
+    connections: ConnectionTracker,
     #[behaviour(ignore)]
     peer_id: PeerId,
     #[behaviour(ignore)]
-    pending_events: VecDeque<PubSubEvent<TxProposal, BlockProposal>>,
+    pending_events: PendingEvents<TxProposal, BlockProposal>,
     #[behaviour(ignore)]
     sub_topics: HashSet<PubSubTopic>,
     #[behaviour(ignore)]
+    relay_topics: HashSet<PubSubTopic>,
+    #[behaviour(ignore)]
     retry_messages: DelayQueue<(PubSubTopic, Vec<u8>, usize, Duration)>,
+    #[behaviour(ignore)]
+    compression: PubSubCompressionConfig,
+    #[behaviour(ignore)]
+    max_message_size: usize,
+    #[behaviour(ignore)]
+    /// Keyed by the source and the id of the chunked messages, as the ids are chosen by the
+    /// sources.
+    partial_messages: HashMap<(PeerId, u64), PartialMessage>,
+    #[behaviour(ignore)]
+    metrics: HashMap<PubSubTopic, TopicMetrics>,
+    #[behaviour(ignore)]
+    reported_duplicates: u64,
+    #[behaviour(ignore)]
+    metrics_interval: Duration,
+    #[behaviour(ignore)]
+    last_metrics_report: Instant,
+    #[behaviour(ignore)]
+    authorized_peers: Option<HashSet<PeerId>>,
+    #[behaviour(ignore)]
+    unauthorized_messages: u64,
+    #[behaviour(ignore)]
+    peer_score_cfg: PeerScoreConfig,
+    #[behaviour(ignore)]
+    peer_scores: HashMap<PeerId, PeerScore>,
+    #[behaviour(ignore)]
+    housekeeping: Interval,
+    #[behaviour(ignore)]
+    shared_peers_report: SharedPubSubPeersReport,
+    #[behaviour(ignore)]
+    shared_metrics_report: SharedPubSubMetricsReport,
+    /// The features to advertise. None for not taking part in the capability exchange.
+    #[behaviour(ignore)]
+    local_features: Option<PubSubFeatures>,
+    #[behaviour(ignore)]
+    peer_features: HashMap<PeerId, PubSubFeatures>,
+    #[behaviour(ignore)]
+    pending_announcement: bool,
+    #[behaviour(ignore)]
+    peer_event_txs: Vec<mpsc::UnboundedSender<PeerEvent>>,
+    /// The reloaded configs, whose queue configs are applied on the housekeeping.
+    #[behaviour(ignore)]
+    reloaded_cfg_rx: mpsc::UnboundedReceiver<PubSubConfig>,
 }
 
 impl<TxProposal, BlockProposal> PubSub<TxProposal, BlockProposal>
@@ -92,25 +614,17 @@ where
     TxProposal: Send + 'static,
     BlockProposal: Send + 'static,
 {
+    /// Create a pubsub consuming the messages on `sub_topics`. The node subscribes to
+    /// `sub_topics` and `relay_topics` only, where the latter are relayed without being
+    /// consumed. Other topics can still be published to via the fanout peers.
     pub fn new(
         keypair: Keypair,
         sub_topics: &[PubSubTopic],
         relay_topics: &[PubSubTopic],
     ) -> Result<Self> {
         let peer_id = PeerId::from(keypair.public());
-        let cfg = GossipsubConfigBuilder::default()
-            .protocol_id_prefix("/slimchain/pubsub/1")
-            .flood_publish(false)
-            .duplicate_cache_time(DUPLICATE_CACHE_TTL)
-            .message_id_fn(|msg: &GossipsubMessage| {
-                let hash = msg.data.to_digest();
-                MessageId::new(hash.as_bytes())
-            })
-            .heartbeat_interval(HEARTBEAT_INTERVAL)
-            .check_explicit_peers_ticks(CHECK_EXPLICIT_PEERS_TICKS)
-            .max_transmit_size(MAX_TRANSMIT_SIZE)
-            .build()
-            .map_err(|e| anyhow!("Failed to create gossipsub config. Error: {}", e))?;
+        let pubsub_cfg = PubSubConfig::get();
+        let cfg = gossipsub_config(&pubsub_cfg)?;
 
         let mut gossipsub = Gossipsub::new(MessageAuthenticity::Signed(keypair), cfg)
             .map_err(|e| anyhow!("Failed to create gossipsub. Error: {}", e))?;
@@ -120,16 +634,115 @@ where
                 .subscribe(&topic.into_topic())
                 .map_err(|e| anyhow!("Failed to subscribe. Error: {:?}", e))?;
         }
+        gossipsub
+            .subscribe(&IdentTopic::new(CAPABILITIES_TOPIC))
+            .map_err(|e| anyhow!("Failed to subscribe. Error: {:?}", e))?;
+
+        let (reloaded_cfg_tx, reloaded_cfg_rx) = mpsc::unbounded();
+        on_section_reload("pubsub", move |cfg: PubSubConfig| match cfg.validate() {
+            Ok(()) => {
+                reloaded_cfg_tx.unbounded_send(cfg).ok();
+            }
+            Err(e) => warn!("Reject the reloaded pubsub config. {}", e),
+        });
 
         Ok(Self {
             gossipsub,
+            connections: ConnectionTracker::new(),
             peer_id,
-            pending_events: VecDeque::new(),
+            pending_events: PendingEvents::new(&pubsub_cfg),
             sub_topics: sub_topics.iter().copied().collect(),
+            relay_topics: relay_topics.iter().copied().collect(),
             retry_messages: DelayQueue::new(),
+            compression: PubSubCompressionConfig::default(),
+            max_message_size: pubsub_cfg.max_message_size,
+            partial_messages: HashMap::new(),
+            metrics: HashMap::new(),
+            reported_duplicates: 0,
+            metrics_interval: Duration::from_millis(0),
+            last_metrics_report: Instant::now(),
+            authorized_peers: None,
+            unauthorized_messages: 0,
+            peer_score_cfg: PeerScoreConfig::default(),
+            peer_scores: HashMap::new(),
+            housekeeping: tokio::time::interval(HOUSEKEEPING_INTERVAL),
+            shared_peers_report: SharedPubSubPeersReport::default(),
+            shared_metrics_report: SharedPubSubMetricsReport::default(),
+            local_features: Some(PubSubFeatures::all()),
+            peer_features: HashMap::new(),
+            pending_announcement: false,
+            peer_event_txs: Vec::new(),
+            reloaded_cfg_rx,
         })
     }
 
+    pub fn set_compression(&mut self, cfg: PubSubCompressionConfig) {
+        self.compression = cfg;
+    }
+
+    /// Encode `input` in the legacy format, unless the compression is enabled and supported by
+    /// all the peers subscribing to `topic`.
+    fn encode_message<T: Serialize>(&self, topic: PubSubTopic, input: &T) -> Result<Vec<u8>> {
+        let cfg = &self.compression;
+        if !cfg.enabled || !self.topic_peers_support(topic, PubSubFeatures::COMPRESSION) {
+            return binary_encode(input);
+        }
+
+        let plain = postcard::to_allocvec(input).map_err(Error::msg)?;
+        if plain.len() >= cfg.threshold {
+            let mut data = vec![POSTCARD_ZSTD_FORMAT_TAG];
+            zstd::stream::copy_encode(&plain[..], &mut data, cfg.level)?;
+            if data.len() <= plain.len() {
+                return Ok(data);
+            }
+        }
+
+        let mut data = Vec::with_capacity(plain.len() + 1);
+        data.push(POSTCARD_FORMAT_TAG);
+        data.extend_from_slice(&plain);
+        Ok(data)
+    }
+
+    /// Publish `input`, which is split into chunks if too large.
+    fn publish_input<T: Serialize>(&mut self, topic: PubSubTopic, input: &T) -> Result<()> {
+        let data = match self.encode_message(topic, input) {
+            Ok(data) => data,
+            Err(e) => {
+                self.topic_metrics(topic)
+                    .record_publish_failure("encode_error");
+                return Err(e);
+            }
+        };
+        if data.len() < self.max_message_size {
+            self.publish_message(topic, data, PUB_MAX_RETRIES, PUB_INIT_RETRY_DELAY);
+            return Ok(());
+        }
+
+        let chunk_size = self.max_message_size - CHUNK_HEADER_SIZE;
+        let total = (data.len() + chunk_size - 1) / chunk_size;
+        if total > MAX_CHUNKS_PER_MESSAGE {
+            self.topic_metrics(topic)
+                .record_publish_failure("message_too_large");
+            bail!("PubSub: data is too large. Size={}.", data.len());
+        }
+
+        let id = rng().gen();
+        let digest = data.to_digest();
+        for (index, part) in data.chunks(chunk_size).enumerate() {
+            let chunk = MessageChunk {
+                id,
+                index: index as u32,
+                total: total as u32,
+                digest,
+                data: part.to_vec(),
+            };
+            let mut chunk_data = vec![CHUNK_FORMAT_TAG];
+            chunk_data.extend(postcard::to_allocvec(&chunk).map_err(Error::msg)?);
+            self.publish_message(topic, chunk_data, PUB_MAX_RETRIES, PUB_INIT_RETRY_DELAY);
+        }
+        Ok(())
+    }
+
     fn publish_message(
         &mut self,
         topic: PubSubTopic,
@@ -138,10 +751,16 @@ where
         retry_delay: Duration,
     ) {
         match self.gossipsub.publish(topic.into_topic(), data.clone()) {
-            Ok(_) => return,
-            Err(PublishError::InsufficientPeers) => {}
+            Ok(_) => {
+                self.topic_metrics(topic).published += 1;
+                return;
+            }
             Err(e) => {
-                panic!("PubSub: Failed to publish message. Error: {:?}", e);
+                self.topic_metrics(topic)
+                    .record_publish_failure(publish_error_kind(&e));
+                if !matches!(e, PublishError::InsufficientPeers) {
+                    panic!("PubSub: Failed to publish message. Error: {:?}", e);
+                }
             }
         }
 
@@ -169,10 +788,32 @@ where
         cx: &mut Context,
         _: &mut impl PollParameters,
     ) -> Poll<NetworkBehaviourAction<T, PubSubEvent<TxProposal, BlockProposal>>> {
-        if let Some(event) = self.pending_events.pop_front() {
+        if let Some((_, event)) = self.pending_events.pop_front() {
             return Poll::Ready(NetworkBehaviourAction::GenerateEvent(event));
         }
 
+        // Poll until pending, so that it wakes up the task on the next tick.
+        while self.housekeeping.poll_tick(cx).is_ready() {
+            let now = Instant::now();
+            while let Ok(Some(cfg)) = self.reloaded_cfg_rx.try_next() {
+                info!("PubSub: update the queue configs.");
+                self.pending_events.set_configs(&cfg);
+            }
+            if self.pending_announcement {
+                self.announce_capabilities();
+            }
+            self.update_peer_scores(now);
+            self.purge_partial_messages(now);
+            self.shared_peers_report.set(self.peers_report());
+            self.shared_metrics_report.set(self.metrics_snapshot());
+            if self.metrics_interval > Duration::from_millis(0)
+                && now.duration_since(self.last_metrics_report) >= self.metrics_interval
+            {
+                self.last_metrics_report = now;
+                record_event!("pubsub_metrics", "report": self.metrics_snapshot());
+            }
+        }
+
         while let Poll::Ready(Some(Ok(message))) = self.retry_messages.poll_expired(cx) {
             let (topic, data, retries, delay) = message.into_inner();
             trace!(retries, ?delay, "PubSub: retry to publish the message.");
@@ -194,16 +835,492 @@ where
         }
     }
 
+    /// Start consuming the messages on `topic`.
+    pub fn subscribe(&mut self, topic: PubSubTopic) -> Result<()> {
+        self.gossipsub
+            .subscribe(&topic.into_topic())
+            .map_err(|e| anyhow!("Failed to subscribe. Error: {:?}", e))?;
+        self.sub_topics.insert(topic);
+        Ok(())
+    }
+
+    /// Stop consuming the messages on `topic`, and drop those not yet polled.
+    /// The gossipsub subscription is kept if `topic` is also relayed.
+    pub fn unsubscribe(&mut self, topic: PubSubTopic) -> Result<()> {
+        if !self.relay_topics.contains(&topic) {
+            self.gossipsub
+                .unsubscribe(&topic.into_topic())
+                .map_err(|e| anyhow!("Failed to unsubscribe. Error: {:?}", e))?;
+        }
+        self.sub_topics.remove(&topic);
+        self.pending_events.clear_topic(topic);
+        Ok(())
+    }
+
+    pub fn subscribed_topics(&self) -> &HashSet<PubSubTopic> {
+        &self.sub_topics
+    }
+
+    /// The features advertised by `peer`. None if it has not advertised any, e.g., as it runs
+    /// an older version, in which case it is assumed to support none of them.
+    pub fn peer_features(&self, peer: &PeerId) -> Option<PubSubFeatures> {
+        self.peer_features.get(peer).copied()
+    }
+
+    /// Whether all the known peers subscribing to `topic` advertise `features`.
+    fn topic_peers_support(&self, topic: PubSubTopic, features: PubSubFeatures) -> bool {
+        let topic_hash = topic.into_topic_hash();
+        self.gossipsub
+            .all_peers()
+            .filter(|(_, topics)| topics.contains(&&topic_hash))
+            .all(|(peer, _)| {
+                self.peer_features(peer)
+                    .map_or(false, |peer_features| peer_features.contains(features))
+            })
+    }
+
+    /// Publish the local features. Retried on the next housekeeping tick if it fails.
+    fn announce_capabilities(&mut self) {
+        let features = match self.local_features {
+            Some(features) => features,
+            None => return,
+        };
+        let announcement = CapabilityAnnouncement {
+            version: PUBSUB_PROTOCOL_VERSION,
+            features,
+            nonce: rng().gen(),
+        };
+        let data = match postcard::to_allocvec(&announcement) {
+            Ok(data) => data,
+            Err(e) => {
+                error!("PubSub: Failed to encode the capabilities. Error: {}", e);
+                return;
+            }
+        };
+        match self
+            .gossipsub
+            .publish(IdentTopic::new(CAPABILITIES_TOPIC), data)
+        {
+            Ok(_) => self.pending_announcement = false,
+            Err(e) => {
+                trace!(?e, "PubSub: Failed to announce the capabilities.");
+                self.pending_announcement = true;
+            }
+        }
+    }
+
+    fn handle_capability_announcement(&mut self, author: PeerId, data: &[u8]) {
+        match postcard::from_bytes::<CapabilityAnnouncement>(data) {
+            Ok(announcement) => {
+                trace!(
+                    %author,
+                    version = announcement.version,
+                    features = announcement.features.bits(),
+                    "PubSub: Received the capabilities."
+                );
+                self.peer_features.insert(author, announcement.features);
+            }
+            Err(e) => {
+                warn!(%author, "PubSub: Failed to decode the capabilities. Error: {}", e);
+            }
+        }
+    }
+
+    /// Only accept the messages authored and propagated by `peers`.
+    pub fn set_authorized_peers(&mut self, peers: impl IntoIterator<Item = PeerId>) {
+        let mut peers: HashSet<_> = peers.into_iter().collect();
+        peers.insert(self.peer_id);
+        self.authorized_peers = Some(peers);
+    }
+
+    /// Only accept the messages from the known peers in `cfg`, if enabled.
+    pub fn authorize_peers_from_net_config(&mut self, cfg: &NetworkConfig) {
+        if cfg.pubsub_allow_list {
+            self.set_authorized_peers(cfg.peers.iter().map(|peer| peer.peer_id));
+        }
+    }
+
+    /// Whether the message is both authored and propagated by the authorized peers.
+    fn is_authorized(&self, propagation_source: &PeerId, author: Option<&PeerId>) -> bool {
+        match &self.authorized_peers {
+            Some(peers) => {
+                peers.contains(propagation_source)
+                    && author.map_or(false, |author| peers.contains(author))
+            }
+            None => true,
+        }
+    }
+
+    /// The number of the messages dropped as they are not from the authorized peers.
+    pub fn unauthorized_messages(&self) -> u64 {
+        self.unauthorized_messages
+    }
+
+    /// The number of the undecodable messages received on `topic`.
+    pub fn malformed_messages(&self, topic: PubSubTopic) -> u64 {
+        self.metrics
+            .get(&topic)
+            .map_or(0, |metrics| metrics.decode_failures)
+    }
+
+    fn topic_metrics(&mut self, topic: PubSubTopic) -> &mut TopicMetrics {
+        self.metrics.entry(topic).or_default()
+    }
+
+    fn record_dropped_event(&mut self, topic: PubSubTopic) {
+        let now = Instant::now();
+        let metrics = self.topic_metrics(topic);
+        metrics.dropped_events += 1;
+        metrics.unlogged_dropped_events += 1;
+        if metrics.last_dropped_events_log.map_or(true, |last| {
+            now.duration_since(last) >= DROPPED_EVENTS_LOG_INTERVAL
+        }) {
+            warn!(
+                ?topic,
+                dropped = metrics.unlogged_dropped_events,
+                total_dropped = metrics.dropped_events,
+                "PubSub: Drop the events as the pending queue is full."
+            );
+            metrics.unlogged_dropped_events = 0;
+            metrics.last_dropped_events_log = Some(now);
+        }
+    }
+
+    /// Record [`PubSub::metrics_snapshot`] to the metrics file every `interval`.
+    /// 0 disables it.
+    pub fn set_metrics_interval(&mut self, interval: Duration) {
+        self.metrics_interval = interval;
+    }
+
+    pub fn metrics_snapshot(&self) -> PubSubMetricsReport {
+        PubSubMetricsReport {
+            topics: self
+                .metrics
+                .iter()
+                .map(|(topic, metrics)| (topic.name().to_string(), metrics.report()))
+                .collect(),
+            reported_duplicates: self.reported_duplicates,
+            unauthorized_messages: self.unauthorized_messages,
+            peer_counts: self
+                .topic_peer_counts()
+                .into_iter()
+                .map(|(topic, count)| (topic.name().to_string(), count))
+                .collect(),
+        }
+    }
+
+    /// Serve the [`PubSubMetricsReport`] refreshed every second in the Prometheus registry.
+    pub fn register_prometheus_collector(&self) {
+        let report = self.shared_metrics_report.clone();
+        REGISTRY.register_collector("pubsub", move |w| report.get().write_prometheus(w));
+    }
+
+    /// Receive the [`PeerEvent`]s from now on, e.g., to react to losing the last peer of a
+    /// topic outside of the swarm.
+    pub fn subscribe_peer_events(&mut self) -> mpsc::UnboundedReceiver<PeerEvent> {
+        let (tx, rx) = mpsc::unbounded();
+        self.peer_event_txs.push(tx);
+        rx
+    }
+
+    /// The number of the connected peers subscribing to each topic, including the topics
+    /// without any peer.
+    pub fn topic_peer_counts(&self) -> HashMap<PubSubTopic, usize> {
+        let mut counts: HashMap<PubSubTopic, usize> =
+            TOPIC_MAP.values().map(|&topic| (topic, 0)).collect();
+        for (topic, peers) in self.known_peers() {
+            counts.insert(topic, peers.len());
+        }
+        counts
+    }
+
+    fn notify_peer_event(
+        &mut self,
+        peer_id: PeerId,
+        kind: PeerEventKind,
+        direction: Option<ConnectionDirection>,
+    ) {
+        let event = PeerEvent {
+            peer_id,
+            kind,
+            direction,
+            topic_peer_counts: self.topic_peer_counts(),
+        };
+        debug!(?event, "PubSub: Peer event.");
+        record_event!(
+            "pubsub_peer_event",
+            "peer": peer_id.to_string(),
+            "kind": format!("{:?}", kind),
+            "peer_counts": self.metrics_snapshot().peer_counts
+        );
+        self.peer_event_txs
+            .retain(|tx| tx.unbounded_send(event.clone()).is_ok());
+    }
+
+    fn decode_message<T: for<'de> Deserialize<'de>>(
+        &mut self,
+        topic: PubSubTopic,
+        source: PeerId,
+        message_id: &MessageId,
+        data: &[u8],
+    ) -> Option<T> {
+        match decode_payload(data) {
+            Ok(input) => Some(input),
+            Err(e) => {
+                self.record_malformed_message(topic, source, message_id, e);
+                None
+            }
+        }
+    }
+
+    fn record_malformed_message(
+        &mut self,
+        topic: PubSubTopic,
+        source: PeerId,
+        message_id: &MessageId,
+        e: impl std::fmt::Display,
+    ) {
+        warn!(
+            %source,
+            %message_id,
+            ?topic,
+            "PubSub: Failed to decode message. Error: {}",
+            e
+        );
+        self.topic_metrics(topic).decode_failures += 1;
+        record_event!("pubsub_malformed_message", "topic": format!("{:?}", topic), "peer": source.to_string());
+        self.report_message_outcome(source, MessageOutcome::InvalidDecode);
+    }
+
+    pub fn set_peer_score_config(&mut self, cfg: PeerScoreConfig) {
+        self.peer_score_cfg = cfg;
+    }
+
+    /// Update the score of `peer` with `outcome`. The peer is banned for a while once its
+    /// score drops to the configured threshold, during which its messages are dropped.
+    pub fn report_message_outcome(&mut self, peer: PeerId, outcome: MessageOutcome) {
+        if outcome == MessageOutcome::Duplicate {
+            self.reported_duplicates += 1;
+        }
+        self.record_message_outcome(peer, outcome, Instant::now());
+    }
+
+    fn record_message_outcome(&mut self, peer: PeerId, outcome: MessageOutcome, now: Instant) {
+        if peer == self.peer_id {
+            return;
+        }
+
+        let cfg = self.peer_score_cfg;
+        let entry = self.peer_scores.entry(peer).or_insert_with(|| PeerScore {
+            score: 0.0,
+            updated: now,
+            banned_until: None,
+        });
+        entry.score =
+            (entry.score_at(cfg.decay_half_life, now) + outcome.score()).min(MAX_PEER_SCORE);
+        entry.updated = now;
+
+        if entry.banned_until.is_none() && entry.score <= cfg.ban_score {
+            warn!(%peer, score = entry.score, "PubSub: Ban the misbehaving peer.");
+            record_event!("pubsub_ban_peer", "peer": peer.to_string());
+            entry.banned_until = Some(now + cfg.ban_duration);
+            self.gossipsub.blacklist_peer(&peer);
+        }
+    }
+
+    /// Decay the peer scores and lift the expired bans.
+    fn update_peer_scores(&mut self, now: Instant) {
+        let half_life = self.peer_score_cfg.decay_half_life;
+        let gossipsub = &mut self.gossipsub;
+        self.peer_scores.retain(|peer, entry| {
+            match entry.banned_until {
+                Some(until) if until > now => return true,
+                Some(_) => {
+                    info!(%peer, "PubSub: Lift the ban of the peer.");
+                    gossipsub.remove_blacklisted_peer(peer);
+                    return false;
+                }
+                None => {}
+            }
+            entry.score = entry.score_at(half_life, now);
+            entry.updated = now;
+            entry.score.abs() >= MIN_TRACKED_PEER_SCORE
+        });
+    }
+
+    pub fn is_banned(&self, peer: &PeerId) -> bool {
+        self.peer_scores
+            .get(peer)
+            .and_then(|entry| entry.banned_until)
+            .map_or(false, |until| until > Instant::now())
+    }
+
+    /// The current scores of the peers with any reported outcome.
+    pub fn peer_scores(&self) -> HashMap<PeerId, f64> {
+        let half_life = self.peer_score_cfg.decay_half_life;
+        let now = Instant::now();
+        self.peer_scores
+            .iter()
+            .map(|(&peer, entry)| (peer, entry.score_at(half_life, now)))
+            .collect()
+    }
+
+    fn purge_partial_messages(&mut self, now: Instant) {
+        self.partial_messages.retain(|(source, id), partial| {
+            let expired = now.duration_since(partial.created) >= REASSEMBLY_TIMEOUT;
+            if expired {
+                warn!(
+                    %source,
+                    id,
+                    topic = ?partial.topic,
+                    received = partial.received,
+                    total = partial.chunks.len(),
+                    "PubSub: Drop the incomplete chunked message."
+                );
+            }
+            !expired
+        });
+    }
+
+    /// Buffer the chunk in `data` of the message from `origin`, and return the whole message once
+    /// all of its chunks are received. `source` is the peer forwarding the chunk.
+    fn reassemble_message(
+        &mut self,
+        topic: PubSubTopic,
+        source: PeerId,
+        origin: PeerId,
+        message_id: &MessageId,
+        data: &[u8],
+    ) -> Option<Vec<u8>> {
+        let chunk: MessageChunk = match postcard::from_bytes(&data[1..]) {
+            Ok(chunk) => chunk,
+            Err(e) => {
+                self.record_malformed_message(topic, source, message_id, e);
+                return None;
+            }
+        };
+        let total = chunk.total as usize;
+        if total == 0 || total > MAX_CHUNKS_PER_MESSAGE || chunk.index >= chunk.total {
+            self.record_malformed_message(topic, source, message_id, "Invalid chunk index.");
+            return None;
+        }
+
+        let now = Instant::now();
+        self.purge_partial_messages(now);
+        let key = (origin, chunk.id);
+        if !self.partial_messages.contains_key(&key) {
+            let from_origin = self
+                .partial_messages
+                .iter()
+                .filter(|((source, _), _)| *source == origin);
+            if from_origin.clone().count() >= MAX_PARTIAL_MESSAGES {
+                let oldest = from_origin
+                    .min_by_key(|(_, partial)| partial.created)
+                    .map(|(&oldest, _)| oldest);
+                if let Some(oldest) = oldest {
+                    warn!(
+                        source = %origin,
+                        id = oldest.1,
+                        "PubSub: Drop the oldest incomplete chunked message."
+                    );
+                    self.partial_messages.remove(&oldest);
+                }
+            }
+        }
+
+        let partial = self
+            .partial_messages
+            .entry(key)
+            .or_insert_with(|| PartialMessage {
+                topic,
+                digest: chunk.digest,
+                chunks: vec![None; total],
+                received: 0,
+                created: now,
+            });
+        if partial.topic != topic || partial.digest != chunk.digest || partial.chunks.len() != total
+        {
+            // Only this chunk is dropped, and the chunks buffered are kept.
+            self.record_malformed_message(topic, source, message_id, "Inconsistent chunk.");
+            return None;
+        }
+
+        let slot = &mut partial.chunks[chunk.index as usize];
+        if slot.is_some() {
+            // The duplicated chunk is ignored.
+            self.topic_metrics(topic).duplicates += 1;
+            return None;
+        }
+        *slot = Some(chunk.data);
+        partial.received += 1;
+        if partial.received < total {
+            return None;
+        }
+
+        let partial = self.partial_messages.remove(&key)?;
+        let mut data = Vec::new();
+        for part in partial.chunks.iter().flatten() {
+            data.extend_from_slice(part);
+        }
+        if data.to_digest() != partial.digest {
+            self.record_malformed_message(topic, source, message_id, "Mismatched digest.");
+            return None;
+        }
+        Some(data)
+    }
+
+    /// The connected peers subscribing to each topic.
+    pub fn known_peers(&self) -> HashMap<PubSubTopic, Vec<PeerId>> {
+        let mut peers: HashMap<PubSubTopic, Vec<PeerId>> = HashMap::new();
+        for (&peer_id, topic_hashes) in self.gossipsub.all_peers() {
+            for hash in topic_hashes {
+                if let Some(&topic) = TOPIC_MAP.get(hash) {
+                    peers.entry(topic).or_default().push(peer_id);
+                }
+            }
+        }
+        peers
+    }
+
+    /// The number of the mesh peers of each topic subscribed to. The fanout peers are not
+    /// exposed by gossipsub.
+    pub fn mesh_peer_counts(&self) -> HashMap<PubSubTopic, usize> {
+        TOPIC_MAP
+            .iter()
+            .map(|(hash, &topic)| (topic, self.gossipsub.mesh_peers(hash).count()))
+            .filter(|(_, count)| *count > 0)
+            .collect()
+    }
+
+    pub fn peers_report(&self) -> PubSubPeersReport {
+        let mesh_peer_counts = self.mesh_peer_counts();
+        let mut report = PubSubPeersReport::new();
+        for (topic, peers) in self.known_peers() {
+            let mut peers: Vec<_> = peers.iter().map(|peer_id| peer_id.to_base58()).collect();
+            peers.sort();
+            report.insert(
+                topic.name().to_string(),
+                TopicPeersReport {
+                    peers,
+                    mesh_peers: mesh_peer_counts.get(&topic).copied().unwrap_or(0),
+                },
+            );
+        }
+        report
+    }
+
+    /// The [`PubSubPeersReport`] refreshed every second, for the health endpoint.
+    pub fn shared_peers_report(&self) -> SharedPubSubPeersReport {
+        self.shared_peers_report.clone()
+    }
+
     pub fn report_known_peers(&self) {
-        println!("[PubSub] Known peers:");
-        for (peer_id, topic_hashes) in self.gossipsub.all_peers() {
-            println!(
-                " {:?} => {:#?}",
-                peer_id,
-                topic_hashes
-                    .iter()
-                    .map(|hash| TOPIC_MAP.get(&hash))
-                    .collect::<Vec<_>>()
+        for (topic, report) in self.peers_report() {
+            info!(
+                %topic,
+                peers = ?report.peers,
+                mesh_peers = report.mesh_peers,
+                "PubSub: Known peers."
             );
         }
     }
@@ -215,35 +1332,67 @@ where
     BlockProposal: Serialize + Send + 'static,
 {
     pub fn publish_tx_proposal(&mut self, input: &TxProposal) -> Result<()> {
-        let data = binary_encode(input)?;
-        ensure!(
-            data.len() < MAX_MESSAGE_SIZE,
-            "PubSub: data is too large. Size={}.",
-            data.len()
-        );
-        self.publish_message(
-            PubSubTopic::TxProposal,
-            data,
-            PUB_MAX_RETRIES,
-            PUB_INIT_RETRY_DELAY,
-        );
-        Ok(())
+        self.publish_input(PubSubTopic::TxProposal, input)
     }
 
     pub fn publish_block_proposal(&mut self, input: &BlockProposal) -> Result<()> {
-        let data = binary_encode(input)?;
-        ensure!(
-            data.len() < MAX_MESSAGE_SIZE,
-            "PubSub: data is too large. Size={}.",
-            data.len()
-        );
-        self.publish_message(
-            PubSubTopic::BlockProposal,
-            data,
-            PUB_MAX_RETRIES,
-            PUB_INIT_RETRY_DELAY,
-        );
-        Ok(())
+        self.publish_input(PubSubTopic::BlockProposal, input)
+    }
+
+    pub fn publish_state_sync(&mut self, input: &StateSyncAnnouncement) -> Result<()> {
+        self.publish_input(PubSubTopic::StateSync, input)
+    }
+}
+
+/// The gossipsub protocol id prefix, i.e., `<protocol_id_prefix>[/<network_id>]/<version>`.
+/// Gossipsub appends its own version to it.
+pub fn protocol_id(cfg: &PubSubConfig) -> String {
+    if cfg.network_id.is_empty() {
+        format!("{}/{}", cfg.protocol_id_prefix, PUBSUB_PROTOCOL_VERSION)
+    } else {
+        format!(
+            "{}/{}/{}",
+            cfg.protocol_id_prefix, cfg.network_id, PUBSUB_PROTOCOL_VERSION
+        )
+    }
+}
+
+pub(crate) fn gossipsub_config(cfg: &PubSubConfig) -> Result<GossipsubConfig> {
+    GossipsubConfigBuilder::default()
+        .protocol_id_prefix(protocol_id(cfg))
+        .flood_publish(false)
+        .duplicate_cache_time(cfg.duplicate_cache_ttl)
+        .message_id_fn(|msg: &GossipsubMessage| {
+            let hash = msg.data.to_digest();
+            MessageId::new(hash.as_bytes())
+        })
+        .heartbeat_interval(cfg.heartbeat_interval)
+        .mesh_n(cfg.mesh_n)
+        .mesh_n_low(cfg.mesh_n_low)
+        .mesh_n_high(cfg.mesh_n_high)
+        .history_length(cfg.history_length)
+        .history_gossip(cfg.history_gossip)
+        .check_explicit_peers_ticks(CHECK_EXPLICIT_PEERS_TICKS)
+        .max_transmit_size(cfg.max_transmit_size)
+        .build()
+        .map_err(|e| anyhow!("Failed to create gossipsub config. Error: {}", e))
+}
+
+fn decode_payload<T: for<'de> Deserialize<'de>>(data: &[u8]) -> Result<T> {
+    match data.split_first() {
+        Some((&POSTCARD_FORMAT_TAG, plain)) => postcard::from_bytes(plain).map_err(Error::msg),
+        Some((&POSTCARD_ZSTD_FORMAT_TAG, compressed)) => {
+            let mut plain = Vec::new();
+            zstd::stream::read::Decoder::new(compressed)?
+                .take(MAX_DECOMPRESSED_SIZE + 1)
+                .read_to_end(&mut plain)?;
+            ensure!(
+                plain.len() as u64 <= MAX_DECOMPRESSED_SIZE,
+                "Decompressed message is too large."
+            );
+            postcard::from_bytes(&plain).map_err(Error::msg)
+        }
+        _ => binary_decode(data),
     }
 }
 
@@ -254,42 +1403,135 @@ where
     BlockProposal: for<'de> Deserialize<'de> + Send + 'static,
 {
     fn inject_event(&mut self, event: GossipsubEvent) {
+        match &event {
+            GossipsubEvent::Subscribed { peer_id, topic } => {
+                if *topic == *CAPABILITIES_TOPIC_HASH {
+                    self.announce_capabilities();
+                } else if let Some(&topic) = TOPIC_MAP.get(topic) {
+                    let direction = self.connections.direction(peer_id);
+                    self.notify_peer_event(*peer_id, PeerEventKind::Subscribed(topic), direction);
+                }
+                return;
+            }
+            GossipsubEvent::Unsubscribed { peer_id, topic } => {
+                if let Some(&topic) = TOPIC_MAP.get(topic) {
+                    let direction = self.connections.direction(peer_id);
+                    self.notify_peer_event(*peer_id, PeerEventKind::Unsubscribed(topic), direction);
+                }
+                return;
+            }
+            _ => {}
+        }
+
         if let GossipsubEvent::Message {
+            propagation_source,
+            message_id,
             message:
                 GossipsubMessage {
+                    source: author,
                     data,
                     topic: topic_hash,
                     ..
                 },
-            ..
         } = event
         {
+            if topic_hash == *CAPABILITIES_TOPIC_HASH {
+                if self.is_authorized(&propagation_source, author.as_ref()) {
+                    self.handle_capability_announcement(
+                        author.unwrap_or(propagation_source),
+                        &data,
+                    );
+                }
+                return;
+            }
+
             let topic = match TOPIC_MAP.get(&topic_hash) {
-                Some(topic) => topic,
+                Some(&topic) => topic,
                 None => {
                     warn!(?topic_hash, "PubSub: Unknown topic.");
                     return;
                 }
             };
+            let metrics = self.topic_metrics(topic);
+            metrics.received += 1;
+            metrics.received_bytes += data.len() as u64;
 
-            if !self.sub_topics.contains(topic) {
+            if self.is_banned(&propagation_source) {
+                trace!(%propagation_source, "PubSub: Drop the message from the banned peer.");
                 return;
             }
 
-            match topic {
-                PubSubTopic::TxProposal => {
-                    let input =
-                        binary_decode(data.as_slice()).expect("PubSub: Failed to decode message.");
-                    self.pending_events
-                        .push_back(PubSubEvent::TxProposal(input));
+            if !self.is_authorized(&propagation_source, author.as_ref()) {
+                debug!(
+                    %propagation_source,
+                    ?author,
+                    %message_id,
+                    "PubSub: Drop the message from the unauthorized peer."
+                );
+                self.unauthorized_messages += 1;
+                record_event!("pubsub_unauthorized_message", "peer": propagation_source.to_string());
+                return;
+            }
+
+            // Messages on the relay topics are forwarded by gossipsub, but not consumed.
+            if !self.sub_topics.contains(&topic) {
+                return;
+            }
+
+            let data = if data.first() == Some(&CHUNK_FORMAT_TAG) {
+                let origin = author.unwrap_or(propagation_source);
+                match self.reassemble_message(topic, propagation_source, origin, &message_id, &data)
+                {
+                    Some(data) => data,
+                    None => return,
                 }
-                PubSubTopic::BlockProposal => {
-                    let input =
-                        binary_decode(data.as_slice()).expect("PubSub: Failed to decode message.");
-                    self.pending_events
-                        .push_back(PubSubEvent::BlockProposal(input));
+            } else {
+                data
+            };
+
+            let info = MessageInfo {
+                propagation_source,
+                original_author: author,
+                message_id: message_id.clone(),
+            };
+            let event = match topic {
+                PubSubTopic::TxProposal => self
+                    .decode_message(topic, propagation_source, &message_id, &data)
+                    .map(|input| PubSubEvent::TxProposal(input, info)),
+                PubSubTopic::BlockProposal => self
+                    .decode_message(topic, propagation_source, &message_id, &data)
+                    .map(|input| PubSubEvent::BlockProposal(input, info)),
+                PubSubTopic::StateSync => self
+                    .decode_message(topic, propagation_source, &message_id, &data)
+                    .map(|input| PubSubEvent::StateSync(input, info)),
+            };
+            if let Some(event) = event {
+                if self.pending_events.push_back(propagation_source, event) {
+                    self.record_dropped_event(topic);
                 }
             }
         }
     }
 }
+
+impl<TxProposal, BlockProposal> NetworkBehaviourEventProcess<ConnectionEvent>
+    for PubSub<TxProposal, BlockProposal>
+where
+    TxProposal: Send + 'static,
+    BlockProposal: Send + 'static,
+{
+    fn inject_event(&mut self, event: ConnectionEvent) {
+        match event {
+            ConnectionEvent::Established { peer_id, direction } => {
+                self.notify_peer_event(peer_id, PeerEventKind::Connected, Some(direction));
+            }
+            ConnectionEvent::Closed { peer_id, direction } => {
+                self.peer_features.remove(&peer_id);
+                self.notify_peer_event(peer_id, PeerEventKind::Disconnected, Some(direction));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests;
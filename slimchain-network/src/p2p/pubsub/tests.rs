@@ -0,0 +1,1132 @@
+use super::*;
+use crate::p2p::control::{Control, Shutdown, Swarmer};
+use futures::{channel::mpsc, prelude::*};
+use libp2p::Multiaddr;
+use serial_test::serial;
+use slimchain_utils::init_tracing_for_test;
+
+type TestEvent = PubSubEvent<String, String>;
+
+#[derive(NetworkBehaviour)]
+struct PubSubTest {
+    pubsub: PubSub<String, String>,
+    #[behaviour(ignore)]
+    event_tx: mpsc::UnboundedSender<TestEvent>,
+}
+
+impl NetworkBehaviourEventProcess<TestEvent> for PubSubTest {
+    fn inject_event(&mut self, event: TestEvent) {
+        self.event_tx.unbounded_send(event).ok();
+    }
+}
+
+#[async_trait::async_trait]
+impl Shutdown for PubSubTest {
+    async fn shutdown(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+async fn create_node(
+    sub_topics: &[PubSubTopic],
+) -> (
+    PeerId,
+    Multiaddr,
+    Control<PubSubTest>,
+    mpsc::UnboundedReceiver<TestEvent>,
+) {
+    let keypair = Keypair::generate_ed25519();
+    let (event_tx, event_rx) = mpsc::unbounded();
+    let pubsub = PubSub::new(keypair.clone(), sub_topics, &[]).unwrap();
+    let mut swarmer = Swarmer::new(keypair.clone(), PubSubTest { pubsub, event_tx })
+        .await
+        .unwrap();
+    let address = swarmer.listen_on_str("/ip4/127.0.0.1/tcp/0").await.unwrap();
+    let ctrl = swarmer.spawn();
+    (keypair.public().into_peer_id(), address, ctrl, event_rx)
+}
+
+impl<TxProposal, BlockProposal> PendingEvents<TxProposal, BlockProposal> {
+    fn front(&self) -> Option<&(PeerId, PubSubEvent<TxProposal, BlockProposal>)> {
+        self.queues.iter().find_map(|queue| queue.front())
+    }
+
+    fn len(&self) -> usize {
+        self.queues.iter().map(|queue| queue.len()).sum()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn clear(&mut self) {
+        while self.pop_front().is_some() {}
+    }
+
+    fn drain(
+        &mut self,
+    ) -> impl Iterator<Item = (PeerId, PubSubEvent<TxProposal, BlockProposal>)> + '_ {
+        std::iter::from_fn(move || self.pop_front())
+    }
+}
+
+fn message(topic: PubSubTopic, data: Vec<u8>) -> GossipsubEvent {
+    message_from(PeerId::random(), topic, data)
+}
+
+fn message_from(source: PeerId, topic: PubSubTopic, data: Vec<u8>) -> GossipsubEvent {
+    GossipsubEvent::Message {
+        propagation_source: source,
+        message_id: MessageId::new(data.to_digest().as_bytes()),
+        message: GossipsubMessage {
+            source: None,
+            data,
+            sequence_number: None,
+            topic: topic.into_topic_hash(),
+        },
+    }
+}
+
+#[tokio::test]
+async fn test_malformed_message() {
+    let mut pubsub = PubSub::<Vec<u8>, Vec<u8>>::new(
+        Keypair::generate_ed25519(),
+        &[PubSubTopic::TxProposal, PubSubTopic::BlockProposal],
+        &[],
+    )
+    .unwrap();
+
+    pubsub.inject_event(message(PubSubTopic::TxProposal, vec![0xff; 16]));
+    pubsub.inject_event(message(PubSubTopic::BlockProposal, Vec::new()));
+    assert!(pubsub.pending_events.is_empty());
+    assert_eq!(1, pubsub.malformed_messages(PubSubTopic::TxProposal));
+    assert_eq!(1, pubsub.malformed_messages(PubSubTopic::BlockProposal));
+
+    // The behaviour keeps working afterwards.
+    let source = PeerId::random();
+    let data = binary_encode(&vec![1u8, 2, 3]).unwrap();
+    let message_id = MessageId::new(data.to_digest().as_bytes());
+    pubsub.inject_event(message_from(source, PubSubTopic::TxProposal, data));
+    match pubsub.pending_events.pop_front().map(|(_, e)| e) {
+        Some(PubSubEvent::TxProposal(input, info)) => {
+            assert_eq!(vec![1u8, 2, 3], input);
+            assert_eq!(
+                MessageInfo {
+                    propagation_source: source,
+                    original_author: None,
+                    message_id,
+                },
+                info
+            );
+            assert_eq!(source, info.origin());
+        }
+        e => panic!("Unexpected event: {:?}", e),
+    }
+}
+
+#[test]
+fn test_protocol_id() {
+    let mut cfg = PubSubConfig::default();
+    assert_eq!("/slimchain/pubsub/1", protocol_id(&cfg));
+    cfg.network_id = "testnet".to_string();
+    assert_eq!("/slimchain/pubsub/testnet/1", protocol_id(&cfg));
+}
+
+#[tokio::test]
+async fn test_compression() {
+    let mut pubsub = PubSub::<Vec<u8>, Vec<u8>>::new(
+        Keypair::generate_ed25519(),
+        &[PubSubTopic::BlockProposal],
+        &[],
+    )
+    .unwrap();
+    let cfg = PubSubCompressionConfig {
+        enabled: true,
+        ..Default::default()
+    };
+    pubsub.set_compression(cfg);
+
+    let proposal: Vec<u8> = (0..4 * cfg.threshold).map(|i| (i % 7) as u8).collect();
+    let plain = postcard::to_allocvec(&proposal).unwrap();
+    let data = pubsub
+        .encode_message(PubSubTopic::BlockProposal, &proposal)
+        .unwrap();
+    assert_eq!(POSTCARD_ZSTD_FORMAT_TAG, data[0]);
+    assert!(data.len() * 10 < plain.len());
+
+    // The small messages are not compressed.
+    let small = pubsub
+        .encode_message(PubSubTopic::BlockProposal, &vec![1u8, 2, 3])
+        .unwrap();
+    assert_eq!(POSTCARD_FORMAT_TAG, small[0]);
+
+    // Along with those in the legacy format from the older peers.
+    let legacy = binary_encode(&vec![4u8, 5, 6]).unwrap();
+    for data in vec![data, small, legacy] {
+        pubsub.inject_event(message(PubSubTopic::BlockProposal, data));
+    }
+    let received: Vec<_> = pubsub
+        .pending_events
+        .drain()
+        .map(|(_, event)| match event {
+            PubSubEvent::BlockProposal(input, _) => input,
+            e => panic!("Unexpected event: {:?}", e),
+        })
+        .collect();
+    assert_eq!(vec![proposal, vec![1u8, 2, 3], vec![4u8, 5, 6]], received);
+    assert_eq!(0, pubsub.malformed_messages(PubSubTopic::BlockProposal));
+}
+
+#[tokio::test]
+async fn test_event_priority() {
+    let mut pubsub = PubSub::<Vec<u8>, Vec<u8>>::new(
+        Keypair::generate_ed25519(),
+        &[PubSubTopic::TxProposal, PubSubTopic::BlockProposal],
+        &[],
+    )
+    .unwrap();
+
+    for i in 0..4u8 {
+        for &topic in &[PubSubTopic::TxProposal, PubSubTopic::BlockProposal] {
+            let data = binary_encode(&vec![topic.priority() as u8, i]).unwrap();
+            pubsub.inject_event(message(topic, data));
+        }
+    }
+
+    let received: Vec<_> = pubsub
+        .pending_events
+        .drain()
+        .map(|(_, event)| match event {
+            PubSubEvent::BlockProposal(input, _) => ("block", input[1]),
+            PubSubEvent::TxProposal(input, _) => ("tx", input[1]),
+            e => panic!("Unexpected event: {:?}", e),
+        })
+        .collect();
+    // The block proposals come first, with the order within each topic preserved.
+    assert_eq!(
+        vec![
+            ("block", 0),
+            ("block", 1),
+            ("block", 2),
+            ("block", 3),
+            ("tx", 0),
+            ("tx", 1),
+            ("tx", 2),
+            ("tx", 3),
+        ],
+        received
+    );
+}
+
+#[tokio::test]
+async fn test_pending_queue_overflow() {
+    let mut pubsub = PubSub::<Vec<u8>, Vec<u8>>::new(
+        Keypair::generate_ed25519(),
+        &[PubSubTopic::TxProposal, PubSubTopic::BlockProposal],
+        &[],
+    )
+    .unwrap();
+
+    for &(overflow, expected) in &[
+        (OverflowPolicy::DropOldest, [6u8, 7, 8, 9]),
+        (OverflowPolicy::DropNewest, [0u8, 1, 2, 3]),
+        (OverflowPolicy::Block, [0u8, 1, 2, 3]),
+    ] {
+        pubsub.pending_events = PendingEvents::new(&PubSubConfig {
+            tx_proposal_queue: PendingQueueConfig {
+                capacity: 4,
+                overflow,
+            },
+            ..Default::default()
+        });
+        for i in 0..10u8 {
+            let data = binary_encode(&vec![i]).unwrap();
+            pubsub.inject_event(message(PubSubTopic::TxProposal, data));
+            if i % 5 == 0 {
+                let data = binary_encode(&vec![i]).unwrap();
+                pubsub.inject_event(message(PubSubTopic::BlockProposal, data));
+            }
+        }
+
+        let mut blocks = Vec::new();
+        let mut txs = Vec::new();
+        for (_, event) in pubsub.pending_events.drain() {
+            match event {
+                PubSubEvent::BlockProposal(input, _) => blocks.push(input[0]),
+                PubSubEvent::TxProposal(input, _) => txs.push(input[0]),
+                e => panic!("Unexpected event: {:?}", e),
+            }
+        }
+        // The block proposals are never dropped.
+        assert_eq!(vec![0, 5], blocks, "{:?}", overflow);
+        assert_eq!(expected.to_vec(), txs, "{:?}", overflow);
+    }
+
+    let snapshot = pubsub.metrics_snapshot();
+    assert_eq!(18, snapshot.topics["tx_proposal"].dropped_events);
+    assert_eq!(0, snapshot.topics["block_proposal"].dropped_events);
+}
+
+#[tokio::test]
+async fn test_metrics() {
+    let mut pubsub = PubSub::<Vec<u8>, Vec<u8>>::new(
+        Keypair::generate_ed25519(),
+        &[PubSubTopic::TxProposal],
+        &[],
+    )
+    .unwrap();
+
+    let data = binary_encode(&vec![1u8, 2, 3]).unwrap();
+    let size = data.len() as u64;
+    pubsub.inject_event(message(PubSubTopic::TxProposal, data.clone()));
+    pubsub.inject_event(message(PubSubTopic::TxProposal, data));
+    pubsub.inject_event(message(PubSubTopic::TxProposal, vec![0xff; 16]));
+    let chunks = chunk_messages(1, &[0u8; 64], 16);
+    let source = PeerId::random();
+    for _ in 0..2 {
+        pubsub.inject_event(message_from(
+            source,
+            PubSubTopic::TxProposal,
+            chunks[0].clone(),
+        ));
+    }
+    pubsub.report_message_outcome(PeerId::random(), MessageOutcome::Duplicate);
+
+    // No peer to publish to.
+    pubsub.publish_tx_proposal(&vec![4u8, 5, 6]).unwrap();
+    pubsub.max_message_size = 1024;
+    // Random data, which is not compressible.
+    let proposal: Vec<u8> = (0..1024 * (MAX_CHUNKS_PER_MESSAGE + 1))
+        .map(|_| rand::random())
+        .collect();
+    assert!(pubsub.publish_block_proposal(&proposal).is_err());
+
+    let snapshot = pubsub.metrics_snapshot();
+    let tx = &snapshot.topics["tx_proposal"];
+    assert_eq!(5, tx.received);
+    assert_eq!(
+        2 * size + 16 + 2 * chunks[0].len() as u64,
+        tx.received_bytes
+    );
+    assert_eq!(1, tx.duplicates);
+    assert_eq!(1, tx.decode_failures);
+    assert_eq!(0, tx.published);
+    assert_eq!(Some(&1), tx.publish_failures.get("insufficient_peers"));
+    let block = &snapshot.topics["block_proposal"];
+    assert_eq!(0, block.received);
+    assert_eq!(Some(&1), block.publish_failures.get("message_too_large"));
+    assert_eq!(1, snapshot.reported_duplicates);
+    assert_eq!(0, snapshot.unauthorized_messages);
+    // The counters are serializable, to be recorded to the metrics file.
+    assert!(serde_json::to_string(&snapshot).is_ok());
+
+    let mut w = MetricsWriter::default();
+    snapshot.write_prometheus(&mut w);
+    let samples = slimchain_utils::prometheus::parse_samples(&w.render()).unwrap();
+    let find = |name: &str, labels: &[(&str, &str)]| {
+        samples
+            .iter()
+            .find(|s| {
+                s.name == name
+                    && labels
+                        .iter()
+                        .all(|(k, v)| s.labels.get(*k).map(String::as_str) == Some(*v))
+            })
+            .map(|s| s.value)
+    };
+    let tx_topic = ("topic", "tx_proposal");
+    assert_eq!(
+        Some(5.),
+        find("slimchain_pubsub_received_total", &[tx_topic])
+    );
+    assert_eq!(
+        Some(1.),
+        find(
+            "slimchain_pubsub_publish_failures_total",
+            &[tx_topic, ("kind", "insufficient_peers")]
+        )
+    );
+    assert_eq!(
+        Some(1.),
+        find("slimchain_pubsub_reported_duplicates_total", &[])
+    );
+}
+
+#[tokio::test]
+async fn test_peer_score() {
+    let mut pubsub = PubSub::<Vec<u8>, Vec<u8>>::new(
+        Keypair::generate_ed25519(),
+        &[PubSubTopic::TxProposal],
+        &[],
+    )
+    .unwrap();
+    let bad_peer = PeerId::random();
+    let good_peer = PeerId::random();
+    let data = binary_encode(&vec![1u8]).unwrap();
+
+    pubsub.inject_event(message_from(
+        bad_peer,
+        PubSubTopic::TxProposal,
+        data.clone(),
+    ));
+    assert_eq!(1, pubsub.pending_events.len());
+    assert_eq!(
+        Some(bad_peer),
+        pubsub.pending_events.front().map(|(peer, _)| *peer)
+    );
+    pubsub.pending_events.clear();
+
+    pubsub.report_message_outcome(good_peer, MessageOutcome::Valid);
+    pubsub.report_message_outcome(bad_peer, MessageOutcome::Duplicate);
+    pubsub.report_message_outcome(bad_peer, MessageOutcome::InvalidContent);
+    pubsub.inject_event(message_from(bad_peer, PubSubTopic::TxProposal, Vec::new()));
+    assert!(!pubsub.is_banned(&bad_peer));
+    pubsub.report_message_outcome(bad_peer, MessageOutcome::InvalidContent);
+    assert!(pubsub.is_banned(&bad_peer));
+    assert!(!pubsub.is_banned(&good_peer));
+    let scores = pubsub.peer_scores();
+    assert!(scores[&bad_peer] <= -25.0);
+    assert!(scores[&good_peer] > 0.0);
+
+    // The messages from the banned peer are dropped.
+    pubsub.inject_event(message_from(
+        bad_peer,
+        PubSubTopic::TxProposal,
+        data.clone(),
+    ));
+    pubsub.inject_event(message_from(bad_peer, PubSubTopic::TxProposal, Vec::new()));
+    assert!(pubsub.pending_events.is_empty());
+    assert_eq!(1, pubsub.malformed_messages(PubSubTopic::TxProposal));
+    pubsub.inject_event(message_from(
+        good_peer,
+        PubSubTopic::TxProposal,
+        data.clone(),
+    ));
+    assert_eq!(1, pubsub.pending_events.len());
+    pubsub.pending_events.clear();
+
+    // The scores decay, and the ban is lifted after the cool-off period.
+    let cfg = PeerScoreConfig::default();
+    pubsub.update_peer_scores(Instant::now() + cfg.decay_half_life * 5);
+    assert!(pubsub.is_banned(&bad_peer));
+    assert!(!pubsub.peer_scores().contains_key(&good_peer));
+    pubsub.update_peer_scores(Instant::now() + cfg.ban_duration);
+    assert!(!pubsub.is_banned(&bad_peer));
+    assert!(pubsub.peer_scores().is_empty());
+    pubsub.inject_event(message_from(bad_peer, PubSubTopic::TxProposal, data));
+    assert_eq!(1, pubsub.pending_events.len());
+}
+
+fn chunk_messages(id: u64, data: &[u8], chunk_size: usize) -> Vec<Vec<u8>> {
+    let digest = data.to_vec().to_digest();
+    let chunks: Vec<_> = data.chunks(chunk_size).collect();
+    chunks
+        .iter()
+        .enumerate()
+        .map(|(index, part)| {
+            let chunk = MessageChunk {
+                id,
+                index: index as u32,
+                total: chunks.len() as u32,
+                digest,
+                data: part.to_vec(),
+            };
+            let mut data = vec![CHUNK_FORMAT_TAG];
+            data.extend(postcard::to_allocvec(&chunk).unwrap());
+            data
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn test_reassemble_chunks() {
+    let mut pubsub = PubSub::<Vec<u8>, Vec<u8>>::new(
+        Keypair::generate_ed25519(),
+        &[PubSubTopic::BlockProposal],
+        &[],
+    )
+    .unwrap();
+
+    let source = PeerId::random();
+    let inject = |pubsub: &mut PubSub<Vec<u8>, Vec<u8>>, source: PeerId, chunk: &[u8]| {
+        pubsub.inject_event(message_from(
+            source,
+            PubSubTopic::BlockProposal,
+            chunk.to_vec(),
+        ));
+    };
+    let proposal: Vec<u8> = (0..100u8).collect();
+    let data = binary_encode(&proposal).unwrap();
+    let chunks = chunk_messages(1, &data, 16);
+    assert!(chunks.len() > 2);
+    // Out of order and duplicated.
+    for i in (1..2).chain((0..chunks.len()).rev()) {
+        inject(&mut pubsub, source, &chunks[i]);
+    }
+    match pubsub.pending_events.pop_front().map(|(_, e)| e) {
+        Some(PubSubEvent::BlockProposal(input, _)) => assert_eq!(proposal, input),
+        e => panic!("Unexpected event: {:?}", e),
+    }
+    assert!(pubsub.pending_events.is_empty());
+    assert!(pubsub.partial_messages.is_empty());
+
+    // The corrupted message is rejected once completed.
+    let mut chunks = chunk_messages(2, &data, 16);
+    let last = chunks.len() - 1;
+    *chunks[last].last_mut().unwrap() ^= 0xff;
+    for chunk in &chunks {
+        inject(&mut pubsub, source, chunk);
+    }
+    assert!(pubsub.pending_events.is_empty());
+    assert_eq!(1, pubsub.malformed_messages(PubSubTopic::BlockProposal));
+
+    // The messages of the same id from other sources are reassembled apart, and an
+    // inconsistent chunk only drops itself.
+    let other = PeerId::random();
+    let other_proposal: Vec<u8> = (100..200u8).collect();
+    let other_data = binary_encode(&other_proposal).unwrap();
+    let chunks = chunk_messages(3, &data, 16);
+    let other_chunks = chunk_messages(3, &other_data, 16);
+    inject(&mut pubsub, source, &chunks[0]);
+    inject(&mut pubsub, source, &other_chunks[1]);
+    assert_eq!(2, pubsub.malformed_messages(PubSubTopic::BlockProposal));
+    inject(&mut pubsub, other, &other_chunks[0]);
+    for (chunk, other_chunk) in chunks.iter().zip(&other_chunks).skip(1) {
+        inject(&mut pubsub, source, chunk);
+        inject(&mut pubsub, other, other_chunk);
+    }
+    let mut received: Vec<_> = pubsub
+        .pending_events
+        .drain()
+        .map(|(_, e)| match e {
+            PubSubEvent::BlockProposal(input, _) => input,
+            e => panic!("Unexpected event: {:?}", e),
+        })
+        .collect();
+    received.sort();
+    assert_eq!(vec![proposal, other_proposal], received);
+    assert!(pubsub.partial_messages.is_empty());
+
+    // The incomplete messages are bounded for each source and garbage collected.
+    inject(&mut pubsub, other, &chunk_messages(3, &data, 16)[0]);
+    for id in 4..(4 + MAX_PARTIAL_MESSAGES as u64 + 2) {
+        inject(&mut pubsub, source, &chunk_messages(id, &data, 16)[0]);
+    }
+    assert_eq!(MAX_PARTIAL_MESSAGES + 1, pubsub.partial_messages.len());
+    assert!(!pubsub.partial_messages.contains_key(&(source, 4)));
+    assert!(pubsub.partial_messages.contains_key(&(other, 3)));
+    pubsub.purge_partial_messages(Instant::now() + REASSEMBLY_TIMEOUT);
+    assert!(pubsub.partial_messages.is_empty());
+}
+
+#[tokio::test]
+async fn test_unsubscribe_pending_events() {
+    let mut pubsub = PubSub::<Vec<u8>, Vec<u8>>::new(
+        Keypair::generate_ed25519(),
+        &[PubSubTopic::TxProposal],
+        &[PubSubTopic::BlockProposal],
+    )
+    .unwrap();
+    pubsub.subscribe(PubSubTopic::BlockProposal).unwrap();
+    assert_eq!(2, pubsub.subscribed_topics().len());
+
+    let data = binary_encode(&vec![1u8]).unwrap();
+    pubsub.inject_event(message(PubSubTopic::BlockProposal, data.clone()));
+    pubsub.inject_event(message(PubSubTopic::TxProposal, data));
+    pubsub.unsubscribe(PubSubTopic::BlockProposal).unwrap();
+    assert_eq!(
+        vec![PubSubTopic::TxProposal],
+        pubsub
+            .subscribed_topics()
+            .iter()
+            .copied()
+            .collect::<Vec<_>>()
+    );
+    assert_eq!(1, pubsub.pending_events.len());
+    assert_eq!(
+        Some(PubSubTopic::TxProposal),
+        pubsub.pending_events.front().map(|(_, e)| e.topic())
+    );
+    // Still relayed.
+    assert_eq!(2, pubsub.gossipsub.topics().count());
+}
+
+async fn wait_for_topics(
+    ctrl: &mut Control<PubSubTest>,
+    peer_id: PeerId,
+    expected: Vec<TopicHash>,
+) {
+    loop {
+        let topics = ctrl
+            .call(move |swarm| {
+                swarm
+                    .behaviour()
+                    .pubsub
+                    .gossipsub
+                    .all_peers()
+                    .find(|(id, _)| **id == peer_id)
+                    .map(|(_, topics)| {
+                        topics
+                            .iter()
+                            .copied()
+                            .filter(|topic| TOPIC_MAP.contains_key(topic))
+                            .cloned()
+                            .collect::<HashSet<_>>()
+                    })
+            })
+            .await
+            .unwrap();
+        if topics == Some(expected.iter().cloned().collect()) {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+}
+
+async fn publish_both(ctrl: &mut Control<PubSubTest>, n: usize) {
+    ctrl.call(move |swarm| {
+        let pubsub = &mut swarm.behaviour_mut().pubsub;
+        pubsub.publish_block_proposal(&format!("block{}", n))?;
+        pubsub.publish_tx_proposal(&format!("tx{}", n))
+    })
+    .await
+    .unwrap()
+    .unwrap();
+}
+
+#[tokio::test]
+#[serial]
+async fn test_unsubscribe() {
+    let _guard = init_tracing_for_test();
+
+    let both = [PubSubTopic::TxProposal, PubSubTopic::BlockProposal];
+    let (peer1, addr1, mut ctrl1, mut event_rx1) = create_node(&both).await;
+    let (_peer2, _addr2, mut ctrl2, _event_rx2) = create_node(&both).await;
+
+    ctrl2
+        .call(move |swarm| {
+            swarm.behaviour_mut().pubsub.add_explicit_peer(peer1);
+            swarm.dial_addr(addr1)
+        })
+        .await
+        .unwrap()
+        .unwrap();
+    wait_for_topics(
+        &mut ctrl2,
+        peer1,
+        both.iter().map(|t| t.into_topic_hash()).collect(),
+    )
+    .await;
+
+    publish_both(&mut ctrl2, 1).await;
+    let mut received = Vec::new();
+    for _ in 0..2 {
+        match tokio::time::timeout(Duration::from_secs(5), event_rx1.next()).await {
+            Ok(Some(PubSubEvent::TxProposal(input, _))) => received.push(input),
+            Ok(Some(PubSubEvent::BlockProposal(input, _))) => received.push(input),
+            e => panic!("Unexpected event: {:?}", e),
+        }
+    }
+    received.sort();
+    assert_eq!(vec!["block1".to_string(), "tx1".to_string()], received);
+
+    ctrl1
+        .call(|swarm| {
+            swarm
+                .behaviour_mut()
+                .pubsub
+                .unsubscribe(PubSubTopic::BlockProposal)
+        })
+        .await
+        .unwrap()
+        .unwrap();
+    wait_for_topics(
+        &mut ctrl2,
+        peer1,
+        vec![PubSubTopic::TxProposal.into_topic_hash()],
+    )
+    .await;
+
+    publish_both(&mut ctrl2, 2).await;
+    match tokio::time::timeout(Duration::from_secs(5), event_rx1.next()).await {
+        Ok(Some(PubSubEvent::TxProposal(input, _))) => assert_eq!("tx2", input),
+        e => panic!("Unexpected event: {:?}", e),
+    }
+    assert!(
+        tokio::time::timeout(Duration::from_millis(500), event_rx1.next())
+            .await
+            .is_err()
+    );
+
+    ctrl1.shutdown().await.unwrap();
+    ctrl2.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+#[serial]
+async fn test_sub_topics() {
+    let _guard = init_tracing_for_test();
+
+    let (peer1, addr1, mut ctrl1, mut event_rx1) = create_node(&[PubSubTopic::TxProposal]).await;
+    let (_peer2, _addr2, mut ctrl2, _event_rx2) =
+        create_node(&[PubSubTopic::TxProposal, PubSubTopic::BlockProposal]).await;
+
+    let topics = ctrl1
+        .call(|swarm| {
+            swarm
+                .behaviour()
+                .pubsub
+                .gossipsub
+                .topics()
+                .cloned()
+                .collect::<Vec<_>>()
+        })
+        .await
+        .unwrap();
+    assert_eq!(vec![PubSubTopic::TxProposal.into_topic_hash()], topics);
+
+    ctrl2
+        .call(move |swarm| {
+            swarm.behaviour_mut().pubsub.add_explicit_peer(peer1);
+            swarm.dial_addr(addr1)
+        })
+        .await
+        .unwrap()
+        .unwrap();
+
+    // Wait until node 2 learns the subscriptions of node 1.
+    let peer1_topics = loop {
+        let topics = ctrl2
+            .call(move |swarm| {
+                swarm
+                    .behaviour()
+                    .pubsub
+                    .gossipsub
+                    .all_peers()
+                    .find(|(peer_id, _)| **peer_id == peer1)
+                    .map(|(_, topics)| topics.iter().copied().cloned().collect::<Vec<_>>())
+            })
+            .await
+            .unwrap();
+        match topics {
+            Some(topics) if !topics.is_empty() => break topics,
+            _ => tokio::time::sleep(Duration::from_millis(100)).await,
+        }
+    };
+    assert_eq!(
+        vec![PubSubTopic::TxProposal.into_topic_hash()],
+        peer1_topics
+    );
+
+    ctrl2
+        .call(|swarm| {
+            let pubsub = &mut swarm.behaviour_mut().pubsub;
+            pubsub.publish_block_proposal(&"block".to_string())?;
+            pubsub.publish_tx_proposal(&"tx".to_string())
+        })
+        .await
+        .unwrap()
+        .unwrap();
+
+    match tokio::time::timeout(Duration::from_secs(5), event_rx1.next()).await {
+        Ok(Some(PubSubEvent::TxProposal(input, _))) => assert_eq!("tx", input),
+        e => panic!("Unexpected event: {:?}", e),
+    }
+    // The block proposal is never delivered to node 1.
+    assert!(
+        tokio::time::timeout(Duration::from_millis(500), event_rx1.next())
+            .await
+            .is_err()
+    );
+
+    ctrl1.shutdown().await.unwrap();
+    ctrl2.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+#[serial]
+async fn test_state_sync() {
+    let _guard = init_tracing_for_test();
+
+    let (peer1, addr1, mut ctrl1, mut event_rx1) =
+        create_node(&[PubSubTopic::BlockProposal, PubSubTopic::StateSync]).await;
+    let (_peer2, _addr2, mut ctrl2, _event_rx2) = create_node(&[PubSubTopic::StateSync]).await;
+
+    ctrl2
+        .call(move |swarm| {
+            swarm.behaviour_mut().pubsub.add_explicit_peer(peer1);
+            swarm.dial_addr(addr1)
+        })
+        .await
+        .unwrap()
+        .unwrap();
+    wait_for_topics(
+        &mut ctrl2,
+        peer1,
+        vec![
+            PubSubTopic::BlockProposal.into_topic_hash(),
+            PubSubTopic::StateSync.into_topic_hash(),
+        ],
+    )
+    .await;
+
+    let announcement = StateSyncAnnouncement {
+        shard_id: ShardId::new(0, 1),
+        height: 10u64.into(),
+        state_root: H256::repeat_byte(1),
+    };
+    let input = announcement.clone();
+    ctrl2
+        .call(move |swarm| swarm.behaviour_mut().pubsub.publish_state_sync(&input))
+        .await
+        .unwrap()
+        .unwrap();
+
+    match tokio::time::timeout(Duration::from_secs(5), event_rx1.next()).await {
+        Ok(Some(PubSubEvent::StateSync(input, _))) => assert_eq!(announcement, input),
+        e => panic!("Unexpected event: {:?}", e),
+    }
+
+    ctrl1.shutdown().await.unwrap();
+    ctrl2.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+#[serial]
+async fn test_chunked_message() {
+    let _guard = init_tracing_for_test();
+
+    let (peer1, addr1, mut ctrl1, mut event_rx1) = create_node(&[PubSubTopic::BlockProposal]).await;
+    let (_peer2, _addr2, mut ctrl2, _event_rx2) = create_node(&[PubSubTopic::BlockProposal]).await;
+
+    const MAX_SIZE: usize = 16 * 1024;
+    ctrl2
+        .call(move |swarm| {
+            swarm.behaviour_mut().pubsub.max_message_size = MAX_SIZE;
+            swarm.behaviour_mut().pubsub.add_explicit_peer(peer1);
+            swarm.dial_addr(addr1)
+        })
+        .await
+        .unwrap()
+        .unwrap();
+    wait_for_topics(
+        &mut ctrl2,
+        peer1,
+        vec![PubSubTopic::BlockProposal.into_topic_hash()],
+    )
+    .await;
+
+    // Random data, which is not compressible.
+    let proposal: String = (0..3 * MAX_SIZE)
+        .map(|_| rand::random::<u8>() as char)
+        .collect();
+    let input = proposal.clone();
+    ctrl2
+        .call(move |swarm| swarm.behaviour_mut().pubsub.publish_block_proposal(&input))
+        .await
+        .unwrap()
+        .unwrap();
+
+    match tokio::time::timeout(Duration::from_secs(5), event_rx1.next()).await {
+        Ok(Some(PubSubEvent::BlockProposal(input, _))) => assert_eq!(proposal, input),
+        e => panic!("Unexpected event: {:?}", e),
+    }
+
+    ctrl1.shutdown().await.unwrap();
+    ctrl2.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+#[serial]
+async fn test_known_peers() {
+    let _guard = init_tracing_for_test();
+
+    let (peer1, addr1, mut ctrl1, _event_rx1) = create_node(&[PubSubTopic::TxProposal]).await;
+    let (peer2, _addr2, mut ctrl2, _event_rx2) =
+        create_node(&[PubSubTopic::TxProposal, PubSubTopic::BlockProposal]).await;
+    let shared_report = ctrl1
+        .call(|swarm| swarm.behaviour().pubsub.shared_peers_report())
+        .await
+        .unwrap();
+
+    ctrl2
+        .call(move |swarm| swarm.dial_addr(addr1))
+        .await
+        .unwrap()
+        .unwrap();
+    wait_for_topics(
+        &mut ctrl2,
+        peer1,
+        vec![PubSubTopic::TxProposal.into_topic_hash()],
+    )
+    .await;
+
+    let known_peers = ctrl2
+        .call(|swarm| swarm.behaviour().pubsub.known_peers())
+        .await
+        .unwrap();
+    assert_eq!(
+        Some(&vec![peer1]),
+        known_peers.get(&PubSubTopic::TxProposal)
+    );
+    assert_eq!(None, known_peers.get(&PubSubTopic::BlockProposal));
+
+    // Wait for the shared report refreshed by node 1.
+    let peer2 = peer2.to_base58();
+    let report = loop {
+        let report = shared_report.get();
+        if !report.is_empty() {
+            break report;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    };
+    assert_eq!(vec![peer2.clone()], report["tx_proposal"].peers);
+    assert_eq!(vec![peer2], report["block_proposal"].peers);
+
+    ctrl1.shutdown().await.unwrap();
+    ctrl2.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+#[serial]
+async fn test_authorized_peers() {
+    let _guard = init_tracing_for_test();
+
+    let (peer1, addr1, mut ctrl1, mut event_rx1) = create_node(&[PubSubTopic::TxProposal]).await;
+    let (peer2, _addr2, mut ctrl2, _event_rx2) = create_node(&[PubSubTopic::TxProposal]).await;
+    let (_peer3, _addr3, mut ctrl3, _event_rx3) = create_node(&[PubSubTopic::TxProposal]).await;
+
+    ctrl1
+        .call(move |swarm| {
+            swarm
+                .behaviour_mut()
+                .pubsub
+                .set_authorized_peers(vec![peer2])
+        })
+        .await
+        .unwrap();
+
+    for ctrl in vec![&mut ctrl2, &mut ctrl3] {
+        let addr = addr1.clone();
+        ctrl.call(move |swarm| {
+            swarm.behaviour_mut().pubsub.add_explicit_peer(peer1);
+            swarm.dial_addr(addr)
+        })
+        .await
+        .unwrap()
+        .unwrap();
+        wait_for_topics(ctrl, peer1, vec![PubSubTopic::TxProposal.into_topic_hash()]).await;
+    }
+
+    // Publish from the unauthorized node first, so that its message would be received first.
+    for (ctrl, tx) in vec![(&mut ctrl3, "tx3"), (&mut ctrl2, "tx2")] {
+        ctrl.call(move |swarm| {
+            swarm
+                .behaviour_mut()
+                .pubsub
+                .publish_tx_proposal(&tx.to_string())
+        })
+        .await
+        .unwrap()
+        .unwrap();
+    }
+
+    match tokio::time::timeout(Duration::from_secs(5), event_rx1.next()).await {
+        Ok(Some(PubSubEvent::TxProposal(input, info))) => {
+            assert_eq!("tx2", input);
+            assert_eq!(peer2, info.propagation_source);
+            assert_eq!(Some(peer2), info.original_author);
+        }
+        e => panic!("Unexpected event: {:?}", e),
+    }
+    assert!(
+        tokio::time::timeout(Duration::from_millis(500), event_rx1.next())
+            .await
+            .is_err()
+    );
+    let unauthorized = ctrl1
+        .call(|swarm| swarm.behaviour().pubsub.unauthorized_messages())
+        .await
+        .unwrap();
+    assert_eq!(1, unauthorized);
+
+    ctrl1.shutdown().await.unwrap();
+    ctrl2.shutdown().await.unwrap();
+    ctrl3.shutdown().await.unwrap();
+}
+
+async fn encode_tx_proposal(ctrl: &mut Control<PubSubTest>, input: String) -> Vec<u8> {
+    ctrl.call(move |swarm| {
+        swarm
+            .behaviour()
+            .pubsub
+            .encode_message(PubSubTopic::TxProposal, &input)
+    })
+    .await
+    .unwrap()
+    .unwrap()
+}
+
+#[tokio::test]
+#[serial]
+async fn test_capabilities() {
+    let _guard = init_tracing_for_test();
+
+    let (_peer1, addr1, mut ctrl1, _event_rx1) = create_node(&[PubSubTopic::TxProposal]).await;
+    let (peer2, _addr2, mut ctrl2, mut event_rx2) = create_node(&[PubSubTopic::TxProposal]).await;
+    let (peer3, _addr3, mut ctrl3, mut event_rx3) = create_node(&[PubSubTopic::TxProposal]).await;
+
+    ctrl1
+        .call(|swarm| {
+            swarm
+                .behaviour_mut()
+                .pubsub
+                .set_compression(PubSubCompressionConfig {
+                    enabled: true,
+                    threshold: 0,
+                    ..Default::default()
+                })
+        })
+        .await
+        .unwrap();
+    // Node 3 runs an older version without the capability exchange.
+    ctrl3
+        .call(|swarm| {
+            let pubsub = &mut swarm.behaviour_mut().pubsub;
+            pubsub.local_features = None;
+            pubsub
+                .gossipsub
+                .unsubscribe(&IdentTopic::new(CAPABILITIES_TOPIC))
+                .unwrap();
+        })
+        .await
+        .unwrap();
+
+    let input = "tx".repeat(1000);
+    let addr = addr1.clone();
+    ctrl2
+        .call(move |swarm| swarm.dial_addr(addr))
+        .await
+        .unwrap()
+        .unwrap();
+    wait_for_topics(
+        &mut ctrl1,
+        peer2,
+        vec![PubSubTopic::TxProposal.into_topic_hash()],
+    )
+    .await;
+    loop {
+        let features = ctrl1
+            .call(move |swarm| swarm.behaviour().pubsub.peer_features(&peer2))
+            .await
+            .unwrap();
+        if features.is_some() {
+            assert_eq!(Some(PubSubFeatures::all()), features);
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    let data = encode_tx_proposal(&mut ctrl1, input.clone()).await;
+    assert_eq!(POSTCARD_ZSTD_FORMAT_TAG, data[0]);
+
+    ctrl3
+        .call(move |swarm| swarm.dial_addr(addr1))
+        .await
+        .unwrap()
+        .unwrap();
+    wait_for_topics(
+        &mut ctrl1,
+        peer3,
+        vec![PubSubTopic::TxProposal.into_topic_hash()],
+    )
+    .await;
+    assert_eq!(
+        None,
+        ctrl1
+            .call(move |swarm| swarm.behaviour().pubsub.peer_features(&peer3))
+            .await
+            .unwrap()
+    );
+    // Fall back to the legacy format, as node 3 does not advertise the compression.
+    let data = encode_tx_proposal(&mut ctrl1, input.clone()).await;
+    assert_eq!(binary_encode(&input).unwrap(), data);
+
+    let proposal = input.clone();
+    ctrl1
+        .call(move |swarm| swarm.behaviour_mut().pubsub.publish_tx_proposal(&proposal))
+        .await
+        .unwrap()
+        .unwrap();
+    for event_rx in vec![&mut event_rx2, &mut event_rx3] {
+        match tokio::time::timeout(Duration::from_secs(5), event_rx.next()).await {
+            Ok(Some(PubSubEvent::TxProposal(received, _))) => assert_eq!(input, received),
+            e => panic!("Unexpected event: {:?}", e),
+        }
+    }
+
+    ctrl1.shutdown().await.unwrap();
+    ctrl2.shutdown().await.unwrap();
+    ctrl3.shutdown().await.unwrap();
+}
+
+async fn next_peer_event(
+    rx: &mut mpsc::UnboundedReceiver<PeerEvent>,
+    kind: PeerEventKind,
+) -> PeerEvent {
+    loop {
+        match tokio::time::timeout(Duration::from_secs(5), rx.next()).await {
+            Ok(Some(event)) if event.kind == kind => return event,
+            Ok(Some(_)) => continue,
+            e => panic!("Unexpected peer event: {:?}", e),
+        }
+    }
+}
+
+#[tokio::test]
+#[serial]
+async fn test_peer_events() {
+    let _guard = init_tracing_for_test();
+
+    let (peer1, addr1, mut ctrl1, _event_rx1) = create_node(&[PubSubTopic::TxProposal]).await;
+    let (peer2, _addr2, mut ctrl2, _event_rx2) = create_node(&[PubSubTopic::TxProposal]).await;
+    let mut peer_rx = ctrl1
+        .call(|swarm| swarm.behaviour_mut().pubsub.subscribe_peer_events())
+        .await
+        .unwrap();
+
+    ctrl2
+        .call(move |swarm| swarm.dial_addr(addr1))
+        .await
+        .unwrap()
+        .unwrap();
+    let event = next_peer_event(&mut peer_rx, PeerEventKind::Connected).await;
+    assert_eq!(peer2, event.peer_id);
+    assert_eq!(Some(ConnectionDirection::Inbound), event.direction);
+    let event = next_peer_event(
+        &mut peer_rx,
+        PeerEventKind::Subscribed(PubSubTopic::TxProposal),
+    )
+    .await;
+    assert_eq!(peer2, event.peer_id);
+    assert_eq!(1, event.topic_peer_counts[&PubSubTopic::TxProposal]);
+
+    let report = ctrl1
+        .call(|swarm| swarm.behaviour().pubsub.metrics_snapshot())
+        .await
+        .unwrap();
+    assert_eq!(Some(&1), report.peer_counts.get("tx_proposal"));
+
+    ctrl2
+        .call(move |swarm| swarm.disconnect_peer_id(peer1))
+        .await
+        .unwrap()
+        .unwrap();
+    let event = next_peer_event(&mut peer_rx, PeerEventKind::Disconnected).await;
+    assert_eq!(peer2, event.peer_id);
+    assert_eq!(Some(ConnectionDirection::Inbound), event.direction);
+    assert_eq!(0, event.topic_peer_counts[&PubSubTopic::TxProposal]);
+    assert_eq!(0, event.topic_peer_counts[&PubSubTopic::BlockProposal]);
+
+    ctrl1.shutdown().await.unwrap();
+    ctrl2.shutdown().await.unwrap();
+}
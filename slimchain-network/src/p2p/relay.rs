@@ -0,0 +1,56 @@
+use crate::p2p::{
+    config::{PeerConfig, RelayConfig},
+    control::{build_base_transport, upgrade_transport},
+};
+use libp2p::{
+    core::{multiaddr::Protocol, muxing, transport},
+    identity::Keypair,
+    relay::{new_transport_and_behaviour, RelayConfig as Libp2pRelayConfig},
+    Multiaddr, PeerId,
+};
+use slimchain_common::error::Result;
+
+pub use libp2p::relay::Relay;
+
+/// Build the transport, which also dials and listens via the relay servers if relaying is
+/// enabled in `cfg`. The returned [`Relay`] must be added to the behaviour of the swarm.
+///
+/// AutoNAT is not available in this version of libp2p. The nodes behind NAT need to set
+/// `listen_via_relay` to be reachable.
+pub async fn build_transport_with_relay(
+    keypair: &Keypair,
+    cfg: &RelayConfig,
+) -> Result<(
+    transport::Boxed<(PeerId, muxing::StreamMuxerBox)>,
+    Option<Relay>,
+)> {
+    let transport = build_base_transport().await?;
+    if !cfg.is_enabled() {
+        return Ok((upgrade_transport(transport, keypair), None));
+    }
+
+    let relay_cfg = Libp2pRelayConfig {
+        // Only relay to the peers connected to the server, e.g., the ones listening via it.
+        actively_connect_to_dst_nodes: false,
+        ..Default::default()
+    };
+    let (transport, relay) = new_transport_and_behaviour(relay_cfg, transport);
+    Ok((upgrade_transport(transport, keypair), Some(relay)))
+}
+
+/// The address to listen on via `server`.
+pub fn relayed_listen_addr(server: &PeerConfig) -> Multiaddr {
+    server
+        .address
+        .clone()
+        .with(Protocol::P2p(server.peer_id.into()))
+        .with(Protocol::P2pCircuit)
+}
+
+/// The address to dial `peer_id` via `server`.
+pub fn relayed_addr(server: &PeerConfig, peer_id: PeerId) -> Multiaddr {
+    relayed_listen_addr(server).with(Protocol::P2p(peer_id.into()))
+}
+
+#[cfg(test)]
+mod tests;
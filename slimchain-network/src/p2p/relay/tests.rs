@@ -0,0 +1,154 @@
+use super::*;
+use crate::p2p::{
+    control::{Control, Shutdown, Swarmer},
+    pubsub::{PubSub, PubSubEvent, PubSubTopic},
+};
+use futures::{channel::mpsc, prelude::*};
+use libp2p::{
+    swarm::{toggle::Toggle, NetworkBehaviourEventProcess},
+    NetworkBehaviour,
+};
+use serial_test::serial;
+use slimchain_utils::init_tracing_for_test;
+use std::time::Duration;
+
+type TestEvent = PubSubEvent<String, String>;
+
+#[derive(NetworkBehaviour)]
+struct RelayTest {
+    relay: Toggle<Relay>,
+    pubsub: PubSub<String, String>,
+    #[behaviour(ignore)]
+    event_tx: mpsc::UnboundedSender<TestEvent>,
+}
+
+impl NetworkBehaviourEventProcess<()> for RelayTest {
+    fn inject_event(&mut self, _: ()) {}
+}
+
+impl NetworkBehaviourEventProcess<TestEvent> for RelayTest {
+    fn inject_event(&mut self, event: TestEvent) {
+        self.event_tx.unbounded_send(event).ok();
+    }
+}
+
+#[async_trait::async_trait]
+impl Shutdown for RelayTest {
+    async fn shutdown(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+async fn create_node(
+    cfg: &RelayConfig,
+    listen: bool,
+) -> (
+    PeerId,
+    Option<Multiaddr>,
+    Control<RelayTest>,
+    mpsc::UnboundedReceiver<TestEvent>,
+) {
+    let keypair = Keypair::generate_ed25519();
+    let (transport, relay) = build_transport_with_relay(&keypair, cfg).await.unwrap();
+    let (event_tx, event_rx) = mpsc::unbounded();
+    let pubsub = PubSub::new(keypair.clone(), &[PubSubTopic::TxProposal], &[]).unwrap();
+    let behaviour = RelayTest {
+        relay: relay.into(),
+        pubsub,
+        event_tx,
+    };
+    let mut swarmer = Swarmer::with_transport(keypair.clone(), transport, behaviour);
+    let address = if listen {
+        Some(swarmer.listen_on_str("/ip4/127.0.0.1/tcp/0").await.unwrap())
+    } else {
+        None
+    };
+    swarmer.listen_via_relays(cfg).unwrap();
+    let ctrl = swarmer.spawn();
+    (keypair.public().into_peer_id(), address, ctrl, event_rx)
+}
+
+#[tokio::test]
+#[serial]
+async fn test_relay() {
+    let _guard = init_tracing_for_test();
+
+    let server_cfg = RelayConfig {
+        server: true,
+        ..Default::default()
+    };
+    let (relay_peer, relay_addr, ctrl0, _event_rx0) = create_node(&server_cfg, true).await;
+    let server = PeerConfig::new(relay_peer, relay_addr.unwrap());
+
+    let subscriber_cfg = RelayConfig {
+        servers: vec![server.clone()],
+        ..Default::default()
+    };
+    let (peer1, _addr1, mut ctrl1, mut event_rx1) = create_node(&subscriber_cfg, true).await;
+
+    // The publisher behind NAT is only reachable via the relay server.
+    let publisher_cfg = RelayConfig {
+        servers: vec![server.clone()],
+        listen_via_relay: true,
+        ..Default::default()
+    };
+    let (peer2, _addr2, mut ctrl2, _event_rx2) = create_node(&publisher_cfg, false).await;
+
+    let address = relayed_addr(&server, peer2);
+    let mut connected = false;
+    for _ in 0..50 {
+        let address = address.clone();
+        connected = ctrl1
+            .call(move |swarm| {
+                if !swarm.is_connected(&peer2) {
+                    swarm.dial_addr(address).ok();
+                }
+                swarm.is_connected(&peer2)
+            })
+            .await
+            .unwrap();
+        if connected {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert!(connected);
+
+    // Wait for the publisher to learn the subscription.
+    loop {
+        let subscribed = ctrl2
+            .call(move |swarm| {
+                swarm
+                    .behaviour()
+                    .pubsub
+                    .known_peers()
+                    .get(&PubSubTopic::TxProposal)
+                    .map_or(false, |peers| peers.contains(&peer1))
+            })
+            .await
+            .unwrap();
+        if subscribed {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+
+    ctrl2
+        .call(|swarm| {
+            swarm
+                .behaviour_mut()
+                .pubsub
+                .publish_tx_proposal(&"tx".to_string())
+        })
+        .await
+        .unwrap()
+        .unwrap();
+    match tokio::time::timeout(Duration::from_secs(5), event_rx1.next()).await {
+        Ok(Some(PubSubEvent::TxProposal(input, _))) => assert_eq!("tx", input),
+        e => panic!("Unexpected event: {:?}", e),
+    }
+
+    ctrl0.shutdown().await.unwrap();
+    ctrl1.shutdown().await.unwrap();
+    ctrl2.shutdown().await.unwrap();
+}
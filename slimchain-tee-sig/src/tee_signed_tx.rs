@@ -53,6 +53,14 @@ impl TxTrait for TEESignedTx {
         self.raw_tx.tx_writes()
     }
 
+    fn gas_used(&self) -> u64 {
+        self.raw_tx.gas_used()
+    }
+
+    fn fee(&self) -> u64 {
+        self.raw_tx.fee()
+    }
+
     fn verify_sig(&self) -> Result<()> {
         self.attest_report
             .verify(&self.pk_sig.public().as_bytes()[..])?;
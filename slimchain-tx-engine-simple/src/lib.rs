@@ -2,12 +2,12 @@ use slimchain_common::{
     basic::{AccountData, Address, BlockHeight, Code, Nonce, StateKey, StateValue, H256},
     ed25519::Keypair,
     error::Result,
-    tx::{RawTx, SignedTx},
-    tx_req::SignedTxRequest,
+    tx::{RawTx, SignedTx, TxTrait},
+    tx_req::{SignedTxRequest, TxRequest, TxSigConfig},
 };
 use slimchain_merkle_trie::prelude::*;
 use slimchain_tx_engine::{TxEngineWorker, TxTaskId};
-use slimchain_tx_executor::execute_tx;
+use slimchain_tx_executor::{execute_tx, execute_tx_req, ExecuteOutput};
 use slimchain_tx_state::{
     trie_view::{AccountTrieView, StateTrieView},
     TxStateView,
@@ -59,6 +59,16 @@ impl<'a, StateView: TxStateView + ?Sized> slimchain_tx_executor::Backend
     }
 }
 
+/// Re-execute the tx on the state it was executed on and return the gas used.
+pub fn reexecute_gas_used<Tx: TxTrait>(
+    state_view: &(impl TxStateView + ?Sized),
+    tx: &Tx,
+) -> Result<u64> {
+    let backend = ExecutorBackend::new(state_view, tx.tx_state_root());
+    let output = execute_tx_req(tx.tx_caller(), tx.tx_input().clone(), &backend)?;
+    Ok(output.gas_used)
+}
+
 pub struct SimpleTxEngineWorker {
     keypair: Keypair,
 }
@@ -75,8 +85,36 @@ impl TxEngineWorker for SimpleTxEngineWorker {
         signed_tx_req: SignedTxRequest,
     ) -> Result<Self::Output> {
         let backend = ExecutorBackend::new(state_view.as_ref(), state_root);
-        let output = execute_tx(signed_tx_req, &backend)?;
+        let output = execute_tx(signed_tx_req, &TxSigConfig::get(), &backend)?;
+        Ok(self.sign_output(output, block_height, state_root))
+    }
+
+    fn reexecute(
+        &self,
+        _id: TxTaskId,
+        block_height: BlockHeight,
+        state_view: Arc<dyn TxStateView + Sync + Send>,
+        state_root: H256,
+        caller: Address,
+        tx_req: TxRequest,
+    ) -> Result<Self::Output> {
+        let backend = ExecutorBackend::new(state_view.as_ref(), state_root);
+        let output = execute_tx_req(caller, tx_req, &backend)?;
+        Ok(self.sign_output(output, block_height, state_root))
+    }
+}
 
+impl SimpleTxEngineWorker {
+    pub fn new(keypair: Keypair) -> Self {
+        Self { keypair }
+    }
+
+    fn sign_output(
+        &self,
+        output: ExecuteOutput,
+        block_height: BlockHeight,
+        state_root: H256,
+    ) -> SignedTx {
         let raw_tx = RawTx {
             caller: output.caller,
             input: output.input,
@@ -84,15 +122,11 @@ impl TxEngineWorker for SimpleTxEngineWorker {
             state_root,
             reads: output.reads.to_set(),
             writes: output.writes,
+            gas_used: output.gas_used,
+            fee: output.fee,
         };
 
-        Ok(raw_tx.sign(&self.keypair))
-    }
-}
-
-impl SimpleTxEngineWorker {
-    pub fn new(keypair: Keypair) -> Self {
-        Self { keypair }
+        raw_tx.sign(&self.keypair)
     }
 }
 
@@ -140,7 +174,7 @@ mod tests {
             nonce: U256::from(0).into(),
             code: contract.code().clone(),
         };
-        let signed_tx_req1 = tx_req1.sign(&keypair);
+        let signed_tx_req1 = tx_req1.sign(&keypair, 0);
 
         let state_root = states.state_root();
         let task1 = TxTask::new(
@@ -161,6 +195,8 @@ mod tests {
         assert_eq!(task_engine.remaining_tasks(), 0);
         write_trie1.verify(states.state_root()).unwrap();
         tx1.verify_sig().unwrap();
+        assert!(tx1.gas_used() > 0);
+        assert_eq!(tx1.gas_used(), reexecute_gas_used(&*states, &tx1).unwrap());
 
         assert!(tx1
             .raw_tx
@@ -182,7 +218,7 @@ mod tests {
                 )
                 .unwrap(),
         };
-        let signed_tx_req2 = tx_req2.sign(&keypair);
+        let signed_tx_req2 = tx_req2.sign(&keypair, 0);
 
         let state_root = states.state_root();
         let task2 = TxTask::new(
@@ -3,7 +3,7 @@ use slimchain_common::{
     basic::{Address, BlockHeight, Code, Nonce, StateKey, StateValue, H256, U256},
     error::{anyhow, ensure, Result},
     tx::{RawTx, SignedTx},
-    tx_req::SignedTxRequest,
+    tx_req::{SignedTxRequest, TxSigConfig},
 };
 use slimchain_tx_state::TxReadProof;
 use std::prelude::v1::*;
@@ -12,40 +12,40 @@ use std::{mem::MaybeUninit, slice};
 extern "C" {
     fn ocall_get_nonce(
         retval: *mut i32,
-        id: u32,
+        id: u64,
         acc_address: *const u8,
         nonce: *mut u8,
     ) -> sgx_status_t;
     fn ocall_get_code_len(
         retval: *mut i32,
-        id: u32,
+        id: u64,
         acc_address: *const u8,
         code_len: *mut usize,
     ) -> sgx_status_t;
     fn ocall_get_code(
         retval: *mut i32,
-        id: u32,
+        id: u64,
         acc_address: *const u8,
         code: *mut u8,
         code_len: usize,
     ) -> sgx_status_t;
     fn ocall_get_value(
         retval: *mut i32,
-        id: u32,
+        id: u64,
         acc_address: *const u8,
         key: *const u8,
         value: *mut u8,
     ) -> sgx_status_t;
-    fn ocall_get_read_proof_len(retval: *mut i32, id: u32, proof_len: *mut usize) -> sgx_status_t;
+    fn ocall_get_read_proof_len(retval: *mut i32, id: u64, proof_len: *mut usize) -> sgx_status_t;
     fn ocall_get_read_proof(
         retval: *mut i32,
-        id: u32,
+        id: u64,
         proof: *mut u8,
         proof_len: usize,
     ) -> sgx_status_t;
     fn ocall_return_result(
         retval: *mut i32,
-        id: u32,
+        id: u64,
         result: *const u8,
         result_len: usize,
     ) -> sgx_status_t;
@@ -53,8 +53,10 @@ extern "C" {
 
 #[no_mangle]
 pub unsafe extern "C" fn ecall_exec_tx(
-    id: u32,
+    id: u64,
     block_height: u64,
+    chain_id: u64,
+    accept_legacy_sig: u8,
     state_root: *const u8,
     signed_tx_req: *const u8,
     req_len: usize,
@@ -74,7 +76,11 @@ pub unsafe extern "C" fn ecall_exec_tx(
             }
         }
     };
-    let signed_tx = match exec_tx(id, block_height.into(), state_root, signed_tx_req) {
+    let sig_cfg = TxSigConfig {
+        chain_id,
+        accept_legacy: accept_legacy_sig != 0,
+    };
+    let signed_tx = match exec_tx(id, block_height.into(), state_root, &sig_cfg, signed_tx_req) {
         Ok(tx) => tx,
         Err(e) => {
             eprintln!("[Enclave Error] Failed to execute tx.");
@@ -106,7 +112,7 @@ pub unsafe extern "C" fn ecall_exec_tx(
 }
 
 struct Backend {
-    id: u32,
+    id: u64,
 }
 
 impl slimchain_tx_executor::Backend for Backend {
@@ -214,7 +220,7 @@ impl slimchain_tx_executor::Backend for Backend {
     }
 }
 
-fn get_read_proof(id: u32) -> Result<TxReadProof> {
+fn get_read_proof(id: u64) -> Result<TxReadProof> {
     let mut retval: i32 = 0;
 
     let mut proof_len: usize = 0;
@@ -250,14 +256,15 @@ fn get_read_proof(id: u32) -> Result<TxReadProof> {
 }
 
 fn exec_tx(
-    id: u32,
+    id: u64,
     block_height: BlockHeight,
     state_root: H256,
+    sig_cfg: &TxSigConfig,
     signed_tx_req: SignedTxRequest,
 ) -> Result<SignedTx> {
     let backend = Backend { id };
 
-    let exec_output = slimchain_tx_executor::execute_tx(signed_tx_req, &backend)?;
+    let exec_output = slimchain_tx_executor::execute_tx(signed_tx_req, sig_cfg, &backend)?;
     let read_proof = get_read_proof(id)?;
     read_proof.verify(&exec_output.reads, state_root)?;
 
@@ -268,6 +275,8 @@ fn exec_tx(
         state_root,
         reads: exec_output.reads.to_set(),
         writes: exec_output.writes,
+        gas_used: exec_output.gas_used,
+        fee: exec_output.fee,
     };
 
     let signed_tx = raw_tx.sign(crate::get_key_pair());
@@ -8,7 +8,7 @@ use sgx_types::*;
 use slimchain_common::{
     basic::{BlockHeight, H256},
     error::{ensure, Result},
-    tx_req::SignedTxRequest,
+    tx_req::{SignedTxRequest, TxSigConfig},
 };
 use slimchain_tee_sig::AttestationReport;
 use slimchain_tx_engine::TxTaskId;
@@ -30,6 +30,7 @@ pub(crate) fn exec_tx(
     signed_tx_req: &SignedTxRequest,
 ) -> Result<()> {
     let mut ret: i32 = 0;
+    let sig_cfg = TxSigConfig::get();
     let tx_req_data = postcard::to_allocvec(signed_tx_req)?;
     let sgx_ret = unsafe {
         ffi::ecall_exec_tx(
@@ -37,6 +38,8 @@ pub(crate) fn exec_tx(
             &mut ret as *mut _,
             id.into(),
             block_height.into(),
+            sig_cfg.chain_id,
+            sig_cfg.accept_legacy as u8,
             state_root.as_bytes().as_ptr(),
             tx_req_data.as_ptr(),
             tx_req_data.len(),
@@ -35,7 +35,7 @@ macro_rules! try_run {
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn ocall_get_nonce(id: u32, acc_address: *const u8, nonce: *mut u8) -> i32 {
+pub unsafe extern "C" fn ocall_get_nonce(id: u64, acc_address: *const u8, nonce: *mut u8) -> i32 {
     let acc_address = get_address_from_raw(acc_address);
     let n = try_run!(get_nonce(id.into(), acc_address));
     let dst = slice::from_raw_parts_mut(nonce, 32);
@@ -51,7 +51,7 @@ fn get_nonce(id: TxTaskId, acc_address: Address) -> Result<Nonce> {
 
 #[no_mangle]
 pub unsafe extern "C" fn ocall_get_code_len(
-    id: u32,
+    id: u64,
     acc_address: *const u8,
     code_len: *mut usize,
 ) -> i32 {
@@ -68,7 +68,7 @@ fn get_code_len(id: TxTaskId, acc_address: Address) -> Result<usize> {
 
 #[no_mangle]
 pub unsafe extern "C" fn ocall_get_code(
-    id: u32,
+    id: u64,
     acc_address: *const u8,
     code: *mut u8,
     code_len: usize,
@@ -88,7 +88,7 @@ fn get_code(id: TxTaskId, acc_address: Address) -> Result<Code> {
 
 #[no_mangle]
 pub unsafe extern "C" fn ocall_get_value(
-    id: u32,
+    id: u64,
     acc_address: *const u8,
     key: *const u8,
     value: *mut u8,
@@ -107,7 +107,7 @@ fn get_value(id: TxTaskId, acc_address: Address, key: StateKey) -> Result<StateV
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn ocall_get_read_proof_len(id: u32, proof_len: *mut usize) -> i32 {
+pub unsafe extern "C" fn ocall_get_read_proof_len(id: u64, proof_len: *mut usize) -> i32 {
     *proof_len = try_run!(get_read_proof_len(id.into()));
     0
 }
@@ -119,7 +119,7 @@ fn get_read_proof_len(id: TxTaskId) -> Result<usize> {
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn ocall_get_read_proof(id: u32, proof: *mut u8, proof_len: usize) -> i32 {
+pub unsafe extern "C" fn ocall_get_read_proof(id: u64, proof: *mut u8, proof_len: usize) -> i32 {
     let mut task_state = try_run!(crate::engine::TaskState::get_task_state(id.into()));
     let p = try_run!(task_state.get_read_proof());
     let len = min(proof_len, p.len());
@@ -128,7 +128,7 @@ pub unsafe extern "C" fn ocall_get_read_proof(id: u32, proof: *mut u8, proof_len
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn ocall_return_result(id: u32, result: *const u8, result_len: usize) -> i32 {
+pub unsafe extern "C" fn ocall_return_result(id: u64, result: *const u8, result_len: usize) -> i32 {
     let mut task_state = try_run!(crate::engine::TaskState::get_task_state(id.into()));
     let signed_tx = {
         let buf = slice::from_raw_parts(result, result_len);
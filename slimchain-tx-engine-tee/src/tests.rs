@@ -41,7 +41,7 @@ async fn test() {
         nonce: U256::from(0).into(),
         code: contract.code().clone(),
     };
-    let signed_tx_req1 = tx_req1.sign(&keypair);
+    let signed_tx_req1 = tx_req1.sign(&keypair, 0);
 
     let state_root = states.state_root();
     let task1 = TxTask::new(
@@ -81,7 +81,7 @@ async fn test() {
             )
             .unwrap(),
     };
-    let signed_tx_req2 = tx_req2.sign(&keypair);
+    let signed_tx_req2 = tx_req2.sign(&keypair, 0);
 
     let state_root = states.state_root();
     let task2 = TxTask::new(
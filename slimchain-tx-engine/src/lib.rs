@@ -1,35 +1,82 @@
 #[macro_use]
 extern crate tracing;
 
-use crossbeam::{
-    channel,
-    deque::{Injector, Stealer, Worker},
-    queue::ArrayQueue,
-    sync::{Parker, Unparker},
-    utils::Backoff,
-};
+use once_cell::sync::Lazy;
+use pool::WorkerPool;
 use slimchain_common::{
-    basic::{BlockHeight, H256},
-    create_id_type_u32,
-    error::Result,
+    basic::{Address, BlockHeight, H256},
+    create_id_type_u64,
+    error::{bail, Result},
     tx::TxTrait,
-    tx_req::SignedTxRequest,
+    tx_req::{SignedTxRequest, TxRequest},
 };
 use slimchain_tx_state::{TxProposal, TxStateView, TxWriteSetTrie};
-use slimchain_utils::{record_event, record_time};
+use slimchain_utils::{
+    prometheus::{Counter, Gauge, Histogram, DEFAULT_BUCKETS, REGISTRY},
+    record_event, record_time,
+};
 use std::{
-    iter,
     sync::{
         atomic::{AtomicBool, AtomicUsize, Ordering},
         Arc,
     },
     task::{Context, Poll},
-    thread::{self, JoinHandle},
-    time::{Duration, Instant},
+    time::Instant,
 };
 use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
 
-create_id_type_u32!(TxTaskId);
+mod pool;
+pub mod verify;
+
+create_id_type_u64!(TxTaskId);
+
+static TX_ENGINE_METRICS: Lazy<TxEngineMetrics> = Lazy::new(TxEngineMetrics::new);
+
+struct TxEngineMetrics {
+    tasks: Arc<Counter>,
+    exec_errors: Arc<Counter>,
+    write_set_errors: Arc<Counter>,
+    remaining_tasks: Arc<Gauge>,
+    exec_time: Arc<Histogram>,
+}
+
+impl TxEngineMetrics {
+    fn new() -> Self {
+        let failed_tasks = |reason| {
+            REGISTRY.counter(
+                "slimchain_tx_engine_failed_tasks_total",
+                "The tx tasks discarded by the reason.",
+                &[("reason", reason)],
+            )
+        };
+        Self {
+            tasks: REGISTRY.counter(
+                "slimchain_tx_engine_tasks_total",
+                "The tx tasks pushed to the engine.",
+                &[],
+            ),
+            exec_errors: failed_tasks("tx_exec_error"),
+            write_set_errors: failed_tasks("tx_exec_error_write_set_failure"),
+            remaining_tasks: REGISTRY.gauge(
+                "slimchain_tx_engine_remaining_tasks",
+                "The tx tasks whose results are not taken yet.",
+                &[],
+            ),
+            exec_time: REGISTRY.histogram(
+                "slimchain_tx_engine_exec_seconds",
+                "The time to execute a tx task.",
+                &DEFAULT_BUCKETS,
+                &[],
+            ),
+        }
+    }
+}
+
+/// Decrease `remaining_tasks` when a task is done or discarded.
+fn finish_task(remaining_tasks: &AtomicUsize) {
+    let remaining = remaining_tasks.fetch_sub(1, Ordering::SeqCst) - 1;
+    TX_ENGINE_METRICS.remaining_tasks.set(remaining as f64);
+}
 
 pub trait TxEngineWorker: Send {
     type Output: TxTrait;
@@ -42,6 +89,20 @@ pub trait TxEngineWorker: Send {
         state_root: H256,
         signed_tx_req: SignedTxRequest,
     ) -> Result<Self::Output>;
+
+    /// Re-execute the tx request from `caller` already included in a block, whose signature is
+    /// not kept, e.g., when replaying the blocks for auditing. Not every engine supports it.
+    fn reexecute(
+        &self,
+        _id: TxTaskId,
+        _block_height: BlockHeight,
+        _state_view: Arc<dyn TxStateView + Sync + Send>,
+        _state_root: H256,
+        _caller: Address,
+        _tx_req: TxRequest,
+    ) -> Result<Self::Output> {
+        bail!("The tx engine does not support the re-execution.");
+    }
 }
 
 pub struct TxTask {
@@ -70,6 +131,11 @@ impl TxTask {
     pub fn get_id(&self) -> TxTaskId {
         self.id
     }
+
+    /// The fee signed in the tx request, which the tx proposal carries on.
+    pub fn fee(&self) -> u64 {
+        self.signed_tx_req.fee
+    }
 }
 
 pub struct TxTaskOutput<Tx: TxTrait> {
@@ -78,11 +144,8 @@ pub struct TxTaskOutput<Tx: TxTrait> {
 }
 
 pub struct TxEngine<Tx: TxTrait + 'static> {
-    task_queue: Arc<Injector<TxTask>>,
+    pool: WorkerPool<TxTask>,
     result_rx: UnboundedReceiver<TxTaskOutput<Tx>>,
-    unparker_queue: Arc<ArrayQueue<Unparker>>,
-    shutdown_flag: Arc<AtomicBool>,
-    worker_threads: Vec<JoinHandle<()>>,
     remaining_tasks: Arc<AtomicUsize>,
 }
 
@@ -94,47 +157,20 @@ impl<Tx: TxTrait + 'static> TxEngine<Tx> {
     ) -> Self {
         info!("Spawning TxEngine workers in {} threads.", threads);
 
-        let task_queue = Arc::new(Injector::new());
         let (result_tx, result_rx) = unbounded_channel();
-        let unparker_queue = Arc::new(ArrayQueue::new(threads));
-        let shutdown_flag = Arc::new(AtomicBool::new(false));
         let remaining_tasks = Arc::new(AtomicUsize::new(0));
-
-        let mut workers: Vec<_> = (0..threads)
-            .map(|_| {
-                TxEngineWorkerInstance::new(
-                    worker_factory(),
-                    task_queue.clone(),
-                    threads - 1,
-                    result_tx.clone(),
-                    unparker_queue.clone(),
-                    shutdown_flag.clone(),
-                    remaining_tasks.clone(),
-                )
-            })
-            .collect();
-
-        let stealers: Vec<_> = workers.iter().map(|w| w.get_local_stealer()).collect();
-
-        for (i, worker) in workers.iter_mut().enumerate() {
-            for (j, stealer) in stealers.iter().enumerate() {
-                if i != j {
-                    worker.add_global_stealer(stealer.clone());
-                }
-            }
-        }
-
-        let worker_threads: Vec<_> = workers
-            .into_iter()
-            .map(|w| thread::spawn(move || w.run()))
-            .collect();
+        let pool = WorkerPool::new("TxEngine", threads, || {
+            let executor = TxTaskExecutor {
+                worker: worker_factory(),
+                result_tx: result_tx.clone(),
+                remaining_tasks: remaining_tasks.clone(),
+            };
+            move |task| executor.execute(task)
+        });
 
         Self {
-            task_queue,
+            pool,
             result_rx,
-            unparker_queue,
-            shutdown_flag,
-            worker_threads,
             remaining_tasks,
         }
     }
@@ -144,11 +180,10 @@ impl<Tx: TxTrait + 'static> TxEngine<Tx> {
     }
 
     pub fn push_task(&self, task: TxTask) {
-        self.remaining_tasks.fetch_add(1, Ordering::SeqCst);
-        self.task_queue.push(task);
-        if let Some(unparker) = self.unparker_queue.pop() {
-            unparker.unpark();
-        }
+        let remaining = self.remaining_tasks.fetch_add(1, Ordering::SeqCst) + 1;
+        TX_ENGINE_METRICS.tasks.inc();
+        TX_ENGINE_METRICS.remaining_tasks.set(remaining as f64);
+        self.pool.push(task);
     }
 
     pub async fn pop_result(&mut self) -> TxTaskOutput<Tx> {
@@ -157,14 +192,14 @@ impl<Tx: TxTrait + 'static> TxEngine<Tx> {
             .recv()
             .await
             .expect("Failed to get the result");
-        self.remaining_tasks.fetch_sub(1, Ordering::SeqCst);
+        finish_task(&self.remaining_tasks);
         result
     }
 
     pub fn poll_result(&mut self, cx: &mut Context<'_>) -> Poll<TxTaskOutput<Tx>> {
         match self.result_rx.poll_recv(cx) {
             Poll::Ready(result) => {
-                self.remaining_tasks.fetch_sub(1, Ordering::SeqCst);
+                finish_task(&self.remaining_tasks);
                 Poll::Ready(result.expect("Failed to get the result"))
             }
             Poll::Pending => Poll::Pending,
@@ -172,175 +207,68 @@ impl<Tx: TxTrait + 'static> TxEngine<Tx> {
     }
 
     pub fn shutdown_token(&self) -> Arc<AtomicBool> {
-        self.shutdown_flag.clone()
+        self.pool.shutdown_token()
     }
 
     pub fn shutdown(&self) {
-        self.shutdown_flag.store(true, Ordering::Release);
+        self.pool.shutdown();
     }
 
     pub fn is_shutdown(&self) -> bool {
-        self.shutdown_flag.load(Ordering::Acquire)
-    }
-}
-
-impl<Tx: TxTrait + 'static> Drop for TxEngine<Tx> {
-    #[tracing::instrument(name = "tx_engine_drop", skip(self))]
-    fn drop(&mut self) {
-        self.shutdown_flag.store(true, Ordering::Release);
-
-        let unparker_queue = self.unparker_queue.clone();
-        let (tx, rx) = channel::bounded(1);
-        let unparker_thread = thread::spawn(move || loop {
-            while let Some(unpacker) = unparker_queue.pop() {
-                unpacker.unpark();
-            }
-
-            if rx.try_recv().is_ok() {
-                break;
-            }
-
-            thread::sleep(Duration::from_millis(50));
-        });
-
-        info!("Waiting TxEngine workers to be shutdown.");
-        for w in self.worker_threads.drain(..) {
-            w.join()
-                .expect("TxEngine: Failed to join the worker thread.");
-        }
-
-        tx.send(()).ok();
-        unparker_thread
-            .join()
-            .expect("TxEngine: Failed to join the unpacker thread.");
-        info!("TxEngine is shutdown.");
+        self.pool.is_shutdown()
     }
 }
 
-struct TxEngineWorkerInstance<Tx: TxTrait> {
-    global_task_queue: Arc<Injector<TxTask>>,
-    local_task_queue: Worker<TxTask>,
-    stealers: Vec<Stealer<TxTask>>,
+struct TxTaskExecutor<Tx: TxTrait> {
+    worker: Box<dyn TxEngineWorker<Output = Tx>>,
     result_tx: UnboundedSender<TxTaskOutput<Tx>>,
-    unparker_queue: Arc<ArrayQueue<Unparker>>,
-    shutdown_flag: Arc<AtomicBool>,
     remaining_tasks: Arc<AtomicUsize>,
-    worker: Box<dyn TxEngineWorker<Output = Tx>>,
 }
 
-impl<Tx: TxTrait> TxEngineWorkerInstance<Tx> {
-    fn new(
-        worker: Box<dyn TxEngineWorker<Output = Tx>>,
-        global_task_queue: Arc<Injector<TxTask>>,
-        stealer_num: usize,
-        result_tx: UnboundedSender<TxTaskOutput<Tx>>,
-        unparker_queue: Arc<ArrayQueue<Unparker>>,
-        shutdown_flag: Arc<AtomicBool>,
-        remaining_tasks: Arc<AtomicUsize>,
-    ) -> Self {
-        let local_task_queue = Worker::new_fifo();
-
-        Self {
-            global_task_queue,
-            local_task_queue,
-            stealers: Vec::with_capacity(stealer_num),
-            result_tx,
-            unparker_queue,
-            shutdown_flag,
-            remaining_tasks,
-            worker,
-        }
-    }
-
-    fn get_local_stealer(&self) -> Stealer<TxTask> {
-        self.local_task_queue.stealer()
-    }
-
-    fn add_global_stealer(&mut self, stealer: Stealer<TxTask>) {
-        self.stealers.push(stealer);
-    }
-
-    fn find_task(&self) -> Option<TxTask> {
-        self.local_task_queue.pop().or_else(|| {
-            iter::repeat_with(|| {
-                self.global_task_queue
-                    .steal_batch_and_pop(&self.local_task_queue)
-                    .or_else(|| self.stealers.iter().map(|s| s.steal()).collect())
-            })
-            .find(|s| !s.is_retry())
-            .and_then(|s| s.success())
-        })
-    }
-
-    fn wait_until_task(&self) -> Option<TxTask> {
-        if self.shutdown_flag.load(Ordering::Acquire) {
-            return None;
-        }
-
-        let backoff = Backoff::new();
-        loop {
-            match self.find_task() {
-                Some(task) => return Some(task),
-                None => {
-                    if backoff.is_completed() {
-                        if self.shutdown_flag.load(Ordering::Acquire) {
-                            return None;
-                        }
-
-                        let parker = Parker::new();
-                        self.unparker_queue
-                            .push(parker.unparker().clone())
-                            .expect("TxEngine: Failed to send unparker.");
-                        parker.park();
-                    } else {
-                        backoff.snooze();
-                    }
-                }
+impl<Tx: TxTrait> TxTaskExecutor<Tx> {
+    fn execute(&self, task: TxTask) {
+        let span = debug_span!("execute_task", id = task.id.0);
+        let _enter = span.enter();
+
+        let begin = Instant::now();
+        let task_id = task.get_id();
+        let tx_id = task.signed_tx_req.id();
+        let state_view = task.state_view.clone();
+        let (block_height, state_root) = (task.block_state_fn)();
+        let tx = match self.worker.execute(
+            task.id,
+            block_height,
+            task.state_view,
+            state_root,
+            task.signed_tx_req,
+        ) {
+            Ok(output) => output,
+            Err(e) => {
+                error!("Failed to execute task. Error: {}", e);
+                record_event!("discard_tx", "tx_id": tx_id, "reason": "tx_exec_error", "detail": std::format!("{}", e));
+                TX_ENGINE_METRICS.exec_errors.inc();
+                finish_task(&self.remaining_tasks);
+                return;
             }
-        }
-    }
-
-    fn run(&self) {
-        while let Some(task) = self.wait_until_task() {
-            let span = debug_span!("execute_task", id = task.id.0);
-            let _enter = span.enter();
-
-            let begin = Instant::now();
-            let task_id = task.get_id();
-            let tx_id = task.signed_tx_req.id();
-            let state_view = task.state_view.clone();
-            let (block_height, state_root) = (task.block_state_fn)();
-            let tx = match self.worker.execute(
-                task.id,
-                block_height,
-                task.state_view,
-                state_root,
-                task.signed_tx_req,
-            ) {
-                Ok(output) => output,
-                Err(e) => {
-                    error!("Failed to execute task. Error: {}", e);
-                    record_event!("discard_tx", "tx_id": tx_id, "reason": "tx_exec_error", "detail": std::format!("{}", e));
-                    self.remaining_tasks.fetch_sub(1, Ordering::SeqCst);
-                    continue;
-                }
-            };
-            let write_trie = match TxWriteSetTrie::new(&state_view, state_root, tx.tx_writes()) {
-                Ok(trie) => trie,
-                Err(e) => {
-                    error!("Failed to create TxWriteSetTrie. Error: {}", e);
-                    record_event!("discard_tx", "tx_id": tx_id, "reason": "tx_exec_error_write_set_failure", "detail": std::format!("{}", e));
-                    self.remaining_tasks.fetch_sub(1, Ordering::SeqCst);
-                    continue;
-                }
-            };
-            record_time!("exec_time", Instant::now() - begin, "task_id": task_id.0, "tx_id": tx_id, "exec_block_height": block_height.0);
-            self.result_tx
-                .send(TxTaskOutput {
-                    task_id,
-                    tx_proposal: TxProposal::new(tx, write_trie),
-                })
-                .ok();
-        }
+        };
+        let write_trie = match TxWriteSetTrie::new(&state_view, state_root, tx.tx_writes()) {
+            Ok(trie) => trie,
+            Err(e) => {
+                error!("Failed to create TxWriteSetTrie. Error: {}", e);
+                record_event!("discard_tx", "tx_id": tx_id, "reason": "tx_exec_error_write_set_failure", "detail": std::format!("{}", e));
+                TX_ENGINE_METRICS.write_set_errors.inc();
+                finish_task(&self.remaining_tasks);
+                return;
+            }
+        };
+        let exec_time = Instant::now() - begin;
+        TX_ENGINE_METRICS.exec_time.observe_duration(exec_time);
+        record_time!("exec_time", exec_time, "task_id": task_id.0, "tx_id": tx_id, "exec_block_height": block_height.0);
+        self.result_tx
+            .send(TxTaskOutput {
+                task_id,
+                tx_proposal: TxProposal::new(tx, write_trie),
+            })
+            .ok();
     }
 }
@@ -0,0 +1,218 @@
+use crossbeam::{
+    channel,
+    deque::{Injector, Stealer, Worker},
+    queue::ArrayQueue,
+    sync::{Parker, Unparker},
+    utils::Backoff,
+};
+use std::{
+    iter,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+/// The work stealing threads shared by [`TxEngine`](crate::TxEngine) and
+/// [`VerifyEngine`](crate::verify::VerifyEngine). Each thread takes the tasks from its local
+/// queue, the global queue or the other threads, and parks once none is left.
+pub(crate) struct WorkerPool<T: Send + 'static> {
+    name: &'static str,
+    task_queue: Arc<Injector<T>>,
+    unparker_queue: Arc<ArrayQueue<Unparker>>,
+    shutdown_flag: Arc<AtomicBool>,
+    worker_threads: Vec<JoinHandle<()>>,
+}
+
+impl<T: Send + 'static> WorkerPool<T> {
+    /// Spawn `threads` threads, each of which runs the tasks by a handler from
+    /// `handler_factory`.
+    pub(crate) fn new<H>(
+        name: &'static str,
+        threads: usize,
+        mut handler_factory: impl FnMut() -> H,
+    ) -> Self
+    where
+        H: FnMut(T) + Send + 'static,
+    {
+        let task_queue = Arc::new(Injector::new());
+        let unparker_queue = Arc::new(ArrayQueue::new(threads));
+        let shutdown_flag = Arc::new(AtomicBool::new(false));
+
+        let mut workers: Vec<_> = (0..threads)
+            .map(|_| {
+                PoolWorker::new(
+                    name,
+                    task_queue.clone(),
+                    threads - 1,
+                    unparker_queue.clone(),
+                    shutdown_flag.clone(),
+                )
+            })
+            .collect();
+
+        let stealers: Vec<_> = workers.iter().map(|w| w.get_local_stealer()).collect();
+
+        for (i, worker) in workers.iter_mut().enumerate() {
+            for (j, stealer) in stealers.iter().enumerate() {
+                if i != j {
+                    worker.add_global_stealer(stealer.clone());
+                }
+            }
+        }
+
+        let worker_threads: Vec<_> = workers
+            .into_iter()
+            .map(|w| {
+                let handler = handler_factory();
+                thread::spawn(move || w.run(handler))
+            })
+            .collect();
+
+        Self {
+            name,
+            task_queue,
+            unparker_queue,
+            shutdown_flag,
+            worker_threads,
+        }
+    }
+
+    pub(crate) fn push(&self, task: T) {
+        self.task_queue.push(task);
+        if let Some(unparker) = self.unparker_queue.pop() {
+            unparker.unpark();
+        }
+    }
+
+    pub(crate) fn shutdown_token(&self) -> Arc<AtomicBool> {
+        self.shutdown_flag.clone()
+    }
+
+    pub(crate) fn shutdown(&self) {
+        self.shutdown_flag.store(true, Ordering::Release);
+    }
+
+    pub(crate) fn is_shutdown(&self) -> bool {
+        self.shutdown_flag.load(Ordering::Acquire)
+    }
+}
+
+impl<T: Send + 'static> Drop for WorkerPool<T> {
+    #[tracing::instrument(name = "worker_pool_drop", skip(self), fields(name = self.name))]
+    fn drop(&mut self) {
+        self.shutdown_flag.store(true, Ordering::Release);
+
+        let unparker_queue = self.unparker_queue.clone();
+        let (tx, rx) = channel::bounded(1);
+        let unparker_thread = thread::spawn(move || loop {
+            while let Some(unpacker) = unparker_queue.pop() {
+                unpacker.unpark();
+            }
+
+            if rx.try_recv().is_ok() {
+                break;
+            }
+
+            thread::sleep(Duration::from_millis(50));
+        });
+
+        info!("Waiting {} workers to be shutdown.", self.name);
+        for w in self.worker_threads.drain(..) {
+            w.join()
+                .unwrap_or_else(|_| panic!("{}: Failed to join the worker thread.", self.name));
+        }
+
+        tx.send(()).ok();
+        unparker_thread
+            .join()
+            .unwrap_or_else(|_| panic!("{}: Failed to join the unpacker thread.", self.name));
+        info!("{} is shutdown.", self.name);
+    }
+}
+
+struct PoolWorker<T> {
+    name: &'static str,
+    global_task_queue: Arc<Injector<T>>,
+    local_task_queue: Worker<T>,
+    stealers: Vec<Stealer<T>>,
+    unparker_queue: Arc<ArrayQueue<Unparker>>,
+    shutdown_flag: Arc<AtomicBool>,
+}
+
+impl<T> PoolWorker<T> {
+    fn new(
+        name: &'static str,
+        global_task_queue: Arc<Injector<T>>,
+        stealer_num: usize,
+        unparker_queue: Arc<ArrayQueue<Unparker>>,
+        shutdown_flag: Arc<AtomicBool>,
+    ) -> Self {
+        let local_task_queue = Worker::new_fifo();
+
+        Self {
+            name,
+            global_task_queue,
+            local_task_queue,
+            stealers: Vec::with_capacity(stealer_num),
+            unparker_queue,
+            shutdown_flag,
+        }
+    }
+
+    fn get_local_stealer(&self) -> Stealer<T> {
+        self.local_task_queue.stealer()
+    }
+
+    fn add_global_stealer(&mut self, stealer: Stealer<T>) {
+        self.stealers.push(stealer);
+    }
+
+    fn find_task(&self) -> Option<T> {
+        self.local_task_queue.pop().or_else(|| {
+            iter::repeat_with(|| {
+                self.global_task_queue
+                    .steal_batch_and_pop(&self.local_task_queue)
+                    .or_else(|| self.stealers.iter().map(|s| s.steal()).collect())
+            })
+            .find(|s| !s.is_retry())
+            .and_then(|s| s.success())
+        })
+    }
+
+    fn wait_until_task(&self) -> Option<T> {
+        if self.shutdown_flag.load(Ordering::Acquire) {
+            return None;
+        }
+
+        let backoff = Backoff::new();
+        loop {
+            match self.find_task() {
+                Some(task) => return Some(task),
+                None => {
+                    if backoff.is_completed() {
+                        if self.shutdown_flag.load(Ordering::Acquire) {
+                            return None;
+                        }
+
+                        let parker = Parker::new();
+                        self.unparker_queue
+                            .push(parker.unparker().clone())
+                            .unwrap_or_else(|_| panic!("{}: Failed to send unparker.", self.name));
+                        parker.park();
+                    } else {
+                        backoff.snooze();
+                    }
+                }
+            }
+        }
+    }
+
+    fn run(&self, mut handler: impl FnMut(T)) {
+        while let Some(task) = self.wait_until_task() {
+            handler(task);
+        }
+    }
+}
@@ -0,0 +1,408 @@
+//! Verify the incoming tx proposals in parallel before the block assembly, i.e., the
+//! signatures, the write tries and whether the state roots apply to the head block.
+
+use crate::pool::WorkerPool;
+use once_cell::sync::Lazy;
+use slimchain_common::{
+    basic::{BlockHeight, H256},
+    create_id_type_u64,
+    tx::TxTrait,
+};
+use slimchain_tx_state::TxProposal;
+use slimchain_utils::{
+    prometheus::{Counter, Histogram, DEFAULT_BUCKETS, REGISTRY},
+    record_time,
+};
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+    time::Instant,
+};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+
+create_id_type_u64!(VerifyTaskId);
+
+static VERIFY_ENGINE_METRICS: Lazy<VerifyEngineMetrics> = Lazy::new(VerifyEngineMetrics::new);
+
+struct VerifyEngineMetrics {
+    tasks: Arc<Counter>,
+    verify_time: Arc<Histogram>,
+}
+
+impl VerifyEngineMetrics {
+    fn new() -> Self {
+        Self {
+            tasks: REGISTRY.counter(
+                "slimchain_verify_engine_tasks_total",
+                "The tx proposals pushed to the verify engine.",
+                &[],
+            ),
+            verify_time: REGISTRY.histogram(
+                "slimchain_verify_engine_verify_seconds",
+                "The time to verify a tx proposal.",
+                &DEFAULT_BUCKETS,
+                &[],
+            ),
+        }
+    }
+
+    fn rejected(&self, reason: &RejectReason) -> Arc<Counter> {
+        REGISTRY.counter(
+            "slimchain_verify_engine_rejected_total",
+            "The tx proposals rejected by the reason.",
+            &[("reason", reason.as_str())],
+        )
+    }
+}
+
+/// Why a tx proposal is rejected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RejectReason {
+    /// The tx is executed on the head block, but its state root is not the one of the head.
+    InvalidStateRoot {
+        expected: H256,
+        actual: H256,
+    },
+    InvalidSig(String),
+    InvalidWriteTrie(String),
+}
+
+impl RejectReason {
+    /// The reason recorded by the `discard_tx` event.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::InvalidStateRoot { .. } => "invalid_state_root",
+            Self::InvalidSig(_) => "invalid_sig",
+            Self::InvalidWriteTrie(_) => "invalid_write_trie",
+        }
+    }
+}
+
+impl fmt::Display for RejectReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidStateRoot { expected, actual } => write!(
+                f,
+                "Invalid state root (expect={}, actual={}).",
+                expected, actual
+            ),
+            Self::InvalidSig(e) => write!(f, "Invalid signature. Error: {}", e),
+            Self::InvalidWriteTrie(e) => write!(f, "Invalid write trie. Error: {}", e),
+        }
+    }
+}
+
+/// The verifier run by each thread of [`VerifyEngine`], like
+/// [`TxEngineWorker`](crate::TxEngineWorker) for [`TxEngine`](crate::TxEngine).
+pub trait ProposalVerifier<Tx: TxTrait>: Send {
+    fn verify(
+        &self,
+        id: VerifyTaskId,
+        head_height: BlockHeight,
+        head_state_root: H256,
+        tx_proposal: &TxProposal<Tx>,
+    ) -> Result<(), RejectReason>;
+}
+
+/// Check the signature and the write trie of a tx proposal, and its state root if it is
+/// executed on the head block. The older state roots are left to the block assembly, which
+/// keeps the recent blocks.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct DefaultProposalVerifier;
+
+impl<Tx: TxTrait> ProposalVerifier<Tx> for DefaultProposalVerifier {
+    fn verify(
+        &self,
+        _id: VerifyTaskId,
+        head_height: BlockHeight,
+        head_state_root: H256,
+        tx_proposal: &TxProposal<Tx>,
+    ) -> Result<(), RejectReason> {
+        let TxProposal { tx, write_trie } = tx_proposal;
+        if tx.tx_block_height() == head_height && tx.tx_state_root() != head_state_root {
+            return Err(RejectReason::InvalidStateRoot {
+                expected: head_state_root,
+                actual: tx.tx_state_root(),
+            });
+        }
+        tx.verify_sig()
+            .map_err(|e| RejectReason::InvalidSig(e.to_string()))?;
+        write_trie
+            .verify(tx.tx_state_root())
+            .map_err(|e| RejectReason::InvalidWriteTrie(e.to_string()))
+    }
+}
+
+pub struct VerifyTask<Tx: TxTrait> {
+    id: VerifyTaskId,
+    tx_proposal: TxProposal<Tx>,
+    head_fn: Box<dyn FnOnce() -> (BlockHeight, H256) + Sync + Send>,
+}
+
+impl<Tx: TxTrait> VerifyTask<Tx> {
+    /// `head_fn` returns the height and the state root of the head block once the task is
+    /// picked by a thread.
+    pub fn new(
+        tx_proposal: TxProposal<Tx>,
+        head_fn: impl FnOnce() -> (BlockHeight, H256) + Sync + Send + 'static,
+    ) -> Self {
+        Self {
+            id: VerifyTaskId::next_id(),
+            tx_proposal,
+            head_fn: Box::new(head_fn),
+        }
+    }
+
+    pub fn get_id(&self) -> VerifyTaskId {
+        self.id
+    }
+}
+
+pub struct VerifiedProposal<Tx: TxTrait> {
+    pub task_id: VerifyTaskId,
+    pub tx_proposal: TxProposal<Tx>,
+}
+
+pub struct RejectedProposal<Tx: TxTrait> {
+    pub task_id: VerifyTaskId,
+    pub tx_proposal: TxProposal<Tx>,
+    pub reason: RejectReason,
+}
+
+pub type VerifyTaskOutput<Tx> = Result<VerifiedProposal<Tx>, RejectedProposal<Tx>>;
+
+/// Verify the tx proposals by a [`ProposalVerifier`] per thread. The results are returned in
+/// the order the verifications finish.
+pub struct VerifyEngine<Tx: TxTrait + 'static> {
+    pool: WorkerPool<VerifyTask<Tx>>,
+    result_rx: UnboundedReceiver<VerifyTaskOutput<Tx>>,
+    remaining_tasks: Arc<AtomicUsize>,
+}
+
+impl<Tx: TxTrait + 'static> VerifyEngine<Tx> {
+    #[tracing::instrument(name = "verify_engine_init", skip(threads, verifier_factory))]
+    pub fn new(
+        threads: usize,
+        verifier_factory: impl Fn() -> Box<dyn ProposalVerifier<Tx>>,
+    ) -> Self {
+        info!("Spawning VerifyEngine workers in {} threads.", threads);
+
+        let (result_tx, result_rx) = unbounded_channel();
+        let pool = WorkerPool::new("VerifyEngine", threads, || {
+            let executor = VerifyTaskExecutor {
+                verifier: verifier_factory(),
+                result_tx: result_tx.clone(),
+            };
+            move |task| executor.execute(task)
+        });
+
+        Self {
+            pool,
+            result_rx,
+            remaining_tasks: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    pub fn remaining_tasks(&self) -> usize {
+        self.remaining_tasks.load(Ordering::SeqCst)
+    }
+
+    pub fn push_task(&self, task: VerifyTask<Tx>) {
+        self.remaining_tasks.fetch_add(1, Ordering::SeqCst);
+        VERIFY_ENGINE_METRICS.tasks.inc();
+        self.pool.push(task);
+    }
+
+    pub async fn pop_result(&mut self) -> VerifyTaskOutput<Tx> {
+        let result = self
+            .result_rx
+            .recv()
+            .await
+            .expect("Failed to get the result");
+        self.remaining_tasks.fetch_sub(1, Ordering::SeqCst);
+        result
+    }
+
+    pub fn poll_result(&mut self, cx: &mut Context<'_>) -> Poll<VerifyTaskOutput<Tx>> {
+        match self.result_rx.poll_recv(cx) {
+            Poll::Ready(result) => {
+                self.remaining_tasks.fetch_sub(1, Ordering::SeqCst);
+                Poll::Ready(result.expect("Failed to get the result"))
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+
+    pub fn shutdown(&self) {
+        self.pool.shutdown();
+    }
+
+    pub fn is_shutdown(&self) -> bool {
+        self.pool.is_shutdown()
+    }
+}
+
+struct VerifyTaskExecutor<Tx: TxTrait> {
+    verifier: Box<dyn ProposalVerifier<Tx>>,
+    result_tx: UnboundedSender<VerifyTaskOutput<Tx>>,
+}
+
+impl<Tx: TxTrait> VerifyTaskExecutor<Tx> {
+    fn execute(&self, task: VerifyTask<Tx>) {
+        let span = debug_span!("verify_task", id = task.id.0);
+        let _enter = span.enter();
+
+        let begin = Instant::now();
+        let VerifyTask {
+            id: task_id,
+            tx_proposal,
+            head_fn,
+        } = task;
+        let (head_height, head_state_root) = head_fn();
+        let result = self
+            .verifier
+            .verify(task_id, head_height, head_state_root, &tx_proposal);
+        let verify_time = Instant::now() - begin;
+        VERIFY_ENGINE_METRICS
+            .verify_time
+            .observe_duration(verify_time);
+        record_time!("verify_time", verify_time, "task_id": task_id.0, "tx_id": tx_proposal.tx.id());
+
+        let output = match result {
+            Ok(()) => Ok(VerifiedProposal {
+                task_id,
+                tx_proposal,
+            }),
+            Err(reason) => {
+                VERIFY_ENGINE_METRICS.rejected(&reason).inc();
+                Err(RejectedProposal {
+                    task_id,
+                    tx_proposal,
+                    reason,
+                })
+            }
+        };
+        self.result_tx.send(output).ok();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use slimchain_common::{
+        basic::{Address, H160},
+        digest::Digestible,
+        error::{bail, Result},
+        rw_set::{TxReadSet, TxWriteData},
+        tx_req::TxRequest,
+    };
+    use slimchain_tx_state::TxWriteSetTrie;
+
+    #[derive(Debug, Clone)]
+    struct DummyTx {
+        id: u64,
+        block_height: BlockHeight,
+        state_root: H256,
+        valid_sig: bool,
+        input: TxRequest,
+        reads: TxReadSet,
+        writes: TxWriteData,
+    }
+
+    impl Digestible for DummyTx {
+        fn to_digest(&self) -> H256 {
+            self.id.to_digest()
+        }
+    }
+
+    impl TxTrait for DummyTx {
+        fn tx_caller(&self) -> Address {
+            Address(H160::zero())
+        }
+
+        fn tx_input(&self) -> &TxRequest {
+            &self.input
+        }
+
+        fn tx_block_height(&self) -> BlockHeight {
+            self.block_height
+        }
+
+        fn tx_state_root(&self) -> H256 {
+            self.state_root
+        }
+
+        fn tx_reads(&self) -> &TxReadSet {
+            &self.reads
+        }
+
+        fn tx_writes(&self) -> &TxWriteData {
+            &self.writes
+        }
+
+        fn verify_sig(&self) -> Result<()> {
+            if !self.valid_sig {
+                bail!("bad sig");
+            }
+            Ok(())
+        }
+    }
+
+    fn tx_proposal(id: u64, block_height: u64, valid_sig: bool) -> TxProposal<DummyTx> {
+        let tx = DummyTx {
+            id,
+            block_height: BlockHeight(block_height),
+            state_root: H256::zero(),
+            valid_sig,
+            input: TxRequest::Create {
+                nonce: 0.into(),
+                code: Vec::new().into(),
+            },
+            reads: TxReadSet::default(),
+            writes: TxWriteData::default(),
+        };
+        TxProposal::new(tx, TxWriteSetTrie::default())
+    }
+
+    #[tokio::test]
+    async fn test_verify_engine() {
+        let mut engine: VerifyEngine<DummyTx> =
+            VerifyEngine::new(2, || Box::new(DefaultProposalVerifier));
+        let head_state_root = H256::repeat_byte(1);
+        let head = move || (BlockHeight(2), head_state_root);
+        engine.push_task(VerifyTask::new(tx_proposal(1, 1, true), head));
+        engine.push_task(VerifyTask::new(tx_proposal(2, 1, false), head));
+        engine.push_task(VerifyTask::new(tx_proposal(3, 2, true), head));
+        assert!(engine.remaining_tasks() <= 3);
+
+        let mut verified = Vec::new();
+        let mut rejected = Vec::new();
+        for _ in 0..3 {
+            match engine.pop_result().await {
+                Ok(output) => verified.push(output.tx_proposal.tx.id),
+                Err(output) => rejected.push((output.tx_proposal.tx.id, output.reason)),
+            }
+        }
+        rejected.sort_by_key(|(id, _)| *id);
+
+        assert_eq!(vec![1], verified);
+        assert_eq!(2, rejected.len());
+        assert_eq!(2, rejected[0].0);
+        assert_eq!("invalid_sig", rejected[0].1.as_str());
+        assert_eq!(
+            (
+                3,
+                RejectReason::InvalidStateRoot {
+                    expected: head_state_root,
+                    actual: H256::zero(),
+                }
+            ),
+            rejected[1]
+        );
+        assert_eq!(0, engine.remaining_tasks());
+    }
+}
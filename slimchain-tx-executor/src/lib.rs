@@ -12,7 +12,7 @@ use slimchain_common::{
     basic::{Address, Code, Nonce, StateKey, StateValue, H160, H256, U256},
     error::{ensure, Context as _, Error, Result},
     rw_set::{TxReadData, TxWriteData},
-    tx_req::{SignedTxRequest, TxRequest},
+    tx_req::{SignedTxRequest, TxRequest, TxSigConfig},
 };
 
 pub trait Backend {
@@ -150,14 +150,34 @@ pub struct ExecuteOutput {
     pub input: TxRequest,
     pub reads: TxReadData,
     pub writes: TxWriteData,
+    pub gas_used: u64,
+    /// The fee signed in the tx request, or 0 if the signature is not checked.
+    pub fee: u64,
 }
 
-pub fn execute_tx(signed_tx_req: SignedTxRequest, backend: &impl Backend) -> Result<ExecuteOutput> {
-    signed_tx_req.verify().context("Invalid signature.")?;
+pub fn execute_tx(
+    signed_tx_req: SignedTxRequest,
+    sig_cfg: &TxSigConfig,
+    backend: &impl Backend,
+) -> Result<ExecuteOutput> {
+    signed_tx_req
+        .verify_with(sig_cfg)
+        .with_context(|| format!("Invalid {} signature.", signed_tx_req.scheme()))?;
 
     let caller = signed_tx_req.caller_address();
-    let tx_req = signed_tx_req.input;
+    let fee = signed_tx_req.fee;
+    let mut output = execute_tx_req(caller, signed_tx_req.input, backend)?;
+    output.fee = fee;
+    Ok(output)
+}
 
+/// Execute the tx request from `caller` without checking its signature, e.g., when
+/// re-executing the txs already included in a block.
+pub fn execute_tx_req(
+    caller: Address,
+    tx_req: TxRequest,
+    backend: &impl Backend,
+) -> Result<ExecuteOutput> {
     let evm_backend = EVMBackend::new(backend);
     let evm_config = evm::Config::istanbul();
     let evm_metadata = evm::executor::StackSubstateMetadata::new(u64::max_value(), &&evm_config);
@@ -205,6 +225,7 @@ pub fn execute_tx(signed_tx_req: SignedTxRequest, backend: &impl Backend) -> Res
         execute_result
     );
 
+    let gas_used = executor.used_gas();
     let mut reads = evm_backend.take_reads();
     let mut writes = TxWriteData::default();
 
@@ -249,5 +270,7 @@ pub fn execute_tx(signed_tx_req: SignedTxRequest, backend: &impl Backend) -> Res
         input: tx_req,
         reads,
         writes,
+        gas_used,
+        fee: 0,
     })
 }
@@ -25,6 +25,16 @@ pub struct TxTrieDiff {
 }
 
 impl TxTrieDiff {
+    /// The diff from an empty trie to the one only knowing the root hash.
+    pub fn from_root_hash(root_hash: H256) -> Self {
+        Self {
+            main_trie_diff: PartialTrieDiff::diff_from_empty(&PartialTrie::from_root_hash(
+                root_hash,
+            )),
+            acc_trie_diffs: HashMap::new(),
+        }
+    }
+
     #[cfg(feature = "draw")]
     pub fn to_graph(
         &self,
@@ -198,6 +198,13 @@ pub struct TxTrie {
 }
 
 impl TxTrie {
+    pub fn from_root_hash(root_hash: H256) -> Self {
+        Self {
+            main_trie: PartialTrie::from_root_hash(root_hash),
+            acc_tries: im::HashMap::new(),
+        }
+    }
+
     pub fn diff_missing_branches(&self, fork: &TxWriteSetTrie) -> TxTrieDiff {
         let main_trie_diff = diff_missing_branches(&self.main_trie, &fork.main_trie);
         let mut acc_trie_diffs = HashMap::new();
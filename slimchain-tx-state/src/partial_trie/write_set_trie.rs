@@ -37,6 +37,14 @@ pub struct TxWriteSetTrie {
 }
 
 impl TxWriteSetTrie {
+    /// The write set trie only knowing the root hash, e.g., the one of the txs writing nothing.
+    pub fn from_root_hash(root_hash: H256) -> Self {
+        Self {
+            main_trie: PartialTrie::from_root_hash(root_hash),
+            acc_tries: HashMap::new(),
+        }
+    }
+
     pub fn new(
         state_view: &impl TxStateView,
         root_address: H256,
@@ -79,6 +87,12 @@ impl TxWriteSetTrie {
         })
     }
 
+    /// The nonce of `address` in the state the write set is read from, if the tx writes to the
+    /// account.
+    pub fn get_nonce(&self, address: &Address) -> Option<Nonce> {
+        self.acc_tries.get(address).map(|acc| acc.nonce)
+    }
+
     pub fn verify(&self, state_root: H256) -> Result<()> {
         for (acc_address, acc_trie) in self.acc_tries.iter() {
             let acc_hash = acc_trie.acc_hash();
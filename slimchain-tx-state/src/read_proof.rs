@@ -1,7 +1,7 @@
 use alloc::format;
 use serde::{Deserialize, Serialize};
 use slimchain_common::{
-    basic::{account_data_to_digest, Address, Nonce, StateValue, H256},
+    basic::{account_data_to_digest, Address, Nonce, StateKey, StateValue, H256},
     collections::HashMap,
     error::{anyhow, ensure, Result},
     rw_set::TxReadData,
@@ -91,4 +91,55 @@ impl TxReadProof {
 
         Ok(())
     }
+
+    /// Verify the value of `key` in the storage of `acc_address` against `state_root`, and
+    /// return the value proven.
+    pub fn verify_value(
+        &self,
+        acc_address: Address,
+        key: StateKey,
+        state_root: H256,
+    ) -> Result<StateValue> {
+        let acc_proof = self.acc_proofs.get(&acc_address).ok_or_else(|| {
+            anyhow!(
+                "TxReadProof: Account proof unavailable (address: {}).",
+                acc_address
+            )
+        })?;
+        let value = acc_proof
+            .state_read_proof
+            .value_hash(&key)
+            .map(StateValue)
+            .ok_or_else(|| {
+                anyhow!(
+                    "TxReadProof: Value proof unavailable (address: {}, key: {}).",
+                    acc_address,
+                    key
+                )
+            })?;
+
+        let acc_hash = account_data_to_digest(
+            acc_proof.nonce.to_digest(),
+            acc_proof.code_hash,
+            acc_proof.state_read_proof.root_hash(),
+        );
+        let main_proof_acc_hash = self.main_proof.value_hash(&acc_address);
+        ensure!(
+            main_proof_acc_hash == Some(acc_hash),
+            "TxReadProof: Invalid account hash (address: {}, expect: {:?}, actual: {:?}).",
+            acc_address,
+            main_proof_acc_hash,
+            Some(acc_hash)
+        );
+
+        let main_proof_root = self.main_proof.root_hash();
+        ensure!(
+            main_proof_root == state_root,
+            "TxReadProof: Invalid state root (expect: {}, actual: {}).",
+            state_root,
+            main_proof_root
+        );
+
+        Ok(value)
+    }
 }
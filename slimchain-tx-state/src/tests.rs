@@ -610,3 +610,90 @@ fn test_prune() {
     assert_eq!(trie3.out_shard.len(), 0);
     assert_eq!(trie3.root_hash(), root);
 }
+
+#[cfg(all(feature = "read", feature = "write"))]
+#[test]
+fn test_merge_update() {
+    use alloc::{sync::Arc, vec::Vec};
+    use rand::{Rng, SeedableRng};
+    use slimchain_common::{
+        basic::{Address, Nonce, StateKey, StateValue, H160},
+        rw_set::TxWriteData,
+    };
+
+    const ACCS: u64 = 4;
+    const KEYS: u64 = 8;
+
+    fn random_writes(rng: &mut impl Rng) -> TxWriteData {
+        let mut writes = TxWriteData::default();
+        for _ in 0..rng.gen_range(1, 8) {
+            let acc = Address::from(H160::from_low_u64_be(rng.gen_range(1, ACCS + 1)));
+            match rng.gen_range(0, 10) {
+                0 => writes.add_reset_values(acc),
+                1 => writes.add_nonce(acc, Nonce::from(rng.gen_range(1u64, 100))),
+                _ => {
+                    let key = StateKey::from(H256::from_low_u64_be(rng.gen_range(1, KEYS + 1)));
+                    writes.add_value(acc, key, StateValue::from(rng.gen_range(0u64, 4)));
+                }
+            }
+        }
+        writes
+    }
+
+    fn read_all(state: &Arc<MemTxState>) -> Vec<(Nonce, Vec<StateValue>)> {
+        let mut read_ctx = TxStateReadContext::new(state.state_view(), state.state_root());
+        (1..=ACCS)
+            .map(|acc| {
+                let acc = Address::from(H160::from_low_u64_be(acc));
+                let values = (1..=KEYS)
+                    .map(|key| {
+                        let key = StateKey::from(H256::from_low_u64_be(key));
+                        read_ctx.get_value(acc, key).unwrap()
+                    })
+                    .collect();
+                (read_ctx.get_nonce(acc).unwrap(), values)
+            })
+            .collect()
+    }
+
+    fn node_count(update: &TxStateUpdate) -> usize {
+        update.acc_nodes.len() + update.state_nodes.values().map(|n| n.len()).sum::<usize>()
+    }
+
+    let mut rng = rand::rngs::StdRng::seed_from_u64(1u64);
+    let mut dropped = 0;
+    for _ in 0..100 {
+        let mut seq_state = MemTxState::new();
+        let mut merged_state = MemTxState::new();
+        if rng.gen() {
+            let writes = random_writes(&mut rng);
+            let update =
+                update_tx_state(&seq_state.state_view(), seq_state.state_root(), &writes).unwrap();
+            seq_state.apply_update(update.clone()).unwrap();
+            merged_state.apply_update(update).unwrap();
+        }
+
+        let mut merged: Option<TxStateUpdate> = None;
+        let mut seq_nodes = 0;
+        for _ in 0..rng.gen_range(1, 6) {
+            let writes = random_writes(&mut rng);
+            let update =
+                update_tx_state(&seq_state.state_view(), seq_state.state_root(), &writes).unwrap();
+            seq_nodes += node_count(&update);
+            seq_state.apply_update(update.clone()).unwrap();
+            merged = Some(match merged {
+                Some(merged) => merged.merge(&update),
+                None => update,
+            });
+        }
+        let merged = merged.unwrap();
+        assert!(node_count(&merged) <= seq_nodes);
+        dropped += seq_nodes - node_count(&merged);
+
+        // Every node of the final state is kept and nothing else is changed.
+        merged_state.apply_update(merged).unwrap();
+        assert_eq!(merged_state.state_root(), seq_state.state_root());
+        assert_eq!(read_all(&merged_state), read_all(&seq_state));
+    }
+    assert!(dropped > 0);
+}
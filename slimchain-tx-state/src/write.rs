@@ -2,14 +2,15 @@ use crate::view::{
     trie_view::{AccountTrieView, StateTrieView},
     TxStateView,
 };
+use alloc::vec::Vec;
 use serde::{Deserialize, Serialize};
 use slimchain_common::{
     basic::{AccountData, Address, StateKey, StateValue, H256},
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     error::Result,
     rw_set::TxWriteData,
 };
-use slimchain_merkle_trie::prelude::*;
+use slimchain_merkle_trie::{prelude::*, traits::Value};
 
 #[derive(Debug, Default, Clone, Serialize, Deserialize)]
 pub struct TxStateUpdate {
@@ -19,7 +20,8 @@ pub struct TxStateUpdate {
 }
 
 impl TxStateUpdate {
-    pub fn merge(&mut self, other: TxStateUpdate) {
+    /// Add the nodes of `other`, which is the update following this one, keeping all of them.
+    pub fn extend(&mut self, other: TxStateUpdate) {
         self.root = other.root;
         self.acc_nodes.extend(other.acc_nodes.into_iter());
         for (acc_addr, nodes) in other.state_nodes {
@@ -29,6 +31,90 @@ impl TxStateUpdate {
                 .extend(nodes.into_iter());
         }
     }
+
+    /// Compose this update with `next`, the update following it, into the one leading to the
+    /// state of `next` directly. The nodes of either one no longer reachable from the root of
+    /// `next` are dropped, so the intermediate state after this update cannot be read from the
+    /// merged one.
+    pub fn merge(&self, next: &TxStateUpdate) -> TxStateUpdate {
+        let mut merged = TxStateUpdate {
+            root: next.root,
+            ..Default::default()
+        };
+
+        // The nodes missing in both updates exist before this update, and so do all the nodes
+        // below them.
+        let mut acc_state_roots = HashSet::new();
+        let mut nodes = Vec::new();
+        nodes.extend(Some(next.root).filter(|root| !root.is_zero()));
+        while let Some(node_address) = nodes.pop() {
+            if merged.acc_nodes.contains_key(&node_address) {
+                continue;
+            }
+            let node = match next
+                .acc_nodes
+                .get(&node_address)
+                .or_else(|| self.acc_nodes.get(&node_address))
+            {
+                Some(node) => node,
+                None => continue,
+            };
+            push_children(node, &mut nodes);
+            if let TrieNode::Leaf(n) = node {
+                acc_state_roots.insert(n.value.acc_state_root);
+            }
+            merged.acc_nodes.insert(node_address, node.clone());
+        }
+
+        let acc_addrs: HashSet<Address> = self
+            .state_nodes
+            .keys()
+            .chain(next.state_nodes.keys())
+            .copied()
+            .collect();
+        for acc_addr in acc_addrs {
+            let prev_nodes = self.state_nodes.get(&acc_addr);
+            let next_nodes = next.state_nodes.get(&acc_addr);
+            let mut acc_nodes = HashMap::new();
+            // Any state root of the account is found among the ones referred by the account
+            // leaves above. Another account sharing the same root only keeps more nodes.
+            let mut nodes: Vec<H256> = acc_state_roots
+                .iter()
+                .copied()
+                .filter(|root| {
+                    prev_nodes.map_or(false, |n| n.contains_key(root))
+                        || next_nodes.map_or(false, |n| n.contains_key(root))
+                })
+                .collect();
+            while let Some(node_address) = nodes.pop() {
+                if acc_nodes.contains_key(&node_address) {
+                    continue;
+                }
+                let node = match next_nodes
+                    .and_then(|n| n.get(&node_address))
+                    .or_else(|| prev_nodes.and_then(|n| n.get(&node_address)))
+                {
+                    Some(node) => node,
+                    None => continue,
+                };
+                push_children(node, &mut nodes);
+                acc_nodes.insert(node_address, node.clone());
+            }
+            if !acc_nodes.is_empty() {
+                merged.state_nodes.insert(acc_addr, acc_nodes);
+            }
+        }
+
+        merged
+    }
+}
+
+fn push_children<V: Value>(node: &TrieNode<V>, out: &mut Vec<H256>) {
+    match node {
+        TrieNode::Extension(n) => out.push(n.child),
+        TrieNode::Branch(n) => out.extend(n.children.iter().flatten().copied()),
+        TrieNode::Leaf(_) => {}
+    }
 }
 
 pub fn update_tx_state(
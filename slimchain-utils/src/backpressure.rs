@@ -0,0 +1,267 @@
+//! The backpressure across the stages of the pipeline of a node.
+
+use crate::prometheus::{Counter, Gauge, REGISTRY};
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::sync::Notify;
+
+/// How long [`PipelineStage::wait_for_room`] sleeps at most before checking the depth again,
+/// in case a wakeup is missed.
+const WAIT_FOR_ROOM_INTERVAL: Duration = Duration::from_millis(50);
+
+/// The items queued in front of a stage of the pipeline, e.g., the execution, the block assembly
+/// or the broadcast.
+pub struct PipelineStage {
+    name: &'static str,
+    capacity: usize,
+    depth: AtomicUsize,
+    room: Notify,
+    depth_gauge: Arc<Gauge>,
+    shed_counter: Arc<Counter>,
+}
+
+impl PipelineStage {
+    /// Create the stage holding at most `capacity` items. 0 leaves it unbounded.
+    pub fn new(name: &'static str, capacity: usize) -> Self {
+        let labels = [("stage", name)];
+        REGISTRY
+            .gauge(
+                "slimchain_pipeline_capacity",
+                "The max items queued in front of the pipeline stage. 0 for unbounded.",
+                &labels,
+            )
+            .set(capacity as f64);
+        Self {
+            name,
+            capacity,
+            depth: AtomicUsize::new(0),
+            room: Notify::new(),
+            depth_gauge: REGISTRY.gauge(
+                "slimchain_pipeline_depth",
+                "The items queued in front of the pipeline stage.",
+                &labels,
+            ),
+            shed_counter: REGISTRY.counter(
+                "slimchain_pipeline_shed_total",
+                "The requests rejected as the pipeline stage is saturated.",
+                &labels,
+            ),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn depth(&self) -> usize {
+        self.depth.load(Ordering::Acquire)
+    }
+
+    pub fn is_saturated(&self) -> bool {
+        self.capacity > 0 && self.depth() >= self.capacity
+    }
+
+    /// Count `n` items entering the queue, once the stage has room for them. So the producers
+    /// of a bounded stage are held back, while `n` items may still go beyond the capacity of a
+    /// stage not yet saturated.
+    pub async fn push(&self, n: usize) {
+        loop {
+            self.wait_for_room().await;
+            let depth = self.depth();
+            if self.capacity > 0 && depth >= self.capacity {
+                continue;
+            }
+            if self
+                .depth
+                .compare_exchange(depth, depth + n, Ordering::AcqRel, Ordering::Acquire)
+                .is_ok()
+            {
+                self.depth_gauge.set((depth + n) as f64);
+                return;
+            }
+        }
+    }
+
+    /// Count `n` items entering the queue again, e.g., the requeued ones. Unlike [`Self::push`],
+    /// it never waits, as they were let in once.
+    pub fn readmit(&self, n: usize) {
+        let depth = self.depth.fetch_add(n, Ordering::AcqRel) + n;
+        self.depth_gauge.set(depth as f64);
+    }
+
+    /// Count `n` items leaving the queue.
+    pub fn pop(&self, n: usize) {
+        let mut depth = self.depth();
+        loop {
+            let new_depth = depth.saturating_sub(n);
+            match self.depth.compare_exchange_weak(
+                depth,
+                new_depth,
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => break,
+                Err(actual) => depth = actual,
+            }
+        }
+        self.on_depth_changed();
+    }
+
+    /// Set the depth of a stage whose queue length is known as a whole.
+    pub fn set_depth(&self, depth: usize) {
+        self.depth.store(depth, Ordering::Release);
+        self.on_depth_changed();
+    }
+
+    fn on_depth_changed(&self) {
+        self.depth_gauge.set(self.depth() as f64);
+        if !self.is_saturated() {
+            self.room.notify_waiters();
+        }
+    }
+
+    /// Wait until the stage has room for more items. It returns at once for an unbounded
+    /// stage.
+    pub async fn wait_for_room(&self) {
+        while self.is_saturated() {
+            tokio::time::timeout(WAIT_FOR_ROOM_INTERVAL, self.room.notified())
+                .await
+                .ok();
+        }
+    }
+}
+
+/// The stage rejecting the new requests, see [`Pipeline::check`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Saturated {
+    pub stage: &'static str,
+    /// When the rejected requests may be retried.
+    pub retry_after: Duration,
+}
+
+/// The stages of the pipeline of a node, from the upstream to the downstream.
+pub struct Pipeline {
+    stages: Vec<Arc<PipelineStage>>,
+    retry_after: Duration,
+}
+
+impl Pipeline {
+    /// Create an empty pipeline, whose rejected requests may be retried after `retry_after`.
+    pub fn new(retry_after: Duration) -> Self {
+        Self {
+            stages: Vec::new(),
+            retry_after,
+        }
+    }
+
+    /// Append the stage `name` at the downstream end.
+    pub fn add_stage(&mut self, name: &'static str, capacity: usize) -> Arc<PipelineStage> {
+        let stage = Arc::new(PipelineStage::new(name, capacity));
+        self.stages.push(stage.clone());
+        stage
+    }
+
+    pub fn stage(&self, name: &str) -> Option<&Arc<PipelineStage>> {
+        self.stages.iter().find(|stage| stage.name == name)
+    }
+
+    pub fn stages(&self) -> &[Arc<PipelineStage>] {
+        &self.stages
+    }
+
+    /// The furthest downstream stage which is saturated.
+    pub fn saturated_stage(&self) -> Option<&PipelineStage> {
+        self.stages
+            .iter()
+            .rev()
+            .find(|stage| stage.is_saturated())
+            .map(|stage| stage.as_ref())
+    }
+
+    /// Check whether the intake may accept a new request, which is counted as shed by the
+    /// saturated stage otherwise.
+    pub fn check(&self) -> Result<(), Saturated> {
+        match self.saturated_stage() {
+            Some(stage) => {
+                stage.shed_counter.inc();
+                Err(Saturated {
+                    stage: stage.name,
+                    retry_after: self.retry_after,
+                })
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    #[tokio::test]
+    async fn test_saturated_stage() {
+        let mut pipeline = Pipeline::new(Duration::from_secs(1));
+        let upstream = pipeline.add_stage("test_upstream", 2);
+        let unbounded = pipeline.add_stage("test_unbounded", 0);
+        let downstream = pipeline.add_stage("test_downstream", 1);
+        assert!(pipeline.check().is_ok());
+
+        unbounded.push(1_000).await;
+        upstream.push(2).await;
+        assert_eq!(pipeline.check().unwrap_err().stage, "test_upstream");
+        downstream.push(1).await;
+        assert_eq!(
+            pipeline.check(),
+            Err(Saturated {
+                stage: "test_downstream",
+                retry_after: Duration::from_secs(1),
+            })
+        );
+
+        downstream.pop(5);
+        assert_eq!(downstream.depth(), 0);
+        upstream.set_depth(1);
+        assert!(pipeline.check().is_ok());
+        assert_eq!(pipeline.stage("test_upstream").unwrap().depth(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_room() {
+        let stage = Arc::new(PipelineStage::new("test_wait", 4));
+        stage.wait_for_room().await;
+
+        // A slow consumer only takes an item every 10ms, while the producer sends as fast as
+        // the room allows.
+        let consumer = {
+            let stage = stage.clone();
+            tokio::spawn(async move {
+                for _ in 0..20 {
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                    stage.pop(1);
+                }
+            })
+        };
+
+        let begin = Instant::now();
+        let mut max_depth = 0;
+        for _ in 0..24 {
+            stage.push(1).await;
+            max_depth = max_depth.max(stage.depth());
+        }
+        consumer.await.unwrap();
+
+        assert!(max_depth <= stage.capacity());
+        assert_eq!(stage.depth(), 4);
+        assert!(begin.elapsed() >= Duration::from_millis(150));
+    }
+}
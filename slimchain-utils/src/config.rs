@@ -1,24 +1,147 @@
 use hex::{FromHex, FromHexError};
-use serde::{de::Error as SerdeError, Deserialize, Deserializer};
-use slimchain_common::error::{anyhow, Error, Result};
-use std::{fs, path::Path, time::Duration};
+use notify::{RecursiveMode, Watcher};
+use once_cell::sync::OnceCell;
+use serde::{
+    de::{DeserializeOwned, Error as SerdeError},
+    Deserialize, Deserializer,
+};
+use slimchain_common::{
+    ed25519::PublicKey,
+    error::{anyhow, Error, Result},
+};
+use std::{
+    fmt, fs,
+    path::{Path, PathBuf},
+    sync::mpsc::{self as std_mpsc, RecvTimeoutError},
+    thread,
+    time::Duration,
+};
+use tokio::sync::watch;
 use toml::Value as TomlValue;
 
 pub const CONFIG_FILE_NAME: &str = "config.toml";
 
-#[derive(Debug, Clone)]
+/// The keys of the config which take effect without a restart, once the consumers subscribe
+/// to them by [`on_section_reload`]. The changes of the other keys only log a warning.
+pub const HOT_RELOADABLE_KEYS: &[&str] = &[
+    "log",
+    "miner.max_txs",
+    "miner.max_block_gas",
+    "miner.min_txs",
+    "miner.max_block_interval",
+    "network.tx_rate_limit",
+    "pubsub.tx_proposal_queue",
+    "pubsub.block_proposal_queue",
+    "pubsub.state_sync_queue",
+];
+
+/// How often [`Config::watch`] polls the file, in case the filesystem notifications are missed
+/// or unavailable.
+pub const DEFAULT_WATCH_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+const WATCH_NOTIFY_DELAY: Duration = Duration::from_millis(200);
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct Config(TomlValue);
 
 impl Config {
     pub fn load(file: &Path) -> Result<Self> {
-        let cfg = toml::from_str(
-            &fs::read_to_string(file)
-                .map_err(|e| anyhow!("Failed to open {}. Reason: {}.", file.display(), e))?,
-        )
-        .map_err(|e| anyhow!("Failed to load {}. Reason: {}.", file.display(), e))?;
+        let content = fs::read_to_string(file)
+            .map_err(|e| anyhow!("Failed to open {}. Reason: {}.", file.display(), e))?;
+        Self::parse(file, &content)
+    }
+
+    fn parse(file: &Path, content: &str) -> Result<Self> {
+        let cfg = toml::from_str(content)
+            .map_err(|e| anyhow!("Failed to load {}. Reason: {}.", file.display(), e))?;
         Ok(Self(cfg))
     }
 
+    /// Load the config from `file` and reload it once the file is changed, using the
+    /// filesystem notifications and polling every [`DEFAULT_WATCH_POLL_INTERVAL`].
+    pub fn watch(file: &Path) -> Result<watch::Receiver<Config>> {
+        Self::watch_with_poll_interval(file, DEFAULT_WATCH_POLL_INTERVAL)
+    }
+
+    /// Same as [`Config::watch`], while polling every `poll_interval`. The configs that fail to
+    /// load are rejected with a warning, and the changed keys not in [`HOT_RELOADABLE_KEYS`] are
+    /// warned to require a restart. The watch stops once the receivers are dropped.
+    pub fn watch_with_poll_interval(
+        file: &Path,
+        poll_interval: Duration,
+    ) -> Result<watch::Receiver<Config>> {
+        let file = file.to_path_buf();
+        let mut content = fs::read_to_string(&file)
+            .map_err(|e| anyhow!("Failed to open {}. Reason: {}.", file.display(), e))?;
+        let mut current = Self::parse(&file, &content)?;
+        let (cfg_tx, cfg_rx) = watch::channel(current.clone());
+
+        let (event_tx, event_rx) = std_mpsc::channel();
+        // Watch the directory instead of the file, which editors may replace with a new one.
+        let watcher = notify::watcher(event_tx, WATCH_NOTIFY_DELAY).and_then(|mut watcher| {
+            watcher.watch(watch_dir(&file), RecursiveMode::NonRecursive)?;
+            Ok(watcher)
+        });
+        let watcher = match watcher {
+            Ok(watcher) => Some(watcher),
+            Err(e) => {
+                warn!(
+                    "Failed to watch {}. Fall back to polling. Error: {}",
+                    file.display(),
+                    e
+                );
+                None
+            }
+        };
+
+        thread::spawn(move || {
+            let _watcher = watcher;
+            loop {
+                if let Err(RecvTimeoutError::Disconnected) = event_rx.recv_timeout(poll_interval) {
+                    thread::sleep(poll_interval);
+                }
+
+                // The file may be missing for a moment while it is being replaced.
+                let new_content = match fs::read_to_string(&file) {
+                    Ok(new_content) => new_content,
+                    Err(_) => continue,
+                };
+                if new_content == content {
+                    continue;
+                }
+                content = new_content;
+
+                let new = match Self::parse(&file, &content) {
+                    Ok(new) => new,
+                    Err(e) => {
+                        warn!("Reject the changed config. Keep the running one. {}", e);
+                        continue;
+                    }
+                };
+                if new == current {
+                    continue;
+                }
+                for key in changed_keys(&current.0, &new.0) {
+                    if is_hot_reloadable(&key) {
+                        info!("Reload `{}` in {}.", key, file.display());
+                    } else {
+                        warn!(
+                            "`{}` in {} is changed, which requires a restart to take effect.",
+                            key,
+                            file.display()
+                        );
+                    }
+                }
+                current = new.clone();
+                if cfg_tx.send(new).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(cfg_rx)
+    }
+
     pub fn load_test() -> Result<Self> {
         Self::load(&crate::path::project_root_directory()?.join(CONFIG_FILE_NAME))
     }
@@ -31,14 +154,330 @@ impl Config {
         Self(value)
     }
 
+    /// Get the value of `key`, where the nested ones are separated by dots, e.g.,
+    /// `network.tx_rate_limit`.
     pub fn get<'de, T: Deserialize<'de>>(&self, key: &str) -> Result<T> {
-        self.0
-            .get(key)
+        key.split('.')
+            .try_fold(&self.0, |value, k| value.get(k))
             .ok_or_else(|| anyhow!("Failed to read `{}` in the config.", key))?
             .clone()
             .try_into()
             .map_err(Error::msg)
     }
+
+    /// Like [`Self::get`], but fall back to the default if `key` is missing. An invalid value is
+    /// still an error.
+    pub fn get_or_default<'de, T: Deserialize<'de> + Default>(&self, key: &str) -> Result<T> {
+        match self.value(key) {
+            Some(value) => value.clone().try_into().map_err(Error::msg),
+            None => Ok(T::default()),
+        }
+    }
+
+    fn value(&self, key: &str) -> Option<&TomlValue> {
+        key.split('.').try_fold(&self.0, |value, k| value.get(k))
+    }
+
+    fn top_level_keys(&self) -> Vec<&str> {
+        match self.0.as_table() {
+            Some(table) => table.keys().map(String::as_str).collect(),
+            None => Vec::new(),
+        }
+    }
+}
+
+/// An invalid value in the config, located by its dotted path, e.g., `miner.max_txs`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigError {
+    pub path: String,
+    pub message: String,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "`{}`: {}", self.path, self.message)
+    }
+}
+
+/// All the errors found by validating a config, so that they can be fixed in one go.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConfigErrors(Vec<ConfigError>);
+
+impl ConfigErrors {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, path: impl Into<String>, message: impl Into<String>) {
+        self.0.push(ConfigError {
+            path: path.into(),
+            message: message.into(),
+        });
+    }
+
+    /// Report an error at `path` unless `cond` holds.
+    pub fn ensure(&mut self, cond: bool, path: &str, message: impl fmt::Display) {
+        if !cond {
+            self.push(path, message.to_string());
+        }
+    }
+
+    pub fn extend(&mut self, other: ConfigErrors) {
+        self.0.extend(other.0);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn errors(&self) -> &[ConfigError] {
+        &self.0
+    }
+
+    /// Whether there is an error at `path`, where the nested paths count.
+    pub fn contains(&self, path: &str) -> bool {
+        self.0.iter().any(|e| {
+            e.path == path
+                || e.path
+                    .strip_prefix(path)
+                    .map_or(false, |rest| rest.starts_with('.'))
+        })
+    }
+
+    pub fn into_result(self) -> std::result::Result<(), Self> {
+        if self.is_empty() {
+            Ok(())
+        } else {
+            Err(self)
+        }
+    }
+}
+
+impl fmt::Display for ConfigErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid config:")?;
+        for e in &self.0 {
+            write!(f, "\n  {}", e)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConfigErrors {}
+
+/// Join the dotted `path` of a section and the `key` in it.
+pub fn join_path(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}.{}", path, key)
+    }
+}
+
+/// The range checks of a config section, which is located at `path` in the config.
+pub trait ValidateConfig {
+    fn validate_at(&self, path: &str, errors: &mut ConfigErrors);
+
+    fn validate_config(&self, path: &str) -> std::result::Result<(), ConfigErrors> {
+        let mut errors = ConfigErrors::new();
+        self.validate_at(path, &mut errors);
+        errors.into_result()
+    }
+}
+
+/// Deserialize the sections of a [`Config`] while collecting the unknown fields and the
+/// invalid values with their dotted paths, instead of stopping at the first one.
+pub struct ConfigValidator<'a> {
+    cfg: &'a Config,
+    visited: Vec<String>,
+    errors: ConfigErrors,
+}
+
+impl<'a> ConfigValidator<'a> {
+    pub fn new(cfg: &'a Config) -> Self {
+        Self {
+            cfg,
+            visited: Vec::new(),
+            errors: ConfigErrors::new(),
+        }
+    }
+
+    /// Deserialize and validate the required section `key`.
+    pub fn section<T>(&mut self, key: &str) -> Option<T>
+    where
+        T: DeserializeOwned + ValidateConfig,
+    {
+        self.visited.push(key.to_string());
+        match self.cfg.value(key) {
+            Some(value) => self.deserialize(key, value.clone()),
+            None => {
+                self.errors.push(key, "Missing section.");
+                None
+            }
+        }
+    }
+
+    /// Deserialize and validate the section `key`, which falls back to its default if missing.
+    pub fn optional_section<T>(&mut self, key: &str) -> Option<T>
+    where
+        T: DeserializeOwned + ValidateConfig + Default,
+    {
+        self.visited.push(key.to_string());
+        match self.cfg.value(key) {
+            Some(value) => self.deserialize(key, value.clone()),
+            None => {
+                let value = T::default();
+                value.validate_at(key, &mut self.errors);
+                Some(value)
+            }
+        }
+    }
+
+    /// Accept the section `key` without checking it.
+    pub fn allow_section(&mut self, key: &str) {
+        self.visited.push(key.to_string());
+    }
+
+    pub fn errors_mut(&mut self) -> &mut ConfigErrors {
+        &mut self.errors
+    }
+
+    /// Report the top-level sections which are never visited, and return all the errors.
+    pub fn finish(mut self) -> std::result::Result<(), ConfigErrors> {
+        for key in self.cfg.top_level_keys() {
+            let known = self
+                .visited
+                .iter()
+                .any(|v| v == key || v.split('.').next() == Some(key));
+            if !known {
+                self.errors.push(key, "Unknown section.");
+            }
+        }
+        self.errors.into_result()
+    }
+
+    fn deserialize<T>(&mut self, key: &str, value: TomlValue) -> Option<T>
+    where
+        T: DeserializeOwned + ValidateConfig,
+    {
+        let mut unknown = Vec::new();
+        let result =
+            serde_path_to_error::deserialize(serde_ignored::Deserializer::new(value, |path| {
+                unknown.push(path.to_string())
+            }));
+        for path in unknown {
+            self.errors.push(join_path(key, &path), "Unknown field.");
+        }
+        match result {
+            Ok(value) => {
+                T::validate_at(&value, key, &mut self.errors);
+                Some(value)
+            }
+            Err(e) => {
+                let path = e.path().to_string();
+                let path = if path == "." {
+                    key.to_string()
+                } else {
+                    join_path(key, &path)
+                };
+                self.errors.push(path, e.into_inner().to_string());
+                None
+            }
+        }
+    }
+}
+
+fn watch_dir(file: &Path) -> PathBuf {
+    match file.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => PathBuf::from("."),
+    }
+}
+
+/// Whether `key` is or is under one of [`HOT_RELOADABLE_KEYS`].
+pub fn is_hot_reloadable(key: &str) -> bool {
+    HOT_RELOADABLE_KEYS.iter().any(|hot| {
+        key.strip_prefix(hot)
+            .map_or(false, |rest| rest.is_empty() || rest.starts_with('.'))
+    })
+}
+
+/// The dotted keys whose values differ between `old` and `new`. The tables are compared key
+/// by key down to the hot-reloadable keys.
+pub fn changed_keys(old: &TomlValue, new: &TomlValue) -> Vec<String> {
+    fn walk(prefix: &str, old: Option<&TomlValue>, new: Option<&TomlValue>, out: &mut Vec<String>) {
+        if old == new {
+            return;
+        }
+        match (old, new) {
+            (Some(TomlValue::Table(old)), Some(TomlValue::Table(new)))
+                if prefix.is_empty() || !is_hot_reloadable(prefix) =>
+            {
+                for key in old
+                    .keys()
+                    .chain(new.keys().filter(|k| !old.contains_key(*k)))
+                {
+                    let path = if prefix.is_empty() {
+                        key.clone()
+                    } else {
+                        format!("{}.{}", prefix, key)
+                    };
+                    walk(&path, old.get(key), new.get(key), out);
+                }
+            }
+            _ => out.push(prefix.to_string()),
+        }
+    }
+
+    let mut out = Vec::new();
+    walk("", Some(old), Some(new), &mut out);
+    out
+}
+
+static GLOBAL_CONFIG_WATCH: OnceCell<watch::Receiver<Config>> = OnceCell::new();
+
+/// Install the receiver of [`Config::watch`] for [`on_section_reload`].
+pub fn install_config_watch_as_global(cfg_rx: watch::Receiver<Config>) -> Result<()> {
+    GLOBAL_CONFIG_WATCH
+        .set(cfg_rx)
+        .map_err(|_| anyhow!("Failed to set the config watch."))
+}
+
+/// Call `apply` with the value of `key` in each config reloaded by the watch installed by
+/// [`install_config_watch_as_global`], once it differs from the previous one. Do nothing if
+/// no watch is installed, e.g., in tests.
+pub fn on_section_reload<T, F>(key: &'static str, apply: F)
+where
+    T: for<'de> Deserialize<'de> + Clone + PartialEq + Send + 'static,
+    F: FnMut(T) + Send + 'static,
+{
+    if let Some(cfg_rx) = GLOBAL_CONFIG_WATCH.get() {
+        follow_section(cfg_rx.clone(), key, apply);
+    }
+}
+
+/// Same as [`on_section_reload`], while following the configs of `cfg_rx`. A value that fails
+/// to deserialize is rejected with a warning, leaving the previous one applied.
+pub fn follow_section<T, F>(mut cfg_rx: watch::Receiver<Config>, key: &'static str, mut apply: F)
+where
+    T: for<'de> Deserialize<'de> + Clone + PartialEq + Send + 'static,
+    F: FnMut(T) + Send + 'static,
+{
+    let mut last: Option<T> = cfg_rx.borrow().get(key).ok();
+    tokio::spawn(async move {
+        while cfg_rx.changed().await.is_ok() {
+            let value = cfg_rx.borrow().get::<T>(key);
+            match value {
+                Ok(value) => {
+                    if last.as_ref() != Some(&value) {
+                        last = Some(value.clone());
+                        apply(value);
+                    }
+                }
+                Err(e) => warn!("Reject the reloaded `{}`. Keep the running one. {}", key, e),
+            }
+        }
+    });
 }
 
 pub fn deserialize_from_hex<'de, D, T>(deserializer: D) -> Result<T, D::Error>
@@ -50,6 +489,28 @@ where
     T::from_hex(encoded_hex).map_err(SerdeError::custom)
 }
 
+fn public_key_from_hex<E: SerdeError>(key: String) -> Result<PublicKey, E> {
+    let bytes = hex::decode(key).map_err(E::custom)?;
+    PublicKey::from_bytes(&bytes[..]).map_err(E::custom)
+}
+
+pub fn deserialize_public_key_from_hex<'de, D>(deserializer: D) -> Result<PublicKey, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    public_key_from_hex(String::deserialize(deserializer)?)
+}
+
+pub fn deserialize_public_keys_from_hex<'de, D>(deserializer: D) -> Result<Vec<PublicKey>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Vec::<String>::deserialize(deserializer)?
+        .into_iter()
+        .map(public_key_from_hex)
+        .collect()
+}
+
 pub fn deserialize_duration_from_millis<'de, D>(deserializer: D) -> Result<Duration, D::Error>
 where
     D: Deserializer<'de>,
@@ -57,3 +518,153 @@ where
     let ms = u64::deserialize(deserializer)?;
     Ok(Duration::from_millis(ms))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::{sync::mpsc, time::timeout};
+
+    fn parse(content: &str) -> Config {
+        Config::parse(Path::new("test.toml"), content).unwrap()
+    }
+
+    #[test]
+    fn test_get_nested() {
+        let cfg = parse("a = 1\n[network.tx_rate_limit]\nglobal_rate = 2\n");
+        assert_eq!(1, cfg.get::<u32>("a").unwrap());
+        assert_eq!(
+            2,
+            cfg.get::<u32>("network.tx_rate_limit.global_rate").unwrap()
+        );
+        assert!(cfg.get::<u32>("network.other").is_err());
+    }
+
+    #[test]
+    fn test_get_or_default() {
+        let cfg = parse("a = 1\nb = \"x\"\n");
+        assert_eq!(1, cfg.get_or_default::<u32>("a").unwrap());
+        assert_eq!(0, cfg.get_or_default::<u32>("c").unwrap());
+        assert!(cfg.get_or_default::<u32>("b").is_err());
+    }
+
+    #[derive(Debug, Default, Deserialize)]
+    #[serde(default)]
+    struct TestSection {
+        min: u32,
+        max: u32,
+        inner: TestInner,
+    }
+
+    #[derive(Debug, Default, Deserialize)]
+    #[serde(default)]
+    struct TestInner {
+        value: u32,
+    }
+
+    impl ValidateConfig for TestSection {
+        fn validate_at(&self, path: &str, errors: &mut ConfigErrors) {
+            errors.ensure(
+                self.min <= self.max,
+                &join_path(path, "min"),
+                "Should not be larger than `max`.",
+            );
+        }
+    }
+
+    fn validate(content: &str) -> std::result::Result<(), ConfigErrors> {
+        let cfg = parse(content);
+        let mut validator = ConfigValidator::new(&cfg);
+        validator.section::<TestSection>("test");
+        validator.optional_section::<TestSection>("nested.test");
+        validator.finish()
+    }
+
+    #[test]
+    fn test_validator() {
+        assert!(validate("[test]\nmin = 1\nmax = 2\n").is_ok());
+        assert!(validate("[test]\n[nested.test.inner]\nvalue = 1\n").is_ok());
+
+        let errors = validate("[nested.test]\nmin = 3\n").unwrap_err();
+        assert!(errors.contains("test"));
+        assert!(errors.contains("nested.test.min"));
+        assert_eq!(2, errors.errors().len());
+
+        let errors = validate(
+            "[test]\nmin = 1\nmax = 2\ntypo = 1\n[test.inner]\nvalu = 1\n[other]\na = 1\n",
+        )
+        .unwrap_err();
+        assert!(errors.contains("test.typo"));
+        assert!(errors.contains("test.inner.valu"));
+        assert!(errors.contains("other"));
+        assert_eq!(3, errors.errors().len());
+
+        let errors = validate("[test.inner]\nvalue = \"a\"\n").unwrap_err();
+        assert!(errors.contains("test.inner.value"));
+        assert!(errors
+            .to_string()
+            .starts_with("Invalid config:\n  `test.inner.value`: "));
+    }
+
+    #[test]
+    fn test_changed_keys() {
+        let old = parse(
+            "[chain]\nstate_len = 16\n[miner]\nmax_txs = 1\ncompress_trie = true\n\
+             [network.tx_rate_limit]\nglobal_rate = 1\n",
+        );
+        let new = parse(
+            "[chain]\nstate_len = 16\n[miner]\nmax_txs = 2\ncompress_trie = false\n\
+             [network.tx_rate_limit]\nglobal_rate = 2\n[log]\nlevel = \"debug\"\n",
+        );
+        let mut keys = changed_keys(&old.0, &new.0);
+        keys.sort();
+        assert_eq!(
+            vec![
+                "log",
+                "miner.compress_trie",
+                "miner.max_txs",
+                "network.tx_rate_limit"
+            ],
+            keys
+        );
+        assert!(is_hot_reloadable("log"));
+        assert!(is_hot_reloadable("log.level"));
+        assert!(is_hot_reloadable("miner.max_txs"));
+        assert!(!is_hot_reloadable("miner.max_txs_other"));
+        assert!(!is_hot_reloadable("miner.compress_trie"));
+        assert!(!is_hot_reloadable("network"));
+    }
+
+    #[tokio::test]
+    async fn test_watch() {
+        let dir =
+            std::env::temp_dir().join(format!("slimchain-config-watch-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join(CONFIG_FILE_NAME);
+        fs::write(&file, "[miner]\nmax_txs = 1\n").unwrap();
+
+        let cfg_rx = Config::watch_with_poll_interval(&file, Duration::from_millis(50)).unwrap();
+        assert_eq!(1, cfg_rx.borrow().get::<usize>("miner.max_txs").unwrap());
+        let (value_tx, mut value_rx) = mpsc::unbounded_channel();
+        follow_section(cfg_rx.clone(), "miner.max_txs", move |v: usize| {
+            value_tx.send(v).ok();
+        });
+
+        fs::write(&file, "[miner]\nmax_txs = 2\n").unwrap();
+        let value = timeout(Duration::from_secs(10), value_rx.recv()).await;
+        assert_eq!(Some(2), value.unwrap());
+
+        // The invalid configs are rejected, both the file and the section.
+        fs::write(&file, "[miner\nmax_txs = 3\n").unwrap();
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert_eq!(2, cfg_rx.borrow().get::<usize>("miner.max_txs").unwrap());
+        fs::write(&file, "[miner]\nmax_txs = \"x\"\n").unwrap();
+        let value = timeout(Duration::from_millis(300), value_rx.recv()).await;
+        assert!(value.is_err());
+
+        fs::write(&file, "[miner]\nmax_txs = 4\n").unwrap();
+        let value = timeout(Duration::from_secs(10), value_rx.recv()).await;
+        assert_eq!(Some(4), value.unwrap());
+
+        fs::remove_dir_all(&dir).ok();
+    }
+}
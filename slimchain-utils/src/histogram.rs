@@ -0,0 +1,547 @@
+//! The aggregation mode of the metrics, which keeps the times of `record_time!` in in-memory
+//! histograms instead of writing one line per call, and periodically flushes their percentile
+//! summaries to the metrics file and/or the Prometheus registry.
+
+use crate::{
+    config::{join_path, ConfigErrors, ValidateConfig},
+    metrics::{
+        set_metrics_sink, set_thread_buffering, FanOutSink, LogSink, MetricsSink, METRICS_DISPATCH,
+    },
+    metrics_file::MetricsFileConfig,
+    prometheus::{PrometheusSink, Registry, REGISTRY},
+};
+use crossbeam_channel::{bounded, RecvTimeoutError, Sender};
+use serde::Deserialize;
+use serde_json::{json, Map as JsonMap, Value as JsonValue};
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex, MutexGuard},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+/// The values below `2^SUB_BUCKET_BITS` are counted exactly. The larger ones share a bucket with
+/// the values of the same `SUB_BUCKET_BITS - 1` leading bits, i.e., within 1/64 of each other.
+const SUB_BUCKET_BITS: u32 = 7;
+const SUB_BUCKETS: u64 = 1 << SUB_BUCKET_BITS;
+const HALF_SUB_BUCKETS: u64 = SUB_BUCKETS / 2;
+
+fn bucket_index(value: u64) -> usize {
+    if value < SUB_BUCKETS {
+        return value as usize;
+    }
+    let msb = 63 - value.leading_zeros();
+    let shift = msb + 1 - SUB_BUCKET_BITS;
+    let top = value >> shift;
+    (SUB_BUCKETS + (shift as u64 - 1) * HALF_SUB_BUCKETS + (top - HALF_SUB_BUCKETS)) as usize
+}
+
+/// The largest value counted in the bucket `index`.
+fn bucket_upper_bound(index: usize) -> u64 {
+    let index = index as u64;
+    if index < SUB_BUCKETS {
+        return index;
+    }
+    let shift = (index - SUB_BUCKETS) / HALF_SUB_BUCKETS + 1;
+    let top = (index - SUB_BUCKETS) % HALF_SUB_BUCKETS + HALF_SUB_BUCKETS;
+    ((((top + 1) as u128) << shift) - 1) as u64
+}
+
+/// An HDR-style histogram of `u64` values with a relative error of at most 1/64.
+#[derive(Debug, Default, Clone)]
+pub struct HdrHistogram {
+    counts: Vec<u64>,
+    count: u64,
+    sum: u64,
+    max: u64,
+}
+
+impl HdrHistogram {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&mut self, value: u64) {
+        let index = bucket_index(value);
+        if index >= self.counts.len() {
+            self.counts.resize(index + 1, 0);
+        }
+        self.counts[index] += 1;
+        self.count += 1;
+        self.sum = self.sum.saturating_add(value);
+        self.max = self.max.max(value);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn sum(&self) -> u64 {
+        self.sum
+    }
+
+    pub fn max(&self) -> u64 {
+        self.max
+    }
+
+    /// The value at the quantile `q` in `[0, 1]`, i.e., the smallest recorded value that at
+    /// least `q` of the values are not larger than, up to the error of the buckets. It never
+    /// underestimates the value, and is 0 if nothing is recorded.
+    pub fn value_at_quantile(&self, q: f64) -> u64 {
+        if self.count == 0 {
+            return 0;
+        }
+        let rank = ((q * self.count as f64).ceil() as u64).clamp(1, self.count);
+        let mut cumulative = 0;
+        for (index, &count) in self.counts.iter().enumerate() {
+            cumulative += count;
+            if cumulative >= rank {
+                return bucket_upper_bound(index).min(self.max);
+            }
+        }
+        self.max
+    }
+
+    pub fn summary(&self) -> HistogramSummary {
+        HistogramSummary {
+            count: self.count,
+            p50: self.value_at_quantile(0.5),
+            p95: self.value_at_quantile(0.95),
+            p99: self.value_at_quantile(0.99),
+            max: self.max,
+            sum: self.sum,
+        }
+    }
+}
+
+/// The percentile summary of a [`HdrHistogram`].
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub struct HistogramSummary {
+    pub count: u64,
+    pub p50: u64,
+    pub p95: u64,
+    pub p99: u64,
+    pub max: u64,
+    pub sum: u64,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MetricsMode {
+    /// Write one line per `record_time!` call.
+    Raw,
+    /// Aggregate the times in histograms and flush their summaries periodically.
+    Aggregate,
+}
+
+impl Default for MetricsMode {
+    fn default() -> Self {
+        Self::Raw
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct MetricsConfig {
+    /// How `record_time!` is recorded. Possible values: raw, aggregate. Default raw.
+    /// The events are always written raw.
+    pub mode: MetricsMode,
+    /// How often the histograms are flushed in the aggregate mode. Default 10 seconds.
+    #[serde(deserialize_with = "crate::config::deserialize_duration_from_millis")]
+    pub flush_interval: Duration,
+    /// Whether to flush the summaries to the metrics file. Default true.
+    pub flush_to_log: bool,
+    /// Whether to flush the summaries to the Prometheus registry. Default false.
+    pub flush_to_prometheus: bool,
+    /// The fields of `record_time!` whose values label the histograms, besides the names.
+    pub group_by: Vec<String>,
+    /// Whether the raw entries are buffered per thread. See [`set_thread_buffering`].
+    /// Default true.
+    pub thread_buffer: bool,
+    /// How the metrics file is rolled over.
+    pub file: MetricsFileConfig,
+}
+
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        Self {
+            mode: MetricsMode::default(),
+            flush_interval: Duration::from_secs(10),
+            flush_to_log: true,
+            flush_to_prometheus: false,
+            group_by: Vec::new(),
+            thread_buffer: true,
+            file: MetricsFileConfig::default(),
+        }
+    }
+}
+
+impl ValidateConfig for MetricsConfig {
+    fn validate_at(&self, path: &str, errors: &mut ConfigErrors) {
+        errors.ensure(
+            self.mode == MetricsMode::Raw || self.flush_interval > Duration::from_millis(0),
+            &join_path(path, "flush_interval"),
+            "Should be positive in the aggregate mode.",
+        );
+    }
+}
+
+impl MetricsConfig {
+    /// Set up the sink of the metrics macros, which also tees to [`REGISTRY`] if
+    /// `prometheus_tee`. In the aggregate mode, the returned guard flushes the histograms until
+    /// it is dropped, which flushes them one last time. Drop it before the guard of the metrics
+    /// file, so that the last summaries are written.
+    pub fn install(&self, prometheus_tee: bool) -> Option<HistogramFlusher> {
+        set_thread_buffering(self.thread_buffer);
+        let mut sink = FanOutSink::default();
+        let flusher = match self.mode {
+            MetricsMode::Raw => {
+                sink.push(Box::new(LogSink));
+                None
+            }
+            MetricsMode::Aggregate => {
+                let histograms = HistogramSink::new(self.group_by.clone());
+                sink.push(Box::new(histograms.clone()));
+                let registry = if self.flush_to_prometheus {
+                    Some(&*REGISTRY)
+                } else {
+                    None
+                };
+                Some(HistogramFlusher::spawn(
+                    histograms,
+                    self.flush_interval,
+                    self.flush_to_log,
+                    registry,
+                ))
+            }
+        };
+        if prometheus_tee {
+            sink.push(Box::new(PrometheusSink::new(&REGISTRY)));
+        }
+        set_metrics_sink(Box::new(sink));
+        flusher
+    }
+}
+
+pub type HistogramKey = (String, Vec<(String, String)>);
+
+/// The [`MetricsSink`] aggregating the times into a [`HdrHistogram`] per name and the values of
+/// the `group_by` fields, in microseconds. The events are written to [`LogSink`].
+#[derive(Debug, Clone)]
+pub struct HistogramSink {
+    group_by: Arc<Vec<String>>,
+    histograms: Arc<Mutex<BTreeMap<HistogramKey, HdrHistogram>>>,
+}
+
+impl HistogramSink {
+    pub fn new(group_by: Vec<String>) -> Self {
+        Self {
+            group_by: Arc::new(group_by),
+            histograms: Arc::new(Mutex::new(BTreeMap::new())),
+        }
+    }
+
+    fn histograms(&self) -> MutexGuard<'_, BTreeMap<HistogramKey, HdrHistogram>> {
+        self.histograms.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn labels(&self, fields: &JsonValue) -> Vec<(String, String)> {
+        self.group_by
+            .iter()
+            .filter_map(|key| {
+                let value = match fields.get(key)? {
+                    JsonValue::String(s) => s.clone(),
+                    v => v.to_string(),
+                };
+                Some((key.clone(), value))
+            })
+            .collect()
+    }
+
+    /// Take the histograms aggregated since the last call.
+    pub fn take(&self) -> BTreeMap<HistogramKey, HdrHistogram> {
+        std::mem::take(&mut *self.histograms())
+    }
+}
+
+impl MetricsSink for HistogramSink {
+    fn record_time(&self, label: &str, time: Duration, fields: &JsonValue) {
+        let key = (label.to_string(), self.labels(fields));
+        self.histograms()
+            .entry(key)
+            .or_default()
+            .record(time.as_micros() as u64);
+    }
+
+    fn record_event(&self, label: &str, fields: &JsonValue) {
+        LogSink.record_event(label, fields);
+    }
+}
+
+/// The entry of a histogram summary written to the metrics file.
+pub fn summary_entry(
+    label: &str,
+    labels: &[(String, String)],
+    ts: &str,
+    summary: &HistogramSummary,
+) -> JsonValue {
+    let labels: JsonMap<String, JsonValue> = labels
+        .iter()
+        .map(|(k, v)| (k.clone(), JsonValue::String(v.clone())))
+        .collect();
+    json!({
+        "k": "summary",
+        "l": label,
+        "ts": ts,
+        "labels": labels,
+        "count": summary.count,
+        "p50_us": summary.p50,
+        "p95_us": summary.p95,
+        "p99_us": summary.p99,
+        "max_us": summary.max,
+        "sum_us": summary.sum,
+    })
+}
+
+fn set_summary_gauges(
+    registry: &Registry,
+    label: &str,
+    labels: &[(String, String)],
+    summary: &HistogramSummary,
+) {
+    let mut base: Vec<(&str, &str)> = vec![("label", label)];
+    base.extend(labels.iter().map(|(k, v)| (k.as_str(), v.as_str())));
+    let quantiles = [
+        ("0.5", summary.p50),
+        ("0.95", summary.p95),
+        ("0.99", summary.p99),
+        ("1", summary.max),
+    ];
+    for (quantile, value) in quantiles.iter() {
+        let mut labels = base.clone();
+        labels.push(("quantile", quantile));
+        registry
+            .gauge(
+                "slimchain_time_summary_seconds",
+                "The quantiles of the times in the last flush interval.",
+                &labels,
+            )
+            .set(*value as f64 / 1e6);
+    }
+    registry
+        .gauge(
+            "slimchain_time_summary_count",
+            "The number of the times in the last flush interval.",
+            &base,
+        )
+        .set(summary.count as f64);
+    registry
+        .gauge(
+            "slimchain_time_summary_sum_seconds",
+            "The sum of the times in the last flush interval.",
+            &base,
+        )
+        .set(summary.sum as f64 / 1e6);
+}
+
+/// Flush the summaries of the histograms aggregated since the last flush.
+fn flush(sink: &HistogramSink, to_log: bool, registry: Option<&Registry>) {
+    let histograms = sink.take();
+    if histograms.is_empty() {
+        return;
+    }
+    let ts = chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Micros, true);
+    for ((label, labels), histogram) in histograms {
+        let summary = histogram.summary();
+        if to_log {
+            if let Some(dispatch) = METRICS_DISPATCH.get() {
+                dispatch.add_entry(summary_entry(&label, &labels, &ts, &summary));
+            }
+        }
+        if let Some(registry) = registry {
+            set_summary_gauges(registry, &label, &labels, &summary);
+        }
+    }
+}
+
+/// The background thread flushing a [`HistogramSink`] periodically. It flushes one last time
+/// when dropped.
+pub struct HistogramFlusher {
+    shutdown: Sender<()>,
+    handler: Option<JoinHandle<()>>,
+}
+
+impl HistogramFlusher {
+    pub fn spawn(
+        sink: HistogramSink,
+        interval: Duration,
+        to_log: bool,
+        registry: Option<&'static Registry>,
+    ) -> Self {
+        let (shutdown, shutdown_rx) = bounded(1);
+        let handler = thread::spawn(move || loop {
+            match shutdown_rx.recv_timeout(interval) {
+                Err(RecvTimeoutError::Timeout) => flush(&sink, to_log, registry),
+                Ok(()) | Err(RecvTimeoutError::Disconnected) => {
+                    flush(&sink, to_log, registry);
+                    break;
+                }
+            }
+        });
+        Self {
+            shutdown,
+            handler: Some(handler),
+        }
+    }
+}
+
+impl Drop for HistogramFlusher {
+    fn drop(&mut self) {
+        self.shutdown.send(()).ok();
+        if let Some(handler) = self.handler.take() {
+            handler.join().ok();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prometheus::parse_samples;
+
+    #[test]
+    fn test_bucket() {
+        for value in (0..100_000).chain(vec![u64::MAX / 3, u64::MAX - 1, u64::MAX]) {
+            let index = bucket_index(value);
+            let upper = bucket_upper_bound(index);
+            assert!(value <= upper, "{} > {}", value, upper);
+            assert!(
+                upper - value <= value / HALF_SUB_BUCKETS,
+                "{} {}",
+                value,
+                upper
+            );
+            if index > 0 {
+                assert!(bucket_upper_bound(index - 1) < value);
+            }
+        }
+        assert_eq!(u64::MAX, bucket_upper_bound(bucket_index(u64::MAX)));
+    }
+
+    #[test]
+    fn test_percentiles() {
+        let mut histogram = HdrHistogram::new();
+        assert_eq!(HistogramSummary::default(), histogram.summary());
+
+        // Small values are exact.
+        for value in 1..=100 {
+            histogram.record(value);
+        }
+        let summary = histogram.summary();
+        assert_eq!(50, summary.p50);
+        assert_eq!(95, summary.p95);
+        assert_eq!(99, summary.p99);
+        assert_eq!(100, summary.max);
+        assert_eq!(5050, summary.sum);
+
+        // A uniform distribution of 1..=1_000_000 in a shuffled order.
+        let mut histogram = HdrHistogram::new();
+        let n = 1_000_000u64;
+        for i in 0..n {
+            histogram.record(i * 7_919 % n + 1);
+        }
+        let summary = histogram.summary();
+        assert_eq!(n, summary.count);
+        assert_eq!(n * (n + 1) / 2, summary.sum);
+        assert_eq!(n, summary.max);
+        for (actual, expected) in [
+            (summary.p50, n / 2),
+            (summary.p95, n / 100 * 95),
+            (summary.p99, n / 100 * 99),
+        ]
+        .iter()
+        {
+            assert!(actual >= expected, "{} < {}", actual, expected);
+            assert!(actual - expected <= expected / HALF_SUB_BUCKETS);
+        }
+        assert_eq!(n, histogram.value_at_quantile(1.));
+        assert_eq!(1, histogram.value_at_quantile(0.));
+    }
+
+    #[test]
+    fn test_histogram_sink() {
+        let sink = HistogramSink::new(vec!["shard".to_string()]);
+        for ms in 1..=10 {
+            sink.record_time(
+                "test_hist",
+                Duration::from_millis(ms),
+                &json!({ "shard": 0 }),
+            );
+        }
+        sink.record_time(
+            "test_hist",
+            Duration::from_millis(20),
+            &json!({ "shard": "a", "other": 1 }),
+        );
+        sink.record_time("test_hist", Duration::from_millis(30), &json!({}));
+
+        let registry: &'static Registry = Box::leak(Box::new(Registry::new()));
+        let histograms = sink.take();
+        assert_eq!(3, histograms.len());
+        let key: HistogramKey = ("test_hist".into(), vec![("shard".into(), "0".into())]);
+        let summary = histograms[&key].summary();
+        assert_eq!(10, summary.count);
+        assert_eq!(10_000, summary.max);
+        assert_eq!(55_000, summary.sum);
+        let entry = summary_entry(&key.0, &key.1, "ts", &summary);
+        assert_eq!("summary", entry["k"]);
+        assert_eq!("0", entry["labels"]["shard"]);
+        assert_eq!(10, entry["count"]);
+        assert_eq!(10_000, entry["max_us"]);
+
+        for ((label, labels), histogram) in histograms {
+            set_summary_gauges(registry, &label, &labels, &histogram.summary());
+        }
+        let samples = parse_samples(&registry.render()).unwrap();
+        let max = samples
+            .iter()
+            .find(|s| {
+                s.name == "slimchain_time_summary_seconds"
+                    && s.labels.get("shard").map(String::as_str) == Some("a")
+                    && s.labels.get("quantile").map(String::as_str) == Some("1")
+            })
+            .unwrap();
+        assert!((max.value - 0.02).abs() < 1e-9);
+        assert!(sink.take().is_empty());
+    }
+
+    #[test]
+    fn test_validate_metrics_config() {
+        let mut cfg = MetricsConfig {
+            flush_interval: Duration::from_millis(0),
+            ..Default::default()
+        };
+        assert!(cfg.validate_config("metrics").is_ok());
+        cfg.mode = MetricsMode::Aggregate;
+        let errors = cfg.validate_config("metrics").unwrap_err();
+        assert!(errors.contains("metrics.flush_interval"));
+    }
+
+    #[test]
+    fn test_flush_on_drop() {
+        let registry: &'static Registry = Box::leak(Box::new(Registry::new()));
+        let sink = HistogramSink::new(Vec::new());
+        let flusher = HistogramFlusher::spawn(
+            sink.clone(),
+            Duration::from_secs(3600),
+            false,
+            Some(registry),
+        );
+        sink.record_time("test_flush", Duration::from_millis(5), &json!({}));
+        drop(flusher);
+        assert!(sink.take().is_empty());
+        assert!(registry
+            .render()
+            .contains("slimchain_time_summary_count{label=\"test_flush\"} 1\n"));
+    }
+}
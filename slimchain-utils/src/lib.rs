@@ -1,34 +1,126 @@
 #[macro_use]
 pub extern crate tracing;
 
-use slimchain_common::error::{Error, Result};
-use std::path::Path;
-use tracing_subscriber::EnvFilter;
+use logging::{log_filter, LogConfig, LogFormat};
+use metrics_file::MetricsFileConfig;
+use once_cell::sync::OnceCell;
+use slimchain_common::error::{anyhow, Error, Result};
+use std::{collections::BTreeMap, path::Path};
+use tracing_subscriber::{
+    fmt::{format, MakeWriter, SubscriberBuilder},
+    EnvFilter,
+};
 
+pub mod backpressure;
 pub mod config;
 pub mod contract;
+pub mod histogram;
+pub mod logging;
 pub mod metrics;
+pub mod metrics_csv;
+pub mod metrics_file;
 pub mod ordered_stream;
 pub mod path;
+pub mod prometheus;
+pub mod rng;
 pub mod serde;
 
 pub use bytes;
 pub use chrono;
 pub use toml;
 
-pub fn init_tracing_subscriber(default_level: &str) -> Result<()> {
-    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| {
-        EnvFilter::new(format!("slimchain={},warp::reject=off,warn", default_level))
-    });
+type ReloadTracingFilterFn = Box<dyn Fn(EnvFilter) -> Result<()> + Send + Sync>;
+
+static RELOAD_TRACING_FILTER: OnceCell<ReloadTracingFilterFn> = OnceCell::new();
+
+/// The JSON formatter of the logs, where the fields of the current span, e.g., `height` and
+/// `tx_id` of the instrumented functions, and the list of the entered spans are the
+/// structured keys besides the fields of the event.
+pub fn json_subscriber_builder<W: MakeWriter + 'static>(
+    filter: EnvFilter,
+    writer: W,
+) -> SubscriberBuilder<format::JsonFields, format::Format<format::Json>, EnvFilter, W> {
     tracing_subscriber::fmt()
+        .json()
+        .flatten_event(true)
+        .with_current_span(true)
+        .with_span_list(true)
         .with_env_filter(filter)
-        .try_init()
-        .map_err(Error::msg)
+        .with_writer(writer)
+}
+
+macro_rules! try_init_with_reloading {
+    ($builder: expr) => {{
+        let builder = $builder.with_filter_reloading();
+        let handle = builder.reload_handle();
+        builder.try_init().map_err(Error::msg)?;
+        RELOAD_TRACING_FILTER
+            .set(Box::new(move |filter| {
+                handle.reload(filter).map_err(Error::msg)
+            }))
+            .ok();
+    }};
+}
+
+pub fn init_tracing_subscriber(default_level: &str) -> Result<()> {
+    init_tracing_subscriber_with_config(default_level, &LogConfig::default())
+}
+
+/// Set up the log output in the format of `cfg`, which is written to both stdout and the log
+/// file if set. `RUST_LOG` overrides `default_level` and `cfg.targets`.
+pub fn init_tracing_subscriber_with_config(default_level: &str, cfg: &LogConfig) -> Result<()> {
+    let filter = match EnvFilter::try_from_default_env() {
+        Ok(filter) => filter,
+        Err(_) => log_filter(default_level, &cfg.targets)?,
+    };
+    let writer = cfg.writer()?;
+    let ansi = !writer.has_file();
+    match cfg.format() {
+        LogFormat::Plain => try_init_with_reloading!(tracing_subscriber::fmt()
+            .with_env_filter(filter)
+            .with_ansi(ansi)
+            .with_writer(writer)),
+        LogFormat::Compact => try_init_with_reloading!(tracing_subscriber::fmt()
+            .compact()
+            .with_env_filter(filter)
+            .with_ansi(ansi)
+            .with_writer(writer)),
+        LogFormat::Json => try_init_with_reloading!(json_subscriber_builder(filter, writer)),
+    }
+    Ok(())
+}
+
+/// Replace the log filter set by [`init_tracing_subscriber`], e.g., on the reload of the `log`
+/// config.
+pub fn reload_tracing_filter(filter: EnvFilter) -> Result<()> {
+    let reload = RELOAD_TRACING_FILTER
+        .get()
+        .ok_or_else(|| anyhow!("Tracing subscriber is not initialized."))?;
+    reload(filter)
+}
+
+/// Replace the log level set by [`init_tracing_subscriber`], dropping the per-target levels.
+pub fn reload_tracing_level(level: &str) -> Result<()> {
+    reload_tracing_filter(log_filter(level, &BTreeMap::new())?)
 }
 
 pub fn init_tracing(default_level: &str, metrics_file: &Path) -> Result<metrics::Guard> {
-    init_tracing_subscriber(default_level)?;
-    metrics::init_metrics_subscriber_using_file(metrics_file)
+    init_tracing_with_config(
+        default_level,
+        &LogConfig::default(),
+        metrics_file,
+        MetricsFileConfig::default(),
+    )
+}
+
+pub fn init_tracing_with_config(
+    default_level: &str,
+    log_cfg: &LogConfig,
+    metrics_file: &Path,
+    metrics_file_cfg: MetricsFileConfig,
+) -> Result<metrics::Guard> {
+    init_tracing_subscriber_with_config(default_level, log_cfg)?;
+    metrics::init_metrics_subscriber_using_rotating_file(metrics_file, metrics_file_cfg)
 }
 
 pub fn init_tracing_for_test() -> Option<metrics::Guard> {
@@ -0,0 +1,446 @@
+use crate::config::{join_path, ConfigErrors, ValidateConfig};
+use serde::Deserialize;
+use slimchain_common::error::{anyhow, bail, Result};
+use std::{
+    collections::BTreeMap,
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
+use tracing_subscriber::{
+    filter::{Directive, LevelFilter},
+    fmt::MakeWriter,
+    EnvFilter,
+};
+
+/// Overrides `log.format` in the config.
+pub const LOG_FORMAT_ENV: &str = "SLIMCHAIN_LOG_FORMAT";
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// The human-readable lines.
+    Plain,
+    /// The human-readable lines without the span contexts.
+    Compact,
+    /// One JSON object per line, with the fields of the current span and the list of the
+    /// entered spans as the structured keys.
+    Json,
+}
+
+impl Default for LogFormat {
+    fn default() -> Self {
+        LogFormat::Plain
+    }
+}
+
+impl FromStr for LogFormat {
+    type Err = slimchain_common::error::Error;
+
+    fn from_str(input: &str) -> Result<Self> {
+        match input.to_lowercase().as_str() {
+            "plain" => Ok(LogFormat::Plain),
+            "compact" => Ok(LogFormat::Compact),
+            "json" => Ok(LogFormat::Json),
+            _ => bail!("Unknown log format {}.", input),
+        }
+    }
+}
+
+impl LogFormat {
+    /// The format set by [`LOG_FORMAT_ENV`], if any.
+    pub fn from_env() -> Option<Self> {
+        let value = std::env::var(LOG_FORMAT_ENV).ok()?;
+        match value.parse() {
+            Ok(format) => Some(format),
+            Err(e) => {
+                eprintln!("Ignore {}. Error: {}", LOG_FORMAT_ENV, e);
+                None
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct LogConfig {
+    /// The log level, overridden by `--log-level`. Default info.
+    pub level: Option<String>,
+    /// Possible values: plain, compact, json. Default plain.
+    pub format: LogFormat,
+    /// Also write the logs to this file. If missing, they are only written to the terminal.
+    pub file: Option<PathBuf>,
+    /// Rotate the log file once it exceeds this number of bytes. 0 means never. Default 100 MiB.
+    pub max_file_size: u64,
+    /// Number of the rotated log files kept besides the current one. Default 5.
+    pub max_files: usize,
+    /// The levels of the modules overriding `level`, e.g., `slimchain_network = "debug"`.
+    /// `default` is the level of the targets not listed, which is warn for the crates other
+    /// than slimchain unless set.
+    pub targets: BTreeMap<String, String>,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            level: None,
+            format: LogFormat::default(),
+            file: None,
+            max_file_size: 100 << 20,
+            max_files: 5,
+            targets: BTreeMap::new(),
+        }
+    }
+}
+
+impl ValidateConfig for LogConfig {
+    fn validate_at(&self, path: &str, errors: &mut ConfigErrors) {
+        if let Some(level) = self.level.as_deref() {
+            if let Err(e) = parse_level("level", level) {
+                errors.push(join_path(path, "level"), e.to_string());
+            }
+        }
+        for (target, level) in &self.targets {
+            if let Err(e) = target_directive(target, level) {
+                errors.push(
+                    join_path(path, &format!("targets.{}", target)),
+                    e.to_string(),
+                );
+            }
+        }
+    }
+}
+
+const DEFAULT_TARGET: &str = "default";
+
+fn parse_level(key: &str, level: &str) -> Result<LevelFilter> {
+    level
+        .parse()
+        .map_err(|e| anyhow!("Invalid log level `{}` of `{}`. Error: {}", level, key, e))
+}
+
+fn target_directive(target: &str, level: &str) -> Result<Directive> {
+    let level = parse_level(target, level)?;
+    if target == DEFAULT_TARGET {
+        return Ok(level.into());
+    }
+    format!("{}={}", target, level)
+        .parse()
+        .map_err(|e| anyhow!("Invalid log target `{}`. Error: {}", target, e))
+}
+
+/// Compile the log filter, where the slimchain crates log at `level` and the others at warn,
+/// unless overridden by `targets`, e.g., `slimchain_network = "debug"`. It reports the first
+/// invalid entry.
+pub fn log_filter(level: &str, targets: &BTreeMap<String, String>) -> Result<EnvFilter> {
+    let level = parse_level("level", level)?;
+    let mut filter = EnvFilter::default()
+        .add_directive(LevelFilter::WARN.into())
+        .add_directive(format!("slimchain={}", level).parse()?)
+        .add_directive("warp::reject=off".parse()?);
+    for (target, level) in targets {
+        filter = filter.add_directive(target_directive(target, level)?);
+    }
+    Ok(filter)
+}
+
+impl LogConfig {
+    /// The format set by [`LOG_FORMAT_ENV`], or `format` if none.
+    pub fn format(&self) -> LogFormat {
+        LogFormat::from_env().unwrap_or(self.format)
+    }
+
+    pub fn writer(&self) -> Result<LogWriter> {
+        let file = match &self.file {
+            Some(path) => Some(Arc::new(Mutex::new(RotatingFile::open(
+                path,
+                self.max_file_size,
+                self.max_files,
+            )?))),
+            None => None,
+        };
+        Ok(LogWriter { file })
+    }
+}
+
+/// Write the logs to stdout, and also to the log file if set.
+#[derive(Clone)]
+pub struct LogWriter {
+    file: Option<Arc<Mutex<RotatingFile>>>,
+}
+
+impl LogWriter {
+    pub fn stdout() -> Self {
+        Self { file: None }
+    }
+
+    pub fn has_file(&self) -> bool {
+        self.file.is_some()
+    }
+}
+
+impl MakeWriter for LogWriter {
+    type Writer = Self;
+
+    fn make_writer(&self) -> Self::Writer {
+        self.clone()
+    }
+}
+
+impl Write for LogWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if let Some(file) = &self.file {
+            let mut file = file.lock().expect("Failed to lock the log file.");
+            file.write_all(buf)?;
+        }
+        io::stdout().write_all(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if let Some(file) = &self.file {
+            file.lock().expect("Failed to lock the log file.").flush()?;
+        }
+        io::stdout().flush()
+    }
+}
+
+/// A file rotated by its size. Once it exceeds `max_size`, `<path>` is renamed to `<path>.1`,
+/// `<path>.1` to `<path>.2`, and so on, where the ones beyond `max_files` are removed.
+pub struct RotatingFile {
+    path: PathBuf,
+    max_size: u64,
+    max_files: usize,
+    file: File,
+    size: u64,
+}
+
+impl RotatingFile {
+    pub fn open(path: &Path, max_size: u64, max_files: usize) -> Result<Self> {
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let size = file.metadata()?.len();
+        Ok(Self {
+            path: path.to_path_buf(),
+            max_size,
+            max_files,
+            file,
+            size,
+        })
+    }
+
+    fn rotated_path(&self, index: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{}", index));
+        path.into()
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        if self.max_files > 0 {
+            let oldest = self.rotated_path(self.max_files);
+            if oldest.exists() {
+                fs::remove_file(&oldest)?;
+            }
+            for i in (1..self.max_files).rev() {
+                let from = self.rotated_path(i);
+                if from.exists() {
+                    fs::rename(&from, self.rotated_path(i + 1))?;
+                }
+            }
+            fs::rename(&self.path, self.rotated_path(1))?;
+        }
+        self.file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.max_size > 0 && self.size > 0 && self.size + buf.len() as u64 > self.max_size {
+            self.rotate()?;
+        }
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn temp_dir() -> PathBuf {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let dir = std::env::temp_dir().join(format!(
+            "slimchain-logging-test-{}-{}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::SeqCst)
+        ));
+        fs::remove_dir_all(&dir).ok();
+        dir
+    }
+
+    #[test]
+    fn test_log_format() {
+        assert_eq!(LogFormat::Json, "JSON".parse().unwrap());
+        assert_eq!(LogFormat::Compact, "compact".parse().unwrap());
+        assert!("xml".parse::<LogFormat>().is_err());
+
+        let cfg: LogConfig = toml::from_str("format = \"json\"\nfile = \"a.log\"\n").unwrap();
+        assert_eq!(LogFormat::Json, cfg.format);
+        assert_eq!(Some(PathBuf::from("a.log")), cfg.file);
+        assert_eq!(5, cfg.max_files);
+    }
+
+    #[test]
+    fn test_validate_log_config() {
+        assert!(LogConfig::default().validate_config("log").is_ok());
+        let cfg = LogConfig {
+            level: Some("verbose!".to_string()),
+            ..Default::default()
+        };
+        assert!(cfg
+            .validate_config("log")
+            .unwrap_err()
+            .contains("log.level"));
+    }
+
+    #[derive(Clone, Default)]
+    struct BufWriter(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for BufWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tracing::instrument]
+    fn import_block(height: u64) {
+        info!(tx_id = 3, "Import a tx.");
+    }
+
+    #[test]
+    fn test_json_output() {
+        let buf = BufWriter::default();
+        let writer = buf.clone();
+        let subscriber =
+            crate::json_subscriber_builder(EnvFilter::new("trace"), move || writer.clone())
+                .finish();
+        tracing::subscriber::with_default(subscriber, || import_block(5));
+
+        let output = String::from_utf8(buf.0.lock().unwrap().clone()).unwrap();
+        let line: serde_json::Value = serde_json::from_str(output.trim()).unwrap();
+        assert_eq!("INFO", line["level"]);
+        assert_eq!("Import a tx.", line["message"]);
+        assert_eq!(3, line["tx_id"]);
+        assert_eq!("import_block", line["span"]["name"]);
+        assert_eq!(5, line["span"]["height"]);
+        assert_eq!("import_block", line["spans"][0]["name"]);
+    }
+
+    #[test]
+    fn test_log_filter() {
+        let mut targets = BTreeMap::new();
+        targets.insert("slimchain_network".to_string(), "debug".to_string());
+        targets.insert("default".to_string(), "info".to_string());
+        assert!(log_filter("info", &targets).is_ok());
+
+        targets.insert("slimchain_merkle_trie".to_string(), "loud".to_string());
+        let err = log_filter("info", &targets).unwrap_err().to_string();
+        assert!(err.contains("slimchain_merkle_trie"));
+
+        let cfg = LogConfig {
+            targets,
+            ..Default::default()
+        };
+        let errors = cfg.validate_config("log").unwrap_err();
+        assert!(errors.contains("log.targets.slimchain_merkle_trie"));
+        assert_eq!(1, errors.errors().len());
+    }
+
+    #[test]
+    fn test_reload_target_level() {
+        let buf = BufWriter::default();
+        let writer = buf.clone();
+        let mut targets = BTreeMap::new();
+        targets.insert("slimchain_merkle_trie".to_string(), "warn".to_string());
+        let builder = tracing_subscriber::fmt()
+            .with_env_filter(log_filter("info", &targets).unwrap())
+            .with_filter_reloading()
+            .with_ansi(false)
+            .with_writer(move || writer.clone());
+        let handle = builder.reload_handle();
+        let log_lines = || {
+            let output = String::from_utf8(buf.0.lock().unwrap().clone()).unwrap();
+            buf.0.lock().unwrap().clear();
+            output.lines().map(str::to_string).collect::<Vec<_>>()
+        };
+
+        tracing::subscriber::with_default(builder.finish(), || {
+            let log_all = || {
+                debug!(target: "slimchain_network::p2p", "network debug");
+                info!(target: "slimchain_merkle_trie::write", "trie info");
+                info!(target: "slimchain_chain", "chain info");
+            };
+
+            log_all();
+            let lines = log_lines();
+            assert_eq!(1, lines.len());
+            assert!(lines[0].contains("chain info"));
+
+            targets.insert("slimchain_network".to_string(), "debug".to_string());
+            targets.insert("slimchain_merkle_trie".to_string(), "info".to_string());
+            handle
+                .reload(log_filter("info", &targets).unwrap())
+                .unwrap();
+            log_all();
+            let lines = log_lines();
+            assert_eq!(3, lines.len());
+            assert!(lines[0].contains("network debug"));
+            assert!(lines[1].contains("trie info"));
+        });
+    }
+
+    #[test]
+    fn test_rotating_file() {
+        let dir = temp_dir();
+        let path = dir.join("node.log");
+        let mut file = RotatingFile::open(&path, 10, 2).unwrap();
+        for line in &["aaaaaaaa\n", "bbbbbbbb\n", "cccccccc\n", "dddddddd\n"] {
+            file.write_all(line.as_bytes()).unwrap();
+        }
+        file.flush().unwrap();
+
+        let read = |path: PathBuf| fs::read_to_string(path).unwrap();
+        assert_eq!("dddddddd\n", read(path.clone()));
+        assert_eq!("cccccccc\n", read(dir.join("node.log.1")));
+        assert_eq!("bbbbbbbb\n", read(dir.join("node.log.2")));
+        assert!(!dir.join("node.log.3").exists());
+
+        let mut file = RotatingFile::open(&path, 10, 2).unwrap();
+        file.write_all(b"e\n").unwrap();
+        file.flush().unwrap();
+        assert_eq!("dddddddd\ne\n", read(path));
+        fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -1,83 +1,264 @@
 pub use serde_json;
 
-use crossbeam_channel::{bounded, Sender};
-use once_cell::sync::OnceCell;
-use serde_json::Value as JsonValue;
+use crate::metrics_file::{MetricsFileConfig, RotatingMetricsFile};
+use chrono::{DateTime, SecondsFormat, Utc};
+use crossbeam_channel::{bounded, RecvTimeoutError, Sender};
+use once_cell::sync::{Lazy, OnceCell};
+use serde_json::{json, Value as JsonValue};
 use slimchain_common::error::{anyhow, Result};
 use std::{
-    fs,
-    io::{BufWriter, Write},
+    cell::RefCell,
+    collections::HashMap,
+    io::Write,
     path::Path,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex, MutexGuard, RwLock,
+    },
     thread::{self, JoinHandle},
+    time::{Duration, Instant},
 };
 
 const BUFFERED_ENTRY_SIZE: usize = 10_000;
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+const THREAD_BUFFER_SIZE: usize = 256;
+const DRAIN_INTERVAL: Duration = Duration::from_millis(100);
 pub static METRICS_DISPATCH: OnceCell<Dispatch> = OnceCell::new();
 
+static THREAD_BUFFERING: AtomicBool = AtomicBool::new(true);
+static NEXT_DISPATCH_ID: AtomicUsize = AtomicUsize::new(0);
+static NEXT_THREAD_ID: AtomicU64 = AtomicU64::new(1);
+
+thread_local! {
+    static THREAD_ID: u64 = NEXT_THREAD_ID.fetch_add(1, Ordering::Relaxed);
+    static THREAD_BUFFERS: RefCell<HashMap<usize, Arc<ThreadBuffer>>> =
+        RefCell::new(HashMap::new());
+}
+
+/// Whether [`LogSink`] appends the records to a buffer of the calling thread, which is moved to
+/// the dispatch thread once full or every 100ms, instead of sending an entry per call.
+/// Default true.
+pub fn set_thread_buffering(enabled: bool) {
+    THREAD_BUFFERING.store(enabled, Ordering::Relaxed);
+}
+
+pub fn thread_buffering() -> bool {
+    THREAD_BUFFERING.load(Ordering::Relaxed)
+}
+
+/// The id of the calling thread, i.e., the `tid` of its entries in the metrics file.
+pub fn metrics_thread_id() -> u64 {
+    THREAD_ID.try_with(|tid| *tid).unwrap_or_default()
+}
+
+/// A metric recorded by [`LogSink`]. It is turned into the JSON entry by the dispatch thread,
+/// so that the recording thread skips the formatting.
+struct MetricRecord {
+    tid: u64,
+    ts: DateTime<Utc>,
+    label: String,
+    time: Option<Duration>,
+    fields: JsonValue,
+}
+
+impl MetricRecord {
+    fn new(label: &str, time: Option<Duration>, fields: &JsonValue) -> Self {
+        Self {
+            tid: metrics_thread_id(),
+            ts: Utc::now(),
+            label: label.to_string(),
+            time,
+            fields: fields.clone(),
+        }
+    }
+
+    fn to_entry(&self) -> JsonValue {
+        let ts = self.ts.to_rfc3339_opts(SecondsFormat::Micros, true);
+        let mut entry = match self.time {
+            Some(time) => time_entry(&self.label, &ts, time, &self.fields),
+            None => event_entry(&self.label, &ts, &self.fields),
+        };
+        entry["tid"] = self.tid.into();
+        entry
+    }
+}
+
+#[derive(Default)]
+struct ThreadBuffer {
+    records: Mutex<Vec<MetricRecord>>,
+}
+
+impl ThreadBuffer {
+    fn records(&self) -> MutexGuard<'_, Vec<MetricRecord>> {
+        self.records.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Send the records while holding the lock, so that the batches of a thread are queued in
+    /// order no matter whether it or the drainer sends them.
+    fn send_to(&self, sender: &Sender<DispatchEvent>, full_only: bool) {
+        let mut records = self.records();
+        if records.is_empty() || (full_only && records.len() < THREAD_BUFFER_SIZE) {
+            return;
+        }
+        let records = std::mem::replace(&mut *records, Vec::with_capacity(THREAD_BUFFER_SIZE));
+        sender.try_send(DispatchEvent::Records(records)).ok();
+    }
+}
+
 pub struct Dispatch {
+    id: usize,
     sender: Sender<DispatchEvent>,
+    buffers: Mutex<Vec<Arc<ThreadBuffer>>>,
 }
 
 impl Dispatch {
+    fn new(sender: Sender<DispatchEvent>) -> Self {
+        Self {
+            id: NEXT_DISPATCH_ID.fetch_add(1, Ordering::Relaxed),
+            sender,
+            buffers: Mutex::new(Vec::new()),
+        }
+    }
+
     pub fn add_entry(&self, value: JsonValue) {
         self.sender.try_send(DispatchEvent::Entry(value)).ok();
     }
+
+    fn add_record(&self, record: MetricRecord) {
+        if !thread_buffering() {
+            self.add_entry(record.to_entry());
+            return;
+        }
+
+        let buffer = THREAD_BUFFERS.try_with(|buffers| {
+            buffers
+                .borrow_mut()
+                .entry(self.id)
+                .or_insert_with(|| {
+                    let buffer = Arc::new(ThreadBuffer::default());
+                    self.buffers().push(buffer.clone());
+                    buffer
+                })
+                .clone()
+        });
+
+        match buffer {
+            Ok(buffer) => {
+                buffer.records().push(record);
+                buffer.send_to(&self.sender, true);
+            }
+            // The thread locals are gone once the thread is exiting.
+            Err(_) => self.add_entry(record.to_entry()),
+        }
+    }
+
+    fn buffers(&self) -> MutexGuard<'_, Vec<Arc<ThreadBuffer>>> {
+        self.buffers.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Move the records buffered by all the threads to the dispatch thread. The buffers of the
+    /// exited threads are dropped once empty.
+    fn drain_thread_buffers(&self) {
+        self.buffers().retain(|buffer| {
+            buffer.send_to(&self.sender, false);
+            Arc::strong_count(buffer) > 1
+        });
+    }
 }
 
 enum DispatchEvent {
     Shutdown,
     Entry(JsonValue),
+    Records(Vec<MetricRecord>),
 }
 
 pub struct Guard {
-    sender: Sender<DispatchEvent>,
     handler: Option<JoinHandle<()>>,
 }
 
 impl Guard {
-    fn new(writer: impl Write + Send + Sync + 'static) -> Result<Self> {
+    fn new(writer: impl EntryWriter) -> Result<Self> {
         let (tx, rx) = bounded(BUFFERED_ENTRY_SIZE);
         METRICS_DISPATCH
-            .set(Dispatch { sender: tx.clone() })
+            .set(Dispatch::new(tx))
             .map_err(|_e| anyhow!("Metrics already init."))?;
+        let dispatch = METRICS_DISPATCH.get().expect("Metrics is just init.");
         let handler = thread::spawn(move || {
             let mut writer = writer;
-            while let Ok(entry) = rx.recv() {
-                match entry {
-                    DispatchEvent::Shutdown => break,
-                    DispatchEvent::Entry(value) => {
-                        serde_json::to_writer(&mut writer, &value).ok();
-                        writeln!(writer).ok();
+            let mut last_drain = Instant::now();
+            let mut last_flush = Instant::now();
+            loop {
+                match rx.recv_timeout(DRAIN_INTERVAL) {
+                    Ok(DispatchEvent::Entry(value)) => writer.write_entry(&value),
+                    Ok(DispatchEvent::Records(records)) => {
+                        for record in &records {
+                            writer.write_entry(&record.to_entry());
+                        }
                     }
+                    Ok(DispatchEvent::Shutdown) | Err(RecvTimeoutError::Disconnected) => break,
+                    Err(RecvTimeoutError::Timeout) => {}
+                }
+                if last_drain.elapsed() >= DRAIN_INTERVAL {
+                    dispatch.drain_thread_buffers();
+                    last_drain = Instant::now();
+                }
+                if last_flush.elapsed() >= FLUSH_INTERVAL {
+                    writer.flush();
+                    last_flush = Instant::now();
                 }
             }
-            writer.flush().ok();
+            writer.flush();
         });
         Ok(Self {
-            sender: tx,
             handler: Some(handler),
         })
     }
 }
 
+/// Where the dispatch thread of [`Guard`] writes the metrics entries. It is flushed every
+/// second.
+pub trait EntryWriter: Send + 'static {
+    fn write_entry(&mut self, entry: &JsonValue);
+
+    fn flush(&mut self);
+}
+
+/// Write the entries as JSON lines to `W`.
+pub struct JsonLinesWriter<W>(pub W);
+
+impl<W: Write + Send + 'static> EntryWriter for JsonLinesWriter<W> {
+    fn write_entry(&mut self, entry: &JsonValue) {
+        write_entry(&mut self.0, entry);
+    }
+
+    fn flush(&mut self) {
+        self.0.flush().ok();
+    }
+}
+
+impl EntryWriter for RotatingMetricsFile {
+    fn write_entry(&mut self, entry: &JsonValue) {
+        RotatingMetricsFile::write_entry(self, entry).ok();
+    }
+
+    fn flush(&mut self) {
+        RotatingMetricsFile::flush(self).ok();
+    }
+}
+
 impl Drop for Guard {
     fn drop(&mut self) {
-        self.sender.send(DispatchEvent::Shutdown).ok();
+        // Queue the tails of the thread buffers before the shutdown, so that none is lost.
+        if let Some(dispatch) = METRICS_DISPATCH.get() {
+            dispatch.drain_thread_buffers();
+            dispatch.sender.send(DispatchEvent::Shutdown).ok();
+        }
         if let Some(handler) = self.handler.take() {
             handler.join().ok();
         }
     }
 }
 
-#[macro_export]
-macro_rules! __record_entry {
-    ($x:expr) => {
-        if let Some(dispatch) = $crate::metrics::METRICS_DISPATCH.get() {
-            dispatch.add_entry($x);
-        }
-    };
-}
-
 #[macro_export]
 macro_rules! record_time {
     ($label:literal, $time:expr) => {
@@ -86,16 +267,8 @@ macro_rules! record_time {
     ($label:literal, $time:expr, $($fields:tt)*) => {
         match $time {
             t => {
-                let ts = $crate::chrono::Utc::now().to_rfc3339_opts($crate::chrono::SecondsFormat::Micros, true);
                 let fields = $crate::metrics::serde_json::json!({ $($fields)* });
-                let entry = $crate::metrics::serde_json::json!({
-                    "k": "time",
-                    "l": $label,
-                    "ts": ts.as_str(),
-                    "t_in_us": t.as_micros() as u64,
-                    "v": fields,
-                });
-                $crate::__record_entry!(entry);
+                $crate::metrics::with_metrics_sink(|sink| sink.record_time($label, t, &fields));
             }
         }
     };
@@ -107,34 +280,211 @@ macro_rules! record_event {
         $crate::record_event!($label,);
     };
     ($label:literal, $($fields:tt)*) => {{
-        let ts = $crate::chrono::Utc::now().to_rfc3339_opts($crate::chrono::SecondsFormat::Micros, true);
         let fields = $crate::metrics::serde_json::json!({ $($fields)* });
-        let entry = $crate::metrics::serde_json::json!({
-            "k": "event",
-            "l": $label,
-            "ts": ts.as_str(),
-            "v": fields,
-        });
-        $crate::__record_entry!(entry);
+        $crate::metrics::with_metrics_sink(|sink| sink.record_event($label, &fields));
     }};
 }
 
+/// The backend of the `record_time!` and `record_event!` macros.
+pub trait MetricsSink: Send + Sync {
+    /// Record the `time` of `label` with the `fields` passed to `record_time!`.
+    fn record_time(&self, label: &str, time: Duration, fields: &JsonValue);
+
+    /// Record the event `label` with the `fields` passed to `record_event!`.
+    fn record_event(&self, label: &str, fields: &JsonValue);
+
+    /// Whether the records are kept anywhere. The callers may skip building the costly fields if
+    /// not.
+    fn enabled(&self) -> bool {
+        true
+    }
+}
+
+static METRICS_SINK: Lazy<RwLock<Box<dyn MetricsSink>>> =
+    Lazy::new(|| RwLock::new(Box::new(LogSink)));
+
+/// Replace the sink of the `record_time!` and `record_event!` macros, which is [`LogSink`] by
+/// default. Return the replaced one.
+pub fn set_metrics_sink(sink: Box<dyn MetricsSink>) -> Box<dyn MetricsSink> {
+    let mut guard = METRICS_SINK.write().unwrap_or_else(|e| e.into_inner());
+    std::mem::replace(&mut *guard, sink)
+}
+
+/// Whether the sink of the `record_time!` and `record_event!` macros keeps the records, e.g.,
+/// [`LogSink`] before the metrics file is set up does not.
+pub fn metrics_enabled() -> bool {
+    let guard = METRICS_SINK.read().unwrap_or_else(|e| e.into_inner());
+    guard.enabled()
+}
+
+#[doc(hidden)]
+pub fn with_metrics_sink(f: impl FnOnce(&dyn MetricsSink)) {
+    let guard = METRICS_SINK.read().unwrap_or_else(|e| e.into_inner());
+    f(guard.as_ref());
+}
+
+/// The entry of a time written to the metrics file.
+pub fn time_entry(label: &str, ts: &str, time: Duration, fields: &JsonValue) -> JsonValue {
+    json!({
+        "k": "time",
+        "l": label,
+        "ts": ts,
+        "t_in_us": time.as_micros() as u64,
+        "v": fields,
+    })
+}
+
+/// The entry of an event written to the metrics file.
+pub fn event_entry(label: &str, ts: &str, fields: &JsonValue) -> JsonValue {
+    json!({
+        "k": "event",
+        "l": label,
+        "ts": ts,
+        "v": fields,
+    })
+}
+
+/// Write the entries as JSON lines to the metrics file set up by [`init_metrics_subscriber`].
+/// Nothing is written before that. The entries of a thread keep the order they are recorded
+/// in, and carry its id as `tid`. See [`set_thread_buffering`].
+#[derive(Debug, Default, Copy, Clone)]
+pub struct LogSink;
+
+impl MetricsSink for LogSink {
+    fn record_time(&self, label: &str, time: Duration, fields: &JsonValue) {
+        if let Some(dispatch) = METRICS_DISPATCH.get() {
+            dispatch.add_record(MetricRecord::new(label, Some(time), fields));
+        }
+    }
+
+    fn record_event(&self, label: &str, fields: &JsonValue) {
+        if let Some(dispatch) = METRICS_DISPATCH.get() {
+            dispatch.add_record(MetricRecord::new(label, None, fields));
+        }
+    }
+
+    fn enabled(&self) -> bool {
+        METRICS_DISPATCH.get().is_some()
+    }
+}
+
+/// Tee the metrics to all of the sinks in order.
+#[derive(Default)]
+pub struct FanOutSink {
+    sinks: Vec<Box<dyn MetricsSink>>,
+}
+
+impl FanOutSink {
+    pub fn new(sinks: Vec<Box<dyn MetricsSink>>) -> Self {
+        Self { sinks }
+    }
+
+    pub fn push(&mut self, sink: Box<dyn MetricsSink>) {
+        self.sinks.push(sink);
+    }
+}
+
+impl MetricsSink for FanOutSink {
+    fn record_time(&self, label: &str, time: Duration, fields: &JsonValue) {
+        for sink in &self.sinks {
+            sink.record_time(label, time, fields);
+        }
+    }
+
+    fn record_event(&self, label: &str, fields: &JsonValue) {
+        for sink in &self.sinks {
+            sink.record_event(label, fields);
+        }
+    }
+
+    fn enabled(&self) -> bool {
+        self.sinks.iter().any(|sink| sink.enabled())
+    }
+}
+
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub struct TimeStats {
+    pub count: u64,
+    pub total: Duration,
+    pub min: Duration,
+    pub max: Duration,
+}
+
+#[derive(Debug, Default)]
+struct Aggregate {
+    times: HashMap<String, TimeStats>,
+    events: HashMap<String, u64>,
+}
+
+/// Aggregate the metrics in memory, e.g., to check them in tests. The clones share the same
+/// aggregate, so that one of them can be kept after the other is set as the sink.
+#[derive(Debug, Default, Clone)]
+pub struct AggregatingSink {
+    inner: Arc<Mutex<Aggregate>>,
+}
+
+impl AggregatingSink {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn inner(&self) -> MutexGuard<'_, Aggregate> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn time_stats(&self, label: &str) -> Option<TimeStats> {
+        self.inner().times.get(label).copied()
+    }
+
+    pub fn event_count(&self, label: &str) -> u64 {
+        self.inner().events.get(label).copied().unwrap_or_default()
+    }
+}
+
+impl MetricsSink for AggregatingSink {
+    fn record_time(&self, label: &str, time: Duration, _fields: &JsonValue) {
+        let mut inner = self.inner();
+        let stats = inner.times.entry(label.to_string()).or_insert(TimeStats {
+            count: 0,
+            total: Duration::default(),
+            min: time,
+            max: time,
+        });
+        stats.count += 1;
+        stats.total += time;
+        stats.min = stats.min.min(time);
+        stats.max = stats.max.max(time);
+    }
+
+    fn record_event(&self, label: &str, _fields: &JsonValue) {
+        *self.inner().events.entry(label.to_string()).or_default() += 1;
+    }
+}
+
+fn write_entry(writer: &mut impl Write, entry: &JsonValue) {
+    serde_json::to_writer(&mut *writer, entry).ok();
+    writeln!(writer).ok();
+}
+
 pub fn init_metrics_subscriber(writer: impl Write + Send + Sync + 'static) -> Result<Guard> {
-    Guard::new(writer)
+    Guard::new(JsonLinesWriter(writer))
 }
 
 pub fn init_metrics_subscriber_using_file(file: impl AsRef<Path>) -> Result<Guard> {
-    let f = fs::OpenOptions::new()
-        .create(true)
-        .append(true)
-        .open(file)?;
-    let w = BufWriter::new(f);
-    init_metrics_subscriber(w)
+    init_metrics_subscriber_using_rotating_file(file, MetricsFileConfig::default())
+}
+
+/// Write the metrics to `file`, which is rolled over as set by `cfg`.
+pub fn init_metrics_subscriber_using_rotating_file(
+    file: impl AsRef<Path>,
+    cfg: MetricsFileConfig,
+) -> Result<Guard> {
+    Guard::new(RotatingMetricsFile::open(file.as_ref(), cfg)?)
 }
 
 #[cfg(test)]
 mod tests {
-    use std::time::Duration;
+    use super::*;
 
     #[test]
     fn test() {
@@ -147,4 +497,209 @@ mod tests {
         tracing::error!("An error");
         tracing::info!("An info");
     }
+
+    #[test]
+    fn test_log_sink_output() {
+        // The entries built by the macros before they dispatched through `MetricsSink`.
+        let ts = "2021-01-01T00:00:00.000000Z";
+        let time = Duration::from_micros(2_200_001);
+        let fields = json!({ "foo": 1, "bar": "baz" });
+        let old_time = json!({
+            "k": "time",
+            "l": "test_time",
+            "ts": ts,
+            "t_in_us": time.as_micros() as u64,
+            "v": fields,
+        });
+        let old_event = json!({
+            "k": "event",
+            "l": "test_event",
+            "ts": ts,
+            "v": json!({}),
+        });
+
+        let mut expected = Vec::new();
+        write_entry(&mut expected, &old_time);
+        write_entry(&mut expected, &old_event);
+        let mut actual = Vec::new();
+        write_entry(&mut actual, &time_entry("test_time", ts, time, &fields));
+        write_entry(&mut actual, &event_entry("test_event", ts, &json!({})));
+        assert_eq!(
+            String::from_utf8(expected).unwrap(),
+            String::from_utf8(actual).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_aggregating_sink() {
+        let sink = AggregatingSink::new();
+        let fan_out = FanOutSink::new(vec![Box::new(sink.clone()), Box::new(LogSink)]);
+        fan_out.record_time("test_agg_time", Duration::from_millis(3), &json!({}));
+        fan_out.record_time("test_agg_time", Duration::from_millis(1), &json!({}));
+        fan_out.record_event("test_agg_event", &json!({ "id": 1 }));
+        assert_eq!(
+            Some(TimeStats {
+                count: 2,
+                total: Duration::from_millis(4),
+                min: Duration::from_millis(1),
+                max: Duration::from_millis(3),
+            }),
+            sink.time_stats("test_agg_time")
+        );
+        assert_eq!(1, sink.event_count("test_agg_event"));
+        assert_eq!(0, sink.event_count("test_agg_other"));
+        assert_eq!(None, sink.time_stats("test_agg_other"));
+    }
+
+    #[test]
+    fn test_set_metrics_sink() {
+        let sink = AggregatingSink::new();
+        let fan_out = FanOutSink::new(vec![Box::new(LogSink), Box::new(sink.clone())]);
+        let prev = set_metrics_sink(Box::new(fan_out));
+        record_event!("test_set_sink_event", "id": 1);
+        record_time!("test_set_sink_time", Duration::from_millis(2), "foo": 1);
+        set_metrics_sink(prev);
+        record_event!("test_set_sink_event", "id": 2);
+
+        assert_eq!(1, sink.event_count("test_set_sink_event"));
+        assert_eq!(1, sink.time_stats("test_set_sink_time").unwrap().count);
+    }
+
+    #[test]
+    fn test_sink_enabled() {
+        assert!(!FanOutSink::default().enabled());
+        let mut fan_out = FanOutSink::new(vec![Box::new(FanOutSink::default())]);
+        assert!(!fan_out.enabled());
+        fan_out.push(Box::new(AggregatingSink::new()));
+        assert!(fan_out.enabled());
+    }
+
+    fn collect_entries(rx: &crossbeam_channel::Receiver<DispatchEvent>) -> Vec<JsonValue> {
+        let mut entries = Vec::new();
+        for event in rx.try_iter() {
+            match event {
+                DispatchEvent::Entry(entry) => entries.push(entry),
+                DispatchEvent::Records(records) => {
+                    entries.extend(records.iter().map(MetricRecord::to_entry))
+                }
+                DispatchEvent::Shutdown => {}
+            }
+        }
+        entries
+    }
+
+    #[test]
+    fn test_thread_buffer_order() {
+        const THREADS: u64 = 4;
+        const RECORDS: u64 = THREAD_BUFFER_SIZE as u64 * 2 + 10;
+
+        let (tx, rx) = bounded(BUFFERED_ENTRY_SIZE);
+        let dispatch = Arc::new(Dispatch::new(tx));
+        let handles: Vec<_> = (0..THREADS)
+            .map(|_| {
+                let dispatch = dispatch.clone();
+                thread::spawn(move || {
+                    for id in 0..RECORDS {
+                        let fields = json!({ "id": id });
+                        dispatch.add_record(MetricRecord::new("test_buffer", None, &fields));
+                    }
+                    metrics_thread_id()
+                })
+            })
+            .collect();
+        let tids: Vec<u64> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+
+        // The full buffers are sent by the threads and the tails are left to the drainer.
+        let sent = collect_entries(&rx);
+        assert_eq!(
+            (THREADS * 2 * THREAD_BUFFER_SIZE as u64) as usize,
+            sent.len()
+        );
+        dispatch.drain_thread_buffers();
+        let entries: Vec<_> = sent.into_iter().chain(collect_entries(&rx)).collect();
+        assert_eq!((THREADS * RECORDS) as usize, entries.len());
+
+        for tid in tids {
+            let ids: Vec<u64> = entries
+                .iter()
+                .filter(|e| e["tid"] == tid)
+                .map(|e| e["v"]["id"].as_u64().unwrap())
+                .collect();
+            assert_eq!((0..RECORDS).collect::<Vec<_>>(), ids);
+        }
+        // The buffers of the exited threads are dropped.
+        assert!(dispatch.buffers().is_empty());
+    }
+
+    #[test]
+    fn test_thread_buffer_entry() {
+        let (tx, rx) = bounded(BUFFERED_ENTRY_SIZE);
+        let dispatch = Dispatch::new(tx);
+        let time = Duration::from_micros(1_500);
+        dispatch.add_record(MetricRecord::new(
+            "test_time",
+            Some(time),
+            &json!({ "foo": 1 }),
+        ));
+        dispatch.add_record(MetricRecord::new("test_event", None, &json!({})));
+        assert!(collect_entries(&rx).is_empty());
+        dispatch.drain_thread_buffers();
+
+        let entries = collect_entries(&rx);
+        assert_eq!(2, entries.len());
+        assert_eq!("time", entries[0]["k"]);
+        assert_eq!("test_time", entries[0]["l"]);
+        assert_eq!(1_500, entries[0]["t_in_us"]);
+        assert_eq!(json!({ "foo": 1 }), entries[0]["v"]);
+        assert_eq!("event", entries[1]["k"]);
+        assert_eq!(metrics_thread_id(), entries[1]["tid"]);
+        // The buffer of this thread is kept while it is alive.
+        assert_eq!(1, dispatch.buffers().len());
+    }
+
+    /// The per call cost of `LogSink` with and without the thread buffers. Run by
+    /// `cargo test -p slimchain-utils --release bench_record -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn bench_record() {
+        const CALLS: u32 = 200_000;
+
+        let bench = |buffered: bool| {
+            let (tx, rx) = bounded(BUFFERED_ENTRY_SIZE);
+            let dispatch = Dispatch::new(tx);
+            let drainer = thread::spawn(move || {
+                let mut writer = JsonLinesWriter(std::io::sink());
+                for event in rx {
+                    match event {
+                        DispatchEvent::Entry(entry) => writer.write_entry(&entry),
+                        DispatchEvent::Records(records) => {
+                            for record in &records {
+                                writer.write_entry(&record.to_entry());
+                            }
+                        }
+                        DispatchEvent::Shutdown => break,
+                    }
+                }
+            });
+
+            let fields = json!({ "height": 1, "tx_count": 512 });
+            let begin = Instant::now();
+            for _ in 0..CALLS {
+                let record = MetricRecord::new("bench_record", Some(begin.elapsed()), &fields);
+                if buffered {
+                    dispatch.add_record(record);
+                } else {
+                    dispatch.add_entry(record.to_entry());
+                }
+            }
+            let elapsed = begin.elapsed();
+            dispatch.drain_thread_buffers();
+            dispatch.sender.send(DispatchEvent::Shutdown).unwrap();
+            drainer.join().unwrap();
+            elapsed / CALLS
+        };
+
+        println!("direct:   {:?} per call", bench(false));
+        println!("buffered: {:?} per call", bench(true));
+    }
 }
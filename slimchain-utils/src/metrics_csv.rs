@@ -0,0 +1,495 @@
+//! Export the metrics as tidy CSV files, one per metric name, i.e., `<out_dir>/<metric>.csv`
+//! with the columns `timestamp,metric,value` followed by the label keys. The label keys are
+//! unioned across the rows of a file, where the cells of the absent labels are empty.
+//!
+//! * The times of `record_time!` have the values in microseconds, and the fields as the labels.
+//! * The events of `record_event!` have the value 1, and the fields as the labels.
+//! * The histogram summaries have a row per statistic, i.e., `count`, `p50_us`, `p95_us`,
+//!   `p99_us`, `max_us` and `sum_us`, labelled by `stat` and their group by labels.
+//!
+//! [`CsvSink`] writes them online, while [`convert_metrics_log_to_csv`] converts a metrics file
+//! written before.
+
+use crate::metrics::MetricsSink;
+use chrono::{SecondsFormat, Utc};
+use flate2::read::GzDecoder;
+use serde_json::Value as JsonValue;
+use slimchain_common::error::{Context as _, Result};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    ffi::OsStr,
+    fs::{self, File, OpenOptions},
+    io::{BufRead, BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::Duration,
+};
+
+const FIXED_COLUMNS: [&str; 3] = ["timestamp", "metric", "value"];
+const SUMMARY_STATS: [&str; 6] = ["count", "p50_us", "p95_us", "p99_us", "max_us", "sum_us"];
+
+/// A row of the CSV files.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MetricRow {
+    pub timestamp: String,
+    pub metric: String,
+    pub value: String,
+    pub labels: BTreeMap<String, String>,
+}
+
+impl MetricRow {
+    fn cells(&self, columns: &[String]) -> Vec<&str> {
+        let mut cells = vec![
+            self.timestamp.as_str(),
+            self.metric.as_str(),
+            self.value.as_str(),
+        ];
+        cells.extend(
+            columns
+                .iter()
+                .map(|col| self.labels.get(col).map_or("", String::as_str)),
+        );
+        cells
+    }
+}
+
+fn label_value(value: &JsonValue) -> String {
+    match value {
+        JsonValue::String(s) => s.clone(),
+        JsonValue::Null => String::new(),
+        v => v.to_string(),
+    }
+}
+
+fn fields_to_labels(fields: Option<&JsonValue>) -> BTreeMap<String, String> {
+    fields
+        .and_then(JsonValue::as_object)
+        .map(|obj| {
+            obj.iter()
+                .map(|(k, v)| (k.clone(), label_value(v)))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Turn an entry of the metrics file into the rows. The entries of unknown kinds are skipped.
+pub fn entry_to_rows(entry: &JsonValue) -> Vec<MetricRow> {
+    let kind = entry["k"].as_str().unwrap_or_default();
+    let metric = match entry["l"].as_str() {
+        Some(metric) => metric.to_string(),
+        None => return Vec::new(),
+    };
+    let timestamp = entry["ts"].as_str().unwrap_or_default().to_string();
+    let row = |value: String, labels: BTreeMap<String, String>| MetricRow {
+        timestamp: timestamp.clone(),
+        metric: metric.clone(),
+        value,
+        labels,
+    };
+    let mut labels = fields_to_labels(entry.get("v"));
+    if let Some(tid) = entry.get("tid") {
+        labels.insert("tid".to_string(), label_value(tid));
+    }
+
+    match kind {
+        "time" => vec![row(label_value(&entry["t_in_us"]), labels)],
+        "event" => vec![row("1".to_string(), labels)],
+        "summary" => {
+            let labels = fields_to_labels(entry.get("labels"));
+            SUMMARY_STATS
+                .iter()
+                .filter(|stat| !entry[**stat].is_null())
+                .map(|stat| {
+                    let mut labels = labels.clone();
+                    labels.insert("stat".to_string(), stat.to_string());
+                    row(label_value(&entry[*stat]), labels)
+                })
+                .collect()
+        }
+        _ => Vec::new(),
+    }
+}
+
+fn escape_cell(cell: &str) -> String {
+    if cell.contains(|c| matches!(c, ',' | '"' | '\n' | '\r')) {
+        format!("\"{}\"", cell.replace('"', "\"\""))
+    } else {
+        cell.to_string()
+    }
+}
+
+fn write_record<'a>(
+    writer: &mut impl Write,
+    cells: impl IntoIterator<Item = &'a str>,
+) -> Result<()> {
+    let line: Vec<String> = cells.into_iter().map(escape_cell).collect();
+    writeln!(writer, "{}", line.join(","))?;
+    Ok(())
+}
+
+fn write_header(writer: &mut impl Write, columns: &[String]) -> Result<()> {
+    let cells = FIXED_COLUMNS
+        .iter()
+        .copied()
+        .chain(columns.iter().map(String::as_str));
+    write_record(writer, cells)
+}
+
+/// The file name of a metric, where the characters other than the alphanumerics, `_`, `-`
+/// and `.` are replaced by `_`.
+pub fn metric_file_name(metric: &str) -> String {
+    let name: String = metric
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    format!("{}.csv", name)
+}
+
+/// Write the rows of a metric to `path` with the label columns unioned across them.
+fn write_metric_csv(path: &Path, rows: &[MetricRow]) -> Result<()> {
+    let columns: Vec<String> = rows
+        .iter()
+        .flat_map(|row| row.labels.keys())
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .cloned()
+        .collect();
+    let mut writer = BufWriter::new(File::create(path)?);
+    write_header(&mut writer, &columns)?;
+    for row in rows {
+        write_record(&mut writer, row.cells(&columns))?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Convert the metrics file `input`, which is gunzipped if it ends with `.gz`, to the CSV
+/// files in `out_dir`. Return the number of rows written per metric name.
+pub fn convert_metrics_log_to_csv(
+    input: impl AsRef<Path>,
+    out_dir: impl AsRef<Path>,
+) -> Result<BTreeMap<String, usize>> {
+    let input = input.as_ref();
+    let out_dir = out_dir.as_ref();
+    let file = File::open(input).with_context(|| format!("Failed to open {}.", input.display()))?;
+    let reader: Box<dyn Read> = if input.extension() == Some(OsStr::new("gz")) {
+        Box::new(GzDecoder::new(file))
+    } else {
+        Box::new(file)
+    };
+
+    let mut metrics: BTreeMap<String, Vec<MetricRow>> = BTreeMap::new();
+    for (i, line) in BufReader::new(reader).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry: JsonValue = serde_json::from_str(&line)
+            .with_context(|| format!("Failed to parse line {} of {}.", i + 1, input.display()))?;
+        for row in entry_to_rows(&entry) {
+            metrics.entry(row.metric.clone()).or_default().push(row);
+        }
+    }
+
+    fs::create_dir_all(out_dir)?;
+    let mut counts = BTreeMap::new();
+    for (metric, rows) in &metrics {
+        write_metric_csv(&out_dir.join(metric_file_name(metric)), rows)?;
+        counts.insert(metric.clone(), rows.len());
+    }
+    Ok(counts)
+}
+
+struct CsvFile {
+    path: PathBuf,
+    columns: Vec<String>,
+    writer: BufWriter<File>,
+}
+
+impl CsvFile {
+    fn create(path: PathBuf, columns: Vec<String>) -> Result<Self> {
+        let mut writer = BufWriter::new(File::create(&path)?);
+        write_header(&mut writer, &columns)?;
+        Ok(Self {
+            path,
+            columns,
+            writer,
+        })
+    }
+
+    fn append(&mut self, row: &MetricRow) -> Result<()> {
+        if row.labels.keys().any(|key| !self.columns.contains(key)) {
+            self.add_columns(row.labels.keys())?;
+        }
+        write_record(&mut self.writer, row.cells(&self.columns))
+    }
+
+    /// Rewrite the file with the new label columns, where the rows written before have empty
+    /// cells. It only happens once a label shows up for the first time.
+    fn add_columns<'a>(&mut self, keys: impl Iterator<Item = &'a String>) -> Result<()> {
+        self.writer.flush()?;
+        let old_len = self.columns.len();
+        let mut columns: BTreeSet<String> = self.columns.iter().cloned().collect();
+        columns.extend(keys.cloned());
+        let columns: Vec<String> = columns.into_iter().collect();
+        let positions: Vec<usize> = self
+            .columns
+            .iter()
+            .map(|col| columns.iter().position(|c| c == col).unwrap_or_default())
+            .collect();
+
+        let content = fs::read_to_string(&self.path)?;
+        let tmp_path = self.path.with_extension("csv.tmp");
+        let mut writer = BufWriter::new(File::create(&tmp_path)?);
+        write_header(&mut writer, &columns)?;
+        for cells in content.lines().skip(1).map(split_record) {
+            let mut new_cells = vec![String::new(); FIXED_COLUMNS.len() + columns.len()];
+            for (i, cell) in cells.into_iter().enumerate() {
+                let pos = match i.checked_sub(FIXED_COLUMNS.len()) {
+                    None => i,
+                    Some(j) if j < old_len => FIXED_COLUMNS.len() + positions[j],
+                    Some(_) => continue,
+                };
+                new_cells[pos] = cell;
+            }
+            write_record(&mut writer, new_cells.iter().map(String::as_str))?;
+        }
+        writer.flush()?;
+        drop(writer);
+        fs::rename(&tmp_path, &self.path)?;
+
+        let file = OpenOptions::new().append(true).open(&self.path)?;
+        self.writer = BufWriter::new(file);
+        self.columns = columns;
+        Ok(())
+    }
+}
+
+/// Split a CSV line written by [`write_record`] into the cells. The cells with line breaks
+/// are not supported, since the rows are read line by line.
+fn split_record(line: &str) -> Vec<String> {
+    let mut cells = Vec::new();
+    let mut cell = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, quoted) {
+            ('"', true) if chars.peek() == Some(&'"') => {
+                chars.next();
+                cell.push('"');
+            }
+            ('"', _) => quoted = !quoted,
+            (',', false) => cells.push(std::mem::take(&mut cell)),
+            (c, _) => cell.push(c),
+        }
+    }
+    cells.push(cell);
+    cells
+}
+
+/// The [`MetricsSink`] writing the CSV files online to a directory. Tee it with the others by
+/// [`crate::metrics::FanOutSink`]. The files are flushed by [`CsvSink::flush`] and on drop.
+pub struct CsvSink {
+    out_dir: PathBuf,
+    files: Mutex<HashMap<String, CsvFile>>,
+}
+
+impl CsvSink {
+    pub fn new(out_dir: impl AsRef<Path>) -> Result<Self> {
+        let out_dir = out_dir.as_ref().to_path_buf();
+        fs::create_dir_all(&out_dir)?;
+        Ok(Self {
+            out_dir,
+            files: Mutex::new(HashMap::new()),
+        })
+    }
+
+    pub fn flush(&self) -> Result<()> {
+        let mut files = self.files.lock().unwrap_or_else(|e| e.into_inner());
+        for file in files.values_mut() {
+            file.writer.flush()?;
+        }
+        Ok(())
+    }
+
+    fn append(&self, row: MetricRow) -> Result<()> {
+        let mut files = self.files.lock().unwrap_or_else(|e| e.into_inner());
+        if !files.contains_key(&row.metric) {
+            let path = self.out_dir.join(metric_file_name(&row.metric));
+            let file = CsvFile::create(path, row.labels.keys().cloned().collect())?;
+            files.insert(row.metric.clone(), file);
+        }
+        files
+            .get_mut(&row.metric)
+            .expect("The file is just created.")
+            .append(&row)
+    }
+
+    fn record(&self, label: &str, value: String, fields: &JsonValue) {
+        let row = MetricRow {
+            timestamp: Utc::now().to_rfc3339_opts(SecondsFormat::Micros, true),
+            metric: label.to_string(),
+            value,
+            labels: fields_to_labels(Some(fields)),
+        };
+        if let Err(e) = self.append(row) {
+            warn!(
+                "Failed to write the metric {} to csv. Error: {:?}",
+                label, e
+            );
+        }
+    }
+}
+
+impl MetricsSink for CsvSink {
+    fn record_time(&self, label: &str, time: Duration, fields: &JsonValue) {
+        self.record(label, (time.as_micros() as u64).to_string(), fields);
+    }
+
+    fn record_event(&self, label: &str, fields: &JsonValue) {
+        self.record(label, "1".to_string(), fields);
+    }
+}
+
+impl Drop for CsvSink {
+    fn drop(&mut self) {
+        self.flush().ok();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn temp_dir() -> PathBuf {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let dir = std::env::temp_dir().join(format!(
+            "slimchain-metrics-csv-test-{}-{}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::SeqCst)
+        ));
+        fs::remove_dir_all(&dir).ok();
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn read_csv(path: &Path) -> Vec<Vec<String>> {
+        fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .map(split_record)
+            .collect()
+    }
+
+    const SAMPLE_LOG: &str = r#"
+{"k":"time","l":"import_block","ts":"2021-01-01T00:00:00.000001Z","t_in_us":1500,"v":{"height":1},"tid":1}
+{"k":"time","l":"import_block","ts":"2021-01-01T00:00:00.000002Z","t_in_us":2500,"v":{"height":2,"peer":"a,b"},"tid":2}
+{"k":"event","l":"tx_commit","ts":"2021-01-01T00:00:00.000003Z","v":{"tx_id":"0x01","ok":true},"tid":1}
+{"k":"event","l":"tx_commit","ts":"2021-01-01T00:00:00.000004Z","v":{},"tid":1}
+{"k":"event","l":"tx_commit","ts":"2021-01-01T00:00:00.000005Z","v":{"tx_id":"0x02"},"tid":3}
+{"k":"summary","l":"exec_tx","ts":"2021-01-01T00:00:10.000000Z","labels":{"shard":"0"},"count":4,"p50_us":10,"p95_us":20,"p99_us":30,"max_us":40,"sum_us":80}
+{"k":"unknown","l":"other","ts":"2021-01-01T00:00:11.000000Z"}
+"#;
+
+    #[test]
+    fn test_convert_metrics_log() {
+        let dir = temp_dir();
+        let input = dir.join("metrics.log");
+        fs::write(&input, SAMPLE_LOG).unwrap();
+        let out_dir = dir.join("csv");
+        let counts = convert_metrics_log_to_csv(&input, &out_dir).unwrap();
+        let expected: BTreeMap<String, usize> = vec![
+            ("exec_tx".to_string(), 6),
+            ("import_block".to_string(), 2),
+            ("tx_commit".to_string(), 3),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(expected, counts);
+
+        let rows = read_csv(&out_dir.join("import_block.csv"));
+        assert_eq!(
+            vec!["timestamp", "metric", "value", "height", "peer", "tid"],
+            rows[0]
+        );
+        assert_eq!(
+            vec![
+                "2021-01-01T00:00:00.000001Z",
+                "import_block",
+                "1500",
+                "1",
+                "",
+                "1"
+            ],
+            rows[1]
+        );
+        assert_eq!("a,b", rows[2][4]);
+
+        let rows = read_csv(&out_dir.join("tx_commit.csv"));
+        assert_eq!(
+            vec!["timestamp", "metric", "value", "ok", "tid", "tx_id"],
+            rows[0]
+        );
+        assert_eq!(4, rows.len());
+        assert_eq!(vec!["1", "true", "1", "0x01"], rows[1][2..].to_vec());
+        assert_eq!(vec!["1", "", "1", ""], rows[2][2..].to_vec());
+
+        let rows = read_csv(&out_dir.join("exec_tx.csv"));
+        assert_eq!(
+            vec!["timestamp", "metric", "value", "shard", "stat"],
+            rows[0]
+        );
+        assert_eq!(vec!["40", "0", "max_us"], rows[5][2..].to_vec());
+        assert!(!out_dir.join("other.csv").exists());
+    }
+
+    #[test]
+    fn test_convert_gzipped_metrics_log() {
+        use flate2::{write::GzEncoder, Compression};
+
+        let dir = temp_dir();
+        let input = dir.join("metrics.log.1.gz");
+        let mut encoder = GzEncoder::new(File::create(&input).unwrap(), Compression::default());
+        encoder.write_all(SAMPLE_LOG.as_bytes()).unwrap();
+        encoder.finish().unwrap();
+        let counts = convert_metrics_log_to_csv(&input, &dir).unwrap();
+        assert_eq!(Some(&3), counts.get("tx_commit"));
+    }
+
+    #[test]
+    fn test_csv_sink() {
+        let dir = temp_dir();
+        let sink = CsvSink::new(&dir).unwrap();
+        sink.record_time(
+            "test/time",
+            Duration::from_micros(7),
+            &json!({ "height": 1 }),
+        );
+        sink.record_time("test/time", Duration::from_micros(8), &json!({}));
+        sink.record_time(
+            "test/time",
+            Duration::from_micros(9),
+            &json!({ "a": "x\"y" }),
+        );
+        sink.record_event("test_event", &json!({ "id": 1 }));
+        drop(sink);
+
+        let rows = read_csv(&dir.join("test_time.csv"));
+        assert_eq!(4, rows.len());
+        assert_eq!(vec!["timestamp", "metric", "value", "a", "height"], rows[0]);
+        assert_eq!(vec!["test/time", "7", "", "1"], rows[1][1..].to_vec());
+        assert_eq!(vec!["test/time", "8", "", ""], rows[2][1..].to_vec());
+        assert_eq!(vec!["test/time", "9", "x\"y", ""], rows[3][1..].to_vec());
+
+        let rows = read_csv(&dir.join("test_event.csv"));
+        assert_eq!(vec!["test_event", "1", "1"], rows[1][1..].to_vec());
+    }
+}
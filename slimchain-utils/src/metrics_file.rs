@@ -0,0 +1,413 @@
+use crossbeam_channel::{unbounded, Receiver, Sender};
+use flate2::{write::GzEncoder, Compression};
+use serde::Deserialize;
+use serde_json::Value as JsonValue;
+use slimchain_common::error::Result;
+use std::{
+    collections::BTreeMap,
+    ffi::{OsStr, OsString},
+    fs::{self, File, OpenOptions},
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct MetricsFileConfig {
+    /// Roll the metrics file over once it exceeds this number of bytes. 0 means never.
+    /// Default 0.
+    pub max_file_size: u64,
+    /// Roll the metrics file over every this time span in milliseconds. 0 means never.
+    /// Default 0.
+    #[serde(deserialize_with = "crate::config::deserialize_duration_from_millis")]
+    pub rotate_interval: Duration,
+    /// Number of the rolled over files kept, where the older ones are removed. 0 means all.
+    /// Default 10.
+    pub max_files: usize,
+    /// Whether to gzip the rolled over files in the background. Default false.
+    pub compress: bool,
+}
+
+impl Default for MetricsFileConfig {
+    fn default() -> Self {
+        Self {
+            max_file_size: 0,
+            rotate_interval: Duration::from_millis(0),
+            max_files: 10,
+            compress: false,
+        }
+    }
+}
+
+/// The metrics file rolled over by its size or age. The active file `<path>` is renamed to
+/// `<path>.<seq>` with an increasing `seq`, and gzipped to `<path>.<seq>.gz` if enabled.
+///
+/// The entries are only split at the lines. Both of the renames are atomic, so that a crash
+/// leaves either the whole files or a `.gz.tmp` next to the uncompressed file, which is
+/// compressed again on the next open.
+pub struct RotatingMetricsFile {
+    path: PathBuf,
+    cfg: MetricsFileConfig,
+    writer: BufWriter<File>,
+    size: u64,
+    opened_at: Instant,
+    next_seq: u64,
+    compress_tx: Option<Sender<PathBuf>>,
+    compress_handle: Option<JoinHandle<()>>,
+}
+
+impl RotatingMetricsFile {
+    pub fn open(path: &Path, cfg: MetricsFileConfig) -> Result<Self> {
+        let path = path.to_path_buf();
+        let rotated = rotated_files(&path)?;
+        let next_seq = rotated.keys().next_back().map_or(1, |seq| seq + 1);
+        let (writer, size) = open_active(&path)?;
+
+        let (compress_tx, compress_handle) = if cfg.compress {
+            let (tx, rx) = unbounded();
+            for files in rotated.values() {
+                if let Some(file) = files
+                    .iter()
+                    .find(|f| f.extension() != Some(OsStr::new("gz")))
+                {
+                    tx.send(file.clone()).ok();
+                }
+            }
+            let handle = {
+                let path = path.clone();
+                thread::spawn(move || compress_rotated_files(&path, cfg.max_files, rx))
+            };
+            (Some(tx), Some(handle))
+        } else {
+            (None, None)
+        };
+
+        Ok(Self {
+            path,
+            cfg,
+            writer,
+            size,
+            opened_at: Instant::now(),
+            next_seq,
+            compress_tx,
+            compress_handle,
+        })
+    }
+
+    pub fn write_entry(&mut self, entry: &JsonValue) -> io::Result<()> {
+        let mut line = serde_json::to_vec(entry)?;
+        line.push(b'\n');
+        if self.should_rotate(line.len() as u64) {
+            self.rotate()?;
+        }
+        self.writer.write_all(&line)?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    fn should_rotate(&self, len: u64) -> bool {
+        if self.size == 0 {
+            return false;
+        }
+        let max_size = self.cfg.max_file_size;
+        let interval = self.cfg.rotate_interval;
+        (max_size > 0 && self.size + len > max_size)
+            || (interval > Duration::from_millis(0) && self.opened_at.elapsed() >= interval)
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.writer.flush()?;
+        self.writer.get_ref().sync_all()?;
+        let rotated = with_suffix(&self.path, &format!(".{}", self.next_seq));
+        self.next_seq += 1;
+        fs::rename(&self.path, &rotated)?;
+
+        let (writer, size) = open_active(&self.path)?;
+        self.writer = writer;
+        self.size = size;
+        self.opened_at = Instant::now();
+
+        match &self.compress_tx {
+            Some(tx) => {
+                tx.send(rotated).ok();
+            }
+            None => remove_old_files(&self.path, self.cfg.max_files)?,
+        }
+        Ok(())
+    }
+}
+
+impl Drop for RotatingMetricsFile {
+    fn drop(&mut self) {
+        self.writer.flush().ok();
+        self.compress_tx.take();
+        if let Some(handle) = self.compress_handle.take() {
+            handle.join().ok();
+        }
+    }
+}
+
+fn open_active(path: &Path) -> io::Result<(BufWriter<File>, u64)> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let size = file.metadata()?.len();
+    Ok((BufWriter::new(file), size))
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path: OsString = path.as_os_str().to_owned();
+    path.push(suffix);
+    path.into()
+}
+
+/// The rolled over files of `path` by their seqs. A seq can have both the uncompressed and the
+/// compressed files while being compressed. The leftover `.gz.tmp` files are removed.
+fn rotated_files(path: &Path) -> io::Result<BTreeMap<u64, Vec<PathBuf>>> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let prefix = match path.file_name().and_then(|name| name.to_str()) {
+        Some(name) => format!("{}.", name),
+        None => return Ok(BTreeMap::new()),
+    };
+
+    let mut files: BTreeMap<u64, Vec<PathBuf>> = BTreeMap::new();
+    if !dir.exists() {
+        return Ok(files);
+    }
+    for entry in fs::read_dir(dir)? {
+        let file = entry?.path();
+        let rest = match file
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.strip_prefix(&prefix))
+        {
+            Some(rest) => rest.to_string(),
+            None => continue,
+        };
+        if rest.ends_with(".gz.tmp") {
+            fs::remove_file(&file)?;
+            continue;
+        }
+        if let Ok(seq) = rest.trim_end_matches(".gz").parse::<u64>() {
+            files.entry(seq).or_default().push(file);
+        }
+    }
+    Ok(files)
+}
+
+fn remove_old_files(path: &Path, max_files: usize) -> io::Result<()> {
+    if max_files == 0 {
+        return Ok(());
+    }
+    let files = rotated_files(path)?;
+    for old_files in files.values().take(files.len().saturating_sub(max_files)) {
+        for file in old_files {
+            fs::remove_file(file)?;
+        }
+    }
+    Ok(())
+}
+
+fn gzip_file(file: &Path) -> io::Result<()> {
+    let tmp = with_suffix(file, ".gz.tmp");
+    let mut input = File::open(file)?;
+    let mut encoder = GzEncoder::new(File::create(&tmp)?, Compression::default());
+    io::copy(&mut input, &mut encoder)?;
+    encoder.finish()?.sync_all()?;
+    fs::rename(&tmp, with_suffix(file, ".gz"))?;
+    fs::remove_file(file)
+}
+
+fn compress_rotated_files(path: &Path, max_files: usize, rx: Receiver<PathBuf>) {
+    for file in rx {
+        // It may be already removed as one of the old files.
+        if !file.exists() {
+            continue;
+        }
+        if let Err(e) = gzip_file(&file) {
+            error!("Failed to compress {}. Error: {}", file.display(), e);
+        }
+        if let Err(e) = remove_old_files(path, max_files) {
+            error!("Failed to remove the old metrics files. Error: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::GzDecoder;
+    use serde_json::json;
+    use std::{
+        io::Read,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    fn temp_dir() -> PathBuf {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let dir = std::env::temp_dir().join(format!(
+            "slimchain-metrics-file-test-{}-{}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::SeqCst)
+        ));
+        fs::remove_dir_all(&dir).ok();
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn file_names(dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        names
+    }
+
+    fn read_ids(file: &Path) -> Vec<u64> {
+        let mut content = String::new();
+        if file.extension() == Some(OsStr::new("gz")) {
+            GzDecoder::new(File::open(file).unwrap())
+                .read_to_string(&mut content)
+                .unwrap();
+        } else {
+            File::open(file)
+                .unwrap()
+                .read_to_string(&mut content)
+                .unwrap();
+        }
+        content
+            .lines()
+            .map(|line| {
+                let entry: JsonValue = serde_json::from_str(line).unwrap();
+                entry["id"].as_u64().unwrap()
+            })
+            .collect()
+    }
+
+    /// The ids in the rolled over files from the oldest to the newest, then the active file.
+    fn all_ids(path: &Path) -> Vec<u64> {
+        let mut ids = Vec::new();
+        for files in rotated_files(path).unwrap().values() {
+            assert_eq!(1, files.len());
+            ids.extend(read_ids(&files[0]));
+        }
+        ids.extend(read_ids(path));
+        ids
+    }
+
+    fn write_ids(path: &Path, cfg: MetricsFileConfig, ids: std::ops::Range<u64>) {
+        let mut file = RotatingMetricsFile::open(path, cfg).unwrap();
+        for id in ids {
+            file.write_entry(&json!({ "id": id })).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_rotate_by_size() {
+        let dir = temp_dir();
+        let path = dir.join("metrics.log");
+        let cfg = MetricsFileConfig {
+            max_file_size: 100,
+            max_files: 0,
+            ..Default::default()
+        };
+        // Each entry is 10 bytes, so that 10 of them fit in a file.
+        write_ids(&path, cfg, 10..100);
+        let mut expected: Vec<_> = (1..=8).map(|i| format!("metrics.log.{}", i)).collect();
+        expected.insert(0, "metrics.log".to_string());
+        assert_eq!(expected, file_names(&dir));
+        for seq in 1..=8 {
+            let file = dir.join(format!("metrics.log.{}", seq));
+            assert!(fs::metadata(&file).unwrap().len() <= 100);
+        }
+        assert_eq!((10..100).collect::<Vec<_>>(), all_ids(&path));
+
+        write_ids(&path, cfg, 100..120);
+        assert!(dir.join("metrics.log.11").exists());
+        assert!(!dir.join("metrics.log.12").exists());
+        assert_eq!((10..120).collect::<Vec<_>>(), all_ids(&path));
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_retention() {
+        let dir = temp_dir();
+        let path = dir.join("metrics.log");
+        let cfg = MetricsFileConfig {
+            max_file_size: 100,
+            max_files: 3,
+            ..Default::default()
+        };
+        write_ids(&path, cfg, 10..100);
+        assert_eq!(
+            vec![
+                "metrics.log",
+                "metrics.log.6",
+                "metrics.log.7",
+                "metrics.log.8"
+            ],
+            file_names(&dir)
+        );
+        assert_eq!((60..100).collect::<Vec<_>>(), all_ids(&path));
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_compress() {
+        let dir = temp_dir();
+        let path = dir.join("metrics.log");
+        let cfg = MetricsFileConfig {
+            max_file_size: 100,
+            max_files: 2,
+            compress: true,
+            ..Default::default()
+        };
+        write_ids(&path, cfg, 10..100);
+        assert_eq!(
+            vec!["metrics.log", "metrics.log.7.gz", "metrics.log.8.gz"],
+            file_names(&dir)
+        );
+        assert_eq!((70..100).collect::<Vec<_>>(), all_ids(&path));
+
+        // A crash in the middle of the compression leaves the uncompressed file and the
+        // partial one, which is compressed again on the next open.
+        fs::rename(&path, dir.join("metrics.log.9")).unwrap();
+        fs::write(dir.join("metrics.log.9.gz.tmp"), "truncated").unwrap();
+        write_ids(&path, cfg, 100..102);
+        assert_eq!(
+            vec!["metrics.log", "metrics.log.8.gz", "metrics.log.9.gz"],
+            file_names(&dir)
+        );
+        assert_eq!((80..102).collect::<Vec<_>>(), all_ids(&path));
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_rotate_by_time() {
+        let dir = temp_dir();
+        let path = dir.join("metrics.log");
+        let cfg = MetricsFileConfig {
+            rotate_interval: Duration::from_millis(50),
+            max_files: 0,
+            ..Default::default()
+        };
+        let mut file = RotatingMetricsFile::open(&path, cfg).unwrap();
+        file.write_entry(&json!({ "id": 1 })).unwrap();
+        file.write_entry(&json!({ "id": 2 })).unwrap();
+        thread::sleep(Duration::from_millis(100));
+        file.write_entry(&json!({ "id": 3 })).unwrap();
+        drop(file);
+        assert_eq!(vec!["metrics.log", "metrics.log.1"], file_names(&dir));
+        assert_eq!(vec![1, 2], read_ids(&dir.join("metrics.log.1")));
+        assert_eq!(vec![1, 2, 3], all_ids(&path));
+        fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -35,6 +35,20 @@ where
             next_key_fn,
         }
     }
+
+    /// The key of the next item to be yielded.
+    pub fn current(&self) -> &K {
+        &self.current
+    }
+
+    /// The smallest key of the items received ahead of [`Self::current`], which are held
+    /// until all the items before them arrive.
+    pub fn first_pending_key(&self) -> Option<&K>
+    where
+        K: Ord,
+    {
+        self.cache.keys().min()
+    }
 }
 
 impl<S, K, V, F> Stream for OrderedStream<S, K, V, F>
@@ -113,4 +127,39 @@ mod tests {
 
         handle.await.unwrap();
     }
+
+    #[tokio::test]
+    async fn test_ordered_stream_duplicate() {
+        let (mut tx, rx) = mpsc::unbounded::<(i32, i32)>();
+        let mut stream = OrderedStream::new(rx, 0, |x: &i32| x + 1);
+
+        tx.send((0, 0)).await.unwrap();
+        tx.send((2, 2)).await.unwrap();
+        tx.send((0, 0)).await.unwrap();
+        tx.send((2, 2)).await.unwrap();
+        tx.send((1, 1)).await.unwrap();
+        tx.send((1, 1)).await.unwrap();
+        tx.close_channel();
+
+        assert_eq!(vec![0, 1, 2], stream.collect::<Vec<_>>().await);
+    }
+
+    #[tokio::test]
+    async fn test_ordered_stream_pending() {
+        let (mut tx, rx) = mpsc::unbounded::<(i32, i32)>();
+        let mut stream = OrderedStream::new(rx, 0, |x: &i32| x + 1);
+        assert_eq!(None, stream.first_pending_key());
+
+        tx.send((0, 0)).await.unwrap();
+        tx.send((3, 3)).await.unwrap();
+        tx.send((2, 2)).await.unwrap();
+        assert_eq!(Some(0), stream.next().await);
+        assert!(stream.next().now_or_never().is_none());
+        assert_eq!(&1, stream.current());
+        assert_eq!(Some(&2), stream.first_pending_key());
+
+        tx.send((1, 1)).await.unwrap();
+        tx.close_channel();
+        assert_eq!(vec![1, 2, 3], stream.collect::<Vec<_>>().await);
+    }
 }
@@ -0,0 +1,678 @@
+//! A small in-process metric registry rendered in the Prometheus text exposition format.
+//!
+//! The metrics are either owned by the registry, i.e., [`Counter`], [`Gauge`] and
+//! [`Histogram`], or written on each scrape by the collectors registered with
+//! [`Registry::register_collector`], which suit the existing snapshot-based metrics.
+
+use crate::metrics::MetricsSink;
+use once_cell::sync::Lazy;
+use serde_json::Value as JsonValue;
+use slimchain_common::error::{anyhow, bail, Result};
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Write as _},
+    iter::Peekable,
+    str::Chars,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, MutexGuard,
+    },
+    time::Duration,
+};
+
+pub static REGISTRY: Lazy<Registry> = Lazy::new(Registry::new);
+
+/// The content type of [`Registry::render`].
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+/// The upper bounds of the default histogram buckets in seconds.
+pub const DEFAULT_BUCKETS: [f64; 12] = [
+    0.001, 0.002, 0.005, 0.01, 0.02, 0.05, 0.1, 0.2, 0.5, 1., 2., 5.,
+];
+
+type Labels = Vec<(String, String)>;
+
+fn to_labels(labels: &[(&str, &str)]) -> Labels {
+    labels
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect()
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum MetricKind {
+    Counter,
+    Gauge,
+    Histogram,
+}
+
+impl fmt::Display for MetricKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Counter => write!(f, "counter"),
+            Self::Gauge => write!(f, "gauge"),
+            Self::Histogram => write!(f, "histogram"),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Counter(AtomicU64);
+
+impl Counter {
+    pub fn inc(&self) {
+        self.inc_by(1);
+    }
+
+    pub fn inc_by(&self, n: u64) {
+        self.0.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// A gauge holding a `f64` in its bits.
+#[derive(Debug, Default)]
+pub struct Gauge(AtomicU64);
+
+impl Gauge {
+    pub fn set(&self, value: f64) {
+        self.0.store(value.to_bits(), Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> f64 {
+        f64::from_bits(self.0.load(Ordering::Relaxed))
+    }
+}
+
+#[derive(Debug)]
+pub struct Histogram {
+    bounds: Vec<f64>,
+    /// The count of each bucket in `bounds`, followed by the one above all of them.
+    counts: Vec<AtomicU64>,
+    sum: Mutex<f64>,
+}
+
+impl Histogram {
+    /// Create a histogram with the buckets of the upper `bounds` in ascending order.
+    pub fn new(bounds: &[f64]) -> Self {
+        Self {
+            bounds: bounds.to_vec(),
+            counts: (0..=bounds.len()).map(|_| AtomicU64::new(0)).collect(),
+            sum: Mutex::new(0.),
+        }
+    }
+
+    pub fn observe(&self, value: f64) {
+        let bucket = self
+            .bounds
+            .iter()
+            .position(|&bound| value <= bound)
+            .unwrap_or(self.bounds.len());
+        self.counts[bucket].fetch_add(1, Ordering::Relaxed);
+        *self.sum.lock().unwrap_or_else(|e| e.into_inner()) += value;
+    }
+
+    /// Observe `time` in seconds.
+    pub fn observe_duration(&self, time: Duration) {
+        self.observe(time.as_secs_f64());
+    }
+
+    /// The non-cumulative counts of the buckets and the sum.
+    pub fn snapshot(&self) -> (Vec<u64>, f64) {
+        let counts = self
+            .counts
+            .iter()
+            .map(|c| c.load(Ordering::Relaxed))
+            .collect();
+        (counts, *self.sum.lock().unwrap_or_else(|e| e.into_inner()))
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Metric {
+    Counter(Arc<Counter>),
+    Gauge(Arc<Gauge>),
+    Histogram(Arc<Histogram>),
+}
+
+impl Metric {
+    fn kind(&self) -> MetricKind {
+        match self {
+            Self::Counter(_) => MetricKind::Counter,
+            Self::Gauge(_) => MetricKind::Gauge,
+            Self::Histogram(_) => MetricKind::Histogram,
+        }
+    }
+}
+
+struct Family {
+    help: &'static str,
+    metrics: BTreeMap<Labels, Metric>,
+}
+
+type Collector = Box<dyn Fn(&mut MetricsWriter) + Send + Sync>;
+
+pub struct Registry {
+    /// The labels added to all the series, e.g., the role of this node.
+    const_labels: Mutex<Labels>,
+    families: Mutex<BTreeMap<&'static str, Family>>,
+    collectors: Mutex<BTreeMap<&'static str, Collector>>,
+}
+
+impl Registry {
+    pub fn new() -> Self {
+        Self {
+            const_labels: Mutex::new(Vec::new()),
+            families: Mutex::new(BTreeMap::new()),
+            collectors: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    fn families(&self) -> MutexGuard<'_, BTreeMap<&'static str, Family>> {
+        self.families.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn collectors(&self) -> MutexGuard<'_, BTreeMap<&'static str, Collector>> {
+        self.collectors.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn set_const_labels(&self, labels: &[(&str, &str)]) {
+        *self.const_labels.lock().unwrap_or_else(|e| e.into_inner()) = to_labels(labels);
+    }
+
+    fn metric(
+        &self,
+        name: &'static str,
+        help: &'static str,
+        labels: &[(&str, &str)],
+        create: impl FnOnce() -> Metric,
+    ) -> Metric {
+        let mut families = self.families();
+        let family = families.entry(name).or_insert_with(|| Family {
+            help,
+            metrics: BTreeMap::new(),
+        });
+        let metric = family
+            .metrics
+            .entry(to_labels(labels))
+            .or_insert_with(create)
+            .clone();
+        if let Some(other) = family.metrics.values().find(|m| m.kind() != metric.kind()) {
+            panic!(
+                "Metric {} is registered as both {} and {}.",
+                name,
+                other.kind(),
+                metric.kind()
+            );
+        }
+        metric
+    }
+
+    /// Get or create the counter `name` with `labels`.
+    pub fn counter(
+        &self,
+        name: &'static str,
+        help: &'static str,
+        labels: &[(&str, &str)],
+    ) -> Arc<Counter> {
+        match self.metric(name, help, labels, || Metric::Counter(Arc::default())) {
+            Metric::Counter(counter) => counter,
+            m => panic!("Metric {} is registered as {}.", name, m.kind()),
+        }
+    }
+
+    /// Get or create the gauge `name` with `labels`.
+    pub fn gauge(
+        &self,
+        name: &'static str,
+        help: &'static str,
+        labels: &[(&str, &str)],
+    ) -> Arc<Gauge> {
+        match self.metric(name, help, labels, || Metric::Gauge(Arc::default())) {
+            Metric::Gauge(gauge) => gauge,
+            m => panic!("Metric {} is registered as {}.", name, m.kind()),
+        }
+    }
+
+    /// Get or create the histogram `name` with `labels`. `bounds` is only used on creation.
+    pub fn histogram(
+        &self,
+        name: &'static str,
+        help: &'static str,
+        bounds: &[f64],
+        labels: &[(&str, &str)],
+    ) -> Arc<Histogram> {
+        let create = || Metric::Histogram(Arc::new(Histogram::new(bounds)));
+        match self.metric(name, help, labels, create) {
+            Metric::Histogram(histogram) => histogram,
+            m => panic!("Metric {} is registered as {}.", name, m.kind()),
+        }
+    }
+
+    /// Call `collector` on each scrape to write the metrics kept elsewhere. It replaces the
+    /// collector registered before with the same `key`.
+    pub fn register_collector(
+        &self,
+        key: &'static str,
+        collector: impl Fn(&mut MetricsWriter) + Send + Sync + 'static,
+    ) {
+        self.collectors().insert(key, Box::new(collector));
+    }
+
+    /// Render all the metrics in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut w = MetricsWriter::default();
+        for (&name, family) in self.families().iter() {
+            for (labels, metric) in &family.metrics {
+                let labels: Vec<(&str, &str)> = labels
+                    .iter()
+                    .map(|(k, v)| (k.as_str(), v.as_str()))
+                    .collect();
+                match metric {
+                    Metric::Counter(c) => w.counter(name, family.help, &labels, c.get() as f64),
+                    Metric::Gauge(g) => w.gauge(name, family.help, &labels, g.get()),
+                    Metric::Histogram(h) => {
+                        let (counts, sum) = h.snapshot();
+                        w.histogram(name, family.help, &labels, &h.bounds, &counts, sum);
+                    }
+                }
+            }
+        }
+        for collector in self.collectors().values() {
+            collector(&mut w);
+        }
+        let const_labels = self
+            .const_labels
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone();
+        w.render_with(&const_labels)
+    }
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+struct WrittenFamily {
+    help: String,
+    kind: MetricKind,
+    /// The suffix of the name, the labels and the value.
+    samples: Vec<(&'static str, Labels, f64)>,
+}
+
+/// The metrics written by the collectors on a scrape. The samples of the same name are
+/// grouped together, so that they can be written in any order.
+#[derive(Default)]
+pub struct MetricsWriter {
+    families: BTreeMap<String, WrittenFamily>,
+}
+
+impl MetricsWriter {
+    fn family(&mut self, name: &str, help: &str, kind: MetricKind) -> &mut WrittenFamily {
+        self.families
+            .entry(name.to_string())
+            .or_insert_with(|| WrittenFamily {
+                help: help.to_string(),
+                kind,
+                samples: Vec::new(),
+            })
+    }
+
+    pub fn counter(&mut self, name: &str, help: &str, labels: &[(&str, &str)], value: f64) {
+        self.family(name, help, MetricKind::Counter)
+            .samples
+            .push(("", to_labels(labels), value));
+    }
+
+    pub fn gauge(&mut self, name: &str, help: &str, labels: &[(&str, &str)], value: f64) {
+        self.family(name, help, MetricKind::Gauge)
+            .samples
+            .push(("", to_labels(labels), value));
+    }
+
+    /// Write a histogram, where `counts` are the non-cumulative counts of the buckets with the
+    /// upper `bounds`, followed by the count above all of them.
+    pub fn histogram(
+        &mut self,
+        name: &str,
+        help: &str,
+        labels: &[(&str, &str)],
+        bounds: &[f64],
+        counts: &[u64],
+        sum: f64,
+    ) {
+        let family = self.family(name, help, MetricKind::Histogram);
+        let mut cumulative = 0;
+        for (i, count) in counts.iter().enumerate() {
+            cumulative += count;
+            let le = bounds.get(i).copied().unwrap_or(f64::INFINITY);
+            let mut bucket_labels = to_labels(labels);
+            bucket_labels.push(("le".to_string(), format_value(le)));
+            family
+                .samples
+                .push(("_bucket", bucket_labels, cumulative as f64));
+        }
+        family.samples.push(("_sum", to_labels(labels), sum));
+        family
+            .samples
+            .push(("_count", to_labels(labels), cumulative as f64));
+    }
+
+    /// Render the written metrics, e.g., to check a collector in tests.
+    pub fn render(self) -> String {
+        self.render_with(&[])
+    }
+
+    fn render_with(self, const_labels: &[(String, String)]) -> String {
+        let mut out = String::new();
+        for (name, family) in self.families {
+            writeln!(out, "# HELP {} {}", name, escape(&family.help, false)).ok();
+            writeln!(out, "# TYPE {} {}", name, family.kind).ok();
+            for (suffix, labels, value) in family.samples {
+                out.push_str(&name);
+                out.push_str(suffix);
+                let mut labels = const_labels.iter().chain(labels.iter()).peekable();
+                if labels.peek().is_some() {
+                    out.push('{');
+                    for (i, (k, v)) in labels.enumerate() {
+                        if i > 0 {
+                            out.push(',');
+                        }
+                        write!(out, "{}=\"{}\"", k, escape(v, true)).ok();
+                    }
+                    out.push('}');
+                }
+                writeln!(out, " {}", format_value(value)).ok();
+            }
+        }
+        out
+    }
+}
+
+fn escape(s: &str, quote: bool) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '"' if quote => out.push_str("\\\""),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+fn format_value(value: f64) -> String {
+    if value.is_nan() {
+        "NaN".to_string()
+    } else if value.is_infinite() {
+        if value > 0. { "+Inf" } else { "-Inf" }.to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+/// The [`MetricsSink`] counting the events and observing the times of the `record_event!` and
+/// `record_time!` macros into a registry, labeled by their labels. The fields are ignored.
+#[derive(Clone, Copy)]
+pub struct PrometheusSink {
+    registry: &'static Registry,
+}
+
+impl PrometheusSink {
+    pub fn new(registry: &'static Registry) -> Self {
+        Self { registry }
+    }
+}
+
+impl MetricsSink for PrometheusSink {
+    fn record_time(&self, label: &str, time: Duration, _fields: &JsonValue) {
+        self.registry
+            .histogram(
+                "slimchain_time_seconds",
+                "The times recorded in the metrics file.",
+                &DEFAULT_BUCKETS,
+                &[("label", label)],
+            )
+            .observe_duration(time);
+    }
+
+    fn record_event(&self, label: &str, _fields: &JsonValue) {
+        self.registry
+            .counter(
+                "slimchain_events_total",
+                "The events recorded in the metrics file.",
+                &[("label", label)],
+            )
+            .inc();
+    }
+}
+
+/// A sample parsed by [`parse_samples`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sample {
+    pub name: String,
+    pub labels: BTreeMap<String, String>,
+    pub value: f64,
+}
+
+/// Parse the samples in the text exposition format, skipping the comments.
+pub fn parse_samples(text: &str) -> Result<Vec<Sample>> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| parse_sample(line).map_err(|e| anyhow!("Invalid sample {:?}. {}", line, e)))
+        .collect()
+}
+
+fn parse_sample(line: &str) -> Result<Sample> {
+    let name_end = line.find(|c| c == '{' || c == ' ').unwrap_or(line.len());
+    let name = line[..name_end].to_string();
+    let mut chars = line[name_end..].chars().peekable();
+    let mut labels = BTreeMap::new();
+    if chars.peek() == Some(&'{') {
+        chars.next();
+        loop {
+            match chars.peek() {
+                Some('}') => {
+                    chars.next();
+                    break;
+                }
+                Some(',') => {
+                    chars.next();
+                }
+                Some(_) => {
+                    let (k, v) = parse_label(&mut chars)?;
+                    labels.insert(k, v);
+                }
+                None => bail!("Unclosed labels."),
+            }
+        }
+    }
+    let value = match chars.collect::<String>().trim() {
+        "+Inf" => f64::INFINITY,
+        "-Inf" => f64::NEG_INFINITY,
+        "NaN" => f64::NAN,
+        v => v.parse()?,
+    };
+    Ok(Sample {
+        name,
+        labels,
+        value,
+    })
+}
+
+fn parse_label(chars: &mut Peekable<Chars<'_>>) -> Result<(String, String)> {
+    let key: String = chars.by_ref().take_while(|&c| c != '=').collect();
+    if chars.next() != Some('"') {
+        bail!("Unquoted label value.");
+    }
+    let mut value = String::new();
+    loop {
+        match chars.next() {
+            Some('"') => break,
+            Some('\\') => match chars.next() {
+                Some('n') => value.push('\n'),
+                Some(c) => value.push(c),
+                None => bail!("Unclosed label value."),
+            },
+            Some(c) => value.push(c),
+            None => bail!("Unclosed label value."),
+        }
+    }
+    Ok((key.trim().to_string(), value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn find<'a>(samples: &'a [Sample], name: &str, labels: &[(&str, &str)]) -> Option<&'a Sample> {
+        samples.iter().find(|s| {
+            s.name == name
+                && labels
+                    .iter()
+                    .all(|(k, v)| s.labels.get(*k).map(String::as_str) == Some(*v))
+        })
+    }
+
+    #[test]
+    fn test_render() {
+        let registry = Registry::new();
+        registry.set_const_labels(&[("role", "client")]);
+        registry
+            .counter("test_requests_total", "The requests.", &[("route", "a")])
+            .inc_by(3);
+        registry
+            .counter("test_requests_total", "The requests.", &[("route", "b")])
+            .inc();
+        registry
+            .gauge("test_queue_depth", "The queue depth.", &[])
+            .set(2.5);
+        let histogram = registry.histogram("test_latency_seconds", "The latency.", &[0.1, 1.], &[]);
+        histogram.observe(0.05);
+        histogram.observe(0.5);
+        histogram.observe(10.);
+
+        let text = registry.render();
+        assert_eq!(
+            1,
+            text.matches("# TYPE test_requests_total counter").count()
+        );
+        assert!(text.contains("# TYPE test_latency_seconds histogram"));
+        assert!(text.contains("test_queue_depth{role=\"client\"} 2.5\n"));
+
+        let samples = parse_samples(&text).unwrap();
+        let expected = [
+            ("test_requests_total", vec![("route", "a")], 3.),
+            ("test_requests_total", vec![("route", "b")], 1.),
+            ("test_latency_seconds_bucket", vec![("le", "0.1")], 1.),
+            ("test_latency_seconds_bucket", vec![("le", "1")], 2.),
+            ("test_latency_seconds_bucket", vec![("le", "+Inf")], 3.),
+            ("test_latency_seconds_count", vec![], 3.),
+            ("test_latency_seconds_sum", vec![], 10.55),
+        ];
+        for (name, labels, value) in expected.iter() {
+            let sample = find(&samples, name, labels).unwrap();
+            assert_eq!(
+                Some("client"),
+                sample.labels.get("role").map(String::as_str)
+            );
+            assert!((sample.value - value).abs() < 1e-9, "{:?}", sample);
+        }
+    }
+
+    #[test]
+    fn test_collector() {
+        let registry = Registry::new();
+        registry.register_collector("test", |w| {
+            w.gauge("test_height", "The height.", &[("shard", "0/1")], 1.);
+            w.histogram(
+                "test_size",
+                "The size.",
+                &[],
+                &[10., 100.],
+                &[1, 0, 2],
+                300.,
+            );
+            w.gauge("test_height", "The height.", &[("shard", "1/1")], 2.);
+        });
+        let samples = parse_samples(&registry.render()).unwrap();
+        assert_eq!(
+            1.,
+            find(&samples, "test_height", &[("shard", "0/1")])
+                .unwrap()
+                .value
+        );
+        assert_eq!(
+            2.,
+            find(&samples, "test_height", &[("shard", "1/1")])
+                .unwrap()
+                .value
+        );
+        assert_eq!(
+            1.,
+            find(&samples, "test_size_bucket", &[("le", "100")])
+                .unwrap()
+                .value
+        );
+        assert_eq!(3., find(&samples, "test_size_count", &[]).unwrap().value);
+
+        // Replaced by the collector of the same key.
+        registry.register_collector("test", |w| w.counter("test_other", "Other.", &[], 1.));
+        let samples = parse_samples(&registry.render()).unwrap();
+        assert!(find(&samples, "test_height", &[]).is_none());
+        assert!(find(&samples, "test_other", &[]).is_some());
+    }
+
+    #[test]
+    fn test_escape() {
+        let registry = Registry::new();
+        registry.register_collector("test", |w| {
+            w.counter(
+                "test_errors_total",
+                "The errors.",
+                &[("msg", "a \"b\"\\\nc")],
+                1.,
+            )
+        });
+        let text = registry.render();
+        assert!(text.contains(r#"msg="a \"b\"\\\nc""#));
+        let samples = parse_samples(&text).unwrap();
+        assert_eq!("a \"b\"\\\nc", samples[0].labels["msg"]);
+    }
+
+    #[test]
+    fn test_prometheus_sink() {
+        let registry: &'static Registry = Box::leak(Box::new(Registry::new()));
+        let sink = PrometheusSink::new(registry);
+        let fields = serde_json::json!({ "id": 1 });
+        sink.record_event("test_tee_event", &fields);
+        sink.record_event("test_tee_event", &fields);
+        sink.record_time("test_tee_time", Duration::from_millis(3), &fields);
+
+        let samples = parse_samples(&registry.render()).unwrap();
+        assert_eq!(
+            2.,
+            find(
+                &samples,
+                "slimchain_events_total",
+                &[("label", "test_tee_event")]
+            )
+            .unwrap()
+            .value
+        );
+        let bucket = find(
+            &samples,
+            "slimchain_time_seconds_bucket",
+            &[("label", "test_tee_time"), ("le", "0.005")],
+        );
+        assert_eq!(1., bucket.unwrap().value);
+    }
+}
@@ -0,0 +1,134 @@
+//! The randomness of the node behaviors, e.g., which peer a request is routed to, so that the
+//! multi-node tests can replay the same choices by a seed.
+//!
+//! Production must not set the seed: the choices then become predictable to the peers. The
+//! keys and the other secrets never come from [`rng`], but from the OS seeded generators.
+
+use once_cell::sync::Lazy;
+use rand::{rngs::SmallRng, rngs::ThreadRng, Error, RngCore, SeedableRng};
+use std::{cell::RefCell, sync::Mutex};
+
+static GLOBAL_SEEDED_RNG: Lazy<Mutex<Option<SmallRng>>> = Lazy::new(|| Mutex::new(None));
+
+thread_local! {
+    static THREAD_SEEDED_RNG: RefCell<Option<SmallRng>> = RefCell::new(None);
+}
+
+/// Make [`rng`] deterministic on all the threads from now on. **For tests only.**
+///
+/// The generators returned are forked from the seeded one in the order [`rng`] is called, so
+/// the choices are reproducible as long as that order is.
+pub fn set_global_rng_seed(seed: u64) {
+    *global_seeded_rng() = Some(SmallRng::seed_from_u64(seed));
+}
+
+/// Like [`set_global_rng_seed`] but only for the calling thread, which takes precedence over
+/// the global seed. It keeps a single threaded test off the [`rng`] calls of the others.
+pub fn set_thread_rng_seed(seed: u64) {
+    THREAD_SEEDED_RNG.with(|rng| *rng.borrow_mut() = Some(SmallRng::seed_from_u64(seed)));
+}
+
+/// Go back to the OS seeded randomness on all the threads.
+pub fn clear_global_rng_seed() {
+    *global_seeded_rng() = None;
+}
+
+/// Go back to the global seed, if any, or the OS seeded randomness on the calling thread.
+pub fn clear_thread_rng_seed() {
+    THREAD_SEEDED_RNG.with(|rng| *rng.borrow_mut() = None);
+}
+
+fn global_seeded_rng() -> std::sync::MutexGuard<'static, Option<SmallRng>> {
+    GLOBAL_SEEDED_RNG.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn fork(seeded: &mut SmallRng) -> SmallRng {
+    SmallRng::seed_from_u64(seeded.next_u64())
+}
+
+/// The generator of the node behaviors. It is a fast deterministic PRNG once a seed is set by
+/// [`set_thread_rng_seed`] or [`set_global_rng_seed`], or the thread local OS seeded one
+/// otherwise.
+pub fn rng() -> NodeRng {
+    let thread_seeded = THREAD_SEEDED_RNG
+        .try_with(|rng| rng.borrow_mut().as_mut().map(fork))
+        .ok()
+        .flatten();
+    if let Some(rng) = thread_seeded.or_else(|| global_seeded_rng().as_mut().map(fork)) {
+        return NodeRng::Seeded(rng);
+    }
+    NodeRng::Os(rand::thread_rng())
+}
+
+/// See [`rng`].
+#[derive(Debug, Clone)]
+pub enum NodeRng {
+    Seeded(SmallRng),
+    Os(ThreadRng),
+}
+
+impl RngCore for NodeRng {
+    fn next_u32(&mut self) -> u32 {
+        match self {
+            Self::Seeded(rng) => rng.next_u32(),
+            Self::Os(rng) => rng.next_u32(),
+        }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        match self {
+            Self::Seeded(rng) => rng.next_u64(),
+            Self::Os(rng) => rng.next_u64(),
+        }
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        match self {
+            Self::Seeded(rng) => rng.fill_bytes(dest),
+            Self::Os(rng) => rng.fill_bytes(dest),
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), Error> {
+        match self {
+            Self::Seeded(rng) => rng.try_fill_bytes(dest),
+            Self::Os(rng) => rng.try_fill_bytes(dest),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{seq::SliceRandom, Rng};
+
+    fn picks() -> Vec<u32> {
+        let items: Vec<u32> = (0..100).collect();
+        (0..20)
+            .map(|_| *items.choose(&mut rng()).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_seeded_rng() {
+        set_global_rng_seed(42);
+        let first = picks();
+        set_global_rng_seed(42);
+        let second = picks();
+        assert_eq!(first, second);
+        set_global_rng_seed(43);
+        assert_ne!(first, picks());
+        clear_global_rng_seed();
+        assert!(matches!(rng(), NodeRng::Os(_)));
+
+        set_thread_rng_seed(42);
+        let thread_first = picks();
+        set_thread_rng_seed(42);
+        assert_eq!(thread_first, picks());
+        let other_thread = std::thread::spawn(|| matches!(rng(), NodeRng::Os(_)));
+        assert!(other_thread.join().unwrap());
+        clear_thread_rng_seed();
+        assert!(matches!(rng(), NodeRng::Os(_)));
+        let _: u64 = rng().gen();
+    }
+}
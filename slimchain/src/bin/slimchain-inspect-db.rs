@@ -1,4 +1,7 @@
-use slimchain_chain::db::{BLOCK_DB_COL, DB, LOG_DB_COL, META_DB_COL, STATE_DB_COL, TX_DB_COL};
+use slimchain_chain::db::{
+    BLOCK_DB_COL, BLOCK_HASH_DB_COL, DB, LOG_DB_COL, META_DB_COL, STATE_DB_COL, TX_DB_COL,
+    TX_LOC_DB_COL,
+};
 use slimchain_common::{
     basic::BlockHeight,
     error::{bail, Context as _, Result},
@@ -35,7 +38,7 @@ fn main() -> Result<()> {
     if !opts.db_path.exists() {
         bail!("DB {:?} not existed.", opts.db_path);
     }
-    let db = DB::open_or_create(&opts.db_path, false)?;
+    let db = DB::open_read_only(&opts.db_path)?;
 
     let height: BlockHeight = db
         .get_existing_meta_object("height")
@@ -45,6 +48,8 @@ fn main() -> Result<()> {
     let block_db_size = db.get_table_size(BLOCK_DB_COL);
     let tx_db_size = db.get_table_size(TX_DB_COL);
     let state_db_size = db.get_table_size(STATE_DB_COL);
+    let tx_loc_db_size = db.get_table_size(TX_LOC_DB_COL);
+    let block_hash_db_size = db.get_table_size(BLOCK_HASH_DB_COL);
     let chain_db_size = block_db_size + tx_db_size + state_db_size;
 
     println!("Database size breakdown:");
@@ -66,6 +71,8 @@ fn main() -> Result<()> {
         state_db_size,
         state_db_size as f64 / height.0 as f64
     );
+    println!(" TX_LOCATION = {}", tx_loc_db_size);
+    println!(" BLOCK_HASH = {}", block_hash_db_size);
     println!(
         " BLOCK + TX + STATE = {} ({} per block)",
         chain_db_size,
@@ -80,6 +87,8 @@ fn main() -> Result<()> {
             "block_db_size": block_db_size,
             "tx_db_size": tx_db_size,
             "state_db_size": state_db_size,
+            "tx_loc_db_size": tx_loc_db_size,
+            "block_hash_db_size": block_hash_db_size,
             "chain_db_size": chain_db_size,
         });
 
@@ -0,0 +1,44 @@
+use slimchain_common::error::{Context as _, Result};
+use slimchain_network::http::{config::NodeRpcAuthConfig, node_rpc::send_node_rpc_using_json};
+use std::io::{self, prelude::*};
+use structopt::StructOpt;
+
+/// Send a JSON encoded node RPC to a raft node for debugging. The node needs to set
+/// `allow_json` in `[network.node_rpc_encoding]`.
+#[derive(Debug, StructOpt)]
+#[structopt(version = git_version::git_version!(prefix = concat!(env!("CARGO_PKG_VERSION"), " ("), suffix = ")", fallback = "unknown"))]
+struct Opts {
+    /// The HTTP endpoint of the node, e.g., 127.0.0.1:8000.
+    #[structopt(short, long, default_value = "127.0.0.1:8000")]
+    endpoint: String,
+
+    /// The token in `[network.auth]` of the node.
+    #[structopt(short, long)]
+    token: Option<String>,
+
+    /// The route of the node RPC, e.g., raft_vote.
+    route: String,
+
+    /// The JSON encoded request. Read from stdin if not set.
+    req: Option<String>,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let opts = Opts::from_args();
+    NodeRpcAuthConfig { token: opts.token }.install_as_global()?;
+
+    let req = match opts.req {
+        Some(req) => req,
+        None => {
+            let mut req = String::new();
+            io::stdin().read_to_string(&mut req)?;
+            req
+        }
+    };
+    let req: serde_json::Value = serde_json::from_str(&req).context("Invalid JSON request.")?;
+    let resp: serde_json::Value =
+        send_node_rpc_using_json(&opts.endpoint, &opts.route, &req).await?;
+    println!("{}", serde_json::to_string_pretty(&resp)?);
+    Ok(())
+}
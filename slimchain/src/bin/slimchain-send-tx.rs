@@ -235,6 +235,7 @@ fn create_deploy_tx(
     rng: &mut (impl Rng + CryptoRng),
     contract: ContractArg,
     shard_id: ShardId,
+    chain_id: u64,
 ) -> (Address, SignedTxRequest) {
     info!(
         "Create deploy tx for contract {:?} at {:?}",
@@ -249,7 +250,7 @@ fn create_deploy_tx(
                 nonce: U256::from(0).into(),
                 code: contract.get_contract().code().clone(),
             };
-            return (contract_address, tx_req.sign(&keypair));
+            return (contract_address, tx_req.sign(&keypair, chain_id));
         }
     }
 }
@@ -289,6 +290,14 @@ struct Opts {
     #[structopt(long)]
     raft: bool,
 
+    /// The chain id signed in the tx requests, i.e., `chain.tx_sig.chain_id` of the nodes.
+    #[structopt(long, default_value = "0")]
+    chain_id: u64,
+
+    /// The fee signed in the call txs for their priority. 0 for none.
+    #[structopt(long, default_value = "0")]
+    fee: u64,
+
     /// List of contracts. Accepted values: cpuheavy, donothing, ioheavy, kvstore, and smallbank.
     #[structopt(parse(try_from_str = parse_contract_arg), required = true)]
     contract: Vec<ContractArg>,
@@ -359,7 +368,8 @@ async fn main() -> Result<()> {
         .map(|(id, &contract)| {
             let id = (id as u64) % opts.shard;
             let shard_id = ShardId::new(id as u64, opts.shard);
-            let (address, deploy_tx) = create_deploy_tx(&mut rng, contract, shard_id);
+            let (address, deploy_tx) =
+                create_deploy_tx(&mut rng, contract, shard_id, opts.chain_id);
             debug!("tx {} address {}", id, address);
             contracts.push((address, shard_id, contract));
             (deploy_tx, shard_id)
@@ -408,7 +418,7 @@ async fn main() -> Result<()> {
             address,
             data: contract.gen_tx_input(&mut rng)?,
         };
-        let signed_tx_req = tx_req.sign(&key);
+        let signed_tx_req = tx_req.sign_with_fee(&key, opts.chain_id, opts.fee);
         accounts.push_back((key, (U256::from(nonce) + 1).into()));
 
         reqs.push((signed_tx_req, shard_id));
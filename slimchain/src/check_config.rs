@@ -0,0 +1,285 @@
+use slimchain_chain::{
+    config::{ChainConfig, MinerConfig, ObserverConfig, PoWConfig},
+    consensus::Consensus,
+    genesis::GenesisConfig,
+    role::Role,
+};
+use slimchain_utils::{
+    config::{Config, ConfigErrors, ConfigValidator},
+    histogram::MetricsConfig,
+    logging::LogConfig,
+};
+
+/// Check the whole config before starting the node, i.e., the unknown fields, the values out
+/// of range and the rules across the sections. All the errors are reported at once with their
+/// dotted paths.
+pub fn validate_config(cfg: &Config) -> Result<(), ConfigErrors> {
+    let mut validator = ConfigValidator::new(cfg);
+
+    validator.allow_section("role");
+    let role = match cfg.get::<Role>("role") {
+        Ok(role) => Some(role),
+        Err(e) => {
+            validator.errors_mut().push("role", e.to_string());
+            None
+        }
+    };
+    let chain_cfg: Option<ChainConfig> = validator.section("chain");
+    validator.optional_section::<GenesisConfig>("genesis");
+    validator.optional_section::<ObserverConfig>("observer");
+    validator.optional_section::<MetricsConfig>("metrics");
+    validator.optional_section::<LogConfig>("log");
+    validator.allow_section("tee");
+
+    let consensus = chain_cfg.as_ref().map(|cfg| cfg.consensus);
+    let needs_miner_cfg = matches!(
+        (role, consensus),
+        (Some(Role::Miner), Some(Consensus::PoW)) | (Some(Role::Client), Some(Consensus::Raft))
+    );
+    let miner_cfg: Option<MinerConfig> = if needs_miner_cfg {
+        validator.section("miner")
+    } else {
+        validator.allow_section("miner");
+        None
+    };
+
+    match consensus {
+        Some(Consensus::PoW) => {
+            use slimchain_network::p2p::config::{NetworkConfig, PubSubConfig};
+
+            validator.section::<NetworkConfig>("network");
+            validator.optional_section::<PubSubConfig>("pubsub");
+            validator.optional_section::<PoWConfig>("pow");
+            validator.allow_section("raft");
+        }
+        Some(Consensus::Raft) => {
+            use slimchain_network::http::config::{NetworkConfig, RaftConfig};
+
+            let net_cfg: Option<NetworkConfig> = validator.section("network");
+            let raft_cfg: Option<RaftConfig> = validator.section("raft");
+            validator.allow_section("pubsub");
+            validator.allow_section("pow");
+
+            if role == Some(Role::Miner) {
+                validator
+                    .errors_mut()
+                    .push("role", "Should not be miner with the raft consensus.");
+            }
+            if let (Some(net_cfg), Some(raft_cfg)) = (net_cfg, raft_cfg) {
+                validator.errors_mut().ensure(
+                    raft_cfg.snapshot_transfer_chunk_size as u64 <= net_cfg.body_limit.default,
+                    "raft.snapshot_transfer_chunk_size",
+                    format_args!(
+                        "Should not be larger than `network.body_limit.default` ({}).",
+                        net_cfg.body_limit.default
+                    ),
+                );
+            }
+        }
+        None => {
+            for key in &["network", "pubsub", "pow", "raft"] {
+                validator.allow_section(key);
+            }
+        }
+    }
+
+    if let (Some(chain_cfg), Some(miner_cfg)) = (chain_cfg, miner_cfg) {
+        if let Some(keypair) = miner_cfg
+            .proposer_keypair
+            .filter(|_| chain_cfg.verify_proposer)
+        {
+            validator.errors_mut().ensure(
+                chain_cfg.proposer_keys.contains(&keypair.0.public),
+                "miner.proposer_keypair",
+                "Its public key should be in `chain.proposer_keys` when `verify_proposer` is set.",
+            );
+            if let Some(proposer_id) = miner_cfg.proposer_id {
+                validator.errors_mut().ensure(
+                    chain_cfg
+                        .proposer_peers
+                        .iter()
+                        .any(|p| p.peer_id == proposer_id && p.public_key == keypair.0.public),
+                    "miner.proposer_id",
+                    "Should be mapped to the key of `proposer_keypair` by `chain.proposer_peers` \
+                     when `verify_proposer` is set.",
+                );
+            }
+        }
+    }
+
+    validator.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use slimchain_common::{ed25519::Keypair, utils::hex};
+    use slimchain_utils::toml;
+
+    const POW_CONFIG: &str = r#"
+        [role]
+        role = "miner"
+
+        [chain]
+        conflict_check = "ssi"
+        state_len = 16
+        consensus = "pow"
+
+        [miner]
+        max_txs = 512
+        max_block_interval = 2000
+
+        [network]
+        keypair = "KEYPAIR"
+    "#;
+
+    const RAFT_CONFIG: &str = r#"
+        [role]
+        role = "client"
+
+        [chain]
+        conflict_check = "ssi"
+        state_len = 16
+        consensus = "raft"
+
+        [miner]
+        max_txs = 512
+        max_block_interval = 2000
+
+        [network]
+        peer_id = 1
+
+        [raft]
+        election_timeout_min = 150
+        election_timeout_max = 300
+    "#;
+
+    fn pow_config(extra: &str) -> Config {
+        let keypair = slimchain_network::p2p::config::KeypairConfig::generate().to_base58();
+        let input = format!("{}{}", POW_CONFIG.replace("KEYPAIR", &keypair), extra);
+        Config::from_toml(toml::from_str(&input).unwrap())
+    }
+
+    fn raft_config(extra: &str) -> Config {
+        let input = format!("{}{}", RAFT_CONFIG, extra);
+        Config::from_toml(toml::from_str(&input).unwrap())
+    }
+
+    fn error_paths(cfg: &Config) -> Vec<String> {
+        validate_config(cfg)
+            .unwrap_err()
+            .errors()
+            .iter()
+            .map(|e| e.path.clone())
+            .collect()
+    }
+
+    #[test]
+    fn test_valid_config() {
+        validate_config(&pow_config("")).unwrap();
+        validate_config(&raft_config("")).unwrap();
+    }
+
+    #[test]
+    fn test_unknown_fields() {
+        let cfg = pow_config("[pubsub]\nmesh_nn = 1\n[pow]\ninit_dif = 1\n[unknown]\na = 1\n");
+        let mut paths = error_paths(&cfg);
+        paths.sort();
+        assert_eq!(vec!["pow.init_dif", "pubsub.mesh_nn", "unknown"], paths);
+
+        let cfg = raft_config("[network.body_limit]\ntx_reqs = 1\n");
+        assert_eq!(vec!["network.body_limit.tx_reqs"], error_paths(&cfg));
+    }
+
+    #[test]
+    fn test_invalid_values() {
+        let cfg = raft_config("[network.tx_verify]\nmax_batch_size = \"a\"\n");
+        assert_eq!(vec!["network.tx_verify.max_batch_size"], error_paths(&cfg));
+
+        let cfg = pow_config("[pow]\ninit_diff = 0\n[pubsub]\nhistory_gossip = 10\n");
+        let mut paths = error_paths(&cfg);
+        paths.sort();
+        assert_eq!(vec!["pow.init_diff", "pubsub.history_gossip"], paths);
+
+        let cfg = Config::from_toml(toml::from_str("[role]\nrole = \"client\"\n").unwrap());
+        assert_eq!(vec!["chain"], error_paths(&cfg));
+    }
+
+    fn set(cfg: &mut toml::Value, section: &str, key: &str, value: toml::Value) {
+        cfg.get_mut(section)
+            .and_then(toml::Value::as_table_mut)
+            .unwrap()
+            .insert(key.to_string(), value);
+    }
+
+    #[test]
+    fn test_miner_with_raft() {
+        let mut cfg: toml::Value = toml::from_str(RAFT_CONFIG).unwrap();
+        set(&mut cfg, "role", "role", "miner".into());
+        let errors = validate_config(&Config::from_toml(cfg)).unwrap_err();
+        assert!(errors.contains("role"));
+    }
+
+    #[test]
+    fn test_snapshot_chunk_size() {
+        let cfg = raft_config(
+            "snapshot_transfer_chunk_size = 2048\n[network.body_limit]\ndefault = 1024\n",
+        );
+        assert_eq!(vec!["raft.snapshot_transfer_chunk_size"], error_paths(&cfg));
+    }
+
+    #[test]
+    fn test_proposer_keypair() {
+        let keypair = Keypair::generate(&mut rand::thread_rng());
+        let other = Keypair::generate(&mut rand::thread_rng());
+        let with_proposer_key = |proposer_key: &Keypair| {
+            let mut cfg: toml::Value = toml::from_str(RAFT_CONFIG).unwrap();
+            let proposer_keys = vec![hex::encode(proposer_key.public.as_bytes())];
+            set(&mut cfg, "chain", "verify_proposer", true.into());
+            set(&mut cfg, "chain", "proposer_keys", proposer_keys.into());
+            let keypair_hex = hex::encode(&keypair.to_bytes()[..]);
+            set(&mut cfg, "miner", "proposer_keypair", keypair_hex.into());
+            Config::from_toml(cfg)
+        };
+        validate_config(&with_proposer_key(&keypair)).unwrap();
+        assert_eq!(
+            vec!["miner.proposer_keypair"],
+            error_paths(&with_proposer_key(&other))
+        );
+    }
+
+    #[test]
+    fn test_proposer_id() {
+        let keypair = Keypair::generate(&mut rand::thread_rng());
+        let other = Keypair::generate(&mut rand::thread_rng());
+        let with_proposer_peer = |peer_id: u64, proposer_key: &Keypair| {
+            let mut cfg: toml::Value = toml::from_str(RAFT_CONFIG).unwrap();
+            let keys = vec![
+                hex::encode(keypair.public.as_bytes()),
+                hex::encode(other.public.as_bytes()),
+            ];
+            let mut peer = toml::value::Table::new();
+            peer.insert("peer_id".to_string(), (peer_id as i64).into());
+            peer.insert(
+                "public_key".to_string(),
+                hex::encode(proposer_key.public.as_bytes()).into(),
+            );
+            set(&mut cfg, "chain", "verify_proposer", true.into());
+            set(&mut cfg, "chain", "proposer_keys", keys.into());
+            set(&mut cfg, "chain", "proposer_peers", vec![peer].into());
+            let keypair_hex = hex::encode(&keypair.to_bytes()[..]);
+            set(&mut cfg, "miner", "proposer_keypair", keypair_hex.into());
+            set(&mut cfg, "miner", "proposer_id", 1.into());
+            Config::from_toml(cfg)
+        };
+        validate_config(&with_proposer_peer(1, &keypair)).unwrap();
+        assert_eq!(
+            vec!["miner.proposer_id"],
+            error_paths(&with_proposer_peer(2, &keypair))
+        );
+        assert_eq!(
+            vec!["miner.proposer_id"],
+            error_paths(&with_proposer_peer(1, &other))
+        );
+    }
+}
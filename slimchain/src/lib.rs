@@ -1,5 +1,6 @@
 #[macro_use]
 extern crate tracing;
 
+pub mod check_config;
 pub mod inspect;
 pub mod node;
@@ -1,17 +1,30 @@
+use crate::check_config::validate_config;
 use serde::{Deserialize, Serialize};
 use slimchain_chain::{
-    config::{ChainConfig, MinerConfig},
+    config::{ChainConfig, MinerConfig, ObserverConfig},
     consensus::Consensus,
     db::DB,
+    genesis::GenesisConfig,
+    metrics::{register_chain_metrics_collector, spawn_chain_metrics_reporter},
     role::Role,
+    tx_status::TX_STATUS,
 };
 use slimchain_common::{
+    basic::ShardId,
     error::{bail, Context as _, Result},
     tx::TxTrait,
 };
 use slimchain_network::p2p::control::Swarmer;
 use slimchain_tx_engine::TxEngine;
-use slimchain_utils::{config::Config, init_tracing, path::binary_directory};
+use slimchain_utils::{
+    config::{install_config_watch_as_global, on_section_reload, Config, CONFIG_FILE_NAME},
+    histogram::MetricsConfig,
+    init_tracing_with_config,
+    logging::{log_filter, LogConfig},
+    path::binary_directory,
+    prometheus::REGISTRY,
+    reload_tracing_filter,
+};
 use std::{path::PathBuf, time::Duration};
 use structopt::StructOpt;
 
@@ -41,6 +54,18 @@ struct Opts {
     /// Enable RocksDB statistics.
     #[structopt(long)]
     db_statistics: bool,
+
+    /// Record the chain metrics to the metrics file every N milliseconds.
+    #[structopt(long)]
+    chain_metrics_interval: Option<u64>,
+
+    /// Also count the events and the times recorded to the metrics file at `/metrics`.
+    #[structopt(long)]
+    prometheus_tee: bool,
+
+    /// Validate the config file, print the errors if any, and exit.
+    #[structopt(long)]
+    check_config: bool,
 }
 
 pub async fn node_main<Tx: TxTrait + Serialize + for<'de> Deserialize<'de> + 'static>(
@@ -50,43 +75,128 @@ pub async fn node_main<Tx: TxTrait + Serialize + for<'de> Deserialize<'de> + 'st
     let opts = Opts::from_args();
     let bin_dir = binary_directory()?;
 
+    let config_path = opts
+        .config
+        .clone()
+        .unwrap_or_else(|| bin_dir.join(CONFIG_FILE_NAME));
+    if opts.check_config {
+        validate_config(&Config::load(&config_path)?)?;
+        println!("{} is valid.", config_path.display());
+        return Ok(());
+    }
+    let cfg_rx = Config::watch(&config_path)?;
+    let cfg = cfg_rx.borrow().clone();
+    validate_config(&cfg)?;
+
     let _guard = {
+        let log_cfg: LogConfig = cfg.get("log").unwrap_or_default();
+        let metrics_cfg: MetricsConfig = cfg.get("metrics").unwrap_or_default();
         let metrics = opts.metrics.unwrap_or_else(|| bin_dir.join("metrics.log"));
-        let log_level = opts.log_level.as_deref().unwrap_or("info");
-        init_tracing(log_level, &metrics)?
-    };
-
-    let cfg = if let Some(config) = opts.config {
-        info!("Load config from {}.", config.display());
-        Config::load(&config)?
-    } else {
-        Config::load_in_the_same_dir()?
+        let log_level = opts
+            .log_level
+            .as_deref()
+            .or_else(|| log_cfg.level.as_deref())
+            .unwrap_or("info");
+        init_tracing_with_config(log_level, &log_cfg, &metrics, metrics_cfg.file)?
     };
+    if opts.config.is_some() {
+        info!("Load config from {}.", config_path.display());
+    }
+    install_config_watch_as_global(cfg_rx)?;
+    let cli_log_level = opts.log_level.clone();
+    on_section_reload("log", move |log_cfg: LogConfig| {
+        let level = cli_log_level
+            .as_deref()
+            .or_else(|| log_cfg.level.as_deref())
+            .unwrap_or("info");
+        info!("Update the log level: {} {:?}", level, log_cfg.targets);
+        if let Err(e) = log_filter(level, &log_cfg.targets).and_then(reload_tracing_filter) {
+            warn!("Failed to update the log level. Error: {}", e);
+        }
+    });
 
     let role: Role = cfg.get("role")?;
     info!("Role: {}", role);
+    match role {
+        Role::Storage(ShardId { id, total }) => REGISTRY
+            .set_const_labels(&[("role", "storage"), ("shard", &format!("{}/{}", id, total))]),
+        _ => REGISTRY.set_const_labels(&[("role", &role.to_string().to_lowercase())]),
+    }
+    let metrics_cfg: MetricsConfig = cfg.get("metrics").unwrap_or_default();
+    info!("Metrics Cfg: {:#?}", metrics_cfg);
+    let _histogram_flusher = metrics_cfg.install(opts.prometheus_tee);
+    register_chain_metrics_collector();
     let chain_cfg: ChainConfig = cfg.get("chain")?;
     info!("Chain Cfg: {:#?}", chain_cfg);
+    TX_STATUS.set_ttl(chain_cfg.tx_status_ttl);
+    chain_cfg.tx_sig.install_as_global()?;
+
+    let db = DB::open_with_backend(
+        chain_cfg.db_backend,
+        &opts.data.unwrap_or(bin_dir),
+        role,
+        opts.db_statistics,
+        !chain_cfg.disable_db_journal,
+        chain_cfg.db_read_threads,
+    )?;
 
-    let db = DB::open_or_create_in_dir(&opts.data.unwrap_or(bin_dir), role, opts.db_statistics)?;
+    let genesis_cfg: GenesisConfig = cfg.get_or_default("genesis")?;
+    info!("Genesis time: {}", genesis_cfg.time_stamp);
+    genesis_cfg.write_genesis_state(&db)?;
+    genesis_cfg.install_as_global()?;
+
+    let chain_metrics_reporter = opts
+        .chain_metrics_interval
+        .map(|interval| spawn_chain_metrics_reporter(Duration::from_millis(interval)));
 
     match chain_cfg.consensus {
         Consensus::PoW => {
             use slimchain_chain::config::PoWConfig;
-            use slimchain_network::{behavior::pow::*, p2p::config::NetworkConfig};
+            use slimchain_network::{
+                behavior::pow::*,
+                p2p::{
+                    config::{NetworkConfig, PubSubConfig},
+                    relay::build_transport_with_relay,
+                },
+            };
 
-            let net_cfg: NetworkConfig = cfg.get("network")?;
+            let mut net_cfg: NetworkConfig = cfg.get("network")?;
+            if let Some(interval) = opts.chain_metrics_interval {
+                if net_cfg.pubsub_metrics_interval == Duration::from_millis(0) {
+                    net_cfg.pubsub_metrics_interval = Duration::from_millis(interval);
+                }
+            }
 
             let pow_cfg: PoWConfig = cfg.get("pow").unwrap_or_default();
             info!("PoW initial difficulty: {}", pow_cfg.init_diff);
             pow_cfg.install_as_global()?;
 
+            let pubsub_cfg: PubSubConfig = cfg.get("pubsub").unwrap_or_default();
+            info!("PubSub Cfg: {:#?}", pubsub_cfg);
+            pubsub_cfg.install_as_global()?;
+
+            let keypair = net_cfg.keypair.to_libp2p_keypair();
+            let (transport, relay) = build_transport_with_relay(&keypair, &net_cfg.relay).await?;
+
             match role {
                 Role::Client => {
-                    let behavior = ClientBehavior::<Tx>::new(db, &chain_cfg, &net_cfg).await?;
-                    let swarmer =
-                        Swarmer::new(net_cfg.keypair.to_libp2p_keypair(), behavior).await?;
+                    let mut behavior = ClientBehavior::<Tx>::new(db, &chain_cfg, &net_cfg).await?;
+                    behavior.discv_mut().set_relay(relay);
+                    let mut swarmer = Swarmer::with_transport(keypair, transport, behavior);
+                    swarmer.listen_via_relays(&net_cfg.relay)?;
                     let mut ctrl = swarmer.spawn_app(&net_cfg.listen).await?;
+                    if !net_cfg.bootnodes.is_empty() {
+                        let bootstrap = ctrl.call_with_sender(|swarm, ret| {
+                            swarm
+                                .behaviour_mut()
+                                .discv_mut()
+                                .wait_bootstrap_with_ret(ret)
+                        });
+                        match tokio::time::timeout(Duration::from_secs(60), bootstrap).await {
+                            Ok(res) => res?,
+                            Err(_) => warn!("Bootstrap is not done after 60s."),
+                        }
+                    }
                     let _miner_peer_id = ctrl
                         .call_with_sender(|swarm, ret| {
                             swarm.behaviour_mut().discv_mut().find_random_peer_with_ret(
@@ -102,21 +212,35 @@ pub async fn node_main<Tx: TxTrait + Serialize + for<'de> Deserialize<'de> + 'st
                 Role::Miner => {
                     let miner_cfg: MinerConfig = cfg.get("miner")?;
                     info!("Miner Cfg: {:#?}", miner_cfg);
-                    let behavior =
+                    let mut behavior =
                         MinerBehavior::<Tx>::new(db, &chain_cfg, &miner_cfg, &net_cfg).await?;
-                    let swarmer =
-                        Swarmer::new(net_cfg.keypair.to_libp2p_keypair(), behavior).await?;
+                    behavior.discv_mut().set_relay(relay);
+                    let mut swarmer = Swarmer::with_transport(keypair, transport, behavior);
+                    swarmer.listen_via_relays(&net_cfg.relay)?;
                     let ctrl = swarmer.spawn_app(&net_cfg.listen).await?;
                     ctrl.run_until_interrupt().await?;
                 }
                 Role::Storage(shard_id) => {
                     let engine = create_tx_engine(&cfg, &opts.enclave)?;
-                    let behavior =
+                    let mut behavior =
                         StorageBehavior::<Tx>::new(db, engine, shard_id, &chain_cfg, &net_cfg)
                             .await?;
-                    let swarmer =
-                        Swarmer::new(net_cfg.keypair.to_libp2p_keypair(), behavior).await?;
+                    behavior.discv_mut().set_relay(relay);
+                    let mut swarmer = Swarmer::with_transport(keypair, transport, behavior);
+                    swarmer.listen_via_relays(&net_cfg.relay)?;
                     let mut ctrl = swarmer.spawn_app(&net_cfg.listen).await?;
+                    if !net_cfg.bootnodes.is_empty() {
+                        let bootstrap = ctrl.call_with_sender(|swarm, ret| {
+                            swarm
+                                .behaviour_mut()
+                                .discv_mut()
+                                .wait_bootstrap_with_ret(ret)
+                        });
+                        match tokio::time::timeout(Duration::from_secs(60), bootstrap).await {
+                            Ok(res) => res?,
+                            Err(_) => warn!("Bootstrap is not done after 60s."),
+                        }
+                    }
                     let _miner_peer_id = ctrl
                         .call_with_sender(|swarm, ret| {
                             swarm.behaviour_mut().discv_mut().find_random_peer_with_ret(
@@ -129,15 +253,38 @@ pub async fn node_main<Tx: TxTrait + Serialize + for<'de> Deserialize<'de> + 'st
                         .context("Failed to find miner.")?;
                     ctrl.run_until_interrupt().await?;
                 }
+                Role::Observer => {
+                    let observer_cfg: ObserverConfig = cfg.get("observer").unwrap_or_default();
+                    info!("Observer Cfg: {:#?}", observer_cfg);
+                    let mut behavior =
+                        ObserverBehavior::<Tx>::new(db, &observer_cfg, &net_cfg).await?;
+                    behavior.discv_mut().set_relay(relay);
+                    let mut swarmer = Swarmer::with_transport(keypair, transport, behavior);
+                    swarmer.listen_via_relays(&net_cfg.relay)?;
+                    let ctrl = swarmer.spawn_app(&net_cfg.listen).await?;
+                    ctrl.run_until_interrupt().await?;
+                }
             }
         }
         Consensus::Raft => {
             use slimchain_network::{
-                behavior::raft::{client::ClientNode, storage::StorageNode},
-                http::config::{NetworkConfig, RaftConfig},
+                behavior::raft::{
+                    client::ClientNode, observer::ObserverNode, storage::StorageNode,
+                },
+                http::{
+                    config::{NetworkConfig, RaftConfig},
+                    route_table::spawn_route_table_watcher,
+                    rpc_metrics::{register_rpc_metrics_collector, start_rpc_metrics},
+                },
             };
 
             let net_cfg: NetworkConfig = cfg.get("network")?;
+            net_cfg.http_client.install_as_global()?;
+            net_cfg.auth.clone().install_as_global()?;
+            net_cfg.node_rpc_encoding.install_as_global()?;
+            net_cfg.body_limit.install_as_global()?;
+            let rpc_metrics_reporter = start_rpc_metrics(&net_cfg.rpc_metrics);
+            register_rpc_metrics_collector();
             let raft_cfg: RaftConfig = cfg.get("raft")?;
 
             match role {
@@ -146,9 +293,23 @@ pub async fn node_main<Tx: TxTrait + Serialize + for<'de> Deserialize<'de> + 'st
                     info!("Miner Cfg: {:#?}", miner_cfg);
                     let mut client: ClientNode<Tx> =
                         ClientNode::new(db, &chain_cfg, &miner_cfg, &net_cfg, &raft_cfg).await?;
+                    let watch_interval = net_cfg.route_update.watch_interval;
+                    let route_table_watcher = if watch_interval > Duration::from_millis(0) {
+                        info!("Watch {} for the peer changes.", config_path.display());
+                        Some(spawn_route_table_watcher(
+                            client.route_table().clone(),
+                            config_path,
+                            watch_interval,
+                        ))
+                    } else {
+                        None
+                    };
                     info!("Press Ctrl-C to quit.");
                     tokio::signal::ctrl_c().await?;
                     info!("Quitting.");
+                    if let Some(watcher) = route_table_watcher {
+                        watcher.abort();
+                    }
                     client.shutdown().await?;
                 }
                 Role::Storage(shard_id) => {
@@ -160,12 +321,30 @@ pub async fn node_main<Tx: TxTrait + Serialize + for<'de> Deserialize<'de> + 'st
                     info!("Quitting.");
                     storage.shutdown().await?;
                 }
+                Role::Observer => {
+                    let observer_cfg: ObserverConfig = cfg.get("observer").unwrap_or_default();
+                    info!("Observer Cfg: {:#?}", observer_cfg);
+                    let mut observer: ObserverNode<Tx> =
+                        ObserverNode::new(db, &observer_cfg, &net_cfg).await?;
+                    info!("Press Ctrl-C to quit.");
+                    tokio::signal::ctrl_c().await?;
+                    info!("Quitting.");
+                    observer.shutdown().await?;
+                }
                 Role::Miner => {
                     bail!("Role cannot be miner.");
                 }
             }
+
+            if let Some(reporter) = rpc_metrics_reporter {
+                reporter.abort();
+            }
         }
     }
 
+    if let Some(reporter) = chain_metrics_reporter {
+        reporter.abort();
+    }
+
     Ok(())
 }